@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::Decode::{Decoded, Op};
+use crate::Isa::Isa;
+use crate::Memory::{Ram, PAGE_SHIFT};
+
+/// Instructions decoded ahead in one go, at most.
+pub const MAX_BLOCK_LEN: usize = 64;
+
+/// Straight-line code starting at some physical pc, decoded up to and
+/// including the first instruction that can jump, trap on purpose or change
+/// how the following ones are fetched. Blocks never cross a page, so one
+/// translation covers all of it; an instruction that would straddle the
+/// page end, or that does not decode, ends the block before it and is left
+/// to the uncached path.
+#[derive(Debug)]
+pub struct Block {
+    pub insts: Vec<Decoded>,
+    /// The XLEN the block was decoded for; RV32 and RV64 encodings differ.
+    pub xlen: u32,
+}
+
+/// Whether `inst` has to be the last instruction of its block.
+fn ends_block(op: Op) -> bool {
+    use Op::*;
+    matches!(
+        op,
+        JAL | JALR | BEQ | BNE | BLT | BGE | BLTU | BGEU
+            | ECALL | EBREAK | MRET | SRET | WFI | SFENCEVMA | HFENCEVVMA | HFENCEGVMA | FENCEI
+            | CSRRW | CSRRS | CSRRC | CSRRWI | CSRRSI | CSRRCI
+    )
+}
+
+/// Decoded blocks by physical pc, shared by every hart on the bus.
+///
+/// Any write to a RAM page that blocks were decoded from drops those
+/// blocks and bumps `generation`, so harts part way through one notice
+/// and look up the new code. `FENCE.I` drops everything. Blocks are
+/// physical, so `satp` changes leave them alone; the harts' fetch
+/// translations are what those invalidate.
+#[derive(Debug)]
+pub struct BlockCache {
+    pub enabled: bool,
+    blocks: HashMap<u64, Arc<Block>>,
+    /// Per RAM page, the physical pcs of the blocks decoded from it.
+    pages: HashMap<usize, Vec<u64>>,
+    /// Per RAM page, whether `pages` has an entry, checked on every store.
+    code_pages: Vec<bool>,
+    generation: u64,
+}
+
+impl Clone for BlockCache {
+    /// A copy starts out empty, in a later generation than anything cached
+    /// by the original, so harts cloned along with it decode afresh.
+    fn clone(&self) -> Self {
+        Self {
+            enabled: self.enabled,
+            generation: self.generation + 1,
+            ..Self::new()
+        }
+    }
+}
+
+impl Default for BlockCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BlockCache {
+    pub fn new() -> Self {
+        Self {
+            enabled: true,
+            blocks: HashMap::new(),
+            pages: HashMap::new(),
+            code_pages: Vec::new(),
+            generation: 0,
+        }
+    }
+
+    /// Changes whenever cached blocks are dropped.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Returns the block at `paddr`, decoding it from `ram` for `isa` if it
+    /// is not cached yet. `None` when caching is off, `paddr` is not in
+    /// RAM, or the first instruction there has to take the uncached path.
+    /// Blocks are shared by every hart, which all implement the same
+    /// extensions; one running at another XLEN decodes the block again.
+    pub fn lookup(&mut self, ram: &Ram, paddr: u64, isa: &Isa) -> Option<Arc<Block>> {
+        if !self.enabled {
+            return None;
+        }
+        let cached = self.blocks.get(&paddr);
+        if let Some(block) = cached.filter(|block| block.xlen == isa.xlen()) {
+            return (!block.insts.is_empty()).then(|| block.clone());
+        }
+        let known = cached.is_some();
+        let start = ram.offset(paddr, 2)?;
+        let block = Arc::new(Self::decode(ram, start, isa));
+        let page = start >> PAGE_SHIFT;
+        if self.code_pages.len() <= page {
+            self.code_pages.resize(page + 1, false);
+        }
+        self.code_pages[page] = true;
+        if !known {
+            self.pages.entry(page).or_default().push(paddr);
+        }
+        self.blocks.insert(paddr, block.clone());
+        (!block.insts.is_empty()).then_some(block)
+    }
+
+    fn decode(ram: &Ram, start: usize, isa: &Isa) -> Block {
+        let page_end = ((start >> PAGE_SHIFT) + 1) << PAGE_SHIFT;
+        let page_end = page_end.min(ram.data.len());
+        let mut insts = Vec::new();
+        let mut off = start;
+        while insts.len() < MAX_BLOCK_LEN && off + 2 <= page_end {
+            let lo = u16::from_le_bytes([ram.data[off], ram.data[off + 1]]) as u32;
+            let (raw, len) = if lo & 0b11 != 0b11 {
+                (lo, 2)
+            } else if off + 4 <= page_end {
+                (u32::from_le_bytes(ram.data[off..off + 4].try_into().unwrap()), 4)
+            } else {
+                break;
+            };
+            let decoded = match Decoded::decode(raw, isa) {
+                Some(decoded) => decoded,
+                None => break,
+            };
+            insts.push(decoded);
+            off += len;
+            if ends_block(decoded.op) {
+                break;
+            }
+        }
+        Block { insts, xlen: isa.xlen() }
+    }
+
+    /// Whether blocks were decoded from RAM page `page`.
+    #[inline]
+    pub fn has_code(&self, page: usize) -> bool {
+        self.code_pages.get(page).copied().unwrap_or(false)
+    }
+
+    /// One flag per RAM page, set for the pages blocks were decoded from;
+    /// pages past its end have none.
+    pub fn code_pages(&self) -> &[bool] {
+        &self.code_pages
+    }
+
+    /// Drops the blocks decoded from the RAM pages that `len` bytes at RAM
+    /// offset `off` touch.
+    pub fn invalidate(&mut self, off: usize, len: usize) {
+        for page in (off >> PAGE_SHIFT)..=((off + len.max(1) - 1) >> PAGE_SHIFT) {
+            if !self.has_code(page) {
+                continue;
+            }
+            for paddr in self.pages.remove(&page).unwrap_or_default() {
+                self.blocks.remove(&paddr);
+            }
+            self.code_pages[page] = false;
+            self.generation += 1;
+        }
+    }
+
+    /// Drops every block, for `FENCE.I`.
+    pub fn flush(&mut self) {
+        if !self.blocks.is_empty() {
+            self.blocks.clear();
+            self.pages.clear();
+            self.code_pages.iter_mut().for_each(|code| *code = false);
+            self.generation += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Cpu::Hart;
+    use crate::Decode::Decoded;
+    use crate::Isa::Isa;
+    use crate::Memory::{Bus, RAM_BASE};
+
+    const ADDI_A0_1: u32 = 0x0015_0513; // addi a0, a0, 1
+    const ADDI_A0_16: u32 = 0x0105_0513; // addi a0, a0, 16
+    const SW_T0_T1: u32 = 0x0053_2023; // sw t0, 0(t1)
+    const JAL_BACK_4: u32 = 0xFFDF_F06F; // j .-4
+    const JAL_BACK_8: u32 = 0xFF9F_F06F; // j .-8
+    const FENCE_I: u32 = 0x0000_100F;
+
+    fn machine(program: &[u32]) -> (Hart, Bus) {
+        let mut bus = Bus::new(4096);
+        let bytes: Vec<u8> = program.iter().flat_map(|word| word.to_le_bytes()).collect();
+        assert!(bus.write_bytes(RAM_BASE, &bytes));
+        let mut hart = Hart::new(0);
+        hart.pc = RAM_BASE;
+        (hart, bus)
+    }
+
+    fn run(hart: &mut Hart, bus: &mut Bus, steps: usize) {
+        for _ in 0..steps {
+            hart.step(bus);
+        }
+    }
+
+    #[test]
+    fn store_into_a_running_block() {
+        // the loop rewrites its own first instruction on every pass.
+        let (mut hart, mut bus) = machine(&[ADDI_A0_1, SW_T0_T1, JAL_BACK_8]);
+        hart.x[5] = ADDI_A0_16 as u64;
+        hart.x[6] = RAM_BASE;
+        run(&mut hart, &mut bus, 3);
+        assert_eq!(hart.x[10], 1);
+        assert_eq!(hart.pc, RAM_BASE);
+        run(&mut hart, &mut bus, 3);
+        assert_eq!(hart.x[10], 17);
+        run(&mut hart, &mut bus, 3);
+        assert_eq!(hart.x[10], 33);
+    }
+
+    #[test]
+    fn fence_i_flushes_the_jump_cache() {
+        let (mut hart, mut bus) = machine(&[ADDI_A0_1, JAL_BACK_4]);
+        run(&mut hart, &mut bus, 4);
+        assert_eq!(hart.x[10], 2);
+        // a write the bus does not see, like a device filling RAM behind
+        // the harts' backs, leaves the cached block in use...
+        let generation = bus.blocks.generation();
+        bus.ram.data[..4].copy_from_slice(&ADDI_A0_16.to_le_bytes());
+        run(&mut hart, &mut bus, 2);
+        assert_eq!(hart.x[10], 3);
+        assert_eq!(bus.blocks.generation(), generation);
+        // ...until `FENCE.I`.
+        let fence = Decoded::decode(FENCE_I, &Isa::default()).unwrap();
+        hart.execute(&mut bus, &fence).unwrap();
+        assert_ne!(bus.blocks.generation(), generation);
+        hart.pc = RAM_BASE;
+        run(&mut hart, &mut bus, 2);
+        assert_eq!(hart.x[10], 19);
+    }
+}
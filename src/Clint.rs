@@ -0,0 +1,151 @@
+use crate::Csr::{MIP_MSIP, MIP_MTIP};
+
+/// Core-local interruptor, where QEMU's `virt` board and Spike put it and
+/// laid out like SiFive's: a 32-bit `msip` word per hart from offset 0, a
+/// 64-bit `mtimecmp` per hart from `0x4000` and `mtime` at `0xbff8`.
+///
+/// `msip` raises the hart's machine software interrupt, and `mtime` at or
+/// past `mtimecmp` its machine timer interrupt.
+pub const CLINT_BASE: u64 = 0x0200_0000;
+pub const CLINT_SIZE: u64 = 0x1_0000;
+const MSIP_OFFSET: u64 = 0x0;
+const MTIMECMP_OFFSET: u64 = 0x4000;
+const MTIME_OFFSET: u64 = 0xBFF8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Register {
+    Msip(usize),
+    Mtimecmp(usize),
+    Mtime,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Clint {
+    /// `msip` of each hart, by hart id.
+    pub msip: Vec<bool>,
+    /// `mtimecmp` of each hart, by hart id; all ones until software arms it.
+    pub mtimecmp: Vec<u64>,
+    /// Set by every write, for the machine to pass the lines on to the
+    /// harts; it does so on every tick of `mtime` too.
+    pub changed: bool,
+}
+
+impl Clint {
+    pub fn new(hart_count: usize) -> Self {
+        Self {
+            msip: vec![false; hart_count],
+            mtimecmp: vec![u64::MAX; hart_count],
+            changed: false,
+        }
+    }
+
+    /// Returns `true` if an access of `size` bytes at `addr` falls in the
+    /// CLINT's address range.
+    pub fn covers(addr: u64, size: u64) -> bool {
+        addr >= CLINT_BASE && addr.saturating_add(size) <= CLINT_BASE + CLINT_SIZE
+    }
+
+    /// The register an access of `size` bytes at `addr` hits and the bit
+    /// it starts at. Accesses straddling two registers or falling between
+    /// them hit nothing.
+    fn register(&self, addr: u64, size: u64) -> Option<(Register, u64)> {
+        let offset = addr - CLINT_BASE;
+        let (register, start, width) = if offset >= MTIME_OFFSET {
+            (Register::Mtime, MTIME_OFFSET, 8)
+        } else if offset >= MTIMECMP_OFFSET {
+            let hart = ((offset - MTIMECMP_OFFSET) / 8) as usize;
+            (Register::Mtimecmp(hart), MTIMECMP_OFFSET + hart as u64 * 8, 8)
+        } else {
+            let hart = ((offset - MSIP_OFFSET) / 4) as usize;
+            (Register::Msip(hart), MSIP_OFFSET + hart as u64 * 4, 4)
+        };
+        let exists = match register {
+            Register::Msip(hart) => hart < self.msip.len(),
+            Register::Mtimecmp(hart) => hart < self.mtimecmp.len(),
+            Register::Mtime => true,
+        };
+        (exists && offset + size <= start + width).then_some((register, (offset - start) * 8))
+    }
+
+    /// Loads `size` bytes at `addr`, with `mtime` the bus's timer.
+    pub fn load(&self, mtime: u64, addr: u64, size: u64) -> Option<u64> {
+        let (register, shift) = self.register(addr, size)?;
+        let value = match register {
+            Register::Msip(hart) => self.msip[hart] as u64,
+            Register::Mtimecmp(hart) => self.mtimecmp[hart],
+            Register::Mtime => mtime,
+        };
+        Some((value >> shift) & size_mask(size))
+    }
+
+    /// Stores the low `size` bytes of `value` at `addr`, leaving the rest of
+    /// the register as it was. Returns `false` if no register is there.
+    pub fn store(&mut self, mtime: &mut u64, addr: u64, size: u64, value: u64) -> bool {
+        let (register, shift) = match self.register(addr, size) {
+            Some(found) => found,
+            None => return false,
+        };
+        self.changed = true;
+        let mask = size_mask(size) << shift;
+        let merge = |old: u64| (old & !mask) | ((value << shift) & mask);
+        match register {
+            Register::Msip(hart) => self.msip[hart] = merge(self.msip[hart] as u64) & 1 != 0,
+            Register::Mtimecmp(hart) => self.mtimecmp[hart] = merge(self.mtimecmp[hart]),
+            Register::Mtime => *mtime = merge(*mtime),
+        }
+        true
+    }
+
+    /// The `MSIP` and `MTIP` bits of `mip` that hart `hart` sees at `mtime`.
+    pub fn interrupts(&self, hart: u64, mtime: u64) -> u64 {
+        let hart = hart as usize;
+        let mut mip = 0;
+        if self.msip.get(hart).copied().unwrap_or(false) {
+            mip |= MIP_MSIP;
+        }
+        if self.mtimecmp.get(hart).is_some_and(|cmp| mtime >= *cmp) {
+            mip |= MIP_MTIP;
+        }
+        mip
+    }
+}
+
+fn size_mask(size: u64) -> u64 {
+    if size >= 8 { u64::MAX } else { (1 << (size * 8)) - 1 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Memory::Bus;
+
+    #[test]
+    fn registers() {
+        let mut bus = Bus::new(0x1000);
+        bus.clint = Clint::new(2);
+        bus.mtime = 0x1234_5678_9ABC_DEF0;
+        assert_eq!(bus.load(CLINT_BASE + MTIME_OFFSET, 8), Some(0x1234_5678_9ABC_DEF0));
+        assert_eq!(bus.load(CLINT_BASE + MTIME_OFFSET + 4, 4), Some(0x1234_5678));
+
+        // RV32 writes `mtimecmp` a half at a time.
+        let cmp = CLINT_BASE + MTIMECMP_OFFSET + 8;
+        assert!(bus.store(cmp, 4, 0x9ABC_DEF1));
+        assert!(bus.store(cmp + 4, 4, 0x1234_5678));
+        assert_eq!(bus.clint.mtimecmp, [u64::MAX, 0x1234_5678_9ABC_DEF1]);
+        assert_eq!(bus.clint.interrupts(1, bus.mtime), 0);
+        assert_eq!(bus.clint.interrupts(1, bus.mtime + 1), MIP_MTIP);
+
+        assert!(bus.store(CLINT_BASE + 4, 4, 0xFFFF_FFFF));
+        assert_eq!(bus.clint.msip, [false, true]);
+        assert_eq!(bus.load(CLINT_BASE + 4, 4), Some(1));
+        assert_eq!(bus.clint.interrupts(1, 0), MIP_MSIP);
+        assert_eq!(bus.clint.interrupts(0, 0), 0);
+
+        // harts that do not exist, and accesses across two registers.
+        assert_eq!(bus.load(CLINT_BASE + 8, 4), None);
+        assert!(!bus.store(CLINT_BASE + MTIMECMP_OFFSET + 16, 8, 0));
+        assert_eq!(bus.load(CLINT_BASE + 2, 4), None);
+        assert!(bus.store(CLINT_BASE + MTIME_OFFSET, 8, 5));
+        assert_eq!(bus.mtime, 5);
+    }
+}
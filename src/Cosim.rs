@@ -0,0 +1,335 @@
+use std::collections::VecDeque;
+use std::io::BufRead;
+
+use crate::Emulator::Machine;
+use crate::Trace::Commit;
+
+/// Instructions shown before a mismatch when nothing else was asked for.
+pub const DEFAULT_CONTEXT: usize = 10;
+
+/// One retired instruction from the reference commit log.
+#[derive(Debug, Clone)]
+struct Entry {
+    privilege: u64,
+    pc: u64,
+    raw: u32,
+    reg_writes: Vec<(usize, u64)>,
+    stores: Vec<(u64, u64)>,
+    line: String,
+}
+
+/// How a co-simulation run ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CosimEnd {
+    /// The target exited through HTIF with this code.
+    Exited(u64),
+    /// The reference log ran out before the target exited.
+    ReferenceEnded,
+    /// The instruction limit handed to `run` was reached.
+    InstructionLimit,
+    /// The two disagreed; the report explains where.
+    Mismatch(String),
+}
+
+fn parse_hex(text: &str) -> Option<u64> {
+    u64::from_str_radix(text.strip_prefix("0x")?, 16).ok()
+}
+
+/// Parses a Spike `--log-commits` line, or one of ours with its trailing
+/// `# disassembly`. Anything else, like the lines Spike's `-l` adds, gives
+/// `None`.
+fn parse_line(line: &str) -> Option<(usize, Entry)> {
+    let body = line.split(" #").next()?;
+    let rest = body.trim_start().strip_prefix("core")?;
+    let (hart, rest) = rest.split_once(':')?;
+    let hart = hart.trim().parse().ok()?;
+    let mut tokens = rest.split_whitespace().peekable();
+    let privilege = tokens.next()?;
+    if privilege.len() != 1 {
+        return None;
+    }
+    let privilege = privilege.parse().ok()?;
+    let pc = parse_hex(tokens.next()?)?;
+    let raw = parse_hex(tokens.next()?.strip_prefix('(')?.strip_suffix(')')?)? as u32;
+    let mut entry = Entry {
+        privilege,
+        pc,
+        raw,
+        reg_writes: Vec::new(),
+        stores: Vec::new(),
+        line: line.trim_end().to_string(),
+    };
+    while let Some(token) = tokens.next() {
+        if token == "mem" {
+            let addr = parse_hex(tokens.next()?)?;
+            // a value after the address makes it a store.
+            if let Some(value) = tokens.peek().and_then(|t| parse_hex(t)) {
+                tokens.next();
+                entry.stores.push((addr, value));
+            }
+        } else if let Some(reg) = token.strip_prefix('x').and_then(|r| r.parse::<usize>().ok()) {
+            entry.reg_writes.push((reg, parse_hex(tokens.next()?)?));
+        } else if token.starts_with('f') || token.starts_with('c') {
+            // floating point and CSR writes are not compared.
+            tokens.next();
+        }
+    }
+    Some((hart, entry))
+}
+
+struct Cosim<R: BufRead> {
+    reader: R,
+    /// Reference entries read ahead for each hart.
+    queues: Vec<VecDeque<Entry>>,
+    /// Set once a hart reached the pc the reference log is at.
+    synced: Vec<bool>,
+    /// Our last few commits, for the report.
+    history: VecDeque<(u64, Commit)>,
+    context: usize,
+    compared: u64,
+}
+
+impl<R: BufRead> Cosim<R> {
+    fn next_entry(&mut self, hart: usize) -> Result<Option<Entry>, String> {
+        if let Some(entry) = self.queues[hart].pop_front() {
+            return Ok(Some(entry));
+        }
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+                return Ok(None);
+            }
+            match parse_line(&line) {
+                Some((id, entry)) if id == hart => return Ok(Some(entry)),
+                Some((id, entry)) if id < self.queues.len() => self.queues[id].push_back(entry),
+                _ => {}
+            }
+        }
+    }
+
+    /// Compares a commit against the reference, returning what differs.
+    /// On RV32 only the low 32 bits of register writes count, as those
+    /// are all the log shows.
+    fn compare(commit: &Commit, entry: &Entry) -> Option<String> {
+        if commit.pc != entry.pc {
+            return Some(format!("pc is {:#x}, expected {:#x}", commit.pc, entry.pc));
+        }
+        if commit.raw != entry.raw {
+            return Some(format!("instruction is {:#x}, expected {:#x}", commit.raw, entry.raw));
+        }
+        if commit.privilege != entry.privilege {
+            return Some(format!("privilege is {}, expected {}", commit.privilege, entry.privilege));
+        }
+        let reg_writes: Vec<(usize, u64)> =
+            commit.reg_writes.iter().map(|(reg, value)| (*reg, commit.narrow(*value))).collect();
+        if reg_writes != entry.reg_writes {
+            return Some(format!("register writes are {:x?}, expected {:x?}", reg_writes, entry.reg_writes));
+        }
+        let stores: Vec<(u64, u64)> = commit.stores.iter().map(|(addr, value, _)| (*addr, *value)).collect();
+        if stores != entry.stores {
+            return Some(format!("memory writes are {:x?}, expected {:x?}", stores, entry.stores));
+        }
+        None
+    }
+
+    fn report(&self, hart: u64, problem: &str, expected: Option<&Entry>) -> String {
+        let mut out = format!(
+            "co-simulation mismatch on hart {} after {} matching instructions: {}\n",
+            hart, self.compared, problem
+        );
+        if let Some(entry) = expected {
+            out.push_str(&format!("expected: {}\n", entry.line));
+        }
+        out.push_str(&format!("last {} instructions (ours):\n", self.history.len()));
+        for (id, commit) in self.history.iter() {
+            out.push_str(&format!("  {}\n", commit.format(*id)));
+        }
+        out
+    }
+
+    /// Checks the instruction every hart retired this round.
+    fn check(&mut self, machine: &Machine) -> Result<Option<CosimEnd>, String> {
+        for (index, hart) in machine.harts.iter().enumerate() {
+            let commit = match &hart.commit {
+                Some(commit) if commit.retired => commit.clone(),
+                _ => continue,
+            };
+            if self.context > 0 {
+                if self.history.len() == self.context {
+                    self.history.pop_front();
+                }
+                self.history.push_back((hart.id, commit.clone()));
+            }
+            let mut entry = match self.next_entry(index)? {
+                Some(entry) => entry,
+                None => return Ok(Some(CosimEnd::ReferenceEnded)),
+            };
+            // the reference may start earlier, e.g. in Spike's boot ROM.
+            if !self.synced[index] {
+                while entry.pc != commit.pc {
+                    entry = match self.next_entry(index)? {
+                        Some(entry) => entry,
+                        None => {
+                            let problem = format!("the reference log never reaches pc {:#x}", commit.pc);
+                            return Ok(Some(CosimEnd::Mismatch(self.report(hart.id, &problem, None))));
+                        }
+                    };
+                }
+                self.synced[index] = true;
+            }
+            if let Some(problem) = Self::compare(&commit, &entry) {
+                return Ok(Some(CosimEnd::Mismatch(self.report(hart.id, &problem, Some(&entry)))));
+            }
+            self.compared += 1;
+        }
+        Ok(None)
+    }
+}
+
+/// Runs the machine in lockstep with a reference commit log, comparing
+/// every retired instruction's pc, instruction word, privilege, register
+/// writes and memory writes. Reference lines before the pc each hart
+/// starts at are skipped. On a mismatch the report includes our last
+/// `context` instructions.
+pub fn run<R: BufRead>(machine: &mut Machine, reference: R, context: usize, limit: Option<u64>) -> Result<CosimEnd, String> {
+    machine.record_commits();
+    let mut cosim = Cosim {
+        reader: reference,
+        queues: vec![VecDeque::new(); machine.harts.len()],
+        synced: vec![false; machine.harts.len()],
+        history: VecDeque::with_capacity(context),
+        context,
+        compared: 0,
+    };
+    let mut count = 0;
+    loop {
+        if let Some(code) = machine.exit_code() {
+            return Ok(CosimEnd::Exited(code));
+        }
+        if limit.is_some_and(|limit| count >= limit) {
+            return Ok(CosimEnd::InstructionLimit);
+        }
+        machine.step();
+        count += 1;
+        if let Some(end) = cosim.check(machine)? {
+            return Ok(end);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Memory::RAM_BASE;
+
+    const PROGRAM: [u32; 7] = [
+        0x0010_0293, // li t0, 1
+        0x01F2_9293, // slli t0, t0, 31
+        0x0050_0513, // li a0, 5
+        0x10A2_B023, // sd a0, 0x100(t0)
+        0x1002_B583, // ld a1, 0x100(t0)
+        0x3405_9073, // csrw mscratch, a1
+        0x0000_006F, // 1: j 1b
+    ];
+
+    /// What Spike logs for `PROGRAM` with `-l --log-commits`, after two
+    /// instructions of its boot ROM.
+    const REFERENCE: &str = "\
+core   0: 0x0000000000001000 (0x00000297) auipc   t0, 0x0
+core   0: 3 0x0000000000001000 (0x00000297) x5  0x0000000000001000
+core   0: 0x0000000000001004 (0x02028593) addi    a1, t0, 32
+core   0: 3 0x0000000000001004 (0x02028593) x11 0x0000000000001020
+core   0: 0x0000000080000000 (0x00100293) li      t0, 1
+core   0: 3 0x0000000080000000 (0x00100293) x5  0x0000000000000001
+core   0: 3 0x0000000080000004 (0x01f29293) x5  0x0000000080000000
+core   0: 3 0x0000000080000008 (0x00500513) x10 0x0000000000000005
+core   0: 3 0x000000008000000c (0x10a2b023) mem 0x0000000080000100 0x0000000000000005
+core   0: 3 0x0000000080000010 (0x1002b583) x11 0x0000000000000005 mem 0x0000000080000100
+core   0: 3 0x0000000080000014 (0x34059073) c832_mscratch 0x0000000000000005
+core   0: 3 0x0000000080000018 (0x0000006f)
+";
+
+    fn machine() -> Machine {
+        let mut machine = Machine::new(1, 1 << 16);
+        for (i, word) in PROGRAM.iter().enumerate() {
+            machine.bus.store(RAM_BASE + i as u64 * 4, 4, *word as u64);
+        }
+        machine.harts[0].pc = RAM_BASE;
+        machine
+    }
+
+    fn cosim(reference: &str, context: usize) -> CosimEnd {
+        run(&mut machine(), reference.as_bytes(), context, Some(7)).unwrap()
+    }
+
+    fn mismatch(end: CosimEnd) -> String {
+        match end {
+            CosimEnd::Mismatch(report) => report,
+            other => panic!("expected a mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_commit_lines() {
+        let (hart, entry) = parse_line("core   1: 1 0x0000000080000004 (0x4515) x10 0x0000000000000005").unwrap();
+        assert_eq!((hart, entry.privilege, entry.pc, entry.raw), (1, 1, 0x8000_0004, 0x4515));
+        assert_eq!(entry.reg_writes, [(10, 5)]);
+
+        // CSR and FP writes are skipped; `mem` with a value is a store.
+        let line = "core   0: 3 0x80000010 (0x0002a503) c1_fflags 0x01 f10 0x3ff0000000000000 x10 0x00000005 \
+                    mem 0x80000100 mem 0x80000200 0x0007 # lw      a0, 0(t0)";
+        let (_, entry) = parse_line(line).unwrap();
+        assert_eq!(entry.reg_writes, [(10, 5)]);
+        assert_eq!(entry.stores, [(0x8000_0200, 7)]);
+        assert_eq!(entry.line, line);
+
+        // the disassembly lines of Spike's `-l`, and anything else.
+        assert!(parse_line("core   0: 0x0000000080000000 (0x00100293) li      t0, 1").is_none());
+        assert!(parse_line("bbl loader").is_none());
+    }
+
+    #[test]
+    fn agrees_with_the_reference() {
+        assert_eq!(cosim(REFERENCE, 4), CosimEnd::InstructionLimit);
+        let end = run(&mut machine(), REFERENCE.as_bytes(), 4, Some(8)).unwrap();
+        assert_eq!(end, CosimEnd::ReferenceEnded);
+    }
+
+    #[test]
+    fn reports_a_divergent_register_write() {
+        let reference = REFERENCE.replace("x10 0x0000000000000005", "x10 0x0000000000000006");
+        let report = mismatch(cosim(&reference, 2));
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(
+            lines[0],
+            "co-simulation mismatch on hart 0 after 2 matching instructions: \
+             register writes are [(a, 5)], expected [(a, 6)]"
+        );
+        assert_eq!(lines[1], "expected: core   0: 3 0x0000000080000008 (0x00500513) x10 0x0000000000000006");
+        // only the last two of ours.
+        assert_eq!(lines[2], "last 2 instructions (ours):");
+        assert!(lines[3].starts_with("  core   0: 3 0x0000000080000004 (0x01f29293) x5  0x0000000080000000 #"));
+        assert!(lines[4].starts_with("  core   0: 3 0x0000000080000008 (0x00500513) x10 0x0000000000000005 #"));
+        assert_eq!(lines.len(), 5);
+    }
+
+    #[test]
+    fn reports_a_divergent_store() {
+        let reference = REFERENCE.replace("0x0000000080000100 0x0000000000000005", "0x0000000080000100 0x0000000000000007");
+        let report = mismatch(cosim(&reference, 0));
+        assert!(report.starts_with(
+            "co-simulation mismatch on hart 0 after 3 matching instructions: \
+             memory writes are [(80000100, 5)], expected [(80000100, 7)]\n"
+        ));
+        assert!(report.ends_with("last 0 instructions (ours):\n"), "{}", report);
+    }
+
+    #[test]
+    fn reports_a_reference_that_never_starts() {
+        let boot_rom: String = REFERENCE.lines().take(4).map(|line| format!("{}\n", line)).collect();
+        let report = mismatch(cosim(&boot_rom, 1));
+        assert!(report.contains("the reference log never reaches pc 0x80000000"), "{}", report);
+        assert!(!report.contains("expected:"));
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write as _;
+
+use crate::Cpu::Hart;
+use crate::Dwarf::{parse_line_tables, StringSections};
+use crate::Elf::{ElfFile, SymbolKind};
+
+/// Returns `true` for the conditional branches: `beq`, `bne`, `blt`,
+/// `bge`, `bltu`, `bgeu`, `c.beqz` and `c.bnez`.
+fn is_branch(raw: u32, len: u64) -> bool {
+    match len {
+        2 => raw & 0b11 == 0b01 && (raw >> 13) & 0b111 >= 0b110,
+        _ => raw & 0x7F == 0x63,
+    }
+}
+
+/// Which instructions retired and which way each conditional branch
+/// went. Needs `Hart::commit` to be recorded.
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    /// Times an instruction retired at each pc.
+    pcs: HashMap<u64, u64>,
+    /// Conditional branches by pc, as `(taken, not taken)` counts.
+    branches: HashMap<u64, (u64, u64)>,
+}
+
+/// What lcov wants to know about one source file.
+#[derive(Default)]
+struct FileCoverage {
+    /// Execution count per line: the most any of its instructions ran.
+    lines: BTreeMap<u64, u64>,
+    /// Per line, each branch on it in address order, `None` if it never ran.
+    branches: BTreeMap<u64, Vec<Option<(u64, u64)>>>,
+    /// `(line, name, calls)`.
+    functions: Vec<(u64, String, u64)>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts the instruction `hart` just ran, if it retired.
+    pub fn record(&mut self, hart: &Hart) {
+        let commit = match &hart.commit {
+            Some(commit) if commit.retired => commit,
+            _ => return,
+        };
+        *self.pcs.entry(commit.pc).or_default() += 1;
+        if is_branch(commit.raw, commit.len) {
+            let outcome = self.branches.entry(commit.pc).or_default();
+            if hart.pc != commit.pc.wrapping_add(commit.len) {
+                outcome.0 += 1;
+            } else {
+                outcome.1 += 1;
+            }
+        }
+    }
+
+    /// Maps the coverage through the program's `.debug_line` to source
+    /// lines and formats it as an lcov tracefile. Every instruction the
+    /// line table covers counts, run or not, so unexecuted lines and
+    /// branches show up as such.
+    pub fn lcov(&self, elf: &ElfFile) -> Result<String, String> {
+        let debug_line = elf.debug_section(".debug_line").ok_or("the program has no .debug_line; build it with -g")?;
+        let strings = StringSections {
+            debug_str: elf.debug_section(".debug_str"),
+            debug_line_str: elf.debug_section(".debug_line_str"),
+        };
+        let tables = parse_line_tables(debug_line, strings)?;
+
+        let mut files: BTreeMap<String, FileCoverage> = BTreeMap::new();
+        let mut ranges: Vec<(u64, u64, String, u64)> = Vec::new();
+        let mut seen: HashSet<u64> = HashSet::new();
+        for table in tables.iter() {
+            for (start, end, file, line) in table.ranges() {
+                if file.is_empty() || line == 0 {
+                    continue;
+                }
+                ranges.push((start, end, file.to_string(), line));
+                let coverage = files.entry(file.to_string()).or_default();
+                let hits = coverage.lines.entry(line).or_default();
+                let mut pc = start;
+                while pc < end {
+                    let lo = match elf.bytes_at(pc, 2) {
+                        Some(bytes) => u16::from_le_bytes([bytes[0], bytes[1]]) as u32,
+                        None => break,
+                    };
+                    let (raw, len) = if lo & 0b11 != 0b11 {
+                        (lo, 2)
+                    } else {
+                        match elf.bytes_at(pc, 4) {
+                            Some(bytes) => (u32::from_le_bytes(bytes.try_into().unwrap()), 4),
+                            None => break,
+                        }
+                    };
+                    *hits = (*hits).max(self.pcs.get(&pc).copied().unwrap_or(0));
+                    if is_branch(raw, len) && seen.insert(pc) {
+                        coverage.branches.entry(line).or_default().push(self.branches.get(&pc).copied());
+                    }
+                    pc += len;
+                }
+            }
+        }
+
+        ranges.sort_by_key(|r| r.0);
+        for symbol in elf.symbols.iter().filter(|s| s.kind == SymbolKind::Function) {
+            let index = ranges.partition_point(|r| r.0 <= symbol.value);
+            if let Some((_, end, file, line)) = index.checked_sub(1).map(|i| &ranges[i]) {
+                if symbol.value < *end {
+                    let calls = self.pcs.get(&symbol.value).copied().unwrap_or(0);
+                    files.get_mut(file).unwrap().functions.push((*line, symbol.name.clone(), calls));
+                }
+            }
+        }
+
+        let mut out = String::new();
+        for (path, coverage) in files.iter() {
+            let _ = writeln!(out, "TN:\nSF:{}", path);
+            for (line, name, _) in coverage.functions.iter() {
+                let _ = writeln!(out, "FN:{},{}", line, name);
+            }
+            for (_, name, calls) in coverage.functions.iter() {
+                let _ = writeln!(out, "FNDA:{},{}", calls, name);
+            }
+            let functions_hit = coverage.functions.iter().filter(|f| f.2 > 0).count();
+            let _ = writeln!(out, "FNF:{}\nFNH:{}", coverage.functions.len(), functions_hit);
+
+            let (mut found, mut hit) = (0, 0);
+            for (line, branches) in coverage.branches.iter() {
+                for (i, outcome) in branches.iter().enumerate() {
+                    let counts = match outcome {
+                        Some((taken, not_taken)) => [taken.to_string(), not_taken.to_string()],
+                        None => ["-".to_string(), "-".to_string()],
+                    };
+                    for (j, count) in counts.iter().enumerate() {
+                        let _ = writeln!(out, "BRDA:{},0,{},{}", line, i * 2 + j, count);
+                    }
+                    found += 2;
+                    hit += outcome.map_or(0, |(taken, not_taken)| (taken > 0) as usize + (not_taken > 0) as usize);
+                }
+            }
+            let _ = writeln!(out, "BRF:{}\nBRH:{}", found, hit);
+
+            for (line, count) in coverage.lines.iter() {
+                let _ = writeln!(out, "DA:{},{}", line, count);
+            }
+            let lines_hit = coverage.lines.values().filter(|count| **count > 0).count();
+            let _ = writeln!(out, "LF:{}\nLH:{}\nend_of_record", coverage.lines.len(), lines_hit);
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Elf::{Segment, Symbol};
+    use crate::Emulator::Machine;
+    use crate::Memory::RAM_BASE;
+
+    const PROGRAM: [u32; 6] = [
+        0x0030_0513, // main: li a0, 3
+        0xFFF5_0513, // 1: addi a0, a0, -1
+        0xFE05_1EE3, // bnez a0, 1b
+        0x0000_006F, // 2: j 2b
+        0x0005_0063, // unused: 3: beqz a0, 3b
+        0x0000_8067, // ret
+    ];
+
+    /// A DWARF 4 line table putting `main` on lines 10 to 12 of `main.c`,
+    /// the loop on line 11, and `unused` on lines 20 and 21 of `util.c`.
+    fn debug_line() -> Vec<u8> {
+        let mut unit = vec![4, 0, 0, 0, 0, 0, 1, 1, 1, 0xFB, 14, 13, 0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1];
+        unit.extend(b"\0main.c\0\0\0\0util.c\0\0\0\0\0");
+        let header_length = unit.len() as u32 - 6;
+        unit.splice(2..6, header_length.to_le_bytes());
+        unit.extend([0, 9, 2]);
+        unit.extend(RAM_BASE.to_le_bytes());
+        // line 10; +4 and line 11; +8 and line 12.
+        unit.extend([3, 9, 1, 75, 131]);
+        // util.c: line 20 at +4, line 21 at +4 more, the end at +4 more.
+        unit.extend([4, 2, 3, 8, 74, 75, 2, 4, 0, 1, 1]);
+        let mut section = (unit.len() as u32).to_le_bytes().to_vec();
+        section.extend(unit);
+        section
+    }
+
+    fn elf() -> ElfFile {
+        let symbol = |name: &str, offset, size| Symbol {
+            name: name.to_string(),
+            value: RAM_BASE + offset,
+            size,
+            kind: SymbolKind::Function,
+        };
+        ElfFile {
+            xlen: 64,
+            entry: RAM_BASE,
+            segments: vec![Segment {
+                vaddr: RAM_BASE,
+                paddr: RAM_BASE,
+                data: PROGRAM.iter().flat_map(|word| word.to_le_bytes()).collect(),
+                memsz: PROGRAM.len() as u64 * 4,
+            }],
+            symbols: vec![symbol("main", 0, 0x10), symbol("unused", 0x10, 8)],
+            debug_sections: vec![(".debug_line".to_string(), debug_line())],
+        }
+    }
+
+    #[test]
+    fn lcov() {
+        let elf = elf();
+        let mut machine = Machine::new(1, 1 << 16);
+        elf.load(&mut machine.bus).unwrap();
+        machine.harts[0].pc = elf.entry;
+        machine.enable_coverage();
+        // li, then the loop body three times, then `j` three times.
+        machine.run(Some(10));
+
+        let lcov = machine.coverage.as_ref().unwrap().lcov(&elf).unwrap();
+        let expected = [
+            "TN:",
+            "SF:main.c",
+            "FN:10,main",
+            "FNDA:1,main",
+            "FNF:1",
+            "FNH:1",
+            // the loop branch was taken twice and fell through once.
+            "BRDA:11,0,0,2",
+            "BRDA:11,0,1,1",
+            "BRF:2",
+            "BRH:2",
+            "DA:10,1",
+            "DA:11,3",
+            "DA:12,3",
+            "LF:3",
+            "LH:3",
+            "end_of_record",
+            "TN:",
+            "SF:util.c",
+            "FN:20,unused",
+            "FNDA:0,unused",
+            "FNF:1",
+            "FNH:0",
+            "BRDA:20,0,0,-",
+            "BRDA:20,0,1,-",
+            "BRF:2",
+            "BRH:0",
+            "DA:20,0",
+            "DA:21,0",
+            "LF:2",
+            "LH:0",
+            "end_of_record",
+        ];
+        assert_eq!(lcov.lines().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn needs_a_line_table() {
+        let mut elf = elf();
+        elf.debug_sections.clear();
+        assert!(Coverage::new().lcov(&elf).unwrap_err().contains(".debug_line"));
+    }
+}
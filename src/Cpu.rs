@@ -0,0 +1,1706 @@
+use std::sync::Arc;
+
+use crate::BlockCache::Block;
+use crate::Csr::*;
+use crate::Decode::Decoded;
+use crate::Execute::sext;
+use crate::Isa::Isa;
+use crate::Memory::Bus;
+use crate::Sbi::Sbi;
+use crate::Trace::Commit;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Privilege {
+    User = 0,
+    Supervisor = 1,
+    Machine = 3,
+}
+
+impl Privilege {
+    pub fn from_bits(bits: u64) -> Self {
+        match bits & 0b11 {
+            0 => Privilege::User,
+            1 => Privilege::Supervisor,
+            _ => Privilege::Machine,
+        }
+    }
+}
+
+/// Synchronous exception causes, as written to `mcause`/`scause`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    InstructionAddressMisaligned = 0,
+    InstructionAccessFault = 1,
+    IllegalInstruction = 2,
+    Breakpoint = 3,
+    LoadAddressMisaligned = 4,
+    LoadAccessFault = 5,
+    StoreAddressMisaligned = 6,
+    StoreAccessFault = 7,
+    EcallFromUMode = 8,
+    EcallFromSMode = 9,
+    EcallFromVSMode = 10,
+    EcallFromMMode = 11,
+    InstructionPageFault = 12,
+    LoadPageFault = 13,
+    StorePageFault = 15,
+    InstructionGuestPageFault = 20,
+    LoadGuestPageFault = 21,
+    VirtualInstruction = 22,
+    StoreGuestPageFault = 23,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trap {
+    pub exception: Exception,
+    pub tval: u64,
+    /// For guest-page faults, the guest physical address that faulted.
+    pub gpa: u64,
+}
+
+impl Trap {
+    pub fn new(exception: Exception, tval: u64) -> Self {
+        Self {
+            exception,
+            tval,
+            gpa: 0,
+        }
+    }
+
+    pub fn illegal(raw: u32) -> Self {
+        Self::new(Exception::IllegalInstruction, raw as u64)
+    }
+
+    /// What a guest gets for an instruction only the hypervisor may run,
+    /// so the hypervisor can emulate it.
+    pub fn virtual_instruction(raw: u32) -> Self {
+        Self::new(Exception::VirtualInstruction, raw as u64)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Fetch,
+    Load,
+    Store,
+}
+
+impl Access {
+    fn page_fault(self, addr: u64) -> Trap {
+        match self {
+            Access::Fetch => Trap::new(Exception::InstructionPageFault, addr),
+            Access::Load => Trap::new(Exception::LoadPageFault, addr),
+            Access::Store => Trap::new(Exception::StorePageFault, addr),
+        }
+    }
+
+    pub fn access_fault(self, addr: u64) -> Trap {
+        match self {
+            Access::Fetch => Trap::new(Exception::InstructionAccessFault, addr),
+            Access::Load => Trap::new(Exception::LoadAccessFault, addr),
+            Access::Store => Trap::new(Exception::StoreAccessFault, addr),
+        }
+    }
+
+    /// A fault of the G-stage translating the guest physical address
+    /// `gpa`; the guest virtual address goes in `tval` once known.
+    fn guest_page_fault(self, gpa: u64) -> Trap {
+        let exception = match self {
+            Access::Fetch => Exception::InstructionGuestPageFault,
+            Access::Load => Exception::LoadGuestPageFault,
+            Access::Store => Exception::StoreGuestPageFault,
+        };
+        Trap {
+            exception,
+            tval: gpa,
+            gpa,
+        }
+    }
+}
+
+/// ABI names of the integer registers, indexed by register number.
+pub const ABI_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2",
+    "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+    "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7",
+    "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+];
+
+pub const REG_A0: usize = 10;
+pub const REG_A1: usize = 11;
+pub const REG_A2: usize = 12;
+pub const REG_A6: usize = 16;
+pub const REG_A7: usize = 17;
+
+/// Returns the `misa` bit for an extension letter.
+pub fn misa_bit(ext: char) -> u64 {
+    1 << (ext.to_ascii_uppercase() as u8 - b'A')
+}
+
+const PAGE_SHIFT: u64 = 12;
+const PAGE_SIZE: u64 = 1 << PAGE_SHIFT;
+const PTE_V: u64 = 1 << 0;
+const PTE_R: u64 = 1 << 1;
+const PTE_W: u64 = 1 << 2;
+const PTE_X: u64 = 1 << 3;
+const PTE_U: u64 = 1 << 4;
+const PTE_A: u64 = 1 << 6;
+const PTE_D: u64 = 1 << 7;
+const PTE_PPN_SHIFT: u64 = 10;
+
+/// A page table format: Sv39 when S-mode runs at XLEN 64, Sv32 when it
+/// runs at XLEN 32, and their G-stage forms Sv39x4 and Sv32x4, whose
+/// guest physical addresses are two bits wider than the virtual ones and
+/// whose root tables are four times as large.
+struct Paging {
+    levels: u64,
+    /// Virtual page number bits per level.
+    vpn_bits: u64,
+    pte_size: u64,
+    /// The PPN field of PTEs and `satp`.
+    ppn_mask: u64,
+    /// PTE bits that must be zero.
+    reserved: u64,
+    /// Virtual addresses are this many bits, sign extended to 64 for Sv39
+    /// and zero extended for the others. The root table takes all the
+    /// bits above the lower levels'.
+    va_bits: u32,
+}
+
+const SV39: Paging = Paging {
+    levels: 3,
+    vpn_bits: 9,
+    pte_size: 8,
+    ppn_mask: (1 << 44) - 1,
+    reserved: 0x3FF << 54,
+    va_bits: 39,
+};
+
+const SV32: Paging = Paging {
+    levels: 2,
+    vpn_bits: 10,
+    pte_size: 4,
+    ppn_mask: (1 << 22) - 1,
+    reserved: 0,
+    va_bits: 32,
+};
+
+const SV39X4: Paging = Paging { va_bits: 41, ..SV39 };
+
+const SV32X4: Paging = Paging { va_bits: 34, ..SV32 };
+
+impl Paging {
+    fn valid(&self, vaddr: u64) -> bool {
+        let unused = 64 - self.va_bits;
+        match self.va_bits {
+            39 => ((vaddr as i64) << unused >> unused) as u64 == vaddr,
+            bits => vaddr >> bits == 0,
+        }
+    }
+}
+
+/// One stage of address translation: the page tables it walks, and what
+/// leaves are checked against.
+struct Stage {
+    paging: &'static Paging,
+    /// The physical address of the root table.
+    root: u64,
+    privilege: Privilege,
+    /// `mstatus.SUM` and `MXR`, or `vsstatus`'s in VS-mode.
+    sum: bool,
+    mxr: bool,
+    /// The G-stage, which translates guest physical addresses as U-mode
+    /// accesses and raises guest-page faults.
+    guest: bool,
+}
+
+impl Stage {
+    fn fault(&self, access: Access, addr: u64) -> Trap {
+        if self.guest { access.guest_page_fault(addr) } else { access.page_fault(addr) }
+    }
+
+    /// Walks the page tables for `addr`, faulting as `access` but checking
+    /// the leaf's permissions for `check`. A guest's VS-stage tables sit
+    /// in guest physical memory, reached through `gstage`.
+    fn walk(
+        &self,
+        bus: &mut Bus,
+        addr: u64,
+        access: Access,
+        check: Access,
+        gstage: Option<&Stage>,
+    ) -> Result<u64, Trap> {
+        let paging = self.paging;
+        if !paging.valid(addr) {
+            return Err(self.fault(access, addr));
+        }
+        // faults of the G-stage on the way report `addr` as well.
+        let guest = |bus: &mut Bus, gpa: u64, check: Access| match gstage {
+            Some(gstage) => gstage.walk(bus, gpa, access, check, None).map_err(|trap| Trap { tval: addr, ..trap }),
+            None => Ok(gpa),
+        };
+        let mut table = self.root;
+        for level in (0..paging.levels).rev() {
+            let shift = PAGE_SHIFT + paging.vpn_bits * level;
+            let bits = if level == paging.levels - 1 { paging.va_bits as u64 - shift } else { paging.vpn_bits };
+            let vpn = (addr >> shift) & ((1 << bits) - 1);
+            let pte_gpa = table + vpn * paging.pte_size;
+            let pte_addr = guest(bus, pte_gpa, Access::Load)?;
+            let pte = match bus.load(pte_addr, paging.pte_size as usize) {
+                Some(pte) => pte,
+                None => return Err(access.access_fault(addr)),
+            };
+            if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) || pte & paging.reserved != 0 {
+                return Err(self.fault(access, addr));
+            }
+            let ppn = (pte >> PTE_PPN_SHIFT) & paging.ppn_mask;
+            if pte & (PTE_R | PTE_X) == 0 {
+                table = ppn << PAGE_SHIFT;
+                continue;
+            }
+
+            // leaf entry, check the permissions.
+            let user = pte & PTE_U != 0;
+            let allowed = match self.privilege {
+                Privilege::User => user,
+                _ => !user || (check != Access::Fetch && self.sum),
+            } && match check {
+                Access::Fetch => pte & PTE_X != 0,
+                Access::Load => pte & PTE_R != 0 || (self.mxr && pte & PTE_X != 0),
+                Access::Store => pte & PTE_W != 0,
+            };
+            // superpages must be aligned to their size.
+            let superpage_mask = (1 << (paging.vpn_bits * level)) - 1;
+            if !allowed || ppn & superpage_mask != 0 {
+                return Err(self.fault(access, addr));
+            }
+            let mut updated = pte | PTE_A;
+            if access == Access::Store {
+                updated |= PTE_D;
+            }
+            if updated != pte {
+                // updating a guest's PTE is a store to guest memory.
+                let pte_addr = if gstage.is_some() { guest(bus, pte_gpa, Access::Store)? } else { pte_addr };
+                if !bus.store(pte_addr, paging.pte_size as usize, updated) {
+                    return Err(access.access_fault(addr));
+                }
+            }
+            let offset_mask = (1 << shift) - 1;
+            return Ok(((ppn << PAGE_SHIFT) & !offset_mask) | (addr & offset_mask));
+        }
+        Err(self.fault(access, addr))
+    }
+
+    /// Walks the page tables for `addr` without checking permissions or
+    /// touching the A/D bits.
+    fn debug_walk(&self, bus: &Bus, addr: u64, gstage: Option<&Stage>) -> Option<u64> {
+        let paging = self.paging;
+        if !paging.valid(addr) {
+            return None;
+        }
+        let mut table = self.root;
+        for level in (0..paging.levels).rev() {
+            let shift = PAGE_SHIFT + paging.vpn_bits * level;
+            let bits = if level == paging.levels - 1 { paging.va_bits as u64 - shift } else { paging.vpn_bits };
+            let vpn = (addr >> shift) & ((1 << bits) - 1);
+            let pte_gpa = table + vpn * paging.pte_size;
+            let pte_addr = match gstage {
+                Some(gstage) => gstage.debug_walk(bus, pte_gpa, None)?,
+                None => pte_gpa,
+            };
+            let bytes = bus.read_bytes(pte_addr, paging.pte_size as usize)?;
+            let pte = bytes.iter().rev().fold(0, |pte, &byte| (pte << 8) | byte as u64);
+            if pte & PTE_V == 0 {
+                return None;
+            }
+            let ppn = (pte >> PTE_PPN_SHIFT) & paging.ppn_mask;
+            if pte & (PTE_R | PTE_X) == 0 {
+                table = ppn << PAGE_SHIFT;
+                continue;
+            }
+            let offset_mask = (1 << shift) - 1;
+            return Some(((ppn << PAGE_SHIFT) & !offset_mask) | (addr & offset_mask));
+        }
+        None
+    }
+}
+
+/// Exceptions that can be delegated to S-mode through `medeleg`.
+const MEDELEG_MASK: u64 = 0xB3FF;
+/// ... and the `H` Extension's, from a guest's `ECALL` up.
+const MEDELEG_H: u64 = (1 << 10) | (0xF << 20);
+/// Exceptions that can be delegated on to VS-mode through `hedeleg`.
+const HEDELEG_MASK: u64 = 0xB1FF;
+
+/// The `hstatus` fields software can write; VSXL follows `mstatus.SXL`.
+const HSTATUS_WRITABLE: u64 = HSTATUS_GVA | HSTATUS_SPV | HSTATUS_SPVP | HSTATUS_HU
+    | HSTATUS_VTVM | HSTATUS_VTW | HSTATUS_VTSR;
+
+/// Interrupts in the order they are taken when several are pending.
+const INTERRUPT_PRIORITY: [u64; 9] = [
+    IRQ_M_EXT, IRQ_M_SOFT, IRQ_M_TIMER, IRQ_S_EXT, IRQ_S_SOFT, IRQ_S_TIMER,
+    IRQ_VS_EXT, IRQ_VS_SOFT, IRQ_VS_TIMER,
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsrFile {
+    pub mstatus: u64,
+    pub misa: u64,
+    pub medeleg: u64,
+    pub mideleg: u64,
+    pub mie: u64,
+    pub mip: u64,
+    pub mtvec: u64,
+    pub mcounteren: u64,
+    pub mscratch: u64,
+    pub mepc: u64,
+    pub mcause: u64,
+    pub mtval: u64,
+    pub mcycle: u64,
+    pub minstret: u64,
+    pub mcountinhibit: u64,
+    pub stvec: u64,
+    pub scounteren: u64,
+    pub sscratch: u64,
+    pub sepc: u64,
+    pub scause: u64,
+    pub stval: u64,
+    pub satp: u64,
+    /// `frm` in bits 7:5 and `fflags` in bits 4:0.
+    pub fcsr: u64,
+    pub vstart: u64,
+    /// `vxrm` in bits 2:1 and `vxsat` in bit 0.
+    pub vcsr: u64,
+    pub vl: u64,
+    /// `vill` is kept at bit 63, wherever the current XLEN shows it.
+    pub vtype: u64,
+    pub pmpcfg: [u64; 16],
+    pub pmpaddr: [u64; 64],
+    pub mtval2: u64,
+    pub mtinst: u64,
+    /// `hvip`, `hie` and `hip` are the VS-level bits of `mip` and `mie`.
+    pub hstatus: u64,
+    pub hedeleg: u64,
+    pub hideleg: u64,
+    pub hcounteren: u64,
+    pub htimedelta: u64,
+    pub htval: u64,
+    pub htinst: u64,
+    pub hgatp: u64,
+    /// UXL left out, as it follows `mstatus.SXL`.
+    pub vsstatus: u64,
+    pub vstvec: u64,
+    pub vsscratch: u64,
+    pub vsepc: u64,
+    pub vscause: u64,
+    pub vstval: u64,
+    pub vsatp: u64,
+}
+
+impl Default for CsrFile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CsrFile {
+    pub fn new() -> Self {
+        Self {
+            // UXL and SXL start at 64 bits; `set_isa` narrows them on RV32.
+            mstatus: (2 << 32) | (2 << 34),
+            misa: Isa::default().misa(),
+            medeleg: 0,
+            mideleg: 0,
+            mie: 0,
+            mip: 0,
+            mtvec: 0,
+            mcounteren: 0,
+            mscratch: 0,
+            mepc: 0,
+            mcause: 0,
+            mtval: 0,
+            mcycle: 0,
+            minstret: 0,
+            mcountinhibit: 0,
+            stvec: 0,
+            scounteren: 0,
+            sscratch: 0,
+            sepc: 0,
+            scause: 0,
+            stval: 0,
+            satp: 0,
+            fcsr: 0,
+            vstart: 0,
+            vcsr: 0,
+            vl: 0,
+            // nothing is configured until the first `vsetvl`.
+            vtype: VTYPE_VILL,
+            pmpcfg: [0; 16],
+            pmpaddr: [0; 64],
+            mtval2: 0,
+            mtinst: 0,
+            hstatus: 0,
+            hedeleg: 0,
+            hideleg: 0,
+            hcounteren: 0,
+            htimedelta: 0,
+            htval: 0,
+            htinst: 0,
+            hgatp: 0,
+            vsstatus: 0,
+            vstvec: 0,
+            vsscratch: 0,
+            vsepc: 0,
+            vscause: 0,
+            vstval: 0,
+            vsatp: 0,
+        }
+    }
+}
+
+/// The page instructions were last fetched from, so fetches from the same
+/// page under the same privilege level and `satp` skip the page walk.
+/// Cleared by `SFENCE.VMA`, the `HFENCE`s, and `satp`, `vsatp` and
+/// `hgatp` writes.
+#[derive(Debug, Clone, Copy)]
+struct FetchPage {
+    vpage: u64,
+    ppage: u64,
+    privilege: Privilege,
+    virt: bool,
+    /// `vsatp` in a guest.
+    satp: u64,
+}
+
+/// Where in a decoded block a hart is running.
+#[derive(Debug, Clone)]
+struct BlockCursor {
+    block: Arc<Block>,
+    index: usize,
+    /// The virtual pc of `block.insts[index]`.
+    pc: u64,
+    /// The bus's `BlockCache::generation` when the block was looked up.
+    generation: u64,
+}
+
+/// Blocks a hart has entered, by virtual pc: a direct-mapped cache in
+/// front of the bus's `BlockCache`, so jumping to code run before needs no
+/// translation or hash lookup.
+const JUMP_CACHE_SIZE: usize = 1024;
+
+#[derive(Debug, Clone)]
+struct JumpEntry {
+    pc: u64,
+    privilege: Privilege,
+    virt: bool,
+    /// The hart's `fetch_epoch` and the bus's `BlockCache::generation` the
+    /// entry is valid for.
+    epoch: u64,
+    generation: u64,
+    block: Arc<Block>,
+}
+
+/// Architectural state of a single hardware thread.
+#[derive(Debug, Clone)]
+pub struct Hart {
+    pub id: u64,
+    /// The extensions this hart implements.
+    pub isa: Isa,
+    pub x: [u64; 32],
+    /// Floating point registers, single-precision values NaN-boxed.
+    pub f: [u64; 32],
+    /// Vector registers, VLEN/8 bytes each and their elements in little
+    /// endian order; empty without a vector unit.
+    pub v: Vec<u8>,
+    pub pc: u64,
+    pub privilege: Privilege,
+    /// Virtualization mode: with it set, `privilege` is VS or VU-mode, and
+    /// without it HS or U-mode.
+    pub virt: bool,
+    pub csr: CsrFile,
+    /// What the current instruction did, kept only while commit logging.
+    pub commit: Option<Commit>,
+    fetch_page: Option<FetchPage>,
+    block: Option<BlockCursor>,
+    /// Allocated on first use.
+    jump_cache: Vec<Option<JumpEntry>>,
+    /// Bumped whenever virtual-to-physical mappings may have changed.
+    pub fetch_epoch: u64,
+    /// Set by an instruction that has to wait for another hart's
+    /// reservation; it neither retires nor moves `pc`, and runs again next
+    /// step.
+    pub waiting: bool,
+    /// The access `HLV`, `HLVX` or `HSV` makes as VS or VU-mode while it
+    /// runs, up to the trap it may take; `Fetch` for `HLVX`, whose loads
+    /// need execute permission.
+    pub hypervisor_access: Option<Access>,
+}
+
+impl Hart {
+    pub fn new(id: u64) -> Self {
+        Self {
+            id,
+            isa: Isa::default(),
+            x: [0; 32],
+            f: [0; 32],
+            v: vec![0; Isa::default().vlen() as usize * 4],
+            pc: 0,
+            privilege: Privilege::Machine,
+            virt: false,
+            csr: CsrFile::new(),
+            commit: None,
+            fetch_page: None,
+            block: None,
+            jump_cache: Vec::new(),
+            fetch_epoch: 0,
+            waiting: false,
+            hypervisor_access: None,
+        }
+    }
+
+    pub fn read_reg(&self, reg: usize) -> u64 {
+        self.x[reg]
+    }
+
+    /// Writes an integer register, keeping `x0` hardwired to `0`. At XLEN
+    /// 32 the value is sign extended from bit 31, so registers always hold
+    /// what the widest XLEN would see.
+    pub fn write_reg(&mut self, reg: usize, value: u64) {
+        if reg != 0 {
+            let value = if self.xlen() == 32 { sext(value, 32) } else { value };
+            self.x[reg] = value;
+            if let Some(commit) = &mut self.commit {
+                commit.reg_writes.push((reg, value));
+            }
+        }
+    }
+
+    /// Switches the hart to the base ISA and extensions in `isa`, for
+    /// `misa` too. S and U-mode start out at the same XLEN as M-mode. The
+    /// vector registers are cleared when VLEN changes.
+    pub fn set_isa(&mut self, isa: Isa) {
+        self.isa = isa;
+        self.csr.misa = isa.misa();
+        self.csr.mstatus &= writable_mstatus(&isa) | !(MSTATUS_FS | MSTATUS_VS | MSTATUS_GVA | MSTATUS_MPV);
+        self.virt &= isa.has('h');
+        if self.v.len() != isa.vlen() as usize * 4 {
+            self.v = vec![0; isa.vlen() as usize * 4];
+        }
+        let xl = if isa.xlen() == 32 { 1 } else { 2 };
+        self.csr.mstatus = (self.csr.mstatus & !(MSTATUS_UXL | MSTATUS_SXL)) | (xl << 32) | (xl << 34);
+        self.block = None;
+        self.jump_cache.clear();
+    }
+
+    /// The XLEN instructions run at: that of the base ISA in M-mode, and
+    /// `mstatus.SXL` and `UXL` below it. On RV32 those are fixed at 32.
+    /// Guests run at S-mode's XLEN, which `hstatus.VSXL` and
+    /// `vsstatus.UXL` report.
+    pub fn xlen(&self) -> u32 {
+        match self.privilege {
+            Privilege::Machine => self.isa.xlen(),
+            _ if self.virt => self.sxlen(),
+            Privilege::Supervisor => self.sxlen(),
+            Privilege::User => 16 << ((self.csr.mstatus & MSTATUS_UXL) >> 32),
+        }
+    }
+
+    /// The XLEN of S-mode, which also picks the `satp` format.
+    fn sxlen(&self) -> u32 {
+        16 << ((self.csr.mstatus & MSTATUS_SXL) >> 34)
+    }
+
+    /// What instructions decode as right now: `isa` at the current XLEN.
+    pub fn decode_isa(&self) -> Isa {
+        self.isa.with_xlen(self.xlen())
+    }
+
+    /// Truncates addresses and jump targets to the current XLEN.
+    pub fn xlen_mask(&self) -> u64 {
+        if self.xlen() == 32 { 0xFFFF_FFFF } else { u64::MAX }
+    }
+
+    /// Clears the pc bits that must be zero: bit 0, bit 1 too without `C`,
+    /// where instructions are 4-byte aligned, and those above XLEN.
+    pub fn pc_mask(&self) -> u64 {
+        let align = if self.isa.has('c') { !1 } else { !3 };
+        align & self.xlen_mask()
+    }
+
+    /// Writes a floating point register, marking the state dirty.
+    pub fn write_freg(&mut self, reg: usize, value: u64) {
+        self.f[reg] = value;
+        if let Some(commit) = &mut self.commit {
+            commit.freg_writes.push((reg, value));
+        }
+        self.dirty_fp();
+    }
+
+    /// Sets `mstatus.FS` to dirty, as writing any floating point state
+    /// does; in a guest `vsstatus.FS` too.
+    pub fn dirty_fp(&mut self) {
+        self.dirty(MSTATUS_FS);
+    }
+
+    /// Sets `mstatus.VS` to dirty, as every vector instruction does; in a
+    /// guest `vsstatus.VS` too.
+    pub fn dirty_vector(&mut self) {
+        self.dirty(MSTATUS_VS);
+    }
+
+    fn dirty(&mut self, field: u64) {
+        if self.csr.mstatus & field != field {
+            self.csr.mstatus |= field;
+            self.log_csr_write(MSTATUS, self.mstatus_view());
+        }
+        if self.virt && self.csr.vsstatus & field != field {
+            self.csr.vsstatus |= field;
+            self.log_csr_write(VSSTATUS, self.vsstatus_view());
+        }
+    }
+
+    /// Whether the floating point unit is on: `mstatus.FS`, and in a guest
+    /// `vsstatus.FS` too, is not off.
+    pub fn fp_enabled(&self) -> bool {
+        self.csr.mstatus & MSTATUS_FS != 0 && (!self.virt || self.csr.vsstatus & MSTATUS_FS != 0)
+    }
+
+    /// Whether the vector unit is on, by `mstatus.VS` and in a guest
+    /// `vsstatus.VS`.
+    pub fn vector_enabled(&self) -> bool {
+        self.csr.mstatus & MSTATUS_VS != 0 && (!self.virt || self.csr.vsstatus & MSTATUS_VS != 0)
+    }
+
+    /// Accrues floating point exception flags into `fflags`.
+    pub fn raise_fp_flags(&mut self, flags: u64) {
+        if flags != 0 {
+            self.csr.fcsr |= flags;
+            self.log_csr_write(FFLAGS, self.csr.fcsr & 0x1F);
+            self.dirty_fp();
+        }
+    }
+
+    /// Executes a single instruction, or takes a pending interrupt instead.
+    pub fn step(&mut self, bus: &mut Bus) {
+        if self.csr.mcountinhibit & 0b001 == 0 {
+            self.csr.mcycle = self.csr.mcycle.wrapping_add(1);
+        }
+        let xlen = self.xlen();
+        if let Some(commit) = &mut self.commit {
+            *commit = Commit::new(self.pc, self.privilege as u64, xlen);
+        }
+        bus.reservations.tick(self.id);
+        if let Some(irq) = self.pending_interrupt() {
+            self.take_trap(irq, 0, 0, true);
+            return;
+        }
+        let result = match self.cached_block(bus) {
+            Some(mut cursor) => {
+                let decoded = cursor.block.insts[cursor.index];
+                if let Some(commit) = &mut self.commit {
+                    commit.raw = decoded.raw;
+                    commit.len = decoded.size();
+                }
+                let result = self.execute(bus, &decoded);
+                cursor.pc = cursor.pc.wrapping_add(decoded.size());
+                cursor.index += 1;
+                if cursor.index < cursor.block.insts.len() {
+                    self.block = Some(cursor);
+                }
+                result
+            }
+            None => self.fetch(bus).and_then(|(raw, len)| {
+                if let Some(commit) = &mut self.commit {
+                    commit.raw = raw;
+                    commit.len = len;
+                }
+                match Decoded::decode(raw, &self.decode_isa()) {
+                    Some(decoded) => self.execute(bus, &decoded),
+                    None => Err(Trap::illegal(raw)),
+                }
+            }),
+        };
+        let result = match result {
+            // the built-in SBI answers in place of M-mode firmware.
+            Err(trap) if trap.exception == Exception::EcallFromSMode && !self.virt && bus.sbi.is_some() => {
+                Sbi::call(self, bus);
+                self.pc = self.pc.wrapping_add(4);
+                Ok(())
+            }
+            result => result,
+        };
+        match result {
+            Ok(()) if self.waiting => self.waiting = false,
+            Ok(()) => {
+                if self.csr.mcountinhibit & 0b100 == 0 {
+                    self.csr.minstret = self.csr.minstret.wrapping_add(1);
+                }
+                if let Some(commit) = &mut self.commit {
+                    commit.retired = true;
+                }
+            }
+            Err(trap) => self.take_trap(trap.exception as u64, trap.tval, trap.gpa, false),
+        }
+    }
+
+    /// The decoded block the instruction at `pc` comes from, positioned at
+    /// it, if the block cache can supply one. The next instruction of the
+    /// current block is used as long as execution ran straight on and no
+    /// code was overwritten; anything else looks the block up afresh.
+    /// `None` leaves the fetch, and any trap it raises, to `fetch`.
+    fn cached_block(&mut self, bus: &mut Bus) -> Option<BlockCursor> {
+        let generation = bus.blocks.generation();
+        if let Some(cursor) = self.block.take() {
+            if cursor.pc == self.pc && cursor.generation == generation {
+                return Some(cursor);
+            }
+        }
+        if !bus.blocks.enabled {
+            return None;
+        }
+        let slot = (self.pc >> 1) as usize % JUMP_CACHE_SIZE;
+        if let Some(Some(entry)) = self.jump_cache.get(slot) {
+            if entry.pc == self.pc
+                && entry.privilege == self.privilege
+                && entry.virt == self.virt
+                && entry.epoch == self.fetch_epoch
+                && entry.generation == generation
+            {
+                return Some(BlockCursor {
+                    block: entry.block.clone(),
+                    index: 0,
+                    pc: self.pc,
+                    generation,
+                });
+            }
+        }
+        let paddr = self.fetch_translate(bus)?;
+        let block = bus.blocks.lookup(&bus.ram, paddr, &self.decode_isa())?;
+        // the page walk may have set A bits in a page code came from.
+        let generation = bus.blocks.generation();
+        if self.jump_cache.is_empty() {
+            self.jump_cache = vec![None; JUMP_CACHE_SIZE];
+        }
+        self.jump_cache[slot] = Some(JumpEntry {
+            pc: self.pc,
+            privilege: self.privilege,
+            virt: self.virt,
+            epoch: self.fetch_epoch,
+            generation,
+            block: block.clone(),
+        });
+        Some(BlockCursor {
+            block,
+            index: 0,
+            pc: self.pc,
+            generation,
+        })
+    }
+
+    /// Translates `pc` for a fetch through `fetch_page`, walking the page
+    /// tables only when the page changes. `None` if the walk faults.
+    pub fn fetch_translate(&mut self, bus: &mut Bus) -> Option<u64> {
+        let (vpage, offset) = (self.pc >> PAGE_SHIFT, self.pc & (PAGE_SIZE - 1));
+        let satp = if self.virt { self.csr.vsatp } else { self.csr.satp };
+        if let Some(page) = self.fetch_page {
+            if page.vpage == vpage && page.privilege == self.privilege && page.virt == self.virt && page.satp == satp {
+                return Some((page.ppage << PAGE_SHIFT) | offset);
+            }
+        }
+        let paddr = self.translate(bus, self.pc, Access::Fetch).ok()?;
+        self.fetch_page = Some(FetchPage {
+            vpage,
+            ppage: paddr >> PAGE_SHIFT,
+            privilege: self.privilege,
+            virt: self.virt,
+            satp,
+        });
+        Some(paddr)
+    }
+
+    /// Forgets cached fetch translations, for the fences and writes to the
+    /// address translation CSRs.
+    pub fn flush_fetch_translation(&mut self) {
+        self.fetch_page = None;
+        self.block = None;
+        self.fetch_epoch += 1;
+    }
+
+    /// Fetches the instruction at `pc`, returning it with its length in
+    /// bytes. Compressed instructions are returned as is.
+    pub fn fetch(&mut self, bus: &mut Bus) -> Result<(u32, u64), Trap> {
+        let pc = self.pc;
+        let lo = self.load_raw(bus, pc, 2, Access::Fetch)? as u32;
+        if lo & 0b11 != 0b11 {
+            return Ok((lo, 2));
+        }
+        // the upper half may live on another page.
+        let hi = self.load_raw(bus, pc.wrapping_add(2), 2, Access::Fetch)? as u32;
+        Ok(((hi << 16) | lo, 4))
+    }
+
+    /// Drives the lines the CLINT holds for this hart into `mip`. Under the
+    /// built-in SBI, which stands in for M-mode firmware, the timer shows
+    /// up as `STIP` and an IPI as `SSIP`, as the firmware would forward
+    /// them, and remote fences aimed at the hart take effect.
+    pub fn sample_interrupts(&mut self, bus: &mut Bus) {
+        let lines = bus.clint.interrupts(self.id, bus.mtime);
+        match &mut bus.sbi {
+            None => self.csr.mip = (self.csr.mip & !(MIP_MSIP | MIP_MTIP)) | lines,
+            Some(sbi) => {
+                self.csr.mip = set_bit(self.csr.mip, MIP_STIP, lines & MIP_MTIP != 0);
+                if lines & MIP_MSIP != 0 {
+                    bus.clint.msip[self.id as usize] = false;
+                    self.csr.mip |= MIP_SSIP;
+                }
+                if sbi.take_fence(self.id) {
+                    self.flush_fetch_translation();
+                }
+            }
+        }
+    }
+
+    /// Returns the `mcause` value of the interrupt that should be taken now,
+    /// if there is one. Interrupts for HS-mode are always enabled in a
+    /// guest, and those `hideleg` passes on to VS-mode only there.
+    pub fn pending_interrupt(&self) -> Option<u64> {
+        let pending = self.csr.mip & self.csr.mie;
+        if pending == 0 {
+            return None;
+        }
+        let mideleg = self.mideleg();
+        let m_enabled = self.privilege != Privilege::Machine
+            || self.csr.mstatus & MSTATUS_MIE != 0;
+        let s_enabled = self.virt
+            || self.privilege == Privilege::User
+            || (self.privilege == Privilege::Supervisor && self.csr.mstatus & MSTATUS_SIE != 0);
+        let vs_enabled = self.virt
+            && (self.privilege == Privilege::User || self.csr.vsstatus & MSTATUS_SIE != 0);
+        let mut enabled = 0;
+        if m_enabled {
+            enabled |= pending & !mideleg;
+        }
+        if s_enabled {
+            enabled |= pending & mideleg & !self.csr.hideleg;
+        }
+        if vs_enabled {
+            enabled |= pending & mideleg & self.csr.hideleg;
+        }
+        INTERRUPT_PRIORITY
+            .iter()
+            .find(|irq| enabled & (1 << **irq) != 0)
+            .map(|irq| (1 << 63) | irq)
+    }
+
+    /// `mideleg`, where the VS-level interrupts always read as delegated
+    /// with the `H` Extension.
+    fn mideleg(&self) -> u64 {
+        if self.isa.has('h') { self.csr.mideleg | VS_INTERRUPTS } else { self.csr.mideleg }
+    }
+
+    /// Enters the trap handler for `cause`, delegating to HS-mode when
+    /// `medeleg`/`mideleg` allow it, and from a guest on to VS-mode when
+    /// `hedeleg`/`hideleg` do too. Guest-page faults leave `gpa`, shifted
+    /// right by 2, in `htval` or `mtval2`. The interrupt bit of `cause`
+    /// moves to bit 31 when the handler runs at XLEN 32.
+    pub fn take_trap(&mut self, cause: u64, tval: u64, gpa: u64, interrupt: bool) {
+        let code = cause & !(1 << 63);
+        let interrupt_bit = |xlen: u32| if interrupt { 1 << (xlen - 1) } else { 0 };
+        let (deleg, hdeleg) = match interrupt {
+            true => (self.mideleg(), self.csr.hideleg),
+            false => (self.csr.medeleg, self.csr.hedeleg),
+        };
+        let to_s = self.privilege != Privilege::Machine && (deleg >> code) & 1 == 1;
+        let to_vs = to_s && self.virt && (hdeleg >> code) & 1 == 1;
+        // whether `tval` is an address a guest, or `HLV`/`HSV` on its
+        // behalf, accessed, and so a guest virtual one.
+        let gva = !interrupt && match code {
+            0 | 1 | 3 | 12 | 20 => self.virt,
+            4..=7 | 13 | 15 | 21 | 23 => self.data_mode().1,
+            _ => false,
+        };
+        // a narrower XLEN's pc is seen sign extended by a wider one.
+        let pc = if self.xlen() == 32 { sext(self.pc, 32) } else { self.pc };
+        self.block = None;
+        self.hypervisor_access = None;
+        if to_vs {
+            // the VS-level interrupts are the S-level ones to the guest.
+            let code = if interrupt { code - 1 } else { code };
+            let mut status = self.csr.vsstatus;
+            status = set_bit(status, MSTATUS_SPIE, status & MSTATUS_SIE != 0);
+            status = set_bit(status, MSTATUS_SPP, self.privilege == Privilege::Supervisor);
+            status &= !MSTATUS_SIE;
+            self.csr.vsstatus = status;
+            self.csr.vsepc = pc;
+            self.csr.vscause = code | interrupt_bit(self.sxlen());
+            self.csr.vstval = tval;
+            self.privilege = Privilege::Supervisor;
+            self.pc = trap_vector(self.csr.vstvec, code, interrupt);
+        } else if to_s {
+            let mut status = self.csr.mstatus;
+            status = set_bit(status, MSTATUS_SPIE, status & MSTATUS_SIE != 0);
+            status = set_bit(status, MSTATUS_SPP, self.privilege == Privilege::Supervisor);
+            status &= !MSTATUS_SIE;
+            self.csr.mstatus = status;
+            if self.isa.has('h') {
+                let mut status = set_bit(self.csr.hstatus, HSTATUS_SPV, self.virt);
+                if self.virt {
+                    status = set_bit(status, HSTATUS_SPVP, self.privilege == Privilege::Supervisor);
+                }
+                self.csr.hstatus = set_bit(status, HSTATUS_GVA, gva);
+                self.csr.htval = gpa >> 2;
+                self.csr.htinst = 0;
+            }
+            self.csr.sepc = pc;
+            self.csr.scause = code | interrupt_bit(self.sxlen());
+            self.csr.stval = tval;
+            self.privilege = Privilege::Supervisor;
+            self.virt = false;
+            self.pc = trap_vector(self.csr.stvec, code, interrupt);
+        } else {
+            let mut status = self.csr.mstatus;
+            status = set_bit(status, MSTATUS_MPIE, status & MSTATUS_MIE != 0);
+            status = (status & !MSTATUS_MPP) | ((self.privilege as u64) << 11);
+            status &= !MSTATUS_MIE;
+            if self.isa.has('h') {
+                status = set_bit(status, MSTATUS_MPV, self.virt);
+                status = set_bit(status, MSTATUS_GVA, gva);
+                self.csr.mtval2 = gpa >> 2;
+                self.csr.mtinst = 0;
+            }
+            self.csr.mstatus = status;
+            self.csr.mepc = pc;
+            self.csr.mcause = code | interrupt_bit(self.isa.xlen());
+            self.csr.mtval = tval;
+            self.privilege = Privilege::Machine;
+            self.virt = false;
+            self.pc = trap_vector(self.csr.mtvec, code, interrupt);
+        }
+    }
+
+    /// Returns from an M-mode trap handler, into a guest if `mstatus.MPV`
+    /// says the trap came from one.
+    pub fn mret(&mut self) {
+        let status = self.csr.mstatus;
+        let mpp = Privilege::from_bits(status >> 11);
+        let mut status = set_bit(status, MSTATUS_MIE, status & MSTATUS_MPIE != 0);
+        status |= MSTATUS_MPIE;
+        status &= !MSTATUS_MPP;
+        if mpp != Privilege::Machine {
+            status &= !MSTATUS_MPRV;
+        }
+        self.virt = mpp != Privilege::Machine && status & MSTATUS_MPV != 0;
+        status &= !MSTATUS_MPV;
+        self.csr.mstatus = status;
+        self.log_csr_write(MSTATUS, self.mstatus_view());
+        self.privilege = mpp;
+        self.pc = self.csr.mepc & self.pc_mask();
+    }
+
+    /// Returns from an S-mode trap handler: HS-mode's, into a guest if
+    /// `hstatus.SPV` says the trap came from one, or the guest's own,
+    /// through `vsstatus` and `vsepc`.
+    pub fn sret(&mut self) {
+        if self.virt {
+            let status = self.csr.vsstatus;
+            let spp = if status & MSTATUS_SPP != 0 { Privilege::Supervisor } else { Privilege::User };
+            let mut status = set_bit(status, MSTATUS_SIE, status & MSTATUS_SPIE != 0);
+            status |= MSTATUS_SPIE;
+            status &= !MSTATUS_SPP;
+            self.csr.vsstatus = status;
+            self.log_csr_write(VSSTATUS, self.vsstatus_view());
+            self.privilege = spp;
+            self.pc = self.csr.vsepc & self.pc_mask();
+            return;
+        }
+        let status = self.csr.mstatus;
+        let spp = if status & MSTATUS_SPP != 0 { Privilege::Supervisor } else { Privilege::User };
+        let mut status = set_bit(status, MSTATUS_SIE, status & MSTATUS_SPIE != 0);
+        status |= MSTATUS_SPIE;
+        status &= !(MSTATUS_SPP | MSTATUS_MPRV);
+        self.csr.mstatus = status;
+        self.log_csr_write(MSTATUS, self.mstatus_view());
+        self.virt = self.csr.hstatus & HSTATUS_SPV != 0;
+        self.csr.hstatus &= !HSTATUS_SPV;
+        self.privilege = spp;
+        self.pc = self.csr.sepc & self.pc_mask();
+    }
+
+    /// Checks that the current privilege level may access `addr` at all,
+    /// and may write it if `write` is set. What a guest may not do but
+    /// HS-mode (or U-mode, for a guest in VU-mode) could raises the
+    /// virtual instruction exception, so the hypervisor can emulate it;
+    /// the rest is illegal.
+    fn csr_access(&self, addr: usize, write: bool) -> Result<(), Exception> {
+        let level = (addr >> 8) & 0b11;
+        // HS-mode has the hypervisor CSRs at level 2 too.
+        let current = match self.privilege {
+            Privilege::Machine => 3,
+            Privilege::Supervisor if self.virt => 1,
+            Privilege::Supervisor => 2,
+            Privilege::User => 0,
+        };
+        if write && (addr >> 10) & 0b11 == 0b11 {
+            return Err(Exception::IllegalInstruction);
+        }
+        if current < level {
+            let virtual_instruction = self.virt && level <= 2;
+            return Err(if virtual_instruction { Exception::VirtualInstruction } else { Exception::IllegalInstruction });
+        }
+        if (FFLAGS..=FCSR).contains(&addr) && !self.fp_enabled() {
+            return Err(Exception::IllegalInstruction);
+        }
+        if matches!(addr, VSTART | VXSAT | VXRM | VCSR | VL | VTYPE | VLENB) && !self.vector_enabled() {
+            return Err(Exception::IllegalInstruction);
+        }
+        // `mstatus.TVM` traps HS-mode's `satp` and `hgatp`,
+        // `hstatus.VTVM` a guest's `satp`.
+        if matches!(addr, SATP | HGATP) && self.privilege == Privilege::Supervisor {
+            if self.virt && self.csr.hstatus & HSTATUS_VTVM != 0 {
+                return Err(Exception::VirtualInstruction);
+            }
+            if !self.virt && self.csr.mstatus & MSTATUS_TVM != 0 {
+                return Err(Exception::IllegalInstruction);
+            }
+        }
+        // user-level counters are gated by `mcounteren`, `hcounteren` in a
+        // guest, and `scounteren`.
+        if (CYCLE..=HPMCOUNTER31).contains(&addr) || (CYCLEH..=HPMCOUNTER31H).contains(&addr) {
+            let bit = 1 << (addr & 0x1F);
+            if self.privilege != Privilege::Machine && self.csr.mcounteren & bit == 0 {
+                return Err(Exception::IllegalInstruction);
+            }
+            if self.virt && self.csr.hcounteren & bit == 0 {
+                return Err(Exception::VirtualInstruction);
+            }
+            if self.privilege == Privilege::User && self.csr.scounteren & bit == 0 {
+                return Err(if self.virt { Exception::VirtualInstruction } else { Exception::IllegalInstruction });
+            }
+        }
+        Ok(())
+    }
+
+    /// The CSR a guest's access to `addr` reaches: the supervisor CSRs
+    /// that have VS copies stand for those.
+    fn virtual_csr(&self, addr: usize) -> usize {
+        if !self.virt {
+            return addr;
+        }
+        match addr {
+            SSTATUS => VSSTATUS,
+            SIE => VSIE,
+            STVEC => VSTVEC,
+            SSCRATCH => VSSCRATCH,
+            SEPC => VSEPC,
+            SCAUSE => VSCAUSE,
+            STVAL => VSTVAL,
+            SIP => VSIP,
+            SATP => VSATP,
+            _ => addr,
+        }
+    }
+
+    /// Reads a CSR as the current XLEN sees it, returning `None` if it does
+    /// not exist.
+    pub fn csr_read(&self, bus: &Bus, addr: usize) -> Option<u64> {
+        let addr = self.virtual_csr(addr);
+        let csr = &self.csr;
+        // a guest's time is offset by `htimedelta`.
+        let time = if self.virt { bus.mtime.wrapping_add(csr.htimedelta) } else { bus.mtime };
+        let value = match addr {
+            FFLAGS | FRM | FCSR if !self.isa.has('f') => return None,
+            VSTART | VXSAT | VXRM | VCSR | VL | VTYPE | VLENB if self.isa.vlen() == 0 => return None,
+            _ if hypervisor_csr(addr) && !self.isa.has('h') => return None,
+            CYCLEH..=HPMCOUNTER31H | MCYCLEH..=MHPMCOUNTER31H | MSTATUSH | MENVCFGH | HENVCFGH | HTIMEDELTAH
+                if self.isa.xlen() != 32 => return None,
+            CYCLEH | MCYCLEH => csr.mcycle >> 32,
+            TIMEH => time >> 32,
+            INSTRETH | MINSTRETH => csr.minstret >> 32,
+            HPMCOUNTER3H..=HPMCOUNTER31H | MHPMCOUNTER3H..=MHPMCOUNTER31H => 0,
+            MSTATUSH => (csr.mstatus >> 32) & ((MSTATUS_GVA | MSTATUS_MPV) >> 32),
+            MENVCFGH | HENVCFGH => 0,
+            HTIMEDELTAH => csr.htimedelta >> 32,
+            FFLAGS => csr.fcsr & 0x1F,
+            FRM => csr.fcsr >> 5,
+            FCSR => csr.fcsr,
+            VSTART => csr.vstart,
+            VXSAT => csr.vcsr & 1,
+            VXRM => csr.vcsr >> 1,
+            VCSR => csr.vcsr,
+            VL => csr.vl,
+            VTYPE => csr.vtype,
+            VLENB => self.isa.vlen() as u64 / 8,
+            CYCLE | MCYCLE => csr.mcycle,
+            TIME => time,
+            INSTRET | MINSTRET => csr.minstret,
+            HPMCOUNTER3..=HPMCOUNTER31 => 0,
+            MHPMCOUNTER3..=MHPMCOUNTER31 => 0,
+            MHPMEVENT3..=MHPMEVENT31 => 0,
+            SSTATUS => sstatus_view(csr.mstatus),
+            SIE => csr.mie & csr.mideleg,
+            STVEC => csr.stvec,
+            SCOUNTEREN => csr.scounteren,
+            SENVCFG => 0,
+            SSCRATCH => csr.sscratch,
+            SEPC => csr.sepc & self.pc_mask(),
+            SCAUSE => csr.scause,
+            STVAL => csr.stval,
+            SIP => csr.mip & csr.mideleg,
+            SATP => csr.satp,
+            HSTATUS => csr.hstatus | ((csr.mstatus & MSTATUS_SXL) >> 2),
+            HEDELEG => csr.hedeleg,
+            HIDELEG => csr.hideleg,
+            HIE => csr.mie & VS_INTERRUPTS,
+            HIP | HVIP => csr.mip & VS_INTERRUPTS,
+            // no guest external interrupts are implemented.
+            HGEIE | HGEIP => 0,
+            HCOUNTEREN => csr.hcounteren,
+            HENVCFG => 0,
+            HTIMEDELTA => csr.htimedelta,
+            HTVAL => csr.htval,
+            HTINST => csr.htinst,
+            HGATP => csr.hgatp,
+            VSSTATUS => self.vsstatus(),
+            // the VS-level interrupts sit one bit above the S-level ones
+            // the guest sees them as.
+            VSIE => (csr.mie & csr.hideleg) >> 1,
+            VSIP => (csr.mip & csr.hideleg) >> 1,
+            VSTVEC => csr.vstvec,
+            VSSCRATCH => csr.vsscratch,
+            VSEPC => csr.vsepc & self.pc_mask(),
+            VSCAUSE => csr.vscause,
+            VSTVAL => csr.vstval,
+            VSATP => csr.vsatp,
+            MVENDORID | MARCHID | MIMPID | MCONFIGPTR => 0,
+            MHARTID => self.id,
+            MSTATUS => status_with_sd(csr.mstatus),
+            MISA => csr.misa,
+            MEDELEG => csr.medeleg,
+            MIDELEG => self.mideleg(),
+            MIE => csr.mie,
+            MTVEC => csr.mtvec,
+            MCOUNTEREN => csr.mcounteren,
+            MENVCFG => 0,
+            MSCRATCH => csr.mscratch,
+            MEPC => csr.mepc & self.pc_mask(),
+            MCAUSE => csr.mcause,
+            MTVAL => csr.mtval,
+            MIP => csr.mip,
+            MTVAL2 => csr.mtval2,
+            MTINST => csr.mtinst,
+            MCOUNTINHIBIT => csr.mcountinhibit,
+            // only the even `pmpcfg` registers exist on RV64.
+            PMPCFG0..=PMPCFG15 if self.isa.xlen() == 32 || addr.is_multiple_of(2) => csr.pmpcfg[addr - PMPCFG0],
+            PMPADDR0..=PMPADDR63 => csr.pmpaddr[addr - PMPADDR0],
+            // no triggers are implemented; `tselect` reads back as 0 and
+            // `tdata1` reports no trigger there.
+            TSELECT | TDATA1 | TDATA2 | TDATA3 => 0,
+            _ => return None,
+        };
+        Some(if self.xlen() == 32 { narrow_csr(addr, value) } else { value })
+    }
+
+    /// `mstatus` as the current XLEN reads it, for the commit log.
+    fn mstatus_view(&self) -> u64 {
+        let status = status_with_sd(self.csr.mstatus);
+        if self.xlen() == 32 { narrow_csr(MSTATUS, status) } else { status }
+    }
+
+    /// `vsstatus`, with UXL following `mstatus.SXL`.
+    fn vsstatus(&self) -> u64 {
+        sstatus_view(self.csr.vsstatus) | ((self.csr.mstatus & MSTATUS_SXL) >> 2)
+    }
+
+    /// `vsstatus` as the current XLEN reads it, for the commit log.
+    fn vsstatus_view(&self) -> u64 {
+        if self.xlen() == 32 { narrow_csr(VSSTATUS, self.vsstatus()) } else { self.vsstatus() }
+    }
+
+    /// Writes a CSR, applying its WARL rules. `value` is as wide as the
+    /// current XLEN. Returns `false` if the CSR does not exist.
+    pub fn csr_write(&mut self, addr: usize, value: u64) -> bool {
+        let addr = self.virtual_csr(addr);
+        let old_status = self.csr.mstatus;
+        let sxlen = self.sxlen();
+        let value = if self.xlen() == 32 { self.widen_csr(addr, value & 0xFFFF_FFFF) } else { value };
+        // the VS-level interrupts are only there with the `H` Extension.
+        let vs_interrupts = if self.isa.has('h') { VS_INTERRUPTS } else { 0 };
+        let csr = &mut self.csr;
+        match addr {
+            FFLAGS | FRM | FCSR if !self.isa.has('f') => return false,
+            VSTART | VXSAT | VXRM | VCSR if self.isa.vlen() == 0 => return false,
+            _ if hypervisor_csr(addr) && !self.isa.has('h') => return false,
+            CYCLEH..=HPMCOUNTER31H | MCYCLEH..=MHPMCOUNTER31H | MSTATUSH | MENVCFGH | HENVCFGH | HTIMEDELTAH
+                if self.isa.xlen() != 32 => return false,
+            MCYCLEH => csr.mcycle = value,
+            MINSTRETH => csr.minstret = value,
+            MHPMCOUNTER3H..=MHPMCOUNTER31H | MENVCFGH | HENVCFGH => {},
+            MSTATUSH => {
+                let mask = writable_mstatus(&self.isa) & (MSTATUS_GVA | MSTATUS_MPV);
+                csr.mstatus = (csr.mstatus & !mask) | ((value << 32) & mask);
+            },
+            HTIMEDELTAH => csr.htimedelta = value,
+            FFLAGS | FRM | FCSR => {
+                csr.fcsr = match addr {
+                    FFLAGS => (csr.fcsr & !0x1F) | (value & 0x1F),
+                    FRM => (csr.fcsr & 0x1F) | ((value & 0b111) << 5),
+                    _ => value & 0xFF,
+                };
+                self.dirty_fp();
+            },
+            // `vstart` only holds element indices below VLEN, the largest
+            // VLMAX.
+            VSTART => {
+                csr.vstart = value & (self.isa.vlen() as u64 - 1);
+                self.dirty_vector();
+            },
+            VXSAT | VXRM | VCSR => {
+                csr.vcsr = match addr {
+                    VXSAT => (csr.vcsr & !1) | (value & 1),
+                    VXRM => (csr.vcsr & 1) | ((value & 0b11) << 1),
+                    _ => value & 0b111,
+                };
+                self.dirty_vector();
+            },
+            MCYCLE => csr.mcycle = value,
+            MINSTRET => csr.minstret = value,
+            MHPMCOUNTER3..=MHPMCOUNTER31 | MHPMEVENT3..=MHPMEVENT31 => {},
+            SSTATUS => {
+                let mask = SSTATUS_MASK & writable_mstatus(&self.isa);
+                csr.mstatus = legal_status(csr.mstatus, (csr.mstatus & !mask) | (value & mask));
+            },
+            SIE => {
+                let mask = csr.mideleg;
+                csr.mie = (csr.mie & !mask) | (value & mask);
+            },
+            STVEC => csr.stvec = legal_tvec(value),
+            SCOUNTEREN => csr.scounteren = value & 0xFFFF_FFFF,
+            SENVCFG => {},
+            SSCRATCH => csr.sscratch = value,
+            SEPC => csr.sepc = value & !1,
+            SCAUSE => csr.scause = value,
+            STVAL => csr.stval = value,
+            SIP => {
+                let mask = csr.mideleg & MIP_SSIP;
+                csr.mip = (csr.mip & !mask) | (value & mask);
+            },
+            SATP => {
+                if let Some(satp) = legal_satp(sxlen, value) {
+                    csr.satp = satp;
+                    self.flush_fetch_translation();
+                }
+            },
+            HSTATUS => csr.hstatus = value & HSTATUS_WRITABLE,
+            HEDELEG => csr.hedeleg = value & HEDELEG_MASK,
+            HIDELEG => csr.hideleg = value & VS_INTERRUPTS,
+            HIE => csr.mie = (csr.mie & !VS_INTERRUPTS) | (value & VS_INTERRUPTS),
+            HIP => csr.mip = (csr.mip & !MIP_VSSIP) | (value & MIP_VSSIP),
+            HVIP => csr.mip = (csr.mip & !VS_INTERRUPTS) | (value & VS_INTERRUPTS),
+            HGEIE | HENVCFG => {},
+            HCOUNTEREN => csr.hcounteren = value & 0xFFFF_FFFF,
+            HTIMEDELTA => csr.htimedelta = value,
+            HTVAL => csr.htval = value,
+            HTINST => csr.htinst = value,
+            HGATP => {
+                // no VMIDs, and the root table is 16 KiB aligned.
+                let (mode, ppn) = (value >> SATP_MODE_SHIFT, value & SATP_PPN_MASK & !0b11);
+                if sxlen == 32 {
+                    csr.hgatp = value & (HGATP32_MODE_SV32X4 | ((1 << 22) - 4));
+                    self.flush_fetch_translation();
+                } else if mode == SATP_MODE_BARE || mode == HGATP_MODE_SV39X4 {
+                    csr.hgatp = (mode << SATP_MODE_SHIFT) | ppn;
+                    self.flush_fetch_translation();
+                }
+            },
+            VSSTATUS => {
+                let mask = SSTATUS_MASK & writable_mstatus(&self.isa) & !MSTATUS_UXL;
+                csr.vsstatus = (csr.vsstatus & !mask) | (value & mask);
+            },
+            VSIE => {
+                let mask = csr.hideleg;
+                csr.mie = (csr.mie & !mask) | ((value << 1) & mask);
+            },
+            VSIP => {
+                let mask = csr.hideleg & MIP_VSSIP;
+                csr.mip = (csr.mip & !mask) | ((value << 1) & mask);
+            },
+            VSTVEC => csr.vstvec = legal_tvec(value),
+            VSSCRATCH => csr.vsscratch = value,
+            VSEPC => csr.vsepc = value & !1,
+            VSCAUSE => csr.vscause = value,
+            VSTVAL => csr.vstval = value,
+            VSATP => {
+                if let Some(satp) = legal_satp(sxlen, value) {
+                    csr.vsatp = satp;
+                    self.flush_fetch_translation();
+                }
+            },
+            MSTATUS => {
+                let mask = writable_mstatus(&self.isa);
+                csr.mstatus = legal_status(csr.mstatus, (csr.mstatus & !mask) | (value & mask));
+            },
+            MISA => {},
+            MEDELEG => {
+                let mask = if self.isa.has('h') { MEDELEG_MASK | MEDELEG_H } else { MEDELEG_MASK };
+                csr.medeleg = value & mask;
+            },
+            MIDELEG => csr.mideleg = value & S_INTERRUPTS,
+            MIE => csr.mie = value & (S_INTERRUPTS | vs_interrupts | MIP_MSIP | MIP_MTIP | MIP_MEIP),
+            MTVEC => csr.mtvec = legal_tvec(value),
+            MCOUNTEREN => csr.mcounteren = value & 0xFFFF_FFFF,
+            MENVCFG => {},
+            MSCRATCH => csr.mscratch = value,
+            MEPC => csr.mepc = value & !1,
+            MCAUSE => csr.mcause = value,
+            MTVAL => csr.mtval = value,
+            MIP => {
+                let mask = S_INTERRUPTS | (vs_interrupts & MIP_VSSIP);
+                csr.mip = (csr.mip & !mask) | (value & mask);
+            },
+            MTVAL2 => csr.mtval2 = value,
+            MTINST => csr.mtinst = value,
+            MCOUNTINHIBIT => csr.mcountinhibit = value & 0b101,
+            PMPCFG0..=PMPCFG15 if self.isa.xlen() == 32 || addr.is_multiple_of(2) => {
+                csr.pmpcfg[addr - PMPCFG0] = value
+            },
+            PMPADDR0..=PMPADDR63 => csr.pmpaddr[addr - PMPADDR0] = value & ((1 << 54) - 1),
+            TSELECT | TDATA1 | TDATA2 | TDATA3 => {},
+            _ => return false,
+        }
+        // a new UXL or SXL changes how code decodes.
+        if (self.csr.mstatus ^ old_status) & (MSTATUS_UXL | MSTATUS_SXL) != 0 {
+            self.flush_fetch_translation();
+        }
+        true
+    }
+
+    /// Merges a 32-bit write into the full CSR it is the low or high half
+    /// of; other CSRs are simply zero extended.
+    fn widen_csr(&self, addr: usize, value: u64) -> u64 {
+        let csr = &self.csr;
+        match addr {
+            MSTATUS | SSTATUS => (csr.mstatus & !0xFFFF_FFFF) | value,
+            VSSTATUS => (csr.vsstatus & !0xFFFF_FFFF) | value,
+            MCYCLE => (csr.mcycle & !0xFFFF_FFFF) | value,
+            MINSTRET => (csr.minstret & !0xFFFF_FFFF) | value,
+            HTIMEDELTA => (csr.htimedelta & !0xFFFF_FFFF) | value,
+            MCYCLEH => (csr.mcycle & 0xFFFF_FFFF) | (value << 32),
+            MINSTRETH => (csr.minstret & 0xFFFF_FFFF) | (value << 32),
+            HTIMEDELTAH => (csr.htimedelta & 0xFFFF_FFFF) | (value << 32),
+            _ => value,
+        }
+    }
+
+    /// Performs a CSR instruction: checks access, reads the old value
+    /// (unless `read` is clear), and writes `f(old)` if `write` is set.
+    pub fn csr_op(
+        &mut self,
+        bus: &Bus,
+        addr: usize,
+        raw: u32,
+        read: bool,
+        write: bool,
+        f: impl Fn(u64) -> u64,
+    ) -> Result<u64, Trap> {
+        let old = match self.csr_read(bus, addr) {
+            Some(old) => old,
+            None => return Err(Trap::illegal(raw)),
+        };
+        if let Err(exception) = self.csr_access(addr, write) {
+            return Err(Trap::new(exception, raw as u64));
+        }
+        if write {
+            if !self.csr_write(addr, f(old)) {
+                return Err(Trap::illegal(raw));
+            }
+            let new = self.csr_read(bus, addr).unwrap_or(0);
+            // a guest's writes land in the VS copies, and are logged so.
+            self.log_csr_write(self.virtual_csr(addr), new);
+        }
+        Ok(if read { old } else { 0 })
+    }
+
+    pub fn log_csr_write(&mut self, addr: usize, value: u64) {
+        if let Some(commit) = &mut self.commit {
+            commit.csr_writes.push((addr, value));
+        }
+    }
+
+    /// The privilege level loads and stores are performed at, and whether
+    /// they are a guest's, taking `mstatus.MPRV` and the hypervisor
+    /// loads and stores into account.
+    pub fn data_mode(&self) -> (Privilege, bool) {
+        let status = self.csr.mstatus;
+        if self.hypervisor_access.is_some() {
+            let spvp = self.csr.hstatus & HSTATUS_SPVP != 0;
+            return (if spvp { Privilege::Supervisor } else { Privilege::User }, true);
+        }
+        if self.privilege == Privilege::Machine && status & MSTATUS_MPRV != 0 {
+            let mpp = Privilege::from_bits(status >> 11);
+            return (mpp, mpp != Privilege::Machine && status & MSTATUS_MPV != 0);
+        }
+        (self.privilege, self.virt)
+    }
+
+    /// The privilege level loads and stores are performed at.
+    pub fn data_privilege(&self) -> Privilege {
+        self.data_mode().0
+    }
+
+    /// The page table format `satp` (or `vsatp`) selects, or `None` when it
+    /// is bare.
+    fn paging(&self, satp: u64) -> Option<&'static Paging> {
+        if self.sxlen() == 32 {
+            (satp & SATP32_MODE_SV32 != 0).then_some(&SV32)
+        } else {
+            (satp >> SATP_MODE_SHIFT == SATP_MODE_SV39).then_some(&SV39)
+        }
+    }
+
+    /// The G-stage `hgatp` selects, or `None` when it is bare.
+    fn gstage(&self) -> Option<Stage> {
+        let hgatp = self.csr.hgatp;
+        let paging = if self.sxlen() == 32 {
+            (hgatp & HGATP32_MODE_SV32X4 != 0).then_some(&SV32X4)
+        } else {
+            (hgatp >> SATP_MODE_SHIFT == HGATP_MODE_SV39X4).then_some(&SV39X4)
+        }?;
+        Some(Stage {
+            paging,
+            root: (hgatp & paging.ppn_mask) << PAGE_SHIFT,
+            privilege: Privilege::User,
+            sum: false,
+            mxr: self.csr.mstatus & MSTATUS_MXR != 0,
+            guest: true,
+        })
+    }
+
+    /// The stages `access` is translated through: the S-mode (or VS-mode)
+    /// one, then in a guest the G-stage.
+    fn stages(&self, access: Access) -> (Option<Stage>, Option<Stage>) {
+        let (privilege, virt) = if access == Access::Fetch { (self.privilege, self.virt) } else { self.data_mode() };
+        let status = self.csr.mstatus;
+        if !virt {
+            if privilege == Privilege::Machine {
+                return (None, None);
+            }
+            let stage = self.paging(self.csr.satp).map(|paging| Stage {
+                paging,
+                root: (self.csr.satp & paging.ppn_mask) << PAGE_SHIFT,
+                privilege,
+                sum: status & MSTATUS_SUM != 0,
+                mxr: status & MSTATUS_MXR != 0,
+                guest: false,
+            });
+            return (stage, None);
+        }
+        // `mstatus.MXR` reaches into the guest's translation too.
+        let vsstatus = self.csr.vsstatus;
+        let stage = self.paging(self.csr.vsatp).map(|paging| Stage {
+            paging,
+            root: (self.csr.vsatp & paging.ppn_mask) << PAGE_SHIFT,
+            privilege,
+            sum: vsstatus & MSTATUS_SUM != 0,
+            mxr: (vsstatus | status) & MSTATUS_MXR != 0,
+            guest: false,
+        });
+        (stage, self.gstage())
+    }
+
+    /// Translates a virtual address to a physical one through the Sv39 or
+    /// Sv32 page tables, and in a guest then through the Sv39x4 or Sv32x4
+    /// G-stage, updating the accessed and dirty bits as it goes.
+    pub fn translate(&mut self, bus: &mut Bus, vaddr: u64, access: Access) -> Result<u64, Trap> {
+        // HLVX loads need execute permission rather than read.
+        let check = if self.hypervisor_access == Some(Access::Fetch) { Access::Fetch } else { access };
+        let (stage, gstage) = self.stages(access);
+        let gpa = match &stage {
+            Some(stage) => stage.walk(bus, vaddr, access, check, gstage.as_ref())?,
+            None => vaddr,
+        };
+        match &gstage {
+            Some(gstage) => gstage.walk(bus, gpa, access, check, None).map_err(|trap| Trap { tval: vaddr, ..trap }),
+            None => Ok(gpa),
+        }
+    }
+
+    /// Translates `vaddr` the way a data access would, but without checking
+    /// permissions or touching the A/D bits. Used by debuggers.
+    pub fn debug_translate(&self, bus: &Bus, vaddr: u64) -> Option<u64> {
+        let (stage, gstage) = self.stages(Access::Load);
+        let gpa = match &stage {
+            Some(stage) => stage.debug_walk(bus, vaddr, gstage.as_ref())?,
+            None => vaddr,
+        };
+        match &gstage {
+            Some(gstage) => gstage.debug_walk(bus, gpa, None),
+            None => Some(gpa),
+        }
+    }
+
+    /// Reads `len` bytes of virtual memory for a debugger, page by page.
+    pub fn debug_read(&self, bus: &Bus, vaddr: u64, len: usize) -> Option<Vec<u8>> {
+        let mut out = Vec::with_capacity(len);
+        while out.len() < len {
+            let addr = vaddr.wrapping_add(out.len() as u64);
+            let chunk = ((PAGE_SIZE - addr % PAGE_SIZE) as usize).min(len - out.len());
+            let paddr = self.debug_translate(bus, addr)?;
+            out.extend_from_slice(bus.read_bytes(paddr, chunk)?);
+        }
+        Some(out)
+    }
+
+    /// Writes virtual memory for a debugger, page by page. Returns `false`
+    /// if any part is unmapped or outside of RAM.
+    pub fn debug_write(&self, bus: &mut Bus, vaddr: u64, bytes: &[u8]) -> bool {
+        let mut done = 0;
+        while done < bytes.len() {
+            let addr = vaddr.wrapping_add(done as u64);
+            let chunk = ((PAGE_SIZE - addr % PAGE_SIZE) as usize).min(bytes.len() - done);
+            match self.debug_translate(bus, addr) {
+                Some(paddr) if bus.write_bytes(paddr, &bytes[done..done + chunk]) => done += chunk,
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    /// Loads `size` bytes at virtual address `vaddr` for `access`. Accesses
+    /// that straddle a page are split into bytes.
+    fn load_raw(&mut self, bus: &mut Bus, vaddr: u64, size: u64, access: Access) -> Result<u64, Trap> {
+        if (vaddr % PAGE_SIZE) + size > PAGE_SIZE {
+            let mut out = 0;
+            for i in (0..size).rev() {
+                out = (out << 8) | self.load_raw(bus, vaddr.wrapping_add(i), 1, access)?;
+            }
+            return Ok(out);
+        }
+        let paddr = self.translate(bus, vaddr, access)?;
+        match bus.load(paddr, size as usize) {
+            Some(value) => Ok(value),
+            None => Err(access.access_fault(vaddr)),
+        }
+    }
+
+    pub fn load(&mut self, bus: &mut Bus, vaddr: u64, size: u64) -> Result<u64, Trap> {
+        let vaddr = vaddr & self.xlen_mask();
+        if let Some(commit) = &mut self.commit {
+            commit.loads.push(vaddr);
+        }
+        self.load_raw(bus, vaddr, size, Access::Load)
+    }
+
+    /// Records a store in `commit`, when committing.
+    pub fn note_store(&mut self, vaddr: u64, value: u64, size: u64) {
+        if let Some(commit) = &mut self.commit {
+            let mask = if size == 8 { u64::MAX } else { (1 << (size * 8)) - 1 };
+            commit.stores.push((vaddr, value & mask, size));
+        }
+    }
+
+    pub fn store(&mut self, bus: &mut Bus, vaddr: u64, size: u64, value: u64) -> Result<(), Trap> {
+        let vaddr = vaddr & self.xlen_mask();
+        self.note_store(vaddr, value, size);
+        if (vaddr % PAGE_SIZE) + size > PAGE_SIZE {
+            // translate every byte before writing any of them, so a fault
+            // on the second page leaves memory untouched.
+            let mut paddrs = Vec::with_capacity(size as usize);
+            for i in 0..size {
+                paddrs.push(self.translate(bus, vaddr.wrapping_add(i), Access::Store)?);
+            }
+            for (i, paddr) in paddrs.into_iter().enumerate() {
+                if !bus.store_from(Some(self.id), paddr, 1, value >> (i * 8)) {
+                    return Err(Access::Store.access_fault(vaddr));
+                }
+            }
+            return Ok(());
+        }
+        let paddr = self.translate(bus, vaddr, Access::Store)?;
+        if !bus.store_from(Some(self.id), paddr, size as usize, value) {
+            return Err(Access::Store.access_fault(vaddr));
+        }
+        Ok(())
+    }
+}
+
+fn set_bit(value: u64, mask: u64, set: bool) -> u64 {
+    if set { value | mask } else { value & !mask }
+}
+
+fn trap_vector(tvec: u64, code: u64, interrupt: bool) -> u64 {
+    let base = tvec & !0b11;
+    if interrupt && tvec & 0b11 == 1 {
+        return base + 4 * code;
+    }
+    base
+}
+
+/// Only direct and vectored modes exist; reserved modes fall back to direct.
+fn legal_tvec(value: u64) -> u64 {
+    if value & 0b11 >= 2 {
+        return value & !0b11;
+    }
+    value
+}
+
+/// Legalizes a `satp` or `vsatp` write, returning `None` when the mode is
+/// unsupported and the write is ignored. The format follows S-mode's
+/// XLEN, where Sv32 is the only mode besides bare.
+fn legal_satp(sxlen: u32, value: u64) -> Option<u64> {
+    let mode = value >> SATP_MODE_SHIFT;
+    if sxlen == 32 {
+        Some(value & 0xFFFF_FFFF)
+    } else {
+        (mode == SATP_MODE_BARE || mode == SATP_MODE_SV39).then_some(value)
+    }
+}
+
+/// The `mstatus` fields software can write; FS stays off without `F`, VS
+/// without a vector unit, MPV/GVA without `H`, and UXL/SXL are only
+/// writable on RV64.
+fn writable_mstatus(isa: &Isa) -> u64 {
+    let fs = if isa.has('f') { MSTATUS_FS } else { 0 };
+    let h = if isa.has('h') { MSTATUS_MPV | MSTATUS_GVA } else { 0 };
+    let vs = if isa.vlen() != 0 { MSTATUS_VS } else { 0 };
+    let xl = if isa.xlen() == 64 { MSTATUS_UXL | MSTATUS_SXL } else { 0 };
+    MSTATUS_SIE | MSTATUS_MIE | MSTATUS_SPIE | MSTATUS_MPIE | MSTATUS_SPP | MSTATUS_MPP | fs | vs
+        | MSTATUS_MPRV | MSTATUS_SUM | MSTATUS_MXR | MSTATUS_TVM | MSTATUS_TW | MSTATUS_TSR | xl | h
+}
+
+/// Fills in `mstatus.SD` from the FS, VS and XS fields.
+fn status_with_sd(status: u64) -> u64 {
+    let dirty = (status & MSTATUS_FS) == MSTATUS_FS
+        || (status & MSTATUS_VS) == MSTATUS_VS
+        || (status & MSTATUS_XS) == MSTATUS_XS;
+    set_bit(status, MSTATUS_SD, dirty)
+}
+
+fn sstatus_view(status: u64) -> u64 {
+    status_with_sd(status) & SSTATUS_MASK
+}
+
+/// Legalizes a new `mstatus`: MPP = 2 is reserved and falls back to
+/// U-mode, and UXL/SXL only take 32 or 64, keeping their old value
+/// otherwise.
+fn legal_status(old: u64, new: u64) -> u64 {
+    let mut status = new;
+    if (status & MSTATUS_MPP) >> 11 == 2 {
+        status &= !MSTATUS_MPP;
+    }
+    for field in [MSTATUS_UXL, MSTATUS_SXL] {
+        let xl = (status & field) >> field.trailing_zeros();
+        if xl == 0 || xl == 3 {
+            status = (status & !field) | (old & field);
+        }
+    }
+    status
+}
+
+/// What XLEN 32 reads of a CSR: the low half, with `mstatus.SD` and
+/// `vtype.vill` moved down to bit 31.
+fn narrow_csr(addr: usize, value: u64) -> u64 {
+    match addr {
+        MSTATUS | SSTATUS | VSSTATUS | VTYPE => (value & 0x7FFF_FFFF) | ((value >> 32) & (1 << 31)),
+        _ => value & 0xFFFF_FFFF,
+    }
+}
@@ -0,0 +1,603 @@
+use crate::Execute::sext;
+
+/// The AES S-box, SubBytes.
+const AES_SBOX: [u8; 256] = [
+    0x63, 0x7C, 0x77, 0x7B, 0xF2, 0x6B, 0x6F, 0xC5, 0x30, 0x01, 0x67, 0x2B, 0xFE, 0xD7, 0xAB, 0x76,
+    0xCA, 0x82, 0xC9, 0x7D, 0xFA, 0x59, 0x47, 0xF0, 0xAD, 0xD4, 0xA2, 0xAF, 0x9C, 0xA4, 0x72, 0xC0,
+    0xB7, 0xFD, 0x93, 0x26, 0x36, 0x3F, 0xF7, 0xCC, 0x34, 0xA5, 0xE5, 0xF1, 0x71, 0xD8, 0x31, 0x15,
+    0x04, 0xC7, 0x23, 0xC3, 0x18, 0x96, 0x05, 0x9A, 0x07, 0x12, 0x80, 0xE2, 0xEB, 0x27, 0xB2, 0x75,
+    0x09, 0x83, 0x2C, 0x1A, 0x1B, 0x6E, 0x5A, 0xA0, 0x52, 0x3B, 0xD6, 0xB3, 0x29, 0xE3, 0x2F, 0x84,
+    0x53, 0xD1, 0x00, 0xED, 0x20, 0xFC, 0xB1, 0x5B, 0x6A, 0xCB, 0xBE, 0x39, 0x4A, 0x4C, 0x58, 0xCF,
+    0xD0, 0xEF, 0xAA, 0xFB, 0x43, 0x4D, 0x33, 0x85, 0x45, 0xF9, 0x02, 0x7F, 0x50, 0x3C, 0x9F, 0xA8,
+    0x51, 0xA3, 0x40, 0x8F, 0x92, 0x9D, 0x38, 0xF5, 0xBC, 0xB6, 0xDA, 0x21, 0x10, 0xFF, 0xF3, 0xD2,
+    0xCD, 0x0C, 0x13, 0xEC, 0x5F, 0x97, 0x44, 0x17, 0xC4, 0xA7, 0x7E, 0x3D, 0x64, 0x5D, 0x19, 0x73,
+    0x60, 0x81, 0x4F, 0xDC, 0x22, 0x2A, 0x90, 0x88, 0x46, 0xEE, 0xB8, 0x14, 0xDE, 0x5E, 0x0B, 0xDB,
+    0xE0, 0x32, 0x3A, 0x0A, 0x49, 0x06, 0x24, 0x5C, 0xC2, 0xD3, 0xAC, 0x62, 0x91, 0x95, 0xE4, 0x79,
+    0xE7, 0xC8, 0x37, 0x6D, 0x8D, 0xD5, 0x4E, 0xA9, 0x6C, 0x56, 0xF4, 0xEA, 0x65, 0x7A, 0xAE, 0x08,
+    0xBA, 0x78, 0x25, 0x2E, 0x1C, 0xA6, 0xB4, 0xC6, 0xE8, 0xDD, 0x74, 0x1F, 0x4B, 0xBD, 0x8B, 0x8A,
+    0x70, 0x3E, 0xB5, 0x66, 0x48, 0x03, 0xF6, 0x0E, 0x61, 0x35, 0x57, 0xB9, 0x86, 0xC1, 0x1D, 0x9E,
+    0xE1, 0xF8, 0x98, 0x11, 0x69, 0xD9, 0x8E, 0x94, 0x9B, 0x1E, 0x87, 0xE9, 0xCE, 0x55, 0x28, 0xDF,
+    0x8C, 0xA1, 0x89, 0x0D, 0xBF, 0xE6, 0x42, 0x68, 0x41, 0x99, 0x2D, 0x0F, 0xB0, 0x54, 0xBB, 0x16,
+];
+
+/// The inverse AES S-box, InvSubBytes.
+const AES_INV_SBOX: [u8; 256] = [
+    0x52, 0x09, 0x6A, 0xD5, 0x30, 0x36, 0xA5, 0x38, 0xBF, 0x40, 0xA3, 0x9E, 0x81, 0xF3, 0xD7, 0xFB,
+    0x7C, 0xE3, 0x39, 0x82, 0x9B, 0x2F, 0xFF, 0x87, 0x34, 0x8E, 0x43, 0x44, 0xC4, 0xDE, 0xE9, 0xCB,
+    0x54, 0x7B, 0x94, 0x32, 0xA6, 0xC2, 0x23, 0x3D, 0xEE, 0x4C, 0x95, 0x0B, 0x42, 0xFA, 0xC3, 0x4E,
+    0x08, 0x2E, 0xA1, 0x66, 0x28, 0xD9, 0x24, 0xB2, 0x76, 0x5B, 0xA2, 0x49, 0x6D, 0x8B, 0xD1, 0x25,
+    0x72, 0xF8, 0xF6, 0x64, 0x86, 0x68, 0x98, 0x16, 0xD4, 0xA4, 0x5C, 0xCC, 0x5D, 0x65, 0xB6, 0x92,
+    0x6C, 0x70, 0x48, 0x50, 0xFD, 0xED, 0xB9, 0xDA, 0x5E, 0x15, 0x46, 0x57, 0xA7, 0x8D, 0x9D, 0x84,
+    0x90, 0xD8, 0xAB, 0x00, 0x8C, 0xBC, 0xD3, 0x0A, 0xF7, 0xE4, 0x58, 0x05, 0xB8, 0xB3, 0x45, 0x06,
+    0xD0, 0x2C, 0x1E, 0x8F, 0xCA, 0x3F, 0x0F, 0x02, 0xC1, 0xAF, 0xBD, 0x03, 0x01, 0x13, 0x8A, 0x6B,
+    0x3A, 0x91, 0x11, 0x41, 0x4F, 0x67, 0xDC, 0xEA, 0x97, 0xF2, 0xCF, 0xCE, 0xF0, 0xB4, 0xE6, 0x73,
+    0x96, 0xAC, 0x74, 0x22, 0xE7, 0xAD, 0x35, 0x85, 0xE2, 0xF9, 0x37, 0xE8, 0x1C, 0x75, 0xDF, 0x6E,
+    0x47, 0xF1, 0x1A, 0x71, 0x1D, 0x29, 0xC5, 0x89, 0x6F, 0xB7, 0x62, 0x0E, 0xAA, 0x18, 0xBE, 0x1B,
+    0xFC, 0x56, 0x3E, 0x4B, 0xC6, 0xD2, 0x79, 0x20, 0x9A, 0xDB, 0xC0, 0xFE, 0x78, 0xCD, 0x5A, 0xF4,
+    0x1F, 0xDD, 0xA8, 0x33, 0x88, 0x07, 0xC7, 0x31, 0xB1, 0x12, 0x10, 0x59, 0x27, 0x80, 0xEC, 0x5F,
+    0x60, 0x51, 0x7F, 0xA9, 0x19, 0xB5, 0x4A, 0x0D, 0x2D, 0xE5, 0x7A, 0x9F, 0x93, 0xC9, 0x9C, 0xEF,
+    0xA0, 0xE0, 0x3B, 0x4D, 0xAE, 0x2A, 0xF5, 0xB0, 0xC8, 0xEB, 0xBB, 0x3C, 0x83, 0x53, 0x99, 0x61,
+    0x17, 0x2B, 0x04, 0x7E, 0xBA, 0x77, 0xD6, 0x26, 0xE1, 0x69, 0x14, 0x63, 0x55, 0x21, 0x0C, 0x7D,
+];
+
+/// The SM4 S-box.
+const SM4_SBOX: [u8; 256] = [
+    0xD6, 0x90, 0xE9, 0xFE, 0xCC, 0xE1, 0x3D, 0xB7, 0x16, 0xB6, 0x14, 0xC2, 0x28, 0xFB, 0x2C, 0x05,
+    0x2B, 0x67, 0x9A, 0x76, 0x2A, 0xBE, 0x04, 0xC3, 0xAA, 0x44, 0x13, 0x26, 0x49, 0x86, 0x06, 0x99,
+    0x9C, 0x42, 0x50, 0xF4, 0x91, 0xEF, 0x98, 0x7A, 0x33, 0x54, 0x0B, 0x43, 0xED, 0xCF, 0xAC, 0x62,
+    0xE4, 0xB3, 0x1C, 0xA9, 0xC9, 0x08, 0xE8, 0x95, 0x80, 0xDF, 0x94, 0xFA, 0x75, 0x8F, 0x3F, 0xA6,
+    0x47, 0x07, 0xA7, 0xFC, 0xF3, 0x73, 0x17, 0xBA, 0x83, 0x59, 0x3C, 0x19, 0xE6, 0x85, 0x4F, 0xA8,
+    0x68, 0x6B, 0x81, 0xB2, 0x71, 0x64, 0xDA, 0x8B, 0xF8, 0xEB, 0x0F, 0x4B, 0x70, 0x56, 0x9D, 0x35,
+    0x1E, 0x24, 0x0E, 0x5E, 0x63, 0x58, 0xD1, 0xA2, 0x25, 0x22, 0x7C, 0x3B, 0x01, 0x21, 0x78, 0x87,
+    0xD4, 0x00, 0x46, 0x57, 0x9F, 0xD3, 0x27, 0x52, 0x4C, 0x36, 0x02, 0xE7, 0xA0, 0xC4, 0xC8, 0x9E,
+    0xEA, 0xBF, 0x8A, 0xD2, 0x40, 0xC7, 0x38, 0xB5, 0xA3, 0xF7, 0xF2, 0xCE, 0xF9, 0x61, 0x15, 0xA1,
+    0xE0, 0xAE, 0x5D, 0xA4, 0x9B, 0x34, 0x1A, 0x55, 0xAD, 0x93, 0x32, 0x30, 0xF5, 0x8C, 0xB1, 0xE3,
+    0x1D, 0xF6, 0xE2, 0x2E, 0x82, 0x66, 0xCA, 0x60, 0xC0, 0x29, 0x23, 0xAB, 0x0D, 0x53, 0x4E, 0x6F,
+    0xD5, 0xDB, 0x37, 0x45, 0xDE, 0xFD, 0x8E, 0x2F, 0x03, 0xFF, 0x6A, 0x72, 0x6D, 0x6C, 0x5B, 0x51,
+    0x8D, 0x1B, 0xAF, 0x92, 0xBB, 0xDD, 0xBC, 0x7F, 0x11, 0xD9, 0x5C, 0x41, 0x1F, 0x10, 0x5A, 0xD8,
+    0x0A, 0xC1, 0x31, 0x88, 0xA5, 0xCD, 0x7B, 0xBD, 0x2D, 0x74, 0xD0, 0x12, 0xB8, 0xE5, 0xB4, 0xB0,
+    0x89, 0x69, 0x97, 0x4A, 0x0C, 0x96, 0x77, 0x7E, 0x65, 0xB9, 0xF1, 0x09, 0xC5, 0x6E, 0xC6, 0x84,
+    0x18, 0xF0, 0x7D, 0xEC, 0x3A, 0xDC, 0x4D, 0x20, 0x79, 0xEE, 0x5F, 0x3E, 0xD7, 0xCB, 0x39, 0x48,
+];
+
+/// AES-128's round constants, by round number.
+const AES_RCON: [u8; 10] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1B, 0x36];
+
+/// A scalar cryptography instruction of `Zknd`, `Zkne`, `Zknh`, `Zksed` or
+/// `Zksh`, as `Decoded` leaves it. `Zbkb`'s general purpose bit
+/// manipulation instructions are ordinary `Op`s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CryptoOp {
+    /// `aes32esi`, `aes32esmi`, `aes32dsi` and `aes32dsmi`: byte `bs` of
+    /// `rs2` through the S-box, and MixColumns too in the `middle` rounds,
+    /// XORed into `rs1`.
+    Aes32 { decrypt: bool, middle: bool, bs: u32 },
+    /// `aes64es`, `aes64esm`, `aes64ds` and `aes64dsm`: a round on the half
+    /// of the state `rs1` is, with `rs2` the other half.
+    Aes64 { decrypt: bool, middle: bool },
+    Aes64Im,
+    /// `aes64ks1i`, with its round number.
+    Aes64Ks1i(u32),
+    Aes64Ks2,
+    Sha256Sig0,
+    Sha256Sig1,
+    Sha256Sum0,
+    Sha256Sum1,
+    Sha512Sig0,
+    Sha512Sig1,
+    Sha512Sum0,
+    Sha512Sum1,
+    /// RV32's halves of the SHA-512 functions, the other half of the
+    /// 64-bit word in `rs2`.
+    Sha512Sig0h,
+    Sha512Sig0l,
+    Sha512Sig1h,
+    Sha512Sig1l,
+    Sha512Sum0r,
+    Sha512Sum1r,
+    Sm3P0,
+    Sm3P1,
+    /// `sm4ed` and `sm4ks`: byte `bs` of `rs2` through the S-box and the
+    /// linear transform of the rounds or of the `key` schedule.
+    Sm4 { key: bool, bs: u32 },
+}
+
+impl CryptoOp {
+    /// Decodes a scalar cryptography instruction that exists at `xlen`.
+    /// The AES and SHA-512 instructions come in different forms for RV32
+    /// and RV64.
+    pub fn decode(raw: u32, xlen: u32) -> Option<Self> {
+        use CryptoOp::*;
+        let (opcode, funct3, funct7) = (raw & 0x7F, (raw >> 12) & 0b111, raw >> 25);
+        let (rv32, rv64, bs) = (xlen == 32, xlen == 64, funct7 >> 5);
+        let op = match (opcode, funct3) {
+            (0b0010011, 0b001) => match raw >> 20 {
+                0x100 => Sha256Sum0,
+                0x101 => Sha256Sum1,
+                0x102 => Sha256Sig0,
+                0x103 => Sha256Sig1,
+                0x104 if rv64 => Sha512Sum0,
+                0x105 if rv64 => Sha512Sum1,
+                0x106 if rv64 => Sha512Sig0,
+                0x107 if rv64 => Sha512Sig1,
+                0x108 => Sm3P0,
+                0x109 => Sm3P1,
+                0x300 if rv64 => Aes64Im,
+                imm @ 0x310..=0x31A if rv64 => Aes64Ks1i(imm & 0xF),
+                _ => return None,
+            },
+            (0b0110011, 0b000) => match (funct7 & 0x1F, funct7) {
+                (0b10001, _) if rv32 => Aes32 { decrypt: false, middle: false, bs },
+                (0b10011, _) if rv32 => Aes32 { decrypt: false, middle: true, bs },
+                (0b10101, _) if rv32 => Aes32 { decrypt: true, middle: false, bs },
+                (0b10111, _) if rv32 => Aes32 { decrypt: true, middle: true, bs },
+                (0b11000, _) => Sm4 { key: false, bs },
+                (0b11010, _) => Sm4 { key: true, bs },
+                (_, 0b0011001) if rv64 => Aes64 { decrypt: false, middle: false },
+                (_, 0b0011011) if rv64 => Aes64 { decrypt: false, middle: true },
+                (_, 0b0011101) if rv64 => Aes64 { decrypt: true, middle: false },
+                (_, 0b0011111) if rv64 => Aes64 { decrypt: true, middle: true },
+                (_, 0b0111111) if rv64 => Aes64Ks2,
+                (_, 0b0101000) if rv32 => Sha512Sum0r,
+                (_, 0b0101001) if rv32 => Sha512Sum1r,
+                (_, 0b0101010) if rv32 => Sha512Sig0l,
+                (_, 0b0101011) if rv32 => Sha512Sig1l,
+                (_, 0b0101110) if rv32 => Sha512Sig0h,
+                (_, 0b0101111) if rv32 => Sha512Sig1h,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    /// The extensions that have the instruction: both AES ones for the
+    /// key schedule.
+    pub fn extensions(self) -> &'static [&'static str] {
+        use CryptoOp::*;
+        match self {
+            Aes32 { decrypt: false, .. } | Aes64 { decrypt: false, .. } => &["Zkne"],
+            Aes32 { .. } | Aes64 { .. } | Aes64Im => &["Zknd"],
+            Aes64Ks1i(_) | Aes64Ks2 => &["Zknd", "Zkne"],
+            Sm3P0 | Sm3P1 => &["Zksh"],
+            Sm4 { .. } => &["Zksed"],
+            _ => &["Zknh"],
+        }
+    }
+
+    /// The assembler mnemonic.
+    pub fn mnemonic(self) -> &'static str {
+        use CryptoOp::*;
+        match self {
+            Aes32 { decrypt: false, middle: false, .. } => "aes32esi",
+            Aes32 { decrypt: false, middle: true, .. } => "aes32esmi",
+            Aes32 { decrypt: true, middle: false, .. } => "aes32dsi",
+            Aes32 { decrypt: true, middle: true, .. } => "aes32dsmi",
+            Aes64 { decrypt: false, middle: false } => "aes64es",
+            Aes64 { decrypt: false, middle: true } => "aes64esm",
+            Aes64 { decrypt: true, middle: false } => "aes64ds",
+            Aes64 { decrypt: true, middle: true } => "aes64dsm",
+            Aes64Im => "aes64im",
+            Aes64Ks1i(_) => "aes64ks1i",
+            Aes64Ks2 => "aes64ks2",
+            Sha256Sig0 => "sha256sig0",
+            Sha256Sig1 => "sha256sig1",
+            Sha256Sum0 => "sha256sum0",
+            Sha256Sum1 => "sha256sum1",
+            Sha512Sig0 => "sha512sig0",
+            Sha512Sig1 => "sha512sig1",
+            Sha512Sum0 => "sha512sum0",
+            Sha512Sum1 => "sha512sum1",
+            Sha512Sig0h => "sha512sig0h",
+            Sha512Sig0l => "sha512sig0l",
+            Sha512Sig1h => "sha512sig1h",
+            Sha512Sig1l => "sha512sig1l",
+            Sha512Sum0r => "sha512sum0r",
+            Sha512Sum1r => "sha512sum1r",
+            Sm3P0 => "sm3p0",
+            Sm3P1 => "sm3p1",
+            Sm4 { key: false, .. } => "sm4ed",
+            Sm4 { key: true, .. } => "sm4ks",
+        }
+    }
+
+    /// Whether the instruction reads `rs1` only.
+    pub fn unary(self) -> bool {
+        use CryptoOp::*;
+        matches!(
+            self,
+            Aes64Im | Aes64Ks1i(_) | Sha256Sig0 | Sha256Sig1 | Sha256Sum0 | Sha256Sum1 | Sha512Sig0 | Sha512Sig1
+                | Sha512Sum0 | Sha512Sum1 | Sm3P0 | Sm3P1
+        )
+    }
+
+    /// The result for source registers `a` (`rs1`) and `b` (`rs2`). The
+    /// 32-bit results are sign extended, as RV64 writes them.
+    pub fn apply(self, a: u64, b: u64) -> u64 {
+        use CryptoOp::*;
+        let word = |value: u32| sext(value as u64, 32);
+        let (a32, b32) = (a as u32, b as u32);
+        let pair = |high: u32, low: u32| ((high as u64) << 32) | low as u64;
+        match self {
+            Aes32 { decrypt, middle, bs } => {
+                let byte = (b32 >> (bs * 8)) as u8;
+                let substituted = if decrypt { AES_INV_SBOX[byte as usize] } else { AES_SBOX[byte as usize] };
+                let mixed = match (middle, decrypt) {
+                    (false, _) => substituted as u32,
+                    (true, false) => mix_column(substituted as u32, FORWARD_MIX),
+                    (true, true) => mix_column(substituted as u32, INVERSE_MIX),
+                };
+                word(a32 ^ mixed.rotate_left(bs * 8))
+            },
+            Aes64 { decrypt, middle } => {
+                let state = shift_rows(a, b, decrypt);
+                let sbox = if decrypt { &AES_INV_SBOX } else { &AES_SBOX };
+                let substituted = u64::from_le_bytes(state.to_le_bytes().map(|byte| sbox[byte as usize]));
+                match (middle, decrypt) {
+                    (false, _) => substituted,
+                    (true, false) => mix_columns(substituted, FORWARD_MIX),
+                    (true, true) => mix_columns(substituted, INVERSE_MIX),
+                }
+            },
+            Aes64Im => mix_columns(a, INVERSE_MIX),
+            Aes64Ks1i(rnum) => {
+                let high = (a >> 32) as u32;
+                let (rotated, rcon) = match rnum {
+                    10 => (high, 0),
+                    _ => (high.rotate_right(8), AES_RCON[rnum as usize] as u32),
+                };
+                let substituted = u32::from_le_bytes(rotated.to_le_bytes().map(|byte| AES_SBOX[byte as usize])) ^ rcon;
+                pair(substituted, substituted)
+            },
+            Aes64Ks2 => {
+                let low = (a >> 32) as u32 ^ b32;
+                pair(low ^ (b >> 32) as u32, low)
+            },
+            Sha256Sig0 => word(a32.rotate_right(7) ^ a32.rotate_right(18) ^ (a32 >> 3)),
+            Sha256Sig1 => word(a32.rotate_right(17) ^ a32.rotate_right(19) ^ (a32 >> 10)),
+            Sha256Sum0 => word(a32.rotate_right(2) ^ a32.rotate_right(13) ^ a32.rotate_right(22)),
+            Sha256Sum1 => word(a32.rotate_right(6) ^ a32.rotate_right(11) ^ a32.rotate_right(25)),
+            Sha512Sig0 => sha512_sig0(a),
+            Sha512Sig1 => sha512_sig1(a),
+            Sha512Sum0 => sha512_sum0(a),
+            Sha512Sum1 => sha512_sum1(a),
+            // each half of a SHA-512 function of the 64-bit word `rs1`
+            // and `rs2` make up, `rs1` being the half wanted for the `h`
+            // and `l` forms, and either for the rotations.
+            Sha512Sig0h => word((sha512_sig0(pair(a32, b32)) >> 32) as u32),
+            Sha512Sig0l => word(sha512_sig0(pair(b32, a32)) as u32),
+            Sha512Sig1h => word((sha512_sig1(pair(a32, b32)) >> 32) as u32),
+            Sha512Sig1l => word(sha512_sig1(pair(b32, a32)) as u32),
+            Sha512Sum0r => word(sha512_sum0(pair(b32, a32)) as u32),
+            Sha512Sum1r => word(sha512_sum1(pair(b32, a32)) as u32),
+            Sm3P0 => word(a32 ^ a32.rotate_left(9) ^ a32.rotate_left(17)),
+            Sm3P1 => word(a32 ^ a32.rotate_left(15) ^ a32.rotate_left(23)),
+            // SM4's linear transforms, on words loaded little-endian where
+            // SM4 numbers bytes big-endian.
+            Sm4 { key, bs } => {
+                let x = SM4_SBOX[((b32 >> (bs * 8)) & 0xFF) as usize] as u32;
+                let y = if key {
+                    x ^ ((x & 0x07) << 29) ^ ((x & 0xFE) << 7) ^ ((x & 0x01) << 23) ^ ((x & 0xF8) << 13)
+                } else {
+                    x ^ (x << 8) ^ (x << 2) ^ (x << 18) ^ ((x & 0x3F) << 26) ^ ((x & 0xC0) << 10)
+                };
+                word(a32 ^ y.rotate_left(bs * 8))
+            },
+        }
+    }
+}
+
+/// MixColumns' and InvMixColumns' coefficients for the bytes of a column,
+/// starting with the one being computed.
+const FORWARD_MIX: [u8; 4] = [2, 3, 1, 1];
+const INVERSE_MIX: [u8; 4] = [0xE, 0xB, 0xD, 0x9];
+
+/// Multiplication in AES's GF(2^8).
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        a = (a << 1) ^ if a & 0x80 != 0 { 0x1B } else { 0 };
+        b >>= 1;
+    }
+    product
+}
+
+/// Multiplies the column `column`, row 0 in the low byte, by the
+/// circulant matrix with `coefficients` as its first row.
+fn mix_column(column: u32, coefficients: [u8; 4]) -> u32 {
+    let bytes = column.to_le_bytes();
+    let row = |i: usize| (0..4).fold(0, |out, j| out ^ gf_mul(bytes[j], coefficients[(j + 4 - i) % 4]));
+    u32::from_le_bytes([row(0), row(1), row(2), row(3)])
+}
+
+/// `mix_column` on both columns of half the state.
+fn mix_columns(columns: u64, coefficients: [u8; 4]) -> u64 {
+    let low = mix_column(columns as u32, coefficients) as u64;
+    low | (mix_column((columns >> 32) as u32, coefficients) as u64) << 32
+}
+
+/// The first two columns of ShiftRows, or InvShiftRows when `inverse`, of
+/// the state whose first two columns are `low` and last two are `high`.
+fn shift_rows(low: u64, high: u64, inverse: bool) -> u64 {
+    let state = ((high as u128) << 64) | low as u128;
+    (0..8).fold(0, |out, i| {
+        let (column, row) = (i / 4, i % 4);
+        let from = if inverse { (column + 4 - row) % 4 } else { (column + row) % 4 };
+        out | ((((state >> ((from * 4 + row) * 8)) & 0xFF) as u64) << (i * 8))
+    })
+}
+
+fn sha512_sig0(x: u64) -> u64 {
+    x.rotate_right(1) ^ x.rotate_right(8) ^ (x >> 7)
+}
+
+fn sha512_sig1(x: u64) -> u64 {
+    x.rotate_right(19) ^ x.rotate_right(61) ^ (x >> 6)
+}
+
+fn sha512_sum0(x: u64) -> u64 {
+    x.rotate_right(28) ^ x.rotate_right(34) ^ x.rotate_right(39)
+}
+
+fn sha512_sum1(x: u64) -> u64 {
+    x.rotate_right(14) ^ x.rotate_right(18) ^ x.rotate_right(41)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use CryptoOp::*;
+
+    /// FIPS-197 appendix C.1: AES-128.
+    const AES_KEY: u128 = 0x000102030405060708090A0B0C0D0E0F;
+    const AES_PLAIN: u128 = 0x00112233445566778899AABBCCDDEEFF;
+    const AES_CIPHER: u128 = 0x69C4E0D86A7B0430D8CDB78070B4C55A;
+
+    const SHA256_K: [u32; 64] = [
+        0x428A2F98, 0x71374491, 0xB5C0FBCF, 0xE9B5DBA5, 0x3956C25B, 0x59F111F1, 0x923F82A4, 0xAB1C5ED5,
+        0xD807AA98, 0x12835B01, 0x243185BE, 0x550C7DC3, 0x72BE5D74, 0x80DEB1FE, 0x9BDC06A7, 0xC19BF174,
+        0xE49B69C1, 0xEFBE4786, 0x0FC19DC6, 0x240CA1CC, 0x2DE92C6F, 0x4A7484AA, 0x5CB0A9DC, 0x76F988DA,
+        0x983E5152, 0xA831C66D, 0xB00327C8, 0xBF597FC7, 0xC6E00BF3, 0xD5A79147, 0x06CA6351, 0x14292967,
+        0x27B70A85, 0x2E1B2138, 0x4D2C6DFC, 0x53380D13, 0x650A7354, 0x766A0ABB, 0x81C2C92E, 0x92722C85,
+        0xA2BFE8A1, 0xA81A664B, 0xC24B8B70, 0xC76C51A3, 0xD192E819, 0xD6990624, 0xF40E3585, 0x106AA070,
+        0x19A4C116, 0x1E376C08, 0x2748774C, 0x34B0BCB5, 0x391C0CB3, 0x4ED8AA4A, 0x5B9CCA4F, 0x682E6FF3,
+        0x748F82EE, 0x78A5636F, 0x84C87814, 0x8CC70208, 0x90BEFFFA, 0xA4506CEB, 0xBEF9A3F7, 0xC67178F2,
+    ];
+
+    const SHA512_K: [u64; 80] = [
+        0x428A2F98D728AE22, 0x7137449123EF65CD, 0xB5C0FBCFEC4D3B2F, 0xE9B5DBA58189DBBC,
+        0x3956C25BF348B538, 0x59F111F1B605D019, 0x923F82A4AF194F9B, 0xAB1C5ED5DA6D8118,
+        0xD807AA98A3030242, 0x12835B0145706FBE, 0x243185BE4EE4B28C, 0x550C7DC3D5FFB4E2,
+        0x72BE5D74F27B896F, 0x80DEB1FE3B1696B1, 0x9BDC06A725C71235, 0xC19BF174CF692694,
+        0xE49B69C19EF14AD2, 0xEFBE4786384F25E3, 0x0FC19DC68B8CD5B5, 0x240CA1CC77AC9C65,
+        0x2DE92C6F592B0275, 0x4A7484AA6EA6E483, 0x5CB0A9DCBD41FBD4, 0x76F988DA831153B5,
+        0x983E5152EE66DFAB, 0xA831C66D2DB43210, 0xB00327C898FB213F, 0xBF597FC7BEEF0EE4,
+        0xC6E00BF33DA88FC2, 0xD5A79147930AA725, 0x06CA6351E003826F, 0x142929670A0E6E70,
+        0x27B70A8546D22FFC, 0x2E1B21385C26C926, 0x4D2C6DFC5AC42AED, 0x53380D139D95B3DF,
+        0x650A73548BAF63DE, 0x766A0ABB3C77B2A8, 0x81C2C92E47EDAEE6, 0x92722C851482353B,
+        0xA2BFE8A14CF10364, 0xA81A664BBC423001, 0xC24B8B70D0F89791, 0xC76C51A30654BE30,
+        0xD192E819D6EF5218, 0xD69906245565A910, 0xF40E35855771202A, 0x106AA07032BBD1B8,
+        0x19A4C116B8D2D0C8, 0x1E376C085141AB53, 0x2748774CDF8EEB99, 0x34B0BCB5E19B48A8,
+        0x391C0CB3C5C95A63, 0x4ED8AA4AE3418ACB, 0x5B9CCA4F7763E373, 0x682E6FF3D6B2B8A3,
+        0x748F82EE5DEFB2FC, 0x78A5636F43172F60, 0x84C87814A1F0AB72, 0x8CC702081A6439EC,
+        0x90BEFFFA23631E28, 0xA4506CEBDE82BDE9, 0xBEF9A3F7B2C67915, 0xC67178F2E372532B,
+        0xCA273ECEEA26619C, 0xD186B8C721C0C207, 0xEADA7DD6CDE0EB1E, 0xF57D4F7FEE6ED178,
+        0x06F067AA72176FBA, 0x0A637DC5A2C898A6, 0x113F9804BEF90DAE, 0x1B710B35131C471B,
+        0x28DB77F523047D84, 0x32CAAB7B40C72493, 0x3C9EBE0A15C9BEBC, 0x431D67C49C100D4C,
+        0x4CC5D4BECB3E42B6, 0x597F299CFC657E2A, 0x5FCB6FAB3AD6FAEC, 0x6C44198C4A475817,
+    ];
+
+    /// A 128-bit block in the two registers `aes64*` keep it in: the
+    /// first byte lowest.
+    fn halves(block: u128) -> [u64; 2] {
+        let bytes = block.to_be_bytes();
+        [u64::from_le_bytes(bytes[..8].try_into().unwrap()), u64::from_le_bytes(bytes[8..].try_into().unwrap())]
+    }
+
+    /// AES-128's round keys, expanded with `aes64ks1i` and `aes64ks2`.
+    fn round_keys(key: u128) -> Vec<[u64; 2]> {
+        let mut keys = vec![halves(key)];
+        for round in 0..10 {
+            let [low, high] = keys[round];
+            let word = Aes64Ks1i(round as u32).apply(high, 0);
+            let low = Aes64Ks2.apply(word, low);
+            keys.push([low, Aes64Ks2.apply(low, high)]);
+        }
+        keys
+    }
+
+    #[test]
+    fn aes64() {
+        let keys = round_keys(AES_KEY);
+        let round = |state: [u64; 2], decrypt: bool, middle: bool, key: [u64; 2]| {
+            let op = Aes64 { decrypt, middle };
+            [op.apply(state[0], state[1]) ^ key[0], op.apply(state[1], state[0]) ^ key[1]]
+        };
+        let plain = halves(AES_PLAIN);
+        let mut state = [plain[0] ^ keys[0][0], plain[1] ^ keys[0][1]];
+        for key in &keys[1..10] {
+            state = round(state, false, true, *key);
+        }
+        state = round(state, false, false, keys[10]);
+        assert_eq!(state, halves(AES_CIPHER));
+
+        // the equivalent inverse cipher, with InvMixColumns applied to the
+        // middle round keys.
+        state = [state[0] ^ keys[10][0], state[1] ^ keys[10][1]];
+        for key in keys[1..10].iter().rev() {
+            state = round(state, true, true, [Aes64Im.apply(key[0], 0), Aes64Im.apply(key[1], 0)]);
+        }
+        state = round(state, true, false, keys[0]);
+        assert_eq!(state, plain);
+    }
+
+    #[test]
+    fn aes32() {
+        let columns = |half: [u64; 2]| [half[0] as u32, (half[0] >> 32) as u32, half[1] as u32, (half[1] >> 32) as u32];
+        let keys: Vec<[u32; 4]> = round_keys(AES_KEY).into_iter().map(columns).collect();
+        // column `j` of a round takes row `i` from column `j + i`, or
+        // `j - i` decrypting.
+        let round = |state: [u32; 4], decrypt: bool, middle: bool, key: [u32; 4]| {
+            let mut out = key;
+            for (j, column) in out.iter_mut().enumerate() {
+                for bs in 0..4 {
+                    let from = if decrypt { (j + 4 - bs) % 4 } else { (j + bs) % 4 };
+                    let op = Aes32 { decrypt, middle, bs: bs as u32 };
+                    *column = op.apply(*column as u64, state[from] as u64) as u32;
+                }
+            }
+            out
+        };
+        let plain = columns(halves(AES_PLAIN));
+        let mut state: [u32; 4] = std::array::from_fn(|i| plain[i] ^ keys[0][i]);
+        for key in &keys[1..10] {
+            state = round(state, false, true, *key);
+        }
+        state = round(state, false, false, keys[10]);
+        assert_eq!(state, columns(halves(AES_CIPHER)));
+
+        // RV32 has no `aes64im`, so InvMixColumns of the middle round keys
+        // is left to software.
+        state = std::array::from_fn(|i| state[i] ^ keys[10][i]);
+        for key in keys[1..10].iter().rev() {
+            let mixed = key.map(|word| mix_column(word, INVERSE_MIX));
+            state = round(state, true, true, mixed);
+        }
+        state = round(state, true, false, keys[0]);
+        assert_eq!(state, plain);
+    }
+
+    #[test]
+    fn sha256() {
+        let unary = |op: CryptoOp, x: u32| op.apply(x as u64, 0) as u32;
+        let mut w = [0u32; 64];
+        w[0] = 0x61626380; // "abc", then the padding
+        w[15] = 24;
+        for i in 16..64 {
+            w[i] = unary(Sha256Sig1, w[i - 2])
+                .wrapping_add(w[i - 7])
+                .wrapping_add(unary(Sha256Sig0, w[i - 15]))
+                .wrapping_add(w[i - 16]);
+        }
+        let iv: [u32; 8] = [0x6A09E667, 0xBB67AE85, 0x3C6EF372, 0xA54FF53A, 0x510E527F, 0x9B05688C, 0x1F83D9AB, 0x5BE0CD19];
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = iv;
+        for i in 0..64 {
+            let t1 = h
+                .wrapping_add(unary(Sha256Sum1, e))
+                .wrapping_add((e & f) ^ (!e & g))
+                .wrapping_add(SHA256_K[i])
+                .wrapping_add(w[i]);
+            let t2 = unary(Sha256Sum0, a).wrapping_add((a & b) ^ (a & c) ^ (b & c));
+            (h, g, f, e, d, c, b, a) = (g, f, e, d.wrapping_add(t1), c, b, a, t1.wrapping_add(t2));
+        }
+        let digest: Vec<u32> = [a, b, c, d, e, f, g, h].iter().zip(iv).map(|(x, y)| x.wrapping_add(y)).collect();
+        assert_eq!(
+            digest,
+            [0xBA7816BF, 0x8F01CFEA, 0x414140DE, 0x5DAE2223, 0xB00361A3, 0x96177A9C, 0xB410FF61, 0xF20015AD]
+        );
+    }
+
+    #[test]
+    fn sha512() {
+        let unary = |op: CryptoOp, x: u64| op.apply(x, 0);
+        let mut w = [0u64; 80];
+        w[0] = 0x6162638000000000;
+        w[15] = 24;
+        for i in 16..80 {
+            w[i] = unary(Sha512Sig1, w[i - 2])
+                .wrapping_add(w[i - 7])
+                .wrapping_add(unary(Sha512Sig0, w[i - 15]))
+                .wrapping_add(w[i - 16]);
+        }
+        let iv: [u64; 8] = [
+            0x6A09E667F3BCC908, 0xBB67AE8584CAA73B, 0x3C6EF372FE94F82B, 0xA54FF53A5F1D36F1,
+            0x510E527FADE682D1, 0x9B05688C2B3E6C1F, 0x1F83D9ABFB41BD6B, 0x5BE0CD19137E2179,
+        ];
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = iv;
+        for i in 0..80 {
+            let t1 = h
+                .wrapping_add(unary(Sha512Sum1, e))
+                .wrapping_add((e & f) ^ (!e & g))
+                .wrapping_add(SHA512_K[i])
+                .wrapping_add(w[i]);
+            let t2 = unary(Sha512Sum0, a).wrapping_add((a & b) ^ (a & c) ^ (b & c));
+            (h, g, f, e, d, c, b, a) = (g, f, e, d.wrapping_add(t1), c, b, a, t1.wrapping_add(t2));
+        }
+        let digest: Vec<u64> = [a, b, c, d, e, f, g, h].iter().zip(iv).map(|(x, y)| x.wrapping_add(y)).collect();
+        assert_eq!(
+            digest,
+            [
+                0xDDAF35A193617ABA, 0xCC417349AE204131, 0x12E6FA4E89A97EA2, 0x0A9EEEE64B55D39A,
+                0x2192992A274FC1A8, 0x36BA3C23A3FEEBBD, 0x454D4423643CE80E, 0x2A9AC94FA54CA49F,
+            ]
+        );
+
+        // RV32 computes the same functions a half at a time.
+        for x in w {
+            let (high, low) = (x >> 32, x & 0xFFFF_FFFF);
+            let half = |op: CryptoOp, a: u64, b: u64| op.apply(a, b) & 0xFFFF_FFFF;
+            let join = |high: u64, low: u64| (high << 32) | low;
+            assert_eq!(join(half(Sha512Sig0h, high, low), half(Sha512Sig0l, low, high)), unary(Sha512Sig0, x));
+            assert_eq!(join(half(Sha512Sig1h, high, low), half(Sha512Sig1l, low, high)), unary(Sha512Sig1, x));
+            assert_eq!(join(half(Sha512Sum0r, high, low), half(Sha512Sum0r, low, high)), unary(Sha512Sum0, x));
+            assert_eq!(join(half(Sha512Sum1r, high, low), half(Sha512Sum1r, low, high)), unary(Sha512Sum1, x));
+        }
+    }
+
+    #[test]
+    fn sm3() {
+        let p0 = |x: u32| Sm3P0.apply(x as u64, 0) as u32;
+        let p1 = |x: u32| Sm3P1.apply(x as u64, 0) as u32;
+        let mut w = [0u32; 68];
+        w[0] = 0x61626380; // "abc", then the padding
+        w[15] = 24;
+        for j in 16..68 {
+            w[j] = p1(w[j - 16] ^ w[j - 9] ^ w[j - 3].rotate_left(15)) ^ w[j - 13].rotate_left(7) ^ w[j - 6];
+        }
+        let iv: [u32; 8] = [0x7380166F, 0x4914B2B9, 0x172442D7, 0xDA8A0600, 0xA96F30BC, 0x163138AA, 0xE38DEE4D, 0xB0FB0E4E];
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = iv;
+        for j in 0..64 {
+            let t: u32 = if j < 16 { 0x79CC4519 } else { 0x7A879D8A };
+            let ss1 = a.rotate_left(12).wrapping_add(e).wrapping_add(t.rotate_left(j as u32 % 32)).rotate_left(7);
+            let ss2 = ss1 ^ a.rotate_left(12);
+            let (ff, gg) = if j < 16 { (a ^ b ^ c, e ^ f ^ g) } else { ((a & b) | (a & c) | (b & c), (e & f) | (!e & g)) };
+            let tt1 = ff.wrapping_add(d).wrapping_add(ss2).wrapping_add(w[j] ^ w[j + 4]);
+            let tt2 = gg.wrapping_add(h).wrapping_add(ss1).wrapping_add(w[j]);
+            (d, c, b, a) = (c, b.rotate_left(9), a, tt1);
+            (h, g, f, e) = (g, f.rotate_left(19), e, p0(tt2));
+        }
+        let digest: Vec<u32> = [a, b, c, d, e, f, g, h].iter().zip(iv).map(|(x, y)| x ^ y).collect();
+        assert_eq!(
+            digest,
+            [0x66C7F0F4, 0x62EEEDD9, 0xD1F2D46B, 0xDC10E4E2, 0x4167C487, 0x5CF2F7A2, 0x297DA02B, 0x8F4BA8E0]
+        );
+    }
+
+    #[test]
+    fn sm4() {
+        // GB/T 32907-2016 appendix A, example 1, where the key is the
+        // plaintext. The instructions take words as loaded from memory,
+        // little-endian, where SM4 reads them big-endian.
+        let block = [0x01234567u32, 0x89ABCDEF, 0xFEDCBA98, 0x76543210].map(u32::swap_bytes);
+        // `x ^ T(y)` a byte of `y` at a time, `T` being the round's or the
+        // key schedule's.
+        let t = |key: bool, x: u32, y: u32| {
+            (0..4).fold(x, |out, bs| Sm4 { key, bs }.apply(out as u64, y as u64) as u32)
+        };
+        let fk = [0xA3B1BAC6u32, 0x56AA3350, 0x677D9197, 0xB27022DC].map(u32::swap_bytes);
+        let mut k: Vec<u32> = block.iter().zip(fk).map(|(x, y)| x ^ y).collect();
+        for i in 0..32 {
+            let ck = u32::from_le_bytes(std::array::from_fn(|j| ((4 * i + j) * 7) as u8));
+            k.push(t(true, k[i], k[i + 1] ^ k[i + 2] ^ k[i + 3] ^ ck));
+        }
+        let mut x = block.to_vec();
+        for i in 0..32 {
+            x.push(t(false, x[i], x[i + 1] ^ x[i + 2] ^ x[i + 3] ^ k[i + 4]));
+        }
+        let cipher = [x[35], x[34], x[33], x[32]].map(u32::swap_bytes);
+        assert_eq!(cipher, [0x681EDF34, 0xD206965E, 0x86B3E94F, 0x536E4246]);
+    }
+}
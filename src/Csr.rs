@@ -0,0 +1,310 @@
+//! CSR addresses, as listed in the privileged specification (tables 2.2
+//! through 2.6).
+
+// Unprivileged Floating-Point CSRs
+pub const FFLAGS: usize = 0x001;
+pub const FRM: usize = 0x002;
+pub const FCSR: usize = 0x003;
+// Unprivileged Vector CSRs
+pub const VSTART: usize = 0x008;
+pub const VXSAT: usize = 0x009;
+pub const VXRM: usize = 0x00A;
+pub const VCSR: usize = 0x00F;
+pub const VL: usize = 0xC20;
+pub const VTYPE: usize = 0xC21;
+pub const VLENB: usize = 0xC22;
+// Unprivileged Counter/Timers
+pub const CYCLE: usize = 0xC00;
+pub const TIME: usize = 0xC01;
+pub const INSTRET: usize = 0xC02;
+pub const HPMCOUNTER3: usize = 0xC03;
+pub const HPMCOUNTER31: usize = 0xC1F;
+// ... and their upper halves, on RV32 only
+pub const CYCLEH: usize = 0xC80;
+pub const TIMEH: usize = 0xC81;
+pub const INSTRETH: usize = 0xC82;
+pub const HPMCOUNTER3H: usize = 0xC83;
+pub const HPMCOUNTER31H: usize = 0xC9F;
+
+// Supervisor Trap Setup
+pub const SSTATUS: usize = 0x100;
+pub const SIE: usize = 0x104;
+pub const STVEC: usize = 0x105;
+pub const SCOUNTEREN: usize = 0x106;
+// Supervisor Configuration
+pub const SENVCFG: usize = 0x10A;
+// Supervisor Trap Handling
+pub const SSCRATCH: usize = 0x140;
+pub const SEPC: usize = 0x141;
+pub const SCAUSE: usize = 0x142;
+pub const STVAL: usize = 0x143;
+pub const SIP: usize = 0x144;
+// Supervisor Protection and Translation
+pub const SATP: usize = 0x180;
+
+// Hypervisor Trap Setup
+pub const HSTATUS: usize = 0x600;
+pub const HEDELEG: usize = 0x602;
+pub const HIDELEG: usize = 0x603;
+pub const HIE: usize = 0x604;
+pub const HCOUNTEREN: usize = 0x606;
+pub const HGEIE: usize = 0x607;
+// Hypervisor Trap Handling
+pub const HTVAL: usize = 0x643;
+pub const HIP: usize = 0x644;
+pub const HVIP: usize = 0x645;
+pub const HTINST: usize = 0x64A;
+pub const HGEIP: usize = 0xE12;
+// Hypervisor Configuration
+pub const HENVCFG: usize = 0x60A;
+pub const HENVCFGH: usize = 0x61A;
+// Hypervisor Protection and Translation
+pub const HGATP: usize = 0x680;
+// Hypervisor Counter/Timer Virtualization
+pub const HTIMEDELTA: usize = 0x605;
+pub const HTIMEDELTAH: usize = 0x615;
+// Virtual Supervisor Registers
+pub const VSSTATUS: usize = 0x200;
+pub const VSIE: usize = 0x204;
+pub const VSTVEC: usize = 0x205;
+pub const VSSCRATCH: usize = 0x240;
+pub const VSEPC: usize = 0x241;
+pub const VSCAUSE: usize = 0x242;
+pub const VSTVAL: usize = 0x243;
+pub const VSIP: usize = 0x244;
+pub const VSATP: usize = 0x280;
+
+// Machine Information Registers
+pub const MVENDORID: usize = 0xF11;
+pub const MARCHID: usize = 0xF12;
+pub const MIMPID: usize = 0xF13;
+pub const MHARTID: usize = 0xF14;
+pub const MCONFIGPTR: usize = 0xF15;
+// Machine Trap Setup
+pub const MSTATUS: usize = 0x300;
+pub const MISA: usize = 0x301;
+pub const MEDELEG: usize = 0x302;
+pub const MIDELEG: usize = 0x303;
+pub const MIE: usize = 0x304;
+pub const MTVEC: usize = 0x305;
+pub const MCOUNTEREN: usize = 0x306;
+pub const MSTATUSH: usize = 0x310;
+// Machine Trap Handling
+pub const MSCRATCH: usize = 0x340;
+pub const MEPC: usize = 0x341;
+pub const MCAUSE: usize = 0x342;
+pub const MTVAL: usize = 0x343;
+pub const MIP: usize = 0x344;
+pub const MTINST: usize = 0x34A;
+pub const MTVAL2: usize = 0x34B;
+// Machine Configuration
+pub const MENVCFG: usize = 0x30A;
+pub const MENVCFGH: usize = 0x31A;
+// Machine Memory Protection
+pub const PMPCFG0: usize = 0x3A0;
+pub const PMPCFG15: usize = 0x3AF;
+pub const PMPADDR0: usize = 0x3B0;
+pub const PMPADDR63: usize = 0x3EF;
+// Machine Counter/Timers
+pub const MCYCLE: usize = 0xB00;
+pub const MINSTRET: usize = 0xB02;
+pub const MHPMCOUNTER3: usize = 0xB03;
+pub const MHPMCOUNTER31: usize = 0xB1F;
+pub const MCYCLEH: usize = 0xB80;
+pub const MINSTRETH: usize = 0xB82;
+pub const MHPMCOUNTER3H: usize = 0xB83;
+pub const MHPMCOUNTER31H: usize = 0xB9F;
+// Machine Counter Setup
+pub const MCOUNTINHIBIT: usize = 0x320;
+pub const MHPMEVENT3: usize = 0x323;
+pub const MHPMEVENT31: usize = 0x33F;
+// Debug/Trace Registers
+pub const TSELECT: usize = 0x7A0;
+pub const TDATA1: usize = 0x7A1;
+pub const TDATA2: usize = 0x7A2;
+pub const TDATA3: usize = 0x7A3;
+
+// `mstatus` fields
+pub const MSTATUS_SIE: u64 = 1 << 1;
+pub const MSTATUS_MIE: u64 = 1 << 3;
+pub const MSTATUS_SPIE: u64 = 1 << 5;
+pub const MSTATUS_UBE: u64 = 1 << 6;
+pub const MSTATUS_MPIE: u64 = 1 << 7;
+pub const MSTATUS_SPP: u64 = 1 << 8;
+pub const MSTATUS_VS: u64 = 0b11 << 9;
+pub const MSTATUS_MPP: u64 = 0b11 << 11;
+pub const MSTATUS_FS: u64 = 0b11 << 13;
+pub const MSTATUS_XS: u64 = 0b11 << 15;
+pub const MSTATUS_MPRV: u64 = 1 << 17;
+pub const MSTATUS_SUM: u64 = 1 << 18;
+pub const MSTATUS_MXR: u64 = 1 << 19;
+pub const MSTATUS_TVM: u64 = 1 << 20;
+pub const MSTATUS_TW: u64 = 1 << 21;
+pub const MSTATUS_TSR: u64 = 1 << 22;
+pub const MSTATUS_UXL: u64 = 0b11 << 32;
+pub const MSTATUS_SXL: u64 = 0b11 << 34;
+pub const MSTATUS_GVA: u64 = 1 << 38;
+pub const MSTATUS_MPV: u64 = 1 << 39;
+pub const MSTATUS_SD: u64 = 1 << 63;
+
+/// The parts of `mstatus` visible through `sstatus`.
+pub const SSTATUS_MASK: u64 = MSTATUS_SIE
+    | MSTATUS_SPIE
+    | MSTATUS_UBE
+    | MSTATUS_SPP
+    | MSTATUS_VS
+    | MSTATUS_FS
+    | MSTATUS_XS
+    | MSTATUS_SUM
+    | MSTATUS_MXR
+    | MSTATUS_UXL
+    | MSTATUS_SD;
+
+// `mip`/`mie` bits
+pub const IRQ_S_SOFT: u64 = 1;
+pub const IRQ_M_SOFT: u64 = 3;
+pub const IRQ_S_TIMER: u64 = 5;
+pub const IRQ_M_TIMER: u64 = 7;
+pub const IRQ_S_EXT: u64 = 9;
+pub const IRQ_M_EXT: u64 = 11;
+pub const MIP_SSIP: u64 = 1 << IRQ_S_SOFT;
+pub const MIP_MSIP: u64 = 1 << IRQ_M_SOFT;
+pub const MIP_STIP: u64 = 1 << IRQ_S_TIMER;
+pub const MIP_MTIP: u64 = 1 << IRQ_M_TIMER;
+pub const MIP_SEIP: u64 = 1 << IRQ_S_EXT;
+pub const MIP_MEIP: u64 = 1 << IRQ_M_EXT;
+/// Interrupts that can be delegated to S-mode through `mideleg`.
+pub const S_INTERRUPTS: u64 = MIP_SSIP | MIP_STIP | MIP_SEIP;
+pub const IRQ_VS_SOFT: u64 = 2;
+pub const IRQ_VS_TIMER: u64 = 6;
+pub const IRQ_VS_EXT: u64 = 10;
+pub const MIP_VSSIP: u64 = 1 << IRQ_VS_SOFT;
+pub const MIP_VSTIP: u64 = 1 << IRQ_VS_TIMER;
+pub const MIP_VSEIP: u64 = 1 << IRQ_VS_EXT;
+/// The VS-level interrupts, which `hideleg` can delegate on to VS-mode and
+/// which are always delegated to HS-mode.
+pub const VS_INTERRUPTS: u64 = MIP_VSSIP | MIP_VSTIP | MIP_VSEIP;
+
+// `hstatus` fields
+pub const HSTATUS_GVA: u64 = 1 << 6;
+pub const HSTATUS_SPV: u64 = 1 << 7;
+pub const HSTATUS_SPVP: u64 = 1 << 8;
+pub const HSTATUS_HU: u64 = 1 << 9;
+pub const HSTATUS_VTVM: u64 = 1 << 20;
+pub const HSTATUS_VTW: u64 = 1 << 21;
+pub const HSTATUS_VTSR: u64 = 1 << 22;
+
+// `satp` fields
+pub const SATP_MODE_SHIFT: u64 = 60;
+pub const SATP_MODE_BARE: u64 = 0;
+pub const SATP_MODE_SV39: u64 = 8;
+pub const SATP_PPN_MASK: u64 = (1 << 44) - 1;
+/// Sv32 is on when bit 31 of a 32-bit `satp` is set.
+pub const SATP32_MODE_SV32: u64 = 1 << 31;
+
+// `hgatp` fields, where the mode and PPN are where `satp` has them
+pub const HGATP_MODE_SV39X4: u64 = 8;
+pub const HGATP32_MODE_SV32X4: u64 = 1 << 31;
+
+// `vtype` fields. `vill` is bit XLEN-1, and kept at bit 63 whatever the
+// XLEN.
+pub const VTYPE_VTA: u64 = 1 << 6;
+pub const VTYPE_VMA: u64 = 1 << 7;
+pub const VTYPE_VILL: u64 = 1 << 63;
+
+/// Returns the name of a CSR, for disassembly and the debugger.
+pub fn csr_name(addr: usize) -> Option<&'static str> {
+    let name = match addr {
+        FFLAGS => "fflags",
+        FRM => "frm",
+        FCSR => "fcsr",
+        VSTART => "vstart",
+        VXSAT => "vxsat",
+        VXRM => "vxrm",
+        VCSR => "vcsr",
+        VL => "vl",
+        VTYPE => "vtype",
+        VLENB => "vlenb",
+        CYCLE => "cycle",
+        TIME => "time",
+        INSTRET => "instret",
+        CYCLEH => "cycleh",
+        TIMEH => "timeh",
+        INSTRETH => "instreth",
+        SSTATUS => "sstatus",
+        SIE => "sie",
+        STVEC => "stvec",
+        SCOUNTEREN => "scounteren",
+        SENVCFG => "senvcfg",
+        SSCRATCH => "sscratch",
+        SEPC => "sepc",
+        SCAUSE => "scause",
+        STVAL => "stval",
+        SIP => "sip",
+        SATP => "satp",
+        HSTATUS => "hstatus",
+        HEDELEG => "hedeleg",
+        HIDELEG => "hideleg",
+        HIE => "hie",
+        HCOUNTEREN => "hcounteren",
+        HGEIE => "hgeie",
+        HTVAL => "htval",
+        HIP => "hip",
+        HVIP => "hvip",
+        HTINST => "htinst",
+        HGEIP => "hgeip",
+        HENVCFG => "henvcfg",
+        HENVCFGH => "henvcfgh",
+        HGATP => "hgatp",
+        HTIMEDELTA => "htimedelta",
+        HTIMEDELTAH => "htimedeltah",
+        VSSTATUS => "vsstatus",
+        VSIE => "vsie",
+        VSTVEC => "vstvec",
+        VSSCRATCH => "vsscratch",
+        VSEPC => "vsepc",
+        VSCAUSE => "vscause",
+        VSTVAL => "vstval",
+        VSIP => "vsip",
+        VSATP => "vsatp",
+        MVENDORID => "mvendorid",
+        MARCHID => "marchid",
+        MIMPID => "mimpid",
+        MHARTID => "mhartid",
+        MCONFIGPTR => "mconfigptr",
+        MSTATUS => "mstatus",
+        MISA => "misa",
+        MEDELEG => "medeleg",
+        MIDELEG => "mideleg",
+        MIE => "mie",
+        MTVEC => "mtvec",
+        MCOUNTEREN => "mcounteren",
+        MSTATUSH => "mstatush",
+        MENVCFG => "menvcfg",
+        MENVCFGH => "menvcfgh",
+        MSCRATCH => "mscratch",
+        MEPC => "mepc",
+        MCAUSE => "mcause",
+        MTVAL => "mtval",
+        MIP => "mip",
+        MTINST => "mtinst",
+        MTVAL2 => "mtval2",
+        MCYCLE => "mcycle",
+        MINSTRET => "minstret",
+        MCYCLEH => "mcycleh",
+        MINSTRETH => "minstreth",
+        MCOUNTINHIBIT => "mcountinhibit",
+        TSELECT => "tselect",
+        TDATA1 => "tdata1",
+        TDATA2 => "tdata2",
+        TDATA3 => "tdata3",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Whether `addr` is one of the `H` Extension's CSRs: the hypervisor and
+/// VS ones at privilege level 2, and `mtval2` and `mtinst`.
+pub fn hypervisor_csr(addr: usize) -> bool {
+    (addr >> 8) & 0b11 == 0b10 || matches!(addr, MTINST | MTVAL2)
+}
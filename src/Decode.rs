@@ -0,0 +1,745 @@
+use crate::Crypto::CryptoOp;
+use crate::Execute::sext;
+use crate::Hypervisor::HypervisorAccess;
+use crate::InstructionDecoder::expand_compressed;
+use crate::Isa::Isa;
+use crate::Vector::{VectorAccess, VectorOp};
+
+/// What a `Decoded` instruction does, one per `Instruction` variant that
+/// `Instruction::parse_instruction` can return.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Op {
+    // `RV32I` Base Instructions
+    LUI,
+    AUIPC,
+    JAL,
+    JALR,
+    BEQ,
+    BNE,
+    BLT,
+    BGE,
+    BLTU,
+    BGEU,
+    LB,
+    LH,
+    LW,
+    LBU,
+    LHU,
+    SB,
+    SH,
+    SW,
+    ADDI,
+    SLTI,
+    SLTIU,
+    XORI,
+    ORI,
+    ANDI,
+    SLLI,
+    SRLI,
+    SRAI,
+    ADD,
+    SUB,
+    SLL,
+    SLT,
+    SLTU,
+    XOR,
+    SRL,
+    SRA,
+    OR,
+    AND,
+    FENCE,
+    ECALL,
+    EBREAK,
+    // Privileged Instructions
+    MRET,
+    SRET,
+    WFI,
+    SFENCEVMA,
+    // `RV64I` Base Instructions
+    LWU,
+    LD,
+    SD,
+    ADDIW,
+    SLLIW,
+    SRLIW,
+    SRAIW,
+    ADDW,
+    SUBW,
+    SLLW,
+    SRLW,
+    SRAW,
+    // `Zifencei` Extension
+    FENCEI,
+    // `Zicsr` Extension
+    CSRRW,
+    CSRRS,
+    CSRRC,
+    CSRRWI,
+    CSRRSI,
+    CSRRCI,
+    // `M` Extension
+    MUL,
+    MULH,
+    MULHSU,
+    MULHU,
+    DIV,
+    DIVU,
+    REM,
+    REMU,
+    MULW,
+    DIVW,
+    DIVUW,
+    REMW,
+    REMUW,
+    // `A` Extension
+    LRW,
+    SCW,
+    AMOSWAPW,
+    AMOADDW,
+    AMOXORW,
+    AMOANDW,
+    AMOORW,
+    AMOMINW,
+    AMOMAXW,
+    AMOMINUW,
+    AMOMAXUW,
+    LRD,
+    SCD,
+    AMOSWAPD,
+    AMOADDD,
+    AMOXORD,
+    AMOANDD,
+    AMOORD,
+    AMOMIND,
+    AMOMAXD,
+    AMOMINUD,
+    AMOMAXUD,
+    // `F` Extension
+    FLW,
+    FSW,
+    FMADDS,
+    FMSUBS,
+    FNMSUBS,
+    FNMADDS,
+    FADDS,
+    FSUBS,
+    FMULS,
+    FDIVS,
+    FSQRTS,
+    FSGNJS,
+    FSGNJNS,
+    FSGNJXS,
+    FMINS,
+    FMAXS,
+    FCVTWS,
+    FCVTWUS,
+    FMVXW,
+    FEQS,
+    FLTS,
+    FLES,
+    FCLASSS,
+    FCVTSW,
+    FCVTSWU,
+    FMVWX,
+    FCVTLS,
+    FCVTLUS,
+    FCVTSL,
+    FCVTSLU,
+    // `D` Extension
+    FLD,
+    FSD,
+    FMADDD,
+    FMSUBD,
+    FNMSUBD,
+    FNMADDD,
+    FADDD,
+    FSUBD,
+    FMULD,
+    FDIVD,
+    FSQRTD,
+    FSGNJD,
+    FSGNJND,
+    FSGNJXD,
+    FMIND,
+    FMAXD,
+    FCVTSD,
+    FCVTDS,
+    FEQD,
+    FLTD,
+    FLED,
+    FCLASSD,
+    FCVTWD,
+    FCVTWUD,
+    FCVTDW,
+    FCVTDWU,
+    FCVTLD,
+    FCVTLUD,
+    FMVXD,
+    FCVTDL,
+    FCVTDLU,
+    FMVDX,
+    // `Zfh` Extension, with the loads, stores, moves and conversions
+    // between formats of `Zfhmin`
+    FLH,
+    FSH,
+    FMADDH,
+    FMSUBH,
+    FNMSUBH,
+    FNMADDH,
+    FADDH,
+    FSUBH,
+    FMULH,
+    FDIVH,
+    FSQRTH,
+    FSGNJH,
+    FSGNJNH,
+    FSGNJXH,
+    FMINH,
+    FMAXH,
+    FCVTSH,
+    FCVTHS,
+    FCVTDH,
+    FCVTHD,
+    FEQH,
+    FLTH,
+    FLEH,
+    FCLASSH,
+    FCVTWH,
+    FCVTWUH,
+    FCVTHW,
+    FCVTHWU,
+    FMVXH,
+    FMVHX,
+    FCVTLH,
+    FCVTLUH,
+    FCVTHL,
+    FCVTHLU,
+    // `Zba` Extension
+    SH1ADD,
+    SH2ADD,
+    SH3ADD,
+    ADDUW,
+    SH1ADDUW,
+    SH2ADDUW,
+    SH3ADDUW,
+    SLLIUW,
+    // `Zbb` Extension
+    ANDN,
+    ORN,
+    XNOR,
+    CLZ,
+    CTZ,
+    CPOP,
+    MAX,
+    MAXU,
+    MIN,
+    MINU,
+    SEXTB,
+    SEXTH,
+    ZEXTH,
+    ROL,
+    ROR,
+    RORI,
+    ORCB,
+    REV8,
+    CLZW,
+    CTZW,
+    CPOPW,
+    ROLW,
+    RORW,
+    RORIW,
+    // `Zbc` Extension
+    CLMUL,
+    CLMULH,
+    CLMULR,
+    // `Zbs` Extension
+    BCLR,
+    BCLRI,
+    BEXT,
+    BEXTI,
+    BINV,
+    BINVI,
+    BSET,
+    BSETI,
+    // `Zbkb` Extension
+    PACK,
+    PACKH,
+    PACKW,
+    BREV8,
+    ZIP,
+    UNZIP,
+    // `Zknd`, `Zkne`, `Zknh`, `Zksed` and `Zksh`, which `Crypto` decodes
+    // further
+    CRYPTO,
+    // `V` Extension: the configuration instructions, and the loads,
+    // stores and arithmetic, which `Vector` decodes further
+    VSETVLI,
+    VSETIVLI,
+    VSETVL,
+    VLOAD,
+    VSTORE,
+    VOP,
+    // `H` Extension, with the loads and stores `Hypervisor` decodes
+    // further
+    HFENCEVVMA,
+    HFENCEGVMA,
+    HLV,
+    HSV,
+}
+
+/// An instruction decoded for execution: 16 bytes and `Copy`, where an
+/// `Instruction` carries a whole format struct of `usize` fields.
+///
+/// Register numbers come straight from their fields, whether or not the
+/// instruction uses them. `imm` is the sign extended immediate of the
+/// instruction's format (already shifted for `LUI` and `AUIPC`), the shift
+/// amount for immediate shifts, and the CSR number for `Zicsr`, whose
+/// immediate forms take their 5 bit immediate from `rs1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decoded {
+    pub op: Op,
+    pub rd: u8,
+    pub rs1: u8,
+    pub rs2: u8,
+    /// The encoding as fetched, 16 bits for compressed instructions.
+    pub raw: u32,
+    pub imm: i64,
+}
+
+impl Decoded {
+    /// Decodes `raw`, expanding compressed instructions first. `None` for
+    /// anything `Instruction::parse_instruction` does not decode either,
+    /// extensions `isa` leaves out included.
+    pub fn decode(raw: u32, isa: &Isa) -> Option<Self> {
+        if !isa.allows(raw) {
+            return None;
+        }
+        let inst = if raw & 0b11 != 0b11 {
+            expand_compressed((raw & 0xFFFF) as usize, isa.xlen())? as u32
+        } else {
+            raw
+        };
+        let opcode = inst & 0x7F;
+        let funct3 = (inst >> 12) & 0b111;
+        let funct7 = inst >> 25;
+        let imm_i = sext((inst >> 20) as u64, 12);
+        let imm_s = sext((((inst >> 25) << 5) | ((inst >> 7) & 0x1F)) as u64, 12);
+        let imm_b = sext(
+            ((((inst >> 31) & 1) << 12) | (((inst >> 7) & 1) << 11) | (((inst >> 25) & 0x3F) << 5)
+                | (((inst >> 8) & 0xF) << 1)) as u64,
+            13,
+        );
+        let imm_u = sext((inst & 0xFFFFF000) as u64, 32);
+        let imm_j = sext(
+            ((((inst >> 31) & 1) << 20) | (inst & 0xFF000) | (((inst >> 20) & 1) << 11)
+                | (((inst >> 21) & 0x3FF) << 1)) as u64,
+            21,
+        );
+        let shamt = ((inst >> 20) & 0x3F) as u64;
+        let csr = ((inst >> 20) & 0xFFF) as u64;
+
+        let (op, imm) = match opcode {
+            0b0110111 => (Op::LUI, imm_u),
+            0b0010111 => (Op::AUIPC, imm_u),
+            0b1101111 => (Op::JAL, imm_j),
+            0b1100111 if funct3 == 0b000 => (Op::JALR, imm_i),
+            0b1100011 => {
+                let op = match funct3 {
+                    0b000 => Op::BEQ,
+                    0b001 => Op::BNE,
+                    0b100 => Op::BLT,
+                    0b101 => Op::BGE,
+                    0b110 => Op::BLTU,
+                    0b111 => Op::BGEU,
+                    _ => return None,
+                };
+                (op, imm_b)
+            },
+            0b0000011 => {
+                let op = match funct3 {
+                    0b000 => Op::LB,
+                    0b001 => Op::LH,
+                    0b010 => Op::LW,
+                    0b011 => Op::LD,
+                    0b100 => Op::LBU,
+                    0b101 => Op::LHU,
+                    0b110 => Op::LWU,
+                    _ => return None,
+                };
+                (op, imm_i)
+            },
+            0b0100011 => {
+                let op = match funct3 {
+                    0b000 => Op::SB,
+                    0b001 => Op::SH,
+                    0b010 => Op::SW,
+                    0b011 => Op::SD,
+                    _ => return None,
+                };
+                (op, imm_s)
+            },
+            0b0010011 => match (funct7 >> 1, funct3) {
+                (_, 0b000) => (Op::ADDI, imm_i),
+                (_, 0b010) => (Op::SLTI, imm_i),
+                (_, 0b011) => (Op::SLTIU, imm_i),
+                (_, 0b100) => (Op::XORI, imm_i),
+                (_, 0b110) => (Op::ORI, imm_i),
+                (_, 0b111) => (Op::ANDI, imm_i),
+                (0b000000, 0b001) => (Op::SLLI, shamt),
+                (0b000000, 0b101) => (Op::SRLI, shamt),
+                (0b010000, 0b101) => (Op::SRAI, shamt),
+                (0b011000, 0b101) => (Op::RORI, shamt),
+                (0b001010, 0b001) => (Op::BSETI, shamt),
+                (0b010010, 0b001) => (Op::BCLRI, shamt),
+                (0b010010, 0b101) => (Op::BEXTI, shamt),
+                (0b011010, 0b001) => (Op::BINVI, shamt),
+                _ => (unary(inst, isa.xlen()).or_else(|| crypto(inst, isa.xlen()))?, 0),
+            },
+            0b0110011 => {
+                let op = match (funct7, funct3) {
+                    (0b0000000, 0b000) => Op::ADD,
+                    (0b0100000, 0b000) => Op::SUB,
+                    (0b0000000, 0b001) => Op::SLL,
+                    (0b0000000, 0b010) => Op::SLT,
+                    (0b0000000, 0b011) => Op::SLTU,
+                    (0b0000000, 0b100) => Op::XOR,
+                    (0b0000000, 0b101) => Op::SRL,
+                    (0b0100000, 0b101) => Op::SRA,
+                    (0b0000000, 0b110) => Op::OR,
+                    (0b0000000, 0b111) => Op::AND,
+                    (0b0000001, 0b000) => Op::MUL,
+                    (0b0000001, 0b001) => Op::MULH,
+                    (0b0000001, 0b010) => Op::MULHSU,
+                    (0b0000001, 0b011) => Op::MULHU,
+                    (0b0000001, 0b100) => Op::DIV,
+                    (0b0000001, 0b101) => Op::DIVU,
+                    (0b0000001, 0b110) => Op::REM,
+                    (0b0000001, 0b111) => Op::REMU,
+                    (0b0010000, 0b010) => Op::SH1ADD,
+                    (0b0010000, 0b100) => Op::SH2ADD,
+                    (0b0010000, 0b110) => Op::SH3ADD,
+                    (0b0100000, 0b111) => Op::ANDN,
+                    (0b0100000, 0b110) => Op::ORN,
+                    (0b0100000, 0b100) => Op::XNOR,
+                    (0b0000101, 0b110) => Op::MAX,
+                    (0b0000101, 0b111) => Op::MAXU,
+                    (0b0000101, 0b100) => Op::MIN,
+                    (0b0000101, 0b101) => Op::MINU,
+                    (0b0000100, 0b100) if isa.xlen() == 32 && (inst >> 20) & 0x1F == 0 => Op::ZEXTH,
+                    (0b0000100, 0b100) if isa.zbkb => Op::PACK,
+                    (0b0000100, 0b111) => Op::PACKH,
+                    (0b0110000, 0b001) => Op::ROL,
+                    (0b0110000, 0b101) => Op::ROR,
+                    (0b0000101, 0b001) => Op::CLMUL,
+                    (0b0000101, 0b011) => Op::CLMULH,
+                    (0b0000101, 0b010) => Op::CLMULR,
+                    (0b0100100, 0b001) => Op::BCLR,
+                    (0b0100100, 0b101) => Op::BEXT,
+                    (0b0110100, 0b001) => Op::BINV,
+                    (0b0010100, 0b001) => Op::BSET,
+                    _ => crypto(inst, isa.xlen())?,
+                };
+                (op, 0)
+            },
+            0b0001111 => match funct3 {
+                0b000 => (Op::FENCE, imm_i),
+                0b001 => (Op::FENCEI, imm_i),
+                _ => return None,
+            },
+            0b1110011 => match funct3 {
+                0b000 => {
+                    let op = match (inst >> 20, funct7) {
+                        (0b00000000000, _) => Op::ECALL,
+                        (0b00000000001, _) => Op::EBREAK,
+                        (0b01100000010, _) => Op::MRET,
+                        (0b00100000010, _) => Op::SRET,
+                        (0b00100000101, _) => Op::WFI,
+                        (_, 0b0001001) => Op::SFENCEVMA,
+                        (_, 0b0010001) => Op::HFENCEVVMA,
+                        (_, 0b0110001) => Op::HFENCEGVMA,
+                        _ => return None,
+                    };
+                    (op, 0)
+                },
+                0b100 => (hypervisor(inst)?, 0),
+                0b001 => (Op::CSRRW, csr),
+                0b010 => (Op::CSRRS, csr),
+                0b011 => (Op::CSRRC, csr),
+                0b101 => (Op::CSRRWI, csr),
+                0b110 => (Op::CSRRSI, csr),
+                0b111 => (Op::CSRRCI, csr),
+                _ => return None,
+            },
+            0b0011011 => match (funct7, funct3) {
+                (_, 0b000) => (Op::ADDIW, imm_i),
+                (0b0000000, 0b001) => (Op::SLLIW, shamt),
+                (0b0000000, 0b101) => (Op::SRLIW, shamt),
+                (0b0100000, 0b101) => (Op::SRAIW, shamt),
+                (0b0110000, 0b101) => (Op::RORIW, shamt),
+                (0b0000100 | 0b0000101, 0b001) => (Op::SLLIUW, shamt),
+                _ => (unary(inst, isa.xlen())?, 0),
+            },
+            0b0111011 => {
+                let op = match (funct7, funct3) {
+                    (0b0000000, 0b000) => Op::ADDW,
+                    (0b0100000, 0b000) => Op::SUBW,
+                    (0b0000000, 0b001) => Op::SLLW,
+                    (0b0000000, 0b101) => Op::SRLW,
+                    (0b0100000, 0b101) => Op::SRAW,
+                    (0b0000100, 0b000) => Op::ADDUW,
+                    (0b0010000, 0b010) => Op::SH1ADDUW,
+                    (0b0010000, 0b100) => Op::SH2ADDUW,
+                    (0b0010000, 0b110) => Op::SH3ADDUW,
+                    (0b0000100, 0b100) if (inst >> 20) & 0x1F == 0 => Op::ZEXTH,
+                    (0b0000100, 0b100) => Op::PACKW,
+                    (0b0110000, 0b001) => Op::ROLW,
+                    (0b0110000, 0b101) => Op::RORW,
+                    (0b0000001, 0b000) => Op::MULW,
+                    (0b0000001, 0b100) => Op::DIVW,
+                    (0b0000001, 0b101) => Op::DIVUW,
+                    (0b0000001, 0b110) => Op::REMW,
+                    (0b0000001, 0b111) => Op::REMUW,
+                    _ => return None,
+                };
+                (op, 0)
+            },
+            0b0101111 => {
+                // `aq` and `rl` are ignored: every access is already
+                // sequentially consistent.
+                let op = match (inst >> 27, funct3) {
+                    (0b00010, _) if (inst >> 20) & 0x1F != 0 => return None,
+                    (0b00010, 0b010) => Op::LRW,
+                    (0b00011, 0b010) => Op::SCW,
+                    (0b00001, 0b010) => Op::AMOSWAPW,
+                    (0b00000, 0b010) => Op::AMOADDW,
+                    (0b00100, 0b010) => Op::AMOXORW,
+                    (0b01100, 0b010) => Op::AMOANDW,
+                    (0b01000, 0b010) => Op::AMOORW,
+                    (0b10000, 0b010) => Op::AMOMINW,
+                    (0b10100, 0b010) => Op::AMOMAXW,
+                    (0b11000, 0b010) => Op::AMOMINUW,
+                    (0b11100, 0b010) => Op::AMOMAXUW,
+                    (0b00010, 0b011) => Op::LRD,
+                    (0b00011, 0b011) => Op::SCD,
+                    (0b00001, 0b011) => Op::AMOSWAPD,
+                    (0b00000, 0b011) => Op::AMOADDD,
+                    (0b00100, 0b011) => Op::AMOXORD,
+                    (0b01100, 0b011) => Op::AMOANDD,
+                    (0b01000, 0b011) => Op::AMOORD,
+                    (0b10000, 0b011) => Op::AMOMIND,
+                    (0b10100, 0b011) => Op::AMOMAXD,
+                    (0b11000, 0b011) => Op::AMOMINUD,
+                    (0b11100, 0b011) => Op::AMOMAXUD,
+                    _ => return None,
+                };
+                (op, 0)
+            },
+            0b0000111 => match funct3 {
+                0b001 => (Op::FLH, imm_i),
+                0b010 => (Op::FLW, imm_i),
+                0b011 => (Op::FLD, imm_i),
+                _ if VectorAccess::decode(inst).is_some() => (Op::VLOAD, 0),
+                _ => return None,
+            },
+            0b0100111 => match funct3 {
+                0b001 => (Op::FSH, imm_s),
+                0b010 => (Op::FSW, imm_s),
+                0b011 => (Op::FSD, imm_s),
+                _ if VectorAccess::decode(inst).is_some() => (Op::VSTORE, 0),
+                _ => return None,
+            },
+            0b1010111 => match (funct3, inst >> 30) {
+                (0b111, 0b00 | 0b01) => (Op::VSETVLI, 0),
+                (0b111, 0b11) => (Op::VSETIVLI, 0),
+                (0b111, _) if funct7 == 0b1000000 => (Op::VSETVL, 0),
+                (0b111, _) => return None,
+                _ => {
+                    VectorOp::decode(inst)?;
+                    (Op::VOP, 0)
+                },
+            },
+            0b1000011 | 0b1000111 | 0b1001011 | 0b1001111 | 0b1010011 => (float(inst)?, 0),
+            _ => return None,
+        };
+
+        Some(Self {
+            op,
+            rd: ((inst >> 7) & 0x1F) as u8,
+            rs1: ((inst >> 15) & 0x1F) as u8,
+            rs2: ((inst >> 20) & 0x1F) as u8,
+            raw,
+            imm: imm as i64,
+        })
+    }
+
+    /// Length in bytes, 2 for compressed instructions.
+    #[inline]
+    pub fn size(&self) -> u64 {
+        if self.raw & 0b11 != 0b11 { 2 } else { 4 }
+    }
+
+    /// The third source register of the fused multiply-adds. Like `rm`,
+    /// read from `raw`, as no compressed instruction has one.
+    #[inline]
+    pub fn rs3(&self) -> usize {
+        (self.raw >> 27) as usize
+    }
+
+    /// The rounding mode field of the floating point instructions.
+    #[inline]
+    pub fn rm(&self) -> u64 {
+        ((self.raw >> 12) & 0b111) as u64
+    }
+}
+
+/// The `Op` of a `Zbb` or `Zbkb` instruction that takes a single register
+/// and has the rest of its immediate field fixed: the counts and sign
+/// extensions, `orc.b`, `brev8`, `rev8`, whose encoding depends on
+/// `xlen`, and RV32's `zip` and `unzip`.
+fn unary(inst: u32, xlen: u32) -> Option<Op> {
+    use Op::*;
+    match (inst & 0x7F, inst >> 20, (inst >> 12) & 0b111) {
+        (0b0010011, 0x600, 0b001) => Some(CLZ),
+        (0b0010011, 0x601, 0b001) => Some(CTZ),
+        (0b0010011, 0x602, 0b001) => Some(CPOP),
+        (0b0010011, 0x604, 0b001) => Some(SEXTB),
+        (0b0010011, 0x605, 0b001) => Some(SEXTH),
+        (0b0010011, 0x287, 0b101) => Some(ORCB),
+        (0b0010011, 0x6B8, 0b101) if xlen == 64 => Some(REV8),
+        (0b0010011, 0x698, 0b101) if xlen == 32 => Some(REV8),
+        (0b0010011, 0x687, 0b101) => Some(BREV8),
+        (0b0010011, 0x08F, 0b001) if xlen == 32 => Some(ZIP),
+        (0b0010011, 0x08F, 0b101) if xlen == 32 => Some(UNZIP),
+        (0b0011011, 0x600, 0b001) => Some(CLZW),
+        (0b0011011, 0x601, 0b001) => Some(CTZW),
+        (0b0011011, 0x602, 0b001) => Some(CPOPW),
+        _ => None,
+    }
+}
+
+/// `Op::CRYPTO` for the scalar cryptography instructions that exist at
+/// `xlen`.
+fn crypto(inst: u32, xlen: u32) -> Option<Op> {
+    CryptoOp::decode(inst, xlen).map(|_| Op::CRYPTO)
+}
+
+/// `Op::HLV` or `Op::HSV` for the hypervisor loads and stores.
+fn hypervisor(inst: u32) -> Option<Op> {
+    HypervisorAccess::decode(inst).map(|access| if access.store { Op::HSV } else { Op::HLV })
+}
+
+/// The `Op` of a floating point computational instruction, as
+/// `Instruction::parse_float` decodes it.
+fn float(inst: u32) -> Option<Op> {
+    use Op::*;
+    let (opcode, funct5, fmt, funct3, rs2) =
+        (inst & 0x7F, inst >> 27, (inst >> 25) & 0b11, (inst >> 12) & 0b111, (inst >> 20) & 0x1F);
+    let pick = |single: Op, double: Op, half: Op| match fmt {
+        0b00 => Some(single),
+        0b01 => Some(double),
+        0b10 => Some(half),
+        _ => None,
+    };
+    match (opcode, funct5, funct3, rs2) {
+        (0b1000011, _, _, _) => pick(FMADDS, FMADDD, FMADDH),
+        (0b1000111, _, _, _) => pick(FMSUBS, FMSUBD, FMSUBH),
+        (0b1001011, _, _, _) => pick(FNMSUBS, FNMSUBD, FNMSUBH),
+        (0b1001111, _, _, _) => pick(FNMADDS, FNMADDD, FNMADDH),
+        (_, 0b00000, _, _) => pick(FADDS, FADDD, FADDH),
+        (_, 0b00001, _, _) => pick(FSUBS, FSUBD, FSUBH),
+        (_, 0b00010, _, _) => pick(FMULS, FMULD, FMULH),
+        (_, 0b00011, _, _) => pick(FDIVS, FDIVD, FDIVH),
+        (_, 0b01011, _, 0) => pick(FSQRTS, FSQRTD, FSQRTH),
+        (_, 0b00100, 0b000, _) => pick(FSGNJS, FSGNJD, FSGNJH),
+        (_, 0b00100, 0b001, _) => pick(FSGNJNS, FSGNJND, FSGNJNH),
+        (_, 0b00100, 0b010, _) => pick(FSGNJXS, FSGNJXD, FSGNJXH),
+        (_, 0b00101, 0b000, _) => pick(FMINS, FMIND, FMINH),
+        (_, 0b00101, 0b001, _) => pick(FMAXS, FMAXD, FMAXH),
+        // the conversions between formats take the source's in `rs2`.
+        (_, 0b01000, _, _) => match (fmt, rs2) {
+            (0b00, 0b01) => Some(FCVTSD),
+            (0b00, 0b10) => Some(FCVTSH),
+            (0b01, 0b00) => Some(FCVTDS),
+            (0b01, 0b10) => Some(FCVTDH),
+            (0b10, 0b00) => Some(FCVTHS),
+            (0b10, 0b01) => Some(FCVTHD),
+            _ => None,
+        },
+        (_, 0b10100, 0b010, _) => pick(FEQS, FEQD, FEQH),
+        (_, 0b10100, 0b001, _) => pick(FLTS, FLTD, FLTH),
+        (_, 0b10100, 0b000, _) => pick(FLES, FLED, FLEH),
+        (_, 0b11000, _, 0) => pick(FCVTWS, FCVTWD, FCVTWH),
+        (_, 0b11000, _, 1) => pick(FCVTWUS, FCVTWUD, FCVTWUH),
+        (_, 0b11000, _, 2) => pick(FCVTLS, FCVTLD, FCVTLH),
+        (_, 0b11000, _, 3) => pick(FCVTLUS, FCVTLUD, FCVTLUH),
+        (_, 0b11010, _, 0) => pick(FCVTSW, FCVTDW, FCVTHW),
+        (_, 0b11010, _, 1) => pick(FCVTSWU, FCVTDWU, FCVTHWU),
+        (_, 0b11010, _, 2) => pick(FCVTSL, FCVTDL, FCVTHL),
+        (_, 0b11010, _, 3) => pick(FCVTSLU, FCVTDLU, FCVTHLU),
+        (_, 0b11100, 0b000, 0) => pick(FMVXW, FMVXD, FMVXH),
+        (_, 0b11100, 0b001, 0) => pick(FCLASSS, FCLASSD, FCLASSH),
+        (_, 0b11110, 0b000, 0) => pick(FMVWX, FMVDX, FMVHX),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InstructionDecoder::Instruction;
+
+    /// The ISAs to sweep: what this build can execute at both XLENs, and
+    /// subsets of it.
+    fn isas() -> Vec<Isa> {
+        let mut isas = vec![Isa::default(), Isa::default().with_xlen(32)];
+        // the ones with extensions left out of this build do not parse.
+        isas.extend(["rv64imac", "rv32imafc_zfhmin_zbb"].iter().filter_map(|isa| Isa::parse(isa).ok()));
+        isas
+    }
+
+    /// What `Instruction::parse_instruction` makes of `raw`, as the name of
+    /// the variant.
+    fn parsed(raw: u32, isa: &Isa) -> Option<String> {
+        if !isa.allows(raw) {
+            return None;
+        }
+        let inst = match raw & 0b11 {
+            0b11 => raw as usize,
+            _ => expand_compressed(raw as usize & 0xFFFF, isa.xlen())?,
+        };
+        let name = format!("{:?}", Instruction::parse_instruction(inst, isa)?);
+        Some(name[..name.find('(').unwrap_or(name.len())].to_string())
+    }
+
+    fn check(raw: u32, isa: &Isa) {
+        let decoded = Decoded::decode(raw, isa).map(|decoded| format!("{:?}", decoded.op));
+        assert_eq!(decoded, parsed(raw, isa), "{:#010x} on rv{}", raw, isa.xlen());
+    }
+
+    #[test]
+    fn agrees_with_parse_instruction() {
+        for isa in isas() {
+            for raw in 0..=0xFFFF {
+                if raw & 0b11 != 0b11 {
+                    check(raw, &isa);
+                }
+            }
+            // every opcode, funct3 and funct7 with every rs2. `rs1` and `rd`
+            // only tell `SYSTEM` and `MISC-MEM` instructions apart.
+            for high in 0..1 << 12 {
+                for funct3 in 0..8 {
+                    for opcode in (0b11..0x80).step_by(4) {
+                        let others: &[(u32, u32)] = match opcode {
+                            0x0F | 0x73 => &[(0, 0), (10, 5)],
+                            _ => &[(10, 5)],
+                        };
+                        for (rs1, rd) in others {
+                            check(high << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode, &isa);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
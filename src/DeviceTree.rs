@@ -0,0 +1,255 @@
+use crate::Clint::{CLINT_BASE, CLINT_SIZE};
+use crate::Csr::{IRQ_M_SOFT, IRQ_M_TIMER};
+
+/// Flattened device tree (DTB) writer, following version 17 of the
+/// devicetree specification.
+const FDT_MAGIC: u32 = 0xd00dfeed;
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_END: u32 = 0x9;
+const FDT_HEADER_SIZE: usize = 40;
+
+pub struct FdtWriter {
+    structure: Vec<u8>,
+    strings: Vec<u8>,
+    depth: usize,
+}
+
+impl Default for FdtWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FdtWriter {
+    pub fn new() -> Self {
+        Self {
+            structure: Vec::new(),
+            strings: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    fn push_u32(&mut self, value: u32) {
+        self.structure.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn align(&mut self) {
+        while !self.structure.len().is_multiple_of(4) {
+            self.structure.push(0);
+        }
+    }
+
+    /// Returns the offset of `name` in the strings block, adding it if
+    /// it is not there yet.
+    fn string_offset(&mut self, name: &str) -> u32 {
+        let mut start = 0;
+        for chunk in self.strings.split(|b| *b == 0) {
+            if chunk == name.as_bytes() {
+                return start as u32;
+            }
+            start += chunk.len() + 1;
+        }
+        let off = self.strings.len();
+        self.strings.extend_from_slice(name.as_bytes());
+        self.strings.push(0);
+        off as u32
+    }
+
+    pub fn begin_node(&mut self, name: &str) {
+        self.push_u32(FDT_BEGIN_NODE);
+        self.structure.extend_from_slice(name.as_bytes());
+        self.structure.push(0);
+        self.align();
+        self.depth += 1;
+    }
+
+    pub fn end_node(&mut self) {
+        assert!(self.depth > 0, "end_node without a matching begin_node");
+        self.push_u32(FDT_END_NODE);
+        self.depth -= 1;
+    }
+
+    pub fn property(&mut self, name: &str, value: &[u8]) {
+        let name_off = self.string_offset(name);
+        self.push_u32(FDT_PROP);
+        self.push_u32(value.len() as u32);
+        self.push_u32(name_off);
+        self.structure.extend_from_slice(value);
+        self.align();
+    }
+
+    pub fn property_empty(&mut self, name: &str) {
+        self.property(name, &[]);
+    }
+
+    pub fn property_u32(&mut self, name: &str, value: u32) {
+        self.property(name, &value.to_be_bytes());
+    }
+
+    pub fn property_u64(&mut self, name: &str, value: u64) {
+        self.property(name, &value.to_be_bytes());
+    }
+
+    pub fn property_cells(&mut self, name: &str, cells: &[u32]) {
+        let bytes: Vec<u8> = cells.iter().flat_map(|c| c.to_be_bytes()).collect();
+        self.property(name, &bytes);
+    }
+
+    pub fn property_string(&mut self, name: &str, value: &str) {
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.push(0);
+        self.property(name, &bytes);
+    }
+
+    pub fn property_strings(&mut self, name: &str, values: &[&str]) {
+        let mut bytes = Vec::new();
+        for value in values {
+            bytes.extend_from_slice(value.as_bytes());
+            bytes.push(0);
+        }
+        self.property(name, &bytes);
+    }
+
+    /// Finishes the tree and returns the DTB blob.
+    pub fn finish(mut self, boot_cpuid: u32) -> Vec<u8> {
+        assert!(self.depth == 0, "unterminated node in device tree");
+        self.push_u32(FDT_END);
+
+        // the memory reservation block only holds the terminating entry.
+        let off_mem_rsvmap = FDT_HEADER_SIZE;
+        let off_dt_struct = off_mem_rsvmap + 16;
+        let off_dt_strings = off_dt_struct + self.structure.len();
+        let total = off_dt_strings + self.strings.len();
+
+        let mut out = Vec::with_capacity(total);
+        for word in [
+            FDT_MAGIC,
+            total as u32,
+            off_dt_struct as u32,
+            off_dt_strings as u32,
+            off_mem_rsvmap as u32,
+            17,
+            16,
+            boot_cpuid,
+            self.strings.len() as u32,
+            self.structure.len() as u32,
+        ] {
+            out.extend_from_slice(&word.to_be_bytes());
+        }
+        out.extend_from_slice(&[0; 16]);
+        out.extend_from_slice(&self.structure);
+        out.extend_from_slice(&self.strings);
+        out
+    }
+}
+
+/// Everything needed to describe a machine to the guest.
+pub struct MachineDescription<'a> {
+    pub hart_count: usize,
+    pub isa: &'a str,
+    pub ram_base: u64,
+    pub ram_size: u64,
+    pub timebase_frequency: u32,
+    pub bootargs: Option<&'a str>,
+    pub initrd: Option<(u64, u64)>,
+    /// Whether to advertise the HTIF (`ucb,htif0`) used by `pk` and `bbl`.
+    pub htif: bool,
+}
+
+/// Picks where a DTB of `len` bytes goes: the top of RAM (below 3 GiB so
+/// 32-bit kernels can reach it), aligned to a 2 MiB megapage so the kernel
+/// can map it cheaply. Returns `None` if RAM is too small.
+pub fn dtb_address(ram_base: u64, ram_end: u64, len: u64) -> Option<u64> {
+    const MEGAPAGE_SIZE: u64 = 0x20_0000;
+    let top = ram_end.min(ram_base + (3 << 30));
+    if top < ram_base + len + MEGAPAGE_SIZE {
+        return None;
+    }
+    Some((top - len) & !(MEGAPAGE_SIZE - 1))
+}
+
+/// Builds the DTB handed to firmware or the kernel in `a1`.
+pub fn build_fdt(desc: &MachineDescription) -> Vec<u8> {
+    let mut fdt = FdtWriter::new();
+    fdt.begin_node("");
+    fdt.property_u32("#address-cells", 2);
+    fdt.property_u32("#size-cells", 2);
+    fdt.property_string("compatible", "zaneoblaneo,zrv64-emu");
+    fdt.property_string("model", "zRV64imc_emu");
+
+    fdt.begin_node("chosen");
+    if let Some(bootargs) = desc.bootargs {
+        fdt.property_string("bootargs", bootargs);
+    }
+    if let Some((start, end)) = desc.initrd {
+        fdt.property_u64("linux,initrd-start", start);
+        fdt.property_u64("linux,initrd-end", end);
+    }
+    fdt.end_node();
+
+    fdt.begin_node(&format!("memory@{:x}", desc.ram_base));
+    fdt.property_string("device_type", "memory");
+    fdt.property_cells("reg", &[
+        (desc.ram_base >> 32) as u32,
+        desc.ram_base as u32,
+        (desc.ram_size >> 32) as u32,
+        desc.ram_size as u32,
+    ]);
+    fdt.end_node();
+
+    fdt.begin_node("cpus");
+    fdt.property_u32("#address-cells", 1);
+    fdt.property_u32("#size-cells", 0);
+    fdt.property_u32("timebase-frequency", desc.timebase_frequency);
+    for hart in 0..desc.hart_count {
+        // phandles start at 1; 0 is not a valid phandle.
+        let intc_phandle = hart as u32 + 1;
+        fdt.begin_node(&format!("cpu@{:x}", hart));
+        fdt.property_string("device_type", "cpu");
+        fdt.property_u32("reg", hart as u32);
+        fdt.property_string("status", "okay");
+        fdt.property_string("compatible", "riscv");
+        fdt.property_string("riscv,isa", desc.isa);
+        let mmu = if desc.isa.starts_with("rv32") { "riscv,sv32" } else { "riscv,sv39" };
+        fdt.property_string("mmu-type", mmu);
+        fdt.begin_node("interrupt-controller");
+        fdt.property_u32("#interrupt-cells", 1);
+        fdt.property_empty("interrupt-controller");
+        fdt.property_string("compatible", "riscv,cpu-intc");
+        fdt.property_u32("phandle", intc_phandle);
+        fdt.end_node();
+        fdt.end_node();
+    }
+    fdt.end_node();
+
+    fdt.begin_node("soc");
+    fdt.property_u32("#address-cells", 2);
+    fdt.property_u32("#size-cells", 2);
+    fdt.property_string("compatible", "simple-bus");
+    fdt.property_empty("ranges");
+    fdt.begin_node(&format!("clint@{:x}", CLINT_BASE));
+    fdt.property_strings("compatible", &["sifive,clint0", "riscv,clint0"]);
+    fdt.property_cells("reg", &[
+        (CLINT_BASE >> 32) as u32,
+        CLINT_BASE as u32,
+        (CLINT_SIZE >> 32) as u32,
+        CLINT_SIZE as u32,
+    ]);
+    let interrupts: Vec<u32> = (0..desc.hart_count as u32)
+        .flat_map(|hart| [hart + 1, IRQ_M_SOFT as u32, hart + 1, IRQ_M_TIMER as u32])
+        .collect();
+    fdt.property_cells("interrupts-extended", &interrupts);
+    fdt.end_node();
+    fdt.end_node();
+
+    if desc.htif {
+        fdt.begin_node("htif");
+        fdt.property_string("compatible", "ucb,htif0");
+        fdt.end_node();
+    }
+
+    fdt.end_node();
+    fdt.finish(0)
+}
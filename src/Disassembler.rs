@@ -0,0 +1,552 @@
+use crate::Cpu::ABI_NAMES;
+use crate::Crypto::CryptoOp;
+use crate::Hypervisor::HypervisorAccess;
+use crate::Csr::csr_name;
+use crate::Execute::sext;
+use crate::InstructionDecoder::{expand_compressed, Instruction};
+use crate::Isa::Isa;
+use crate::Vector::{vtype_name, Addressing, VectorAccess, VectorOp};
+
+/// ABI names of the floating point registers, indexed by register number.
+pub const FP_ABI_NAMES: [&str; 32] = [
+    "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7",
+    "fs0", "fs1", "fa0", "fa1", "fa2", "fa3", "fa4", "fa5",
+    "fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7",
+    "fs8", "fs9", "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+];
+
+fn x(reg: usize) -> &'static str {
+    ABI_NAMES[reg]
+}
+
+fn f(reg: usize) -> &'static str {
+    FP_ABI_NAMES[reg]
+}
+
+fn csr(addr: usize) -> String {
+    match csr_name(addr) {
+        Some(name) => name.to_string(),
+        None => format!("unknown_{:03x}", addr),
+    }
+}
+
+/// Formats a pc-relative target the way Spike does: `pc + 0x10`.
+fn target(offset: u64) -> String {
+    let offset = offset as i64;
+    let sign = if offset < 0 { '-' } else { '+' };
+    format!("pc {} {:#x}", sign, offset.unsigned_abs())
+}
+
+/// Pads the mnemonic to a fixed column like Spike and objdump do.
+fn op(name: &str, args: &str) -> String {
+    if args.is_empty() {
+        return name.to_string();
+    }
+    format!("{:<7} {}", name, args)
+}
+
+/// Returns the mnemonic of every instruction whose operands follow one of
+/// the regular formats handled in `disassemble`.
+fn mnemonic(inst: &Instruction) -> &'static str {
+    use Instruction::*;
+    match inst {
+        LUI(_) => "lui",
+        AUIPC(_) => "auipc",
+        JAL(_) => "jal",
+        JALR(_) => "jalr",
+        BEQ(_) => "beq",
+        BNE(_) => "bne",
+        BLT(_) => "blt",
+        BGE(_) => "bge",
+        BLTU(_) => "bltu",
+        BGEU(_) => "bgeu",
+        LB(_) => "lb",
+        LH(_) => "lh",
+        LW(_) => "lw",
+        LBU(_) => "lbu",
+        LHU(_) => "lhu",
+        LWU(_) => "lwu",
+        LD(_) => "ld",
+        SB(_) => "sb",
+        SH(_) => "sh",
+        SW(_) => "sw",
+        SD(_) => "sd",
+        ADDI(_) => "addi",
+        SLTI(_) => "slti",
+        SLTIU(_) => "sltiu",
+        XORI(_) => "xori",
+        ORI(_) => "ori",
+        ANDI(_) => "andi",
+        SLLI(_) => "slli",
+        SRLI(_) => "srli",
+        SRAI(_) => "srai",
+        ADD(_) => "add",
+        SUB(_) => "sub",
+        SLL(_) => "sll",
+        SLT(_) => "slt",
+        SLTU(_) => "sltu",
+        XOR(_) => "xor",
+        SRL(_) => "srl",
+        SRA(_) => "sra",
+        OR(_) => "or",
+        AND(_) => "and",
+        FENCE(_) => "fence",
+        ECALL(_) => "ecall",
+        EBREAK(_) => "ebreak",
+        MRET(_) => "mret",
+        SRET(_) => "sret",
+        WFI(_) => "wfi",
+        SFENCEVMA(_) => "sfence.vma",
+        HFENCEVVMA(_) => "hfence.vvma",
+        HFENCEGVMA(_) => "hfence.gvma",
+        ADDIW(_) => "addiw",
+        SLLIW(_) => "slliw",
+        SRLIW(_) => "srliw",
+        SRAIW(_) => "sraiw",
+        ADDW(_) => "addw",
+        SUBW(_) => "subw",
+        SLLW(_) => "sllw",
+        SRLW(_) => "srlw",
+        SRAW(_) => "sraw",
+        FENCEI(_) => "fence.i",
+        CSRRW(_) => "csrrw",
+        CSRRS(_) => "csrrs",
+        CSRRC(_) => "csrrc",
+        CSRRWI(_) => "csrrwi",
+        CSRRSI(_) => "csrrsi",
+        CSRRCI(_) => "csrrci",
+        MUL(_) => "mul",
+        MULH(_) => "mulh",
+        MULHSU(_) => "mulhsu",
+        MULHU(_) => "mulhu",
+        DIV(_) => "div",
+        DIVU(_) => "divu",
+        REM(_) => "rem",
+        REMU(_) => "remu",
+        MULW(_) => "mulw",
+        DIVW(_) => "divw",
+        DIVUW(_) => "divuw",
+        REMW(_) => "remw",
+        REMUW(_) => "remuw",
+        LRW(_) => "lr.w",
+        SCW(_) => "sc.w",
+        AMOSWAPW(_) => "amoswap.w",
+        AMOADDW(_) => "amoadd.w",
+        AMOXORW(_) => "amoxor.w",
+        AMOANDW(_) => "amoand.w",
+        AMOORW(_) => "amoor.w",
+        AMOMINW(_) => "amomin.w",
+        AMOMAXW(_) => "amomax.w",
+        AMOMINUW(_) => "amominu.w",
+        AMOMAXUW(_) => "amomaxu.w",
+        LRD(_) => "lr.d",
+        SCD(_) => "sc.d",
+        AMOSWAPD(_) => "amoswap.d",
+        AMOADDD(_) => "amoadd.d",
+        AMOXORD(_) => "amoxor.d",
+        AMOANDD(_) => "amoand.d",
+        AMOORD(_) => "amoor.d",
+        AMOMIND(_) => "amomin.d",
+        AMOMAXD(_) => "amomax.d",
+        AMOMINUD(_) => "amominu.d",
+        AMOMAXUD(_) => "amomaxu.d",
+        FLW(_) => "flw",
+        FSW(_) => "fsw",
+        FMADDS(_) => "fmadd.s",
+        FMSUBS(_) => "fmsub.s",
+        FNMSUBS(_) => "fnmsub.s",
+        FNMADDS(_) => "fnmadd.s",
+        FADDS(_) => "fadd.s",
+        FSUBS(_) => "fsub.s",
+        FMULS(_) => "fmul.s",
+        FDIVS(_) => "fdiv.s",
+        FSQRTS(_) => "fsqrt.s",
+        FSGNJS(_) => "fsgnj.s",
+        FSGNJNS(_) => "fsgnjn.s",
+        FSGNJXS(_) => "fsgnjx.s",
+        FMINS(_) => "fmin.s",
+        FMAXS(_) => "fmax.s",
+        FCVTWS(_) => "fcvt.w.s",
+        FCVTWUS(_) => "fcvt.wu.s",
+        FMVXW(_) => "fmv.x.w",
+        FEQS(_) => "feq.s",
+        FLTS(_) => "flt.s",
+        FLES(_) => "fle.s",
+        FCLASSS(_) => "fclass.s",
+        FCVTSW(_) => "fcvt.s.w",
+        FCVTSWU(_) => "fcvt.s.wu",
+        FMVWX(_) => "fmv.w.x",
+        FCVTLS(_) => "fcvt.l.s",
+        FCVTLUS(_) => "fcvt.lu.s",
+        FCVTSL(_) => "fcvt.s.l",
+        FCVTSLU(_) => "fcvt.s.lu",
+        FLD(_) => "fld",
+        FSD(_) => "fsd",
+        FMADDD(_) => "fmadd.d",
+        FMSUBD(_) => "fmsub.d",
+        FNMSUBD(_) => "fnmsub.d",
+        FNMADDD(_) => "fnmadd.d",
+        FADDD(_) => "fadd.d",
+        FSUBD(_) => "fsub.d",
+        FMULD(_) => "fmul.d",
+        FDIVD(_) => "fdiv.d",
+        FSQRTD(_) => "fsqrt.d",
+        FSGNJD(_) => "fsgnj.d",
+        FSGNJND(_) => "fsgnjn.d",
+        FSGNJXD(_) => "fsgnjx.d",
+        FMIND(_) => "fmin.d",
+        FMAXD(_) => "fmax.d",
+        FCVTSD(_) => "fcvt.s.d",
+        FCVTDS(_) => "fcvt.d.s",
+        FEQD(_) => "feq.d",
+        FLTD(_) => "flt.d",
+        FLED(_) => "fle.d",
+        FCLASSD(_) => "fclass.d",
+        FCVTWD(_) => "fcvt.w.d",
+        FCVTWUD(_) => "fcvt.wu.d",
+        FCVTDW(_) => "fcvt.d.w",
+        FCVTDWU(_) => "fcvt.d.wu",
+        FCVTLD(_) => "fcvt.l.d",
+        FCVTLUD(_) => "fcvt.lu.d",
+        FMVXD(_) => "fmv.x.d",
+        FCVTDL(_) => "fcvt.d.l",
+        FCVTDLU(_) => "fcvt.d.lu",
+        FMVDX(_) => "fmv.d.x",
+        FLH(_) => "flh",
+        FSH(_) => "fsh",
+        FMADDH(_) => "fmadd.h",
+        FMSUBH(_) => "fmsub.h",
+        FNMSUBH(_) => "fnmsub.h",
+        FNMADDH(_) => "fnmadd.h",
+        FADDH(_) => "fadd.h",
+        FSUBH(_) => "fsub.h",
+        FMULH(_) => "fmul.h",
+        FDIVH(_) => "fdiv.h",
+        FSQRTH(_) => "fsqrt.h",
+        FSGNJH(_) => "fsgnj.h",
+        FSGNJNH(_) => "fsgnjn.h",
+        FSGNJXH(_) => "fsgnjx.h",
+        FMINH(_) => "fmin.h",
+        FMAXH(_) => "fmax.h",
+        FCVTSH(_) => "fcvt.s.h",
+        FCVTHS(_) => "fcvt.h.s",
+        FCVTDH(_) => "fcvt.d.h",
+        FCVTHD(_) => "fcvt.h.d",
+        FEQH(_) => "feq.h",
+        FLTH(_) => "flt.h",
+        FLEH(_) => "fle.h",
+        FCLASSH(_) => "fclass.h",
+        FCVTWH(_) => "fcvt.w.h",
+        FCVTWUH(_) => "fcvt.wu.h",
+        FCVTHW(_) => "fcvt.h.w",
+        FCVTHWU(_) => "fcvt.h.wu",
+        FMVXH(_) => "fmv.x.h",
+        FMVHX(_) => "fmv.h.x",
+        FCVTLH(_) => "fcvt.l.h",
+        FCVTLUH(_) => "fcvt.lu.h",
+        FCVTHL(_) => "fcvt.h.l",
+        FCVTHLU(_) => "fcvt.h.lu",
+        SH1ADD(_) => "sh1add",
+        SH2ADD(_) => "sh2add",
+        SH3ADD(_) => "sh3add",
+        ADDUW(_) => "add.uw",
+        SH1ADDUW(_) => "sh1add.uw",
+        SH2ADDUW(_) => "sh2add.uw",
+        SH3ADDUW(_) => "sh3add.uw",
+        SLLIUW(_) => "slli.uw",
+        ANDN(_) => "andn",
+        ORN(_) => "orn",
+        XNOR(_) => "xnor",
+        CLZ(_) => "clz",
+        CTZ(_) => "ctz",
+        CPOP(_) => "cpop",
+        MAX(_) => "max",
+        MAXU(_) => "maxu",
+        MIN(_) => "min",
+        MINU(_) => "minu",
+        SEXTB(_) => "sext.b",
+        SEXTH(_) => "sext.h",
+        ZEXTH(_) => "zext.h",
+        ROL(_) => "rol",
+        ROR(_) => "ror",
+        RORI(_) => "rori",
+        ORCB(_) => "orc.b",
+        REV8(_) => "rev8",
+        CLZW(_) => "clzw",
+        CTZW(_) => "ctzw",
+        CPOPW(_) => "cpopw",
+        ROLW(_) => "rolw",
+        RORW(_) => "rorw",
+        RORIW(_) => "roriw",
+        CLMUL(_) => "clmul",
+        CLMULH(_) => "clmulh",
+        CLMULR(_) => "clmulr",
+        BCLR(_) => "bclr",
+        BCLRI(_) => "bclri",
+        BEXT(_) => "bext",
+        BEXTI(_) => "bexti",
+        BINV(_) => "binv",
+        BINVI(_) => "binvi",
+        BSET(_) => "bset",
+        BSETI(_) => "bseti",
+        PACK(_) => "pack",
+        PACKH(_) => "packh",
+        BREV8(_) => "brev8",
+        ZIP(_) => "zip",
+        UNZIP(_) => "unzip",
+        PACKW(_) => "packw",
+        VSETVLI(_) => "vsetvli",
+        VSETIVLI(_) => "vsetivli",
+        VSETVL(_) => "vsetvl",
+        // named by `Vector`, from the fields it decodes.
+        CRYPTO(_) | VLOAD(_) | VSTORE(_) | VOP(_) | HLV(_) | HSV(_) => "",
+    }
+}
+
+/// Disassembles a raw instruction word in the syntax Spike uses, pseudo
+/// instructions included. Compressed instructions are shown as the
+/// instruction they expand to, which can depend on `xlen`. Every
+/// extension this build has is decoded, whatever a hart implements.
+pub fn disassemble(raw: u32, xlen: u32) -> String {
+    let isa = Isa::default().with_xlen(xlen);
+    let expanded = if raw & 0b11 != 0b11 {
+        match expand_compressed(raw as usize & 0xFFFF, xlen) {
+            Some(expanded) => expanded,
+            None => return "unknown".to_string(),
+        }
+    } else {
+        raw as usize
+    };
+    let inst = match Instruction::parse_instruction(expanded, &isa) {
+        Some(inst) => inst,
+        None => return "unknown".to_string(),
+    };
+    let raw = expanded as u64;
+    let name = mnemonic(&inst);
+    let imm_i = sext(raw >> 20, 12) as i64;
+    let imm_s = sext(((raw >> 25) << 5) | ((raw >> 7) & 0x1F), 12) as i64;
+    let shamt = (raw >> 20) & 0x3F;
+
+    use Instruction::*;
+    match &inst {
+        LUI(u) | AUIPC(u) => op(name, &format!("{}, {:#x}", x(u.rd), (raw >> 12) & 0xFFFFF)),
+        JAL(j) => {
+            let to = target(sext(j.parsed_imm as u64, 21));
+            match j.rd {
+                0 => op("j", &to),
+                1 => op("jal", &to),
+                rd => op("jal", &format!("{}, {}", x(rd), to)),
+            }
+        }
+        JALR(i) => match (i.rd, i.rs1, imm_i) {
+            (0, 1, 0) => "ret".to_string(),
+            (0, rs1, 0) => op("jr", x(rs1)),
+            (1, rs1, 0) => op("jalr", x(rs1)),
+            (rd, rs1, imm) => op("jalr", &format!("{}, {}({})", x(rd), imm, x(rs1))),
+        },
+        BEQ(b) | BNE(b) | BLT(b) | BGE(b) | BLTU(b) | BGEU(b) => {
+            let to = target(sext(b.parsed_imm as u64, 13));
+            match (&inst, b.rs1, b.rs2) {
+                (BEQ(_), rs1, 0) => op("beqz", &format!("{}, {}", x(rs1), to)),
+                (BNE(_), rs1, 0) => op("bnez", &format!("{}, {}", x(rs1), to)),
+                (BLT(_), rs1, 0) => op("bltz", &format!("{}, {}", x(rs1), to)),
+                (BGE(_), rs1, 0) => op("bgez", &format!("{}, {}", x(rs1), to)),
+                (BLT(_), 0, rs2) => op("bgtz", &format!("{}, {}", x(rs2), to)),
+                (BGE(_), 0, rs2) => op("blez", &format!("{}, {}", x(rs2), to)),
+                (_, rs1, rs2) => op(name, &format!("{}, {}, {}", x(rs1), x(rs2), to)),
+            }
+        }
+        LB(i) | LH(i) | LW(i) | LBU(i) | LHU(i) | LWU(i) | LD(i) => {
+            op(name, &format!("{}, {}({})", x(i.rd), imm_i, x(i.rs1)))
+        }
+        SB(s) | SH(s) | SW(s) | SD(s) => op(name, &format!("{}, {}({})", x(s.rs2), imm_s, x(s.rs1))),
+        ADDI(i) if i.rd == 0 && i.rs1 == 0 && imm_i == 0 => "nop".to_string(),
+        ADDI(i) if i.rs1 == 0 => op("li", &format!("{}, {}", x(i.rd), imm_i)),
+        ADDI(i) if imm_i == 0 => op("mv", &format!("{}, {}", x(i.rd), x(i.rs1))),
+        ADDIW(i) if imm_i == 0 => op("sext.w", &format!("{}, {}", x(i.rd), x(i.rs1))),
+        SLTIU(i) if imm_i == 1 => op("seqz", &format!("{}, {}", x(i.rd), x(i.rs1))),
+        XORI(i) if imm_i == -1 => op("not", &format!("{}, {}", x(i.rd), x(i.rs1))),
+        ADDI(i) | SLTI(i) | SLTIU(i) | XORI(i) | ORI(i) | ANDI(i) | ADDIW(i) => {
+            op(name, &format!("{}, {}, {}", x(i.rd), x(i.rs1), imm_i))
+        }
+        SLLI(r) | SRLI(r) | SRAI(r) | SLLIW(r) | SRLIW(r) | SRAIW(r) | SLLIUW(r) | RORI(r) | RORIW(r)
+        | BCLRI(r) | BEXTI(r) | BINVI(r) | BSETI(r) => {
+            op(name, &format!("{}, {}, {}", x(r.rd), x(r.rs1), shamt))
+        }
+        SUB(r) if r.rs1 == 0 => op("neg", &format!("{}, {}", x(r.rd), x(r.rs2))),
+        SUBW(r) if r.rs1 == 0 => op("negw", &format!("{}, {}", x(r.rd), x(r.rs2))),
+        SLTU(r) if r.rs1 == 0 => op("snez", &format!("{}, {}", x(r.rd), x(r.rs2))),
+        ADDUW(r) if r.rs2 == 0 => op("zext.w", &format!("{}, {}", x(r.rd), x(r.rs1))),
+        CLZ(r) | CTZ(r) | CPOP(r) | SEXTB(r) | SEXTH(r) | ZEXTH(r) | ORCB(r) | REV8(r) | CLZW(r)
+        | CTZW(r) | CPOPW(r) | BREV8(r) | ZIP(r) | UNZIP(r) => op(name, &format!("{}, {}", x(r.rd), x(r.rs1))),
+        FENCE(_) | FENCEI(_) | ECALL(_) | EBREAK(_) | MRET(_) | SRET(_) | WFI(_) => name.to_string(),
+        SFENCEVMA(r) | HFENCEVVMA(r) | HFENCEGVMA(r) => match (r.rs1, r.rs2) {
+            (0, 0) => name.to_string(),
+            (rs1, 0) => op(name, x(rs1)),
+            (rs1, rs2) => op(name, &format!("{}, {}", x(rs1), x(rs2))),
+        },
+        CSRRW(i) | CSRRS(i) | CSRRC(i) => {
+            let csr = csr(i.imm_110);
+            match (&inst, i.rd, i.rs1) {
+                (CSRRS(_), rd, 0) => op("csrr", &format!("{}, {}", x(rd), csr)),
+                (CSRRW(_), 0, rs1) => op("csrw", &format!("{}, {}", csr, x(rs1))),
+                (CSRRS(_), 0, rs1) => op("csrs", &format!("{}, {}", csr, x(rs1))),
+                (CSRRC(_), 0, rs1) => op("csrc", &format!("{}, {}", csr, x(rs1))),
+                (_, rd, rs1) => op(name, &format!("{}, {}, {}", x(rd), csr, x(rs1))),
+            }
+        }
+        CSRRWI(i) | CSRRSI(i) | CSRRCI(i) => {
+            let csr = csr(i.imm_110);
+            match (&inst, i.rd) {
+                (CSRRWI(_), 0) => op("csrwi", &format!("{}, {}", csr, i.rs1)),
+                (CSRRSI(_), 0) => op("csrsi", &format!("{}, {}", csr, i.rs1)),
+                (CSRRCI(_), 0) => op("csrci", &format!("{}, {}", csr, i.rs1)),
+                (_, rd) => op(name, &format!("{}, {}, {}", x(rd), csr, i.rs1)),
+            }
+        }
+        LRW(r) | LRD(r) => op(name, &format!("{}, ({})", x(r.rd), x(r.rs1))),
+        SCW(r) | SCD(r) | AMOSWAPW(r) | AMOADDW(r) | AMOXORW(r) | AMOANDW(r) | AMOORW(r)
+        | AMOMINW(r) | AMOMAXW(r) | AMOMINUW(r) | AMOMAXUW(r) | AMOSWAPD(r) | AMOADDD(r)
+        | AMOXORD(r) | AMOANDD(r) | AMOORD(r) | AMOMIND(r) | AMOMAXD(r) | AMOMINUD(r)
+        | AMOMAXUD(r) => op(name, &format!("{}, {}, ({})", x(r.rd), x(r.rs2), x(r.rs1))),
+        FLW(i) | FLH(i) => op(name, &format!("{}, {}({})", f(i.rd), imm_i, x(i.rs1))),
+        FLD(r) => op(name, &format!("{}, {}({})", f(r.rd), imm_i, x(r.rs1))),
+        FSW(s) | FSH(s) => op(name, &format!("{}, {}({})", f(s.rs2), imm_s, x(s.rs1))),
+        FSD(r) => op(name, &format!("{}, {}({})", f(r.rs2), imm_s, x(r.rs1))),
+        FMADDS(r) | FMSUBS(r) | FNMSUBS(r) | FNMADDS(r) | FMADDD(r) | FMSUBD(r) | FNMSUBD(r)
+        | FNMADDD(r) | FMADDH(r) | FMSUBH(r) | FNMSUBH(r) | FNMADDH(r) => op(name, &format!("{}, {}, {}, {}", f(r.rd), f(r.rs1), f(r.rs2), f(r.rs3))),
+        FSQRTS(r) | FSQRTD(r) | FCVTSD(r) | FCVTDS(r) | FSQRTH(r) | FCVTSH(r) | FCVTHS(r) | FCVTDH(r)
+        | FCVTHD(r) => op(name, &format!("{}, {}", f(r.rd), f(r.rs1))),
+        FCVTWS(r) | FCVTWUS(r) | FMVXW(r) | FCLASSS(r) | FCVTLS(r) | FCVTLUS(r) | FCLASSD(r)
+        | FCVTWD(r) | FCVTWUD(r) | FCVTLD(r) | FCVTLUD(r) | FMVXD(r) | FCVTWH(r) | FCVTWUH(r)
+        | FCVTLH(r) | FCVTLUH(r) | FMVXH(r) | FCLASSH(r) => {
+            op(name, &format!("{}, {}", x(r.rd), f(r.rs1)))
+        }
+        FCVTSW(r) | FCVTSWU(r) | FMVWX(r) | FCVTSL(r) | FCVTSLU(r) | FCVTDW(r) | FCVTDWU(r)
+        | FCVTDL(r) | FCVTDLU(r) | FMVDX(r) | FCVTHW(r) | FCVTHWU(r) | FCVTHL(r) | FCVTHLU(r)
+        | FMVHX(r) => op(name, &format!("{}, {}", f(r.rd), x(r.rs1))),
+        FEQS(r) | FLTS(r) | FLES(r) | FEQD(r) | FLTD(r) | FLED(r) | FEQH(r) | FLTH(r) | FLEH(r) => {
+            op(name, &format!("{}, {}, {}", x(r.rd), f(r.rs1), f(r.rs2)))
+        }
+        FADDS(r) | FSUBS(r) | FMULS(r) | FDIVS(r) | FSGNJS(r) | FSGNJNS(r) | FSGNJXS(r)
+        | FMINS(r) | FMAXS(r) | FADDD(r) | FSUBD(r) | FMULD(r) | FDIVD(r) | FSGNJD(r)
+        | FSGNJND(r) | FSGNJXD(r) | FMIND(r) | FMAXD(r) | FADDH(r) | FSUBH(r) | FMULH(r) | FDIVH(r)
+        | FSGNJH(r) | FSGNJNH(r) | FSGNJXH(r) | FMINH(r) | FMAXH(r) => {
+            op(name, &format!("{}, {}, {}", f(r.rd), f(r.rs1), f(r.rs2)))
+        }
+        ADD(r) | SUB(r) | SLL(r) | SLT(r) | SLTU(r) | XOR(r) | SRL(r) | SRA(r) | OR(r) | AND(r)
+        | ADDW(r) | SUBW(r) | SLLW(r) | SRLW(r) | SRAW(r) | MUL(r) | MULH(r) | MULHSU(r)
+        | MULHU(r) | DIV(r) | DIVU(r) | REM(r) | REMU(r) | MULW(r) | DIVW(r) | DIVUW(r)
+        | REMW(r) | REMUW(r) | SH1ADD(r) | SH2ADD(r) | SH3ADD(r) | ADDUW(r) | SH1ADDUW(r)
+        | SH2ADDUW(r) | SH3ADDUW(r) | ANDN(r) | ORN(r) | XNOR(r) | MAX(r) | MAXU(r) | MIN(r)
+        | MINU(r) | ROL(r) | ROR(r) | ROLW(r) | RORW(r) | CLMUL(r) | CLMULH(r) | CLMULR(r)
+        | BCLR(r) | BEXT(r) | BINV(r) | BSET(r) | PACK(r) | PACKH(r) | PACKW(r) => {
+            op(name, &format!("{}, {}, {}", x(r.rd), x(r.rs1), x(r.rs2)))
+        }
+        CRYPTO(r) => crypto(raw as u32, xlen, r.rd, r.rs1, r.rs2),
+        VSETVLI(i) => op(name, &format!("{}, {}, {}", x(i.rd), x(i.rs1), vtype_name((raw >> 20) & 0x7FF))),
+        VSETIVLI(i) => op(name, &format!("{}, {}, {}", x(i.rd), i.rs1, vtype_name((raw >> 20) & 0x3FF))),
+        VSETVL(r) => op(name, &format!("{}, {}, {}", x(r.rd), x(r.rs1), x(r.rs2))),
+        VLOAD(_) | VSTORE(_) => vector_access(raw as u32),
+        VOP(_) => vector_op(raw as u32),
+        HLV(r) | HSV(r) => hypervisor_access(raw as u32, r.rd, r.rs1, r.rs2),
+    }
+}
+
+/// Disassembles a hypervisor load, `rd, (rs1)`, or store, `rs2, (rs1)`.
+fn hypervisor_access(raw: u32, rd: usize, rs1: usize, rs2: usize) -> String {
+    match HypervisorAccess::decode(raw) {
+        Some(access) if access.store => op(&access.mnemonic(), &format!("{}, ({})", x(rs2), x(rs1))),
+        Some(access) => op(&access.mnemonic(), &format!("{}, ({})", x(rd), x(rs1))),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Disassembles a scalar cryptography instruction: `rd, rs1`, then `rs2`
+/// unless it is unary, and the byte select or round number.
+fn crypto(raw: u32, xlen: u32, rd: usize, rs1: usize, rs2: usize) -> String {
+    let crypto = match CryptoOp::decode(raw, xlen) {
+        Some(crypto) => crypto,
+        None => return "unknown".to_string(),
+    };
+    let mut args = format!("{}, {}", x(rd), x(rs1));
+    if !crypto.unary() {
+        args += &format!(", {}", x(rs2));
+    }
+    match crypto {
+        CryptoOp::Aes32 { bs, .. } | CryptoOp::Sm4 { bs, .. } => args += &format!(", {}", bs),
+        CryptoOp::Aes64Ks1i(rnum) => args += &format!(", {}", rnum),
+        _ => {},
+    }
+    op(crypto.mnemonic(), &args)
+}
+
+/// Disassembles a vector load or store: `vd, (rs1)`, then the stride or
+/// index register, then `v0.t` when masked.
+fn vector_access(raw: u32) -> String {
+    let access = match VectorAccess::decode(raw) {
+        Some(access) => access,
+        None => return "unknown".to_string(),
+    };
+    let (vd, rs1, rs2) = ((raw >> 7) & 0x1F, (raw >> 15) & 0x1F, (raw >> 20) & 0x1F);
+    let mut args = format!("v{}, ({})", vd, x(rs1 as usize));
+    match access.addressing {
+        Addressing::Strided => args += &format!(", {}", x(rs2 as usize)),
+        Addressing::Indexed { .. } => args += &format!(", v{}", rs2),
+        _ => {},
+    }
+    if access.masked {
+        args += ", v0.t";
+    }
+    op(&access.mnemonic(), &args)
+}
+
+/// Disassembles an OP-V instruction: `vd, vs2, vs1` with `vs1` being
+/// `rs1` or the immediate in the scalar forms, the multiplicand first for
+/// the multiply-adds, and `v0` last as a mask or carry.
+fn vector_op(raw: u32) -> String {
+    use VectorOp::*;
+    let vop = match VectorOp::decode(raw) {
+        Some(vop) => vop,
+        None => return "unknown".to_string(),
+    };
+    let (vd, vs1, vs2) = ((raw >> 7) & 0x1F, (raw >> 15) & 0x1F, (raw >> 20) & 0x1F);
+    let masked = raw & (1 << 25) == 0;
+    let src1 = match (raw >> 12) & 0b111 {
+        0b000 | 0b010 => format!("v{}", vs1),
+        0b011 if vop.unsigned_operand() => vs1.to_string(),
+        0b011 => (sext(vs1 as u64, 5) as i64).to_string(),
+        _ => x(vs1 as usize).to_string(),
+    };
+    let mut args = match vop {
+        MvXS | Cpop | First => format!("{}, v{}", x(vd as usize), vs2),
+        MvSX => format!("v{}, {}", vd, x(vs1 as usize)),
+        Merge if !masked => format!("v{}, {}", vd, src1),
+        Zext(_) | Sext(_) | Msbf | Msof | Msif | Iota | Mvnr => format!("v{}, v{}", vd, vs2),
+        Id => format!("v{}", vd),
+        _ if vop.multiply_add() => format!("v{}, {}, v{}", vd, src1, vs2),
+        _ => format!("v{}, v{}, {}", vd, vs2, src1),
+    };
+    if masked {
+        args += if vop.carry() { ", v0" } else { ", v0.t" };
+    }
+    op(&vop.mnemonic(raw), &args)
+}
+
+/// Returns where a direct jump or branch at `pc` goes, for annotating the
+/// disassembly with a symbol.
+pub fn jump_target(raw: u32, pc: u64, xlen: u32) -> Option<u64> {
+    let expanded = if raw & 0b11 != 0b11 { expand_compressed(raw as usize & 0xFFFF, xlen)? } else { raw as usize };
+    let offset = match Instruction::parse_instruction(expanded, &Isa::default().with_xlen(xlen))? {
+        Instruction::JAL(j) => sext(j.parsed_imm as u64, 21),
+        Instruction::BEQ(b)
+        | Instruction::BNE(b)
+        | Instruction::BLT(b)
+        | Instruction::BGE(b)
+        | Instruction::BLTU(b)
+        | Instruction::BGEU(b) => sext(b.parsed_imm as u64, 13),
+        _ => return None,
+    };
+    let target = pc.wrapping_add(offset);
+    Some(if xlen == 32 { target & 0xFFFF_FFFF } else { target })
+}
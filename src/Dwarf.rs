@@ -0,0 +1,462 @@
+// line number program opcodes.
+const DW_LNS_COPY: u8 = 1;
+const DW_LNS_ADVANCE_PC: u8 = 2;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNS_SET_FILE: u8 = 4;
+const DW_LNS_NEGATE_STMT: u8 = 6;
+const DW_LNS_CONST_ADD_PC: u8 = 8;
+const DW_LNS_FIXED_ADVANCE_PC: u8 = 9;
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+const DW_LNE_DEFINE_FILE: u8 = 3;
+
+// DWARF 5 directory and file entry formats.
+const DW_LNCT_PATH: u64 = 1;
+const DW_LNCT_DIRECTORY_INDEX: u64 = 2;
+const DW_FORM_BLOCK: u64 = 0x09;
+const DW_FORM_DATA1: u64 = 0x0b;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_DATA16: u64 = 0x1e;
+const DW_FORM_LINE_STRP: u64 = 0x1f;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_STRP: u64 = 0x0e;
+const DW_FORM_UDATA: u64 = 0x0f;
+
+/// One row of the line number matrix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineRow {
+    pub address: u64,
+    /// Index into `LineTable::files`.
+    pub file: usize,
+    pub line: u64,
+    pub is_stmt: bool,
+    /// Marks the first address past a sequence; its line means nothing.
+    pub end_sequence: bool,
+}
+
+/// The line number program of one compilation unit, run to completion.
+#[derive(Debug, Clone, Default)]
+pub struct LineTable {
+    /// File paths, joined with their directory where the table gives one.
+    /// Indexed the way the version numbers them: from 1 before DWARF 5,
+    /// from 0 since, so slot 0 is empty for older tables.
+    pub files: Vec<String>,
+    pub rows: Vec<LineRow>,
+}
+
+impl LineTable {
+    /// Address ranges with their file and line, in the order of the rows.
+    pub fn ranges(&self) -> impl Iterator<Item = (u64, u64, &str, u64)> + '_ {
+        self.rows.windows(2).filter(|w| !w[0].end_sequence && w[1].address > w[0].address).map(|w| {
+            let file = self.files.get(w[0].file).map_or("", |f| f.as_str());
+            (w[0].address, w[1].address, file, w[0].line)
+        })
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.data.len());
+        let end = end.ok_or_else(|| format!(".debug_line is truncated at {:#x}", self.pos))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// A little-endian unsigned value of `size` bytes.
+    fn sized(&mut self, size: usize) -> Result<u64, String> {
+        let bytes = self.take(size)?;
+        Ok(bytes.iter().rev().take(8).fold(0, |value, byte| (value << 8) | *byte as u64))
+    }
+
+    /// A section offset: 4 bytes in 32-bit DWARF, 8 in 64-bit DWARF.
+    fn offset(&mut self, dwarf64: bool) -> Result<u64, String> {
+        if dwarf64 { self.u64() } else { Ok(self.u32()? as u64) }
+    }
+
+    fn uleb(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= ((byte & 0x7F) as u64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn sleb(&mut self) -> Result<i64, String> {
+        let mut value = 0i64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= ((byte & 0x7F) as i64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    value |= -1i64 << shift;
+                }
+                return Ok(value);
+            }
+        }
+    }
+
+    fn c_str(&mut self) -> Result<String, String> {
+        let rest = &self.data[self.pos.min(self.data.len())..];
+        let len = rest.iter().position(|b| *b == 0).ok_or(".debug_line has an unterminated string")?;
+        let text = String::from_utf8_lossy(&rest[..len]).into_owned();
+        self.pos += len + 1;
+        Ok(text)
+    }
+}
+
+/// Reads the string at `offset` in a string section.
+fn section_str(section: Option<&[u8]>, offset: u64, name: &str) -> Result<String, String> {
+    let section = section.ok_or(format!("{} is missing", name))?;
+    let mut r = Reader { data: section, pos: offset as usize };
+    r.c_str()
+}
+
+/// Strings the line tables may point into.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StringSections<'a> {
+    pub debug_str: Option<&'a [u8]>,
+    pub debug_line_str: Option<&'a [u8]>,
+}
+
+fn join(dir: &str, file: &str) -> String {
+    if dir.is_empty() || file.starts_with('/') {
+        file.to_string()
+    } else {
+        format!("{}/{}", dir.trim_end_matches('/'), file)
+    }
+}
+
+/// Reads a DWARF 5 directory or file name table as `(path, directory)`.
+fn entry_table(r: &mut Reader, dwarf64: bool, strings: StringSections) -> Result<Vec<(String, u64)>, String> {
+    let format_count = r.u8()?;
+    let mut format = Vec::new();
+    for _ in 0..format_count {
+        format.push((r.uleb()?, r.uleb()?));
+    }
+    let count = r.uleb()?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let (mut path, mut dir) = (String::new(), 0);
+        for (content, form) in format.iter() {
+            let text = match *form {
+                DW_FORM_STRING => Some(r.c_str()?),
+                DW_FORM_LINE_STRP => Some(section_str(strings.debug_line_str, r.offset(dwarf64)?, ".debug_line_str")?),
+                DW_FORM_STRP => Some(section_str(strings.debug_str, r.offset(dwarf64)?, ".debug_str")?),
+                _ => None,
+            };
+            let value = match *form {
+                DW_FORM_UDATA => r.uleb()?,
+                DW_FORM_DATA1 => r.u8()? as u64,
+                DW_FORM_DATA2 => r.u16()? as u64,
+                DW_FORM_DATA4 => r.u32()? as u64,
+                DW_FORM_DATA8 => r.u64()?,
+                DW_FORM_DATA16 => r.sized(16)?,
+                DW_FORM_BLOCK => {
+                    let len = r.uleb()? as usize;
+                    r.take(len)?;
+                    0
+                }
+                _ if text.is_some() => 0,
+                other => return Err(format!("unsupported form {:#x} in a line table header", other)),
+            };
+            match *content {
+                DW_LNCT_PATH => path = text.unwrap_or_default(),
+                DW_LNCT_DIRECTORY_INDEX => dir = value,
+                _ => {}
+            }
+        }
+        entries.push((path, dir));
+    }
+    Ok(entries)
+}
+
+/// Runs the line number program of every unit in `.debug_line`, DWARF
+/// versions 2 to 5, giving what is needed to map addresses back to source
+/// lines.
+pub fn parse_line_tables(debug_line: &[u8], strings: StringSections) -> Result<Vec<LineTable>, String> {
+    let mut tables = Vec::new();
+    let mut r = Reader { data: debug_line, pos: 0 };
+    while r.pos < debug_line.len() {
+        let mut unit_length = r.u32()? as u64;
+        let dwarf64 = unit_length == 0xFFFF_FFFF;
+        if dwarf64 {
+            unit_length = r.u64()?;
+        }
+        let unit_end = r.pos.checked_add(unit_length as usize).filter(|end| *end <= debug_line.len());
+        let unit_end = unit_end.ok_or(".debug_line unit runs past the end of the section")?;
+        let version = r.u16()?;
+        if !(2..=5).contains(&version) {
+            return Err(format!("unsupported .debug_line version {}", version));
+        }
+        if version >= 5 {
+            let _address_size = r.u8()?;
+            let _segment_selector_size = r.u8()?;
+        }
+        let header_length = r.offset(dwarf64)?;
+        let program_start = r.pos + header_length as usize;
+        let min_inst_length = r.u8()? as u64;
+        if version >= 4 {
+            let _max_ops_per_inst = r.u8()?;
+        }
+        let default_is_stmt = r.u8()? != 0;
+        let line_base = r.u8()? as i8 as i64;
+        let line_range = r.u8()? as u64;
+        let opcode_base = r.u8()?;
+        if line_range == 0 {
+            return Err(".debug_line has a line range of 0".to_string());
+        }
+        let mut opcode_lengths = vec![0u8; opcode_base as usize];
+        for length in opcode_lengths.iter_mut().skip(1) {
+            *length = r.u8()?;
+        }
+
+        let mut table = LineTable::default();
+        let mut dirs: Vec<String> = Vec::new();
+        if version >= 5 {
+            dirs = entry_table(&mut r, dwarf64, strings)?.into_iter().map(|(path, _)| path).collect();
+            for (path, dir) in entry_table(&mut r, dwarf64, strings)? {
+                table.files.push(join(dirs.get(dir as usize).map_or("", |d| d.as_str()), &path));
+            }
+        } else {
+            // directory 0 is the compilation directory, which only
+            // `.debug_info` knows; paths relative to it stay relative.
+            dirs.push(String::new());
+            loop {
+                let dir = r.c_str()?;
+                if dir.is_empty() {
+                    break;
+                }
+                dirs.push(dir);
+            }
+            table.files.push(String::new());
+            loop {
+                let path = r.c_str()?;
+                if path.is_empty() {
+                    break;
+                }
+                let dir = r.uleb()?;
+                r.uleb()?;
+                r.uleb()?;
+                table.files.push(join(dirs.get(dir as usize).map_or("", |d| d.as_str()), &path));
+            }
+        }
+
+        r.pos = program_start;
+        let reset = LineRow {
+            address: 0,
+            file: 1,
+            line: 1,
+            is_stmt: default_is_stmt,
+            end_sequence: false,
+        };
+        let mut row = reset;
+        while r.pos < unit_end {
+            let opcode = r.u8()?;
+            if opcode >= opcode_base {
+                let adjusted = (opcode - opcode_base) as u64;
+                row.address = row.address.wrapping_add((adjusted / line_range) * min_inst_length);
+                row.line = row.line.wrapping_add_signed(line_base + (adjusted % line_range) as i64);
+                table.rows.push(row);
+                continue;
+            }
+            match opcode {
+                0 => {
+                    let len = r.uleb()? as usize;
+                    let end = r.pos + len;
+                    if len == 0 {
+                        continue;
+                    }
+                    match r.u8()? {
+                        DW_LNE_END_SEQUENCE => {
+                            row.end_sequence = true;
+                            table.rows.push(row);
+                            row = reset;
+                        }
+                        DW_LNE_SET_ADDRESS => row.address = r.sized(len - 1)?,
+                        DW_LNE_DEFINE_FILE => {
+                            let path = r.c_str()?;
+                            let dir = r.uleb()?;
+                            table.files.push(join(dirs.get(dir as usize).map_or("", |d| d.as_str()), &path));
+                        }
+                        _ => {}
+                    }
+                    r.pos = end;
+                }
+                DW_LNS_COPY => table.rows.push(row),
+                DW_LNS_ADVANCE_PC => row.address = row.address.wrapping_add(r.uleb()? * min_inst_length),
+                DW_LNS_ADVANCE_LINE => row.line = row.line.wrapping_add_signed(r.sleb()?),
+                DW_LNS_SET_FILE => row.file = r.uleb()? as usize,
+                DW_LNS_NEGATE_STMT => row.is_stmt = !row.is_stmt,
+                DW_LNS_CONST_ADD_PC => {
+                    let adjusted = (255 - opcode_base) as u64;
+                    row.address = row.address.wrapping_add((adjusted / line_range) * min_inst_length);
+                }
+                DW_LNS_FIXED_ADVANCE_PC => row.address = row.address.wrapping_add(r.u16()? as u64),
+                _ => {
+                    // set_column, set_prologue_end and the like, or
+                    // opcodes newer than this reader: skip the operands.
+                    for _ in 0..opcode_lengths[opcode as usize] {
+                        r.uleb()?;
+                    }
+                }
+            }
+        }
+        tables.push(table);
+        r.pos = unit_end;
+    }
+    Ok(tables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DW_LNS_SET_COLUMN: u8 = 5;
+
+    /// A 32-bit DWARF line table unit: the version, `tables` (the
+    /// directories and files) and the program. The header has
+    /// `line_base = -5`, `line_range = 14` and `opcode_base = 13`, as GCC
+    /// and LLVM emit them.
+    fn unit(version: u16, tables: &[u8], program: &[u8]) -> Vec<u8> {
+        let mut header = vec![1];
+        if version >= 4 {
+            header.push(1);
+        }
+        header.extend([1, 0xFB, 14, 13, 0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1]);
+        header.extend(tables);
+        let mut body = version.to_le_bytes().to_vec();
+        if version >= 5 {
+            body.extend([8, 0]);
+        }
+        body.extend((header.len() as u32).to_le_bytes());
+        body.extend(header);
+        body.extend(program);
+        let mut unit = (body.len() as u32).to_le_bytes().to_vec();
+        unit.extend(body);
+        unit
+    }
+
+    /// A special opcode advancing the address by `address` and the line by
+    /// `line`.
+    fn special(address: u8, line: i8) -> u8 {
+        (line + 5) as u8 + 14 * address + 13
+    }
+
+    fn set_address(address: u64) -> Vec<u8> {
+        let mut op = vec![0, 9, DW_LNE_SET_ADDRESS];
+        op.extend(address.to_le_bytes());
+        op
+    }
+
+    #[test]
+    fn dwarf4_line_program() {
+        let tables = b"src\0\0main.c\0\x01\0\0/abs/util.h\0\0\0\0\0";
+        let mut program = set_address(0x8000_0000);
+        program.extend([DW_LNS_ADVANCE_LINE, 9, DW_LNS_COPY]);
+        program.push(special(4, 1));
+        program.extend([DW_LNS_SET_COLUMN, 7, DW_LNS_SET_FILE, 2, DW_LNS_ADVANCE_PC, 8]);
+        program.extend([DW_LNS_NEGATE_STMT, DW_LNS_ADVANCE_LINE, 0x7D, DW_LNS_COPY]);
+        program.extend([DW_LNS_CONST_ADD_PC, DW_LNS_FIXED_ADVANCE_PC, 3, 0]);
+        program.extend([0, 1, DW_LNE_END_SEQUENCE]);
+
+        let tables = parse_line_tables(&unit(4, tables, &program), StringSections::default()).unwrap();
+        assert_eq!(tables.len(), 1);
+        let table = &tables[0];
+        assert_eq!(table.files, ["", "src/main.c", "/abs/util.h"]);
+        let row = |address, file, line, is_stmt, end_sequence| LineRow {
+            address,
+            file,
+            line,
+            is_stmt,
+            end_sequence,
+        };
+        assert_eq!(
+            table.rows,
+            [
+                row(0x8000_0000, 1, 10, true, false),
+                row(0x8000_0004, 1, 11, true, false),
+                row(0x8000_000C, 2, 8, false, false),
+                // 17 from const_add_pc, 3 from fixed_advance_pc.
+                row(0x8000_0020, 2, 8, false, true),
+            ]
+        );
+        assert_eq!(
+            table.ranges().collect::<Vec<_>>(),
+            [
+                (0x8000_0000, 0x8000_0004, "src/main.c", 10),
+                (0x8000_0004, 0x8000_000C, "src/main.c", 11),
+                (0x8000_000C, 0x8000_0020, "/abs/util.h", 8),
+            ]
+        );
+    }
+
+    #[test]
+    fn dwarf5_line_program() {
+        // directories: one path in .debug_line_str.
+        let mut tables = vec![1, DW_LNCT_PATH as u8, DW_FORM_LINE_STRP as u8, 1, 5, 0, 0, 0];
+        // files: an inline path and a directory index.
+        tables.extend([2, DW_LNCT_PATH as u8, DW_FORM_STRING as u8]);
+        tables.extend([DW_LNCT_DIRECTORY_INDEX as u8, DW_FORM_DATA1 as u8, 1]);
+        tables.extend(b"a.c\0\0");
+        let mut program = vec![DW_LNS_SET_FILE, 0];
+        program.extend(set_address(0x1000));
+        program.extend([DW_LNS_COPY, DW_LNS_ADVANCE_PC, 2, 0, 1, DW_LNE_END_SEQUENCE]);
+
+        let section = unit(5, &tables, &program);
+        let strings = StringSections {
+            debug_str: None,
+            debug_line_str: Some(b"/tmp\0/work\0"),
+        };
+        let tables = parse_line_tables(&section, strings).unwrap();
+        assert_eq!(tables[0].files, ["/work/a.c"]);
+        assert_eq!(tables[0].ranges().collect::<Vec<_>>(), [(0x1000, 0x1002, "/work/a.c", 1)]);
+
+        let err = parse_line_tables(&section, StringSections::default()).unwrap_err();
+        assert!(err.contains(".debug_line_str"), "{}", err);
+    }
+
+    #[test]
+    fn bad_units() {
+        let good = unit(3, b"\0\0", &[DW_LNS_COPY]);
+        assert_eq!(parse_line_tables(&good, StringSections::default()).unwrap()[0].rows.len(), 1);
+        let err = parse_line_tables(&unit(6, b"\0\0", &[]), StringSections::default()).unwrap_err();
+        assert!(err.contains("version 6"), "{}", err);
+        let err = parse_line_tables(&good[..good.len() - 1], StringSections::default()).unwrap_err();
+        assert!(err.contains("past the end"), "{}", err);
+    }
+}
@@ -0,0 +1,247 @@
+use crate::Memory::Bus;
+
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELFCLASS32: u8 = 1;
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EM_RISCV: u16 = 243;
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHF_COMPRESSED: u64 = 0x800;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+const STT_SECTION: u8 = 3;
+const STT_FILE: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function,
+    Object,
+    Other,
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub value: u64,
+    pub size: u64,
+    pub kind: SymbolKind,
+}
+
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub vaddr: u64,
+    pub paddr: u64,
+    pub data: Vec<u8>,
+    pub memsz: u64,
+}
+
+/// The parts of a RISC-V ELF executable the emulator cares about.
+#[derive(Debug, Clone)]
+pub struct ElfFile {
+    /// 32 for ELF32 files, which are RV32 code, 64 for ELF64.
+    pub xlen: u32,
+    pub entry: u64,
+    pub segments: Vec<Segment>,
+    /// Symbols from `.symtab`, sorted by address.
+    pub symbols: Vec<Symbol>,
+    /// The `.debug_*` sections, by name.
+    pub debug_sections: Vec<(String, Vec<u8>)>,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    /// The size of addresses and offsets: 4 bytes in ELF32, 8 in ELF64.
+    word: u64,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&self, off: u64, len: u64) -> Result<&'a [u8], String> {
+        let start = off as usize;
+        let end = start.checked_add(len as usize).ok_or("ELF offset overflows")?;
+        self.data.get(start..end).ok_or_else(|| format!("ELF is truncated at {:#x}", off))
+    }
+
+    fn u8(&self, off: u64) -> Result<u8, String> {
+        Ok(self.bytes(off, 1)?[0])
+    }
+
+    fn u16(&self, off: u64) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.bytes(off, 2)?.try_into().unwrap()))
+    }
+
+    fn u32(&self, off: u64) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.bytes(off, 4)?.try_into().unwrap()))
+    }
+
+    fn u64(&self, off: u64) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.bytes(off, 8)?.try_into().unwrap()))
+    }
+
+    fn word(&self, off: u64) -> Result<u64, String> {
+        if self.word == 4 { Ok(self.u32(off)? as u64) } else { self.u64(off) }
+    }
+
+    fn c_str(&self, off: u64) -> Result<String, String> {
+        let rest = self.data.get(off as usize..).ok_or("ELF string out of range")?;
+        let len = rest.iter().position(|b| *b == 0).unwrap_or(rest.len());
+        Ok(String::from_utf8_lossy(&rest[..len]).into_owned())
+    }
+}
+
+impl ElfFile {
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let mut r = Reader { data, word: 8 };
+        if r.bytes(0, 4)? != ELF_MAGIC {
+            return Err("not an ELF file".to_string());
+        }
+        let xlen = match (r.u8(4)?, r.u8(5)?) {
+            (ELFCLASS32, ELFDATA2LSB) => 32,
+            (ELFCLASS64, ELFDATA2LSB) => 64,
+            _ => return Err("only little-endian ELF32 and ELF64 files are supported".to_string()),
+        };
+        if r.u16(18)? != EM_RISCV {
+            return Err("ELF file is not for RISC-V".to_string());
+        }
+        // past `e_entry`, the header, program headers, section headers
+        // and symbols all lay out by the word size.
+        let w = xlen as u64 / 8;
+        r.word = w;
+        let entry = r.word(24)?;
+        let phoff = r.word(24 + w)?;
+        let shoff = r.word(24 + 2 * w)?;
+        let phentsize = r.u16(30 + 3 * w)? as u64;
+        let phnum = r.u16(32 + 3 * w)? as u64;
+        let shentsize = r.u16(34 + 3 * w)? as u64;
+        let shnum = r.u16(36 + 3 * w)? as u64;
+        let shstrndx = r.u16(38 + 3 * w)? as u64;
+
+        let mut segments = Vec::new();
+        for i in 0..phnum {
+            let ph = phoff + i * phentsize;
+            if r.u32(ph)? != PT_LOAD {
+                continue;
+            }
+            // ELF64 moves `p_flags` up next to `p_type`.
+            let fields = if w == 8 { ph + 8 } else { ph + 4 };
+            let offset = r.word(fields)?;
+            let filesz = r.word(fields + 3 * w)?;
+            segments.push(Segment {
+                vaddr: r.word(fields + w)?,
+                paddr: r.word(fields + 2 * w)?,
+                data: r.bytes(offset, filesz)?.to_vec(),
+                memsz: r.word(fields + 4 * w)?,
+            });
+        }
+
+        let mut symbols = Vec::new();
+        let mut debug_sections = Vec::new();
+        let section_offset = |sh: u64| r.word(sh + 8 + 2 * w);
+        let shstrtab = if shnum > 0 { section_offset(shoff + shstrndx * shentsize)? } else { 0 };
+        for i in 0..shnum {
+            let sh = shoff + i * shentsize;
+            let name = r.c_str(shstrtab + r.u32(sh)? as u64)?;
+            let (offset, size) = (section_offset(sh)?, r.word(sh + 8 + 3 * w)?);
+            if name.starts_with(".debug_") && r.word(sh + 8)? & SHF_COMPRESSED == 0 {
+                debug_sections.push((name, r.bytes(offset, size)?.to_vec()));
+            }
+            if r.u32(sh + 4)? != SHT_SYMTAB {
+                continue;
+            }
+            let link = r.u32(sh + 8 + 4 * w)? as u64;
+            let entsize = r.word(sh + 16 + 5 * w)?.max(8 + 2 * w);
+            let strtab = section_offset(shoff + link * shentsize)?;
+            for sym in (offset..offset + size).step_by(entsize as usize) {
+                let name = r.c_str(strtab + r.u32(sym)? as u64)?;
+                // ELF32 puts `st_value` and `st_size` before `st_info`.
+                let (info, shndx, value) = if w == 8 {
+                    (sym + 4, sym + 6, sym + 8)
+                } else {
+                    (sym + 12, sym + 14, sym + 4)
+                };
+                let (info, shndx) = (r.u8(info)?, r.u16(shndx)?);
+                if name.is_empty() || shndx == 0 || matches!(info & 0xF, STT_SECTION | STT_FILE) {
+                    continue;
+                }
+                symbols.push(Symbol {
+                    name,
+                    value: r.word(value)?,
+                    size: r.word(value + w)?,
+                    kind: match info & 0xF {
+                        STT_FUNC => SymbolKind::Function,
+                        STT_OBJECT => SymbolKind::Object,
+                        _ => SymbolKind::Other,
+                    },
+                });
+            }
+        }
+        symbols.sort_by_key(|s| s.value);
+
+        Ok(Self {
+            xlen,
+            entry,
+            segments,
+            symbols,
+            debug_sections,
+        })
+    }
+
+    /// Copies every loadable segment to its physical address, zero filling
+    /// the part past the end of the file.
+    pub fn load(&self, bus: &mut Bus) -> Result<(), String> {
+        for seg in &self.segments {
+            let mut bytes = seg.data.clone();
+            bytes.resize(seg.memsz.max(seg.data.len() as u64) as usize, 0);
+            if !bus.write_bytes(seg.paddr, &bytes) {
+                return Err(format!(
+                    "segment at {:#x} ({:#x} bytes) is outside of RAM",
+                    seg.paddr, bytes.len()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn debug_section(&self, name: &str) -> Option<&[u8]> {
+        self.debug_sections.iter().find(|(n, _)| n == name).map(|(_, data)| data.as_slice())
+    }
+
+    /// The `len` file-backed bytes at virtual address `vaddr`, if one
+    /// segment holds all of them.
+    pub fn bytes_at(&self, vaddr: u64, len: u64) -> Option<&[u8]> {
+        self.segments.iter().find_map(|seg| {
+            let start = vaddr.checked_sub(seg.vaddr)? as usize;
+            seg.data.get(start..start.checked_add(len as usize)?)
+        })
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<u64> {
+        self.symbols.iter().find(|s| s.name == name).map(|s| s.value)
+    }
+
+    /// Finds the symbol `addr` falls in, along with the offset of `addr`
+    /// into it. Symbols without a size reach up to the next symbol.
+    pub fn symbolize(&self, addr: u64) -> Option<(&Symbol, u64)> {
+        let end = self.symbols.partition_point(|s| s.value <= addr);
+        self.symbols[..end]
+            .iter()
+            .rev()
+            .find(|s| s.size == 0 || addr < s.value + s.size)
+            .map(|s| (s, addr - s.value))
+    }
+
+    /// Finds the function `addr` is in. Hand-written assembly often has
+    /// no function symbols, so untyped global labels count too; local
+    /// `.L` labels and `$x`/`$d` mapping symbols do not.
+    pub fn function_at(&self, addr: u64) -> Option<&Symbol> {
+        let end = self.symbols.partition_point(|s| s.value <= addr);
+        self.symbols[..end].iter().rev().find(|s| {
+            let candidate = match s.kind {
+                SymbolKind::Function => true,
+                SymbolKind::Object => false,
+                SymbolKind::Other => !s.name.starts_with(".L") && !s.name.starts_with('$'),
+            };
+            candidate && (s.size == 0 || addr < s.value + s.size)
+        })
+    }
+}
@@ -0,0 +1,326 @@
+use crate::Clint::Clint;
+use crate::Coverage::Coverage;
+use crate::Cpu::{Hart, Privilege, REG_A0, REG_A1};
+use crate::DeviceTree::{build_fdt, dtb_address, MachineDescription};
+use crate::Elf::ElfFile;
+use crate::Htif::Htif;
+use crate::Isa::Isa;
+use crate::Jit::Jit;
+use crate::Memory::Bus;
+use crate::Profile::Profiler;
+use crate::Replay::{DeviceEvent, DeviceMode};
+use crate::Stats::Stats;
+use crate::Trace::{Commit, CommitLog};
+
+/// Instructions retired by each hart per tick of `mtime`.
+pub const INSTRUCTIONS_PER_TICK: u64 = 100;
+/// The frequency `mtime` is advertised to tick at in the device tree.
+pub const TIMEBASE_FREQUENCY: u32 = 10_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The target exited through HTIF or the SBI with this code.
+    Exit(u64),
+    /// The instruction limit handed to `run` was reached.
+    InstructionLimit,
+    /// The JIT and the interpreter disagreed; `Jit::mismatch` says where.
+    JitMismatch,
+}
+
+/// A complete system: every hart plus the bus they share.
+#[derive(Debug, Clone)]
+pub struct Machine {
+    pub harts: Vec<Hart>,
+    pub bus: Bus,
+    /// Number of rounds `step` has run, one instruction per hart each.
+    pub steps: u64,
+    /// Where retired instructions are logged, if anywhere.
+    pub commit_log: Option<CommitLog>,
+    pub profiler: Option<Profiler>,
+    pub coverage: Option<Coverage>,
+    pub stats: Option<Stats>,
+    /// Runs a lone hart's code translated to x86-64, when set.
+    pub jit: Option<Jit>,
+}
+
+impl Machine {
+    pub fn new(hart_count: usize, ram_size: u64) -> Self {
+        let mut bus = Bus::new(ram_size);
+        bus.clint = Clint::new(hart_count);
+        Self {
+            harts: (0..hart_count as u64).map(Hart::new).collect(),
+            bus,
+            steps: 0,
+            commit_log: None,
+            profiler: None,
+            coverage: None,
+            stats: None,
+            jit: None,
+        }
+    }
+
+    /// The base ISA and extensions the harts implement, the same for all
+    /// of them.
+    pub fn isa(&self) -> Isa {
+        self.harts[0].isa
+    }
+
+    /// Gives every hart the extensions in `isa`, dropping code decoded for
+    /// the old ones.
+    pub fn set_isa(&mut self, isa: Isa) {
+        for hart in self.harts.iter_mut() {
+            hart.set_isa(isa);
+        }
+        self.bus.blocks.flush();
+    }
+
+    /// Makes every hart record what each instruction did in `hart.commit`.
+    pub fn record_commits(&mut self) {
+        for hart in self.harts.iter_mut() {
+            if hart.commit.is_none() {
+                hart.commit = Some(Commit::default());
+            }
+        }
+    }
+
+    /// Logs every retired instruction to `log` in Spike's commit format.
+    pub fn enable_commit_log(&mut self, log: CommitLog) {
+        self.record_commits();
+        self.commit_log = Some(log);
+    }
+
+    /// Counts every round against its pc and call stack from now on.
+    pub fn enable_profiler(&mut self) {
+        self.record_commits();
+        self.profiler = Some(Profiler::new(&self.harts));
+    }
+
+    /// Tracks executed pcs and branch outcomes from now on.
+    pub fn enable_coverage(&mut self) {
+        self.record_commits();
+        self.coverage = Some(Coverage::new());
+    }
+
+    /// Counts the instruction mix from now on.
+    pub fn enable_stats(&mut self) {
+        self.record_commits();
+        self.stats = Some(Stats::new());
+    }
+
+    /// Runs translated code from now on where possible, cross-checking
+    /// every block against the interpreter when `check` is set. Only `run`
+    /// uses it, and only with a single hart and no observers.
+    pub fn enable_jit(&mut self, check: bool) -> Result<(), String> {
+        self.jit = Some(Jit::new(check)?);
+        Ok(())
+    }
+
+    pub fn flush_logs(&self) {
+        if let Some(log) = &self.commit_log {
+            log.flush();
+        }
+    }
+
+    /// Runs one instruction on every hart in turn, then lets the devices
+    /// catch up.
+    pub fn step(&mut self) {
+        self.step_with(&mut DeviceMode::Live);
+    }
+
+    /// `step`, with the devices recorded or replayed as `devices` says.
+    pub fn step_with(&mut self, devices: &mut DeviceMode) {
+        for index in 0..self.harts.len() {
+            let hart = &mut self.harts[index];
+            hart.step(&mut self.bus);
+            if let (Some(log), Some(commit)) = (&self.commit_log, &hart.commit) {
+                if commit.retired {
+                    log.write(hart.id, commit);
+                }
+            }
+            if let Some(profiler) = &mut self.profiler {
+                profiler.record(index, hart);
+            }
+            if let Some(coverage) = &mut self.coverage {
+                coverage.record(hart);
+            }
+            if let Some(stats) = &mut self.stats {
+                stats.record(hart);
+            }
+            if self.bus.htif.as_ref().is_some_and(|h| h.tohost_written) {
+                self.run_devices(Some(index), devices);
+            }
+            if self.bus.clint.changed {
+                self.sample_interrupts();
+            }
+        }
+        self.end_round(devices);
+    }
+
+    /// Runs as many rounds as it can, up to `budget`, through the JIT,
+    /// never past the next tick of `mtime`, with the devices seeing them
+    /// just as they would under `step_with`. Returns the rounds run; 0
+    /// leaves the next one to `step_with`.
+    fn step_jit(&mut self, budget: u64, devices: &mut DeviceMode) -> u64 {
+        let jit = match &mut self.jit {
+            Some(jit) if self.harts.len() == 1 && self.harts[0].commit.is_none() => jit,
+            _ => return 0,
+        };
+        let budget = budget.min(INSTRUCTIONS_PER_TICK - self.steps % INSTRUCTIONS_PER_TICK);
+        let rounds = jit.run(&mut self.harts[0], &mut self.bus, budget);
+        if rounds == 0 {
+            return 0;
+        }
+        // only the last instruction of a run can have written `tohost`.
+        self.steps += rounds - 1;
+        if self.bus.htif.as_ref().is_some_and(|h| h.tohost_written) {
+            self.run_devices(Some(0), devices);
+        }
+        if self.bus.clint.changed {
+            self.sample_interrupts();
+        }
+        self.end_round(devices);
+        rounds
+    }
+
+    fn end_round(&mut self, devices: &mut DeviceMode) {
+        self.steps += 1;
+        if self.steps.is_multiple_of(INSTRUCTIONS_PER_TICK) {
+            self.bus.mtime += 1;
+            self.sample_interrupts();
+            self.run_devices(None, devices);
+        }
+        if let DeviceMode::Record(recording) = devices {
+            recording.horizon = self.steps;
+        }
+    }
+
+    /// Passes the CLINT's lines on to every hart's `mip`.
+    fn sample_interrupts(&mut self) {
+        self.bus.clint.changed = false;
+        for hart in self.harts.iter_mut() {
+            hart.sample_interrupts(&mut self.bus);
+        }
+    }
+
+    /// Lets HTIF answer a `tohost` write from `hart`, or poll for console
+    /// input on a timer tick when `hart` is `None`.
+    fn run_devices(&mut self, hart: Option<usize>, devices: &mut DeviceMode) {
+        let mut htif = match self.bus.htif.take() {
+            Some(htif) => htif,
+            None => return,
+        };
+        match devices {
+            DeviceMode::Live => Self::service(&mut htif, &mut self.bus, hart),
+            DeviceMode::Record(recording) => {
+                let exited = htif.exit_code.is_some();
+                self.bus.journal = Some(Vec::new());
+                Self::service(&mut htif, &mut self.bus, hart);
+                let writes = self.bus.journal.take().unwrap_or_default();
+                let exit_code = if exited { None } else { htif.exit_code };
+                if !writes.is_empty() || exit_code.is_some() {
+                    recording.push(DeviceEvent {
+                        step: self.steps,
+                        hart,
+                        writes,
+                        exit_code,
+                    });
+                }
+            }
+            DeviceMode::Replay(recording) => {
+                htif.tohost_written = false;
+                while let Some(event) = recording.next_event(self.steps, hart) {
+                    for (addr, bytes) in event.writes.iter() {
+                        self.bus.write_bytes(*addr, bytes);
+                    }
+                    if event.exit_code.is_some() {
+                        htif.exit_code = event.exit_code;
+                    }
+                }
+            }
+        }
+        self.bus.htif = Some(htif);
+    }
+
+    fn service(htif: &mut Htif, bus: &mut Bus, hart: Option<usize>) {
+        match hart {
+            Some(_) => htif.service(bus),
+            None => htif.poll(bus),
+        }
+    }
+
+    /// Loads a bare-metal ELF program and points every hart at its entry
+    /// in M-mode with `a0 = hartid` and `a1 = DTB address`, like Spike does.
+    /// Programs with a `tohost` symbol get an HTIF, with `args` as the
+    /// arguments `pk` sees. The ELF class has to match the harts' XLEN.
+    pub fn load_program(&mut self, elf: &ElfFile, args: Vec<String>) -> Result<(), String> {
+        if elf.xlen != self.isa().xlen() {
+            return Err(format!("a {}-bit ELF file cannot run on {}", elf.xlen, self.isa()));
+        }
+        elf.load(&mut self.bus)?;
+        let htif = elf.symbol("tohost").map(|tohost| Htif::new(tohost, elf.symbol("fromhost"), args));
+
+        let isa = self.isa().to_string();
+        let dtb_blob = build_fdt(&MachineDescription {
+            hart_count: self.harts.len(),
+            isa: &isa,
+            ram_base: self.bus.ram.base,
+            ram_size: self.bus.ram.size(),
+            timebase_frequency: TIMEBASE_FREQUENCY,
+            bootargs: None,
+            initrd: None,
+            htif: htif.is_some(),
+        });
+        let dtb = dtb_address(self.bus.ram.base, self.bus.ram.end(), dtb_blob.len() as u64)
+            .ok_or("not enough RAM for the device tree")?;
+        let end = dtb + dtb_blob.len() as u64;
+        if elf.segments.iter().any(|s| s.paddr < end && dtb < s.paddr + s.memsz) {
+            return Err("program overlaps the device tree at the top of RAM".to_string());
+        }
+        self.bus.write_bytes(dtb, &dtb_blob);
+        self.bus.htif = htif;
+
+        for hart in self.harts.iter_mut() {
+            hart.write_reg(REG_A0, hart.id);
+            hart.write_reg(REG_A1, dtb);
+            hart.pc = elf.entry;
+            hart.privilege = Privilege::Machine;
+            hart.virt = false;
+        }
+        Ok(())
+    }
+
+    /// The exit code the target asked for through HTIF, or through the
+    /// built-in SBI's shutdown.
+    pub fn exit_code(&self) -> Option<u64> {
+        self.bus.htif.as_ref().and_then(|h| h.exit_code)
+            .or_else(|| self.bus.sbi.as_ref().and_then(|sbi| sbi.exit_code))
+    }
+
+    /// Runs until the target exits, or for at most `limit` rounds.
+    pub fn run(&mut self, limit: Option<u64>) -> StopReason {
+        self.run_with(limit, &mut DeviceMode::Live)
+    }
+
+    /// `run`, with the devices recorded or replayed as `devices` says.
+    pub fn run_with(&mut self, limit: Option<u64>, devices: &mut DeviceMode) -> StopReason {
+        let mut count = 0;
+        loop {
+            if let Some(code) = self.exit_code() {
+                return StopReason::Exit(code);
+            }
+            if limit.is_some_and(|limit| count >= limit) {
+                return StopReason::InstructionLimit;
+            }
+            let rounds = self.step_jit(limit.map_or(u64::MAX, |limit| limit - count), devices);
+            if self.jit.as_ref().is_some_and(|jit| jit.mismatch.is_some()) {
+                return StopReason::JitMismatch;
+            }
+            if rounds > 0 {
+                count += rounds;
+                continue;
+            }
+            self.step_with(devices);
+            count += 1;
+        }
+    }
+}
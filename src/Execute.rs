@@ -0,0 +1,1304 @@
+use crate::Cpu::{Access, Exception, Hart, Privilege, Trap};
+use crate::Crypto::CryptoOp;
+use crate::Csr::*;
+use crate::Decode::{Decoded, Op};
+use crate::Hypervisor::HypervisorAccess;
+use crate::Memory::Bus;
+#[cfg(feature = "f")]
+use crate::SoftFloat::{box_f16, box_f32, classify, inject_sign, unbox_f16, unbox_f32, Env, Format, Rounding, F16, F32, F64};
+
+/// Sign extends the low `bits` bits of `value`.
+pub fn sext(value: u64, bits: u32) -> u64 {
+    let shift = 64 - bits;
+    (((value << shift) as i64) >> shift) as u64
+}
+
+/// Signed division as `DIV` defines it: all ones when dividing by zero,
+/// and the dividend when `i64::MIN / -1` overflows.
+pub fn div(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        -1
+    } else {
+        a.wrapping_div(b)
+    }
+}
+
+/// Signed remainder as `REM` defines it: the dividend when dividing by
+/// zero, and `0` when `i64::MIN % -1` overflows.
+pub fn rem(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a
+    } else {
+        a.wrapping_rem(b)
+    }
+}
+
+/// The carry-less product of `a` and `b`: long multiplication with XOR
+/// in place of addition.
+fn clmul(a: u64, b: u64) -> u128 {
+    (0..64).filter(|i| (b >> i) & 1 != 0).fold(0, |product, i| product ^ ((a as u128) << i))
+}
+
+impl Hart {
+    /// Executes a decoded instruction at `pc`, advancing `pc` past it unless
+    /// the instruction jumps.
+    pub fn execute(&mut self, bus: &mut Bus, inst: &Decoded) -> Result<(), Trap> {
+        let pc = self.pc;
+        let mut next_pc = pc.wrapping_add(inst.size());
+        let (rd, rs1, rs2) = (inst.rd as usize, inst.rs1 as usize, inst.rs2 as usize);
+        let (imm, raw) = (inst.imm as u64, inst.raw);
+
+        match inst.op {
+            // `RV32I` Base Instructions
+            Op::LUI => self.write_reg(rd, imm),
+            Op::AUIPC => self.write_reg(rd, pc.wrapping_add(imm)),
+            Op::JAL => {
+                let target = self.jump_target(pc.wrapping_add(imm))?;
+                self.write_reg(rd, next_pc);
+                next_pc = target;
+            },
+            Op::JALR => {
+                let target = self.jump_target(self.x[rs1].wrapping_add(imm) & !1)?;
+                self.write_reg(rd, next_pc);
+                next_pc = target;
+            },
+            Op::BEQ => {
+                if self.src(rs1) == self.src(rs2) {
+                    next_pc = self.jump_target(pc.wrapping_add(imm))?;
+                }
+            },
+            Op::BNE => {
+                if self.src(rs1) != self.src(rs2) {
+                    next_pc = self.jump_target(pc.wrapping_add(imm))?;
+                }
+            },
+            Op::BLT => {
+                if (self.src(rs1) as i64) < (self.src(rs2) as i64) {
+                    next_pc = self.jump_target(pc.wrapping_add(imm))?;
+                }
+            },
+            Op::BGE => {
+                if (self.src(rs1) as i64) >= (self.src(rs2) as i64) {
+                    next_pc = self.jump_target(pc.wrapping_add(imm))?;
+                }
+            },
+            Op::BLTU => {
+                if self.src(rs1) < self.src(rs2) {
+                    next_pc = self.jump_target(pc.wrapping_add(imm))?;
+                }
+            },
+            Op::BGEU => {
+                if self.src(rs1) >= self.src(rs2) {
+                    next_pc = self.jump_target(pc.wrapping_add(imm))?;
+                }
+            },
+            Op::LB => {
+                let value = self.load(bus, self.x[rs1].wrapping_add(imm), 1)?;
+                self.write_reg(rd, sext(value, 8));
+            },
+            Op::LH => {
+                let value = self.load(bus, self.x[rs1].wrapping_add(imm), 2)?;
+                self.write_reg(rd, sext(value, 16));
+            },
+            Op::LW => {
+                let value = self.load(bus, self.x[rs1].wrapping_add(imm), 4)?;
+                self.write_reg(rd, sext(value, 32));
+            },
+            Op::LBU => {
+                let value = self.load(bus, self.x[rs1].wrapping_add(imm), 1)?;
+                self.write_reg(rd, value);
+            },
+            Op::LHU => {
+                let value = self.load(bus, self.x[rs1].wrapping_add(imm), 2)?;
+                self.write_reg(rd, value);
+            },
+            Op::SB => self.store(bus, self.x[rs1].wrapping_add(imm), 1, self.x[rs2])?,
+            Op::SH => self.store(bus, self.x[rs1].wrapping_add(imm), 2, self.x[rs2])?,
+            Op::SW => self.store(bus, self.x[rs1].wrapping_add(imm), 4, self.x[rs2])?,
+            Op::ADDI => self.write_reg(rd, self.x[rs1].wrapping_add(imm)),
+            Op::SLTI => {
+                self.write_reg(rd, ((self.src(rs1) as i64) < (imm as i64)) as u64);
+            },
+            Op::SLTIU => self.write_reg(rd, (self.src(rs1) < imm) as u64),
+            Op::XORI => self.write_reg(rd, self.x[rs1] ^ imm),
+            Op::ORI => self.write_reg(rd, self.x[rs1] | imm),
+            Op::ANDI => self.write_reg(rd, self.x[rs1] & imm),
+            Op::SLLI => self.write_reg(rd, self.x[rs1] << imm),
+            Op::SRLI => self.write_reg(rd, self.src_unsigned(rs1) >> imm),
+            Op::SRAI => self.write_reg(rd, ((self.src(rs1) as i64) >> imm) as u64),
+            Op::ADD => self.write_reg(rd, self.x[rs1].wrapping_add(self.x[rs2])),
+            Op::SUB => self.write_reg(rd, self.x[rs1].wrapping_sub(self.x[rs2])),
+            Op::SLL => self.write_reg(rd, self.x[rs1] << self.shamt(rs2)),
+            Op::SLT => {
+                self.write_reg(rd, ((self.src(rs1) as i64) < (self.src(rs2) as i64)) as u64);
+            },
+            Op::SLTU => self.write_reg(rd, (self.src(rs1) < self.src(rs2)) as u64),
+            Op::XOR => self.write_reg(rd, self.x[rs1] ^ self.x[rs2]),
+            Op::SRL => self.write_reg(rd, self.src_unsigned(rs1) >> self.shamt(rs2)),
+            Op::SRA => {
+                self.write_reg(rd, ((self.src(rs1) as i64) >> self.shamt(rs2)) as u64);
+            },
+            Op::OR => self.write_reg(rd, self.x[rs1] | self.x[rs2]),
+            Op::AND => self.write_reg(rd, self.x[rs1] & self.x[rs2]),
+            // memory is always coherent, so there is nothing to order.
+            Op::FENCE => {},
+            Op::ECALL => {
+                let exception = match self.privilege {
+                    Privilege::User => Exception::EcallFromUMode,
+                    Privilege::Supervisor if self.virt => Exception::EcallFromVSMode,
+                    Privilege::Supervisor => Exception::EcallFromSMode,
+                    Privilege::Machine => Exception::EcallFromMMode,
+                };
+                return Err(Trap::new(exception, 0));
+            },
+            Op::EBREAK => return Err(Trap::new(Exception::Breakpoint, pc)),
+            // Privileged Instructions. What a guest may not run but the
+            // hypervisor could is a virtual instruction for it to emulate.
+            Op::MRET => {
+                if self.privilege != Privilege::Machine {
+                    return Err(Trap::illegal(raw));
+                }
+                self.mret();
+                bus.reservations.release(self.id);
+                return Ok(());
+            },
+            Op::SRET => {
+                if self.virt && (self.privilege == Privilege::User || self.csr.hstatus & HSTATUS_VTSR != 0) {
+                    return Err(Trap::virtual_instruction(raw));
+                }
+                if self.privilege == Privilege::User
+                    || (self.privilege == Privilege::Supervisor && self.csr.mstatus & MSTATUS_TSR != 0) {
+                    return Err(Trap::illegal(raw));
+                }
+                self.sret();
+                bus.reservations.release(self.id);
+                return Ok(());
+            },
+            Op::WFI => {
+                // treated as a hint: execution simply continues.
+                if self.privilege != Privilege::Machine && self.csr.mstatus & MSTATUS_TW != 0 {
+                    return Err(Trap::illegal(raw));
+                }
+                if self.virt && (self.privilege == Privilege::User || self.csr.hstatus & HSTATUS_VTW != 0) {
+                    return Err(Trap::virtual_instruction(raw));
+                }
+                if self.privilege == Privilege::User {
+                    return Err(Trap::illegal(raw));
+                }
+            },
+            Op::SFENCEVMA => {
+                // the only TLB is the page fetches last came from.
+                if self.virt && (self.privilege == Privilege::User || self.csr.hstatus & HSTATUS_VTVM != 0) {
+                    return Err(Trap::virtual_instruction(raw));
+                }
+                if self.privilege == Privilege::User
+                    || (self.privilege == Privilege::Supervisor && self.csr.mstatus & MSTATUS_TVM != 0) {
+                    return Err(Trap::illegal(raw));
+                }
+                self.flush_fetch_translation();
+            },
+            // `RV64I` Base Instructions
+            Op::LWU => {
+                let value = self.load(bus, self.x[rs1].wrapping_add(imm), 4)?;
+                self.write_reg(rd, value);
+            },
+            Op::LD => {
+                let value = self.load(bus, self.x[rs1].wrapping_add(imm), 8)?;
+                self.write_reg(rd, value);
+            },
+            Op::SD => self.store(bus, self.x[rs1].wrapping_add(imm), 8, self.x[rs2])?,
+            Op::ADDIW => self.write_reg(rd, sext(self.x[rs1].wrapping_add(imm), 32)),
+            Op::SLLIW => self.write_reg(rd, sext(self.x[rs1] << imm, 32)),
+            Op::SRLIW => self.write_reg(rd, sext((self.x[rs1] as u32 >> imm) as u64, 32)),
+            Op::SRAIW => self.write_reg(rd, ((self.x[rs1] as i32) >> imm) as i64 as u64),
+            Op::ADDW => self.write_reg(rd, sext(self.x[rs1].wrapping_add(self.x[rs2]), 32)),
+            Op::SUBW => self.write_reg(rd, sext(self.x[rs1].wrapping_sub(self.x[rs2]), 32)),
+            Op::SLLW => {
+                self.write_reg(rd, sext((self.x[rs1] as u32).wrapping_shl(self.x[rs2] as u32 & 0x1F) as u64, 32));
+            },
+            Op::SRLW => {
+                self.write_reg(rd, sext((self.x[rs1] as u32 >> (self.x[rs2] & 0x1F)) as u64, 32));
+            },
+            Op::SRAW => {
+                self.write_reg(rd, ((self.x[rs1] as i32) >> (self.x[rs2] & 0x1F)) as i64 as u64);
+            },
+            // `Zifencei` Extension
+            // stores already drop the blocks decoded from what they
+            // overwrite; this drops the rest too.
+            Op::FENCEI => bus.blocks.flush(),
+            // `Zicsr` Extension
+            Op::CSRRW => {
+                let src = self.x[rs1];
+                let old = self.csr_op(bus, imm as usize, raw, rd != 0, true, |_| src)?;
+                self.write_reg(rd, old);
+            },
+            Op::CSRRS => {
+                let src = self.x[rs1];
+                let old = self.csr_op(bus, imm as usize, raw, true, rs1 != 0, |old| old | src)?;
+                self.write_reg(rd, old);
+            },
+            Op::CSRRC => {
+                let src = self.x[rs1];
+                let old = self.csr_op(bus, imm as usize, raw, true, rs1 != 0, |old| old & !src)?;
+                self.write_reg(rd, old);
+            },
+            Op::CSRRWI => {
+                let src = inst.rs1 as u64;
+                let old = self.csr_op(bus, imm as usize, raw, rd != 0, true, |_| src)?;
+                self.write_reg(rd, old);
+            },
+            Op::CSRRSI => {
+                let src = inst.rs1 as u64;
+                let old = self.csr_op(bus, imm as usize, raw, true, src != 0, |old| old | src)?;
+                self.write_reg(rd, old);
+            },
+            Op::CSRRCI => {
+                let src = inst.rs1 as u64;
+                let old = self.csr_op(bus, imm as usize, raw, true, src != 0, |old| old & !src)?;
+                self.write_reg(rd, old);
+            },
+            // `M` Extension
+            Op::MUL => self.write_reg(rd, self.x[rs1].wrapping_mul(self.x[rs2])),
+            Op::MULH => {
+                let product = (self.src(rs1) as i64 as i128) * (self.src(rs2) as i64 as i128);
+                self.write_reg(rd, (product >> self.xlen()) as u64);
+            },
+            Op::MULHSU => {
+                let product = (self.src(rs1) as i64 as i128) * (self.src_unsigned(rs2) as i128);
+                self.write_reg(rd, (product >> self.xlen()) as u64);
+            },
+            Op::MULHU => {
+                let product = (self.src_unsigned(rs1) as u128) * (self.src_unsigned(rs2) as u128);
+                self.write_reg(rd, (product >> self.xlen()) as u64);
+            },
+            // at XLEN 32 the 64-bit results are sign extended back, which
+            // also turns `i32::MIN / -1` into `i32::MIN`.
+            Op::DIV => self.write_reg(rd, div(self.src(rs1) as i64, self.src(rs2) as i64) as u64),
+            Op::DIVU => {
+                let (a, b) = (self.src_unsigned(rs1), self.src_unsigned(rs2));
+                self.write_reg(rd, a.checked_div(b).unwrap_or(u64::MAX));
+            },
+            Op::REM => self.write_reg(rd, rem(self.src(rs1) as i64, self.src(rs2) as i64) as u64),
+            Op::REMU => {
+                let (a, b) = (self.src_unsigned(rs1), self.src_unsigned(rs2));
+                self.write_reg(rd, a.checked_rem(b).unwrap_or(a));
+            },
+            Op::MULW => self.write_reg(rd, sext(self.x[rs1].wrapping_mul(self.x[rs2]), 32)),
+            Op::DIVW => {
+                self.write_reg(rd, div(self.x[rs1] as i32 as i64, self.x[rs2] as i32 as i64) as i32 as u64);
+            },
+            Op::DIVUW => {
+                let (a, b) = (self.x[rs1] as u32, self.x[rs2] as u32);
+                self.write_reg(rd, a.checked_div(b).unwrap_or(u32::MAX) as i32 as u64);
+            },
+            Op::REMW => {
+                self.write_reg(rd, rem(self.x[rs1] as i32 as i64, self.x[rs2] as i32 as i64) as i32 as u64);
+            },
+            Op::REMUW => {
+                let (a, b) = (self.x[rs1] as u32, self.x[rs2] as u32);
+                self.write_reg(rd, a.checked_rem(b).unwrap_or(a) as i32 as u64);
+            },
+            // `A` Extension
+            Op::LRW | Op::SCW | Op::AMOSWAPW | Op::AMOADDW | Op::AMOXORW | Op::AMOANDW | Op::AMOORW
+            | Op::AMOMINW | Op::AMOMAXW | Op::AMOMINUW | Op::AMOMAXUW => {
+                if !self.atomic(bus, inst.op, 4, rd, rs1, rs2)? {
+                    return Ok(());
+                }
+            },
+            Op::LRD | Op::SCD | Op::AMOSWAPD | Op::AMOADDD | Op::AMOXORD | Op::AMOANDD | Op::AMOORD
+            | Op::AMOMIND | Op::AMOMAXD | Op::AMOMINUD | Op::AMOMAXUD => {
+                if !self.atomic(bus, inst.op, 8, rd, rs1, rs2)? {
+                    return Ok(());
+                }
+            },
+            // `F` and `D` Extensions
+            Op::FLW | Op::FSW | Op::FMADDS | Op::FMSUBS | Op::FNMSUBS | Op::FNMADDS | Op::FADDS | Op::FSUBS
+            | Op::FMULS | Op::FDIVS | Op::FSQRTS | Op::FSGNJS | Op::FSGNJNS | Op::FSGNJXS | Op::FMINS
+            | Op::FMAXS | Op::FCVTWS | Op::FCVTWUS | Op::FMVXW | Op::FEQS | Op::FLTS | Op::FLES
+            | Op::FCLASSS | Op::FCVTSW | Op::FCVTSWU | Op::FMVWX | Op::FCVTLS | Op::FCVTLUS | Op::FCVTSL
+            | Op::FCVTSLU | Op::FLD | Op::FSD | Op::FMADDD | Op::FMSUBD | Op::FNMSUBD | Op::FNMADDD
+            | Op::FADDD | Op::FSUBD | Op::FMULD | Op::FDIVD | Op::FSQRTD | Op::FSGNJD | Op::FSGNJND
+            | Op::FSGNJXD | Op::FMIND | Op::FMAXD | Op::FCVTSD | Op::FCVTDS | Op::FEQD | Op::FLTD
+            | Op::FLED | Op::FCLASSD | Op::FCVTWD | Op::FCVTWUD | Op::FCVTDW | Op::FCVTDWU | Op::FCVTLD
+            | Op::FCVTLUD | Op::FMVXD | Op::FCVTDL | Op::FCVTDLU | Op::FMVDX | Op::FLH | Op::FSH | Op::FMADDH
+            | Op::FMSUBH | Op::FNMSUBH | Op::FNMADDH | Op::FADDH | Op::FSUBH | Op::FMULH | Op::FDIVH | Op::FSQRTH
+            | Op::FSGNJH | Op::FSGNJNH | Op::FSGNJXH | Op::FMINH | Op::FMAXH | Op::FCVTSH | Op::FCVTHS
+            | Op::FCVTDH | Op::FCVTHD | Op::FEQH | Op::FLTH | Op::FLEH | Op::FCLASSH | Op::FCVTWH | Op::FCVTWUH
+            | Op::FCVTHW | Op::FCVTHWU | Op::FMVXH | Op::FMVHX | Op::FCVTLH | Op::FCVTLUH | Op::FCVTHL
+            | Op::FCVTHLU => self.float(bus, inst)?,
+            // `Zba` Extension
+            Op::SH1ADD => self.write_reg(rd, (self.x[rs1] << 1).wrapping_add(self.x[rs2])),
+            Op::SH2ADD => self.write_reg(rd, (self.x[rs1] << 2).wrapping_add(self.x[rs2])),
+            Op::SH3ADD => self.write_reg(rd, (self.x[rs1] << 3).wrapping_add(self.x[rs2])),
+            Op::ADDUW => self.write_reg(rd, (self.x[rs1] as u32 as u64).wrapping_add(self.x[rs2])),
+            Op::SH1ADDUW => self.write_reg(rd, ((self.x[rs1] as u32 as u64) << 1).wrapping_add(self.x[rs2])),
+            Op::SH2ADDUW => self.write_reg(rd, ((self.x[rs1] as u32 as u64) << 2).wrapping_add(self.x[rs2])),
+            Op::SH3ADDUW => self.write_reg(rd, ((self.x[rs1] as u32 as u64) << 3).wrapping_add(self.x[rs2])),
+            Op::SLLIUW => self.write_reg(rd, (self.x[rs1] as u32 as u64) << imm),
+            // `Zbb` Extension
+            Op::ANDN => self.write_reg(rd, self.x[rs1] & !self.x[rs2]),
+            Op::ORN => self.write_reg(rd, self.x[rs1] | !self.x[rs2]),
+            Op::XNOR => self.write_reg(rd, !(self.x[rs1] ^ self.x[rs2])),
+            // counting in the XLEN-bit register, whatever is above it.
+            Op::CLZ => {
+                let zeros = self.src_unsigned(rs1).leading_zeros() - (64 - self.xlen());
+                self.write_reg(rd, zeros as u64);
+            },
+            Op::CTZ => self.write_reg(rd, self.src_unsigned(rs1).trailing_zeros().min(self.xlen()) as u64),
+            Op::CPOP => self.write_reg(rd, self.src_unsigned(rs1).count_ones() as u64),
+            Op::MAX => self.write_reg(rd, (self.src(rs1) as i64).max(self.src(rs2) as i64) as u64),
+            Op::MAXU => self.write_reg(rd, self.src(rs1).max(self.src(rs2))),
+            Op::MIN => self.write_reg(rd, (self.src(rs1) as i64).min(self.src(rs2) as i64) as u64),
+            Op::MINU => self.write_reg(rd, self.src(rs1).min(self.src(rs2))),
+            Op::SEXTB => self.write_reg(rd, sext(self.x[rs1], 8)),
+            Op::SEXTH => self.write_reg(rd, sext(self.x[rs1], 16)),
+            Op::ZEXTH => self.write_reg(rd, self.x[rs1] & 0xFFFF),
+            Op::ROL => {
+                let amount = self.shamt(rs2) as u32;
+                self.write_reg(rd, self.rotate_right(rs1, (self.xlen() - amount) % self.xlen()));
+            },
+            Op::ROR => self.write_reg(rd, self.rotate_right(rs1, self.shamt(rs2) as u32)),
+            Op::RORI => self.write_reg(rd, self.rotate_right(rs1, imm as u32)),
+            Op::ORCB => {
+                let value = self.x[rs1];
+                let bytes = (0..64).step_by(8).filter(|i| (value >> i) & 0xFF != 0);
+                self.write_reg(rd, bytes.fold(0, |out, i| out | (0xFF << i)));
+            },
+            Op::REV8 => {
+                let value = if self.xlen() == 32 { (self.x[rs1] as u32).swap_bytes() as u64 } else { self.x[rs1].swap_bytes() };
+                self.write_reg(rd, value);
+            },
+            Op::CLZW => self.write_reg(rd, (self.x[rs1] as u32).leading_zeros() as u64),
+            Op::CTZW => self.write_reg(rd, (self.x[rs1] as u32).trailing_zeros() as u64),
+            Op::CPOPW => self.write_reg(rd, (self.x[rs1] as u32).count_ones() as u64),
+            Op::ROLW => self.write_reg(rd, (self.x[rs1] as u32).rotate_left(self.x[rs2] as u32 & 0x1F) as i32 as u64),
+            Op::RORW => self.write_reg(rd, (self.x[rs1] as u32).rotate_right(self.x[rs2] as u32 & 0x1F) as i32 as u64),
+            Op::RORIW => self.write_reg(rd, (self.x[rs1] as u32).rotate_right(imm as u32) as i32 as u64),
+            // `Zbc` Extension
+            Op::CLMUL => self.write_reg(rd, clmul(self.src_unsigned(rs1), self.src_unsigned(rs2)) as u64),
+            Op::CLMULH => {
+                let product = clmul(self.src_unsigned(rs1), self.src_unsigned(rs2));
+                self.write_reg(rd, (product >> self.xlen()) as u64);
+            },
+            Op::CLMULR => {
+                let product = clmul(self.src_unsigned(rs1), self.src_unsigned(rs2));
+                self.write_reg(rd, (product >> (self.xlen() - 1)) as u64);
+            },
+            // `Zbs` Extension
+            Op::BCLR => self.write_reg(rd, self.x[rs1] & !(1 << self.shamt(rs2))),
+            Op::BCLRI => self.write_reg(rd, self.x[rs1] & !(1 << imm)),
+            Op::BEXT => self.write_reg(rd, (self.x[rs1] >> self.shamt(rs2)) & 1),
+            Op::BEXTI => self.write_reg(rd, (self.x[rs1] >> imm) & 1),
+            Op::BINV => self.write_reg(rd, self.x[rs1] ^ (1 << self.shamt(rs2))),
+            Op::BINVI => self.write_reg(rd, self.x[rs1] ^ (1 << imm)),
+            Op::BSET => self.write_reg(rd, self.x[rs1] | (1 << self.shamt(rs2))),
+            Op::BSETI => self.write_reg(rd, self.x[rs1] | (1 << imm)),
+            // `Zbkb` Extension
+            Op::PACK => {
+                let half = self.xlen() / 2;
+                let low = |value: u64| value & ((1 << half) - 1);
+                self.write_reg(rd, low(self.x[rs1]) | (low(self.x[rs2]) << half));
+            },
+            Op::PACKH => self.write_reg(rd, (self.x[rs1] & 0xFF) | ((self.x[rs2] & 0xFF) << 8)),
+            Op::PACKW => self.write_reg(rd, sext((self.x[rs1] & 0xFFFF) | ((self.x[rs2] & 0xFFFF) << 16), 32)),
+            Op::BREV8 => {
+                let bytes = self.x[rs1].to_le_bytes().map(u8::reverse_bits);
+                self.write_reg(rd, u64::from_le_bytes(bytes));
+            },
+            // RV32 only: the bits of the low half go to the even positions,
+            // and of the high half to the odd ones.
+            Op::ZIP => {
+                let value = self.x[rs1];
+                self.write_reg(rd, (0..32).fold(0, |out, i| out | (((value >> (i / 2 + (i % 2) * 16)) & 1) << i)));
+            },
+            Op::UNZIP => {
+                let value = self.x[rs1];
+                self.write_reg(rd, (0..32).fold(0, |out, i| out | (((value >> i) & 1) << (i / 2 + (i % 2) * 16))));
+            },
+            // `Zknd`, `Zkne`, `Zknh`, `Zksed` and `Zksh`
+            Op::CRYPTO => {
+                let op = CryptoOp::decode(inst.raw, self.xlen()).ok_or(Trap::illegal(inst.raw))?;
+                self.write_reg(rd, op.apply(self.x[rs1], self.x[rs2]));
+            },
+            // `V` Extension
+            Op::VSETVLI | Op::VSETIVLI | Op::VSETVL | Op::VLOAD | Op::VSTORE | Op::VOP => self.vector(bus, inst)?,
+            // `H` Extension
+            Op::HFENCEVVMA | Op::HFENCEGVMA => {
+                if self.virt {
+                    return Err(Trap::virtual_instruction(raw));
+                }
+                if self.privilege == Privilege::User
+                    || (inst.op == Op::HFENCEGVMA && self.privilege == Privilege::Supervisor
+                        && self.csr.mstatus & MSTATUS_TVM != 0) {
+                    return Err(Trap::illegal(raw));
+                }
+                self.flush_fetch_translation();
+            },
+            Op::HLV | Op::HSV => self.hypervisor_access(bus, inst)?,
+        }
+
+        self.pc = next_pc;
+        Ok(())
+    }
+
+    /// Executes a hypervisor load or store, which accesses memory as the
+    /// guest would at the privilege `hstatus.SPVP` selects. Only HS-mode,
+    /// M-mode, and U-mode with `hstatus.HU` set may.
+    fn hypervisor_access(&mut self, bus: &mut Bus, inst: &Decoded) -> Result<(), Trap> {
+        let access = HypervisorAccess::decode(inst.raw).ok_or(Trap::illegal(inst.raw))?;
+        if self.virt {
+            return Err(Trap::virtual_instruction(inst.raw));
+        }
+        if self.privilege == Privilege::User && self.csr.hstatus & HSTATUS_HU == 0 {
+            return Err(Trap::illegal(inst.raw));
+        }
+        // a fault leaves it set for `take_trap` to see the access was a
+        // guest's.
+        self.hypervisor_access = Some(if access.execute { Access::Fetch } else { Access::Load });
+        let addr = self.x[inst.rs1 as usize];
+        if access.store {
+            self.store(bus, addr, access.size, self.x[inst.rs2 as usize])?;
+        } else {
+            let value = self.load(bus, addr, access.size)?;
+            let value = if access.signed { sext(value, access.size as u32 * 8) } else { value };
+            self.write_reg(inst.rd as usize, value);
+        }
+        self.hypervisor_access = None;
+        Ok(())
+    }
+
+    /// Checks that a jump or taken branch goes somewhere instructions can
+    /// be: anywhere 2-byte aligned with `C`, 4-byte aligned without. The
+    /// target wraps at XLEN.
+    fn jump_target(&self, target: u64) -> Result<u64, Trap> {
+        let target = target & self.xlen_mask();
+        if target & !self.pc_mask() & 0b10 != 0 {
+            return Err(Trap::new(Exception::InstructionAddressMisaligned, target));
+        }
+        Ok(target)
+    }
+
+    /// A source register as the current XLEN sees it: at XLEN 32 only its
+    /// low half counts, sign extended. Registers written at XLEN 32 are
+    /// already, but ones left over from a wider `mstatus.UXL` need not be.
+    pub fn src(&self, reg: usize) -> u64 {
+        if self.xlen() == 32 { sext(self.x[reg], 32) } else { self.x[reg] }
+    }
+
+    /// A source register zero extended from XLEN, for unsigned operands.
+    pub fn src_unsigned(&self, reg: usize) -> u64 {
+        self.x[reg] & self.xlen_mask()
+    }
+
+    /// The shift amount in a register: its low 5 bits at XLEN 32, 6 at 64.
+    fn shamt(&self, reg: usize) -> u64 {
+        self.x[reg] & (self.xlen() as u64 - 1)
+    }
+
+    /// Rotates a source register right by `amount` bits within XLEN.
+    fn rotate_right(&self, reg: usize, amount: u32) -> u64 {
+        if self.xlen() == 32 {
+            (self.x[reg] as u32).rotate_right(amount) as u64
+        } else {
+            self.x[reg].rotate_right(amount)
+        }
+    }
+
+    /// Executes an `A` Extension instruction on `size` bytes. Returns
+    /// `false`, having changed nothing but `waiting`, when another hart's
+    /// reservation holds the granule. Words are sign extended, which keeps
+    /// their unsigned order too.
+    fn atomic(&mut self, bus: &mut Bus, op: Op, size: u64, rd: usize, rs1: usize, rs2: usize) -> Result<bool, Trap> {
+        let addr = self.x[rs1] & self.xlen_mask();
+        let extend = |value: u64| if size == 4 { sext(value, 32) } else { value };
+        let load = matches!(op, Op::LRW | Op::LRD);
+        let access = if load { Access::Load } else { Access::Store };
+        if !addr.is_multiple_of(size) {
+            let exception = if load { Exception::LoadAddressMisaligned } else { Exception::StoreAddressMisaligned };
+            return Err(Trap::new(exception, addr));
+        }
+        let paddr = self.translate(bus, addr, access)?;
+        if bus.reservations.held_by_other(self.id, paddr) {
+            self.waiting = true;
+            return Ok(false);
+        }
+
+        if matches!(op, Op::SCW | Op::SCD) {
+            let success = bus.reservations.take(self.id, paddr);
+            if success {
+                let value = self.x[rs2];
+                if !bus.store_from(Some(self.id), paddr, size as usize, value) {
+                    return Err(access.access_fault(addr));
+                }
+                self.note_store(addr, value, size);
+            }
+            self.write_reg(rd, !success as u64);
+            return Ok(true);
+        }
+
+        let old = match bus.load(paddr, size as usize) {
+            Some(value) => extend(value),
+            None => return Err(access.access_fault(addr)),
+        };
+        if let Some(commit) = &mut self.commit {
+            commit.loads.push(addr);
+        }
+        if load {
+            bus.reservations.reserve(self.id, paddr);
+            self.write_reg(rd, old);
+            return Ok(true);
+        }
+        let src = extend(self.x[rs2]);
+        let value = match op {
+            Op::AMOSWAPW | Op::AMOSWAPD => src,
+            Op::AMOADDW | Op::AMOADDD => old.wrapping_add(src),
+            Op::AMOXORW | Op::AMOXORD => old ^ src,
+            Op::AMOANDW | Op::AMOANDD => old & src,
+            Op::AMOORW | Op::AMOORD => old | src,
+            Op::AMOMINW | Op::AMOMIND => (old as i64).min(src as i64) as u64,
+            Op::AMOMAXW | Op::AMOMAXD => (old as i64).max(src as i64) as u64,
+            Op::AMOMINUW | Op::AMOMINUD => old.min(src),
+            _ => old.max(src),
+        };
+        if !bus.store_from(Some(self.id), paddr, size as usize, value) {
+            return Err(access.access_fault(addr));
+        }
+        self.note_store(addr, value, size);
+        self.write_reg(rd, old);
+        Ok(true)
+    }
+
+    /// Built without the `F` Extension: its instructions never decode.
+    #[cfg(not(feature = "f"))]
+    fn float(&mut self, _bus: &mut Bus, inst: &Decoded) -> Result<(), Trap> {
+        Err(Trap::illegal(inst.raw))
+    }
+
+    /// Executes an `F`, `D` or `Zfh` Extension instruction, all of which
+    /// are illegal while `mstatus.FS` is off, or a guest's `vsstatus.FS`.
+    /// Single and half-precision values are kept NaN-boxed, and read as the
+    /// canonical NaN when they are not.
+    #[cfg(feature = "f")]
+    fn float(&mut self, bus: &mut Bus, inst: &Decoded) -> Result<(), Trap> {
+        if !self.fp_enabled() {
+            return Err(Trap::illegal(inst.raw));
+        }
+        let (rd, rs1, rs2) = (inst.rd as usize, inst.rs1 as usize, inst.rs2 as usize);
+        let addr = self.x[rs1].wrapping_add(inst.imm as u64);
+        match inst.op {
+            Op::FLH => {
+                let value = self.load(bus, addr, 2)?;
+                self.write_freg(rd, box_f16(value));
+                return Ok(());
+            },
+            Op::FLW => {
+                let value = self.load(bus, addr, 4)?;
+                self.write_freg(rd, box_f32(value));
+                return Ok(());
+            },
+            Op::FLD => {
+                let value = self.load(bus, addr, 8)?;
+                self.write_freg(rd, value);
+                return Ok(());
+            },
+            Op::FSH => return self.store(bus, addr, 2, self.f[rs2]),
+            Op::FSW => return self.store(bus, addr, 4, self.f[rs2]),
+            Op::FSD => return self.store(bus, addr, 8, self.f[rs2]),
+            Op::FMVXH => {
+                self.write_reg(rd, sext(self.f[rs1], 16));
+                return Ok(());
+            },
+            Op::FMVXW => {
+                self.write_reg(rd, sext(self.f[rs1], 32));
+                return Ok(());
+            },
+            Op::FMVXD => {
+                self.write_reg(rd, self.f[rs1]);
+                return Ok(());
+            },
+            Op::FMVHX => {
+                self.write_freg(rd, box_f16(self.x[rs1] & 0xFFFF));
+                return Ok(());
+            },
+            Op::FMVWX => {
+                self.write_freg(rd, box_f32(self.x[rs1] & 0xFFFF_FFFF));
+                return Ok(());
+            },
+            Op::FMVDX => {
+                self.write_freg(rd, self.x[rs1]);
+                return Ok(());
+            },
+            _ => {},
+        }
+
+        // bits 26:25 pick the format everywhere else, the destination's for
+        // the conversions between formats, which take the source's in `rs2`.
+        let format = |bits: u32| match bits {
+            0b00 => F32,
+            0b01 => F64,
+            _ => F16,
+        };
+        let fmt = format((inst.raw >> 25) & 0b11);
+        let src = match inst.op {
+            Op::FCVTSD | Op::FCVTDS | Op::FCVTSH | Op::FCVTHS | Op::FCVTDH | Op::FCVTHD => format(rs2 as u32),
+            _ => fmt,
+        };
+        let read = |value: u64, fmt: Format| match fmt {
+            F32 => unbox_f32(value),
+            F16 => unbox_f16(value),
+            _ => value,
+        };
+        let a = read(self.f[rs1], src);
+        let b = read(self.f[rs2], fmt);
+        let c = read(self.f[inst.rs3()], fmt);
+        let rounded = !matches!(
+            inst.op,
+            Op::FSGNJS | Op::FSGNJNS | Op::FSGNJXS | Op::FMINS | Op::FMAXS | Op::FEQS | Op::FLTS | Op::FLES
+                | Op::FCLASSS | Op::FSGNJD | Op::FSGNJND | Op::FSGNJXD | Op::FMIND | Op::FMAXD | Op::FEQD
+                | Op::FLTD | Op::FLED | Op::FCLASSD | Op::FSGNJH | Op::FSGNJNH | Op::FSGNJXH | Op::FMINH
+                | Op::FMAXH | Op::FEQH | Op::FLTH | Op::FLEH | Op::FCLASSH
+        );
+        let rounding = match inst.rm() {
+            _ if !rounded => Some(Rounding::NearestEven),
+            0b111 => Rounding::from_bits(self.csr.fcsr >> 5),
+            rm => Rounding::from_bits(rm),
+        };
+        let mut env = Env::new(rounding.ok_or(Trap::illegal(inst.raw))?);
+        let x = self.x[rs1];
+
+        let value = match inst.op {
+            Op::FMADDS | Op::FMADDD | Op::FMADDH => env.fma(fmt, a, b, c, false, false),
+            Op::FMSUBS | Op::FMSUBD | Op::FMSUBH => env.fma(fmt, a, b, c, false, true),
+            Op::FNMSUBS | Op::FNMSUBD | Op::FNMSUBH => env.fma(fmt, a, b, c, true, false),
+            Op::FNMADDS | Op::FNMADDD | Op::FNMADDH => env.fma(fmt, a, b, c, true, true),
+            Op::FADDS | Op::FADDD | Op::FADDH => env.add(fmt, a, b),
+            Op::FSUBS | Op::FSUBD | Op::FSUBH => env.sub(fmt, a, b),
+            Op::FMULS | Op::FMULD | Op::FMULH => env.mul(fmt, a, b),
+            Op::FDIVS | Op::FDIVD | Op::FDIVH => env.div(fmt, a, b),
+            Op::FSQRTS | Op::FSQRTD | Op::FSQRTH => env.sqrt(fmt, a),
+            Op::FSGNJS | Op::FSGNJD | Op::FSGNJH => inject_sign(fmt, a, b, false, false),
+            Op::FSGNJNS | Op::FSGNJND | Op::FSGNJNH => inject_sign(fmt, a, b, true, false),
+            Op::FSGNJXS | Op::FSGNJXD | Op::FSGNJXH => inject_sign(fmt, a, b, false, true),
+            Op::FMINS | Op::FMIND | Op::FMINH => env.min_max(fmt, a, b, false),
+            Op::FMAXS | Op::FMAXD | Op::FMAXH => env.min_max(fmt, a, b, true),
+            Op::FCVTSD | Op::FCVTDS | Op::FCVTSH | Op::FCVTHS | Op::FCVTDH | Op::FCVTHD => env.convert(src, fmt, a),
+            Op::FCVTSW | Op::FCVTDW | Op::FCVTHW => env.from_int(fmt, x, true, 32),
+            Op::FCVTSWU | Op::FCVTDWU | Op::FCVTHWU => env.from_int(fmt, x, false, 32),
+            Op::FCVTSL | Op::FCVTDL | Op::FCVTHL => env.from_int(fmt, x, true, 64),
+            Op::FCVTSLU | Op::FCVTDLU | Op::FCVTHLU => env.from_int(fmt, x, false, 64),
+            _ => {
+                let value = match inst.op {
+                    Op::FCVTWS | Op::FCVTWD | Op::FCVTWH => env.to_int(fmt, a, true, 32),
+                    Op::FCVTWUS | Op::FCVTWUD | Op::FCVTWUH => env.to_int(fmt, a, false, 32),
+                    Op::FCVTLS | Op::FCVTLD | Op::FCVTLH => env.to_int(fmt, a, true, 64),
+                    Op::FCVTLUS | Op::FCVTLUD | Op::FCVTLUH => env.to_int(fmt, a, false, 64),
+                    Op::FEQS | Op::FEQD | Op::FEQH => env.eq(fmt, a, b) as u64,
+                    Op::FLTS | Op::FLTD | Op::FLTH => env.lt(fmt, a, b) as u64,
+                    Op::FLES | Op::FLED | Op::FLEH => env.le(fmt, a, b) as u64,
+                    _ => classify(fmt, a),
+                };
+                self.write_reg(rd, value);
+                self.raise_fp_flags(env.flags);
+                return Ok(());
+            },
+        };
+        let value = match fmt {
+            F32 => box_f32(value),
+            F16 => box_f16(value),
+            _ => value,
+        };
+        self.write_freg(rd, value);
+        self.raise_fp_flags(env.flags);
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "m"))]
+mod tests {
+    use super::*;
+    use crate::Isa::Isa;
+
+    const MIN: u64 = i64::MIN as u64;
+    const MAX: u64 = i64::MAX as u64;
+    const MIN32: u64 = i32::MIN as i64 as u64;
+    const ONES: u64 = u64::MAX;
+
+    /// Values around every boundary the `M` instructions care about.
+    const EDGES: [u64; 20] = [
+        0, 1, 2, 3, 7, ONES, ONES - 1, MIN, MIN + 1, MAX, MAX - 1,
+        0x7FFF_FFFF, 0x8000_0000, 0xFFFF_FFFF, 0x1_0000_0000, MIN32, 0xFFFF_FFFF_7FFF_FFFF,
+        0x1234_5678_9ABC_DEF0, 0xDEAD_BEEF_0000_0001, 0x0000_0001_FFFF_FFFF,
+    ];
+
+    #[derive(Clone, Copy, Debug)]
+    enum M {
+        Mul, Mulh, Mulhsu, Mulhu, Div, Divu, Rem, Remu,
+        Mulw, Divw, Divuw, Remw, Remuw,
+    }
+
+    /// Runs `op x3, x1, x2` with `x1 = a` and `x2 = b`, returning `x3`.
+    fn run(op: M, a: u64, b: u64) -> u64 {
+        let (funct3, opcode) = match op {
+            M::Mul => (0b000, 0b0110011),
+            M::Mulh => (0b001, 0b0110011),
+            M::Mulhsu => (0b010, 0b0110011),
+            M::Mulhu => (0b011, 0b0110011),
+            M::Div => (0b100, 0b0110011),
+            M::Divu => (0b101, 0b0110011),
+            M::Rem => (0b110, 0b0110011),
+            M::Remu => (0b111, 0b0110011),
+            M::Mulw => (0b000, 0b0111011),
+            M::Divw => (0b100, 0b0111011),
+            M::Divuw => (0b101, 0b0111011),
+            M::Remw => (0b110, 0b0111011),
+            M::Remuw => (0b111, 0b0111011),
+        };
+        let raw = (0b0000001 << 25) | (2 << 20) | (1 << 15) | (funct3 << 12) | (3 << 7) | opcode;
+        let inst = Decoded::decode(raw, &Isa::default()).expect("M instructions decode");
+        let mut hart = Hart::new(0);
+        let mut bus = Bus::new(4096);
+        hart.x[1] = a;
+        hart.x[2] = b;
+        hart.execute(&mut bus, &inst).expect("M instructions do not trap");
+        assert_eq!(hart.pc, 4);
+        hart.x[3]
+    }
+
+    fn sext32(value: u64) -> u64 {
+        value as i32 as i64 as u64
+    }
+
+    #[test]
+    fn division_by_zero() {
+        for &a in EDGES.iter() {
+            assert_eq!(run(M::Div, a, 0), ONES, "div {:#x}", a);
+            assert_eq!(run(M::Divu, a, 0), ONES, "divu {:#x}", a);
+            assert_eq!(run(M::Rem, a, 0), a, "rem {:#x}", a);
+            assert_eq!(run(M::Remu, a, 0), a, "remu {:#x}", a);
+            assert_eq!(run(M::Divw, a, 0), ONES, "divw {:#x}", a);
+            assert_eq!(run(M::Divuw, a, 0), ONES, "divuw {:#x}", a);
+            assert_eq!(run(M::Remw, a, 0), sext32(a), "remw {:#x}", a);
+            assert_eq!(run(M::Remuw, a, 0), sext32(a), "remuw {:#x}", a);
+            // only the low 32 bits of the divisor count in the W forms.
+            assert_eq!(run(M::Divw, a, 0xFFFF_FFFF_0000_0000), ONES, "divw {:#x}", a);
+            assert_eq!(run(M::Remuw, a, 0x1_0000_0000), sext32(a), "remuw {:#x}", a);
+        }
+    }
+
+    #[test]
+    fn signed_overflow() {
+        assert_eq!(run(M::Div, MIN, ONES), MIN);
+        assert_eq!(run(M::Rem, MIN, ONES), 0);
+        assert_eq!(run(M::Divw, MIN32, ONES), MIN32);
+        assert_eq!(run(M::Remw, MIN32, ONES), 0);
+        // upper halves are ignored, so these overflow too.
+        assert_eq!(run(M::Divw, 0x8000_0000, 0xFFFF_FFFF), MIN32);
+        assert_eq!(run(M::Remw, 0x1234_5678_8000_0000, 0x0000_0001_FFFF_FFFF), 0);
+        // unsigned division has no overflow case.
+        assert_eq!(run(M::Divu, MIN, ONES), 0);
+        assert_eq!(run(M::Remu, MIN, ONES), MIN);
+    }
+
+    #[test]
+    fn division_rounds_towards_zero() {
+        assert_eq!(run(M::Div, (-7i64) as u64, 2), (-3i64) as u64);
+        assert_eq!(run(M::Rem, (-7i64) as u64, 2), ONES);
+        assert_eq!(run(M::Div, 7, (-2i64) as u64), (-3i64) as u64);
+        assert_eq!(run(M::Rem, 7, (-2i64) as u64), 1);
+        assert_eq!(run(M::Divw, (-7i64) as u64, 2), (-3i64) as u64);
+        assert_eq!(run(M::Remw, 7, (-2i64) as u64), 1);
+    }
+
+    #[test]
+    fn division_identity() {
+        for &a in EDGES.iter() {
+            for &b in EDGES.iter().filter(|&&b| b != 0) {
+                let (q, r) = (run(M::Divu, a, b), run(M::Remu, a, b));
+                assert_eq!(q as u128 * b as u128 + r as u128, a as u128, "divu {:#x} {:#x}", a, b);
+                assert!(r < b);
+
+                let (q, r) = (run(M::Div, a, b) as i64, run(M::Rem, a, b) as i64);
+                if !(a == MIN && b == ONES) {
+                    assert_eq!(q as i128 * b as i64 as i128 + r as i128, a as i64 as i128, "div {:#x} {:#x}", a, b);
+                    assert!(r == 0 || (r < 0) == ((a as i64) < 0), "rem sign {:#x} {:#x}", a, b);
+                    assert!((r as i128).abs() < (b as i64 as i128).abs());
+                }
+
+                let (a32, b32) = (a as u32, b as u32);
+                if let (Some(q), Some(r)) = (a32.checked_div(b32), a32.checked_rem(b32)) {
+                    assert_eq!(run(M::Divuw, a, b), sext32(q as u64), "divuw {:#x} {:#x}", a, b);
+                    assert_eq!(run(M::Remuw, a, b), sext32(r as u64), "remuw {:#x} {:#x}", a, b);
+                    let (a32, b32) = (a32 as i32, b32 as i32);
+                    if !(a32 == i32::MIN && b32 == -1) {
+                        assert_eq!(run(M::Divw, a, b), (a32 / b32) as i64 as u64, "divw {:#x} {:#x}", a, b);
+                        assert_eq!(run(M::Remw, a, b), (a32 % b32) as i64 as u64, "remw {:#x} {:#x}", a, b);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn multiplication() {
+        for &a in EDGES.iter() {
+            for &b in EDGES.iter() {
+                let signed = a as i64 as i128 * b as i64 as i128;
+                let unsigned = a as u128 * b as u128;
+                let mixed = a as i64 as i128 * b as i128;
+                assert_eq!(run(M::Mul, a, b), unsigned as u64, "mul {:#x} {:#x}", a, b);
+                assert_eq!(run(M::Mulh, a, b), (signed >> 64) as u64, "mulh {:#x} {:#x}", a, b);
+                assert_eq!(run(M::Mulhu, a, b), (unsigned >> 64) as u64, "mulhu {:#x} {:#x}", a, b);
+                assert_eq!(run(M::Mulhsu, a, b), (mixed >> 64) as u64, "mulhsu {:#x} {:#x}", a, b);
+                assert_eq!(run(M::Mulw, a, b), sext32((a as u32).wrapping_mul(b as u32) as u64), "mulw {:#x} {:#x}", a, b);
+            }
+        }
+        assert_eq!(run(M::Mulh, MIN, MIN), 1 << 62);
+        assert_eq!(run(M::Mulhu, ONES, ONES), ONES - 1);
+        assert_eq!(run(M::Mulhsu, ONES, ONES), ONES);
+        assert_eq!(run(M::Mulhsu, 1, ONES), 0);
+        assert_eq!(run(M::Mulw, 0x7FFF_FFFF, 2), ONES - 1);
+        assert_eq!(run(M::Mulw, 0x1_0000_0001, 0x1_0000_0001), 1);
+    }
+
+    #[test]
+    fn results_to_x0_are_dropped() {
+        // mul x0, x1, x2
+        let inst = Decoded::decode(0x0220_8033, &Isa::default()).unwrap();
+        let mut hart = Hart::new(0);
+        let mut bus = Bus::new(4096);
+        hart.x[1] = 6;
+        hart.x[2] = 7;
+        hart.execute(&mut bus, &inst).unwrap();
+        assert_eq!(hart.x[0], 0);
+    }
+}
+
+#[cfg(test)]
+mod bitmanip_tests {
+    use super::*;
+    use crate::Disassembler::disassemble;
+    use crate::Isa::Isa;
+
+    const CLMUL: u32 = 0x0A20_91B3; // clmul gp, ra, sp
+    const CLMULH: u32 = 0x0A20_B1B3; // clmulh gp, ra, sp
+    const CLMULR: u32 = 0x0A20_A1B3; // clmulr gp, ra, sp
+    const REV8: u32 = 0x6B80_D193; // rev8 gp, ra
+    const REV8_RV32: u32 = 0x6980_D193; // rev8 gp, ra
+    const ORCB: u32 = 0x2870_D193; // orc.b gp, ra
+    const RORI: u32 = 0x60C0_D193; // rori gp, ra, 12
+    const RORIW: u32 = 0x60C0_D19B; // roriw gp, ra, 12
+    const SH1ADDUW: u32 = 0x2020_A1BB; // sh1add.uw gp, ra, sp
+    const SH2ADDUW: u32 = 0x2020_C1BB; // sh2add.uw gp, ra, sp
+    const SH3ADDUW: u32 = 0x2020_E1BB; // sh3add.uw gp, ra, sp
+    const SLLIUW: u32 = 0x0840_919B; // slli.uw gp, ra, 4
+    const ADDUW: u32 = 0x0820_81BB; // add.uw gp, ra, sp
+
+    /// Runs `raw`, with `rd = x3`, `rs1 = x1` and `rs2 = x2`, on a hart of
+    /// `xlen` bits, returning `x3`.
+    fn run(raw: u32, xlen: u32, a: u64, b: u64) -> u64 {
+        let isa = Isa::default().with_xlen(xlen);
+        let inst = Decoded::decode(raw, &isa).expect("bitmanip instructions decode");
+        let mut hart = Hart::new(0);
+        let mut bus = Bus::new(4096);
+        hart.set_isa(isa);
+        hart.write_reg(1, a);
+        hart.write_reg(2, b);
+        hart.execute(&mut bus, &inst).expect("bitmanip instructions do not trap");
+        hart.x[3]
+    }
+
+    #[test]
+    fn carry_less_multiply() {
+        let a = 0x8000_0000_0000_0001;
+        assert_eq!(run(CLMUL, 64, a, 3), 0x8000_0000_0000_0003);
+        assert_eq!(run(CLMULH, 64, a, 3), 1);
+        assert_eq!(run(CLMULR, 64, a, 3), 3);
+        assert_eq!(run(CLMUL, 64, 0b1011, 0b1101), 0b111_1111);
+        // on RV32 the product is of the low words, its halves sign extended.
+        assert_eq!(run(CLMUL, 32, 0x8000_0001, 3), 0xFFFF_FFFF_8000_0003);
+        assert_eq!(run(CLMULH, 32, 0x8000_0001, 3), 1);
+    }
+
+    #[test]
+    fn byte_operations() {
+        assert_eq!(run(REV8, 64, 0x0102_0304_0506_0708, 0), 0x0807_0605_0403_0201);
+        assert_eq!(run(REV8_RV32, 32, 0x1122_3380, 0), 0xFFFF_FFFF_8033_2211);
+        assert_eq!(run(ORCB, 64, 0x0001_0000_FF00_0010, 0), 0x00FF_0000_FF00_00FF);
+        assert_eq!(run(ORCB, 64, 0, 0), 0);
+    }
+
+    #[test]
+    fn rotates() {
+        assert_eq!(run(RORI, 64, 0xABC, 0), 0xABC0_0000_0000_0000);
+        // the W form and RV32 rotate the low word only.
+        assert_eq!(run(RORIW, 64, 0xFFFF_0000_0000_0ABC, 0), 0xFFFF_FFFF_ABC0_0000);
+        assert_eq!(run(RORI, 32, 0xABC, 0), 0xFFFF_FFFF_ABC0_0000);
+        assert_eq!(run(RORI, 32, 0x1000, 0), 1);
+        // shift amounts from 32 are reserved on RV32.
+        assert!(Decoded::decode(RORI | 32 << 20, &Isa::default().with_xlen(32)).is_none());
+    }
+
+    #[test]
+    fn unsigned_word_addressing() {
+        let a = 0xFFFF_FFFF_8000_0001;
+        assert_eq!(run(ADDUW, 64, a, 0x10), 0x8000_0011);
+        assert_eq!(run(SH1ADDUW, 64, a, 0x10), 0x1_0000_0012);
+        assert_eq!(run(SH2ADDUW, 64, a, 0x10), 0x2_0000_0014);
+        assert_eq!(run(SH3ADDUW, 64, a, 0x10), 0x4_0000_0018);
+        assert_eq!(run(SLLIUW, 64, a, 0), 0x8_0000_0010);
+    }
+
+    #[test]
+    fn disassembly() {
+        for (raw, xlen, text) in [
+            (CLMULR, 64, "clmulr gp, ra, sp"),
+            (REV8, 64, "rev8 gp, ra"),
+            (REV8_RV32, 32, "rev8 gp, ra"),
+            (ORCB, 64, "orc.b gp, ra"),
+            (RORIW, 64, "roriw gp, ra, 12"),
+            (SH3ADDUW, 64, "sh3add.uw gp, ra, sp"),
+            (SLLIUW, 64, "slli.uw gp, ra, 4"),
+        ] {
+            // the operands start at a fixed column.
+            let line = disassemble(raw, xlen);
+            assert_eq!(line.split_whitespace().collect::<Vec<_>>().join(" "), text, "{:#010x}", raw);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "f"))]
+mod zfh_tests {
+    use super::*;
+    use crate::Isa::Isa;
+    use crate::Memory::RAM_BASE;
+
+    const FLH: u32 = 0x0000_9187; // flh ft3, 0(ra)
+    const FSH: u32 = 0x0030_9427; // fsh ft3, 8(ra)
+    const FCVT_S_H: u32 = 0x4020_81D3; // fcvt.s.h ft3, ft1
+    const FCVT_H_S: u32 = 0x4400_F1D3; // fcvt.h.s ft3, ft1
+    const FCVT_H_S_RNE: u32 = 0x4400_81D3; // fcvt.h.s ft3, ft1, rne
+    const FCVT_D_H: u32 = 0x4220_81D3; // fcvt.d.h ft3, ft1
+    const FCVT_H_D: u32 = 0x4410_F1D3; // fcvt.h.d ft3, ft1
+    const FCVT_H_W: u32 = 0xD400_F1D3; // fcvt.h.w ft3, ra
+    const FCVT_H_L: u32 = 0xD420_F1D3; // fcvt.h.l ft3, ra
+    const FCVT_W_H: u32 = 0xC400_91D3; // fcvt.w.h gp, ft1, rtz
+    const FCVT_WU_H: u32 = 0xC410_91D3; // fcvt.wu.h gp, ft1, rtz
+    const FCVT_L_H: u32 = 0xC420_91D3; // fcvt.l.h gp, ft1, rtz
+    const FMV_X_H: u32 = 0xE400_81D3; // fmv.x.h gp, ft1
+    const FMV_H_X: u32 = 0xF400_81D3; // fmv.h.x ft3, ra
+    const FADD_H: u32 = 0x0420_F1D3; // fadd.h ft3, ft1, ft2
+
+    const NX: u64 = 1;
+    const OF: u64 = 1 << 2;
+    const NV: u64 = 1 << 4;
+
+    /// A hart with the floating point unit on and `ra` pointing at RAM.
+    fn hart(isa: Isa) -> Hart {
+        let mut hart = Hart::new(0);
+        hart.set_isa(isa);
+        hart.csr.mstatus |= MSTATUS_FS;
+        hart.x[1] = RAM_BASE;
+        hart
+    }
+
+    fn execute(hart: &mut Hart, bus: &mut Bus, raw: u32) -> Result<(), Trap> {
+        let inst = Decoded::decode(raw, &hart.decode_isa()).expect("Zfh instructions decode");
+        hart.execute(bus, &inst)
+    }
+
+    /// Runs `raw` with `ft1 = a` and `ra = x` on a hart with everything.
+    fn ran(raw: u32, a: u64, x: u64) -> Hart {
+        let mut hart = hart(Isa::default());
+        let mut bus = Bus::new(4096);
+        hart.f[1] = a;
+        hart.x[1] = x;
+        execute(&mut hart, &mut bus, raw).expect("Zfh instructions do not trap");
+        hart
+    }
+
+    /// `ft3` and `gp` after `ran`.
+    fn run(raw: u32, a: u64, x: u64) -> (u64, u64) {
+        let hart = ran(raw, a, x);
+        (hart.f[3], hart.x[3])
+    }
+
+    fn fflags(raw: u32, a: u64, x: u64) -> u64 {
+        ran(raw, a, x).csr.fcsr & 0x1F
+    }
+
+    #[test]
+    fn loads_and_stores_nan_box() {
+        let mut hart = hart(Isa::default());
+        let mut bus = Bus::new(4096);
+        assert!(bus.store(RAM_BASE, 4, 0xAAAA_BC00));
+        assert!(bus.store(RAM_BASE + 8, 4, 0x5555_5555));
+        execute(&mut hart, &mut bus, FLH).unwrap();
+        assert_eq!(hart.f[3], 0xFFFF_FFFF_FFFF_BC00);
+        // only the low half of the register is stored, boxed or not.
+        execute(&mut hart, &mut bus, FSH).unwrap();
+        assert_eq!(bus.load(RAM_BASE + 8, 4), Some(0x5555_BC00));
+        hart.f[3] = 0x1234_3C00;
+        execute(&mut hart, &mut bus, FSH).unwrap();
+        assert_eq!(bus.load(RAM_BASE + 8, 4), Some(0x5555_3C00));
+    }
+
+    #[test]
+    fn moves_nan_box() {
+        assert_eq!(run(FMV_H_X, 0, 0x1234_3C00).0, 0xFFFF_FFFF_FFFF_3C00);
+        // the bits move as they are, sign extended, boxed or not.
+        assert_eq!(run(FMV_X_H, 0xFFFF_FFFF_FFFF_BC00, 0).1, 0xFFFF_FFFF_FFFF_BC00);
+        assert_eq!(run(FMV_X_H, 0x3C00, 0).1, 0x3C00);
+    }
+
+    #[test]
+    fn improperly_boxed_inputs_are_nan() {
+        // 1.0 without its box, and 1.0 in single precision.
+        assert_eq!(run(FCVT_S_H, 0x3C00, 0).0, 0xFFFF_FFFF_7FC0_0000);
+        assert_eq!(run(FCVT_S_H, 0xFFFF_FFFF_3F80_0000, 0).0, 0xFFFF_FFFF_7FC0_0000);
+        assert_eq!(run(FCVT_S_H, 0xFFFF_FFFF_FFFF_3C00, 0).0, 0xFFFF_FFFF_3F80_0000);
+        assert_eq!(run(FCVT_W_H, 0x3C00, 0).1, i32::MAX as u64);
+    }
+
+    #[test]
+    fn conversions_between_formats() {
+        // 1.5 from single precision and back.
+        let half = run(FCVT_H_S, 0xFFFF_FFFF_3FC0_0000, 0).0;
+        assert_eq!(half, 0xFFFF_FFFF_FFFF_3E00);
+        assert_eq!(run(FCVT_S_H, half, 0).0, 0xFFFF_FFFF_3FC0_0000);
+        // 1 + 2^-11 is halfway between two halves, and rounds to even.
+        assert_eq!(run(FCVT_H_S_RNE, 0xFFFF_FFFF_3F80_1000, 0).0, 0xFFFF_FFFF_FFFF_3C00);
+        assert_eq!(fflags(FCVT_H_S_RNE, 0xFFFF_FFFF_3F80_1000, 0), NX);
+        // too large for half precision.
+        assert_eq!(run(FCVT_H_S, 0xFFFF_FFFF_4780_0000, 0).0, 0xFFFF_FFFF_FFFF_7C00);
+        assert_eq!(fflags(FCVT_H_S, 0xFFFF_FFFF_4780_0000, 0), OF | NX);
+    }
+
+    #[cfg(feature = "d")]
+    #[test]
+    fn conversions_with_double() {
+        let double = run(FCVT_D_H, 0xFFFF_FFFF_FFFF_3E00, 0).0;
+        assert_eq!(double, 0x3FF8_0000_0000_0000);
+        assert_eq!(run(FCVT_H_D, double, 0).0, 0xFFFF_FFFF_FFFF_3E00);
+        // NaNs come out canonical.
+        assert_eq!(run(FCVT_D_H, 0xFFFF_FFFF_FFFF_7C01, 0).0, 0x7FF8_0000_0000_0000);
+        assert_eq!(fflags(FCVT_D_H, 0xFFFF_FFFF_FFFF_7C01, 0), NV);
+    }
+
+    #[test]
+    fn conversions_with_integers() {
+        let half = run(FCVT_H_W, 0, -3i64 as u64).0;
+        assert_eq!(half, 0xFFFF_FFFF_FFFF_C200);
+        assert_eq!(run(FCVT_W_H, half, 0).1, -3i64 as u64);
+        assert_eq!(run(FCVT_L_H, half, 0).1, -3i64 as u64);
+        assert_eq!(run(FCVT_WU_H, half, 0).1, 0);
+        assert_eq!(fflags(FCVT_WU_H, half, 0), NV);
+        // 65504 is the largest half, and anything much larger overflows.
+        let max = run(FCVT_H_L, 0, 65504).0;
+        assert_eq!(max, 0xFFFF_FFFF_FFFF_7BFF);
+        assert_eq!(run(FCVT_L_H, max, 0).1, 65504);
+        assert_eq!(run(FCVT_H_L, 0, 1 << 40).0, 0xFFFF_FFFF_FFFF_7C00);
+        assert_eq!(fflags(FCVT_H_L, 0, 1 << 40), OF | NX);
+    }
+
+    #[test]
+    fn zfhmin_has_no_arithmetic() {
+        let isa = match Isa::parse("rv64imaf_zfhmin") {
+            Ok(isa) => isa,
+            // left out of this build.
+            Err(_) => return,
+        };
+        for raw in [FLH, FSH, FCVT_S_H, FCVT_H_S, FMV_X_H, FMV_H_X] {
+            assert!(Decoded::decode(raw, &isa).is_some(), "{:#010x}", raw);
+        }
+        for raw in [FADD_H, FCVT_H_W, FCVT_H_L, FCVT_W_H, FCVT_L_H] {
+            assert!(Decoded::decode(raw, &isa).is_none(), "{:#010x}", raw);
+        }
+        // the conversions with double precision need `D` as well.
+        assert!(Decoded::decode(FCVT_D_H, &isa).is_none());
+        // and without either, none of them decode.
+        let isa = Isa::parse("rv64imaf").unwrap();
+        assert!(Decoded::decode(FLH, &isa).is_none());
+        assert!(Decoded::decode(FCVT_H_S, &isa).is_none());
+
+        let mut hart = hart(Isa::parse("rv64imaf_zfhmin").unwrap());
+        let mut bus = Bus::new(4096);
+        hart.f[1] = 0xFFFF_FFFF_3FC0_0000;
+        execute(&mut hart, &mut bus, FCVT_H_S).unwrap();
+        assert_eq!(hart.f[3], 0xFFFF_FFFF_FFFF_3E00);
+    }
+
+    #[test]
+    fn off_with_mstatus_fs() {
+        let mut hart = hart(Isa::default());
+        let mut bus = Bus::new(4096);
+        hart.csr.mstatus &= !MSTATUS_FS;
+        assert_eq!(execute(&mut hart, &mut bus, FLH), Err(Trap::illegal(FLH)));
+        assert_eq!(execute(&mut hart, &mut bus, FCVT_H_S), Err(Trap::illegal(FCVT_H_S)));
+    }
+}
+
+#[cfg(test)]
+mod rv32_tests {
+    use super::*;
+    use crate::Elf::ElfFile;
+    use crate::Emulator::Machine;
+    use crate::Isa::Isa;
+    use crate::Memory::RAM_BASE;
+
+    const ADD: u32 = 0x0020_81B3; // add gp, ra, sp
+    const SLL: u32 = 0x0020_91B3; // sll gp, ra, sp
+    const SRA: u32 = 0x4020_D1B3; // sra gp, ra, sp
+    const SRAI: u32 = 0x41F0_D193; // srai gp, ra, 31
+    const SLLI_32: u32 = 0x0200_9193; // slli gp, ra, 32
+    const LD: u32 = 0x0000_B183; // ld gp, 0(ra)
+    const LWU: u32 = 0x0000_E183; // lwu gp, 0(ra)
+    const SD: u32 = 0x0020_B023; // sd sp, 0(ra)
+    const ADDIW: u32 = 0x0010_819B; // addiw gp, ra, 1
+    const ADDW: u32 = 0x0020_81BB; // addw gp, ra, sp
+
+    /// Runs `raw`, with `rd = x3`, `rs1 = x1` and `rs2 = x2`, on an RV32
+    /// hart, returning `x3`.
+    fn run(raw: u32, a: u64, b: u64) -> u64 {
+        let isa = Isa::default().with_xlen(32);
+        let inst = Decoded::decode(raw, &isa).expect("RV32I instructions decode");
+        let mut hart = Hart::new(0);
+        let mut bus = Bus::new(4096);
+        hart.set_isa(isa);
+        hart.write_reg(1, a);
+        hart.write_reg(2, b);
+        hart.execute(&mut bus, &inst).expect("RV32I instructions do not trap");
+        hart.x[3]
+    }
+
+    #[test]
+    fn results_wrap_and_sign_extend() {
+        // registers hold 32-bit values sign extended to 64 bits.
+        assert_eq!(run(ADD, 0x7FFF_FFFF, 1), 0xFFFF_FFFF_8000_0000);
+        assert_eq!(run(ADD, 0xFFFF_FFFF, 1), 0);
+        assert_eq!(run(SLL, 1, 31), 0xFFFF_FFFF_8000_0000);
+        // only the low five bits of rs2 count.
+        assert_eq!(run(SLL, 1, 33), 2);
+        assert_eq!(run(SRA, 0x8000_0000, 4), 0xFFFF_FFFF_F800_0000);
+        assert_eq!(run(SRA, 0x8000_0000, 36), 0xFFFF_FFFF_F800_0000);
+        assert_eq!(run(SRAI, 0x8000_0000, 0), u64::MAX);
+    }
+
+    #[test]
+    fn rv64_only_encodings_are_rejected() {
+        let rv32 = Isa::default().with_xlen(32);
+        for raw in [LD, LWU, SD, ADDIW, ADDW, SLLI_32] {
+            assert!(Decoded::decode(raw, &Isa::default()).is_some(), "{:#010x}", raw);
+            assert!(!rv32.allows(raw), "{:#010x}", raw);
+            assert!(Decoded::decode(raw, &rv32).is_none(), "{:#010x}", raw);
+        }
+    }
+
+    #[cfg(feature = "c")]
+    #[test]
+    fn c_jal_replaces_c_addiw() {
+        // addiw ra, ra, 1 on RV64; jal 96 on RV32.
+        const RAW: u32 = 0x2085;
+        let mut bus = Bus::new(4096);
+
+        let mut hart = Hart::new(0);
+        hart.pc = RAM_BASE;
+        hart.x[1] = 0x7FFF_FFFF;
+        hart.execute(&mut bus, &Decoded::decode(RAW, &Isa::default()).unwrap()).unwrap();
+        assert_eq!((hart.x[1], hart.pc), (0xFFFF_FFFF_8000_0000, RAM_BASE + 2));
+
+        let isa = Isa::default().with_xlen(32);
+        let mut hart = Hart::new(0);
+        hart.set_isa(isa);
+        hart.pc = RAM_BASE;
+        hart.execute(&mut bus, &Decoded::decode(RAW, &isa).unwrap()).unwrap();
+        assert_eq!((hart.x[1], hart.pc), (sext(RAM_BASE + 2, 32), RAM_BASE + 96));
+    }
+
+    /// A minimal ELF32 executable with one segment at `RAM_BASE` holding
+    /// `code`, followed by `bss` bytes of zeros.
+    fn elf32(code: &[u32], bss: u32) -> Vec<u8> {
+        const EHSIZE: u32 = 52;
+        const PHENTSIZE: u32 = 32;
+        let filesz = code.len() as u32 * 4;
+        let mut elf = vec![0x7F, b'E', b'L', b'F', 1, 1, 1];
+        elf.resize(16, 0);
+        for half in [2u16, 243] {
+            elf.extend(half.to_le_bytes()); // e_type, e_machine
+        }
+        for word in [1, RAM_BASE as u32, EHSIZE, 0, 0] {
+            elf.extend(word.to_le_bytes()); // e_version to e_flags
+        }
+        for half in [EHSIZE as u16, PHENTSIZE as u16, 1, 40, 0, 0] {
+            elf.extend(half.to_le_bytes()); // e_ehsize to e_shstrndx
+        }
+        let offset = EHSIZE + PHENTSIZE;
+        for word in [1, offset, RAM_BASE as u32, RAM_BASE as u32, filesz, filesz + bss, 5, 4] {
+            elf.extend(word.to_le_bytes()); // p_type to p_align
+        }
+        for word in code {
+            elf.extend(word.to_le_bytes());
+        }
+        elf
+    }
+
+    #[test]
+    fn elf32_programs_load_and_run() {
+        // lui a0, 0x80000; add a0, a0, a0
+        let elf = ElfFile::parse(&elf32(&[0x8000_0537, 0x00A5_0533], 8)).unwrap();
+        assert_eq!((elf.xlen, elf.entry), (32, RAM_BASE));
+        assert_eq!(elf.segments.len(), 1);
+        assert_eq!((elf.segments[0].data.len(), elf.segments[0].memsz), (8, 16));
+
+        let mut machine = Machine::new(1, 4 << 20);
+        let err = machine.load_program(&elf, Vec::new()).unwrap_err();
+        assert!(err.contains("32-bit ELF"), "{}", err);
+
+        machine.set_isa(Isa::default().with_xlen(32));
+        machine.bus.write_bytes(RAM_BASE + 8, &[0xFF; 8]);
+        machine.load_program(&elf, Vec::new()).unwrap();
+        assert_eq!(machine.bus.load(RAM_BASE, 4), Some(0x8000_0537));
+        assert_eq!(machine.bus.load(RAM_BASE + 8, 8), Some(0));
+
+        let hart = &mut machine.harts[0];
+        assert_eq!(hart.pc, RAM_BASE);
+        hart.step(&mut machine.bus);
+        assert_eq!(hart.x[10], 0xFFFF_FFFF_8000_0000);
+        hart.step(&mut machine.bus);
+        assert_eq!((hart.x[10], hart.pc), (0, RAM_BASE + 8));
+    }
+
+    #[test]
+    fn uxl_and_sxl_switch_lower_privileges_to_rv32() {
+        let mut bus = Bus::new(4096);
+        bus.store(RAM_BASE, 4, ADD as u64);
+        bus.store(RAM_BASE + 4, 4, LD as u64);
+        let mut hart = Hart::new(0);
+        let status = hart.csr.mstatus & !(MSTATUS_UXL | MSTATUS_SXL);
+        // UXL = 1 (32 bits) and SXL = 2 (64 bits); 0 is not a legal XLEN
+        // and leaves the fields alone.
+        assert!(hart.csr_write(MSTATUS, status | (1 << 32) | (2 << 34)));
+        assert!(hart.csr_write(MSTATUS, hart.csr.mstatus & !MSTATUS_UXL));
+        assert_eq!(hart.csr.mstatus & (MSTATUS_UXL | MSTATUS_SXL), (1 << 32) | (2 << 34));
+        assert_eq!(hart.xlen(), 64);
+        hart.privilege = Privilege::Supervisor;
+        assert_eq!(hart.xlen(), 64);
+        hart.privilege = Privilege::User;
+        assert_eq!(hart.xlen(), 32);
+
+        hart.pc = RAM_BASE;
+        hart.x[1] = 0x7FFF_FFFF;
+        hart.x[2] = 1;
+        hart.step(&mut bus);
+        assert_eq!(hart.x[3], 0xFFFF_FFFF_8000_0000);
+        // LD is illegal in a 32-bit U-mode.
+        hart.step(&mut bus);
+        assert_eq!(hart.privilege, Privilege::Machine);
+        // the pc, like any address, was sign extended from 32 bits.
+        assert_eq!(hart.csr.mcause, Exception::IllegalInstruction as u64);
+        assert_eq!(hart.csr.mepc, sext(RAM_BASE + 4, 32));
+
+        assert!(hart.csr_write(MSTATUS, (hart.csr.mstatus & !MSTATUS_UXL) | (2 << 32)));
+        hart.privilege = Privilege::User;
+        assert_eq!(hart.xlen(), 64);
+    }
+}
@@ -0,0 +1,870 @@
+use std::collections::BTreeSet;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use crate::Cpu::{Privilege, ABI_NAMES};
+use crate::Csr::csr_name;
+use crate::Emulator::Machine;
+use crate::Replay::History;
+
+/// GDB remote serial protocol server, enough for `riscv64-unknown-elf-gdb`
+/// to attach with `target remote`.
+///
+/// Register numbers follow GDB's built-in RISC-V layout:
+///
+/// | regnum      | register             |
+/// |-------------|----------------------|
+/// | 0..=31      | `x0`-`x31`           |
+/// | 32          | `pc`                 |
+/// | 33..=64     | `f0`-`f31`           |
+/// | 65 + addr   | the CSR at `addr`    |
+/// | 4161        | `priv` (virtual)     |
+///
+/// Every hart is reported as a thread, with thread id `hartid + 1`.
+/// Execution goes through a `History`, so `reverse-stepi` and
+/// `reverse-continue` work too.
+const REG_PC: usize = 32;
+const REG_F0: usize = 33;
+const REG_CSR_BASE: usize = 65;
+const REG_PRIV: usize = REG_CSR_BASE + 4096;
+
+/// Rounds run between checks for a Ctrl-C from GDB while continuing.
+const INTERRUPT_POLL_INTERVAL: u64 = 4096;
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+/// How a debugging session ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionEnd {
+    /// The target exited through HTIF with this code.
+    Exited(u64),
+    /// GDB detached and the target should keep running on its own.
+    Detached,
+    /// GDB killed the target.
+    Killed,
+}
+
+/// Why the target stopped and control went back to GDB.
+enum Stop {
+    Breakpoint(usize, &'static str),
+    Step(usize),
+    Interrupt(usize),
+    Exited(u64),
+    /// Ran into the `begin` or `end` of the recorded history.
+    ReplayLog(usize, &'static str),
+}
+
+/// A connection accepted on either a TCP port or a Unix socket.
+enum Connection {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Connection {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Connection::Tcp(s) => s.set_nonblocking(nonblocking),
+            Connection::Unix(s) => s.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(s) => s.read(buf),
+            Connection::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(s) => s.write(buf),
+            Connection::Unix(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(s) => s.flush(),
+            Connection::Unix(s) => s.flush(),
+        }
+    }
+}
+
+/// Waits for GDB to connect to `address`, which is either `unix:<path>`,
+/// `<host>:<port>` or just a port number on localhost.
+fn accept(address: &str) -> Result<Connection, String> {
+    if let Some(path) = address.strip_prefix("unix:") {
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path).map_err(|e| format!("could not listen on `{}`: {}", path, e))?;
+        eprintln!("waiting for GDB on unix socket {}", path);
+        let (stream, _) = listener.accept().map_err(|e| e.to_string())?;
+        return Ok(Connection::Unix(stream));
+    }
+    let address = if address.contains(':') { address.to_string() } else { format!("127.0.0.1:{}", address) };
+    let listener = TcpListener::bind(&address).map_err(|e| format!("could not listen on `{}`: {}", address, e))?;
+    eprintln!("waiting for GDB on {} (target remote {})", address, address);
+    let (stream, _) = listener.accept().map_err(|e| e.to_string())?;
+    let _ = stream.set_nodelay(true);
+    Ok(Connection::Tcp(stream))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_u64(text: &str) -> Option<u64> {
+    u64::from_str_radix(text, 16).ok()
+}
+
+/// Escapes the bytes that may not appear raw in a binary packet.
+fn escape_binary(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    for &byte in data {
+        if matches!(byte, b'#' | b'$' | b'}' | b'*') {
+            out.push(b'}');
+            out.push(byte ^ 0x20);
+        } else {
+            out.push(byte);
+        }
+    }
+    out
+}
+
+/// Builds the target description advertising the integer and floating
+/// point registers, the CSRs this machine implements and the virtual
+/// `priv` register. Everything but the FP registers is XLEN wide.
+fn target_xml(machine: &Machine) -> String {
+    let xlen = machine.isa().xlen();
+    let mut xml = format!(
+        "<?xml version=\"1.0\"?>\n\
+         <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n\
+         <target version=\"1.0\">\n\
+         <architecture>riscv:rv{}</architecture>\n\
+         <feature name=\"org.gnu.gdb.riscv.cpu\">\n",
+        xlen
+    );
+    for (i, name) in ABI_NAMES.iter().enumerate() {
+        let kind = match i {
+            1 => "code_ptr",
+            2..=4 | 8 => "data_ptr",
+            _ => "int",
+        };
+        xml.push_str(&format!("<reg name=\"{}\" bitsize=\"{}\" type=\"{}\" regnum=\"{}\"/>\n", name, xlen, kind, i));
+    }
+    xml.push_str(&format!("<reg name=\"pc\" bitsize=\"{}\" type=\"code_ptr\" regnum=\"{}\"/>\n", xlen, REG_PC));
+    xml.push_str("</feature>\n<feature name=\"org.gnu.gdb.riscv.fpu\">\n");
+    for i in 0..32 {
+        xml.push_str(&format!("<reg name=\"f{}\" bitsize=\"64\" type=\"ieee_double\" regnum=\"{}\"/>\n", i, REG_F0 + i));
+    }
+    xml.push_str("</feature>\n<feature name=\"org.gnu.gdb.riscv.csr\">\n");
+    let hart = &machine.harts[0];
+    for addr in 0..4096 {
+        if let (Some(name), Some(_)) = (csr_name(addr), hart.csr_read(&machine.bus, addr)) {
+            xml.push_str(&format!(
+                "<reg name=\"{}\" bitsize=\"{}\" type=\"int\" regnum=\"{}\" group=\"csr\"/>\n",
+                name,
+                xlen,
+                REG_CSR_BASE + addr
+            ));
+        }
+    }
+    xml.push_str("</feature>\n<feature name=\"org.gnu.gdb.riscv.virtual\">\n");
+    xml.push_str(&format!("<reg name=\"priv\" bitsize=\"{}\" type=\"int\" regnum=\"{}\"/>\n", xlen, REG_PRIV));
+    xml.push_str("</feature>\n</target>\n");
+    xml
+}
+
+struct GdbStub<'a> {
+    conn: Connection,
+    history: &'a mut History,
+    /// Bytes read from the connection but not consumed yet.
+    pending: Vec<u8>,
+    no_ack: bool,
+    breakpoints: BTreeSet<u64>,
+    hw_breakpoints: BTreeSet<u64>,
+    /// Hart selected with `Hg` for register and memory access.
+    hart: usize,
+    /// Reply sent for the last stop, repeated for `?`.
+    last_stop: String,
+}
+
+impl GdbStub<'_> {
+    fn read_byte(&mut self) -> io::Result<u8> {
+        if !self.pending.is_empty() {
+            return Ok(self.pending.remove(0));
+        }
+        let mut byte = [0u8; 1];
+        loop {
+            match self.conn.read(&mut byte) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "GDB disconnected")),
+                Ok(_) => return Ok(byte[0]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Reads the next packet, acknowledging it unless no-ack mode is on.
+    /// A Ctrl-C outside of a packet is returned as the packet `"\x03"`.
+    fn read_packet(&mut self) -> io::Result<String> {
+        loop {
+            match self.read_byte()? {
+                b'$' => {}
+                0x03 => return Ok("\x03".to_string()),
+                _ => continue,
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    b'#' => break,
+                    byte => data.push(byte),
+                }
+            }
+            let checksum = [self.read_byte()?, self.read_byte()?];
+            let expected = std::str::from_utf8(&checksum).ok().and_then(|c| u8::from_str_radix(c, 16).ok());
+            let sum = data.iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+            if !self.no_ack {
+                if expected != Some(sum) {
+                    self.conn.write_all(b"-")?;
+                    continue;
+                }
+                self.conn.write_all(b"+")?;
+            }
+            return Ok(String::from_utf8_lossy(&data).into_owned());
+        }
+    }
+
+    fn send_bytes(&mut self, data: &[u8]) -> io::Result<()> {
+        let sum = data.iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        packet.extend_from_slice(data);
+        packet.extend_from_slice(format!("#{:02x}", sum).as_bytes());
+        loop {
+            self.conn.write_all(&packet)?;
+            self.conn.flush()?;
+            if self.no_ack {
+                return Ok(());
+            }
+            // wait for the acknowledgement, keeping anything else around.
+            loop {
+                match self.read_byte()? {
+                    b'+' => return Ok(()),
+                    b'-' => break,
+                    byte => self.pending.push(byte),
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        self.send_bytes(data.as_bytes())
+    }
+
+    /// Checks, without blocking, whether GDB sent a Ctrl-C.
+    fn interrupted(&mut self) -> bool {
+        if self.conn.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let mut buf = [0u8; 64];
+        let mut seen = false;
+        while let Ok(n) = self.conn.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            for &byte in &buf[..n] {
+                if byte == 0x03 {
+                    seen = true;
+                } else {
+                    self.pending.push(byte);
+                }
+            }
+        }
+        let _ = self.conn.set_nonblocking(false);
+        seen
+    }
+
+    /// Bytes of register `reg` in packets: 8 for the FP registers, XLEN
+    /// for the rest.
+    fn register_size(machine: &Machine, reg: usize) -> usize {
+        match reg {
+            REG_F0..=64 => 8,
+            _ => machine.isa().xlen() as usize / 8,
+        }
+    }
+
+    fn read_register(&self, machine: &Machine, reg: usize) -> Option<u64> {
+        let hart = &machine.harts[self.hart];
+        match reg {
+            0..=31 => Some(hart.read_reg(reg)),
+            REG_PC => Some(hart.pc),
+            REG_F0..=64 => Some(hart.f[reg - REG_F0]),
+            REG_PRIV => Some(hart.privilege as u64),
+            _ if (REG_CSR_BASE..REG_PRIV).contains(&reg) => hart.csr_read(&machine.bus, reg - REG_CSR_BASE),
+            _ => None,
+        }
+    }
+
+    fn write_register(&self, machine: &mut Machine, reg: usize, value: u64) -> bool {
+        let hart = &mut machine.harts[self.hart];
+        match reg {
+            0..=31 => hart.write_reg(reg, value),
+            REG_PC => hart.pc = value,
+            REG_F0..=64 => hart.f[reg - REG_F0] = value,
+            REG_PRIV => match value {
+                0 | 1 | 3 => hart.privilege = Privilege::from_bits(value),
+                _ => return false,
+            },
+            _ if (REG_CSR_BASE..REG_PRIV).contains(&reg) => return hart.csr_write(reg - REG_CSR_BASE, value),
+            _ => return false,
+        }
+        true
+    }
+
+    fn breakpoint_at(&self, machine: &Machine) -> Option<(usize, &'static str)> {
+        machine.harts.iter().enumerate().find_map(|(i, hart)| {
+            if self.breakpoints.contains(&hart.pc) {
+                Some((i, "swbreak"))
+            } else if self.hw_breakpoints.contains(&hart.pc) {
+                Some((i, "hwbreak"))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Runs the machine until a breakpoint, exit or Ctrl-C.
+    fn resume(&mut self, machine: &mut Machine, single_step: bool) -> Stop {
+        let mut rounds: u64 = 0;
+        loop {
+            if !self.history.step(machine) {
+                return Stop::ReplayLog(self.hart, "end");
+            }
+            rounds += 1;
+            if let Some(code) = machine.exit_code() {
+                return Stop::Exited(code);
+            }
+            if let Some((hart, kind)) = self.breakpoint_at(machine) {
+                return Stop::Breakpoint(hart, kind);
+            }
+            if single_step {
+                return Stop::Step(self.hart);
+            }
+            if rounds.is_multiple_of(INTERRUPT_POLL_INTERVAL) && self.interrupted() {
+                return Stop::Interrupt(self.hart);
+            }
+        }
+    }
+
+    /// Runs backwards one round, or to the last breakpoint hit before now.
+    fn reverse(&mut self, machine: &mut Machine, single_step: bool) -> Stop {
+        if single_step {
+            if machine.steps == self.history.start() {
+                return Stop::ReplayLog(self.hart, "begin");
+            }
+            self.history.seek(machine, machine.steps - 1);
+            return Stop::Step(self.hart);
+        }
+        let (breakpoints, hw_breakpoints) = (&self.breakpoints, &self.hw_breakpoints);
+        let found = self.history.reverse_until(machine, |machine| {
+            machine.harts.iter().any(|h| breakpoints.contains(&h.pc) || hw_breakpoints.contains(&h.pc))
+        });
+        match self.breakpoint_at(machine) {
+            Some((hart, kind)) if found => Stop::Breakpoint(hart, kind),
+            _ => Stop::ReplayLog(self.hart, "begin"),
+        }
+    }
+
+    fn stop_reply(&mut self, stop: Stop) -> String {
+        let reply = match stop {
+            Stop::Breakpoint(hart, kind) => {
+                self.hart = hart;
+                format!("T{:02x}thread:{:x};{}:;", SIGTRAP, hart + 1, kind)
+            }
+            Stop::Step(hart) => format!("T{:02x}thread:{:x};", SIGTRAP, hart + 1),
+            Stop::Interrupt(hart) => format!("T{:02x}thread:{:x};", SIGINT, hart + 1),
+            Stop::Exited(code) => format!("W{:02x}", code & 0xFF),
+            Stop::ReplayLog(hart, end) => format!("T{:02x}thread:{:x};replaylog:{};", SIGTRAP, hart + 1, end),
+        };
+        self.last_stop = reply.clone();
+        reply
+    }
+
+    /// Parses a thread id from `H`/`T` packets; `0` and `-1` mean any.
+    fn thread(&self, machine: &Machine, text: &str) -> Option<Option<usize>> {
+        if text == "-1" || text == "0" {
+            return Some(None);
+        }
+        let id = parse_u64(text)? as usize;
+        if id == 0 || id > machine.harts.len() {
+            return None;
+        }
+        Some(Some(id - 1))
+    }
+
+    /// Handles one packet. Returns `Some` when the session is over.
+    fn handle(&mut self, machine: &mut Machine, packet: &str) -> io::Result<Option<SessionEnd>> {
+        if packet == "QStartNoAckMode" {
+            // the reply to this packet is still acknowledged.
+            self.send("OK")?;
+            self.no_ack = true;
+            return Ok(None);
+        }
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, |c| c.len_utf8()));
+        let reply: String = match command {
+            "\x03" => {
+                // Ctrl-C while already stopped.
+                let stop = Stop::Interrupt(self.hart);
+                self.stop_reply(stop)
+            }
+            "?" => self.last_stop.clone(),
+            "g" => {
+                let hart = &machine.harts[self.hart];
+                let size = Self::register_size(machine, 0);
+                let mut bytes = Vec::with_capacity(33 * size);
+                for value in hart.x.iter().chain(std::iter::once(&hart.pc)) {
+                    bytes.extend_from_slice(&value.to_le_bytes()[..size]);
+                }
+                to_hex(&bytes)
+            }
+            "G" => match from_hex(args) {
+                Some(bytes) => {
+                    for (reg, chunk) in bytes.chunks_exact(Self::register_size(machine, 0)).take(33).enumerate() {
+                        let mut raw = [0u8; 8];
+                        raw[..chunk.len()].copy_from_slice(chunk);
+                        self.write_register(machine, reg, u64::from_le_bytes(raw));
+                    }
+                    self.history.restart(machine);
+                    "OK".to_string()
+                }
+                None => "E01".to_string(),
+            },
+            "p" => {
+                let reg = parse_u64(args).map(|reg| reg as usize);
+                match reg.and_then(|reg| Some((reg, self.read_register(machine, reg)?))) {
+                    Some((reg, value)) => to_hex(&value.to_le_bytes()[..Self::register_size(machine, reg)]),
+                    None => "E01".to_string(),
+                }
+            }
+            "P" => {
+                let parsed = args.split_once('=').and_then(|(reg, value)| {
+                    let bytes = from_hex(value)?;
+                    let mut raw = [0u8; 8];
+                    raw[..bytes.len().min(8)].copy_from_slice(&bytes[..bytes.len().min(8)]);
+                    Some((parse_u64(reg)? as usize, u64::from_le_bytes(raw)))
+                });
+                match parsed {
+                    Some((reg, value)) if self.write_register(machine, reg, value) => {
+                        self.history.restart(machine);
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "m" => {
+                let parsed = args.split_once(',').and_then(|(addr, len)| Some((parse_u64(addr)?, parse_u64(len)?)));
+                let data = parsed.and_then(|(addr, len)| {
+                    machine.harts[self.hart].debug_read(&machine.bus, addr, len.min(0x10000) as usize)
+                });
+                match data {
+                    Some(data) => to_hex(&data),
+                    None => "E14".to_string(),
+                }
+            }
+            "M" => {
+                let parsed = args.split_once(':').and_then(|(head, data)| {
+                    let (addr, len) = head.split_once(',')?;
+                    let data = from_hex(data)?;
+                    (parse_u64(len)? as usize == data.len()).then_some((parse_u64(addr)?, data))
+                });
+                let written = parsed.is_some_and(|(addr, data)| {
+                    let hart = &machine.harts[self.hart];
+                    hart.debug_write(&mut machine.bus, addr, &data)
+                });
+                if written {
+                    self.history.restart(machine);
+                    "OK".to_string()
+                } else {
+                    "E14".to_string()
+                }
+            }
+            "Z" | "z" => {
+                let mut fields = args.split(',');
+                let kind = fields.next();
+                let addr = fields.next().and_then(parse_u64);
+                let set = match kind {
+                    Some("0") => Some(&mut self.breakpoints),
+                    Some("1") => Some(&mut self.hw_breakpoints),
+                    _ => None,
+                };
+                match (set, addr) {
+                    (Some(set), Some(addr)) => {
+                        if command == "Z" {
+                            set.insert(addr);
+                        } else {
+                            set.remove(&addr);
+                        }
+                        "OK".to_string()
+                    }
+                    // watchpoints are not supported.
+                    (None, _) => String::new(),
+                    _ => "E01".to_string(),
+                }
+            }
+            "c" | "s" => {
+                if let Some(addr) = parse_u64(args) {
+                    machine.harts[self.hart].pc = addr;
+                    self.history.restart(machine);
+                }
+                let stop = self.resume(machine, command == "s");
+                let exited = match stop {
+                    Stop::Exited(code) => Some(code),
+                    _ => None,
+                };
+                let reply = self.stop_reply(stop);
+                let sent = self.send(&reply);
+                // GDB may hang up as soon as it sees the exit.
+                if let Some(code) = exited {
+                    return Ok(Some(SessionEnd::Exited(code)));
+                }
+                sent?;
+                return Ok(None);
+            }
+            "b" if args == "s" || args == "c" => {
+                let stop = self.reverse(machine, args == "s");
+                self.stop_reply(stop)
+            }
+            "H" => {
+                let (_, thread) = args.split_at(args.len().min(1));
+                match self.thread(machine, thread) {
+                    Some(Some(hart)) => {
+                        self.hart = hart;
+                        "OK".to_string()
+                    }
+                    Some(None) => "OK".to_string(),
+                    None => "E01".to_string(),
+                }
+            }
+            "T" => match self.thread(machine, args) {
+                Some(_) => "OK".to_string(),
+                None => "E01".to_string(),
+            },
+            "D" => {
+                self.send("OK")?;
+                return Ok(Some(SessionEnd::Detached));
+            }
+            "k" => return Ok(Some(SessionEnd::Killed)),
+            "q" | "Q" | "v" => self.handle_query(machine, packet),
+            _ => String::new(),
+        };
+        self.send(&reply)?;
+        Ok(None)
+    }
+
+    /// Handles the `q`, `Q` and `v` packets.
+    fn handle_query(&mut self, machine: &Machine, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+;swbreak+;hwbreak+;ReverseStep+;ReverseContinue+".to_string();
+        }
+        if let Some(rest) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let xml = target_xml(machine);
+            let parsed = rest.split_once(',').and_then(|(off, len)| Some((parse_u64(off)?, parse_u64(len)?)));
+            let (offset, len) = match parsed {
+                Some((offset, len)) => (offset as usize, len as usize),
+                None => return "E01".to_string(),
+            };
+            let data = xml.as_bytes();
+            let start = offset.min(data.len());
+            let end = (start + len).min(data.len());
+            let marker = if end == data.len() { "l" } else { "m" };
+            let mut reply = marker.as_bytes().to_vec();
+            reply.extend(escape_binary(&data[start..end]));
+            return String::from_utf8(reply).unwrap_or_default();
+        }
+        if let Some(command) = packet.strip_prefix("qRcmd,") {
+            // `monitor <command>`: the reply is the hex encoded output.
+            let output = match from_hex(command).as_deref().map(String::from_utf8_lossy).as_deref().map(str::trim) {
+                Some("stats") => match &machine.stats {
+                    Some(stats) => stats.report(),
+                    None => "the instruction mix is not being counted; start with `--stats`\n".to_string(),
+                },
+                Some(other) => format!("unknown monitor command `{}`; try `stats`\n", other),
+                None => return "E01".to_string(),
+            };
+            return to_hex(output.as_bytes());
+        }
+        match packet {
+            "qfThreadInfo" => {
+                let ids: Vec<String> = (1..=machine.harts.len()).map(|id| format!("{:x}", id)).collect();
+                format!("m{}", ids.join(","))
+            }
+            "qsThreadInfo" => "l".to_string(),
+            "qC" => format!("QC{:x}", self.hart + 1),
+            "qAttached" => "1".to_string(),
+            _ => {
+                if let Some(id) = packet.strip_prefix("qThreadExtraInfo,") {
+                    if let Some(Some(hart)) = self.thread(machine, id) {
+                        return to_hex(format!("hart {}", machine.harts[hart].id).as_bytes());
+                    }
+                }
+                String::new()
+            }
+        }
+    }
+}
+
+/// Waits for GDB on `address` and serves it until it detaches, kills the
+/// target or the target exits. The machine starts out stopped and runs
+/// through `history`.
+pub fn serve(machine: &mut Machine, address: &str, history: &mut History) -> Result<SessionEnd, String> {
+    let conn = accept(address)?;
+    let mut stub = GdbStub {
+        conn,
+        history,
+        pending: Vec::new(),
+        no_ack: false,
+        breakpoints: BTreeSet::new(),
+        hw_breakpoints: BTreeSet::new(),
+        hart: 0,
+        last_stop: format!("T{:02x}thread:1;", SIGTRAP),
+    };
+    loop {
+        let packet = stub.read_packet().map_err(|e| e.to_string())?;
+        if let Some(end) = stub.handle(machine, &packet).map_err(|e| e.to_string())? {
+            return Ok(end);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Memory::RAM_BASE;
+
+    /// Four `nop`s and then `j .`.
+    const PROGRAM: [u32; 5] = [0x0000_0013, 0x0000_0013, 0x0000_0013, 0x0000_0013, 0x0000_006F];
+
+    fn machine() -> Machine {
+        let mut machine = Machine::new(2, 1 << 16);
+        for (i, word) in PROGRAM.iter().enumerate() {
+            machine.bus.store(RAM_BASE + i as u64 * 4, 4, *word as u64);
+        }
+        for hart in machine.harts.iter_mut() {
+            hart.pc = RAM_BASE;
+        }
+        machine
+    }
+
+    /// A stub talking over a socket pair, and GDB's end of it.
+    fn stub(history: &mut History) -> (GdbStub<'_>, UnixStream) {
+        let (ours, gdb) = UnixStream::pair().unwrap();
+        gdb.set_read_timeout(Some(std::time::Duration::from_secs(10))).unwrap();
+        let stub = GdbStub {
+            conn: Connection::Unix(ours),
+            history,
+            pending: Vec::new(),
+            no_ack: false,
+            breakpoints: BTreeSet::new(),
+            hw_breakpoints: BTreeSet::new(),
+            hart: 0,
+            last_stop: format!("T{:02x}thread:1;", SIGTRAP),
+        };
+        (stub, gdb)
+    }
+
+    fn frame(packet: &str) -> String {
+        let sum = packet.bytes().fold(0u8, |acc, b| acc.wrapping_add(b));
+        format!("${}#{:02x}", packet, sum)
+    }
+
+    /// Reads from GDB's end up to the end of the next packet, returning
+    /// everything before it (acknowledgements) and the packet's data.
+    fn receive(gdb: &mut UnixStream) -> (String, String) {
+        let mut text = Vec::new();
+        let mut byte = [0u8];
+        while !text.ends_with(b"#") {
+            gdb.read_exact(&mut byte).unwrap();
+            text.push(byte[0]);
+        }
+        let mut checksum = [0u8; 2];
+        gdb.read_exact(&mut checksum).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        let (before, data) = text.trim_end_matches('#').split_once('$').unwrap();
+        assert_eq!(frame(data), format!("${}#{}", data, std::str::from_utf8(&checksum).unwrap()));
+        (before.to_string(), data.to_string())
+    }
+
+    /// Sends `packet` as GDB would, lets the stub handle it and returns
+    /// the reply.
+    fn exchange(stub: &mut GdbStub, machine: &mut Machine, gdb: &mut UnixStream, packet: &str) -> String {
+        // our acknowledgement of the reply goes out ahead of time.
+        gdb.write_all(format!("{}+", frame(packet)).as_bytes()).unwrap();
+        let packet = stub.read_packet().unwrap();
+        assert_eq!(stub.handle(machine, &packet).unwrap(), None);
+        let (ack, reply) = receive(gdb);
+        assert_eq!(ack, "+");
+        reply
+    }
+
+    #[test]
+    fn registers() {
+        let mut machine = machine();
+        let mut history = History::new(&mut machine, None);
+        let (mut stub, mut gdb) = stub(&mut history);
+        machine.harts[0].x[1] = 0x1122_3344_5566_7788;
+
+        let g = exchange(&mut stub, &mut machine, &mut gdb, "g");
+        assert_eq!(g.len(), 33 * 16);
+        assert_eq!(&g[16..32], "8877665544332211");
+        assert_eq!(&g[32 * 16..], "0000008000000000");
+
+        // G writes x1-x31 and the pc; x0 stays zero.
+        let mut blob = "ff".repeat(8);
+        blob.push_str(&"00".repeat(8));
+        blob.push_str(&"01".repeat(8 * 30));
+        blob.push_str("0400008000000000");
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, &format!("G{}", blob)), "OK");
+        assert_eq!((machine.harts[0].x[0], machine.harts[0].x[1]), (0, 0));
+        assert_eq!(machine.harts[0].x[31], 0x0101_0101_0101_0101);
+        assert_eq!(machine.harts[0].pc, RAM_BASE + 4);
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "Gxyz"), "E01");
+
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "P5=efbeadde00000000"), "OK");
+        assert_eq!(machine.harts[0].x[5], 0xDEAD_BEEF);
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "p5"), "efbeadde00000000");
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "p20"), "0400008000000000");
+        // f0, mscratch (0x340 + 65) and `priv`.
+        machine.harts[0].f[0] = 0x3FF0_0000_0000_0000;
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "p21"), "000000000000f03f");
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "P381=2a00000000000000"), "OK");
+        assert_eq!(machine.harts[0].csr.mscratch, 42);
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "p1041"), "0300000000000000");
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "P1041=0100000000000000"), "OK");
+        assert_eq!(machine.harts[0].privilege, Privilege::Supervisor);
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "P1041=0200000000000000"), "E01");
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "p2000"), "E01");
+
+        // `Hg` picks the hart the registers come from.
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "Hg2"), "OK");
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "p5"), "0000000000000000");
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "Hg3"), "E01");
+    }
+
+    #[test]
+    fn memory() {
+        let mut machine = machine();
+        let mut history = History::new(&mut machine, None);
+        let (mut stub, mut gdb) = stub(&mut history);
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "m80000000,8"), "1300000013000000");
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "M80000100,4:01020304"), "OK");
+        assert_eq!(machine.bus.load(RAM_BASE + 0x100, 4), Some(0x0403_0201));
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "m80000100,4"), "01020304");
+        // the length has to match the data, and memory has to be there.
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "M80000100,2:01020304"), "E14");
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "m10,4"), "E14");
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "M10,1:00"), "E14");
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut machine = machine();
+        let mut history = History::new(&mut machine, None);
+        let (mut stub, mut gdb) = stub(&mut history);
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "Z0,80000008,4"), "OK");
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "Z1,8000000c,4"), "OK");
+        // watchpoints are not supported.
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "Z2,80000100,4"), "");
+
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "c"), "T05thread:1;swbreak:;");
+        assert_eq!(machine.harts[0].pc, RAM_BASE + 8);
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "c"), "T05thread:1;hwbreak:;");
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "?"), "T05thread:1;hwbreak:;");
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "s"), "T05thread:1;");
+        assert_eq!(machine.harts[0].pc, RAM_BASE + 0x10);
+
+        // and backwards.
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "bc"), "T05thread:1;hwbreak:;");
+        assert_eq!(machine.harts[0].pc, RAM_BASE + 0xC);
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "z1,8000000c,4"), "OK");
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "bc"), "T05thread:1;swbreak:;");
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "z0,80000008,4"), "OK");
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "bc"), "T05thread:1;replaylog:begin;");
+        assert_eq!(machine.harts[0].pc, RAM_BASE);
+    }
+
+    #[test]
+    fn target_description() {
+        let mut machine = machine();
+        let mut history = History::new(&mut machine, None);
+        let xml = target_xml(&machine);
+        assert!(xml.contains("<architecture>riscv:rv64</architecture>"));
+        assert!(xml.contains("<reg name=\"pc\" bitsize=\"64\" type=\"code_ptr\" regnum=\"32\"/>"));
+        assert!(xml.contains("<reg name=\"mstatus\" bitsize=\"64\" type=\"int\" regnum=\"833\" group=\"csr\"/>"));
+        assert!(xml.contains("<reg name=\"priv\" bitsize=\"64\" type=\"int\" regnum=\"4161\"/>"));
+
+        let (mut stub, mut gdb) = stub(&mut history);
+        let supported = exchange(&mut stub, &mut machine, &mut gdb, "qSupported:multiprocess+;swbreak+");
+        assert!(supported.split(';').any(|feature| feature == "qXfer:features:read+"));
+        // GDB reads it in pieces until one starts with `l`.
+        let mut read = String::new();
+        loop {
+            let packet = format!("qXfer:features:read:target.xml:{:x},400", read.len());
+            let reply = exchange(&mut stub, &mut machine, &mut gdb, &packet);
+            read.push_str(&reply[1..]);
+            if reply.starts_with('l') {
+                break;
+            }
+            assert!(reply.starts_with('m'));
+            assert_eq!(reply.len(), 0x401);
+        }
+        assert_eq!(read, xml);
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "qXfer:features:read:target.xml:zz"), "E01");
+    }
+
+    #[test]
+    fn interrupts_and_framing() {
+        let mut machine = machine();
+        let mut history = History::new(&mut machine, None);
+        let (mut stub, mut gdb) = stub(&mut history);
+
+        // a Ctrl-C while stopped.
+        gdb.write_all(b"\x03+").unwrap();
+        let packet = stub.read_packet().unwrap();
+        assert_eq!(packet, "\x03");
+        stub.handle(&mut machine, &packet).unwrap();
+        assert_eq!(receive(&mut gdb), (String::new(), "T02thread:1;".to_string()));
+
+        // a bad checksum is refused and the packet sent again.
+        gdb.write_all(b"$g#00").unwrap();
+        gdb.write_all(frame("qC").as_bytes()).unwrap();
+        assert_eq!(stub.read_packet().unwrap(), "qC");
+        let mut acks = [0u8; 2];
+        gdb.read_exact(&mut acks).unwrap();
+        assert_eq!(&acks, b"-+");
+
+        // and one while running `j .`, seen on the next poll.
+        assert_eq!(exchange(&mut stub, &mut machine, &mut gdb, "QStartNoAckMode"), "OK");
+        gdb.write_all(format!("{}\x03", frame("c")).as_bytes()).unwrap();
+        let packet = stub.read_packet().unwrap();
+        stub.handle(&mut machine, &packet).unwrap();
+        assert_eq!(receive(&mut gdb), (String::new(), "T02thread:1;".to_string()));
+        assert_eq!(machine.steps, INTERRUPT_POLL_INTERVAL);
+        assert_eq!(machine.harts[0].pc, RAM_BASE + 0x10);
+    }
+}
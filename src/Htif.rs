@@ -0,0 +1,545 @@
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::{FileExt, MetadataExt, OpenOptionsExt};
+use std::sync::{Arc, Mutex};
+
+use crate::Memory::Bus;
+
+const DEVICE_SYSCALL: u64 = 0;
+const DEVICE_CONSOLE: u64 = 1;
+const CONSOLE_GETCHAR: u64 = 0;
+const CONSOLE_PUTCHAR: u64 = 1;
+
+// Linux syscall numbers used by the `pk` frontend.
+const SYS_GETCWD: u64 = 17;
+const SYS_FACCESSAT: u64 = 48;
+const SYS_OPENAT: u64 = 56;
+const SYS_CLOSE: u64 = 57;
+const SYS_LSEEK: u64 = 62;
+const SYS_READ: u64 = 63;
+const SYS_WRITE: u64 = 64;
+const SYS_PREAD: u64 = 67;
+const SYS_PWRITE: u64 = 68;
+const SYS_FSTATAT: u64 = 79;
+const SYS_FSTAT: u64 = 80;
+const SYS_EXIT: u64 = 93;
+const SYS_EXIT_GROUP: u64 = 94;
+const SYS_GETMAINVARS: u64 = 2011;
+
+const EBADF: i64 = 9;
+const EFAULT: i64 = 14;
+const EINVAL: i64 = 22;
+const ENOSYS: i64 = 38;
+const AT_FDCWD: i64 = -100;
+
+// `open` flags, as defined by the generic Linux ABI RISC-V uses.
+const O_ACCMODE: u64 = 0o3;
+const O_WRONLY: u64 = 0o1;
+const O_RDWR: u64 = 0o2;
+const O_CREAT: u64 = 0o100;
+const O_EXCL: u64 = 0o200;
+const O_TRUNC: u64 = 0o1000;
+const O_APPEND: u64 = 0o2000;
+
+/// Size of `struct stat` on RISC-V Linux.
+const STAT_SIZE: usize = 128;
+
+#[derive(Debug, Clone)]
+enum HostFile {
+    Stdin,
+    Stdout,
+    Stderr,
+    File(Arc<File>),
+}
+
+/// Host-target interface used by Spike, `riscv-tests` and the proxy kernel.
+///
+/// The target writes a command to the `tohost` word:
+///
+/// | bits  | field   |
+/// |-------|---------|
+/// | 63:56 | device  |
+/// | 55:48 | command |
+/// | 47:0  | payload |
+///
+/// and the host acknowledges it by writing `fromhost`.
+#[derive(Debug, Clone)]
+pub struct Htif {
+    pub tohost: u64,
+    pub fromhost: Option<u64>,
+    /// Set by the bus whenever the target stores to `tohost`.
+    pub tohost_written: bool,
+    /// `Some(code)` once the target asked to exit.
+    pub exit_code: Option<u64>,
+    /// Arguments handed to the target by `getmainvars`.
+    pub args: Vec<String>,
+    fds: Vec<Option<HostFile>>,
+    console_input: Arc<Mutex<VecDeque<u8>>>,
+    console_reader_started: bool,
+    getchar_pending: bool,
+}
+
+impl Htif {
+    pub fn new(tohost: u64, fromhost: Option<u64>, args: Vec<String>) -> Self {
+        Self {
+            tohost,
+            fromhost,
+            tohost_written: false,
+            exit_code: None,
+            args,
+            fds: vec![Some(HostFile::Stdin), Some(HostFile::Stdout), Some(HostFile::Stderr)],
+            console_input: Arc::new(Mutex::new(VecDeque::new())),
+            console_reader_started: false,
+            getchar_pending: false,
+        }
+    }
+
+    /// Rebuilds the device from a machine snapshot. Files the target had
+    /// open on the host are not part of a snapshot and come back closed.
+    pub fn restore(tohost: u64, fromhost: Option<u64>, args: Vec<String>, exit_code: Option<u64>, getchar_pending: bool) -> Self {
+        let mut htif = Self::new(tohost, fromhost, args);
+        htif.exit_code = exit_code;
+        if getchar_pending {
+            htif.start_console_reader();
+            htif.getchar_pending = true;
+        }
+        htif
+    }
+
+    /// Whether the target is waiting for a console byte.
+    pub fn getchar_pending(&self) -> bool {
+        self.getchar_pending
+    }
+
+    /// Returns `true` if a store of `size` bytes at `addr` touches `tohost`.
+    pub fn covers(&self, addr: u64, size: u64) -> bool {
+        addr < self.tohost + 8 && self.tohost < addr + size
+    }
+
+    /// Handles the command sitting in `tohost`, if any.
+    pub fn service(&mut self, bus: &mut Bus) {
+        self.tohost_written = false;
+        let cmd = match bus.load(self.tohost, 8) {
+            Some(0) | None => return,
+            Some(cmd) => cmd,
+        };
+        bus.store(self.tohost, 8, 0);
+        let device = cmd >> 56;
+        let command = (cmd >> 48) & 0xFF;
+        let payload = cmd & 0xFFFF_FFFF_FFFF;
+
+        match (device, command) {
+            (DEVICE_SYSCALL, _) if payload & 1 == 1 => {
+                self.exit_code = Some(payload >> 1);
+            },
+            (DEVICE_SYSCALL, _) => {
+                self.proxy_syscall(bus, payload);
+                self.respond(bus, device, command, 1);
+            },
+            (DEVICE_CONSOLE, CONSOLE_PUTCHAR) => {
+                let mut out = std::io::stdout();
+                let _ = out.write_all(&[payload as u8]);
+                let _ = out.flush();
+                // fesvr acknowledges with the request itself.
+                self.respond(bus, device, command, 0x100 | (payload & 0xFF));
+            },
+            (DEVICE_CONSOLE, CONSOLE_GETCHAR) => {
+                // answered by `poll` once a byte arrives on stdin.
+                self.start_console_reader();
+                self.getchar_pending = true;
+            },
+            _ => {
+                eprintln!("htif: unknown device {} command {}", device, command);
+            },
+        }
+    }
+
+    /// Delivers console input to a waiting `getchar` request.
+    pub fn poll(&mut self, bus: &mut Bus) {
+        if !self.getchar_pending {
+            return;
+        }
+        if let Some(byte) = self.console_input.lock().unwrap().pop_front() {
+            self.getchar_pending = false;
+            self.respond(bus, DEVICE_CONSOLE, CONSOLE_GETCHAR, byte as u64);
+        }
+    }
+
+    fn respond(&self, bus: &mut Bus, device: u64, command: u64, payload: u64) {
+        if let Some(fromhost) = self.fromhost {
+            bus.store(fromhost, 8, (device << 56) | (command << 48) | payload);
+        }
+    }
+
+    fn start_console_reader(&mut self) {
+        if self.console_reader_started {
+            return;
+        }
+        self.console_reader_started = true;
+        spawn_stdin_reader(self.console_input.clone());
+    }
+
+    /// Runs a `pk` frontend syscall described by the eight words at
+    /// `magic_mem` and writes the return value over the first one.
+    fn proxy_syscall(&mut self, bus: &mut Bus, magic_mem: u64) {
+        let mut words = [0u64; 8];
+        for (i, word) in words.iter_mut().enumerate() {
+            *word = bus.load(magic_mem + i as u64 * 8, 8).unwrap_or(0);
+        }
+        let [number, a0, a1, a2, a3, a4, _, _] = words;
+
+        let result: i64 = match number {
+            SYS_EXIT | SYS_EXIT_GROUP => {
+                self.exit_code = Some(a0);
+                0
+            },
+            SYS_READ => self.sys_read(bus, a0, a1, a2, None),
+            SYS_PREAD => self.sys_read(bus, a0, a1, a2, Some(a3)),
+            SYS_WRITE => self.sys_write(bus, a0, a1, a2, None),
+            SYS_PWRITE => self.sys_write(bus, a0, a1, a2, Some(a3)),
+            SYS_OPENAT => self.sys_openat(bus, a0, a1, a2, a3, a4),
+            SYS_CLOSE => match self.fds.get_mut(a0 as usize) {
+                Some(slot @ Some(_)) => {
+                    *slot = None;
+                    0
+                },
+                _ => -EBADF,
+            },
+            SYS_LSEEK => match self.fd(a0) {
+                Some(HostFile::File(file)) => {
+                    let pos = match a2 {
+                        0 => SeekFrom::Start(a1),
+                        1 => SeekFrom::Current(a1 as i64),
+                        2 => SeekFrom::End(a1 as i64),
+                        _ => return self.finish_syscall(bus, magic_mem, -EINVAL),
+                    };
+                    errno_result((&*file).seek(pos).map(|p| p as i64))
+                },
+                Some(_) => -29, // ESPIPE
+                None => -EBADF,
+            },
+            SYS_FSTAT => match self.fd(a0) {
+                Some(HostFile::File(file)) => match file.metadata() {
+                    Ok(meta) => write_stat(bus, a1, &meta),
+                    Err(e) => errno(&e),
+                },
+                Some(_) => {
+                    // the standard streams look like character devices.
+                    let mut stat = [0u8; STAT_SIZE];
+                    stat[16..20].copy_from_slice(&0o20620u32.to_le_bytes());
+                    if bus.write_bytes(a1, &stat) { 0 } else { -EFAULT }
+                },
+                None => -EBADF,
+            },
+            SYS_FSTATAT => match self.path(bus, a0, a1, a2) {
+                Ok(path) => match std::fs::metadata(path) {
+                    Ok(meta) => write_stat(bus, a3, &meta),
+                    Err(e) => errno(&e),
+                },
+                Err(e) => e,
+            },
+            SYS_FACCESSAT => match self.path(bus, a0, a1, a2) {
+                Ok(path) => match std::fs::metadata(path) {
+                    Ok(_) => 0,
+                    Err(e) => errno(&e),
+                },
+                Err(e) => e,
+            },
+            SYS_GETCWD => match std::env::current_dir() {
+                Ok(dir) => {
+                    let mut bytes = dir.to_string_lossy().into_owned().into_bytes();
+                    bytes.push(0);
+                    if bytes.len() as u64 > a1 {
+                        -34 // ERANGE
+                    } else if bus.write_bytes(a0, &bytes) {
+                        a0 as i64
+                    } else {
+                        -EFAULT
+                    }
+                },
+                Err(e) => errno(&e),
+            },
+            SYS_GETMAINVARS => self.sys_getmainvars(bus, a0, a1),
+            _ => {
+                eprintln!("htif: unsupported frontend syscall {}", number);
+                -ENOSYS
+            },
+        };
+        self.finish_syscall(bus, magic_mem, result);
+    }
+
+    fn finish_syscall(&self, bus: &mut Bus, magic_mem: u64, result: i64) {
+        bus.store(magic_mem, 8, result as u64);
+    }
+
+    fn fd(&self, fd: u64) -> Option<HostFile> {
+        self.fds.get(fd as usize).cloned().flatten()
+    }
+
+    /// Reads the `len` byte path at `addr` (including its terminator). Only
+    /// paths relative to the current directory are supported.
+    fn path(&self, bus: &Bus, dirfd: u64, addr: u64, len: u64) -> Result<String, i64> {
+        if dirfd as i64 != AT_FDCWD {
+            return Err(-EBADF);
+        }
+        let bytes = bus.read_bytes(addr, len as usize).ok_or(-EFAULT)?;
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+    }
+
+    fn sys_read(&mut self, bus: &mut Bus, fd: u64, addr: u64, len: u64, offset: Option<u64>) -> i64 {
+        let file = match (self.fd(fd), offset) {
+            (Some(file @ HostFile::Stdin), None) | (Some(file @ HostFile::File(_)), _) => file,
+            _ => return -EBADF,
+        };
+        // the buffer has to be guest RAM, which also bounds what the host
+        // allocates for it.
+        if bus.read_bytes(addr, len as usize).is_none() {
+            return -EFAULT;
+        }
+        let mut buf = vec![0u8; len as usize];
+        let n = match (file, offset) {
+            (HostFile::File(file), Some(off)) => file.read_at(&mut buf, off),
+            (HostFile::File(file), None) => (&*file).read(&mut buf),
+            _ => std::io::stdin().read(&mut buf),
+        };
+        match n {
+            Ok(n) => {
+                if bus.write_bytes(addr, &buf[..n]) { n as i64 } else { -EFAULT }
+            },
+            Err(e) => errno(&e),
+        }
+    }
+
+    fn sys_write(&mut self, bus: &mut Bus, fd: u64, addr: u64, len: u64, offset: Option<u64>) -> i64 {
+        let buf = match bus.read_bytes(addr, len as usize) {
+            Some(buf) => buf.to_vec(),
+            None => return -EFAULT,
+        };
+        let n = match (self.fd(fd), offset) {
+            (Some(HostFile::Stdout), None) => {
+                let mut out = std::io::stdout();
+                out.write(&buf).and_then(|n| out.flush().map(|_| n))
+            },
+            (Some(HostFile::Stderr), None) => std::io::stderr().write(&buf),
+            (Some(HostFile::File(file)), None) => (&*file).write(&buf),
+            (Some(HostFile::File(file)), Some(off)) => file.write_at(&buf, off),
+            (Some(_), _) => return -EBADF,
+            (None, _) => return -EBADF,
+        };
+        errno_result(n.map(|n| n as i64))
+    }
+
+    fn sys_openat(&mut self, bus: &mut Bus, dirfd: u64, addr: u64, len: u64, flags: u64, mode: u64) -> i64 {
+        let path = match self.path(bus, dirfd, addr, len) {
+            Ok(path) => path,
+            Err(e) => return e,
+        };
+        let mut options = OpenOptions::new();
+        match flags & O_ACCMODE {
+            O_WRONLY => options.write(true),
+            O_RDWR => options.read(true).write(true),
+            _ => options.read(true),
+        };
+        options
+            .append(flags & O_APPEND != 0)
+            .truncate(flags & O_TRUNC != 0)
+            .mode(mode as u32);
+        if flags & O_CREAT != 0 {
+            if flags & O_EXCL != 0 {
+                options.create_new(true);
+            } else {
+                options.create(true);
+            }
+        }
+        match options.open(path) {
+            Ok(file) => {
+                let file = Some(HostFile::File(Arc::new(file)));
+                match self.fds.iter().position(|f| f.is_none()) {
+                    Some(fd) => {
+                        self.fds[fd] = file;
+                        fd as i64
+                    },
+                    None => {
+                        self.fds.push(file);
+                        self.fds.len() as i64 - 1
+                    },
+                }
+            },
+            Err(e) => errno(&e),
+        }
+    }
+
+    /// Lays out `argc`, `argv` and the argument strings the way `pk` expects
+    /// them: a word array of target pointers followed by the strings.
+    fn sys_getmainvars(&self, bus: &mut Bus, addr: u64, limit: u64) -> i64 {
+        // argc, argv[..], NULL, envp NULL
+        let words = 1 + self.args.len() + 2;
+        let mut pointers = vec![self.args.len() as u64];
+        let mut strings = Vec::new();
+        for arg in &self.args {
+            pointers.push(addr + (words * 8 + strings.len()) as u64);
+            strings.extend_from_slice(arg.as_bytes());
+            strings.push(0);
+        }
+        pointers.push(0);
+        pointers.push(0);
+        let mut buf: Vec<u8> = pointers.iter().flat_map(|p| p.to_le_bytes()).collect();
+        buf.extend_from_slice(&strings);
+        if buf.len() as u64 > limit {
+            return -7; // E2BIG
+        }
+        if bus.write_bytes(addr, &buf) { 0 } else { -EFAULT }
+    }
+}
+
+/// Reads the emulator's stdin on a thread of its own, queueing every byte
+/// for a console device to hand over when the target asks.
+pub fn spawn_stdin_reader(queue: Arc<Mutex<VecDeque<u8>>>) {
+    std::thread::spawn(move || {
+        let mut byte = [0u8];
+        while let Ok(1) = std::io::stdin().read(&mut byte) {
+            queue.lock().unwrap().push_back(byte[0]);
+        }
+    });
+}
+
+fn errno(e: &std::io::Error) -> i64 {
+    -(e.raw_os_error().unwrap_or(5) as i64)
+}
+
+fn errno_result(r: std::io::Result<i64>) -> i64 {
+    match r {
+        Ok(v) => v,
+        Err(e) => errno(&e),
+    }
+}
+
+/// Writes a RISC-V Linux `struct stat` for `meta` at `addr`.
+fn write_stat(bus: &mut Bus, addr: u64, meta: &std::fs::Metadata) -> i64 {
+    let mut stat = [0u8; STAT_SIZE];
+    let mut put = |off: usize, bytes: &[u8]| stat[off..off + bytes.len()].copy_from_slice(bytes);
+    put(0, &meta.dev().to_le_bytes());
+    put(8, &meta.ino().to_le_bytes());
+    put(16, &meta.mode().to_le_bytes());
+    put(20, &(meta.nlink() as u32).to_le_bytes());
+    put(24, &meta.uid().to_le_bytes());
+    put(28, &meta.gid().to_le_bytes());
+    put(32, &meta.rdev().to_le_bytes());
+    put(48, &meta.size().to_le_bytes());
+    put(56, &(meta.blksize() as u32).to_le_bytes());
+    put(64, &meta.blocks().to_le_bytes());
+    put(72, &meta.atime().to_le_bytes());
+    put(80, &meta.atime_nsec().to_le_bytes());
+    put(88, &meta.mtime().to_le_bytes());
+    put(96, &meta.mtime_nsec().to_le_bytes());
+    put(104, &meta.ctime().to_le_bytes());
+    put(112, &meta.ctime_nsec().to_le_bytes());
+    if bus.write_bytes(addr, &stat) { 0 } else { -EFAULT }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Memory::RAM_BASE;
+
+    const TOHOST: u64 = RAM_BASE;
+    const FROMHOST: u64 = RAM_BASE + 8;
+    const MAGIC_MEM: u64 = RAM_BASE + 0x100;
+    const DATA: u64 = RAM_BASE + 0x200;
+
+    fn htif() -> (Htif, Bus) {
+        let htif = Htif::new(TOHOST, Some(FROMHOST), vec!["prog".into(), "-v".into()]);
+        (htif, Bus::new(0x1000))
+    }
+
+    /// Runs a `pk` syscall through `magic_mem` and returns its result,
+    /// checking the host took the command and acknowledged it.
+    fn syscall(htif: &mut Htif, bus: &mut Bus, words: &[u64]) -> i64 {
+        for i in 0..8 {
+            bus.store(MAGIC_MEM + i * 8, 8, words.get(i as usize).copied().unwrap_or(0));
+        }
+        bus.store(FROMHOST, 8, 0);
+        bus.store(TOHOST, 8, MAGIC_MEM);
+        htif.service(bus);
+        assert_eq!(bus.load(TOHOST, 8), Some(0));
+        assert_eq!(bus.load(FROMHOST, 8), Some(1));
+        bus.load(MAGIC_MEM, 8).unwrap() as i64
+    }
+
+    #[test]
+    fn exit() {
+        let (mut htif, mut bus) = htif();
+        htif.service(&mut bus);
+        assert_eq!(htif.exit_code, None);
+        bus.store(TOHOST, 8, (42 << 1) | 1);
+        htif.service(&mut bus);
+        assert_eq!(htif.exit_code, Some(42));
+        assert_eq!(bus.load(TOHOST, 8), Some(0));
+        // exiting is not acknowledged.
+        assert_eq!(bus.load(FROMHOST, 8), Some(0));
+    }
+
+    #[test]
+    fn file_syscalls() {
+        let (mut htif, mut bus) = htif();
+        let path = std::env::temp_dir().join(format!("htif-test-{}", std::process::id()));
+        let mut name = path.to_string_lossy().into_owned().into_bytes();
+        name.push(0);
+        bus.write_bytes(DATA, &name);
+        let open = |flags| [SYS_OPENAT, AT_FDCWD as u64, DATA, name.len() as u64, flags, 0o600];
+        let buf = DATA + 0x100;
+
+        assert_eq!(syscall(&mut htif, &mut bus, &open(O_WRONLY | O_CREAT | O_TRUNC)), 3);
+        bus.write_bytes(buf, b"hello, host");
+        assert_eq!(syscall(&mut htif, &mut bus, &[SYS_WRITE, 3, buf, 11]), 11);
+        assert_eq!(syscall(&mut htif, &mut bus, &[SYS_CLOSE, 3]), 0);
+        assert_eq!(syscall(&mut htif, &mut bus, &[SYS_CLOSE, 3]), -EBADF);
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello, host");
+
+        assert_eq!(syscall(&mut htif, &mut bus, &open(0)), 3, "the lowest free fd is reused");
+        bus.write_bytes(buf, &[0; 16]);
+        assert_eq!(syscall(&mut htif, &mut bus, &[SYS_READ, 3, buf, 64]), 11);
+        assert_eq!(bus.read_bytes(buf, 12).unwrap(), b"hello, host\0");
+        assert_eq!(syscall(&mut htif, &mut bus, &[SYS_READ, 3, buf, 64]), 0);
+        // the buffer has to lie in RAM.
+        assert_eq!(syscall(&mut htif, &mut bus, &[SYS_READ, 3, RAM_BASE + 0xFF0, 64]), -EFAULT);
+        assert_eq!(syscall(&mut htif, &mut bus, &[SYS_CLOSE, 3]), 0);
+        assert_eq!(syscall(&mut htif, &mut bus, &[SYS_READ, 3, buf, 64]), -EBADF);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn getmainvars() {
+        let (mut htif, mut bus) = htif();
+        assert_eq!(syscall(&mut htif, &mut bus, &[SYS_GETMAINVARS, DATA, 0x100, 0]), 0);
+        let word = |i: u64| bus.read_bytes(DATA + i * 8, 8).map(|b| u64::from_le_bytes(b.try_into().unwrap()));
+        // argc, argv[0], argv[1], NULL, envp NULL, then the strings.
+        let strings = DATA + 5 * 8;
+        assert_eq!(
+            (0..5).map(word).collect::<Vec<_>>(),
+            [Some(2), Some(strings), Some(strings + 5), Some(0), Some(0)]
+        );
+        assert_eq!(bus.read_bytes(strings, 8).unwrap(), b"prog\0-v\0");
+        assert_eq!(syscall(&mut htif, &mut bus, &[SYS_GETMAINVARS, DATA, 47, 0]), -7);
+        assert_eq!(syscall(&mut htif, &mut bus, &[12345]), -ENOSYS);
+    }
+
+    #[test]
+    fn console_getchar() {
+        let (mut htif, mut bus) = htif();
+        // keep the test's stdin out of it.
+        htif.console_reader_started = true;
+        bus.store(TOHOST, 8, DEVICE_CONSOLE << 56 | CONSOLE_GETCHAR << 48);
+        htif.service(&mut bus);
+        assert!(htif.getchar_pending());
+        htif.poll(&mut bus);
+        assert_eq!(bus.load(FROMHOST, 8), Some(0), "nothing to answer with yet");
+
+        htif.console_input.lock().unwrap().push_back(b'x');
+        htif.poll(&mut bus);
+        assert!(!htif.getchar_pending());
+        assert_eq!(bus.load(FROMHOST, 8), Some(DEVICE_CONSOLE << 56 | CONSOLE_GETCHAR << 48 | b'x' as u64));
+    }
+}
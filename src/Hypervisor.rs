@@ -0,0 +1,180 @@
+/// A hypervisor virtual-machine load or store, `HLV`, `HLVX` or `HSV`, as
+/// `Decoded` leaves it. They access memory the way the guest would: with
+/// two-stage translation, at the privilege `hstatus.SPVP` selects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HypervisorAccess {
+    pub store: bool,
+    /// Bytes accessed.
+    pub size: u64,
+    /// Loads sign extend, except for the `U` forms.
+    pub signed: bool,
+    /// `HLVX`: needs execute permission rather than read, as a fetch would.
+    pub execute: bool,
+}
+
+impl HypervisorAccess {
+    /// Decodes a SYSTEM word with `funct3` 100, `None` for the reserved
+    /// encodings. The width is in `funct7`, the `U` and `X` forms of the
+    /// loads in `rs2`.
+    pub fn decode(raw: u32) -> Option<Self> {
+        let (funct7, rs2, rd) = (raw >> 25, (raw >> 20) & 0x1F, (raw >> 7) & 0x1F);
+        if raw & 0x7F != 0b1110011 || (raw >> 12) & 0b111 != 0b100 || funct7 >> 3 != 0b0110 {
+            return None;
+        }
+        let size = 1 << ((funct7 >> 1) & 0b11);
+        let store = funct7 & 1 != 0;
+        if store {
+            return (rd == 0).then_some(Self { store: true, size, signed: false, execute: false });
+        }
+        let (signed, execute) = match (size, rs2) {
+            (_, 0) => (true, false),
+            (1 | 2 | 4, 1) => (false, false),
+            (2 | 4, 3) => (false, true),
+            _ => return None,
+        };
+        Some(Self { store: false, size, signed, execute })
+    }
+
+    pub fn mnemonic(&self) -> String {
+        let width = match self.size {
+            1 => "b",
+            2 => "h",
+            4 => "w",
+            _ => "d",
+        };
+        match (self.store, self.execute, self.signed) {
+            (true, _, _) => format!("hsv.{}", width),
+            (false, true, _) => format!("hlvx.{}u", width),
+            (false, false, true) => format!("hlv.{}", width),
+            (false, false, false) => format!("hlv.{}u", width),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Cpu::{Exception, Hart, Privilege, Trap};
+    use crate::Csr::*;
+    use crate::Decode::Decoded;
+    use crate::Memory::{Bus, RAM_BASE};
+
+    const LD: u32 = 0x0005_B503; // ld a0, 0(a1)
+    const ECALL: u32 = 0x0000_0073;
+    const HLV_W: u32 = 0x6805_C573; // hlv.w a0, (a1)
+
+    /// The G-stage root, 16 KiB aligned, and the guest's Sv39 root.
+    const GROOT: u64 = RAM_BASE + 0x4000;
+    const VROOT: u64 = RAM_BASE + 0x8000;
+    /// Guest virtual page 0 is at this guest physical address, which the
+    /// G-stage leaves unmapped.
+    const UNMAPPED_GPA: u64 = 0x1_0000_0000;
+
+    /// A hart in M-mode with two-stage translation set up: the G-stage maps
+    /// the RAM gigapage to itself for VS and VU-mode, and the guest maps it
+    /// to itself for VS-mode and its first gigapage to `UNMAPPED_GPA`.
+    fn machine() -> (Hart, Bus) {
+        let mut hart = Hart::new(0);
+        let mut bus = Bus::new(0x10000);
+        // leaves with V, R, W, X, A and D; U as well in the G-stage.
+        bus.store(GROOT + 2 * 8, 8, (RAM_BASE >> 12) << 10 | 0xDF);
+        bus.store(VROOT + 2 * 8, 8, (RAM_BASE >> 12) << 10 | 0xCF);
+        bus.store(VROOT, 8, (UNMAPPED_GPA >> 12) << 10 | 0xCF);
+        assert!(hart.csr_write(HGATP, HGATP_MODE_SV39X4 << 60 | GROOT >> 12));
+        assert!(hart.csr_write(VSATP, SATP_MODE_SV39 << SATP_MODE_SHIFT | VROOT >> 12));
+        hart.pc = RAM_BASE;
+        (hart, bus)
+    }
+
+    fn enter(hart: &mut Hart, privilege: Privilege, virt: bool) {
+        hart.privilege = privilege;
+        hart.virt = virt;
+    }
+
+    /// Runs `raw`, taking the trap it raises the way `step` would.
+    fn run(hart: &mut Hart, bus: &mut Bus, raw: u32) -> Result<(), Trap> {
+        let inst = Decoded::decode(raw, &hart.isa).unwrap();
+        let result = hart.execute(bus, &inst);
+        if let Err(trap) = result {
+            hart.take_trap(trap.exception as u64, trap.tval, trap.gpa, false);
+        }
+        result
+    }
+
+    #[test]
+    fn guest_page_faults() {
+        let (mut hart, mut bus) = machine();
+        bus.store(RAM_BASE + 0x100, 8, 0x1122_3344_5566_7788);
+        enter(&mut hart, Privilege::Supervisor, true);
+        hart.x[11] = RAM_BASE + 0x100;
+        run(&mut hart, &mut bus, LD).unwrap();
+        assert_eq!(hart.x[10], 0x1122_3344_5566_7788);
+
+        hart.x[11] = 0x1008;
+        let trap = run(&mut hart, &mut bus, LD).unwrap_err();
+        assert_eq!((trap.exception, trap.gpa), (Exception::LoadGuestPageFault, UNMAPPED_GPA + 0x1008));
+        assert_eq!((hart.privilege, hart.virt), (Privilege::Machine, false));
+        assert_eq!((hart.csr.mtval, hart.csr.mtval2), (0x1008, (UNMAPPED_GPA + 0x1008) >> 2));
+        assert_eq!(hart.csr.mstatus & (MSTATUS_GVA | MSTATUS_MPV), MSTATUS_GVA | MSTATUS_MPV);
+
+        // delegated to HS-mode the same goes to `stval`, `htval` and
+        // `hstatus`.
+        let (mut hart, mut bus) = machine();
+        assert!(hart.csr_write(MEDELEG, 1 << Exception::LoadGuestPageFault as u64));
+        enter(&mut hart, Privilege::Supervisor, true);
+        hart.x[11] = 0x1008;
+        run(&mut hart, &mut bus, LD).unwrap_err();
+        assert_eq!((hart.privilege, hart.virt), (Privilege::Supervisor, false));
+        assert_eq!((hart.csr.scause, hart.csr.stval), (21, 0x1008));
+        assert_eq!(hart.csr.htval, (UNMAPPED_GPA + 0x1008) >> 2);
+        assert_eq!(hart.csr.hstatus & (HSTATUS_GVA | HSTATUS_SPV), HSTATUS_GVA | HSTATUS_SPV);
+        assert_eq!(hart.csr.mstatus & MSTATUS_GVA, 0);
+    }
+
+    #[test]
+    fn ecall_routing() {
+        let (mut hart, mut bus) = machine();
+        assert!(hart.csr_write(MEDELEG, 1 << 8 | 1 << 10));
+        // ecalls from VS-mode always go to the hypervisor.
+        assert!(hart.csr_write(HEDELEG, u64::MAX));
+        assert_eq!(hart.csr.hedeleg & (1 << 8 | 1 << 10), 1 << 8);
+        enter(&mut hart, Privilege::Supervisor, true);
+        run(&mut hart, &mut bus, ECALL).unwrap_err();
+        assert_eq!((hart.privilege, hart.virt), (Privilege::Supervisor, false));
+        assert_eq!((hart.csr.scause, hart.csr.sepc), (10, RAM_BASE));
+        assert_eq!(hart.csr.hstatus & (HSTATUS_SPV | HSTATUS_SPVP), HSTATUS_SPV | HSTATUS_SPVP);
+
+        // those from VU-mode the guest kernel takes, through the `vs` CSRs.
+        let (mut hart, mut bus) = machine();
+        assert!(hart.csr_write(MEDELEG, 1 << 8));
+        assert!(hart.csr_write(HEDELEG, 1 << 8));
+        assert!(hart.csr_write(VSTVEC, 0x4000));
+        enter(&mut hart, Privilege::User, true);
+        run(&mut hart, &mut bus, ECALL).unwrap_err();
+        assert_eq!((hart.privilege, hart.virt, hart.pc), (Privilege::Supervisor, true, 0x4000));
+        assert_eq!((hart.csr.vscause, hart.csr.vsepc), (8, RAM_BASE));
+        assert_eq!(hart.csr.scause, 0);
+    }
+
+    #[test]
+    fn hlv_from_user_mode() {
+        let (mut hart, mut bus) = machine();
+        bus.store(RAM_BASE + 0x100, 4, 0x8000_0001);
+        // the guest's own translation would not let VU-mode in.
+        assert!(hart.csr_write(VSATP, 0));
+        hart.x[11] = RAM_BASE + 0x100;
+        enter(&mut hart, Privilege::User, false);
+        assert_eq!(run(&mut hart, &mut bus, HLV_W), Err(Trap::illegal(HLV_W)));
+
+        let (mut hart, mut bus) = machine();
+        bus.store(RAM_BASE + 0x100, 4, 0x8000_0001);
+        assert!(hart.csr_write(VSATP, 0));
+        assert!(hart.csr_write(HSTATUS, HSTATUS_HU));
+        hart.x[11] = RAM_BASE + 0x100;
+        enter(&mut hart, Privilege::User, false);
+        run(&mut hart, &mut bus, HLV_W).unwrap();
+        assert_eq!(hart.x[10], 0xFFFF_FFFF_8000_0001);
+        // guests never may.
+        enter(&mut hart, Privilege::Supervisor, true);
+        assert_eq!(run(&mut hart, &mut bus, HLV_W), Err(Trap::virtual_instruction(HLV_W)));
+    }
+}
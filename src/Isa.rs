@@ -0,0 +1,564 @@
+use std::fmt;
+
+use crate::Cpu::misa_bit;
+use crate::Crypto::CryptoOp;
+
+/// The single-letter extensions, in the canonical order ISA strings list
+/// them.
+const LETTERS: [char; 8] = ['i', 'm', 'a', 'f', 'd', 'c', 'v', 'h'];
+
+/// `misa.MXL` for RV32 and RV64.
+const MISA_MXL_32: u64 = 1 << 30;
+const MISA_MXL_64: u64 = 2 << 62;
+
+/// The optional extensions are cargo features, so a build may not be able
+/// to execute some of them at all; `f` leaves out the floating point unit
+/// altogether. The hypervisor extension `h` is always there.
+const COMPILED_IN: [(char, bool); 6] = [
+    ('m', cfg!(feature = "m")),
+    ('a', cfg!(feature = "a")),
+    ('f', cfg!(feature = "f")),
+    ('d', cfg!(feature = "d")),
+    ('c', cfg!(feature = "c")),
+    ('v', cfg!(feature = "v")),
+];
+
+/// Whether this build can execute `letter`.
+fn compiled_in(letter: char) -> bool {
+    letter == 'i' || letter == 'h' || COMPILED_IN.iter().any(|(l, on)| *l == letter && *on)
+}
+
+/// The base ISA and extensions a hart implements, as given by an ISA
+/// string such as `rv64imafdc_zicsr_zifencei` or `rv32imc`. Instructions
+/// of the others decode as illegal.
+///
+/// Zicsr is always there, as the privileged architecture needs it; like
+/// Spike, `_zicsr` is accepted but changes nothing. Half precision needs
+/// `f`: `_zfhmin` only moves halves around and converts them, `_zfh` also
+/// computes with them.
+///
+/// The vector unit comes with `v`, or with `_zve32x` or `_zve64x` for the
+/// embedded subsets with integer elements of up to 32 or 64 bits. Its
+/// registers are as wide as the extension requires, 128 bits for `v`,
+/// unless a `_zvl<N>b` asks for more.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Isa {
+    /// 32 or 64.
+    xlen: u32,
+    /// `misa` bits of the single-letter extensions.
+    letters: u64,
+    pub zifencei: bool,
+    pub zfh: bool,
+    pub zfhmin: bool,
+    pub zba: bool,
+    pub zbb: bool,
+    pub zbc: bool,
+    pub zbs: bool,
+    pub zbkb: bool,
+    pub zknd: bool,
+    pub zkne: bool,
+    pub zknh: bool,
+    pub zksed: bool,
+    pub zksh: bool,
+    /// VLEN and ELEN in bits, both 0 without a vector unit.
+    vlen: u32,
+    elen: u32,
+}
+
+impl Default for Isa {
+    /// Everything this build can execute.
+    fn default() -> Self {
+        Self {
+            xlen: 64,
+            letters: LETTERS.iter().filter(|l| compiled_in(**l)).fold(0, |bits, l| bits | misa_bit(*l)),
+            zifencei: true,
+            zfh: compiled_in('f'),
+            zfhmin: compiled_in('f'),
+            zba: true,
+            zbb: true,
+            zbc: true,
+            zbs: true,
+            zbkb: true,
+            zknd: true,
+            zkne: true,
+            zknh: true,
+            zksed: true,
+            zksh: true,
+            vlen: if compiled_in('v') { 128 } else { 0 },
+            elen: if compiled_in('v') { 64 } else { 0 },
+        }
+    }
+}
+
+impl Isa {
+    /// Parses an ISA string: `rv32` or `rv64`, then `i` or `g` (`imafd_zicsr_zifencei`),
+    /// more single letters, and `_`-separated multi-letter extensions.
+    /// Case is ignored.
+    pub fn parse(isa: &str) -> Result<Self, String> {
+        let lower = isa.to_ascii_lowercase();
+        let (xlen, rest) = match lower.split_at_checked(4) {
+            Some(("rv32", rest)) => (32, rest),
+            Some(("rv64", rest)) => (64, rest),
+            _ => return Err(format!("`{}` does not start with `rv32` or `rv64`", isa)),
+        };
+        let (letters, multi) = match rest.find(['_', 'z', 's', 'x']) {
+            Some(at) => rest.split_at(at),
+            None => (rest, ""),
+        };
+        let mut out = Self {
+            xlen,
+            letters: 0,
+            zifencei: false,
+            zfh: false,
+            zfhmin: false,
+            zba: false,
+            zbb: false,
+            zbc: false,
+            zbs: false,
+            zbkb: false,
+            zknd: false,
+            zkne: false,
+            zknh: false,
+            zksed: false,
+            zksh: false,
+            vlen: 0,
+            elen: 0,
+        };
+        let mut zvl = 0;
+        for (i, letter) in letters.chars().enumerate() {
+            match letter {
+                'g' if i == 0 => {
+                    out.letters |= "imafd".chars().fold(0, |bits, l| bits | misa_bit(l));
+                    out.zifencei = true;
+                }
+                _ if i == 0 && letter != 'i' => {
+                    return Err(format!("`{}` has to start with the `i` or `g` base after `rv{}`", isa, xlen));
+                }
+                _ if LETTERS.contains(&letter) => out.letters |= misa_bit(letter),
+                _ => return Err(format!("`{}` in `{}` is not a supported extension", letter, isa)),
+            }
+        }
+        if letters.is_empty() {
+            return Err(format!("`{}` has no base ISA", isa));
+        }
+        for name in multi.split('_').filter(|name| !name.is_empty()) {
+            match name {
+                "zicsr" => {}
+                "zifencei" => out.zifencei = true,
+                "zfh" => (out.zfh, out.zfhmin) = (true, true),
+                "zfhmin" => out.zfhmin = true,
+                "zba" => out.zba = true,
+                "zbb" => out.zbb = true,
+                "zbc" => out.zbc = true,
+                "zbs" => out.zbs = true,
+                "zbkb" => out.zbkb = true,
+                "zknd" => out.zknd = true,
+                "zkne" => out.zkne = true,
+                "zknh" => out.zknh = true,
+                "zksed" => out.zksed = true,
+                "zksh" => out.zksh = true,
+                "zve32x" => out.elen = out.elen.max(32),
+                "zve64x" => out.elen = 64,
+                _ if name.starts_with("zvl") && name.ends_with('b') => {
+                    zvl = match name[3..name.len() - 1].parse::<u32>() {
+                        Ok(bits) if bits.is_power_of_two() && (32..=65536).contains(&bits) => zvl.max(bits),
+                        _ => return Err(format!("`{}` in `{}` is not a power of two from 32 to 65536 bits", name, isa)),
+                    };
+                }
+                _ => return Err(format!("`{}` in `{}` is not a supported extension", name, isa)),
+            }
+        }
+        if out.has('d') && !out.has('f') {
+            return Err(format!("`{}`: `d` needs `f`", isa));
+        }
+        if out.zfhmin && !out.has('f') {
+            return Err(format!("`{}`: `{}` needs `f`", isa, if out.zfh { "zfh" } else { "zfhmin" }));
+        }
+        if out.has('v') && !out.has('d') {
+            return Err(format!("`{}`: `v` needs `d`", isa));
+        }
+        if out.has('v') {
+            out.elen = 64;
+        }
+        // `v` has at least 128-bit registers, the embedded subsets at
+        // least ELEN.
+        let min_vlen = if out.has('v') { 128 } else { out.elen };
+        if out.elen == 0 && zvl != 0 {
+            return Err(format!("`{}`: `zvl` needs `v`, `zve32x` or `zve64x`", isa));
+        }
+        out.vlen = min_vlen.max(zvl);
+        if out.elen != 0 && !compiled_in('v') {
+            return Err("the vector unit was left out of this build (cargo feature `v`)".to_string());
+        }
+        if let Some(letter) = LETTERS.iter().find(|l| out.has(**l) && !compiled_in(**l)) {
+            return Err(format!("`{}` was left out of this build (cargo feature `{}`)", letter, letter));
+        }
+        Ok(out)
+    }
+
+    /// The width of the integer registers, 32 or 64.
+    pub fn xlen(&self) -> u32 {
+        self.xlen
+    }
+
+    /// The same extensions on the other base ISA, for a privilege level
+    /// running at a narrower XLEN through `mstatus.UXL`/`SXL`.
+    pub fn with_xlen(self, xlen: u32) -> Self {
+        Self { xlen, ..self }
+    }
+
+    /// The width of the vector registers in bits, 0 without a vector
+    /// unit.
+    pub fn vlen(&self) -> u32 {
+        self.vlen
+    }
+
+    /// The widest vector element in bits, 32 or 64, and 0 without a
+    /// vector unit.
+    pub fn elen(&self) -> u32 {
+        self.elen
+    }
+
+    /// Whether the single-letter extension `letter` is enabled.
+    pub fn has(&self, letter: char) -> bool {
+        self.letters & misa_bit(letter) != 0
+    }
+
+    /// `misa` for a hart with these extensions; S and U mode are always
+    /// there.
+    pub fn misa(&self) -> u64 {
+        let mxl = if self.xlen == 32 { MISA_MXL_32 } else { MISA_MXL_64 };
+        mxl | self.letters | misa_bit('S') | misa_bit('U')
+    }
+
+    /// Whether the encoding `raw` (16 bits for compressed instructions)
+    /// belongs to an enabled extension and exists at this XLEN. Says
+    /// nothing about whether it is a valid instruction at all.
+    pub fn allows(&self, raw: u32) -> bool {
+        if raw & 0b11 != 0b11 {
+            // C.FLD, C.FSD, C.FLDSP and C.FSDSP need `D` too, and on RV32
+            // C.FLW, C.FSW, C.FLWSP and C.FSWSP take the place of the
+            // doubleword loads and stores and need `F`.
+            let double = matches!(raw & 0xE003, 0x2000 | 0xA000 | 0x2002 | 0xA002);
+            let single = self.xlen == 32 && matches!(raw & 0xE003, 0x6000 | 0xE000 | 0x6002 | 0xE002);
+            return self.has('c') && (!double || self.has('d')) && (!single || self.has('f'));
+        }
+        if self.xlen == 32 && rv64_only(raw) {
+            return false;
+        }
+        // an encoding several extensions share, such as `ror` in `Zbb` and
+        // `Zbkb`, needs any one of them.
+        let crypto = crypto(raw);
+        if let Some(name) = bitmanip(raw) {
+            return self.named(name) || crypto.iter().any(|name| self.named(name));
+        }
+        if !crypto.is_empty() {
+            return crypto.iter().any(|name| self.named(name));
+        }
+        let (opcode, funct3, funct7) = (raw & 0x7F, (raw >> 12) & 0b111, raw >> 25);
+        // `Zfhmin` has the half-precision loads, stores and moves, and the
+        // conversions to and from the other formats.
+        let format = |fmt: u32, minimal: bool| match fmt {
+            0b00 => self.has('f'),
+            0b01 => self.has('d'),
+            0b10 => self.zfh || (minimal && self.zfhmin),
+            _ => false,
+        };
+        match opcode {
+            // the hypervisor fences, loads and stores.
+            0b1110011 if funct3 == 0b100 => self.has('h'),
+            0b1110011 if funct3 == 0b000 && matches!(funct7, 0b0010001 | 0b0110001) => self.has('h'),
+            // the vector loads and stores share their opcodes with the
+            // floating point ones, and take 64-bit elements only with
+            // ELEN 64.
+            0b1010111 => self.elen != 0,
+            0b0000111 | 0b0100111 if matches!(funct3, 0b000 | 0b101 | 0b110) => self.elen != 0,
+            0b0000111 | 0b0100111 if funct3 == 0b111 => self.elen == 64,
+            0b0001111 if funct3 == 0b001 => self.zifencei,
+            0b0110011 | 0b0111011 if funct7 == 1 => self.has('m'),
+            0b0101111 => self.has('a'),
+            0b0000111 | 0b0100111 => match funct3 {
+                0b001 => format(0b10, true),
+                0b010 => format(0b00, true),
+                _ => format(0b01, true),
+            },
+            0b1000011 | 0b1000111 | 0b1001011 | 0b1001111 => format(funct7 & 0b11, false),
+            // a conversion between formats needs both, and FCVT.S.D is in
+            // `D` although its result is single-precision.
+            0b1010011 => match funct7 {
+                0b0100000..=0b0100011 => format(funct7 & 0b11, true) && format((raw >> 20) & 0x1F, true),
+                0b1110010 => format(0b10, funct3 == 0b000),
+                0b1111010 => format(0b10, true),
+                _ => format(funct7 & 0b11, false),
+            },
+            _ => true,
+        }
+    }
+
+    /// Whether the multi-letter extension `name`, as `bitmanip` and
+    /// `crypto` spell it, is enabled.
+    fn named(&self, name: &str) -> bool {
+        match name {
+            "Zba" => self.zba,
+            "Zbb" => self.zbb,
+            "Zbc" => self.zbc,
+            "Zbs" => self.zbs,
+            "Zbkb" => self.zbkb,
+            "Zknd" => self.zknd,
+            "Zkne" => self.zkne,
+            "Zknh" => self.zknh,
+            "Zksed" => self.zksed,
+            _ => self.zksh,
+        }
+    }
+}
+
+/// Whether the 32-bit encoding `raw` only exists on RV64: the doubleword
+/// and `*W` instructions, shift amounts of 32 and up, the floating point
+/// conversions and moves to and from 64-bit integers, the RV64 forms of
+/// the AES and SHA-512 instructions, and the hypervisor loads and stores
+/// of doublewords and unsigned words.
+fn rv64_only(raw: u32) -> bool {
+    let (opcode, funct3, funct7, rs2) = (raw & 0x7F, (raw >> 12) & 0b111, raw >> 25, (raw >> 20) & 0x1F);
+    match opcode {
+        0b0000011 => funct3 == 0b011 || funct3 == 0b110,
+        0b0100011 | 0b0101111 => funct3 == 0b011,
+        0b0011011 | 0b0111011 => true,
+        // `aes64im`, `aes64ks1i` and the SHA-512 functions are unary
+        // instructions among the immediate shifts.
+        0b0010011 => {
+            (funct3 == 0b001 || funct3 == 0b101) && funct7 & 1 != 0
+                || funct3 == 0b001 && matches!(raw >> 20, 0x104..=0x107 | 0x300..=0x31A)
+        },
+        // `aes64es`, `aes64esm`, `aes64ds`, `aes64dsm` and `aes64ks2`.
+        0b0110011 => funct3 == 0b000 && matches!(funct7, 0b0011001 | 0b0011011 | 0b0011101 | 0b0011111 | 0b0111111),
+        0b1010011 => match funct7 {
+            0b1100000 | 0b1100001 | 0b1100010 | 0b1101000 | 0b1101001 | 0b1101010 => rs2 & 0b10 != 0,
+            0b1110001 | 0b1111001 => funct3 == 0b000,
+            _ => false,
+        },
+        // `hlv.wu`, `hlv.d` and `hsv.d`.
+        0b1110011 => funct3 == 0b100 && (funct7 == 0b0110100 && rs2 == 1 || matches!(funct7, 0b0110110 | 0b0110111)),
+        _ => false,
+    }
+}
+
+/// The bit manipulation extension, `Zba`, `Zbb`, `Zbc` or `Zbs`, that
+/// the 32-bit encoding `raw` would belong to. They all share the integer
+/// opcodes, so which one is told apart by `funct7` (`funct6` for the
+/// immediate shifts) and `funct3`.
+pub fn bitmanip(raw: u32) -> Option<&'static str> {
+    let (opcode, funct3, funct7, rs2) = (raw & 0x7F, (raw >> 12) & 0b111, raw >> 25, (raw >> 20) & 0x1F);
+    match (opcode, funct7, funct3) {
+        (0b0110011 | 0b0111011, 0b0010000, 0b010 | 0b100 | 0b110) => Some("Zba"),
+        (0b0111011, 0b0000100, 0b000) => Some("Zba"),
+        (0b0011011, 0b0000100 | 0b0000101, 0b001) => Some("Zba"),
+        // `zext.h` is `add.uw`'s neighbour, with `rs2` fixed to zero.
+        (0b0110011 | 0b0111011, 0b0000100, 0b100) if rs2 == 0 => Some("Zbb"),
+        (0b0110011, 0b0100000, 0b100 | 0b110 | 0b111) => Some("Zbb"),
+        (0b0110011, 0b0000101, 0b100..=0b111) => Some("Zbb"),
+        (0b0110011, 0b0000101, 0b001..=0b011) => Some("Zbc"),
+        (0b0110011 | 0b0111011 | 0b0011011, 0b0110000, 0b001 | 0b101) => Some("Zbb"),
+        (0b0010011, 0b0110000 | 0b0110001, 0b001 | 0b101) => Some("Zbb"),
+        // `orc.b` and `rev8`, whose RV64 form has the top bit of `shamt` set.
+        (0b0010011, 0b0010100, 0b101) => Some("Zbb"),
+        (0b0010011, 0b0110100 | 0b0110101, 0b101) if rs2 == 0b11000 => Some("Zbb"),
+        (0b0110011, 0b0010100 | 0b0110100, 0b001) | (0b0110011, 0b0100100, 0b001 | 0b101) => Some("Zbs"),
+        (0b0010011, 0b0010100 | 0b0010101 | 0b0110100 | 0b0110101, 0b001) => Some("Zbs"),
+        (0b0010011, 0b0100100 | 0b0100101, 0b001 | 0b101) => Some("Zbs"),
+        _ => None,
+    }
+}
+
+/// The scalar cryptography extensions that have the 32-bit encoding
+/// `raw`, none if it is not one of theirs. `Zbkb` takes the rotates,
+/// `andn`, `orn`, `xnor` and `rev8` from `Zbb` (which `bitmanip` reports),
+/// and adds packing, `brev8`, `zip` and `unzip`. The AES key schedule is
+/// in both `Zknd` and `Zkne`.
+pub fn crypto(raw: u32) -> &'static [&'static str] {
+    let (opcode, funct3, funct7, imm) = (raw & 0x7F, (raw >> 12) & 0b111, raw >> 25, raw >> 20);
+    match (opcode, funct7, funct3) {
+        (0b0110011 | 0b0111011, 0b0110000, 0b001 | 0b101) | (0b0011011, 0b0110000, 0b101) => &["Zbkb"],
+        (0b0010011, 0b0110000 | 0b0110001, 0b101) => &["Zbkb"],
+        (0b0110011, 0b0100000, 0b100 | 0b110 | 0b111) => &["Zbkb"],
+        (0b0010011, _, 0b101) if imm == 0x698 || imm == 0x6B8 || imm == 0x687 => &["Zbkb"],
+        (0b0110011, 0b0000100, 0b100 | 0b111) | (0b0111011, 0b0000100, 0b100) => &["Zbkb"],
+        (0b0010011, 0b0000100, 0b001 | 0b101) if imm == 0x08F => &["Zbkb"],
+        _ => match CryptoOp::decode(raw, 64).or(CryptoOp::decode(raw, 32)) {
+            Some(op) => op.extensions(),
+            None => &[],
+        },
+    }
+}
+
+impl fmt::Display for Isa {
+    /// The canonical ISA string, as the device tree reports it.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rv{}", self.xlen)?;
+        for letter in LETTERS.iter().filter(|l| self.has(**l)) {
+            write!(f, "{}", letter)?;
+        }
+        f.write_str("_zicsr")?;
+        if self.zifencei {
+            f.write_str("_zifencei")?;
+        }
+        // `zfh` includes `zfhmin`.
+        let named = [
+            ("_zfh", self.zfh),
+            ("_zfhmin", self.zfhmin && !self.zfh),
+            ("_zba", self.zba),
+            ("_zbb", self.zbb),
+            ("_zbc", self.zbc),
+            ("_zbkb", self.zbkb),
+            ("_zbs", self.zbs),
+            ("_zknd", self.zknd),
+            ("_zkne", self.zkne),
+            ("_zknh", self.zknh),
+            ("_zksed", self.zksed),
+            ("_zksh", self.zksh),
+        ];
+        for (name, on) in named {
+            if on {
+                f.write_str(name)?;
+            }
+        }
+        if self.elen != 0 && !self.has('v') {
+            write!(f, "_zve{}x", self.elen)?;
+        }
+        let min_vlen = if self.has('v') { 128 } else { self.elen };
+        if self.vlen > min_vlen {
+            write!(f, "_zvl{}b", self.vlen)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "m", feature = "a", feature = "d", feature = "c", feature = "v"))]
+mod tests {
+    use super::*;
+
+    const DEFAULT: &str = "rv64gcvh_zfh_zba_zbb_zbc_zbkb_zbs_zknd_zkne_zknh_zksed_zksh";
+
+    #[test]
+    fn canonical_strings() {
+        assert_eq!(Isa::parse("rv64gc").unwrap().to_string(), "rv64imafdc_zicsr_zifencei");
+        assert_eq!(Isa::parse("RV64IMAC_Zicsr").unwrap().to_string(), "rv64imac_zicsr");
+        assert_eq!(Isa::parse("rv64i_zicsr_zifencei").unwrap().misa(), MISA_MXL_64 | 0x140100);
+        assert_eq!(Isa::default(), Isa::parse(DEFAULT).unwrap());
+        assert_eq!(Isa::parse("rv64gc_zbs_zba").unwrap().to_string(), "rv64imafdc_zicsr_zifencei_zba_zbs");
+        assert_eq!(Isa::parse("rv32imc").unwrap().to_string(), "rv32imc_zicsr");
+        assert_eq!(Isa::parse("rv32i").unwrap().misa(), MISA_MXL_32 | 0x140100);
+        assert_eq!(Isa::parse(&DEFAULT.replace("rv64", "rv32")).unwrap(), Isa::default().with_xlen(32));
+        assert_eq!(Isa::parse("rv64if_zfhmin").unwrap().to_string(), "rv64if_zicsr_zfhmin");
+        assert_eq!(Isa::parse("rv32if_zfh").unwrap().to_string(), "rv32if_zicsr_zfh");
+        assert_eq!(Isa::parse("rv64gch").unwrap().to_string(), "rv64imafdch_zicsr_zifencei");
+    }
+
+    #[test]
+    fn vector_strings() {
+        let v = Isa::parse("rv64gcv").unwrap();
+        assert_eq!((v.to_string().as_str(), v.vlen(), v.elen()), ("rv64imafdcv_zicsr_zifencei", 128, 64));
+        let wide = Isa::parse("rv64gcv_zvl512b_zvl256b").unwrap();
+        assert_eq!((wide.to_string().as_str(), wide.vlen()), ("rv64imafdcv_zicsr_zifencei_zvl512b", 512));
+        let zve32 = Isa::parse("rv32imc_zve32x").unwrap();
+        assert_eq!((zve32.to_string().as_str(), zve32.vlen(), zve32.elen()), ("rv32imc_zicsr_zve32x", 32, 32));
+        assert_eq!(zve32.misa() & misa_bit('v'), 0);
+        assert_eq!(Isa::parse("rv64imac_zve64x_zvl128b").unwrap().to_string(), "rv64imac_zicsr_zve64x_zvl128b");
+        assert_eq!(Isa::parse("rv64gc").unwrap().vlen(), 0);
+        for isa in ["rv64imacv", "rv64gc_zvl128b", "rv64gcv_zvl100b", "rv64gcv_zvl16b", "rv64gcv_zvlb"] {
+            assert!(Isa::parse(isa).is_err(), "{}", isa);
+        }
+    }
+
+    #[test]
+    fn bad_strings() {
+        for isa in ["rv128i", "rv64", "rv64m", "rv64id", "rv64iq", "rv64i_zbd", "rv64ig", "rv64i_zfh"] {
+            assert!(Isa::parse(isa).is_err(), "{}", isa);
+        }
+    }
+
+    /// Whether an ISA string lets an encoding decode.
+    const GATING: [(&str, u32, bool, &str); 74] = [
+        ("rv64imc", 0x02B50533, true, "mul a0, a0, a1"),
+        ("rv64imc", 0x00B5202F, false, "amoadd.w zero, a1, (a0)"),
+        ("rv64imc", 0x0000100F, false, "fence.i"),
+        ("rv64imc", 0x00000505, true, "c.addi a0, 1"),
+        ("rv64imc", 0x00002008, false, "c.fld f10, 0(s0)"),
+        ("rv64if", 0x00B57553, true, "fadd.s fa0, fa0, fa1"),
+        ("rv64if", 0x02B57553, false, "fadd.d fa0, fa0, fa1"),
+        ("rv64if", 0x40157553, false, "fcvt.s.d fa0, fa0"),
+        ("rv64if", 0x00000505, false, "c.addi a0, 1"),
+        ("rv64if_zfhmin", 0x00251507, true, "flh fa0, 2(a0)"),
+        ("rv64if_zfhmin", 0x40258553, true, "fcvt.s.h fa0, fa1"),
+        ("rv64if_zfhmin", 0xE4058553, true, "fmv.x.h a0, fa1"),
+        ("rv64if_zfhmin", 0x42258553, false, "fcvt.d.h fa0, fa1"),
+        ("rv64if_zfhmin", 0x04C5F553, false, "fadd.h fa0, fa1, fa2"),
+        ("rv64if_zfhmin", 0xE4059553, false, "fclass.h a0, fa1"),
+        ("rv64ifd_zfhmin", 0x42258553, true, "fcvt.d.h fa0, fa1"),
+        ("rv32if_zfh", 0x04C5F553, true, "fadd.h fa0, fa1, fa2"),
+        ("rv32if_zfh", 0xC405F553, true, "fcvt.w.h a0, fa1"),
+        ("rv32if_zfh", 0xC425F553, false, "fcvt.l.h a0, fa1"),
+        ("rv64if", 0x00251507, false, "flh fa0, 2(a0)"),
+        ("rv32gc", 0x00052503, true, "lw a0, 0(a0)"),
+        ("rv32gc", 0x00053503, false, "ld a0, 0(a0)"),
+        ("rv32gc", 0x0015051B, false, "addiw a0, a0, 1"),
+        ("rv32gc", 0x01F51513, true, "slli a0, a0, 31"),
+        ("rv32gc", 0x02051513, false, "slli a0, a0, 32"),
+        ("rv32gc", 0xD0257553, false, "fcvt.s.l fa0, a0"),
+        ("rv32gc", 0x00002001, true, "c.jal 0"),
+        ("rv32gc", 0x00006008, true, "c.flw fa0, 0(s0)"),
+        ("rv64gc_zbb", 0x40B57533, true, "andn a0, a0, a1"),
+        ("rv64gc_zbb", 0x6B855513, true, "rev8 a0, a0"),
+        ("rv64gc_zbb", 0x20B52533, false, "sh1add a0, a0, a1"),
+        ("rv64gc_zbb", 0x0AB51533, false, "clmul a0, a0, a1"),
+        ("rv64gc_zbb", 0x2A851513, false, "bseti a0, a0, 40"),
+        ("rv64i_zba", 0x08B5053B, true, "add.uw a0, a0, a1"),
+        ("rv32i_zba_zbb_zbs", 0x08B5053B, false, "add.uw a0, a0, a1"),
+        ("rv32i_zba_zbb_zbs", 0x6B855513, false, "rev8 a0, a0 (RV64)"),
+        ("rv32i_zba_zbb_zbs", 0x69855513, true, "rev8 a0, a0"),
+        ("rv32i_zba_zbb_zbs", 0x2A851513, false, "bseti a0, a0, 40"),
+        ("rv64gc", 0x02210057, false, "vadd.vv v0, v2, v2"),
+        ("rv64gc", 0x02057007, false, "vle64.v v0, (a0)"),
+        ("rv64gc", 0x00053007, true, "fld ft0, 0(a0)"),
+        ("rv64imac_zve32x", 0x02210057, true, "vadd.vv v0, v2, v2"),
+        ("rv64imac_zve32x", 0x02056007, true, "vle32.v v0, (a0)"),
+        ("rv64imac_zve32x", 0x02057007, false, "vle64.v v0, (a0)"),
+        ("rv64imac_zve32x", 0x00053007, false, "fld ft0, 0(a0)"),
+        (DEFAULT, 0x02057007, true, "vle64.v v0, (a0)"),
+        ("rv64i_zbkb", 0x60C5D533, true, "ror a0, a1, a2"),
+        ("rv64i_zbkb", 0x08C5C533, true, "pack a0, a1, a2"),
+        ("rv64i_zbkb", 0x6875D513, true, "brev8 a0, a1"),
+        ("rv64i_zbkb", 0x60059513, false, "clz a0, a1"),
+        ("rv64i_zbb", 0x60C5D533, true, "ror a0, a1, a2"),
+        ("rv64i_zbb", 0x08C5C533, false, "pack a0, a1, a2"),
+        ("rv64i_zbb", 0x6875D513, false, "brev8 a0, a1"),
+        ("rv64i_zkne", 0x32C58533, true, "aes64es a0, a1, a2"),
+        ("rv64i_zkne", 0x3AC58533, false, "aes64ds a0, a1, a2"),
+        ("rv64i_zkne", 0x31A59513, true, "aes64ks1i a0, a1, 10"),
+        ("rv64i_zknd", 0x31A59513, true, "aes64ks1i a0, a1, 10"),
+        ("rv64i_zkne", 0x10259513, false, "sha256sig0 a0, a1"),
+        ("rv32i_zknd_zkne_zknh_zksed_zksh", 0x32C58533, false, "aes64es a0, a1, a2"),
+        ("rv32i_zknd_zkne_zknh_zksed_zksh", 0xE2C58533, true, "aes32esi a0, a1, a2, 3"),
+        ("rv32i_zknd_zkne_zknh_zksed_zksh", 0x10259513, true, "sha256sig0 a0, a1"),
+        ("rv32i_zknd_zkne_zknh_zksed_zksh", 0x10659513, false, "sha512sig0 a0, a1"),
+        ("rv32i_zknd_zkne_zknh_zksed_zksh", 0x5CC58533, true, "sha512sig0h a0, a1, a2"),
+        ("rv32i_zknd_zkne_zknh_zksed_zksh", 0xF0C58533, true, "sm4ed a0, a1, a2, 3"),
+        ("rv32i_zknd_zkne_zknh_zksed_zksh", 0x10859513, true, "sm3p0 a0, a1"),
+        ("rv64gc", 0x22000073, false, "hfence.vvma zero, zero"),
+        ("rv64gc", 0x6005C573, false, "hlv.b a0, (a1)"),
+        ("rv64gc", 0x12000073, true, "sfence.vma zero, zero"),
+        ("rv64gch", 0x62000073, true, "hfence.gvma zero, zero"),
+        ("rv64gch", 0x6C05C573, true, "hlv.d a0, (a1)"),
+        ("rv32ich", 0x6805C573, true, "hlv.w a0, (a1)"),
+        ("rv32ich", 0x6815C573, false, "hlv.wu a0, (a1)"),
+        ("rv32ich", 0x6EA5C073, false, "hsv.d a0, (a1)"),
+        ("rv32ich", 0x6AA5C073, true, "hsv.w a0, (a1)"),
+    ];
+
+    #[test]
+    fn gating() {
+        for (isa, raw, allowed, asm) in GATING {
+            assert_eq!(Isa::parse(isa).unwrap().allows(raw), allowed, "{} in {}", asm, isa);
+        }
+    }
+}
@@ -0,0 +1,1504 @@
+use std::collections::HashMap;
+use std::mem::offset_of;
+use std::sync::Arc;
+
+use crate::BlockCache::Block;
+use crate::Cpu::{Access, Hart, Privilege, Trap};
+use crate::Csr::{MSTATUS_MXR, MSTATUS_SUM};
+use crate::Decode::{Decoded, Op};
+use crate::Disassembler::disassemble;
+use crate::Execute::sext;
+use crate::Isa::Isa;
+use crate::Memory::{Bus, PAGE_SHIFT, PAGE_SIZE};
+
+/// Bytes of executable memory translated blocks are written to. When it
+/// fills up every translation is thrown away and the cache starts over.
+pub const CODE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Entries of the data TLB in `JitContext`, direct-mapped by virtual page.
+const TLB_SIZE: usize = 256;
+const TLB_ENTRY_SHIFT: u32 = 5;
+/// A TLB tag no virtual page number can have.
+const NO_PAGE: u64 = u64::MAX;
+
+/// Translated blocks a hart has entered, by virtual pc, in front of the
+/// map by physical pc.
+const LOOKUP_SIZE: usize = 4096;
+
+/// `JitContext::status` after a block: ran to its end, stopped early after
+/// an instruction that has to be looked at, or trapped.
+const STATUS_OK: u64 = 0;
+const STATUS_STOP: u64 = 1;
+const STATUS_TRAP: u64 = 2;
+
+/// Flags packed with the size and pc offset handed to the memory helpers.
+const INFO_SIGNED: u64 = 1 << 4;
+const INFO_OFFSET_SHIFT: u64 = 8;
+
+/// One data TLB entry: `host address = vaddr + delta` for loads from
+/// `read` and stores to `write`, the virtual page numbers it is valid for.
+/// `page` is the RAM page behind it, for dirty tracking and code checks.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct TlbEntry {
+    read: u64,
+    write: u64,
+    delta: u64,
+    page: u64,
+}
+
+const EMPTY_TLB_ENTRY: TlbEntry = TlbEntry {
+    read: NO_PAGE,
+    write: NO_PAGE,
+    delta: 0,
+    page: 0,
+};
+
+/// Everything translated code touches, reached through `rbx`. The guest
+/// registers live here while a run is in progress; `pc` holds the virtual
+/// address of the running block, so one translation serves every mapping
+/// of the same physical code.
+#[derive(Debug)]
+#[repr(C)]
+pub struct JitContext {
+    pub x: [u64; 32],
+    pub pc: u64,
+    /// Instructions the last block retired.
+    retired: u64,
+    status: u64,
+    hart: *mut Hart,
+    bus: *mut Bus,
+    /// `Ram::dirty`, one flag per RAM page.
+    dirty: *mut bool,
+    /// Pages stores have to leave to the helper because blocks were
+    /// decoded from them, `code_len` flags long.
+    code_pages: *const bool,
+    code_len: u64,
+    /// The RAM page holding `tohost`, whose stores the devices must see.
+    htif_page: u64,
+    tlb: [TlbEntry; TLB_SIZE],
+}
+
+/// What the data TLB was filled under; any change empties it. Mappings
+/// that change without one of these changing are architecturally allowed
+/// to stay cached until the next `SFENCE.VMA`, which bumps `fetch_epoch`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TlbKey {
+    privilege: Privilege,
+    virt: bool,
+    data_mode: (Privilege, bool),
+    satp: u64,
+    vsatp: u64,
+    hgatp: u64,
+    status: u64,
+    vsstatus: u64,
+    epoch: u64,
+    ram: usize,
+}
+
+/// A translated block, by the physical pc of the block it came from.
+#[derive(Debug, Clone)]
+struct Translation {
+    block: Arc<Block>,
+    /// Where the code starts in the code buffer, meaningless if `count`
+    /// is 0.
+    entry: usize,
+    /// Leading instructions of `block` that were translated; 0 when the
+    /// first one has to be left to the interpreter.
+    count: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LookupEntry {
+    pc: u64,
+    privilege: Privilege,
+    virt: bool,
+    epoch: u64,
+    generation: u64,
+    entry: usize,
+    count: u64,
+}
+
+/// Translates the decoded blocks of `BlockCache` into x86-64 and runs them
+/// in place of the interpreter.
+///
+/// Integer computation, jumps, branches, loads and stores are translated;
+/// everything else ends the translation before it and is left to the
+/// interpreter, as are CSR and privileged instructions. Loads and stores
+/// go through a small TLB of host pointers and only call back into the
+/// hart on a miss, a misaligned access, a store to a page code was decoded
+/// from or to `tohost`.
+///
+/// With `check` set every block is run twice: translated, then undone and
+/// run again by the interpreter, and the registers, CSRs and memory writes
+/// of the two compared. The interpreter's results are kept; the first
+/// difference is left in `mismatch` and stops the machine.
+#[derive(Debug)]
+pub struct Jit {
+    pub check: bool,
+    pub mismatch: Option<String>,
+    code: Option<CodeBuffer>,
+    translations: HashMap<u64, Translation>,
+    lookup: Vec<Option<LookupEntry>>,
+    context: Box<JitContext>,
+    tlb_key: Option<TlbKey>,
+    /// In `check` mode, a code page flag set for every page, so stores
+    /// that hit the TLB still go through the helper and get logged.
+    store_guard: Vec<bool>,
+}
+
+impl Clone for Jit {
+    /// A copy starts with nothing translated, its code buffer allocated
+    /// when first needed.
+    fn clone(&self) -> Self {
+        Self::empty(self.check)
+    }
+}
+
+impl Jit {
+    /// Returns an error if this host cannot run translated code.
+    pub fn new(check: bool) -> Result<Self, String> {
+        let mut jit = Self::empty(check);
+        jit.code = Some(CodeBuffer::new(CODE_SIZE)?);
+        Ok(jit)
+    }
+
+    fn empty(check: bool) -> Self {
+        Self {
+            check,
+            mismatch: None,
+            code: None,
+            translations: HashMap::new(),
+            lookup: vec![None; LOOKUP_SIZE],
+            context: Box::new(JitContext {
+                x: [0; 32],
+                pc: 0,
+                retired: 0,
+                status: STATUS_OK,
+                hart: std::ptr::null_mut(),
+                bus: std::ptr::null_mut(),
+                dirty: std::ptr::null_mut(),
+                code_pages: std::ptr::null(),
+                code_len: 0,
+                htif_page: NO_PAGE,
+                tlb: [EMPTY_TLB_ENTRY; TLB_SIZE],
+            }),
+            tlb_key: None,
+            store_guard: Vec::new(),
+        }
+    }
+
+    /// Runs translated blocks on `hart` for at most `budget` instructions,
+    /// stopping after a trap, a store to `tohost`, or before a block that
+    /// is not translated or would not fit. Returns the instructions run,
+    /// traps included; 0 means the interpreter has to take the next one.
+    pub fn run(&mut self, hart: &mut Hart, bus: &mut Bus, budget: u64) -> u64 {
+        if self.code.is_none() {
+            match CodeBuffer::new(CODE_SIZE) {
+                Ok(code) => self.code = Some(code),
+                Err(_) => return 0,
+            }
+        }
+        // translated code checks for interrupts only between runs, and
+        // never reports accesses to the debugger's watchpoints.
+        if self.mismatch.is_some()
+            || hart.pending_interrupt().is_some()
+            || !bus.watchpoints.is_empty()
+            || bus.journal.is_some()
+            || !bus.blocks.enabled
+        {
+            return 0;
+        }
+        self.prepare(hart, bus);
+        self.context.x = hart.x;
+        self.context.pc = hart.pc;
+        let mut rounds = 0;
+        while rounds < budget {
+            let entry = match self.lookup(hart, bus) {
+                Some((entry, count)) if count <= budget - rounds => entry,
+                _ => break,
+            };
+            let (steps, status) = match self.check {
+                true => self.run_checked(hart, bus, entry),
+                false => self.call(hart, bus, entry),
+            };
+            rounds += steps;
+            if status == STATUS_TRAP
+                || self.mismatch.is_some()
+                || bus.htif.as_ref().is_some_and(|htif| htif.tohost_written)
+            {
+                break;
+            }
+        }
+        hart.x = self.context.x;
+        rounds
+    }
+
+    /// Points the context at `bus`, emptying the TLB if what it was filled
+    /// under changed.
+    fn prepare(&mut self, hart: &Hart, bus: &mut Bus) {
+        let key = TlbKey {
+            privilege: hart.privilege,
+            virt: hart.virt,
+            data_mode: hart.data_mode(),
+            satp: hart.csr.satp,
+            vsatp: hart.csr.vsatp,
+            hgatp: hart.csr.hgatp,
+            status: hart.csr.mstatus & (MSTATUS_SUM | MSTATUS_MXR),
+            vsstatus: hart.csr.vsstatus & (MSTATUS_SUM | MSTATUS_MXR),
+            epoch: hart.fetch_epoch,
+            ram: bus.ram.data.as_ptr() as usize,
+        };
+        if self.tlb_key != Some(key) {
+            self.flush_tlb();
+            self.tlb_key = Some(key);
+        }
+        let context = &mut self.context;
+        context.dirty = bus.ram.dirty.as_mut_ptr();
+        context.htif_page = match &bus.htif {
+            Some(htif) => bus.ram.offset(htif.tohost, 8).map_or(NO_PAGE, |off| (off >> PAGE_SHIFT) as u64),
+            None => NO_PAGE,
+        };
+        if self.check && self.store_guard.len() != bus.ram.dirty.len() {
+            self.store_guard = vec![true; bus.ram.dirty.len()];
+        }
+    }
+
+    fn flush_tlb(&mut self) {
+        self.context.tlb = [EMPTY_TLB_ENTRY; TLB_SIZE];
+    }
+
+    /// Finds, or translates, the block at `hart.pc`. `None` when there is
+    /// no decoded block there or its first instruction is not translated.
+    fn lookup(&mut self, hart: &mut Hart, bus: &mut Bus) -> Option<(usize, u64)> {
+        let generation = bus.blocks.generation();
+        let slot = (hart.pc >> 1) as usize % LOOKUP_SIZE;
+        if let Some(entry) = &self.lookup[slot] {
+            if entry.pc == hart.pc
+                && entry.privilege == hart.privilege
+                && entry.virt == hart.virt
+                && entry.epoch == hart.fetch_epoch
+                && entry.generation == generation
+            {
+                return (entry.count > 0).then_some((entry.entry, entry.count));
+            }
+        }
+        let paddr = hart.fetch_translate(bus)?;
+        let isa = hart.decode_isa();
+        let block = bus.blocks.lookup(&bus.ram, paddr, &isa)?;
+        // the page walk may have set A bits in a page code came from.
+        let generation = bus.blocks.generation();
+        let translation = match self.translations.get(&paddr) {
+            Some(translation) if Arc::ptr_eq(&translation.block, &block) => translation.clone(),
+            _ => {
+                let translation = self.translate(block, &isa)?;
+                self.translations.insert(paddr, translation.clone());
+                translation
+            }
+        };
+        self.lookup[slot] = Some(LookupEntry {
+            pc: hart.pc,
+            privilege: hart.privilege,
+            virt: hart.virt,
+            epoch: hart.fetch_epoch,
+            generation,
+            entry: translation.entry,
+            count: translation.count,
+        });
+        (translation.count > 0).then_some((translation.entry, translation.count))
+    }
+
+    fn translate(&mut self, block: Arc<Block>, isa: &Isa) -> Option<Translation> {
+        let count = block.insts.iter().take_while(|d| translatable(d, isa)).count();
+        if count == 0 {
+            return Some(Translation { block, entry: 0, count: 0 });
+        }
+        let bytes = emit_block(&block.insts[..count]);
+        let code = self.code.as_mut()?;
+        let entry = match code.push(&bytes) {
+            Some(entry) => entry,
+            None => {
+                // out of room: start over with an empty buffer.
+                code.clear();
+                self.translations.clear();
+                self.lookup.iter_mut().for_each(|entry| *entry = None);
+                code.push(&bytes)?
+            }
+        };
+        Some(Translation {
+            block,
+            entry,
+            count: count as u64,
+        })
+    }
+
+    /// Runs the block at `entry` once. Returns the instructions it ran,
+    /// counting one that trapped, and how it ended.
+    fn call(&mut self, hart: &mut Hart, bus: &mut Bus, entry: usize) -> (u64, u64) {
+        let code = match &self.code {
+            Some(code) => code,
+            None => return (0, STATUS_OK),
+        };
+        let context = &mut *self.context;
+        let guard = if self.check { &self.store_guard } else { bus.blocks.code_pages() };
+        context.code_pages = guard.as_ptr();
+        context.code_len = guard.len() as u64;
+        context.retired = 0;
+        context.status = STATUS_OK;
+        context.hart = hart;
+        context.bus = bus;
+        // SAFETY: `entry` is the start of a function `emit_block` wrote to
+        // the executable buffer, which only reads and writes the context
+        // and the RAM the TLB points into, and reaches the hart and bus
+        // through the helpers below while nothing else holds them.
+        unsafe {
+            let block: unsafe extern "C" fn(*mut JitContext) = std::mem::transmute(code.ptr.add(entry));
+            block(context);
+        }
+        context.hart = std::ptr::null_mut();
+        context.bus = std::ptr::null_mut();
+        let steps = context.retired + (context.status == STATUS_TRAP) as u64;
+        if hart.csr.mcountinhibit & 0b001 == 0 {
+            hart.csr.mcycle = hart.csr.mcycle.wrapping_add(steps);
+        }
+        if hart.csr.mcountinhibit & 0b100 == 0 {
+            hart.csr.minstret = hart.csr.minstret.wrapping_add(context.retired);
+        }
+        hart.pc = context.pc;
+        (steps, context.status)
+    }
+
+    /// `call`, then the same instructions again through the interpreter
+    /// from the same starting point, comparing the two.
+    fn run_checked(&mut self, hart: &mut Hart, bus: &mut Bus, entry: usize) -> (u64, u64) {
+        hart.x = self.context.x;
+        let before = hart.clone();
+        let reservations = bus.reservations.clone();
+        let tohost_written = bus.htif.as_ref().is_some_and(|htif| htif.tohost_written);
+        // each block starts from an empty TLB, so the page walks that
+        // refill it show up in both runs alike.
+        self.flush_tlb();
+        bus.journal = Some(Vec::new());
+        bus.undo = Some(Vec::new());
+        let (steps, status) = self.call(hart, bus, entry);
+        let jit_writes = bus.journal.take().unwrap_or_default();
+        let undo = bus.undo.take().unwrap_or_default();
+        let mut translated = hart.clone();
+        translated.x = self.context.x;
+
+        for (addr, bytes) in undo.iter().rev() {
+            bus.write_bytes(*addr, bytes);
+        }
+        // the undo writes look like a device's and broke the reservations.
+        bus.reservations = reservations;
+        if let Some(htif) = &mut bus.htif {
+            htif.tohost_written = tohost_written;
+        }
+        *hart = before;
+        let start = hart.pc;
+        bus.journal = Some(Vec::new());
+        for _ in 0..steps {
+            hart.step(bus);
+        }
+        let interpreter_writes = bus.journal.take().unwrap_or_default();
+        self.context.x = hart.x;
+        self.context.pc = hart.pc;
+
+        let mut differences = Vec::new();
+        for reg in 1..32 {
+            if translated.x[reg] != hart.x[reg] {
+                differences.push(format!("x{:<2} jit {:#018x}  interpreter {:#018x}", reg, translated.x[reg], hart.x[reg]));
+            }
+        }
+        if translated.pc != hart.pc {
+            differences.push(format!("pc  jit {:#018x}  interpreter {:#018x}", translated.pc, hart.pc));
+        }
+        if translated.privilege != hart.privilege {
+            differences.push(format!("privilege  jit {:?}  interpreter {:?}", translated.privilege, hart.privilege));
+        }
+        if translated.csr != hart.csr {
+            differences.push(format!("CSRs\n  jit         {:x?}\n  interpreter {:x?}", translated.csr, hart.csr));
+        }
+        if jit_writes != interpreter_writes {
+            differences.push(format!(
+                "memory writes\n  jit         {}\n  interpreter {}",
+                format_writes(&jit_writes),
+                format_writes(&interpreter_writes)
+            ));
+        }
+        if !differences.is_empty() {
+            let mut report = format!("jit mismatch in the block at {:#018x} after {} instructions:\n", start, steps);
+            if let Some(block) = hart.debug_translate(bus, start).and_then(|paddr| bus.blocks.lookup(&bus.ram, paddr, &hart.decode_isa())) {
+                let mut pc = start;
+                for decoded in block.insts.iter().take(steps as usize) {
+                    report.push_str(&format!("  {:#018x}: {:08x}  {}\n", pc, decoded.raw, disassemble(decoded.raw, hart.xlen())));
+                    pc = pc.wrapping_add(decoded.size());
+                }
+            }
+            for difference in differences {
+                report.push_str(&difference);
+                report.push('\n');
+            }
+            self.mismatch = Some(report);
+        }
+        (steps, status)
+    }
+}
+
+fn format_writes(writes: &[(u64, Vec<u8>)]) -> String {
+    let list: Vec<String> = writes
+        .iter()
+        .map(|(addr, bytes)| {
+            let hex: String = bytes.iter().rev().map(|b| format!("{:02x}", b)).collect();
+            format!("{:#x}={}", addr, hex)
+        })
+        .collect();
+    if list.is_empty() { "none".to_string() } else { list.join(" ") }
+}
+
+/// Whether `inst` is translated rather than left to the interpreter.
+fn translatable(inst: &Decoded, isa: &Isa) -> bool {
+    use Op::*;
+    // the translated code computes at XLEN 64 only.
+    if isa.xlen() == 32 {
+        return false;
+    }
+    match inst.op {
+        // shift amounts of 32 and up are illegal in the W forms.
+        SLLIW | SRLIW | SRAIW => inst.imm < 32,
+        // without `C` jump targets have to be 4-byte aligned, which the
+        // translated code does not check.
+        JAL | BEQ | BNE | BLT | BGE | BLTU | BGEU if !isa.has('c') => inst.imm % 4 == 0,
+        JALR if !isa.has('c') => false,
+        LUI | AUIPC | JAL | JALR
+        | BEQ | BNE | BLT | BGE | BLTU | BGEU
+        | LB | LH | LW | LD | LBU | LHU | LWU
+        | SB | SH | SW | SD
+        | ADDI | SLTI | SLTIU | XORI | ORI | ANDI | SLLI | SRLI | SRAI
+        | ADD | SUB | SLL | SLT | SLTU | XOR | SRL | SRA | OR | AND
+        | ADDIW | ADDW | SUBW | SLLW | SRLW | SRAW
+        | FENCE => true,
+        _ => false,
+    }
+}
+
+// The memory helpers translated code calls on the slow path. `info` holds
+// the access size, `INFO_SIGNED` for loads, and the offset of the
+// instruction from the start of the block, so a trap can point at it.
+
+/// Records a trap taken by the instruction `offset` bytes into the block.
+fn trap(context: &mut JitContext, hart: &mut Hart, offset: u64, trap: Trap) {
+    hart.pc = context.pc.wrapping_add(offset);
+    hart.take_trap(trap.exception as u64, trap.tval, trap.gpa, false);
+    context.pc = hart.pc;
+    context.status = STATUS_TRAP;
+}
+
+/// Caches the translation of `vaddr` after an access through the hart
+/// succeeded. A store fills both tags, as writable pages are readable.
+fn fill_tlb(context: &mut JitContext, hart: &mut Hart, bus: &mut Bus, vaddr: u64, access: Access) {
+    let paddr = match hart.translate(bus, vaddr, access) {
+        Ok(paddr) => paddr & !(PAGE_SIZE as u64 - 1),
+        Err(_) => return,
+    };
+    let off = match bus.ram.offset(paddr, PAGE_SIZE as u64) {
+        Some(off) => off,
+        None => return,
+    };
+    let page = (off >> PAGE_SHIFT) as u64;
+    if access == Access::Store && page == context.htif_page {
+        return;
+    }
+    let vpage = vaddr >> PAGE_SHIFT;
+    let delta = (bus.ram.data.as_mut_ptr() as u64)
+        .wrapping_add(off as u64)
+        .wrapping_sub(vpage << PAGE_SHIFT);
+    let entry = &mut context.tlb[vpage as usize % TLB_SIZE];
+    if entry.delta != delta || entry.page != page || (entry.read != vpage && entry.write != vpage) {
+        *entry = TlbEntry { read: NO_PAGE, write: NO_PAGE, delta, page };
+    }
+    entry.read = vpage;
+    if access == Access::Store {
+        entry.write = vpage;
+    }
+}
+
+unsafe extern "C" fn jit_load(context: *mut JitContext, vaddr: u64, info: u64) -> u64 {
+    let context = &mut *context;
+    let (hart, bus) = (&mut *context.hart, &mut *context.bus);
+    let size = info & 0xF;
+    match hart.load(bus, vaddr, size) {
+        Ok(value) => {
+            fill_tlb(context, hart, bus, vaddr, Access::Load);
+            if info & INFO_SIGNED != 0 { sext(value, size as u32 * 8) } else { value }
+        }
+        Err(e) => {
+            trap(context, hart, info >> INFO_OFFSET_SHIFT, e);
+            0
+        }
+    }
+}
+
+unsafe extern "C" fn jit_store(context: *mut JitContext, vaddr: u64, value: u64, info: u64) {
+    let context = &mut *context;
+    let (hart, bus) = (&mut *context.hart, &mut *context.bus);
+    let generation = bus.blocks.generation();
+    match hart.store(bus, vaddr, info & 0xF, value) {
+        Ok(()) => {
+            fill_tlb(context, hart, bus, vaddr, Access::Store);
+            // code was overwritten, possibly the rest of this very block,
+            // or the devices have to see the store before anything else.
+            if bus.blocks.generation() != generation || bus.htif.as_ref().is_some_and(|htif| htif.tohost_written) {
+                context.status = STATUS_STOP;
+            }
+        }
+        Err(e) => trap(context, hart, info >> INFO_OFFSET_SHIFT, e),
+    }
+}
+
+/// Executable memory translated code is copied into.
+#[derive(Debug)]
+struct CodeBuffer {
+    ptr: *mut u8,
+    len: usize,
+    used: usize,
+}
+
+#[cfg(all(target_arch = "x86_64", unix))]
+mod host {
+    use std::ffi::{c_int, c_long, c_void};
+
+    extern "C" {
+        fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: c_long) -> *mut c_void;
+        fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+
+    const PROT_READ: c_int = 1;
+    const PROT_WRITE: c_int = 2;
+    const PROT_EXEC: c_int = 4;
+    const MAP_PRIVATE: c_int = 0x02;
+    #[cfg(target_os = "macos")]
+    const MAP_ANONYMOUS: c_int = 0x1000;
+    #[cfg(not(target_os = "macos"))]
+    const MAP_ANONYMOUS: c_int = 0x20;
+
+    pub fn map(len: usize) -> Result<*mut u8, String> {
+        // SAFETY: a fresh anonymous mapping aliases nothing.
+        let ptr = unsafe {
+            mmap(std::ptr::null_mut(), len, PROT_READ | PROT_WRITE | PROT_EXEC, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0)
+        };
+        if ptr as isize == -1 {
+            return Err(format!("could not map memory for the JIT: {}", std::io::Error::last_os_error()));
+        }
+        Ok(ptr as *mut u8)
+    }
+
+    pub fn unmap(ptr: *mut u8, len: usize) {
+        // SAFETY: `ptr` came from `map` with the same `len`.
+        unsafe {
+            munmap(ptr as *mut c_void, len);
+        }
+    }
+}
+
+#[cfg(not(all(target_arch = "x86_64", unix)))]
+mod host {
+    pub fn map(_len: usize) -> Result<*mut u8, String> {
+        Err("the JIT needs an x86-64 Unix host".to_string())
+    }
+
+    pub fn unmap(_ptr: *mut u8, _len: usize) {}
+}
+
+impl CodeBuffer {
+    fn new(len: usize) -> Result<Self, String> {
+        Ok(Self {
+            ptr: host::map(len)?,
+            len,
+            used: 0,
+        })
+    }
+
+    /// Copies `bytes` in, returning their offset, or `None` if they do not
+    /// fit any more.
+    fn push(&mut self, bytes: &[u8]) -> Option<usize> {
+        let start = self.used.next_multiple_of(16);
+        if start + bytes.len() > self.len {
+            return None;
+        }
+        // SAFETY: the range was checked to lie within the mapping.
+        unsafe {
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), self.ptr.add(start), bytes.len());
+        }
+        self.used = start + bytes.len();
+        Some(start)
+    }
+
+    fn clear(&mut self) {
+        self.used = 0;
+    }
+}
+
+impl Drop for CodeBuffer {
+    fn drop(&mut self) {
+        host::unmap(self.ptr, self.len);
+    }
+}
+
+// x86-64 registers, as numbered in ModRM. Only the first eight are used,
+// so no instruction needs REX.R or REX.B. `rbx` holds the context for the
+// whole block; the others are scratch.
+const RAX: u8 = 0;
+const RCX: u8 = 1;
+const RDX: u8 = 2;
+const RBX: u8 = 3;
+const RSI: u8 = 6;
+const RDI: u8 = 7;
+
+// condition codes, as added to the `Jcc` and `SETcc` opcodes.
+const CC_B: u8 = 0x2;
+const CC_AE: u8 = 0x3;
+const CC_E: u8 = 0x4;
+const CC_NE: u8 = 0x5;
+const CC_L: u8 = 0xC;
+const CC_GE: u8 = 0xD;
+
+// `/digit` extensions of the group 1 (`81`) and group 2 (`C1`, `D3`)
+// opcodes, and the matching register forms of group 1.
+const ALU_ADD: u8 = 0;
+const ALU_OR: u8 = 1;
+const ALU_AND: u8 = 4;
+const ALU_SUB: u8 = 5;
+const ALU_XOR: u8 = 6;
+const ALU_CMP: u8 = 7;
+const SHIFT_SHL: u8 = 4;
+const SHIFT_SHR: u8 = 5;
+const SHIFT_SAR: u8 = 7;
+
+/// `op r/m, r` opcodes beyond group 1.
+const OP_MOV: u8 = 0x89;
+const OP_TEST: u8 = 0x85;
+/// `op r, r/m` opcodes, for operands in memory.
+const OP_LOAD: u8 = 0x8B;
+const OP_ADD_FROM: u8 = 0x03;
+const OP_CMP_FROM: u8 = 0x3B;
+
+/// The `op r/m, r` opcode of a group 1 operation.
+fn alu_opcode(ext: u8) -> u8 {
+    ext * 8 + 1
+}
+
+const REX_W: u8 = 0x48;
+
+fn context_offset_x(reg: usize) -> usize {
+    offset_of!(JitContext, x) + reg * 8
+}
+
+fn tlb_offset(field: usize) -> usize {
+    offset_of!(JitContext, tlb) + field
+}
+
+#[derive(Debug, Default)]
+struct Assembler {
+    code: Vec<u8>,
+}
+
+impl Assembler {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn imm32(&mut self, value: i32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn modrm(&mut self, mode: u8, reg: u8, rm: u8) {
+        self.code.push((mode << 6) | (reg << 3) | rm);
+    }
+
+    /// The `[rbx + disp32]` operand, a field of the context.
+    fn context_operand(&mut self, reg: u8, disp: usize) {
+        self.modrm(0b10, reg, RBX);
+        self.imm32(disp as i32);
+    }
+
+    /// The `[rbx + rdx + disp32]` operand, a field of the TLB entry `rdx`
+    /// is the byte offset of.
+    fn tlb_operand(&mut self, reg: u8, field: usize) {
+        self.modrm(0b10, reg, 0b100);
+        self.code.push((RDX << 3) | RBX);
+        self.imm32(tlb_offset(field) as i32);
+    }
+
+    /// `op reg, [rbx + disp]`, `opcode` being an `op r, r/m` form.
+    fn op_context(&mut self, opcode: u8, reg: u8, disp: usize) {
+        self.bytes(&[REX_W, opcode]);
+        self.context_operand(reg, disp);
+    }
+
+    fn load_context(&mut self, reg: u8, disp: usize) {
+        self.op_context(OP_LOAD, reg, disp);
+    }
+
+    fn store_context(&mut self, disp: usize, reg: u8) {
+        self.op_context(OP_MOV, reg, disp);
+    }
+
+    /// `mov qword [rbx + disp], imm32`.
+    fn store_context_imm(&mut self, disp: usize, value: i32) {
+        self.bytes(&[REX_W, 0xC7]);
+        self.context_operand(0, disp);
+        self.imm32(value);
+    }
+
+    /// `add qword [rbx + disp], imm32`.
+    fn add_context_imm(&mut self, disp: usize, value: i32) {
+        self.bytes(&[REX_W, 0x81]);
+        self.context_operand(ALU_ADD, disp);
+        self.imm32(value);
+    }
+
+    fn op_tlb(&mut self, opcode: u8, reg: u8, field: usize) {
+        self.bytes(&[REX_W, opcode]);
+        self.tlb_operand(reg, field);
+    }
+
+    /// Loads guest register `guest` into `reg`.
+    fn load_guest(&mut self, reg: u8, guest: usize) {
+        if guest == 0 {
+            self.alu32(alu_opcode(ALU_XOR), reg, reg);
+        } else {
+            self.load_context(reg, context_offset_x(guest));
+        }
+    }
+
+    /// Stores `reg` to guest register `guest`, unless it is `x0`.
+    fn store_guest(&mut self, guest: usize, reg: u8) {
+        if guest != 0 {
+            self.store_context(context_offset_x(guest), reg);
+        }
+    }
+
+    /// `op dst, src` on 64 bits, `opcode` being an `op r/m, r` form.
+    fn alu(&mut self, opcode: u8, dst: u8, src: u8) {
+        self.bytes(&[REX_W, opcode]);
+        self.modrm(0b11, src, dst);
+    }
+
+    fn alu32(&mut self, opcode: u8, dst: u8, src: u8) {
+        self.code.push(opcode);
+        self.modrm(0b11, src, dst);
+    }
+
+    fn alu_imm(&mut self, ext: u8, dst: u8, value: i32) {
+        self.bytes(&[REX_W, 0x81]);
+        self.modrm(0b11, ext, dst);
+        self.imm32(value);
+    }
+
+    fn shift_imm(&mut self, ext: u8, dst: u8, amount: u32) {
+        self.bytes(&[REX_W, 0xC1]);
+        self.modrm(0b11, ext, dst);
+        self.code.push(amount as u8);
+    }
+
+    fn shift32_imm(&mut self, ext: u8, dst: u8, amount: u32) {
+        self.code.push(0xC1);
+        self.modrm(0b11, ext, dst);
+        self.code.push(amount as u8);
+    }
+
+    fn shift_cl(&mut self, ext: u8, dst: u8) {
+        self.bytes(&[REX_W, 0xD3]);
+        self.modrm(0b11, ext, dst);
+    }
+
+    fn shift32_cl(&mut self, ext: u8, dst: u8) {
+        self.code.push(0xD3);
+        self.modrm(0b11, ext, dst);
+    }
+
+    /// Sign extends the low 32 bits of `reg` into all of it.
+    fn sext32(&mut self, reg: u8) {
+        self.bytes(&[REX_W, 0x63]);
+        self.modrm(0b11, reg, reg);
+    }
+
+    /// `rax = flags say cc ? 1 : 0`.
+    fn set_rax(&mut self, cc: u8) {
+        self.bytes(&[0x0F, 0x90 | cc, 0xC0, 0x0F, 0xB6, 0xC0]);
+    }
+
+    fn mov_imm(&mut self, dst: u8, value: u64) {
+        if value as i64 == value as i32 as i64 {
+            self.bytes(&[REX_W, 0xC7]);
+            self.modrm(0b11, 0, dst);
+            self.imm32(value as i32);
+        } else if value <= u32::MAX as u64 {
+            self.code.push(0xB8 + dst);
+            self.bytes(&(value as u32).to_le_bytes());
+        } else {
+            self.bytes(&[REX_W, 0xB8 + dst]);
+            self.bytes(&value.to_le_bytes());
+        }
+    }
+
+    /// `dst += value`, with `rdi` as scratch for values beyond 32 bits.
+    fn add_imm(&mut self, dst: u8, value: u64) {
+        if value == 0 {
+        } else if value as i64 == value as i32 as i64 {
+            self.alu_imm(ALU_ADD, dst, value as i32);
+        } else {
+            self.mov_imm(RDI, value);
+            self.alu(alu_opcode(ALU_ADD), dst, RDI);
+        }
+    }
+
+    /// A `Jcc rel32` to be bound later; returns where its offset goes.
+    fn jcc(&mut self, cc: u8) -> usize {
+        self.bytes(&[0x0F, 0x80 | cc]);
+        self.imm32(0);
+        self.code.len() - 4
+    }
+
+    fn jmp(&mut self) -> usize {
+        self.code.push(0xE9);
+        self.imm32(0);
+        self.code.len() - 4
+    }
+
+    /// Points the jump whose offset is at `at` here.
+    fn bind(&mut self, at: usize) {
+        let rel = (self.code.len() - (at + 4)) as i32;
+        self.code[at..at + 4].copy_from_slice(&rel.to_le_bytes());
+    }
+
+    fn call(&mut self, target: u64) {
+        self.bytes(&[REX_W, 0xB8 + RAX]);
+        self.bytes(&target.to_le_bytes());
+        self.bytes(&[0xFF, 0xD0]);
+    }
+}
+
+/// Emits the x86-64 function for `insts`, the translatable start of a
+/// block: `extern "C" fn(*mut JitContext)`, leaving in the context the
+/// instructions retired, the pc to go on at, and how the block ended.
+fn emit_block(insts: &[Decoded]) -> Vec<u8> {
+    let mut asm = Assembler::default();
+    let mut exits = Vec::new();
+    // push rbx; mov rbx, rdi
+    asm.code.push(0x53);
+    asm.alu(OP_MOV, RBX, RDI);
+    let mut off = 0;
+    let mut ended = false;
+    for (index, decoded) in insts.iter().enumerate() {
+        let at = Site {
+            index: index as u64,
+            off,
+            next: off + decoded.size(),
+        };
+        ended = emit_instruction(&mut asm, &mut exits, decoded, &at, insts.len() as u64);
+        off = at.next;
+    }
+    if !ended {
+        asm.store_context_imm(offset_of!(JitContext, retired), insts.len() as i32);
+        asm.add_context_imm(offset_of!(JitContext, pc), off as i32);
+    }
+    for exit in exits {
+        asm.bind(exit);
+    }
+    // pop rbx; ret
+    asm.bytes(&[0x5B, 0xC3]);
+    asm.code
+}
+
+/// Where an instruction sits in its block.
+struct Site {
+    index: u64,
+    /// Byte offsets of the instruction and the one after it.
+    off: u64,
+    next: u64,
+}
+
+/// Emits one instruction, adding jumps to the end of the block to `exits`.
+/// Returns whether it ended the block, having set the pc and count.
+fn emit_instruction(asm: &mut Assembler, exits: &mut Vec<usize>, inst: &Decoded, at: &Site, count: u64) -> bool {
+    use Op::*;
+    let (rd, rs1, rs2) = (inst.rd as usize, inst.rs1 as usize, inst.rs2 as usize);
+    let imm = inst.imm as u64;
+    let retired = offset_of!(JitContext, retired);
+    let pc = offset_of!(JitContext, pc);
+    match inst.op {
+        LUI => {
+            if rd != 0 {
+                asm.mov_imm(RAX, imm);
+                asm.store_guest(rd, RAX);
+            }
+        }
+        AUIPC => {
+            if rd != 0 {
+                asm.load_context(RAX, pc);
+                asm.add_imm(RAX, imm.wrapping_add(at.off));
+                asm.store_guest(rd, RAX);
+            }
+        }
+        JAL => {
+            asm.store_context_imm(retired, count as i32);
+            if rd != 0 {
+                asm.load_context(RAX, pc);
+                asm.add_imm(RAX, at.next);
+                asm.store_guest(rd, RAX);
+            }
+            asm.add_context_imm(pc, imm.wrapping_add(at.off) as i32);
+            return true;
+        }
+        JALR => {
+            asm.store_context_imm(retired, count as i32);
+            asm.load_guest(RAX, rs1);
+            asm.add_imm(RAX, imm);
+            asm.alu_imm(ALU_AND, RAX, -2);
+            if rd != 0 {
+                asm.load_context(RCX, pc);
+                asm.add_imm(RCX, at.next);
+                asm.store_guest(rd, RCX);
+            }
+            asm.store_context(pc, RAX);
+            return true;
+        }
+        BEQ | BNE | BLT | BGE | BLTU | BGEU => {
+            let cc = match inst.op {
+                BEQ => CC_E,
+                BNE => CC_NE,
+                BLT => CC_L,
+                BGE => CC_GE,
+                BLTU => CC_B,
+                _ => CC_AE,
+            };
+            asm.store_context_imm(retired, count as i32);
+            asm.load_guest(RAX, rs1);
+            asm.load_guest(RCX, rs2);
+            asm.alu(alu_opcode(ALU_CMP), RAX, RCX);
+            let taken = asm.jcc(cc);
+            asm.add_context_imm(pc, at.next as i32);
+            exits.push(asm.jmp());
+            asm.bind(taken);
+            asm.add_context_imm(pc, imm.wrapping_add(at.off) as i32);
+            return true;
+        }
+        LB => emit_load(asm, exits, inst, 1, true, at),
+        LH => emit_load(asm, exits, inst, 2, true, at),
+        LW => emit_load(asm, exits, inst, 4, true, at),
+        LD => emit_load(asm, exits, inst, 8, false, at),
+        LBU => emit_load(asm, exits, inst, 1, false, at),
+        LHU => emit_load(asm, exits, inst, 2, false, at),
+        LWU => emit_load(asm, exits, inst, 4, false, at),
+        SB => emit_store(asm, exits, inst, 1, at),
+        SH => emit_store(asm, exits, inst, 2, at),
+        SW => emit_store(asm, exits, inst, 4, at),
+        SD => emit_store(asm, exits, inst, 8, at),
+        ADDI | SLTI | SLTIU | XORI | ORI | ANDI | ADDIW => {
+            if rd == 0 {
+                return false;
+            }
+            let imm = imm as i32;
+            asm.load_guest(RAX, rs1);
+            match inst.op {
+                ADDI => asm.add_imm(RAX, imm as u64),
+                ADDIW => {
+                    asm.add_imm(RAX, imm as u64);
+                    asm.sext32(RAX);
+                }
+                SLTI | SLTIU => {
+                    asm.alu_imm(ALU_CMP, RAX, imm);
+                    asm.set_rax(if matches!(inst.op, SLTI) { CC_L } else { CC_B });
+                }
+                XORI => asm.alu_imm(ALU_XOR, RAX, imm),
+                ORI => asm.alu_imm(ALU_OR, RAX, imm),
+                _ => asm.alu_imm(ALU_AND, RAX, imm),
+            }
+            asm.store_guest(rd, RAX);
+        }
+        SLLI | SRLI | SRAI | SLLIW | SRLIW | SRAIW => {
+            if rd == 0 {
+                return false;
+            }
+            asm.load_guest(RAX, rs1);
+            match inst.op {
+                SLLI => asm.shift_imm(SHIFT_SHL, RAX, imm as u32),
+                SRLI => asm.shift_imm(SHIFT_SHR, RAX, imm as u32),
+                SRAI => asm.shift_imm(SHIFT_SAR, RAX, imm as u32),
+                SLLIW => asm.shift32_imm(SHIFT_SHL, RAX, imm as u32),
+                SRLIW => asm.shift32_imm(SHIFT_SHR, RAX, imm as u32),
+                _ => asm.shift32_imm(SHIFT_SAR, RAX, imm as u32),
+            }
+            if matches!(inst.op, SLLIW | SRLIW | SRAIW) {
+                asm.sext32(RAX);
+            }
+            asm.store_guest(rd, RAX);
+        }
+        ADD | SUB | SLL | SLT | SLTU | XOR | SRL | SRA | OR | AND
+        | ADDW | SUBW | SLLW | SRLW | SRAW => {
+            if rd == 0 {
+                return false;
+            }
+            asm.load_guest(RAX, rs1);
+            asm.load_guest(RCX, rs2);
+            match inst.op {
+                ADD | ADDW => asm.alu(alu_opcode(ALU_ADD), RAX, RCX),
+                SUB | SUBW => asm.alu(alu_opcode(ALU_SUB), RAX, RCX),
+                XOR => asm.alu(alu_opcode(ALU_XOR), RAX, RCX),
+                OR => asm.alu(alu_opcode(ALU_OR), RAX, RCX),
+                AND => asm.alu(alu_opcode(ALU_AND), RAX, RCX),
+                SLT | SLTU => {
+                    asm.alu(alu_opcode(ALU_CMP), RAX, RCX);
+                    asm.set_rax(if matches!(inst.op, SLT) { CC_L } else { CC_B });
+                }
+                // x86 masks shift counts to 6 bits, and to 5 on 32 bits,
+                // just like RISC-V.
+                SLL => asm.shift_cl(SHIFT_SHL, RAX),
+                SRL => asm.shift_cl(SHIFT_SHR, RAX),
+                SRA => asm.shift_cl(SHIFT_SAR, RAX),
+                SLLW => asm.shift32_cl(SHIFT_SHL, RAX),
+                SRLW => asm.shift32_cl(SHIFT_SHR, RAX),
+                _ => asm.shift32_cl(SHIFT_SAR, RAX),
+            }
+            if matches!(inst.op, ADDW | SUBW | SLLW | SRLW | SRAW) {
+                asm.sext32(RAX);
+            }
+            asm.store_guest(rd, RAX);
+        }
+        FENCE => {}
+        _ => unreachable!("`translatable` let {:?} through", inst.op),
+    }
+    false
+}
+
+/// Leaves in `rdx` the byte offset of the TLB entry for the address in
+/// `rax`, and its virtual page number in `rcx`.
+fn emit_tlb_index(asm: &mut Assembler) {
+    // mov rcx, rax; shr rcx, 12; mov edx, ecx; and edx, TLB_SIZE - 1; shl edx, 5
+    asm.alu(OP_MOV, RCX, RAX);
+    asm.shift_imm(SHIFT_SHR, RCX, PAGE_SHIFT as u32);
+    asm.alu32(OP_MOV, RDX, RCX);
+    asm.bytes(&[0x81]);
+    asm.modrm(0b11, ALU_AND, RDX);
+    asm.imm32(TLB_SIZE as i32 - 1);
+    asm.shift32_imm(SHIFT_SHL, RDX, TLB_ENTRY_SHIFT);
+}
+
+/// Adds to `slow` a jump taken when the address in `rax` is not aligned
+/// to `size`; only aligned accesses stay within one page.
+fn emit_alignment_check(asm: &mut Assembler, size: u64, slow: &mut Vec<usize>) {
+    if size > 1 {
+        // test al, size - 1
+        asm.bytes(&[0xA8, size as u8 - 1]);
+        slow.push(asm.jcc(CC_NE));
+    }
+}
+
+fn emit_load(asm: &mut Assembler, exits: &mut Vec<usize>, inst: &Decoded, size: u64, signed: bool, at: &Site) {
+    let mut slow = Vec::new();
+    asm.load_guest(RAX, inst.rs1 as usize);
+    asm.add_imm(RAX, inst.imm as u64);
+    emit_alignment_check(asm, size, &mut slow);
+    emit_tlb_index(asm);
+    asm.op_tlb(OP_CMP_FROM, RCX, offset_of!(TlbEntry, read));
+    slow.push(asm.jcc(CC_NE));
+    asm.op_tlb(OP_ADD_FROM, RAX, offset_of!(TlbEntry, delta));
+    // mov{zx,sx} rax, [rax]
+    match (size, signed) {
+        (1, false) => asm.bytes(&[REX_W, 0x0F, 0xB6, 0x00]),
+        (1, true) => asm.bytes(&[REX_W, 0x0F, 0xBE, 0x00]),
+        (2, false) => asm.bytes(&[REX_W, 0x0F, 0xB7, 0x00]),
+        (2, true) => asm.bytes(&[REX_W, 0x0F, 0xBF, 0x00]),
+        (4, false) => asm.bytes(&[0x8B, 0x00]),
+        (4, true) => asm.bytes(&[REX_W, 0x63, 0x00]),
+        _ => asm.bytes(&[REX_W, 0x8B, 0x00]),
+    }
+    let join = asm.jmp();
+
+    for label in slow {
+        asm.bind(label);
+    }
+    let info = size | if signed { INFO_SIGNED } else { 0 } | (at.off << INFO_OFFSET_SHIFT);
+    asm.alu(OP_MOV, RSI, RAX);
+    asm.alu(OP_MOV, RDI, RBX);
+    asm.mov_imm(RDX, info);
+    asm.call(jit_load as *const () as u64);
+    asm.load_context(RCX, offset_of!(JitContext, status));
+    asm.alu(OP_TEST, RCX, RCX);
+    let ok = asm.jcc(CC_E);
+    asm.store_context_imm(offset_of!(JitContext, retired), at.index as i32);
+    exits.push(asm.jmp());
+    asm.bind(ok);
+
+    asm.bind(join);
+    asm.store_guest(inst.rd as usize, RAX);
+}
+
+fn emit_store(asm: &mut Assembler, exits: &mut Vec<usize>, inst: &Decoded, size: u64, at: &Site) {
+    let mut slow = Vec::new();
+    asm.load_guest(RAX, inst.rs1 as usize);
+    asm.add_imm(RAX, inst.imm as u64);
+    emit_alignment_check(asm, size, &mut slow);
+    emit_tlb_index(asm);
+    asm.op_tlb(OP_CMP_FROM, RCX, offset_of!(TlbEntry, write));
+    slow.push(asm.jcc(CC_NE));
+    // stores to pages code was decoded from have to drop that code.
+    asm.op_tlb(OP_LOAD, RCX, offset_of!(TlbEntry, page));
+    asm.op_context(OP_CMP_FROM, RCX, offset_of!(JitContext, code_len));
+    let clean = asm.jcc(CC_AE);
+    asm.load_context(RSI, offset_of!(JitContext, code_pages));
+    // cmp byte [rsi + rcx], 0
+    asm.bytes(&[0x80, 0x3C, 0x0E, 0x00]);
+    slow.push(asm.jcc(CC_NE));
+    asm.bind(clean);
+    asm.load_context(RSI, offset_of!(JitContext, dirty));
+    // mov byte [rsi + rcx], 1
+    asm.bytes(&[0xC6, 0x04, 0x0E, 0x01]);
+    asm.op_tlb(OP_ADD_FROM, RAX, offset_of!(TlbEntry, delta));
+    asm.load_guest(RCX, inst.rs2 as usize);
+    // mov [rax], {cl,cx,ecx,rcx}
+    match size {
+        1 => asm.bytes(&[0x88, 0x08]),
+        2 => asm.bytes(&[0x66, 0x89, 0x08]),
+        4 => asm.bytes(&[0x89, 0x08]),
+        _ => asm.bytes(&[REX_W, 0x89, 0x08]),
+    }
+    let join = asm.jmp();
+
+    for label in slow {
+        asm.bind(label);
+    }
+    asm.alu(OP_MOV, RSI, RAX);
+    asm.alu(OP_MOV, RDI, RBX);
+    asm.load_guest(RDX, inst.rs2 as usize);
+    asm.mov_imm(RCX, size | (at.off << INFO_OFFSET_SHIFT));
+    asm.call(jit_store as *const () as u64);
+    asm.load_context(RCX, offset_of!(JitContext, status));
+    asm.alu(OP_TEST, RCX, RCX);
+    let ok = asm.jcc(CC_E);
+    asm.alu_imm(ALU_CMP, RCX, STATUS_STOP as i32);
+    let trapped = asm.jcc(CC_NE);
+    // the store went through but the block cannot go on.
+    asm.store_context_imm(offset_of!(JitContext, retired), at.index as i32 + 1);
+    asm.add_context_imm(offset_of!(JitContext, pc), at.next as i32);
+    exits.push(asm.jmp());
+    asm.bind(trapped);
+    asm.store_context_imm(offset_of!(JitContext, retired), at.index as i32);
+    exits.push(asm.jmp());
+    asm.bind(ok);
+
+    asm.bind(join);
+}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+    use super::*;
+    use crate::Memory::RAM_BASE;
+
+    /// ALU and W-form results, accesses straddling the page boundary at
+    /// `s0`, a store over an instruction it then runs, and a loop whose
+    /// `beq` alternates between taken and not taken.
+    const PROGRAM: [u32; 52] = [
+        0x80002437, // lui s0, 0x80002
+        0x02041413, // slli s0, s0, 32
+        0x02045413, // srli s0, s0, 32
+        0x12345537, // lui a0, 0x12345
+        0x6785051B, // addiw a0, a0, 0x678
+        0xFFD00593, // addi a1, zero, -3
+        0x00B50633, // add a2, a0, a1
+        0x40A586B3, // sub a3, a1, a0
+        0x00B54733, // xor a4, a0, a1
+        0x00B567B3, // or a5, a0, a1
+        0x00B57833, // and a6, a0, a1
+        0x00B518B3, // sll a7, a0, a1
+        0x00A5D933, // srl s2, a1, a0
+        0x40A5D9B3, // sra s3, a1, a0
+        0x00A5AA33, // slt s4, a1, a0
+        0x00A5BAB3, // sltu s5, a1, a0
+        0x02351B13, // slli s6, a0, 35
+        0x4076DB93, // srai s7, a3, 7
+        0xFFF53C13, // sltiu s8, a0, -1
+        0x7FF50C9B, // addiw s9, a0, 2047
+        0x00A50D3B, // addw s10, a0, a0
+        0x40A58DBB, // subw s11, a1, a0
+        0x00B51E3B, // sllw t3, a0, a1
+        0x00B5DEBB, // srlw t4, a1, a1
+        0x40B5DF3B, // sraw t5, a1, a1
+        0x01151F9B, // slliw t6, a0, 17
+        0xFEA43E23, // sd a0, -4(s0)
+        0xFEB42F23, // sw a1, -2(s0)
+        0x00D410A3, // sh a3, 1(s0)
+        0xFFC43283, // ld t0, -4(s0)
+        0xFFE42303, // lw t1, -2(s0)
+        0x00145383, // lhu t2, 1(s0)
+        0xFFF40183, // lb gp, -1(s0)
+        0xFFE46203, // lwu tp, -2(s0)
+        0x00543423, // sd t0, 8(s0)
+        0x00643823, // sd t1, 16(s0)
+        0x00000297, // auipc t0, 0
+        0x0102A303, // lw t1, 16(t0)
+        0x0062AA23, // sw t1, 20(t0)
+        0x0080006F, // j .+8
+        0x00508093, // addi ra, ra, 5
+        0x00108093, // addi ra, ra, 1, overwritten by the one above
+        0x00A00293, // addi t0, zero, 10
+        0x00000393, // addi t2, zero, 0
+        0x00000493, // addi s1, zero, 0
+        0x00338393, // loop: addi t2, t2, 3
+        0x0013FE13, // andi t3, t2, 1
+        0x000E0463, // beq t3, zero, .+8
+        0x00148493, // addi s1, s1, 1
+        0xFFF28293, // addi t0, t0, -1
+        0xFE0296E3, // bne t0, zero, loop
+        0x0000006F, // j .
+    ];
+
+    const BRANCHES: [u32; 54] = [
+        0xFFF00413, // addi s0, zero, -1
+        0x00100493, // addi s1, zero, 1
+        0x00000513, // addi a0, zero, 0
+        0x00151513, // slli a0, a0, 1
+        0x00840463, // beq s0, s0, .+8
+        0x00150513, // addi a0, a0, 1
+        0x00151513, // slli a0, a0, 1
+        0x00940463, // beq s0, s1, .+8
+        0x00150513, // addi a0, a0, 1
+        0x00151513, // slli a0, a0, 1
+        0x00941463, // bne s0, s1, .+8
+        0x00150513, // addi a0, a0, 1
+        0x00151513, // slli a0, a0, 1
+        0x00949463, // bne s1, s1, .+8
+        0x00150513, // addi a0, a0, 1
+        0x00151513, // slli a0, a0, 1
+        0x00944463, // blt s0, s1, .+8
+        0x00150513, // addi a0, a0, 1
+        0x00151513, // slli a0, a0, 1
+        0x0084C463, // blt s1, s0, .+8
+        0x00150513, // addi a0, a0, 1
+        0x00151513, // slli a0, a0, 1
+        0x00845463, // bge s0, s0, .+8
+        0x00150513, // addi a0, a0, 1
+        0x00151513, // slli a0, a0, 1
+        0x00945463, // bge s0, s1, .+8
+        0x00150513, // addi a0, a0, 1
+        0x00151513, // slli a0, a0, 1
+        0x0084E463, // bltu s1, s0, .+8
+        0x00150513, // addi a0, a0, 1
+        0x00151513, // slli a0, a0, 1
+        0x00946463, // bltu s0, s1, .+8
+        0x00150513, // addi a0, a0, 1
+        0x00151513, // slli a0, a0, 1
+        0x00947463, // bgeu s0, s1, .+8
+        0x00150513, // addi a0, a0, 1
+        0x00151513, // slli a0, a0, 1
+        0x0084F463, // bgeu s1, s0, .+8
+        0x00150513, // addi a0, a0, 1
+        0x00500313, // addi t1, zero, 5
+        0x00000613, // addi a2, zero, 0
+        0xFFF30313, // loop: addi t1, t1, -1
+        0x00360613, // addi a2, a2, 3
+        0xFE604CE3, // blt zero, t1, loop
+        0x00000297, // auipc t0, 0
+        0x00F28293, // addi t0, t0, 15
+        0x001280E7, // jalr ra, 1(t0), to the second `addi` below
+        0x06300593, // addi a1, zero, 99
+        0x00008593, // addi a1, ra, 0
+        0x0080096F, // jal s2, .+8
+        0x06300693, // addi a3, zero, 99
+        0x00000717, // auipc a4, 0
+        0x41270733, // sub a4, a4, s2
+        0x0000006F, // j .
+    ];
+
+    /// Every width signed and unsigned, misaligned, and a store into the
+    /// page the code is on, at `s0`.
+    const MEMORY: [u32; 30] = [
+        0x80001437, // lui s0, 0x80001
+        0x02041413, // slli s0, s0, 32
+        0x02045413, // srli s0, s0, 32
+        0x88776537, // lui a0, 0x88776
+        0x6555051B, // addiw a0, a0, 0x655
+        0x02051513, // slli a0, a0, 32
+        0x443325B7, // lui a1, 0x44332
+        0x21158593, // addi a1, a1, 0x211
+        0x00B56533, // or a0, a0, a1
+        0x00A43023, // sd a0, 0(s0)
+        0x00A42423, // sw a0, 8(s0)
+        0x00A41623, // sh a0, 12(s0)
+        0x00A40723, // sb a0, 14(s0)
+        0x00A438A3, // sd a0, 17(s0)
+        0x00A42F23, // sw a0, 30(s0)
+        0x02A411A3, // sh a0, 35(s0)
+        0x00740283, // lb t0, 7(s0)
+        0x00744303, // lbu t1, 7(s0)
+        0x00641383, // lh t2, 6(s0)
+        0x00645E03, // lhu t3, 6(s0)
+        0x00442E83, // lw t4, 4(s0)
+        0x00446F03, // lwu t5, 4(s0)
+        0x01143F83, // ld t6, 17(s0)
+        0x01E42483, // lw s1, 30(s0)
+        0x02341903, // lh s2, 35(s0)
+        0x00D45983, // lhu s3, 13(s0)
+        0xFF843A03, // ld s4, -8(s0)
+        0xFEA40FA3, // sb a0, -1(s0)
+        0xFFE45A83, // lhu s5, -2(s0)
+        0x0000006F, // j .
+    ];
+
+    /// An `ecall` and a load and a store outside of RAM, each skipped by
+    /// `trap`, which counts them in `s1` and sums their causes in `s2`.
+    const TRAPS: [u32; 20] = [
+        0x00000297, // auipc t0, 0
+        0x03028293, // addi t0, t0, 48
+        0x30529073, // csrw mtvec, t0
+        0x00700513, // addi a0, zero, 7
+        0x00000073, // ecall
+        0x00150513, // addi a0, a0, 1
+        0x10000313, // addi t1, zero, 0x100
+        0x00033583, // ld a1, 0(t1)
+        0x00150513, // addi a0, a0, 1
+        0x00A33423, // sd a0, 8(t1)
+        0x00150513, // addi a0, a0, 1
+        0x0200006F, // j done
+        0x341023F3, // trap: csrr t2, mepc
+        0x00438393, // addi t2, t2, 4
+        0x34139073, // csrw mepc, t2
+        0x00148493, // addi s1, s1, 1
+        0x34202E73, // csrr t3, mcause
+        0x01C90933, // add s2, s2, t3
+        0x30200073, // mret
+        0x0000006F, // done: j .
+    ];
+
+    /// The W forms on operands whose upper halves disagree with their low
+    /// words, and shift amounts with bits above the five that count.
+    const WORDS: [u32; 21] = [
+        0x80000537, // lui a0, 0x80000
+        0xFFF50513, // addi a0, a0, -1
+        0x00100593, // addi a1, zero, 1
+        0x02059593, // slli a1, a1, 32
+        0x02158593, // addi a1, a1, 33
+        0x0015029B, // addiw t0, a0, 1
+        0x00A5033B, // addw t1, a0, a0
+        0x40A003BB, // subw t2, zero, a0
+        0x00B51E3B, // sllw t3, a0, a1
+        0x00B55EBB, // srlw t4, a0, a1
+        0x40B55F3B, // sraw t5, a0, a1
+        0x01F51F9B, // slliw t6, a0, 31
+        0x01F5541B, // srliw s0, a0, 31
+        0x41F5549B, // sraiw s1, a0, 31
+        0x0005D91B, // srliw s2, a1, 0
+        0x4005D99B, // sraiw s3, a1, 0
+        0xFDE58A1B, // addiw s4, a1, -34
+        0x00A59ABB, // sllw s5, a1, a0
+        0x00A5DB3B, // srlw s6, a1, a0
+        0x40A55BBB, // sraw s7, a0, a0
+        0x0000006F, // j .
+    ];
+
+    const ONES: u64 = u64::MAX;
+
+    /// Runs `program` to its final `j .` on a hart of `xlen` bits, through
+    /// `jit` where it can.
+    fn run(program: &[u32], xlen: u32, mut jit: Option<&mut Jit>) -> (Hart, Bus) {
+        let mut bus = Bus::new(3 * PAGE_SIZE as u64);
+        let code: Vec<u8> = program.iter().flat_map(|word| word.to_le_bytes()).collect();
+        assert!(bus.write_bytes(RAM_BASE, &code));
+        let mut hart = Hart::new(0);
+        hart.set_isa(Isa::default().with_xlen(xlen));
+        hart.pc = RAM_BASE;
+        let end = RAM_BASE + (program.len() as u64 - 1) * 4;
+        for _ in 0..1000 {
+            if hart.pc == end {
+                break;
+            }
+            let rounds = jit.as_mut().map_or(0, |jit| jit.run(&mut hart, &mut bus, 16));
+            if rounds == 0 {
+                hart.step(&mut bus);
+            }
+        }
+        assert_eq!(hart.pc, end);
+        (hart, bus)
+    }
+
+    /// Runs `program` through the interpreter, then through a `Jit` with
+    /// and without `check`, and returns the interpreter's results once all
+    /// three agree.
+    fn cross_check(program: &[u32], xlen: u32) -> (Hart, Bus) {
+        let (expected, expected_bus) = run(program, xlen, None);
+        for check in [false, true] {
+            let mut jit = Jit::new(check).unwrap();
+            let (hart, bus) = run(program, xlen, Some(&mut jit));
+            // RV32 is left to the interpreter.
+            let translated = jit.translations.values().any(|translation| translation.count > 0);
+            assert_eq!(translated, xlen == 64, "check: {}", check);
+            assert_eq!(jit.mismatch, None);
+            assert_eq!(hart.x, expected.x, "check: {}", check);
+            // translated code can spin on the final `j .` a few more times
+            // before `run` gets to see it.
+            let mut csr = hart.csr.clone();
+            (csr.mcycle, csr.minstret) = (expected.csr.mcycle, expected.csr.minstret);
+            assert_eq!(csr, expected.csr, "check: {}", check);
+            assert!(bus.ram.data == expected_bus.ram.data, "check: {}", check);
+        }
+        (expected, expected_bus)
+    }
+
+    #[test]
+    fn matches_the_interpreter() {
+        let (expected, expected_bus) = cross_check(&PROGRAM, 64);
+        assert_eq!(expected.x[1], 5);
+        assert_eq!(expected.x[9], 5);
+        // `lw t1, -2(s0)` reads the `sw` across the page boundary, with
+        // its top byte replaced by the low one of `sh a3, 1(s0)`.
+        let page = 2 * PAGE_SIZE;
+        assert_eq!(expected_bus.ram.data[page + 16..page + 24], 0xFFFF_FFFF_85FF_FFFDu64.to_le_bytes());
+    }
+
+    #[test]
+    fn branches_and_jumps() {
+        for xlen in [64, 32] {
+            let (hart, _) = cross_check(&BRANCHES, xlen);
+            // a bit set for each branch not taken.
+            assert_eq!(hart.x[10], 0b0101_0101_0101, "rv{}", xlen);
+            assert_eq!(hart.x[12], 15, "rv{}", xlen);
+            // `jalr` clears bit 0 of its target; on RV32 the link is sign
+            // extended like any other register value.
+            let link = RAM_BASE + 47 * 4;
+            assert_eq!(hart.x[1], if xlen == 32 { sext(link, 32) } else { link }, "rv{}", xlen);
+            assert_eq!(hart.x[11], hart.x[1], "rv{}", xlen);
+            assert_eq!(hart.x[14], 4, "rv{}", xlen);
+        }
+    }
+
+    #[test]
+    fn loads_and_stores() {
+        let (hart, _) = cross_check(&MEMORY, 64);
+        assert_eq!(hart.x[10], 0x8877_6655_4433_2211);
+        assert_eq!(hart.x[5..=7], [0xFFFF_FFFF_FFFF_FF88, 0x88, 0xFFFF_FFFF_FFFF_8877]);
+        assert_eq!(hart.x[28..=31], [0x8877, 0xFFFF_FFFF_8877_6655, 0x8877_6655, 0x8877_6655_4433_2211]);
+        assert_eq!(hart.x[9], 0x4433_2211);
+        assert_eq!(hart.x[18..=21], [0x2211, 0x1122, 0, 0x1100]);
+    }
+
+    #[test]
+    fn traps() {
+        let (hart, _) = cross_check(&TRAPS, 64);
+        assert_eq!(hart.x[9], 3);
+        // an `ecall` from M-mode, then a load and a store access fault.
+        assert_eq!(hart.x[18], 11 + 5 + 7);
+        assert_eq!(hart.x[10], 10);
+        assert_eq!(hart.csr.mtval, 0x108);
+    }
+
+    #[test]
+    fn word_forms() {
+        let (hart, _) = cross_check(&WORDS, 64);
+        assert_eq!(hart.x[5..=7], [0xFFFF_FFFF_8000_0000, ONES - 1, 0xFFFF_FFFF_8000_0001]);
+        assert_eq!(hart.x[28..=31], [ONES - 1, 0x3FFF_FFFF, 0x3FFF_FFFF, 0xFFFF_FFFF_8000_0000]);
+        assert_eq!(hart.x[8..=9], [0, 0]);
+        assert_eq!(hart.x[18..=23], [33, 33, ONES, 0xFFFF_FFFF_8000_0000, 0, 0]);
+    }
+}
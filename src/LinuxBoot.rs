@@ -0,0 +1,301 @@
+use crate::Cpu::{Privilege, REG_A0, REG_A1, REG_A2};
+use crate::DeviceTree::{build_fdt, dtb_address, MachineDescription};
+use crate::Emulator::{Machine, TIMEBASE_FREQUENCY};
+use crate::Sbi::Sbi;
+
+/// `RISCV\0\0\0`, the deprecated magic at offset 48 of the header.
+pub const IMAGE_MAGIC: u64 = 0x0000_0056_4353_4952;
+/// `RSC\x05`, the current magic at offset 56 of the header.
+pub const IMAGE_MAGIC2: u32 = 0x0543_5352;
+pub const IMAGE_HEADER_SIZE: usize = 64;
+
+/// Magic placed in `fw_dynamic_info` for OpenSBI (`OSBI`).
+const FW_DYNAMIC_INFO_MAGIC: u64 = 0x4942_534f;
+const FW_DYNAMIC_INFO_VERSION: u64 = 2;
+const FW_DYNAMIC_INFO_NEXT_MODE_S: u64 = 1;
+
+const PAGE_SIZE: u64 = 0x1000;
+const MEGAPAGE_SIZE: u64 = 0x20_0000;
+
+/// The 64 byte header at the start of a RISC-V Linux `Image`, as described
+/// in `Documentation/riscv/boot-image-header.rst`.
+#[derive(Debug, Clone)]
+pub struct ImageHeader {
+    pub code0: u32,
+    pub code1: u32,
+    pub text_offset: u64,
+    pub image_size: u64,
+    pub flags: u64,
+    pub version: u32,
+    pub magic: u64,
+    pub magic2: u32,
+}
+
+impl ImageHeader {
+    pub fn parse(image: &[u8]) -> Result<Self, String> {
+        if image.len() < IMAGE_HEADER_SIZE {
+            return Err(format!("kernel image is only {} bytes long", image.len()));
+        }
+        let u32_at = |off: usize| u32::from_le_bytes(image[off..off + 4].try_into().unwrap());
+        let u64_at = |off: usize| u64::from_le_bytes(image[off..off + 8].try_into().unwrap());
+        let header = Self {
+            code0: u32_at(0),
+            code1: u32_at(4),
+            text_offset: u64_at(8),
+            image_size: u64_at(16),
+            flags: u64_at(24),
+            version: u32_at(32),
+            magic: u64_at(48),
+            magic2: u32_at(56),
+        };
+        if header.magic2 != IMAGE_MAGIC2 && header.magic != IMAGE_MAGIC {
+            return Err("kernel image has no RISC-V Image header magic".to_string());
+        }
+        Ok(header)
+    }
+
+    /// Bytes of RAM the kernel needs, including everything past the end of
+    /// the file (`.bss` and friends). Headers before version 0.2 may leave
+    /// this at `0`.
+    pub fn footprint(&self, file_size: u64) -> u64 {
+        self.image_size.max(file_size)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BootConfig {
+    pub kernel: Vec<u8>,
+    pub initrd: Option<Vec<u8>>,
+    pub bootargs: Option<String>,
+    /// Optional M-mode firmware (e.g. OpenSBI `fw_dynamic.bin`) that is
+    /// entered first and told where the kernel is.
+    pub firmware: Option<Vec<u8>>,
+}
+
+/// Where everything ended up in RAM.
+#[derive(Debug, Clone)]
+pub struct BootInfo {
+    pub header: ImageHeader,
+    pub firmware: Option<(u64, u64)>,
+    pub kernel: (u64, u64),
+    pub initrd: Option<(u64, u64)>,
+    pub dtb: (u64, u64),
+    pub entry: u64,
+    pub privilege: Privilege,
+}
+
+fn align_up(value: u64, align: u64) -> u64 {
+    (value + align - 1) & !(align - 1)
+}
+
+fn overlaps(a: (u64, u64), b: (u64, u64)) -> bool {
+    a.0 < b.1 && b.0 < a.1
+}
+
+/// Places the kernel, initramfs, DTB and optional firmware in RAM and points
+/// every hart at the entry with `a0 = hartid` and `a1 = DTB address`.
+///
+/// Without firmware the harts enter the kernel directly in S-mode, with the
+/// built-in SBI answering its firmware calls, and use the kernel's spinwait
+/// hart lottery. With firmware they enter it in M-mode with `a2` pointing
+/// at an OpenSBI `fw_dynamic_info` describing the kernel.
+pub fn boot_linux(machine: &mut Machine, config: &BootConfig) -> Result<BootInfo, String> {
+    let header = ImageHeader::parse(&config.kernel)?;
+    let ram_base = machine.bus.ram.base;
+    let ram_end = machine.bus.ram.end();
+    let ram_size = machine.bus.ram.size();
+
+    let firmware = config
+        .firmware
+        .as_ref()
+        .map(|fw| (ram_base, ram_base + fw.len() as u64));
+
+    // the kernel wants to sit `text_offset` past a 2 MiB aligned base; move
+    // it up a megapage at a time if the firmware is in the way.
+    let footprint = header.footprint(config.kernel.len() as u64);
+    let mut kernel_start = ram_base.checked_add(header.text_offset);
+    if let (Some(start), Some((_, fw_end))) = (kernel_start, firmware) {
+        if start < fw_end {
+            kernel_start = align_up(fw_end, MEGAPAGE_SIZE).checked_add(header.text_offset);
+        }
+    }
+    let kernel = match kernel_start.and_then(|start| Some((start, start.checked_add(footprint)?))) {
+        Some(kernel) if kernel.1 <= ram_end => kernel,
+        _ => {
+            return Err(format!(
+                "kernel needs {:#x} bytes at text offset {:#x} but RAM ends at {:#x}",
+                footprint, header.text_offset, ram_end
+            ))
+        }
+    };
+
+    // keep the initramfs well clear of the kernel so decompressing or
+    // relocating it does not clobber the archive.
+    let initrd = config.initrd.as_ref().map(|data| {
+        let start = align_up(kernel.1.max(ram_base + (ram_size / 2).min(128 << 20)), PAGE_SIZE);
+        (start, start + data.len() as u64)
+    });
+
+    let isa = machine.isa().to_string();
+    let dtb_blob = build_fdt(&MachineDescription {
+        hart_count: machine.harts.len(),
+        isa: &isa,
+        ram_base,
+        ram_size,
+        timebase_frequency: TIMEBASE_FREQUENCY,
+        bootargs: config.bootargs.as_deref(),
+        initrd,
+        htif: false,
+    });
+    let dtb_start = dtb_address(ram_base, ram_end, dtb_blob.len() as u64)
+        .ok_or("not enough RAM for the device tree")?;
+    let dtb = (dtb_start, dtb_start + dtb_blob.len() as u64);
+    // `fw_dynamic_info` follows the DTB; its fields are `unsigned long`,
+    // so XLEN wide.
+    let fw_info_addr = align_up(dtb.1, 8);
+    let word = machine.isa().xlen() as u64 / 8;
+
+    let mut regions = vec![("kernel", kernel), ("device tree", dtb)];
+    if let Some(fw) = firmware {
+        regions.push(("firmware", fw));
+        regions.push(("fw_dynamic_info", (fw_info_addr, fw_info_addr + 6 * word)));
+    }
+    if let Some(rd) = initrd {
+        regions.push(("initrd", rd));
+    }
+    for (i, (name_a, a)) in regions.iter().enumerate() {
+        if a.1 > ram_end {
+            return Err(format!("{} does not fit in RAM", name_a));
+        }
+        for (name_b, b) in regions.iter().skip(i + 1) {
+            if overlaps(*a, *b) {
+                return Err(format!("{} overlaps {} in RAM", name_a, name_b));
+            }
+        }
+    }
+
+    let bus = &mut machine.bus;
+    bus.write_bytes(kernel.0, &config.kernel);
+    bus.write_bytes(dtb.0, &dtb_blob);
+    if let (Some(rd), Some(data)) = (initrd, config.initrd.as_ref()) {
+        bus.write_bytes(rd.0, data);
+    }
+    let (entry, privilege) = match (firmware, config.firmware.as_ref()) {
+        (Some(fw), Some(data)) => {
+            bus.write_bytes(fw.0, data);
+            for (i, value) in [
+                FW_DYNAMIC_INFO_MAGIC,
+                FW_DYNAMIC_INFO_VERSION,
+                kernel.0,
+                FW_DYNAMIC_INFO_NEXT_MODE_S,
+                0,
+                0,
+            ]
+            .iter()
+            .enumerate()
+            {
+                bus.store(fw_info_addr + i as u64 * word, word as usize, *value);
+            }
+            (fw.0, Privilege::Machine)
+        }
+        _ => {
+            bus.sbi = Some(Sbi::new(machine.harts.len()));
+            (kernel.0, Privilege::Supervisor)
+        }
+    };
+
+    for hart in machine.harts.iter_mut() {
+        hart.x = [0; 32];
+        hart.write_reg(REG_A0, hart.id);
+        hart.write_reg(REG_A1, dtb.0);
+        match privilege {
+            Privilege::Machine => hart.write_reg(REG_A2, fw_info_addr),
+            _ => Sbi::prepare_hart(hart),
+        }
+        hart.pc = entry;
+        hart.privilege = privilege;
+    }
+
+    Ok(BootInfo {
+        header,
+        firmware,
+        kernel,
+        initrd,
+        dtb,
+        entry,
+        privilege,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Emulator::StopReason;
+    use crate::Memory::DEFAULT_RAM_SIZE;
+
+    /// An `Image` whose header jumps to code that asks the SBI for its
+    /// version, arms the timer 5 ticks out, waits for the interrupt with
+    /// `STIE` on, and shuts down from the handler with `scause` in `s2`.
+    const PAYLOAD: [u32; 26] = [
+        0x00000297, // auipc t0, 0
+        0x04828293, // addi t0, t0, handler - start
+        0x10529073, // csrw stvec, t0
+        0x01000893, // li a7, BASE
+        0x00000813, // li a6, get_spec_version
+        0x00000073, // ecall
+        0x00050413, // mv s0, a0
+        0x00058493, // mv s1, a1
+        0xC0102573, // rdtime a0
+        0x00550513, // addi a0, a0, 5
+        0x544958B7, // lui a7, TIME
+        0xD458889B, // addiw a7, a7, TIME
+        0x00000813, // li a6, set_timer
+        0x00000073, // ecall
+        0x02000293, // li t0, STIE
+        0x1042A073, // csrs sie, t0
+        0x10016073, // csrsi sstatus, SIE
+        0x0000006F, // spin: j spin
+        0x14202973, // handler: csrr s2, scause
+        0x535258B7, // lui a7, SRST
+        0x3548889B, // addiw a7, a7, SRST
+        0x00000813, // li a6, system_reset
+        0x00000513, // li a0, shutdown
+        0x00000593, // li a1, no reason
+        0x00000073, // ecall
+        0x0000006F, // hang: j hang
+    ];
+
+    fn image() -> Vec<u8> {
+        let mut image = vec![0; IMAGE_HEADER_SIZE];
+        image[0..4].copy_from_slice(&0x0400_006Fu32.to_le_bytes()); // j start
+        image[8..16].copy_from_slice(&0x20_0000u64.to_le_bytes());
+        image[56..60].copy_from_slice(&IMAGE_MAGIC2.to_le_bytes());
+        for word in PAYLOAD {
+            image.extend_from_slice(&word.to_le_bytes());
+        }
+        image
+    }
+
+    #[test]
+    fn sbi_call_and_timer_interrupt() {
+        let mut machine = Machine::new(1, DEFAULT_RAM_SIZE);
+        let config = BootConfig {
+            kernel: image(),
+            initrd: None,
+            bootargs: None,
+            firmware: None,
+        };
+        let info = boot_linux(&mut machine, &config).unwrap();
+        assert_eq!(info.privilege, Privilege::Supervisor);
+        assert!(machine.bus.sbi.is_some());
+
+        assert_eq!(machine.run(Some(10_000)), StopReason::Exit(0));
+        let hart = &machine.harts[0];
+        assert_eq!(hart.privilege, Privilege::Supervisor);
+        assert_eq!(hart.x[8], 0, "get_spec_version error");
+        assert_eq!(hart.x[9], 2 << 24, "SBI version 2.0");
+        assert_eq!(hart.x[18], (1 << 63) | 5, "supervisor timer interrupt");
+        assert_eq!(hart.csr.sepc, info.kernel.0 + IMAGE_HEADER_SIZE as u64 + 17 * 4);
+        assert!(machine.bus.mtime >= 5);
+    }
+}
@@ -0,0 +1,228 @@
+use crate::BlockCache::BlockCache;
+use crate::Clint::Clint;
+use crate::Htif::Htif;
+use crate::Reservation::Reservations;
+use crate::Sbi::Sbi;
+
+/// Physical address where RAM starts, matching the layout used by the
+/// `virt` boards of QEMU and Spike.
+pub const RAM_BASE: u64 = 0x8000_0000;
+
+/// Default amount of RAM given to a machine (128 MiB).
+pub const DEFAULT_RAM_SIZE: u64 = 128 * 1024 * 1024;
+
+/// Granularity of the dirty page tracking in `Ram`.
+pub const PAGE_SHIFT: usize = 12;
+pub const PAGE_SIZE: usize = 1 << PAGE_SHIFT;
+
+#[derive(Debug, Clone)]
+pub struct Ram {
+    pub base: u64,
+    pub data: Vec<u8>,
+    /// One flag per page, set by every write. Whoever needs to know what
+    /// changed clears them.
+    pub dirty: Vec<bool>,
+}
+
+impl Ram {
+    pub fn new(base: u64, size: u64) -> Self {
+        Self {
+            base,
+            data: vec![0; size as usize],
+            dirty: vec![false; (size as usize).div_ceil(PAGE_SIZE)],
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    pub fn end(&self) -> u64 {
+        self.base + self.size()
+    }
+
+    /// Returns the offset into `data` for an access of `len` bytes at
+    /// `addr`, or `None` if any part of it falls outside of RAM.
+    pub fn offset(&self, addr: u64, len: u64) -> Option<usize> {
+        if addr < self.base || addr.checked_add(len)? > self.end() {
+            return None;
+        }
+        Some((addr - self.base) as usize)
+    }
+
+    fn mark_dirty(&mut self, off: usize, len: usize) {
+        for page in (off >> PAGE_SHIFT)..=((off + len - 1) >> PAGE_SHIFT) {
+            self.dirty[page] = true;
+        }
+    }
+
+    /// Pages written since their dirty flags were last cleared.
+    pub fn dirty_pages(&self) -> impl Iterator<Item = usize> + '_ {
+        self.dirty.iter().enumerate().filter(|(_, dirty)| **dirty).map(|(page, _)| page)
+    }
+
+    pub fn clear_dirty(&mut self) {
+        self.dirty.iter_mut().for_each(|dirty| *dirty = false);
+    }
+}
+
+/// A physical address the monitor stops on when it is read or written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub addr: u64,
+    pub read: bool,
+    pub write: bool,
+}
+
+/// The physical address space seen by the harts.
+#[derive(Debug, Clone)]
+pub struct Bus {
+    pub ram: Ram,
+    /// The platform timer read through the `time` CSR.
+    pub mtime: u64,
+    pub clint: Clint,
+    pub htif: Option<Htif>,
+    /// Firmware calls from HS-mode, answered by the emulator itself when
+    /// a kernel runs without M-mode firmware.
+    pub sbi: Option<Sbi>,
+    pub watchpoints: Vec<Watchpoint>,
+    /// The last access that hit a watchpoint, as `(addr, is_write)`.
+    pub watch_hit: Option<(u64, bool)>,
+    /// When set, every write is also appended here as `(addr, bytes)`.
+    pub journal: Option<Vec<(u64, Vec<u8>)>>,
+    /// When set, every write first appends the bytes it overwrites here as
+    /// `(addr, bytes)`, so it can be taken back.
+    pub undo: Option<Vec<(u64, Vec<u8>)>>,
+    /// Instructions decoded from RAM, dropped as their pages are written.
+    pub blocks: BlockCache,
+    /// What each hart's `LR` reserved, broken by other writers.
+    pub reservations: Reservations,
+}
+
+impl Bus {
+    pub fn new(ram_size: u64) -> Self {
+        Self {
+            ram: Ram::new(RAM_BASE, ram_size),
+            mtime: 0,
+            clint: Clint::new(1),
+            htif: None,
+            sbi: None,
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            journal: None,
+            undo: None,
+            blocks: BlockCache::new(),
+            reservations: Reservations::new(),
+        }
+    }
+
+    fn check_watchpoints(&mut self, addr: u64, size: usize, write: bool) {
+        let hit = self.watchpoints.iter().find(|w| {
+            (if write { w.write } else { w.read }) && addr <= w.addr && w.addr < addr + size as u64
+        });
+        if let Some(w) = hit {
+            self.watch_hit = Some((w.addr, write));
+        }
+    }
+
+    // the devices are kept out of line so the RAM paths stay small.
+    #[cold]
+    #[inline(never)]
+    fn load_device(&mut self, addr: u64, size: usize) -> Option<u64> {
+        if !Clint::covers(addr, size as u64) {
+            return None;
+        }
+        self.clint.load(self.mtime, addr, size as u64)
+    }
+
+    #[cold]
+    #[inline(never)]
+    fn store_device(&mut self, addr: u64, size: usize, value: u64) -> bool {
+        Clint::covers(addr, size as u64) && self.clint.store(&mut self.mtime, addr, size as u64, value)
+    }
+
+    /// Loads `size` (1, 2, 4 or 8) bytes little-endian from `addr`.
+    pub fn load(&mut self, addr: u64, size: usize) -> Option<u64> {
+        let off = match self.ram.offset(addr, size as u64) {
+            Some(off) => off,
+            None => return self.load_device(addr, size),
+        };
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(addr, size, false);
+        }
+        let mut out: u64 = 0;
+        for i in (0..size).rev() {
+            out = (out << 8) | self.ram.data[off + i] as u64;
+        }
+        Some(out)
+    }
+
+    /// Stores the low `size` (1, 2, 4 or 8) bytes of `value` little-endian
+    /// to `addr`. Returns `false` if nothing backs the address.
+    pub fn store(&mut self, addr: u64, size: usize, value: u64) -> bool {
+        self.store_from(None, addr, size, value)
+    }
+
+    /// `store` on behalf of hart `hart`, whose own reservation survives it;
+    /// with no hart it is a device writing.
+    pub fn store_from(&mut self, hart: Option<u64>, addr: u64, size: usize, value: u64) -> bool {
+        let off = match self.ram.offset(addr, size as u64) {
+            Some(off) => off,
+            None => return self.store_device(addr, size, value),
+        };
+        self.reservations.invalidate(addr, size as u64, hart);
+        if let Some(undo) = &mut self.undo {
+            undo.push((addr, self.ram.data[off..off + size].to_vec()));
+        }
+        for i in 0..size {
+            self.ram.data[off + i] = (value >> (i * 8)) as u8;
+        }
+        self.ram.mark_dirty(off, size);
+        if self.blocks.has_code(off >> PAGE_SHIFT) {
+            self.blocks.invalidate(off, size);
+        }
+        if let Some(journal) = &mut self.journal {
+            journal.push((addr, self.ram.data[off..off + size].to_vec()));
+        }
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(addr, size, true);
+        }
+        if let Some(htif) = &mut self.htif {
+            if htif.covers(addr, size as u64) {
+                htif.tohost_written = true;
+            }
+        }
+        true
+    }
+
+    pub fn read_bytes(&self, addr: u64, len: usize) -> Option<&[u8]> {
+        let off = self.ram.offset(addr, len as u64)?;
+        Some(&self.ram.data[off..off + len])
+    }
+
+    pub fn write_bytes(&mut self, addr: u64, bytes: &[u8]) -> bool {
+        let off = match self.ram.offset(addr, bytes.len() as u64) {
+            Some(off) => off,
+            None => return false,
+        };
+        if bytes.is_empty() {
+            return true;
+        }
+        if let Some(undo) = &mut self.undo {
+            undo.push((addr, self.ram.data[off..off + bytes.len()].to_vec()));
+        }
+        self.ram.data[off..off + bytes.len()].copy_from_slice(bytes);
+        self.ram.mark_dirty(off, bytes.len());
+        self.reservations.invalidate(addr, bytes.len() as u64, None);
+        self.blocks.invalidate(off, bytes.len());
+        if let Some(journal) = &mut self.journal {
+            journal.push((addr, bytes.to_vec()));
+        }
+        if let Some(htif) = &mut self.htif {
+            if htif.covers(addr, bytes.len() as u64) {
+                htif.tohost_written = true;
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,662 @@
+use std::collections::BTreeSet;
+use std::io::{BufRead, Write};
+
+use crate::Cpu::ABI_NAMES;
+use crate::Csr::csr_name;
+use crate::Disassembler::{disassemble, jump_target, FP_ABI_NAMES};
+use crate::Elf::ElfFile;
+use crate::Emulator::{Machine, StopReason};
+use crate::Memory::Watchpoint;
+use crate::Replay::History;
+use crate::Snapshot;
+
+const HELP: &str = "\
+commands:
+    step [n]                 run n instructions on every hart (default 1)
+    continue                 run until a breakpoint, watchpoint or exit
+    reverse-step [n]         go back n rounds (default 1)
+    reverse-continue         go back to the last breakpoint or watchpoint
+                             hit, or to the start of the history
+    break [addr|symbol]      set a breakpoint, or list breakpoints and
+                             watchpoints
+    delete <addr|symbol>     remove the breakpoints and watchpoints there
+    watch <addr> [r|w|rw]    stop after an access to addr (default w)
+    regs                     show the integer registers
+    fregs                    show the floating point registers and fcsr
+    csr <name|number>        show a CSR
+    mem <addr> <len>         dump memory
+    disas [addr] [n]         disassemble n instructions (default pc, 10)
+    bt                       backtrace by walking the frame pointer chain
+    set <reg>=<value>        write pc, an integer register or a CSR
+    hart <n>                 select the hart the commands above act on
+    save <file>              save a snapshot of the whole machine
+    stats                    show the instruction mix so far (starts
+                             counting if `--stats` was not given)
+    quit
+
+Addresses are virtual, as seen by the selected hart, and may be given as
+numbers (`0x` for hex), symbols or `symbol+offset`. An empty line repeats
+the last command.";
+
+/// Stack frames `bt` walks before giving up.
+const MAX_FRAMES: usize = 64;
+
+/// Why `run_until` stopped.
+enum Stop {
+    Done,
+    Breakpoint(usize),
+    Watchpoint(u64, bool),
+    Exited(u64),
+    /// Ran into the start or the end of the recorded history.
+    HistoryStart,
+    HistoryEnd,
+}
+
+struct Monitor<'a, W: Write> {
+    elf: Option<&'a ElfFile>,
+    history: &'a mut History,
+    breakpoints: BTreeSet<u64>,
+    /// The hart `regs`, `mem` and friends look at.
+    hart: usize,
+    /// Where everything the commands show goes.
+    out: W,
+}
+
+/// Parses a number with an optional `0x` prefix.
+fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse::<i64>().ok().map(|v| v as u64),
+    }
+}
+
+fn register_index(name: &str) -> Option<usize> {
+    if name == "fp" {
+        return Some(8);
+    }
+    if let Some(index) = ABI_NAMES.iter().position(|n| *n == name) {
+        return Some(index);
+    }
+    let index: usize = name.strip_prefix('x')?.parse().ok()?;
+    (index < 32).then_some(index)
+}
+
+fn csr_address(name: &str) -> Option<usize> {
+    (0..4096).find(|addr| csr_name(*addr) == Some(name)).or_else(|| {
+        let addr = parse_number(name)? as usize;
+        (addr < 4096).then_some(addr)
+    })
+}
+
+impl<W: Write> Monitor<'_, W> {
+    /// Resolves a number, symbol or `symbol+offset`.
+    fn address(&self, text: &str) -> Result<u64, String> {
+        if let Some(value) = parse_number(text) {
+            return Ok(value);
+        }
+        let (name, offset) = match text.split_once('+') {
+            Some((name, offset)) => (name, parse_number(offset).ok_or(format!("bad offset `{}`", offset))?),
+            None => (text, 0),
+        };
+        self.elf
+            .and_then(|elf| elf.symbol(name))
+            .map(|value| value.wrapping_add(offset))
+            .ok_or(format!("no symbol `{}`", name))
+    }
+
+    /// Formats ` <symbol+offset>` for an address, if it has a symbol.
+    fn describe(&self, addr: u64) -> String {
+        match self.elf.and_then(|elf| elf.symbolize(addr)) {
+            Some((symbol, 0)) => format!(" <{}>", symbol.name),
+            Some((symbol, offset)) => format!(" <{}+{:#x}>", symbol.name, offset),
+            None => String::new(),
+        }
+    }
+
+    fn print_instruction(&mut self, machine: &Machine, addr: u64) -> u64 {
+        let hart = &machine.harts[self.hart];
+        let bytes = match hart.debug_read(&machine.bus, addr, 4).or_else(|| hart.debug_read(&machine.bus, addr, 2)) {
+            Some(bytes) => bytes,
+            None => {
+                let _ = writeln!(self.out, "{:#018x}: <unmapped>", addr);
+                return 4;
+            }
+        };
+        let lo = u16::from_le_bytes([bytes[0], bytes[1]]) as u32;
+        let (raw, len) = if lo & 0b11 != 0b11 {
+            (lo, 2)
+        } else if bytes.len() == 4 {
+            (u32::from_le_bytes(bytes[..4].try_into().unwrap()), 4)
+        } else {
+            let _ = writeln!(self.out, "{:#018x}: <unmapped>", addr);
+            return 4;
+        };
+        let word = if len == 2 { format!("{:04x}    ", raw) } else { format!("{:08x}", raw) };
+        let marker = if addr == hart.pc { "=>" } else { "  " };
+        let xlen = hart.xlen();
+        let target = jump_target(raw, addr, xlen).map(|t| self.describe(t)).unwrap_or_default();
+        let symbol = self.describe(addr);
+        let text = disassemble(raw, xlen);
+        let _ = writeln!(self.out, "{} {:#018x}{}: {}  {}{}", marker, addr, symbol, word, text, target);
+        len
+    }
+
+    fn print_stop(&mut self, machine: &Machine, stop: &Stop) {
+        let message = match stop {
+            Stop::Done => String::new(),
+            Stop::Breakpoint(hart) => format!("hart {} hit a breakpoint\n", hart),
+            Stop::Watchpoint(addr, write) => {
+                let kind = if *write { "write to" } else { "read of" };
+                format!("watchpoint: {} {:#x}\n", kind, addr)
+            }
+            Stop::Exited(code) => format!("target exited with code {}\n", code),
+            Stop::HistoryStart => "reached the start of the recorded history\n".to_string(),
+            Stop::HistoryEnd => "reached the end of the recording\n".to_string(),
+        };
+        let _ = write!(self.out, "{}", message);
+        if !matches!(stop, Stop::Exited(_)) {
+            self.print_instruction(machine, machine.harts[self.hart].pc);
+        }
+    }
+
+    /// Runs up to `count` rounds, or forever with `None`, stopping early at
+    /// breakpoints, watchpoints and exit.
+    fn run_until(&mut self, machine: &mut Machine, count: Option<u64>) -> Stop {
+        let mut done = 0;
+        while count.is_none_or(|count| done < count) {
+            if !self.history.step(machine) {
+                return Stop::HistoryEnd;
+            }
+            done += 1;
+            if let Some(code) = machine.exit_code() {
+                return Stop::Exited(code);
+            }
+            if let Some((addr, write)) = machine.bus.watch_hit.take() {
+                return Stop::Watchpoint(addr, write);
+            }
+            if let Some(hart) = machine.harts.iter().position(|h| self.breakpoints.contains(&h.pc)) {
+                self.hart = hart;
+                return Stop::Breakpoint(hart);
+            }
+        }
+        Stop::Done
+    }
+
+    /// Goes back `count` rounds, or with `None` to the last round that hit
+    /// a breakpoint or watchpoint.
+    fn reverse(&mut self, machine: &mut Machine, count: Option<u64>) -> Stop {
+        if let Some(count) = count {
+            if machine.steps < self.history.start() + count {
+                self.history.seek(machine, self.history.start());
+                return Stop::HistoryStart;
+            }
+            self.history.seek(machine, machine.steps - count);
+            return Stop::Done;
+        }
+        let breakpoints = &self.breakpoints;
+        let mut last_watch = None;
+        let found = self.history.reverse_until(machine, |machine| {
+            let watch = machine.bus.watch_hit.take();
+            let hit = watch.is_some() || machine.harts.iter().any(|h| breakpoints.contains(&h.pc));
+            if hit {
+                last_watch = watch;
+            }
+            hit
+        });
+        if !found {
+            return Stop::HistoryStart;
+        }
+        if let Some(hart) = machine.harts.iter().position(|h| self.breakpoints.contains(&h.pc)) {
+            self.hart = hart;
+            return Stop::Breakpoint(hart);
+        }
+        match last_watch {
+            Some((addr, write)) => Stop::Watchpoint(addr, write),
+            None => Stop::Done,
+        }
+    }
+
+    fn regs(&mut self, machine: &Machine) {
+        let hart = &machine.harts[self.hart];
+        // registers are shown at the XLEN the hart runs at.
+        let width = hart.xlen() as usize / 4 + 2;
+        let symbol = self.describe(hart.pc);
+        let (id, pc, privilege) = (hart.id, hart.pc, hart.privilege);
+        let _ = writeln!(self.out, "hart {}  pc {:#0w$x}{}  ({:?} mode)", id, pc, symbol, privilege, w = width);
+        for row in 0..8 {
+            let line: Vec<String> = (0..4)
+                .map(|col| {
+                    let reg = row + col * 8;
+                    format!("{:>4} {:#0w$x}", ABI_NAMES[reg], hart.x[reg] & hart.xlen_mask(), w = width)
+                })
+                .collect();
+            let _ = writeln!(self.out, "{}", line.join("  "));
+        }
+    }
+
+    fn fregs(&mut self, machine: &Machine) {
+        let hart = &machine.harts[self.hart];
+        let fcsr = hart.csr.fcsr;
+        let (id, frm, fflags) = (hart.id, fcsr >> 5, fcsr & 0x1F);
+        let _ = writeln!(self.out, "hart {}  fcsr {:#04x} (frm {}, fflags {:#07b})", id, fcsr, frm, fflags);
+        for row in 0..8 {
+            let line: Vec<String> = (0..4)
+                .map(|col| {
+                    let reg = row + col * 8;
+                    format!("{:>4} {:#018x}", FP_ABI_NAMES[reg], hart.f[reg])
+                })
+                .collect();
+            let _ = writeln!(self.out, "{}", line.join("  "));
+        }
+    }
+
+    fn mem(&mut self, machine: &Machine, addr: u64, len: u64) -> Result<(), String> {
+        let hart = &machine.harts[self.hart];
+        let bytes = hart
+            .debug_read(&machine.bus, addr, len.min(1 << 20) as usize)
+            .ok_or(format!("{:#x}..{:#x} is not mapped", addr, addr.wrapping_add(len)))?;
+        for (i, line) in bytes.chunks(16).enumerate() {
+            let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+            let ascii: String = line
+                .iter()
+                .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+                .collect();
+            let _ = writeln!(self.out, "{:#018x}: {:<47}  |{}|", addr + i as u64 * 16, hex.join(" "), ascii);
+        }
+        Ok(())
+    }
+
+    /// Walks the chain of saved frame pointers, assuming the standard
+    /// RISC-V frame layout where `ra` sits one XLEN word below `fp` and the
+    /// caller's `fp` two words below (`fp - 8`/`fp - 16` on RV64,
+    /// `fp - 4`/`fp - 8` on RV32).
+    fn backtrace(&mut self, machine: &Machine) {
+        let hart = &machine.harts[self.hart];
+        let word = hart.xlen() as u64 / 8;
+        let mask = hart.xlen_mask();
+        // RV32 addresses are kept zero extended, like the pc.
+        let read = |addr: u64| {
+            hart.debug_read(&machine.bus, addr & mask, word as usize).map(|b| {
+                b.iter().rev().fold(0u64, |value, &byte| value << 8 | byte as u64)
+            })
+        };
+        let _ = writeln!(self.out, "#0  {:#018x}{}", hart.pc, self.describe(hart.pc));
+        let mut fp = hart.x[8] & mask;
+        for frame in 1..MAX_FRAMES {
+            if fp == 0 || !fp.is_multiple_of(word) {
+                break;
+            }
+            let (ra, next) = match (read(fp.wrapping_sub(word)), read(fp.wrapping_sub(2 * word))) {
+                (Some(ra), Some(next)) => (ra, next),
+                _ => break,
+            };
+            if ra == 0 {
+                break;
+            }
+            let _ = writeln!(self.out, "#{:<2} {:#018x}{}", frame, ra, self.describe(ra));
+            // the stack grows down, so callers' frames live above ours.
+            if next <= fp {
+                break;
+            }
+            fp = next;
+        }
+    }
+
+    fn set(&mut self, machine: &mut Machine, assignment: &str) -> Result<(), String> {
+        let (name, value) = assignment.split_once('=').ok_or("usage: set <reg>=<value>")?;
+        let (name, value) = (name.trim(), value.trim());
+        let value = self.address(value)?;
+        let hart = &mut machine.harts[self.hart];
+        if name == "pc" {
+            hart.pc = value;
+        } else if let Some(reg) = register_index(name) {
+            hart.write_reg(reg, value);
+        } else if let Some(addr) = csr_address(name) {
+            if !hart.csr_write(addr, value) {
+                return Err(format!("`{}` is read-only", name));
+            }
+        } else {
+            return Err(format!("no register `{}`", name));
+        }
+        // the recorded future no longer applies.
+        self.history.restart(machine);
+        Ok(())
+    }
+
+    /// Runs one command. Returns `Some` once the session is over.
+    fn command(&mut self, machine: &mut Machine, line: &str) -> Result<Option<Option<StopReason>>, String> {
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => return Ok(None),
+        };
+        let args: Vec<&str> = words.collect();
+        let arg = |i: usize| args.get(i).copied().ok_or(format!("`{}` needs more arguments", command));
+
+        match command {
+            "step" | "s" | "si" => {
+                let count = match args.first() {
+                    Some(n) => parse_number(n).ok_or(format!("bad count `{}`", n))?,
+                    None => 1,
+                };
+                let stop = self.run_until(machine, Some(count));
+                self.print_stop(machine, &stop);
+                if let Stop::Exited(code) = stop {
+                    return Ok(Some(Some(StopReason::Exit(code))));
+                }
+            }
+            "continue" | "c" => {
+                let stop = self.run_until(machine, None);
+                self.print_stop(machine, &stop);
+                if let Stop::Exited(code) = stop {
+                    return Ok(Some(Some(StopReason::Exit(code))));
+                }
+            }
+            "reverse-step" | "rs" => {
+                let count = match args.first() {
+                    Some(n) => parse_number(n).ok_or(format!("bad count `{}`", n))?,
+                    None => 1,
+                };
+                let stop = self.reverse(machine, Some(count));
+                self.print_stop(machine, &stop);
+            }
+            "reverse-continue" | "rc" => {
+                let stop = self.reverse(machine, None);
+                self.print_stop(machine, &stop);
+            }
+            "break" | "b" => match args.first() {
+                Some(target) => {
+                    let addr = self.address(target)?;
+                    self.breakpoints.insert(addr);
+                    let _ = writeln!(self.out, "breakpoint at {:#x}{}", addr, self.describe(addr));
+                }
+                None => {
+                    for addr in self.breakpoints.iter() {
+                        let _ = writeln!(self.out, "breakpoint {:#018x}{}", addr, self.describe(*addr));
+                    }
+                    for w in machine.bus.watchpoints.iter() {
+                        let kind = match (w.read, w.write) {
+                            (true, true) => "rw",
+                            (true, false) => "r",
+                            _ => "w",
+                        };
+                        let _ = writeln!(self.out, "watchpoint {:#018x} {} (physical)", w.addr, kind);
+                    }
+                }
+            },
+            "delete" | "d" => {
+                let addr = self.address(arg(0)?)?;
+                let hart = &machine.harts[self.hart];
+                let paddr = hart.debug_translate(&machine.bus, addr);
+                let removed = self.breakpoints.remove(&addr);
+                let watches = machine.bus.watchpoints.len();
+                machine.bus.watchpoints.retain(|w| Some(w.addr) != paddr);
+                if !removed && watches == machine.bus.watchpoints.len() {
+                    return Err(format!("nothing set at {:#x}", addr));
+                }
+            }
+            "watch" | "w" => {
+                let addr = self.address(arg(0)?)?;
+                let (read, write) = match args.get(1).copied().unwrap_or("w") {
+                    "r" => (true, false),
+                    "w" => (false, true),
+                    "rw" | "wr" => (true, true),
+                    other => return Err(format!("bad watch kind `{}`, expected r, w or rw", other)),
+                };
+                // watchpoints live on the bus, so they are physical.
+                let paddr = machine.harts[self.hart]
+                    .debug_translate(&machine.bus, addr)
+                    .ok_or(format!("{:#x} is not mapped", addr))?;
+                machine.bus.watchpoints.push(Watchpoint { addr: paddr, read, write });
+                let _ = writeln!(self.out, "watchpoint at {:#x} (physical {:#x})", addr, paddr);
+            }
+            "regs" | "r" => self.regs(machine),
+            "fregs" => self.fregs(machine),
+            "csr" => {
+                let name = arg(0)?;
+                let addr = csr_address(name).ok_or(format!("no CSR `{}`", name))?;
+                let value = machine.harts[self.hart]
+                    .csr_read(&machine.bus, addr)
+                    .ok_or(format!("CSR {:#x} is not implemented", addr))?;
+                let width = machine.harts[self.hart].xlen() as usize / 4 + 2;
+                let name = csr_name(addr).unwrap_or("?");
+                let _ = writeln!(self.out, "{} ({:#05x}) = {:#0w$x}", name, addr, value, w = width);
+            }
+            "mem" | "x" => {
+                let addr = self.address(arg(0)?)?;
+                let len = parse_number(arg(1)?).ok_or("bad length")?;
+                self.mem(machine, addr, len)?;
+            }
+            "disas" => {
+                let mut addr = match args.first() {
+                    Some(target) => self.address(target)?,
+                    None => machine.harts[self.hart].pc,
+                };
+                let count = match args.get(1) {
+                    Some(n) => parse_number(n).ok_or(format!("bad count `{}`", n))?,
+                    None => 10,
+                };
+                for _ in 0..count {
+                    addr = addr.wrapping_add(self.print_instruction(machine, addr));
+                }
+            }
+            "bt" => self.backtrace(machine),
+            "set" => self.set(machine, &args.join(" "))?,
+            "hart" => {
+                let hart = parse_number(arg(0)?).ok_or("bad hart number")? as usize;
+                if hart >= machine.harts.len() {
+                    return Err(format!("hart {} does not exist", hart));
+                }
+                self.hart = hart;
+            }
+            "save" => {
+                let path = arg(0)?;
+                Snapshot::save(machine, path)?;
+                let steps = machine.steps;
+                let _ = writeln!(self.out, "saved the machine after {} instructions per hart to {}", steps, path);
+            }
+            "stats" => match &machine.stats {
+                Some(stats) => {
+                    let _ = write!(self.out, "{}", stats.report());
+                }
+                None => {
+                    machine.enable_stats();
+                    let _ = writeln!(self.out, "counting the instruction mix from here on");
+                }
+            },
+            "help" | "h" | "?" => {
+                let _ = writeln!(self.out, "{}", HELP);
+            }
+            "quit" | "q" => return Ok(Some(None)),
+            _ => return Err(format!("unknown command `{}`, try `help`", command)),
+        }
+        Ok(None)
+    }
+}
+
+/// Runs the interactive monitor on stdin until the target exits or the
+/// user quits. `elf` supplies symbol names when there is one. The machine
+/// runs through `history`, which is what the reverse commands go back in.
+pub fn run(machine: &mut Machine, elf: Option<&ElfFile>, history: &mut History) -> Option<StopReason> {
+    session(machine, elf, history, std::io::stdin().lock(), std::io::stdout())
+}
+
+/// `run`, reading commands from `input` and writing to `out`.
+fn session<R: BufRead, W: Write>(
+    machine: &mut Machine,
+    elf: Option<&ElfFile>,
+    history: &mut History,
+    mut input: R,
+    out: W,
+) -> Option<StopReason> {
+    let mut monitor = Monitor {
+        elf,
+        history,
+        breakpoints: BTreeSet::new(),
+        hart: 0,
+        out,
+    };
+    monitor.print_instruction(machine, machine.harts[0].pc);
+    let mut last = String::new();
+    loop {
+        let _ = write!(monitor.out, "(mon) ");
+        let _ = monitor.out.flush();
+        let mut line = String::new();
+        match input.read_line(&mut line) {
+            Ok(0) | Err(_) => return None,
+            Ok(_) => {}
+        }
+        let line = if line.trim().is_empty() { last.clone() } else { line.trim().to_string() };
+        match monitor.command(machine, &line) {
+            Ok(Some(end)) => return end,
+            Ok(None) => {}
+            Err(e) => {
+                let _ = writeln!(monitor.out, "error: {}", e);
+            }
+        }
+        last = line;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Elf::{Segment, Symbol, SymbolKind};
+    use crate::Memory::RAM_BASE;
+
+    const PROGRAM: [u32; 13] = [
+        0xFF01_0113, // main: addi sp, sp, -16
+        0x0011_3423, // sd ra, 8(sp)
+        0x0081_3023, // sd s0, 0(sp)
+        0x0101_0413, // addi s0, sp, 16
+        0x0080_00EF, // jal leaf
+        0x0000_006F, // 1: j 1b
+        0xFF01_0113, // leaf: addi sp, sp, -16
+        0x0011_3423, // sd ra, 8(sp)
+        0x0081_3023, // sd s0, 0(sp)
+        0x0101_0413, // addi s0, sp, 16
+        0x10A1_A023, // sw a0, 0x100(gp)
+        0x1041_A583, // lw a1, 0x104(gp)
+        0x0000_006F, // 2: j 2b
+    ];
+
+    fn elf() -> ElfFile {
+        let symbol = |name: &str, offset, size| Symbol {
+            name: name.to_string(),
+            value: RAM_BASE + offset,
+            size,
+            kind: SymbolKind::Function,
+        };
+        ElfFile {
+            xlen: 64,
+            entry: RAM_BASE,
+            segments: vec![Segment {
+                vaddr: RAM_BASE,
+                paddr: RAM_BASE,
+                data: PROGRAM.iter().flat_map(|word| word.to_le_bytes()).collect(),
+                memsz: PROGRAM.len() as u64 * 4,
+            }],
+            symbols: vec![symbol("main", 0, 0x18), symbol("leaf", 0x18, 0x1C)],
+            debug_sections: Vec::new(),
+        }
+    }
+
+    /// Runs `script` through the monitor, returning how the session ended
+    /// and what it printed.
+    fn script(script: &str) -> (Option<StopReason>, String) {
+        let elf = elf();
+        let mut machine = Machine::new(1, 1 << 16);
+        elf.load(&mut machine.bus).unwrap();
+        machine.harts[0].pc = elf.entry;
+        let mut history = History::new(&mut machine, None);
+        let mut out = Vec::new();
+        let end = session(&mut machine, Some(&elf), &mut history, script.as_bytes(), &mut out);
+        (end, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn breakpoints_watchpoints_and_backtrace() {
+        let (end, out) = script(
+            "set sp=0x80008000\n\
+             set gp = 0x80001000\n\
+             set a0=0xcafe\n\
+             break leaf\n\
+             continue\n\
+             watch 0x80001100 w\n\
+             watch 0x80001104 r\n\
+             break\n\
+             continue\n\
+             mem 0x80001100 8\n\
+             continue\n\
+             bt\n\
+             disas leaf+8 2\n\
+             quit\n",
+        );
+        assert_eq!(end, None);
+        let expected = "\
+=> 0x0000000080000000 <main>: ff010113  addi    sp, sp, -16
+(mon) (mon) (mon) (mon) breakpoint at 0x80000018 <leaf>
+(mon) hart 0 hit a breakpoint
+=> 0x0000000080000018 <leaf>: ff010113  addi    sp, sp, -16
+(mon) watchpoint at 0x80001100 (physical 0x80001100)
+(mon) watchpoint at 0x80001104 (physical 0x80001104)
+(mon) breakpoint 0x0000000080000018 <leaf>
+watchpoint 0x0000000080001100 w (physical)
+watchpoint 0x0000000080001104 r (physical)
+(mon) watchpoint: write to 0x80001100
+=> 0x000000008000002c <leaf+0x14>: 1041a583  lw      a1, 260(gp)
+(mon) 0x0000000080001100: fe ca 00 00 00 00 00 00                          |........|
+(mon) watchpoint: read of 0x80001104
+=> 0x0000000080000030 <leaf+0x18>: 0000006f  j       pc + 0x0 <leaf+0x18>
+(mon) #0  0x0000000080000030 <leaf+0x18>
+#1  0x0000000080000014 <main+0x14>
+(mon)    0x0000000080000020 <leaf+0x8>: 00813023  sd      s0, 0(sp)
+   0x0000000080000024 <leaf+0xc>: 01010413  addi    s0, sp, 16
+(mon) ";
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn set_and_errors() {
+        let (end, out) = script(
+            "set sp=0x80008000\n\
+             set a0=leaf+4\n\
+             regs\n\
+             set mscratch=0x2a\n\
+             csr mscratch\n\
+             set nope=1\n\
+             set pc=nowhere\n\
+             break nowhere+0x10\n\
+             mem 0x10 4\n\
+             watch 0x80001100 x\n\
+             frobnicate\n\
+             \n\
+             step 3\n\
+             \n\
+             reverse-step 10\n",
+        );
+        assert_eq!(end, None);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[1], "(mon) (mon) (mon) hart 0  pc 0x0000000080000000 <main>  (Machine mode)");
+        assert!(lines[2].starts_with("zero 0x0000000000000000    s0 0x0000000000000000    a6 "));
+        assert!(lines[4].starts_with("  sp 0x0000000080008000    a0 0x000000008000001c    s2 "));
+        let rest = lines[10..].join("\n");
+        assert_eq!(
+            rest,
+            "\
+(mon) (mon) mscratch (0x340) = 0x000000000000002a
+(mon) error: no register `nope`
+(mon) error: no symbol `nowhere`
+(mon) error: no symbol `nowhere`
+(mon) error: 0x10..0x14 is not mapped
+(mon) error: bad watch kind `x`, expected r, w or rw
+(mon) error: unknown command `frobnicate`, try `help`
+(mon) error: unknown command `frobnicate`, try `help`
+(mon) => 0x000000008000000c <main+0xc>: 01010413  addi    s0, sp, 16
+(mon) => 0x000000008000001c <leaf+0x4>: 00113423  sd      ra, 8(sp)
+(mon) reached the start of the recorded history
+=> 0x0000000080000000 <main>: ff010113  addi    sp, sp, -16
+(mon) "
+        );
+    }
+}
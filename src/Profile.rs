@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use crate::Cpu::Hart;
+use crate::Disassembler::disassemble;
+use crate::Elf::ElfFile;
+use crate::InstructionDecoder::expand_compressed;
+
+/// Functions and pcs listed in a report when nothing else was asked for.
+pub const DEFAULT_TOP: usize = 20;
+
+/// Shadow call stacks deeper than this lose their oldest frames, so code
+/// that never returns (context switches, `longjmp`) cannot grow them forever.
+const MAX_DEPTH: usize = 256;
+
+const MRET: u32 = 0x3020_0073;
+const SRET: u32 = 0x1020_0073;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct PcCount {
+    /// Rounds the hart spent at this pc, including attempts that trapped.
+    cycles: u64,
+    /// Instructions that retired at this pc.
+    instructions: u64,
+    /// The instruction last seen here and the XLEN it ran at, for the
+    /// report.
+    raw: u32,
+    xlen: u32,
+}
+
+/// What a control transfer does to the shadow call stack.
+enum Transfer {
+    Call,
+    Return,
+    Other,
+}
+
+fn is_link(reg: u32) -> bool {
+    reg == 1 || reg == 5
+}
+
+/// Classifies a retired instruction the way the RISC-V calling convention
+/// hints do: `jal`/`jalr` writing `ra` (or `t0`) call, `jalr` through one
+/// of them without linking returns.
+fn transfer(raw: u32, len: u64, xlen: u32) -> Transfer {
+    let raw = if len == 2 {
+        match expand_compressed(raw as usize, xlen) {
+            Some(inst) => inst as u32,
+            None => return Transfer::Other,
+        }
+    } else {
+        raw
+    };
+    let (opcode, rd, rs1) = (raw & 0x7F, (raw >> 7) & 0x1F, (raw >> 15) & 0x1F);
+    match opcode {
+        0x6F if is_link(rd) => Transfer::Call,
+        0x67 if is_link(rd) => Transfer::Call,
+        0x67 if rd == 0 && is_link(rs1) => Transfer::Return,
+        _ if raw == MRET || raw == SRET => Transfer::Return,
+        _ => Transfer::Other,
+    }
+}
+
+/// Exact execution profile: every round of every hart is counted against
+/// its pc and its call stack. The interpreter has no timing model, so a
+/// cycle is one round; an instruction that traps costs a cycle without
+/// retiring. Needs `Hart::commit` to be recorded.
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    pcs: HashMap<u64, PcCount>,
+    /// Per hart, the entry points of the functions on the call stack, as
+    /// followed from calls, returns and traps.
+    stacks: Vec<Vec<u64>>,
+    /// Cycles per distinct call stack.
+    folded: HashMap<Vec<u64>, u64>,
+}
+
+impl Profiler {
+    pub fn new(harts: &[Hart]) -> Self {
+        Self {
+            pcs: HashMap::new(),
+            stacks: harts.iter().map(|hart| vec![hart.pc]).collect(),
+            folded: HashMap::new(),
+        }
+    }
+
+    /// Counts the round `hart` (number `index`) just ran.
+    pub fn record(&mut self, index: usize, hart: &Hart) {
+        let commit = match &hart.commit {
+            Some(commit) => commit,
+            None => return,
+        };
+        let count = self.pcs.entry(commit.pc).or_default();
+        count.cycles += 1;
+        if commit.len != 0 {
+            count.raw = commit.raw;
+            count.xlen = commit.xlen;
+        }
+        let stack = &mut self.stacks[index];
+        match self.folded.get_mut(stack.as_slice()) {
+            Some(cycles) => *cycles += 1,
+            None => {
+                self.folded.insert(stack.clone(), 1);
+            }
+        }
+        if !commit.retired {
+            // a trap enters its handler like a call; `mret`/`sret` return.
+            Self::push(stack, hart.pc);
+            return;
+        }
+        count.instructions += 1;
+        match transfer(commit.raw, commit.len, commit.xlen) {
+            Transfer::Call => Self::push(stack, hart.pc),
+            Transfer::Return if stack.len() > 1 => {
+                stack.pop();
+            }
+            _ => {}
+        }
+    }
+
+    fn push(stack: &mut Vec<u64>, entry: u64) {
+        if stack.len() == MAX_DEPTH {
+            stack.remove(0);
+        }
+        stack.push(entry);
+    }
+
+    fn name(elf: Option<&ElfFile>, addr: u64) -> String {
+        match elf.and_then(|elf| elf.function_at(addr)) {
+            Some(symbol) => symbol.name.clone(),
+            None => format!("{:#x}", addr),
+        }
+    }
+
+    /// The `top` functions and pcs that took the most cycles.
+    pub fn report(&self, elf: Option<&ElfFile>, top: usize) -> String {
+        let cycles: u64 = self.pcs.values().map(|c| c.cycles).sum();
+        let instructions: u64 = self.pcs.values().map(|c| c.instructions).sum();
+        let percent = |part: u64| if cycles == 0 { 0.0 } else { part as f64 * 100.0 / cycles as f64 };
+        let mut out = format!("{} cycles, {} instructions retired\n", cycles, instructions);
+
+        let mut functions: HashMap<String, (u64, u64)> = HashMap::new();
+        for (pc, count) in self.pcs.iter() {
+            let entry = functions.entry(Self::name(elf, *pc)).or_default();
+            entry.0 += count.cycles;
+            entry.1 += count.instructions;
+        }
+        let mut functions: Vec<(String, (u64, u64))> = functions.into_iter().collect();
+        functions.sort_by(|a, b| b.1 .0.cmp(&a.1 .0).then_with(|| a.0.cmp(&b.0)));
+        let _ = writeln!(out, "\ntop functions:\n{:>14} {:>7} {:>14}  function", "cycles", "%", "instructions");
+        for (name, (cycles, instructions)) in functions.iter().take(top) {
+            let _ = writeln!(out, "{:>14} {:>6.2}% {:>14}  {}", cycles, percent(*cycles), instructions, name);
+        }
+
+        let mut pcs: Vec<(&u64, &PcCount)> = self.pcs.iter().collect();
+        pcs.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then_with(|| a.0.cmp(b.0)));
+        let _ = writeln!(out, "\ntop pcs:\n{:>14} {:>7} {:>18}  instruction", "cycles", "%", "pc");
+        for (pc, count) in pcs.iter().take(top) {
+            let place = elf
+                .and_then(|elf| elf.symbolize(**pc))
+                .map(|(symbol, offset)| format!(" <{}+{:#x}>", symbol.name, offset))
+                .unwrap_or_default();
+            let _ = writeln!(
+                out,
+                "{:>14} {:>6.2}% {:#018x}  {}{}",
+                count.cycles,
+                percent(count.cycles),
+                pc,
+                disassemble(count.raw, count.xlen),
+                place
+            );
+        }
+        out
+    }
+
+    /// Call stacks in the folded format flame graph tools read: frames
+    /// from the outermost in, separated by `;`, then the cycle count.
+    pub fn folded(&self, elf: Option<&ElfFile>) -> String {
+        let mut lines: HashMap<String, u64> = HashMap::new();
+        for (stack, cycles) in self.folded.iter() {
+            let frames: Vec<String> = stack.iter().map(|entry| Self::name(elf, *entry)).collect();
+            *lines.entry(frames.join(";")).or_default() += cycles;
+        }
+        let mut lines: Vec<(String, u64)> = lines.into_iter().collect();
+        lines.sort();
+        let mut out = String::new();
+        for (stack, cycles) in lines {
+            let _ = writeln!(out, "{} {}", stack, cycles);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Elf::{Segment, Symbol, SymbolKind};
+    use crate::Emulator::Machine;
+    use crate::Memory::RAM_BASE;
+
+    const PROGRAM: [u32; 9] = [
+        0x00C0_00EF, // main: jal leaf
+        0x0080_00EF, // jal leaf
+        0x0000_006F, // 1: j 1b, past the end of `main`
+        0x0000_0073, // leaf: ecall
+        0x0000_8067, // ret
+        0x3410_22F3, // trap: csrr t0, mepc
+        0x0042_8293, // addi t0, t0, 4
+        0x3412_9073, // csrw mepc, t0
+        0x3020_0073, // mret
+    ];
+
+    fn elf() -> ElfFile {
+        let symbol = |name: &str, offset, size| Symbol {
+            name: name.to_string(),
+            value: RAM_BASE + offset,
+            size,
+            kind: SymbolKind::Function,
+        };
+        ElfFile {
+            xlen: 64,
+            entry: RAM_BASE,
+            segments: vec![Segment {
+                vaddr: RAM_BASE,
+                paddr: RAM_BASE,
+                data: PROGRAM.iter().flat_map(|word| word.to_le_bytes()).collect(),
+                memsz: PROGRAM.len() as u64 * 4,
+            }],
+            symbols: vec![symbol("main", 0, 8), symbol("leaf", 0xC, 8), symbol("trap", 0x14, 0x10)],
+            debug_sections: Vec::new(),
+        }
+    }
+
+    /// Two calls to `leaf`, each trapping into `trap` once, then six
+    /// rounds spinning outside of any function.
+    fn profile(elf: &ElfFile) -> Profiler {
+        let mut machine = Machine::new(1, 1 << 16);
+        elf.load(&mut machine.bus).unwrap();
+        machine.harts[0].pc = elf.entry;
+        machine.harts[0].csr.mtvec = RAM_BASE + 0x14;
+        machine.enable_profiler();
+        machine.run(Some(20));
+        machine.profiler.unwrap()
+    }
+
+    #[test]
+    fn functions_and_pcs() {
+        let elf = elf();
+        let report = profile(&elf).report(Some(&elf), 2);
+        // the `ecall`s cost a cycle each without retiring.
+        let expected = "\
+20 cycles, 18 instructions retired
+
+top functions:
+        cycles       %   instructions  function
+             8  40.00%              8  trap
+             6  30.00%              6  0x80000008
+
+top pcs:
+        cycles       %                 pc  instruction
+             6  30.00% 0x0000000080000008  j       pc + 0x0
+             2  10.00% 0x000000008000000c  ecall <leaf+0x0>
+";
+        assert_eq!(report, expected);
+        let report = profile(&elf).report(Some(&elf), 4);
+        assert!(report.contains("             4  20.00%              2  leaf\n"));
+        assert!(report.contains("             2  10.00%              2  main\n"));
+    }
+
+    #[test]
+    fn folded_stacks() {
+        let elf = elf();
+        let profiler = profile(&elf);
+        assert_eq!(profiler.folded(Some(&elf)), "main 8\nmain;leaf 4\nmain;leaf;trap 8\n");
+        // without symbols, frames are their entry points.
+        assert_eq!(
+            profiler.folded(None),
+            "0x80000000 8\n0x80000000;0x8000000c 4\n0x80000000;0x8000000c;0x80000014 8\n"
+        );
+    }
+}
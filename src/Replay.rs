@@ -0,0 +1,566 @@
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+
+use crate::Coverage::Coverage;
+use crate::Emulator::{Machine, StopReason};
+use crate::Htif::Htif;
+use crate::Memory::{PAGE_SHIFT, PAGE_SIZE};
+use crate::Profile::Profiler;
+use crate::Stats::Stats;
+use crate::Trace::CommitLog;
+
+/// Rounds between the checkpoints `History` takes while running live.
+pub const CHECKPOINT_INTERVAL: u64 = 100_000;
+
+const MAGIC: &str = "zrv64-recording 1";
+
+/// Everything the devices did to the machine at one point in time. The
+/// harts themselves are deterministic, so these are the only inputs a
+/// replay needs: console bytes, syscall results read from host files,
+/// exit requests. `mtime` ticks with the instruction count and is not an
+/// input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceEvent {
+    /// `Machine::steps` when it happened.
+    pub step: u64,
+    /// The hart whose `tohost` write was answered, or `None` for the poll
+    /// on a timer tick.
+    pub hart: Option<usize>,
+    /// Guest memory written, as `(addr, bytes)`.
+    pub writes: Vec<(u64, Vec<u8>)>,
+    /// Set when the target asked to exit.
+    pub exit_code: Option<u64>,
+}
+
+/// How `Machine::step_with` deals with the devices.
+pub enum DeviceMode<'a> {
+    /// Talk to the host.
+    Live,
+    /// Talk to the host and append what came back to the recording.
+    Record(&'a mut Recording),
+    /// Leave the host alone and take the devices' effects from the
+    /// recording instead.
+    Replay(&'a mut Recording),
+}
+
+/// A log of device activity, timestamped by instruction count.
+#[derive(Debug, Clone, Default)]
+pub struct Recording {
+    pub events: Vec<DeviceEvent>,
+    /// How far the recording reaches: the rounds before it are complete.
+    pub horizon: u64,
+    /// The next event to replay.
+    cursor: usize,
+}
+
+impl Recording {
+    /// An empty recording of a machine that has run `steps` rounds so far.
+    pub fn new(steps: u64) -> Self {
+        Self {
+            events: Vec::new(),
+            horizon: steps,
+            cursor: 0,
+        }
+    }
+
+    /// Appends an event seen while recording.
+    pub fn push(&mut self, event: DeviceEvent) {
+        self.events.push(event);
+        self.cursor = self.events.len();
+    }
+
+    /// Takes the next event if it belongs at `step` for `hart`.
+    pub fn next_event(&mut self, step: u64, hart: Option<usize>) -> Option<&DeviceEvent> {
+        let event = self.events.get(self.cursor)?;
+        if event.step != step || event.hart != hart {
+            return None;
+        }
+        self.cursor += 1;
+        self.events.get(self.cursor - 1)
+    }
+
+    /// Moves the replay position to a machine that has run `steps` rounds.
+    /// The tick poll of round `steps` has already happened by then, the
+    /// harts' `tohost` writes of that round have not.
+    pub fn seek(&mut self, steps: u64) {
+        self.cursor = self.events.partition_point(|e| e.step < steps || (e.step == steps && e.hart.is_none()));
+    }
+
+    /// Events that have not been replayed.
+    pub fn remaining(&self) -> usize {
+        self.events.len() - self.cursor
+    }
+
+    /// Saves the recording as text: a header line, the horizon, then one
+    /// line per event:
+    ///
+    /// `<step> <hart|poll> [exit=<code>] [<addr>:<hex bytes>]...`
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let mut out = format!("{}\nhorizon {}\n", MAGIC, self.horizon);
+        for event in self.events.iter() {
+            let _ = match event.hart {
+                Some(hart) => write!(out, "{} {}", event.step, hart),
+                None => write!(out, "{} poll", event.step),
+            };
+            if let Some(code) = event.exit_code {
+                let _ = write!(out, " exit={}", code);
+            }
+            for (addr, bytes) in event.writes.iter() {
+                let _ = write!(out, " {:x}:", addr);
+                for byte in bytes.iter() {
+                    let _ = write!(out, "{:02x}", byte);
+                }
+            }
+            out.push('\n');
+        }
+        std::fs::write(path, out).map_err(|e| format!("could not write `{}`: {}", path, e))
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("could not read `{}`: {}", path, e))?;
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, line)| line) != Some(MAGIC) {
+            return Err(format!("`{}` is not a recording", path));
+        }
+        let horizon = lines
+            .next()
+            .and_then(|(_, line)| line.strip_prefix("horizon ")?.parse().ok())
+            .ok_or(format!("`{}` has no horizon line", path))?;
+        let mut recording = Self::new(horizon);
+        for (number, line) in lines {
+            let event = parse_event(line).ok_or(format!("{}:{}: bad event `{}`", path, number + 1, line))?;
+            recording.events.push(event);
+        }
+        Ok(recording)
+    }
+}
+
+fn parse_event(line: &str) -> Option<DeviceEvent> {
+    let mut fields = line.split_whitespace();
+    let step = fields.next()?.parse().ok()?;
+    let hart = match fields.next()? {
+        "poll" => None,
+        hart => Some(hart.parse().ok()?),
+    };
+    let mut event = DeviceEvent {
+        step,
+        hart,
+        writes: Vec::new(),
+        exit_code: None,
+    };
+    for field in fields {
+        if let Some(code) = field.strip_prefix("exit=") {
+            event.exit_code = Some(code.parse().ok()?);
+            continue;
+        }
+        let (addr, hex) = field.split_once(':')?;
+        if !hex.len().is_multiple_of(2) {
+            return None;
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        event.writes.push((u64::from_str_radix(addr, 16).ok()?, bytes));
+    }
+    Some(event)
+}
+
+/// Runs the machine live like `Machine::run`, appending device activity
+/// to `recording`.
+pub fn record(machine: &mut Machine, recording: &mut Recording, limit: Option<u64>) -> StopReason {
+    machine.run_with(limit, &mut DeviceMode::Record(recording))
+}
+
+/// Re-runs a recorded execution, stopping where the recording ends.
+pub fn replay(machine: &mut Machine, recording: &mut Recording, limit: Option<u64>) -> StopReason {
+    recording.seek(machine.steps);
+    let left = recording.horizon.saturating_sub(machine.steps);
+    machine.run_with(Some(limit.map_or(left, |limit| limit.min(left))), &mut DeviceMode::Replay(recording))
+}
+
+/// The machine as of a checkpoint, RAM aside.
+#[derive(Debug, Clone)]
+struct Checkpoint {
+    /// Everything but `bus.ram.data`, which is left empty.
+    machine: Machine,
+    /// Pages written since the previous checkpoint, as they were at this one.
+    pages: Vec<(usize, Vec<u8>)>,
+}
+
+/// What watches the machine rather than being part of it. Kept across
+/// restores, and taken out while rounds already seen are re-executed so
+/// logs, profiles, coverage and statistics do not count them twice.
+struct Observers {
+    commit_log: Option<CommitLog>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    stats: Option<Stats>,
+}
+
+impl Observers {
+    fn take(machine: &mut Machine) -> Self {
+        Self {
+            commit_log: machine.commit_log.take(),
+            profiler: machine.profiler.take(),
+            coverage: machine.coverage.take(),
+            stats: machine.stats.take(),
+        }
+    }
+
+    fn put_back(self, machine: &mut Machine) {
+        machine.commit_log = self.commit_log;
+        machine.profiler = self.profiler;
+        machine.coverage = self.coverage;
+        machine.stats = self.stats;
+    }
+}
+
+/// Execution history for reverse debugging. Stepping through a `History`
+/// records device activity and takes a checkpoint every
+/// `CHECKPOINT_INTERVAL` rounds; going back restores the closest checkpoint
+/// and replays forward from it. Stepping again after going back replays
+/// until the newest point reached, then continues live.
+#[derive(Debug)]
+pub struct History {
+    pub recording: Recording,
+    /// When replaying a recording loaded from a file there is nothing
+    /// live to continue with past its end.
+    replay_only: bool,
+    /// RAM when the history started.
+    base_ram: Vec<u8>,
+    checkpoints: Vec<Checkpoint>,
+    /// The device state at the newest point reached, kept while the
+    /// machine is back in the past.
+    frontier_htif: Option<Option<Htif>>,
+}
+
+impl History {
+    /// Starts recording history from the machine's current state. With a
+    /// `recording` from a file, the history is that recording instead and
+    /// ends where it does.
+    pub fn new(machine: &mut Machine, recording: Option<Recording>) -> Self {
+        let replay_only = recording.is_some();
+        let mut recording = recording.unwrap_or_else(|| Recording::new(machine.steps));
+        recording.seek(machine.steps);
+        let mut history = Self {
+            recording,
+            replay_only,
+            base_ram: machine.bus.ram.data.clone(),
+            checkpoints: Vec::new(),
+            frontier_htif: None,
+        };
+        machine.bus.ram.clear_dirty();
+        history.checkpoint(machine);
+        history
+    }
+
+    fn checkpoint(&mut self, machine: &mut Machine) {
+        let pages = machine
+            .bus
+            .ram
+            .dirty_pages()
+            .map(|page| {
+                let start = page << PAGE_SHIFT;
+                let end = (start + PAGE_SIZE).min(machine.bus.ram.data.len());
+                (page, machine.bus.ram.data[start..end].to_vec())
+            })
+            .collect();
+        machine.bus.ram.clear_dirty();
+        let data = std::mem::take(&mut machine.bus.ram.data);
+        let observers = Observers::take(machine);
+        let copy = machine.clone();
+        observers.put_back(machine);
+        machine.bus.ram.data = data;
+        self.checkpoints.push(Checkpoint { machine: copy, pages });
+    }
+
+    /// The earliest point the machine can go back to.
+    pub fn start(&self) -> u64 {
+        self.checkpoints[0].machine.steps
+    }
+
+    /// The latest point the machine can go to without running live.
+    pub fn end(&self) -> u64 {
+        self.recording.horizon
+    }
+
+    /// Runs one round, replaying where history is already known. Returns
+    /// `false`, without running anything, at the end of a replay-only
+    /// history.
+    pub fn step(&mut self, machine: &mut Machine) -> bool {
+        if machine.steps < self.recording.horizon {
+            machine.step_with(&mut DeviceMode::Replay(&mut self.recording));
+            if machine.steps == self.recording.horizon {
+                if let Some(htif) = self.frontier_htif.take() {
+                    machine.bus.htif = htif;
+                }
+            }
+            return true;
+        }
+        if self.replay_only {
+            return false;
+        }
+        machine.step_with(&mut DeviceMode::Record(&mut self.recording));
+        if machine.steps.is_multiple_of(CHECKPOINT_INTERVAL) {
+            self.checkpoint(machine);
+        }
+        true
+    }
+
+    /// Like `Machine::run`, but through the history.
+    pub fn run(&mut self, machine: &mut Machine, limit: Option<u64>) -> StopReason {
+        let mut count = 0;
+        loop {
+            if let Some(code) = machine.exit_code() {
+                return StopReason::Exit(code);
+            }
+            if limit.is_some_and(|limit| count >= limit) || !self.step(machine) {
+                return StopReason::InstructionLimit;
+            }
+            count += 1;
+        }
+    }
+
+    /// Puts the machine back to checkpoint `index`. Watchpoints and the
+    /// observers belong to the debugging session and are kept.
+    fn restore(&mut self, machine: &mut Machine, index: usize) {
+        if machine.steps == self.recording.horizon && self.frontier_htif.is_none() {
+            self.frontier_htif = Some(machine.bus.htif.clone());
+        }
+        // pages written after the checkpoint need their old contents back.
+        let mut touched: BTreeSet<usize> = machine.bus.ram.dirty_pages().collect();
+        for later in self.checkpoints[index + 1..].iter() {
+            touched.extend(later.pages.iter().map(|(page, _)| *page));
+        }
+        let data = std::mem::take(&mut machine.bus.ram.data);
+        let watchpoints = std::mem::take(&mut machine.bus.watchpoints);
+        let observers = Observers::take(machine);
+        *machine = self.checkpoints[index].machine.clone();
+        machine.bus.ram.data = data;
+        machine.bus.watchpoints = watchpoints;
+        observers.put_back(machine);
+        for page in touched {
+            let start = page << PAGE_SHIFT;
+            let end = (start + PAGE_SIZE).min(self.base_ram.len());
+            let contents = self.checkpoints[..=index]
+                .iter()
+                .rev()
+                .find_map(|c| c.pages.iter().find(|(p, _)| *p == page).map(|(_, bytes)| bytes.as_slice()))
+                .unwrap_or(&self.base_ram[start..end]);
+            machine.bus.ram.data[start..end].copy_from_slice(contents);
+        }
+        machine.bus.ram.clear_dirty();
+        machine.bus.watch_hit = None;
+        self.recording.seek(machine.steps);
+    }
+
+    /// Moves the machine to the point where it had run `target` rounds,
+    /// clamped to the history. Re-executed rounds are not logged or
+    /// profiled again.
+    pub fn seek(&mut self, machine: &mut Machine, target: u64) {
+        let target = target.clamp(self.start(), self.end());
+        if target < machine.steps {
+            let index = self.checkpoints.partition_point(|c| c.machine.steps <= target) - 1;
+            self.restore(machine, index);
+        }
+        let observers = Observers::take(machine);
+        while machine.steps < target && self.step(machine) {}
+        observers.put_back(machine);
+    }
+
+    /// Goes back to the last point before the current one where
+    /// `stop_at` holds, checked after every round (and at checkpoints).
+    /// Returns `false` after going all the way back to the start without
+    /// finding one.
+    pub fn reverse_until(&mut self, machine: &mut Machine, mut stop_at: impl FnMut(&mut Machine) -> bool) -> bool {
+        let mut end = machine.steps;
+        let observers = Observers::take(machine);
+        let mut found = None;
+        for index in (0..self.checkpoints.len()).rev() {
+            let start = self.checkpoints[index].machine.steps;
+            if start >= end {
+                continue;
+            }
+            self.restore(machine, index);
+            if stop_at(machine) {
+                found = Some(start);
+            }
+            while machine.steps + 1 < end {
+                self.step(machine);
+                if stop_at(machine) {
+                    found = Some(machine.steps);
+                }
+            }
+            if found.is_some() {
+                break;
+            }
+            end = start;
+        }
+        observers.put_back(machine);
+        match found {
+            Some(target) => {
+                self.seek(machine, target);
+                machine.bus.watch_hit = None;
+                true
+            }
+            None => {
+                self.seek(machine, self.start());
+                false
+            }
+        }
+    }
+
+    /// Forgets everything after the current point and starts over from
+    /// here, for when the debugger changes registers or memory and the
+    /// recorded future no longer applies.
+    pub fn restart(&mut self, machine: &mut Machine) {
+        self.frontier_htif = None;
+        self.recording.seek(machine.steps);
+        let cursor = self.recording.events.len() - self.recording.remaining();
+        self.recording.events.truncate(cursor);
+        self.recording.horizon = machine.steps;
+        self.replay_only = false;
+        self.base_ram = machine.bus.ram.data.clone();
+        self.checkpoints.clear();
+        machine.bus.ram.clear_dirty();
+        self.checkpoint(machine);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Memory::RAM_BASE;
+
+    const TOHOST: u64 = RAM_BASE;
+    const FROMHOST: u64 = RAM_BASE + 8;
+    const CODE: u64 = RAM_BASE + 0x1000;
+    /// Where the countdown loop stores its counter, on a page of its own.
+    const COUNTER: u64 = RAM_BASE + 0x3000;
+    const LOOP: u64 = CODE + 0x40;
+    const AFTER_WAIT: u64 = CODE + 0x30;
+    const ITERATIONS: u64 = 15 << 12;
+
+    /// Asks the host for `argc` with `pk`'s getmainvars, counts down from
+    /// `ITERATIONS` storing the counter, then exits with `argc`.
+    const PROGRAM: [u32; 23] = [
+        0x0010_0293, // li t0, 1
+        0x01F2_9293, // slli t0, t0, 31
+        0x1002_8313, // addi t1, t0, 0x100
+        0x7DB0_0393, // li t2, 2011
+        0x0073_3023, // sd t2, 0(t1)
+        0x2002_8E13, // addi t3, t0, 0x200
+        0x01C3_3423, // sd t3, 8(t1)
+        0x1000_0E93, // li t4, 0x100
+        0x01D3_3823, // sd t4, 16(t1)
+        0x0062_B023, // sd t1, 0(t0)
+        0x0082_BF03, // 1: ld t5, 8(t0)
+        0xFE0F_0EE3, // beqz t5, 1b
+        0x2002_B503, // ld a0, 0x200(t0)
+        0x0000_3FB7, // lui t6, 3
+        0x005F_8FB3, // add t6, t6, t0
+        0x0000_F437, // lui s0, 15
+        0x008F_B023, // 2: sd s0, 0(t6)
+        0xFFF4_0413, // addi s0, s0, -1
+        0xFE04_1CE3, // bnez s0, 2b
+        0x0015_1593, // slli a1, a0, 1
+        0x0015_E593, // ori a1, a1, 1
+        0x00B2_B023, // sd a1, 0(t0)
+        0x0000_006F, // 3: j 3b
+    ];
+
+    fn machine(args: &[&str]) -> Machine {
+        let mut machine = Machine::new(1, 1 << 16);
+        for (i, word) in PROGRAM.iter().enumerate() {
+            machine.bus.store(CODE + i as u64 * 4, 4, *word as u64);
+        }
+        let args = args.iter().map(|arg| arg.to_string()).collect();
+        machine.bus.htif = Some(Htif::new(TOHOST, Some(FROMHOST), args));
+        machine.harts[0].pc = CODE;
+        machine
+    }
+
+    fn assert_same(a: &Machine, b: &Machine) {
+        assert_eq!(a.steps, b.steps);
+        for (a, b) in a.harts.iter().zip(b.harts.iter()) {
+            assert_eq!((a.pc, a.x, a.f), (b.pc, b.x, b.f));
+            assert_eq!(a.csr, b.csr);
+        }
+        assert!(a.bus.ram.data == b.bus.ram.data, "RAM differs");
+    }
+
+    #[test]
+    fn replay_is_deterministic() {
+        let mut live = machine(&["prog", "-v"]);
+        let mut recording = Recording::new(live.steps);
+        assert_eq!(record(&mut live, &mut recording, None), StopReason::Exit(2));
+        assert_eq!(recording.horizon, live.steps);
+        assert_eq!(recording.events.iter().filter(|e| e.hart == Some(0)).count(), 2);
+
+        let path = std::env::temp_dir().join(format!("replay-test-{}", std::process::id()));
+        let path = path.to_string_lossy();
+        recording.save(&path).unwrap();
+        let loaded = Recording::load(&path).unwrap();
+        std::fs::remove_file(&*path).unwrap();
+        assert_eq!((&loaded.events, loaded.horizon), (&recording.events, recording.horizon));
+
+        // without arguments of its own, the replay still sees argc = 2 and
+        // exits the same way: the host is not asked.
+        for mut recording in [recording, loaded] {
+            let mut replayed = machine(&[]);
+            assert_eq!(replay(&mut replayed, &mut recording, None), StopReason::Exit(2));
+            assert_eq!(recording.remaining(), 0);
+            assert_same(&live, &replayed);
+        }
+    }
+
+    /// The pc, `s0` and the counter in memory.
+    fn state(machine: &mut Machine) -> (u64, u64, Option<u64>) {
+        let hart = &machine.harts[0];
+        (hart.pc, hart.x[8], machine.bus.load(COUNTER, 8))
+    }
+
+    #[test]
+    fn reverse_execution() {
+        let mut machine = machine(&["prog"]);
+        let mut history = History::new(&mut machine, None);
+        // the state before every round.
+        let mut states = Vec::new();
+        let mut after_wait = None;
+        loop {
+            if machine.harts[0].pc == AFTER_WAIT {
+                after_wait = Some(machine.steps);
+            }
+            states.push(state(&mut machine));
+            if history.run(&mut machine, Some(1)) == StopReason::Exit(1) {
+                break;
+            }
+        }
+        let end = machine.steps;
+        assert!(end > CHECKPOINT_INTERVAL + 1, "the run spans checkpoints");
+
+        // reverse-step, across a checkpoint too.
+        history.seek(&mut machine, end - 1);
+        assert_eq!(state(&mut machine), states[end as usize - 1]);
+        for target in [CHECKPOINT_INTERVAL + 1, CHECKPOINT_INTERVAL, CHECKPOINT_INTERVAL - 1, 5] {
+            history.seek(&mut machine, target);
+            assert_eq!(machine.steps, target);
+            assert_eq!(state(&mut machine), states[target as usize]);
+        }
+
+        // reverse-continue lands on the last time the condition held.
+        history.seek(&mut machine, end);
+        assert!(history.reverse_until(&mut machine, |machine| machine.harts[0].pc == LOOP));
+        assert_eq!(state(&mut machine), (LOOP, 1, Some(2)));
+        assert!(history.reverse_until(&mut machine, |machine| machine.harts[0].pc == AFTER_WAIT));
+        assert_eq!(Some(machine.steps), after_wait);
+        assert_eq!(state(&mut machine), states[machine.steps as usize]);
+        assert!(!history.reverse_until(&mut machine, |machine| machine.harts[0].pc == AFTER_WAIT));
+        assert_eq!(machine.steps, history.start());
+
+        // and forwards again, through the recorded host reply, to the exit.
+        assert_eq!(history.run(&mut machine, None), StopReason::Exit(1));
+        assert_eq!(machine.steps, end);
+    }
+}
@@ -0,0 +1,259 @@
+use crate::Memory::PAGE_SIZE;
+
+/// Bytes covered by a reservation unless `--reservation-granule` says
+/// otherwise: a typical cache line.
+pub const DEFAULT_GRANULE: u64 = 64;
+
+/// Steps of its hart during which a fresh reservation keeps other harts'
+/// atomics out of its granule. A constrained `LR`/`SC` loop is at most 16
+/// instructions, so its `SC` always gets in before a competing loop's can
+/// break the reservation, and every such loop eventually succeeds.
+pub const HOLD_STEPS: u64 = 16;
+
+/// A hart's reservation from its last `LR`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reservation {
+    /// Physical address of the reserved granule.
+    pub addr: u64,
+    /// Steps left before other harts may touch the granule again.
+    pub hold: u64,
+}
+
+/// The reservation set of every hart on a bus.
+///
+/// `LR` reserves the granule it loads from. A store by any other hart, or
+/// by a device, to a reserved granule breaks that reservation, and a later
+/// `SC` of its hart fails; a hart's own stores leave it alone, and its
+/// trap returns drop it. While a reservation is fresh, other harts' `LR`,
+/// `SC` and AMOs on its granule wait rather than break it (see
+/// `HOLD_STEPS`). Harts take turns one instruction at a time, so AMOs are
+/// atomic as they are.
+#[derive(Debug, Clone)]
+pub struct Reservations {
+    granule: u64,
+    /// By hart id.
+    harts: Vec<Option<Reservation>>,
+    /// How many of `harts` are set, so stores check nothing when none are.
+    active: usize,
+}
+
+impl Default for Reservations {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reservations {
+    pub fn new() -> Self {
+        Self {
+            granule: DEFAULT_GRANULE,
+            harts: Vec::new(),
+            active: 0,
+        }
+    }
+
+    pub fn granule(&self) -> u64 {
+        self.granule
+    }
+
+    /// Sets the reservation granule: a power of two from 8 bytes, so it
+    /// covers any `LR.D`, to a page.
+    pub fn set_granule(&mut self, granule: u64) -> Result<(), String> {
+        if !granule.is_power_of_two() || !(8..=PAGE_SIZE as u64).contains(&granule) {
+            return Err(format!(
+                "the reservation granule must be a power of two from 8 to {} bytes, not {}",
+                PAGE_SIZE, granule
+            ));
+        }
+        self.granule = granule;
+        Ok(())
+    }
+
+    fn base(&self, addr: u64) -> u64 {
+        addr & !(self.granule - 1)
+    }
+
+    pub fn get(&self, hart: u64) -> Option<Reservation> {
+        self.harts.get(hart as usize).copied().flatten()
+    }
+
+    /// Replaces `hart`'s reservation.
+    pub fn set(&mut self, hart: u64, reservation: Option<Reservation>) {
+        let hart = hart as usize;
+        if self.harts.len() <= hart {
+            self.harts.resize(hart + 1, None);
+        }
+        self.active -= self.harts[hart].is_some() as usize;
+        self.active += reservation.is_some() as usize;
+        self.harts[hart] = reservation;
+    }
+
+    /// `LR` by `hart` from physical address `addr`.
+    pub fn reserve(&mut self, hart: u64, addr: u64) {
+        let reservation = Reservation {
+            addr: self.base(addr),
+            hold: HOLD_STEPS,
+        };
+        self.set(hart, Some(reservation));
+    }
+
+    /// `SC` by `hart` to physical address `addr`: whether it may store,
+    /// dropping the reservation either way.
+    pub fn take(&mut self, hart: u64, addr: u64) -> bool {
+        let held = self.get(hart).is_some_and(|r| r.addr == self.base(addr));
+        if self.active > 0 {
+            self.set(hart, None);
+        }
+        held
+    }
+
+    /// `MRET` or `SRET` by `hart`: drops its reservation, so an `LR` before
+    /// a trap never pairs with an `SC` after the handler returns.
+    pub fn release(&mut self, hart: u64) {
+        if self.active > 0 {
+            self.set(hart, None);
+        }
+    }
+
+    /// Whether `hart` has to wait before touching physical address `addr`,
+    /// because another hart's reservation is holding its granule.
+    #[inline]
+    pub fn held_by_other(&self, hart: u64, addr: u64) -> bool {
+        if self.active == 0 {
+            return false;
+        }
+        let base = self.base(addr);
+        self.harts.iter().enumerate().any(|(id, r)| {
+            id as u64 != hart && r.is_some_and(|r| r.hold > 0 && r.addr == base)
+        })
+    }
+
+    /// Breaks the reservations on granules that `len` bytes at physical
+    /// address `addr` touch, except for the storing hart's own, if any.
+    #[inline]
+    pub fn invalidate(&mut self, addr: u64, len: u64, by: Option<u64>) {
+        if self.active == 0 {
+            return;
+        }
+        let (first, last) = (self.base(addr), self.base(addr.wrapping_add(len.max(1) - 1)));
+        for id in 0..self.harts.len() {
+            if let Some(r) = self.harts[id] {
+                if Some(id as u64) != by && first <= r.addr && r.addr <= last {
+                    self.set(id as u64, None);
+                }
+            }
+        }
+    }
+
+    /// Counts a step of `hart` against its reservation's hold.
+    #[inline]
+    pub fn tick(&mut self, hart: u64) {
+        if self.active == 0 {
+            return;
+        }
+        if let Some(Some(r)) = self.harts.get_mut(hart as usize) {
+            r.hold = r.hold.saturating_sub(1);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "a"))]
+mod tests {
+    use super::*;
+    use crate::Cpu::{Hart, Privilege};
+    use crate::Decode::Decoded;
+    use crate::Isa::Isa;
+    use crate::Memory::{Bus, RAM_BASE};
+
+    const LR_W: u32 = 0x1005_A52F; // lr.w a0, (a1)
+    const SC_W: u32 = 0x18C5_A52F; // sc.w a0, a2, (a1)
+    const SW: u32 = 0x00C5_A023; // sw a2, 0(a1)
+    const MRET: u32 = 0x3020_0073;
+
+    const WORD: u64 = RAM_BASE + 0x100;
+
+    fn run(hart: &mut Hart, bus: &mut Bus, raw: u32, addr: u64) -> u64 {
+        hart.x[11] = addr;
+        hart.x[12] = 0x1000 + hart.id;
+        let inst = Decoded::decode(raw, &Isa::default()).unwrap();
+        hart.execute(bus, &inst).unwrap();
+        hart.x[10]
+    }
+
+    /// Two harts sharing a bus.
+    fn harts() -> (Hart, Hart, Bus) {
+        (Hart::new(0), Hart::new(1), Bus::new(4096))
+    }
+
+    /// Lets the holds of both harts' reservations run out.
+    fn unhold(bus: &mut Bus) {
+        for _ in 0..HOLD_STEPS {
+            bus.reservations.tick(0);
+            bus.reservations.tick(1);
+        }
+    }
+
+    #[test]
+    fn other_harts_stores_break_the_granule() {
+        let (mut a, mut b, mut bus) = harts();
+        run(&mut a, &mut bus, LR_W, WORD);
+        unhold(&mut bus);
+        // a store by `a` itself, or by `b` outside the granule, leaves the
+        // reservation alone.
+        run(&mut a, &mut bus, SW, WORD + 4);
+        run(&mut b, &mut bus, SW, WORD + DEFAULT_GRANULE);
+        assert_eq!(run(&mut a, &mut bus, SC_W, WORD), 0);
+        assert_eq!(bus.load(WORD, 4), Some(0x1000));
+
+        run(&mut a, &mut bus, LR_W, WORD);
+        run(&mut b, &mut bus, SW, WORD + DEFAULT_GRANULE - 4);
+        assert_eq!(run(&mut a, &mut bus, SC_W, WORD), 1);
+        assert_eq!(bus.load(WORD, 4), Some(0x1000));
+    }
+
+    #[test]
+    fn sc_within_the_granule() {
+        let (mut a, _, mut bus) = harts();
+        bus.reservations.set_granule(16).unwrap();
+        run(&mut a, &mut bus, LR_W, WORD + 4);
+        assert_eq!(run(&mut a, &mut bus, SC_W, WORD + 12), 0);
+        run(&mut a, &mut bus, LR_W, WORD + 4);
+        assert_eq!(run(&mut a, &mut bus, SC_W, WORD + 16), 1);
+        assert_eq!(bus.load(WORD + 16, 4), Some(0));
+        // the failed `SC` used the reservation up.
+        assert_eq!(run(&mut a, &mut bus, SC_W, WORD + 4), 1);
+    }
+
+    #[test]
+    fn trap_returns_and_context_switches_drop_it() {
+        let (mut a, _, mut bus) = harts();
+        run(&mut a, &mut bus, LR_W, WORD);
+        a.csr.mepc = RAM_BASE;
+        run(&mut a, &mut bus, MRET, 0);
+        a.privilege = Privilege::Machine;
+        assert_eq!(run(&mut a, &mut bus, SC_W, WORD), 1);
+
+        // a kernel switching threads clears the reservation with a dummy
+        // `SC` of its own, so the thread it resumes cannot succeed.
+        run(&mut a, &mut bus, LR_W, WORD);
+        run(&mut a, &mut bus, SC_W, RAM_BASE + 0x800);
+        assert_eq!(run(&mut a, &mut bus, SC_W, WORD), 1);
+        assert_eq!(bus.load(WORD, 4), Some(0));
+    }
+
+    #[test]
+    fn fresh_reservations_hold_off_other_harts() {
+        let (mut a, mut b, mut bus) = harts();
+        run(&mut a, &mut bus, LR_W, WORD);
+        run(&mut b, &mut bus, LR_W, WORD + 8);
+        assert!(b.waiting);
+        assert_eq!(bus.reservations.get(1), None);
+        unhold(&mut bus);
+        b.waiting = false;
+        run(&mut b, &mut bus, LR_W, WORD + 8);
+        assert!(!b.waiting);
+        // both now hold the granule; whichever stores first wins.
+        assert_eq!(run(&mut b, &mut bus, SC_W, WORD + 8), 0);
+        assert_eq!(run(&mut a, &mut bus, SC_W, WORD), 1);
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use crate::Cpu::{Access, Hart, REG_A0, REG_A1, REG_A6, REG_A7};
+use crate::Csr::*;
+use crate::Htif::spawn_stdin_reader;
+use crate::Memory::Bus;
+
+/// A built-in implementation of the RISC-V Supervisor Binary Interface,
+/// standing in for M-mode firmware when a kernel is booted without any.
+///
+/// An `ECALL` from HS-mode is answered here instead of trapping to M-mode,
+/// with the extension in `a7`, the function in `a6` and the error and
+/// value returned in `a0` and `a1`. It implements:
+///
+/// | extension | functions                                               |
+/// |-----------|---------------------------------------------------------|
+/// | legacy    | `set_timer`, `console_putchar`, `console_getchar`,      |
+/// |           | `clear_ipi`, `send_ipi`, the remote fences, `shutdown`  |
+/// | `BASE`    | all of them                                             |
+/// | `TIME`    | `set_timer`, through the hart's CLINT `mtimecmp`        |
+/// | `sPI`     | `send_ipi`, through the hart's CLINT `msip`             |
+/// | `RFNC`    | all of them                                             |
+/// | `SRST`    | `system_reset`, which stops the machine                 |
+/// | `DBCN`    | all of them, on the emulator's stdin and stdout         |
+///
+/// There is no `HSM`, so secondary harts boot through the kernel's
+/// spinwait lottery. The CLINT lines firmware would forward to S-mode
+/// arrive there directly: `Hart::sample_interrupts` turns the timer into
+/// `STIP` and an IPI into `SSIP`.
+const EXT_BASE: u64 = 0x10;
+const EXT_TIME: u64 = 0x5449_4D45;
+const EXT_IPI: u64 = 0x0073_5049;
+const EXT_RFENCE: u64 = 0x5246_4E43;
+const EXT_SRST: u64 = 0x5352_5354;
+const EXT_DBCN: u64 = 0x4442_434E;
+
+const LEGACY_SET_TIMER: u64 = 0;
+const LEGACY_CONSOLE_PUTCHAR: u64 = 1;
+const LEGACY_CONSOLE_GETCHAR: u64 = 2;
+const LEGACY_CLEAR_IPI: u64 = 3;
+const LEGACY_SEND_IPI: u64 = 4;
+const LEGACY_REMOTE_FENCE_I: u64 = 5;
+const LEGACY_REMOTE_SFENCE_VMA_ASID: u64 = 7;
+const LEGACY_SHUTDOWN: u64 = 8;
+
+const SBI_SUCCESS: i64 = 0;
+const SBI_ERR_FAILED: i64 = -1;
+const SBI_ERR_NOT_SUPPORTED: i64 = -2;
+const SBI_ERR_INVALID_PARAM: i64 = -3;
+
+/// Version 2.0 of the specification, the first with `DBCN`.
+const SPEC_VERSION: u64 = 2 << 24;
+/// Not an id from the specification's registry, which has none for us.
+const IMPL_ID: u64 = 0x5A52;
+const IMPL_VERSION: u64 = 1;
+
+/// Exceptions the kernel handles itself, as OpenSBI delegates them: all
+/// but `ECALL`s to M-mode and from HS-mode, plus a guest's `ECALL`s and
+/// faults with the `H` Extension.
+const DELEGATED_EXCEPTIONS: u64 = 0xB1FF | (1 << 10) | (0xF << 20);
+
+#[derive(Debug, Clone)]
+pub struct Sbi {
+    /// `Some(code)` once the kernel asked to shut down or reboot; the code
+    /// is 1 when it gave a system failure as the reason.
+    pub exit_code: Option<u64>,
+    /// Harts a remote `SFENCE.VMA` or `HFENCE` is waiting for, by hart id.
+    pub fences: Vec<bool>,
+    console_input: Arc<Mutex<VecDeque<u8>>>,
+    console_reader_started: bool,
+}
+
+impl Sbi {
+    pub fn new(hart_count: usize) -> Self {
+        Self {
+            exit_code: None,
+            fences: vec![false; hart_count],
+            console_input: Arc::new(Mutex::new(VecDeque::new())),
+            console_reader_started: false,
+        }
+    }
+
+    /// Sets up what firmware would before entering the kernel on `hart`:
+    /// the delegated exceptions and interrupts and the counters S and
+    /// U-mode may read.
+    pub fn prepare_hart(hart: &mut Hart) {
+        hart.csr_write(MEDELEG, DELEGATED_EXCEPTIONS);
+        hart.csr_write(MIDELEG, S_INTERRUPTS);
+        hart.csr_write(MCOUNTEREN, 0b111);
+    }
+
+    /// Clears the remote fence waiting for hart `hart`, returning whether
+    /// there was one.
+    pub fn take_fence(&mut self, hart: u64) -> bool {
+        self.fences.get_mut(hart as usize).is_some_and(std::mem::take)
+    }
+
+    /// Answers the `ECALL` `hart` just made from HS-mode. The caller moves
+    /// the pc past it.
+    pub fn call(hart: &mut Hart, bus: &mut Bus) {
+        let mut sbi = match bus.sbi.take() {
+            Some(sbi) => sbi,
+            None => return,
+        };
+        let mask = hart.xlen_mask();
+        let args: [u64; 3] = std::array::from_fn(|i| hart.x[REG_A0 + i] & mask);
+        let (extension, function) = (hart.x[REG_A7] & mask, hart.x[REG_A6] & mask);
+        if extension < EXT_BASE {
+            let error = sbi.legacy(hart, bus, extension, args);
+            hart.write_reg(REG_A0, error as u64);
+        } else {
+            let (error, value) = sbi.dispatch(hart, bus, extension, function, args);
+            hart.write_reg(REG_A0, error as u64);
+            hart.write_reg(REG_A1, value);
+        }
+        bus.sbi = Some(sbi);
+    }
+
+    fn dispatch(&mut self, hart: &mut Hart, bus: &mut Bus, extension: u64, function: u64, args: [u64; 3]) -> (i64, u64) {
+        let [a0, a1, a2] = args;
+        match (extension, function) {
+            (EXT_BASE, 0) => (SBI_SUCCESS, SPEC_VERSION),
+            (EXT_BASE, 1) => (SBI_SUCCESS, IMPL_ID),
+            (EXT_BASE, 2) => (SBI_SUCCESS, IMPL_VERSION),
+            (EXT_BASE, 3) => (SBI_SUCCESS, Self::probe(a0) as u64),
+            // `mvendorid`, `marchid` and `mimpid`, all zero.
+            (EXT_BASE, 4..=6) => (SBI_SUCCESS, 0),
+            (EXT_TIME, 0) => {
+                Self::set_timer(hart, bus, a0, a1);
+                (SBI_SUCCESS, 0)
+            }
+            (EXT_IPI, 0) => (self.send(hart, bus, a0, a1, false), 0),
+            (EXT_RFENCE, 0) => {
+                // stores already drop the code they overwrite.
+                bus.blocks.flush();
+                (SBI_SUCCESS, 0)
+            }
+            (EXT_RFENCE, 1..=6) => (self.send(hart, bus, a0, a1, true), 0),
+            (EXT_SRST, 0) => match a0 {
+                0..=2 => {
+                    self.exit_code = Some((a1 == 1) as u64);
+                    (SBI_SUCCESS, 0)
+                }
+                _ => (SBI_ERR_INVALID_PARAM, 0),
+            },
+            (EXT_DBCN, 0) => match Self::physical(hart, a1, a2).and_then(|addr| bus.read_bytes(addr, a0 as usize)) {
+                Some(bytes) => {
+                    let mut out = std::io::stdout();
+                    match out.write_all(bytes).and_then(|_| out.flush()) {
+                        Ok(()) => (SBI_SUCCESS, a0),
+                        Err(_) => (SBI_ERR_FAILED, 0),
+                    }
+                }
+                None => (SBI_ERR_INVALID_PARAM, 0),
+            },
+            (EXT_DBCN, 1) => {
+                let addr = match Self::physical(hart, a1, a2) {
+                    Some(addr) if bus.read_bytes(addr, a0 as usize).is_some() => addr,
+                    _ => return (SBI_ERR_INVALID_PARAM, 0),
+                };
+                let bytes = self.read_console(a0 as usize);
+                bus.write_bytes(addr, &bytes);
+                (SBI_SUCCESS, bytes.len() as u64)
+            }
+            (EXT_DBCN, 2) => {
+                let mut out = std::io::stdout();
+                let _ = out.write_all(&[a0 as u8]);
+                let _ = out.flush();
+                (SBI_SUCCESS, 0)
+            }
+            _ => (SBI_ERR_NOT_SUPPORTED, 0),
+        }
+    }
+
+    /// The version 0.1 calls, which return only an error in `a0` and take
+    /// hart masks by address.
+    fn legacy(&mut self, hart: &mut Hart, bus: &mut Bus, extension: u64, args: [u64; 3]) -> i64 {
+        let [a0, a1, _] = args;
+        match extension {
+            LEGACY_SET_TIMER => {
+                Self::set_timer(hart, bus, a0, a1);
+                SBI_SUCCESS
+            }
+            LEGACY_CONSOLE_PUTCHAR => {
+                let mut out = std::io::stdout();
+                let _ = out.write_all(&[a0 as u8]);
+                let _ = out.flush();
+                SBI_SUCCESS
+            }
+            LEGACY_CONSOLE_GETCHAR => match self.read_console(1).first() {
+                Some(byte) => *byte as i64,
+                None => -1,
+            },
+            LEGACY_CLEAR_IPI => {
+                hart.csr.mip &= !MIP_SSIP;
+                SBI_SUCCESS
+            }
+            LEGACY_SEND_IPI..=LEGACY_REMOTE_SFENCE_VMA_ASID => {
+                // a null mask means every hart.
+                let harts = match a0 {
+                    0 => hart.xlen_mask(),
+                    _ => match Self::load_mask(hart, bus, a0) {
+                        Some(harts) => harts,
+                        None => return SBI_ERR_INVALID_PARAM,
+                    },
+                };
+                match extension {
+                    LEGACY_SEND_IPI => self.send(hart, bus, harts, 0, false),
+                    LEGACY_REMOTE_FENCE_I => {
+                        bus.blocks.flush();
+                        SBI_SUCCESS
+                    }
+                    _ => self.send(hart, bus, harts, 0, true),
+                }
+            }
+            LEGACY_SHUTDOWN => {
+                self.exit_code = Some(0);
+                SBI_SUCCESS
+            }
+            _ => SBI_ERR_NOT_SUPPORTED,
+        }
+    }
+
+    fn probe(extension: u64) -> bool {
+        matches!(extension, LEGACY_SET_TIMER..=LEGACY_SHUTDOWN | EXT_BASE | EXT_TIME | EXT_IPI | EXT_RFENCE | EXT_SRST | EXT_DBCN)
+    }
+
+    /// Arms the timer of `hart` for `mtime` reaching `low`, or `high:low`
+    /// on RV32. Its `STIP` follows until the next call moves it.
+    fn set_timer(hart: &Hart, bus: &mut Bus, low: u64, high: u64) {
+        let value = if hart.xlen() == 32 { (high << 32) | low } else { low };
+        if let Some(cmp) = bus.clint.mtimecmp.get_mut(hart.id as usize) {
+            *cmp = value;
+            bus.clint.changed = true;
+        }
+    }
+
+    /// Sends an IPI, or with `fence` a remote `SFENCE.VMA`, to the harts in
+    /// `mask`, bit 0 of which is hart `base`; a `base` of all ones means
+    /// every hart.
+    fn send(&mut self, hart: &Hart, bus: &mut Bus, mask: u64, base: u64, fence: bool) -> i64 {
+        let count = bus.clint.msip.len() as u64;
+        let targets: Vec<u64> = if base == hart.xlen_mask() {
+            (0..count).collect()
+        } else {
+            (0..hart.xlen() as u64).filter(|bit| mask >> bit & 1 != 0).map(|bit| base.wrapping_add(bit)).collect()
+        };
+        if targets.iter().any(|target| *target >= count) {
+            return SBI_ERR_INVALID_PARAM;
+        }
+        bus.clint.changed = true;
+        for target in targets {
+            match fence {
+                true => self.fences[target as usize] = true,
+                false => bus.clint.msip[target as usize] = true,
+            }
+        }
+        SBI_SUCCESS
+    }
+
+    /// The physical address `DBCN` hands over as two halves; the upper one
+    /// must be zero on RV64.
+    fn physical(hart: &Hart, low: u64, high: u64) -> Option<u64> {
+        match hart.xlen() {
+            32 => Some((high << 32) | low),
+            _ => (high == 0).then_some(low),
+        }
+    }
+
+    /// Reads the hart mask a legacy call passes the virtual address of.
+    fn load_mask(hart: &mut Hart, bus: &mut Bus, vaddr: u64) -> Option<u64> {
+        let paddr = hart.translate(bus, vaddr, Access::Load).ok()?;
+        bus.load(paddr, hart.xlen() as usize / 8)
+    }
+
+    /// Takes up to `len` bytes of console input that have arrived, without
+    /// waiting for more.
+    fn read_console(&mut self, len: usize) -> Vec<u8> {
+        if !self.console_reader_started {
+            self.console_reader_started = true;
+            spawn_stdin_reader(self.console_input.clone());
+        }
+        let mut input = self.console_input.lock().unwrap();
+        let len = len.min(input.len());
+        input.drain(..len).collect()
+    }
+}
@@ -0,0 +1,577 @@
+//! Whole-machine snapshots.
+//!
+//! A snapshot file is `MAGIC`, a little-endian `u32` format version, then
+//! a list of sections, each a 4-byte tag, a `u64` payload length and the
+//! payload:
+//!
+//! | tag    | payload                                                    |
+//! |--------|------------------------------------------------------------|
+//! | `MACH` | hart count, RAM base, RAM size, rounds run, `mtime`        |
+//! | `ISA ` | the harts' ISA string, e.g. `rv64imac_zicsr_zifencei`      |
+//! | `HART` | one per hart: id, `x0`-`x31`, pc, privilege, then the CSRs |
+//! |        | as a count followed by `(u16 addr, u64 value)` pairs       |
+//! | `FPRS` | hart id then `f0`-`f31`, for each hart                     |
+//! | `VREG` | hart id then `v0`-`v31` as bytes, for each hart with them  |
+//! | `VIRT` | the ids of the harts running a guest, in VS or VU-mode     |
+//! | `RAM ` | the pages that are not all zero, as `(u64 index, bytes)`   |
+//! | `HTIF` | `tohost`, `fromhost`, exit code, pending getchar, args     |
+//! | `RSRV` | reservation granule, then `(hart id, addr, hold)` for each |
+//! |        | hart holding an `LR` reservation                           |
+//! | `CLNT` | `(msip, mtimecmp)` for each hart                           |
+//! | `SBI ` | exit code, then the ids of the harts owed a remote fence   |
+//!
+//! Pending interrupts are the `mip` bits and travel with the CSRs.
+//! Readers skip sections they do not know, so new state goes in new
+//! sections; the version only changes when an existing one does.
+
+use crate::Clint::Clint;
+use crate::Cpu::{CsrFile, Hart, Privilege};
+use crate::Csr::*;
+use crate::Emulator::Machine;
+use crate::Htif::Htif;
+use crate::Isa::Isa;
+use crate::Memory::{Ram, PAGE_SIZE};
+use crate::Reservation::Reservation;
+use crate::Sbi::Sbi;
+
+/// The first bytes of every snapshot file.
+pub const MAGIC: &[u8; 8] = b"ZRV64SNP";
+pub const VERSION: u32 = 1;
+/// The most RAM a snapshot may ask for, so a damaged or hostile file
+/// cannot make loading it allocate without bound.
+pub const MAX_RAM_SIZE: u64 = 64 << 30;
+
+/// Every CSR a hart keeps state in, by address.
+fn csr_fields(csr: &mut CsrFile) -> Vec<(usize, &mut u64)> {
+    let mut fields: Vec<(usize, &mut u64)> = vec![
+        (MSTATUS, &mut csr.mstatus),
+        (MISA, &mut csr.misa),
+        (MEDELEG, &mut csr.medeleg),
+        (MIDELEG, &mut csr.mideleg),
+        (MIE, &mut csr.mie),
+        (MIP, &mut csr.mip),
+        (MTVEC, &mut csr.mtvec),
+        (MCOUNTEREN, &mut csr.mcounteren),
+        (MSCRATCH, &mut csr.mscratch),
+        (MEPC, &mut csr.mepc),
+        (MCAUSE, &mut csr.mcause),
+        (MTVAL, &mut csr.mtval),
+        (MCYCLE, &mut csr.mcycle),
+        (MINSTRET, &mut csr.minstret),
+        (MCOUNTINHIBIT, &mut csr.mcountinhibit),
+        (MTVAL2, &mut csr.mtval2),
+        (MTINST, &mut csr.mtinst),
+        (STVEC, &mut csr.stvec),
+        (SCOUNTEREN, &mut csr.scounteren),
+        (SSCRATCH, &mut csr.sscratch),
+        (SEPC, &mut csr.sepc),
+        (SCAUSE, &mut csr.scause),
+        (STVAL, &mut csr.stval),
+        (SATP, &mut csr.satp),
+        (FCSR, &mut csr.fcsr),
+        (VSTART, &mut csr.vstart),
+        (VCSR, &mut csr.vcsr),
+        (VL, &mut csr.vl),
+        (VTYPE, &mut csr.vtype),
+        (HSTATUS, &mut csr.hstatus),
+        (HEDELEG, &mut csr.hedeleg),
+        (HIDELEG, &mut csr.hideleg),
+        (HCOUNTEREN, &mut csr.hcounteren),
+        (HTIMEDELTA, &mut csr.htimedelta),
+        (HTVAL, &mut csr.htval),
+        (HTINST, &mut csr.htinst),
+        (HGATP, &mut csr.hgatp),
+        (VSSTATUS, &mut csr.vsstatus),
+        (VSTVEC, &mut csr.vstvec),
+        (VSSCRATCH, &mut csr.vsscratch),
+        (VSEPC, &mut csr.vsepc),
+        (VSCAUSE, &mut csr.vscause),
+        (VSTVAL, &mut csr.vstval),
+        (VSATP, &mut csr.vsatp),
+    ];
+    fields.extend(csr.pmpcfg.iter_mut().enumerate().map(|(i, value)| (PMPCFG0 + i, value)));
+    fields.extend(csr.pmpaddr.iter_mut().enumerate().map(|(i, value)| (PMPADDR0 + i, value)));
+    fields
+}
+
+#[derive(Default)]
+struct Writer {
+    out: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.out.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.out.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.out.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn option(&mut self, value: Option<u64>) {
+        self.u8(value.is_some() as u8);
+        self.u64(value.unwrap_or(0));
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.u64(bytes.len() as u64);
+        self.out.extend_from_slice(bytes);
+    }
+
+    fn section(&mut self, tag: &[u8; 4], payload: Writer) {
+        self.out.extend_from_slice(tag);
+        self.bytes(&payload.out);
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < len {
+            return Err("snapshot is truncated".to_string());
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn option(&mut self) -> Result<Option<u64>, String> {
+        let present = self.u8()? != 0;
+        let value = self.u64()?;
+        Ok(present.then_some(value))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = self.u64()?;
+        self.take(usize::try_from(len).map_err(|_| "snapshot is truncated")?)
+    }
+}
+
+fn write_hart(hart: &Hart) -> Writer {
+    let mut w = Writer::default();
+    w.u64(hart.id);
+    for value in hart.x.iter() {
+        w.u64(*value);
+    }
+    w.u64(hart.pc);
+    w.u8(hart.privilege as u8);
+    let mut csr = hart.csr.clone();
+    let fields = csr_fields(&mut csr);
+    w.u64(fields.len() as u64);
+    for (addr, value) in fields {
+        w.u16(addr as u16);
+        w.u64(*value);
+    }
+    w
+}
+
+fn read_hart(r: &mut Reader) -> Result<Hart, String> {
+    let mut hart = Hart::new(r.u64()?);
+    for value in hart.x.iter_mut() {
+        *value = r.u64()?;
+    }
+    hart.x[0] = 0;
+    hart.pc = r.u64()?;
+    hart.privilege = match r.u8()? {
+        0 => Privilege::User,
+        1 => Privilege::Supervisor,
+        3 => Privilege::Machine,
+        other => return Err(format!("hart {} has bad privilege level {}", hart.id, other)),
+    };
+    let count = r.u64()?;
+    for _ in 0..count {
+        let (addr, value) = (r.u16()? as usize, r.u64()?);
+        let mut fields = csr_fields(&mut hart.csr);
+        match fields.iter_mut().find(|(a, _)| *a == addr) {
+            Some((_, field)) => **field = value,
+            None => return Err(format!("hart {} has unknown CSR {:#x}", hart.id, addr)),
+        }
+    }
+    Ok(hart)
+}
+
+/// Serializes everything needed to resume `machine` later. Breakpoints,
+/// logs and files the target has open on the host are left out.
+pub fn to_bytes(machine: &Machine) -> Vec<u8> {
+    let mut out = Writer::default();
+    out.out.extend_from_slice(MAGIC);
+    out.out.extend_from_slice(&VERSION.to_le_bytes());
+
+    let mut w = Writer::default();
+    w.u64(machine.harts.len() as u64);
+    w.u64(machine.bus.ram.base);
+    w.u64(machine.bus.ram.size());
+    w.u64(machine.steps);
+    w.u64(machine.bus.mtime);
+    out.section(b"MACH", w);
+
+    let mut w = Writer::default();
+    w.bytes(machine.isa().to_string().as_bytes());
+    out.section(b"ISA ", w);
+
+    for hart in machine.harts.iter() {
+        out.section(b"HART", write_hart(hart));
+    }
+
+    let mut w = Writer::default();
+    for hart in machine.harts.iter() {
+        w.u64(hart.id);
+        for value in hart.f.iter() {
+            w.u64(*value);
+        }
+    }
+    out.section(b"FPRS", w);
+
+    let mut w = Writer::default();
+    for hart in machine.harts.iter().filter(|hart| !hart.v.is_empty()) {
+        w.u64(hart.id);
+        w.bytes(&hart.v);
+    }
+    out.section(b"VREG", w);
+
+    let mut w = Writer::default();
+    for hart in machine.harts.iter().filter(|hart| hart.virt) {
+        w.u64(hart.id);
+    }
+    out.section(b"VIRT", w);
+
+    let mut w = Writer::default();
+    for (index, page) in machine.bus.ram.data.chunks(PAGE_SIZE).enumerate() {
+        if page.iter().any(|byte| *byte != 0) {
+            w.u64(index as u64);
+            w.bytes(page);
+        }
+    }
+    out.section(b"RAM ", w);
+
+    if let Some(htif) = &machine.bus.htif {
+        let mut w = Writer::default();
+        w.u64(htif.tohost);
+        w.option(htif.fromhost);
+        w.option(htif.exit_code);
+        w.u8(htif.getchar_pending() as u8);
+        w.u64(htif.args.len() as u64);
+        for arg in htif.args.iter() {
+            w.bytes(arg.as_bytes());
+        }
+        out.section(b"HTIF", w);
+    }
+
+    let reservations = &machine.bus.reservations;
+    let mut w = Writer::default();
+    w.u64(reservations.granule());
+    for hart in machine.harts.iter() {
+        if let Some(reservation) = reservations.get(hart.id) {
+            w.u64(hart.id);
+            w.u64(reservation.addr);
+            w.u64(reservation.hold);
+        }
+    }
+    out.section(b"RSRV", w);
+
+    let clint = &machine.bus.clint;
+    let mut w = Writer::default();
+    for (msip, mtimecmp) in clint.msip.iter().zip(clint.mtimecmp.iter()) {
+        w.u8(*msip as u8);
+        w.u64(*mtimecmp);
+    }
+    out.section(b"CLNT", w);
+
+    if let Some(sbi) = &machine.bus.sbi {
+        let mut w = Writer::default();
+        w.option(sbi.exit_code);
+        for (id, fence) in sbi.fences.iter().enumerate() {
+            if *fence {
+                w.u64(id as u64);
+            }
+        }
+        out.section(b"SBI ", w);
+    }
+    out.out
+}
+
+/// Rebuilds a machine from `to_bytes` output.
+pub fn from_bytes(data: &[u8]) -> Result<Machine, String> {
+    let mut r = Reader { data };
+    if r.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+        return Err("not a machine snapshot".to_string());
+    }
+    let version = r.u32()?;
+    if version != VERSION {
+        return Err(format!("snapshot format version {} is not supported (expected {})", version, VERSION));
+    }
+
+    let mut machine: Option<Machine> = None;
+    let mut hart_count = 0;
+    let mut harts = Vec::new();
+    let mut fprs = Vec::new();
+    let mut vregs = Vec::new();
+    let mut guests = Vec::new();
+    let mut isa = Isa::default();
+    while !r.data.is_empty() {
+        let tag = r.take(4)?;
+        let mut s = Reader { data: r.bytes()? };
+        match tag {
+            b"MACH" => {
+                hart_count = s.u64()? as usize;
+                let (base, size) = (s.u64()?, s.u64()?);
+                if size > MAX_RAM_SIZE || base.checked_add(size).is_none() {
+                    return Err(format!("snapshot asks for {:#x} bytes of RAM at {:#x}", size, base));
+                }
+                let mut m = Machine::new(0, 0);
+                m.bus.ram = Ram::new(base, size);
+                m.steps = s.u64()?;
+                m.bus.mtime = s.u64()?;
+                machine = Some(m);
+            }
+            b"ISA " => isa = Isa::parse(&String::from_utf8_lossy(s.bytes()?))?,
+            b"HART" => harts.push(read_hart(&mut s)?),
+            b"FPRS" => {
+                while !s.data.is_empty() {
+                    let id = s.u64()?;
+                    let mut f = [0; 32];
+                    for value in f.iter_mut() {
+                        *value = s.u64()?;
+                    }
+                    fprs.push((id, f));
+                }
+            }
+            b"VREG" => {
+                while !s.data.is_empty() {
+                    let id = s.u64()?;
+                    vregs.push((id, s.bytes()?.to_vec()));
+                }
+            }
+            b"VIRT" => {
+                while !s.data.is_empty() {
+                    guests.push(s.u64()?);
+                }
+            }
+            b"RAM " => {
+                let ram = &mut machine.as_mut().ok_or("RAM before the machine section")?.bus.ram;
+                while !s.data.is_empty() {
+                    let index = s.u64()? as usize;
+                    let page = s.bytes()?;
+                    let start = match index.checked_mul(PAGE_SIZE) {
+                        Some(start) if start.checked_add(page.len()).is_some_and(|end| end <= ram.data.len()) => start,
+                        _ => return Err(format!("RAM page {} is out of range", index)),
+                    };
+                    ram.data[start..start + page.len()].copy_from_slice(page);
+                }
+            }
+            b"HTIF" => {
+                let bus = &mut machine.as_mut().ok_or("HTIF before the machine section")?.bus;
+                let tohost = s.u64()?;
+                let fromhost = s.option()?;
+                let exit_code = s.option()?;
+                let getchar_pending = s.u8()? != 0;
+                let mut args = Vec::new();
+                for _ in 0..s.u64()? {
+                    args.push(String::from_utf8_lossy(s.bytes()?).into_owned());
+                }
+                bus.htif = Some(Htif::restore(tohost, fromhost, args, exit_code, getchar_pending));
+            }
+            b"RSRV" => {
+                let bus = &mut machine.as_mut().ok_or("RSRV before the machine section")?.bus;
+                bus.reservations.set_granule(s.u64()?)?;
+                while !s.data.is_empty() {
+                    let hart = s.u64()?;
+                    let reservation = Reservation {
+                        addr: s.u64()?,
+                        hold: s.u64()?,
+                    };
+                    bus.reservations.set(hart, Some(reservation));
+                }
+            }
+            b"CLNT" => {
+                let clint = &mut machine.as_mut().ok_or("CLNT before the machine section")?.bus.clint;
+                *clint = Clint::new(0);
+                while !s.data.is_empty() {
+                    clint.msip.push(s.u8()? != 0);
+                    clint.mtimecmp.push(s.u64()?);
+                }
+            }
+            b"SBI " => {
+                let bus = &mut machine.as_mut().ok_or("SBI before the machine section")?.bus;
+                let mut sbi = Sbi::new(hart_count);
+                sbi.exit_code = s.option()?;
+                while !s.data.is_empty() {
+                    let id = s.u64()? as usize;
+                    *sbi.fences.get_mut(id).ok_or(format!("remote fence for missing hart {}", id))? = true;
+                }
+                bus.sbi = Some(sbi);
+            }
+            _ => {}
+        }
+    }
+
+    let mut machine = machine.ok_or("snapshot has no machine section")?;
+    // `set_isa` resets UXL and SXL, which the snapshot has as they were.
+    let statuses: Vec<u64> = harts.iter().map(|hart| hart.csr.mstatus).collect();
+    machine.harts = harts;
+    machine.set_isa(isa);
+    for (hart, status) in machine.harts.iter_mut().zip(statuses) {
+        hart.csr.mstatus = status;
+    }
+    for (id, f) in fprs {
+        let hart = machine.harts.iter_mut().find(|hart| hart.id == id);
+        hart.ok_or(format!("floating point registers for missing hart {}", id))?.f = f;
+    }
+    for (id, v) in vregs {
+        let hart = machine.harts.iter_mut().find(|hart| hart.id == id);
+        let hart = hart.ok_or(format!("vector registers for missing hart {}", id))?;
+        if v.len() != hart.v.len() {
+            return Err(format!("hart {} has {} bytes of vector registers, not {}", id, v.len(), hart.v.len()));
+        }
+        hart.v = v;
+    }
+    for id in guests {
+        let hart = machine.harts.iter_mut().find(|hart| hart.id == id);
+        hart.ok_or(format!("virtualization mode for missing hart {}", id))?.virt = true;
+    }
+    if machine.harts.is_empty() || machine.harts.len() != hart_count {
+        return Err(format!("snapshot has {} of {} harts", machine.harts.len(), hart_count));
+    }
+    // snapshots from before the CLINT have no `CLNT` section.
+    match machine.bus.clint.msip.len() {
+        0 => machine.bus.clint = Clint::new(hart_count),
+        count if count != hart_count => return Err(format!("snapshot has a CLINT for {} of {} harts", count, hart_count)),
+        _ => {}
+    }
+    Ok(machine)
+}
+
+pub fn save(machine: &Machine, path: &str) -> Result<(), String> {
+    std::fs::write(path, to_bytes(machine)).map_err(|e| format!("could not write `{}`: {}", path, e))
+}
+
+pub fn load(path: &str) -> Result<Machine, String> {
+    let data = std::fs::read(path).map_err(|e| format!("could not read `{}`: {}", path, e))?;
+    from_bytes(&data).map_err(|e| format!("`{}`: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Memory::RAM_BASE;
+
+    /// A two-hart machine with something other than its reset value in
+    /// every part a snapshot carries.
+    fn machine() -> Machine {
+        let mut machine = Machine::new(2, 0x10000);
+        machine.steps = 12345;
+        machine.bus.mtime = 77;
+        for (i, hart) in machine.harts.iter_mut().enumerate() {
+            for (r, x) in hart.x.iter_mut().enumerate().skip(1) {
+                *x = (i as u64) << 32 | r as u64;
+            }
+            for (r, f) in hart.f.iter_mut().enumerate() {
+                *f = 0xFFFF_FFFF_0000_0000 | (i as u64) << 8 | r as u64;
+            }
+            for (b, v) in hart.v.iter_mut().enumerate() {
+                *v = (b + i) as u8;
+            }
+            hart.pc = RAM_BASE + 0x100 * i as u64;
+            hart.privilege = Privilege::Supervisor;
+            hart.csr.mepc = 0x8000_1000 + i as u64;
+            hart.csr.satp = 8 << 60 | 0x80010;
+            hart.csr.mip = MIP_SSIP;
+            hart.csr.vl = 3;
+        }
+        machine.harts[1].virt = true;
+        machine.bus.write_bytes(RAM_BASE + 0x20, b"snapshot");
+        machine.bus.write_bytes(RAM_BASE + 0xFFF8, &[0xAA; 8]);
+        let mut htif = Htif::new(RAM_BASE + 0x1000, Some(RAM_BASE + 0x1008), vec!["prog".into(), "arg".into()]);
+        htif.exit_code = Some(3);
+        machine.bus.htif = Some(htif);
+        machine.bus.reservations.set_granule(16).unwrap();
+        machine.bus.reservations.set(1, Some(Reservation { addr: RAM_BASE + 0x40, hold: 5 }));
+        machine.bus.clint.msip[1] = true;
+        machine.bus.clint.mtimecmp[0] = 1234;
+        let mut sbi = Sbi::new(2);
+        sbi.fences[1] = true;
+        machine.bus.sbi = Some(sbi);
+        machine
+    }
+
+    #[test]
+    fn round_trip() {
+        let original = machine();
+        let bytes = to_bytes(&original);
+        let restored = from_bytes(&bytes).unwrap();
+
+        assert_eq!(restored.steps, original.steps);
+        assert_eq!(restored.bus.mtime, original.bus.mtime);
+        assert_eq!(restored.isa(), original.isa());
+        for (a, b) in original.harts.iter().zip(restored.harts.iter()) {
+            assert_eq!((a.id, a.x, a.f, a.pc, a.privilege, a.virt), (b.id, b.x, b.f, b.pc, b.privilege, b.virt));
+            assert_eq!(a.v, b.v);
+            assert_eq!(a.csr, b.csr);
+        }
+        assert_eq!(restored.bus.ram.base, original.bus.ram.base);
+        assert!(restored.bus.ram.data == original.bus.ram.data);
+        let (a, b) = (original.bus.htif.as_ref().unwrap(), restored.bus.htif.as_ref().unwrap());
+        assert_eq!((a.tohost, a.fromhost, a.exit_code, &a.args), (b.tohost, b.fromhost, b.exit_code, &b.args));
+        assert_eq!(restored.bus.reservations.granule(), 16);
+        assert_eq!(restored.bus.reservations.get(0), None);
+        assert_eq!(restored.bus.reservations.get(1), Some(Reservation { addr: RAM_BASE + 0x40, hold: 5 }));
+        assert_eq!(restored.bus.clint, original.bus.clint);
+        assert_eq!(restored.bus.sbi.as_ref().unwrap().fences, [false, true]);
+
+        // everything the format holds came back.
+        assert!(to_bytes(&restored) == bytes);
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let bytes = to_bytes(&machine());
+
+        assert_eq!(from_bytes(b"ZRV64SNQ\x01\0\0\0").unwrap_err(), "not a machine snapshot");
+        assert_eq!(from_bytes(&bytes[..4]).unwrap_err(), "not a machine snapshot");
+        let mut newer = bytes.clone();
+        newer[8] = 2;
+        assert_eq!(
+            from_bytes(&newer).unwrap_err(),
+            "snapshot format version 2 is not supported (expected 1)"
+        );
+
+        // a cut inside a section always shows; one between sections may
+        // leave a machine that is merely missing state, but never panics.
+        assert_eq!(from_bytes(&bytes[..10]).unwrap_err(), "snapshot is truncated");
+        assert_eq!(from_bytes(&bytes[..bytes.len() - 1]).unwrap_err(), "snapshot is truncated");
+        for len in (0..bytes.len()).step_by(61) {
+            let _ = from_bytes(&bytes[..len]);
+        }
+
+        // the RAM size is checked before anything is allocated for it.
+        let mut huge = bytes.clone();
+        let size = 12 + 4 + 8 + 16;
+        huge[size..size + 8].copy_from_slice(&(MAX_RAM_SIZE + 1).to_le_bytes());
+        assert!(from_bytes(&huge).unwrap_err().starts_with("snapshot asks for"));
+        huge[size..size + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(from_bytes(&huge).unwrap_err().starts_with("snapshot asks for"));
+    }
+}
@@ -0,0 +1,726 @@
+//! IEEE 754 binary floating point in software, bit for bit what the F, D
+//! and Zfh extensions require: every operation is done on integer
+//! significands wide enough to be exact, then rounded once.
+//!
+//! Values are passed around as their encodings in a `u64`, single and
+//! half precision in the low 32 and 16 bits. NaN results are always the
+//! canonical NaN of their format, as RISC-V does not propagate payloads.
+
+// `fflags` bits
+pub const NX: u64 = 1 << 0;
+pub const UF: u64 = 1 << 1;
+pub const OF: u64 = 1 << 2;
+pub const DZ: u64 = 1 << 3;
+pub const NV: u64 = 1 << 4;
+
+/// The rounding modes, numbered as in the `rm` field and `frm`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    NearestEven = 0,
+    TowardZero = 1,
+    Down = 2,
+    Up = 3,
+    NearestMaxMagnitude = 4,
+}
+
+impl Rounding {
+    /// The mode `rm` names, `None` for the reserved values and for `7`
+    /// (dynamic), which has to be resolved through `frm` first.
+    pub fn from_bits(rm: u64) -> Option<Self> {
+        match rm {
+            0 => Some(Rounding::NearestEven),
+            1 => Some(Rounding::TowardZero),
+            2 => Some(Rounding::Down),
+            3 => Some(Rounding::Up),
+            4 => Some(Rounding::NearestMaxMagnitude),
+            _ => None,
+        }
+    }
+}
+
+/// A binary interchange format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    exp_bits: u32,
+    frac_bits: u32,
+}
+
+pub const F16: Format = Format { exp_bits: 5, frac_bits: 10 };
+pub const F32: Format = Format { exp_bits: 8, frac_bits: 23 };
+pub const F64: Format = Format { exp_bits: 11, frac_bits: 52 };
+
+impl Format {
+    fn bias(self) -> i32 {
+        (1 << (self.exp_bits - 1)) - 1
+    }
+
+    /// Exponent of the smallest normal number.
+    fn emin(self) -> i32 {
+        1 - self.bias()
+    }
+
+    fn max_exp(self) -> u64 {
+        (1 << self.exp_bits) - 1
+    }
+
+    fn frac_mask(self) -> u64 {
+        (1 << self.frac_bits) - 1
+    }
+
+    fn sign_bit(self) -> u64 {
+        1 << (self.exp_bits + self.frac_bits)
+    }
+
+    fn sign(self, a: u64) -> bool {
+        a & self.sign_bit() != 0
+    }
+
+    fn exp(self, a: u64) -> u64 {
+        (a >> self.frac_bits) & self.max_exp()
+    }
+
+    fn frac(self, a: u64) -> u64 {
+        a & self.frac_mask()
+    }
+
+    pub fn is_nan(self, a: u64) -> bool {
+        self.exp(a) == self.max_exp() && self.frac(a) != 0
+    }
+
+    pub fn is_signaling_nan(self, a: u64) -> bool {
+        self.is_nan(a) && a & (1 << (self.frac_bits - 1)) == 0
+    }
+
+    fn is_inf(self, a: u64) -> bool {
+        self.exp(a) == self.max_exp() && self.frac(a) == 0
+    }
+
+    fn is_zero(self, a: u64) -> bool {
+        a & !self.sign_bit() == 0
+    }
+
+    pub fn canonical_nan(self) -> u64 {
+        (self.max_exp() << self.frac_bits) | (1 << (self.frac_bits - 1))
+    }
+
+    fn inf(self, sign: bool) -> u64 {
+        self.zero(sign) | (self.max_exp() << self.frac_bits)
+    }
+
+    fn zero(self, sign: bool) -> u64 {
+        if sign { self.sign_bit() } else { 0 }
+    }
+
+    /// The largest finite number.
+    fn max_finite(self, sign: bool) -> u64 {
+        self.zero(sign) | ((self.max_exp() - 1) << self.frac_bits) | self.frac_mask()
+    }
+
+    /// A finite, nonzero `a` as `(sign, sig, e)` with a value of
+    /// `sig * 2^e`.
+    fn unpack(self, a: u64) -> (bool, u128, i32) {
+        let (exp, frac) = (self.exp(a), self.frac(a));
+        let e = self.emin() - self.frac_bits as i32;
+        match exp {
+            0 => (self.sign(a), frac as u128, e),
+            _ => (self.sign(a), (frac | (1 << self.frac_bits)) as u128, e + exp as i32 - 1),
+        }
+    }
+}
+
+/// Boxes a single-precision value for a 64-bit register.
+pub fn box_f32(a: u64) -> u64 {
+    a | 0xFFFF_FFFF_0000_0000
+}
+
+/// The single-precision value in a 64-bit register: the canonical NaN
+/// unless it is properly boxed.
+pub fn unbox_f32(a: u64) -> u64 {
+    if a >> 32 == 0xFFFF_FFFF { a & 0xFFFF_FFFF } else { F32.canonical_nan() }
+}
+
+/// Boxes a half-precision value for a 64-bit register.
+pub fn box_f16(a: u64) -> u64 {
+    a | 0xFFFF_FFFF_FFFF_0000
+}
+
+/// The half-precision value in a 64-bit register, unboxed like
+/// `unbox_f32`.
+pub fn unbox_f16(a: u64) -> u64 {
+    if a >> 16 == 0xFFFF_FFFF_FFFF { a & 0xFFFF } else { F16.canonical_nan() }
+}
+
+/// `FCLASS`: one bit set for the class of `a`, from negative infinity in
+/// bit 0 to quiet NaN in bit 9.
+pub fn classify(fmt: Format, a: u64) -> u64 {
+    let sign = fmt.sign(a);
+    let bit = match fmt.exp(a) {
+        _ if fmt.is_signaling_nan(a) => 8,
+        _ if fmt.is_nan(a) => 9,
+        _ if fmt.is_inf(a) => if sign { 0 } else { 7 },
+        _ if fmt.is_zero(a) => if sign { 3 } else { 4 },
+        0 => if sign { 2 } else { 5 },
+        _ => if sign { 1 } else { 6 },
+    };
+    1 << bit
+}
+
+fn bit_length(sig: u128) -> i32 {
+    128 - sig.leading_zeros() as i32
+}
+
+/// Shifts `sig` right by `shift`, returning what is kept, the highest bit
+/// shifted out, and whether anything below it (or `sticky`) was set.
+fn shift_right(sig: u128, shift: i32, sticky: bool) -> (u128, bool, bool) {
+    if shift <= 0 {
+        return (sig << -shift, false, sticky);
+    }
+    // significands stay below 2^127, so this shifts everything out below
+    // the guard bit.
+    if shift >= 128 {
+        return (0, false, sig != 0 || sticky);
+    }
+    let guard = (sig >> (shift - 1)) & 1 != 0;
+    let rest = sig & ((1 << (shift - 1)) - 1) != 0 || sticky;
+    (sig >> shift, guard, rest)
+}
+
+/// The integer square root of `n`, rounded down.
+fn isqrt(n: u128) -> u128 {
+    let (mut rest, mut root) = (n, 0u128);
+    let mut bit = 1u128 << 126;
+    while bit > n {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if rest >= root + bit {
+            rest -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    root
+}
+
+/// A rounding mode and the exception flags raised under it, like the
+/// globals of Berkeley SoftFloat.
+#[derive(Debug, Clone, Copy)]
+pub struct Env {
+    pub rounding: Rounding,
+    /// `fflags` bits raised so far.
+    pub flags: u64,
+}
+
+impl Env {
+    pub fn new(rounding: Rounding) -> Self {
+        Self {
+            rounding,
+            flags: 0,
+        }
+    }
+
+    /// Whether a magnitude `kept` followed by `guard` and `rest` rounds
+    /// up.
+    fn rounds_up(&self, sign: bool, kept: u128, guard: bool, rest: bool) -> bool {
+        match self.rounding {
+            Rounding::NearestEven => guard && (rest || kept & 1 != 0),
+            Rounding::NearestMaxMagnitude => guard,
+            Rounding::TowardZero => false,
+            Rounding::Down => sign && (guard || rest),
+            Rounding::Up => !sign && (guard || rest),
+        }
+    }
+
+    /// Rounds `sig * 2^e`, plus a little more if `sticky` is set, to
+    /// `fmt`. `sig` is nonzero and below 2^127, and `sticky` only ever
+    /// stands for bits below the rounding position.
+    fn round_pack(&mut self, fmt: Format, sign: bool, sig: u128, e: i32, sticky: bool) -> u64 {
+        let precision = fmt.frac_bits as i32 + 1;
+        let top = e + bit_length(sig) - 1;
+        let mut q = top.max(fmt.emin()) - fmt.frac_bits as i32;
+        let (mut kept, guard, rest) = shift_right(sig, q - e, sticky);
+        let inexact = guard || rest;
+        if self.rounds_up(sign, kept, guard, rest) {
+            kept += 1;
+            if kept == 1 << precision {
+                kept >>= 1;
+                q += 1;
+            }
+        }
+
+        // tininess is detected after rounding: a result just below the
+        // smallest normal that rounds up to it with an unbounded exponent
+        // is not tiny.
+        let tiny = top < fmt.emin() - 1
+            || (top == fmt.emin() - 1 && {
+                let (kept, guard, rest) = shift_right(sig, top - fmt.frac_bits as i32 - e, sticky);
+                !(self.rounds_up(sign, kept, guard, rest) && kept + 1 == 1 << precision)
+            });
+        if tiny && inexact {
+            self.flags |= UF;
+        }
+        if inexact {
+            self.flags |= NX;
+        }
+
+        if kept >> fmt.frac_bits == 0 {
+            return fmt.zero(sign) | kept as u64;
+        }
+        let exp = (q + fmt.frac_bits as i32 + fmt.bias()) as u64;
+        if exp >= fmt.max_exp() {
+            self.flags |= OF | NX;
+            let to_inf = match self.rounding {
+                Rounding::NearestEven | Rounding::NearestMaxMagnitude => true,
+                Rounding::TowardZero => false,
+                Rounding::Down => sign,
+                Rounding::Up => !sign,
+            };
+            return if to_inf { fmt.inf(sign) } else { fmt.max_finite(sign) };
+        }
+        fmt.zero(sign) | (exp << fmt.frac_bits) | (kept as u64 & fmt.frac_mask())
+    }
+
+    /// The sign of an exact zero sum of operands with opposite signs.
+    fn zero_sum_sign(&self) -> bool {
+        self.rounding == Rounding::Down
+    }
+
+    /// Raises `NV` for signaling NaNs among `operands` and returns the
+    /// canonical NaN if any of them is a NaN.
+    fn nan_operands(&mut self, fmt: Format, operands: &[u64]) -> Option<u64> {
+        if operands.iter().any(|a| fmt.is_signaling_nan(*a)) {
+            self.flags |= NV;
+        }
+        operands.iter().any(|a| fmt.is_nan(*a)).then(|| fmt.canonical_nan())
+    }
+
+    fn invalid(&mut self, fmt: Format) -> u64 {
+        self.flags |= NV;
+        fmt.canonical_nan()
+    }
+
+    /// Rounds the exact sum of two finite, nonzero values given as
+    /// `(sign, sig, e)`.
+    fn add_exact(&mut self, fmt: Format, a: (bool, u128, i32), b: (bool, u128, i32)) -> u64 {
+        // line the operand with the higher leading bit up at bit 124, so
+        // the other one either fits or only its bits far below the result
+        // are lost.
+        let (a, b) = if b.2 + bit_length(b.1) > a.2 + bit_length(a.1) { (b, a) } else { (a, b) };
+        let shift = 125 - bit_length(a.1);
+        let (sig_a, e) = (a.1 << shift, a.2 - shift);
+        let (sig_b, sticky) = match b.2 - e {
+            d if d >= 0 => (b.1 << d, false),
+            d if d <= -128 => (0, true),
+            d => (b.1 >> -d, b.1 & ((1 << -d) - 1) != 0),
+        };
+        let (sign, sum) = if a.0 == b.0 {
+            (a.0, sig_a + sig_b)
+        } else if sig_a >= sig_b {
+            // the lost bits of `b` borrow one from the sum and leave the
+            // rest sticky.
+            (a.0, sig_a - sig_b - sticky as u128)
+        } else {
+            (b.0, sig_b - sig_a)
+        };
+        if sum == 0 && !sticky {
+            return fmt.zero(self.zero_sum_sign());
+        }
+        self.round_pack(fmt, sign, sum, e, sticky)
+    }
+
+    pub fn add(&mut self, fmt: Format, a: u64, b: u64) -> u64 {
+        if let Some(nan) = self.nan_operands(fmt, &[a, b]) {
+            return nan;
+        }
+        match (fmt.is_inf(a), fmt.is_inf(b)) {
+            (true, true) if fmt.sign(a) != fmt.sign(b) => return self.invalid(fmt),
+            (true, _) => return a,
+            (_, true) => return b,
+            _ => {}
+        }
+        match (fmt.is_zero(a), fmt.is_zero(b)) {
+            (true, true) if fmt.sign(a) == fmt.sign(b) => a,
+            (true, true) => fmt.zero(self.zero_sum_sign()),
+            (true, false) => b,
+            (false, true) => a,
+            (false, false) => self.add_exact(fmt, fmt.unpack(a), fmt.unpack(b)),
+        }
+    }
+
+    pub fn sub(&mut self, fmt: Format, a: u64, b: u64) -> u64 {
+        // flipping the sign of a NaN does not matter, it is never returned.
+        self.add(fmt, a, b ^ fmt.sign_bit())
+    }
+
+    pub fn mul(&mut self, fmt: Format, a: u64, b: u64) -> u64 {
+        if let Some(nan) = self.nan_operands(fmt, &[a, b]) {
+            return nan;
+        }
+        let sign = fmt.sign(a) != fmt.sign(b);
+        if fmt.is_inf(a) || fmt.is_inf(b) {
+            if fmt.is_zero(a) || fmt.is_zero(b) {
+                return self.invalid(fmt);
+            }
+            return fmt.inf(sign);
+        }
+        if fmt.is_zero(a) || fmt.is_zero(b) {
+            return fmt.zero(sign);
+        }
+        let ((_, sig_a, e_a), (_, sig_b, e_b)) = (fmt.unpack(a), fmt.unpack(b));
+        self.round_pack(fmt, sign, sig_a * sig_b, e_a + e_b, false)
+    }
+
+    /// `a * b + c` with a single rounding, the product negated if
+    /// `negate_product` is set and `c` if `negate_addend` is.
+    pub fn fma(&mut self, fmt: Format, a: u64, b: u64, c: u64, negate_product: bool, negate_addend: bool) -> u64 {
+        // infinity times zero is invalid even with a quiet NaN to add.
+        let inf_times_zero = (fmt.is_inf(a) && fmt.is_zero(b)) || (fmt.is_zero(a) && fmt.is_inf(b));
+        if let Some(nan) = self.nan_operands(fmt, &[a, b, c]) {
+            if inf_times_zero {
+                self.flags |= NV;
+            }
+            return nan;
+        }
+        if inf_times_zero {
+            return self.invalid(fmt);
+        }
+        let sign = (fmt.sign(a) != fmt.sign(b)) != negate_product;
+        let c = if negate_addend { c ^ fmt.sign_bit() } else { c };
+        if fmt.is_inf(a) || fmt.is_inf(b) {
+            if fmt.is_inf(c) && fmt.sign(c) != sign {
+                return self.invalid(fmt);
+            }
+            return fmt.inf(sign);
+        }
+        if fmt.is_inf(c) {
+            return c;
+        }
+        if fmt.is_zero(a) || fmt.is_zero(b) {
+            return match fmt.is_zero(c) {
+                true if fmt.sign(c) == sign => c,
+                true => fmt.zero(self.zero_sum_sign()),
+                false => c,
+            };
+        }
+        let ((_, sig_a, e_a), (_, sig_b, e_b)) = (fmt.unpack(a), fmt.unpack(b));
+        let product = (sign, sig_a * sig_b, e_a + e_b);
+        if fmt.is_zero(c) {
+            return self.round_pack(fmt, sign, product.1, product.2, false);
+        }
+        self.add_exact(fmt, product, fmt.unpack(c))
+    }
+
+    pub fn div(&mut self, fmt: Format, a: u64, b: u64) -> u64 {
+        if let Some(nan) = self.nan_operands(fmt, &[a, b]) {
+            return nan;
+        }
+        let sign = fmt.sign(a) != fmt.sign(b);
+        match (fmt.is_inf(a), fmt.is_inf(b)) {
+            (true, true) => return self.invalid(fmt),
+            (true, false) => return fmt.inf(sign),
+            (false, true) => return fmt.zero(sign),
+            _ => {}
+        }
+        match (fmt.is_zero(a), fmt.is_zero(b)) {
+            (true, true) => return self.invalid(fmt),
+            (true, false) => return fmt.zero(sign),
+            (false, true) => {
+                self.flags |= DZ;
+                return fmt.inf(sign);
+            }
+            _ => {}
+        }
+        let ((_, sig_a, e_a), (_, sig_b, e_b)) = (fmt.unpack(a), fmt.unpack(b));
+        // at least 62 quotient bits: plenty to round from, with the
+        // remainder as the sticky bit.
+        let shift = 62 + bit_length(sig_b) - bit_length(sig_a);
+        let dividend = sig_a << shift;
+        let (quotient, remainder) = (dividend / sig_b, dividend % sig_b);
+        self.round_pack(fmt, sign, quotient, e_a - e_b - shift, remainder != 0)
+    }
+
+    pub fn sqrt(&mut self, fmt: Format, a: u64) -> u64 {
+        if let Some(nan) = self.nan_operands(fmt, &[a]) {
+            return nan;
+        }
+        if fmt.is_zero(a) {
+            return a;
+        }
+        if fmt.sign(a) {
+            return self.invalid(fmt);
+        }
+        if fmt.is_inf(a) {
+            return a;
+        }
+        let (_, mut sig, mut e) = fmt.unpack(a);
+        if e % 2 != 0 {
+            sig <<= 1;
+            e -= 1;
+        }
+        // about 60 bits of root, the rest sticky.
+        let shift = (120 - bit_length(sig)) / 2;
+        let radicand = sig << (2 * shift);
+        let root = isqrt(radicand);
+        self.round_pack(fmt, false, root, e / 2 - shift, root * root != radicand)
+    }
+
+    /// `a < b`, for values that are not NaNs.
+    fn less(fmt: Format, a: u64, b: u64) -> bool {
+        if fmt.is_zero(a) && fmt.is_zero(b) {
+            return false;
+        }
+        let (magnitude_a, magnitude_b) = (a & !fmt.sign_bit(), b & !fmt.sign_bit());
+        match (fmt.sign(a), fmt.sign(b)) {
+            (false, false) => magnitude_a < magnitude_b,
+            (true, true) => magnitude_a > magnitude_b,
+            (sign_a, _) => sign_a,
+        }
+    }
+
+    /// `FEQ`: quiet, raising `NV` only for signaling NaNs.
+    pub fn eq(&mut self, fmt: Format, a: u64, b: u64) -> bool {
+        if self.nan_operands(fmt, &[a, b]).is_some() {
+            return false;
+        }
+        a == b || (fmt.is_zero(a) && fmt.is_zero(b))
+    }
+
+    /// `FLT`: signaling, raising `NV` for any NaN.
+    pub fn lt(&mut self, fmt: Format, a: u64, b: u64) -> bool {
+        if fmt.is_nan(a) || fmt.is_nan(b) {
+            self.flags |= NV;
+            return false;
+        }
+        Self::less(fmt, a, b)
+    }
+
+    /// `FLE`: signaling, raising `NV` for any NaN.
+    pub fn le(&mut self, fmt: Format, a: u64, b: u64) -> bool {
+        if fmt.is_nan(a) || fmt.is_nan(b) {
+            self.flags |= NV;
+            return false;
+        }
+        !Self::less(fmt, b, a)
+    }
+
+    /// `FMIN` (`max` clear) or `FMAX`: a NaN operand gives way to the other
+    /// operand, and `-0` counts as less than `+0`.
+    pub fn min_max(&mut self, fmt: Format, a: u64, b: u64, max: bool) -> u64 {
+        if fmt.is_signaling_nan(a) || fmt.is_signaling_nan(b) {
+            self.flags |= NV;
+        }
+        match (fmt.is_nan(a), fmt.is_nan(b)) {
+            (true, true) => return fmt.canonical_nan(),
+            (true, false) => return b,
+            (false, true) => return a,
+            _ => {}
+        }
+        let a_first = Self::less(fmt, a, b) || (fmt.is_zero(a) && fmt.is_zero(b) && fmt.sign(a));
+        if a_first != max { a } else { b }
+    }
+
+    /// `FCVT` to an integer of `bits` (32 or 64) bits, signed or not. Out
+    /// of range values and NaNs saturate and raise `NV`; 32-bit results
+    /// come sign extended.
+    pub fn to_int(&mut self, fmt: Format, a: u64, signed: bool, bits: u32) -> u64 {
+        let max: u128 = if signed { (1 << (bits - 1)) - 1 } else { (1 << bits) - 1 };
+        let min: i128 = if signed { -(1 << (bits - 1)) } else { 0 };
+        let extend = |value: i128| if bits == 32 { value as i32 as i64 as u64 } else { value as u64 };
+        if fmt.is_nan(a) {
+            self.flags |= NV;
+            return extend(max as i128);
+        }
+        let sign = fmt.sign(a);
+        if fmt.is_inf(a) {
+            self.flags |= NV;
+            return extend(if sign { min } else { max as i128 });
+        }
+        if fmt.is_zero(a) {
+            return 0;
+        }
+        let (_, sig, e) = fmt.unpack(a);
+        let (magnitude, inexact) = match e {
+            e if e >= 64 => (u128::MAX, false),
+            e if e >= 0 => (sig << e, false),
+            e => {
+                let (kept, guard, rest) = shift_right(sig, -e, false);
+                (kept + self.rounds_up(sign, kept, guard, rest) as u128, guard || rest)
+            }
+        };
+        let value = match sign {
+            false if magnitude <= max => magnitude as i128,
+            true if magnitude <= min.unsigned_abs() => -(magnitude as i128),
+            _ => {
+                self.flags |= NV;
+                return extend(if sign { min } else { max as i128 });
+            }
+        };
+        if inexact {
+            self.flags |= NX;
+        }
+        extend(value)
+    }
+
+    /// `FCVT` from the low `bits` (32 or 64) bits of `value`, signed or
+    /// not.
+    pub fn from_int(&mut self, fmt: Format, value: u64, signed: bool, bits: u32) -> u64 {
+        let value: i128 = match (signed, bits) {
+            (true, 32) => value as i32 as i128,
+            (false, 32) => value as u32 as i128,
+            (true, _) => value as i64 as i128,
+            (false, _) => value as i128,
+        };
+        if value == 0 {
+            return 0;
+        }
+        self.round_pack(fmt, value < 0, value.unsigned_abs(), 0, false)
+    }
+
+    /// `FCVT` between formats.
+    pub fn convert(&mut self, from: Format, to: Format, a: u64) -> u64 {
+        if self.nan_operands(from, &[a]).is_some() {
+            return to.canonical_nan();
+        }
+        let sign = from.sign(a);
+        if from.is_inf(a) {
+            return to.inf(sign);
+        }
+        if from.is_zero(a) {
+            return to.zero(sign);
+        }
+        let (_, sig, e) = from.unpack(a);
+        self.round_pack(to, sign, sig, e, false)
+    }
+}
+
+/// `FSGNJ`, `FSGNJN` (`negate`) and `FSGNJX` (`xor`): `a` with its sign
+/// taken from `b`.
+pub fn inject_sign(fmt: Format, a: u64, b: u64, negate: bool, xor: bool) -> u64 {
+    let sign = match (negate, xor) {
+        (_, true) => fmt.sign(a) != fmt.sign(b),
+        (true, _) => !fmt.sign(b),
+        _ => fmt.sign(b),
+    };
+    (a & !fmt.sign_bit()) | fmt.zero(sign)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `op` in `rounding`, returning its result and flags.
+    fn run(rounding: Rounding, op: impl FnOnce(&mut Env) -> u64) -> (u64, u64) {
+        let mut env = Env::new(rounding);
+        let value = op(&mut env);
+        (value, env.flags)
+    }
+
+    #[test]
+    fn tininess_is_detected_after_rounding() {
+        // (1 - 2^-23) * 2^-126 (1 + 2^-23) is tiny but rounds to the
+        // smallest normal, so only inexact.
+        let (value, flags) = run(Rounding::NearestEven, |e| e.mul(F32, 0x3F7F_FFFE, 0x0080_0001));
+        assert_eq!((value, flags), (0x0080_0000, NX));
+        // the same product rounded down stays subnormal.
+        let (value, flags) = run(Rounding::TowardZero, |e| e.mul(F32, 0x3F7F_FFFE, 0x0080_0001));
+        assert_eq!((value, flags), (0x007F_FFFF, UF | NX));
+        // exact subnormal results do not underflow.
+        assert_eq!(run(Rounding::NearestEven, |e| e.mul(F64, 0x0010_0000_0000_0000, 0x3FE0_0000_0000_0000)),
+            (0x0008_0000_0000_0000, 0));
+    }
+
+    #[test]
+    fn overflow_depends_on_rounding() {
+        let max = F64.max_finite(false);
+        assert_eq!(run(Rounding::NearestEven, |e| e.add(F64, max, max)), (F64.inf(false), OF | NX));
+        assert_eq!(run(Rounding::TowardZero, |e| e.add(F64, max, max)), (max, OF | NX));
+        assert_eq!(run(Rounding::Up, |e| e.mul(F64, max | F64.sign_bit(), max)), (F64.max_finite(true), OF | NX));
+    }
+
+    #[test]
+    fn nearest_max_magnitude_breaks_ties_away_from_zero() {
+        let (two_and_a_half, minus) = (0x4020_0000, 0xC020_0000);
+        assert_eq!(run(Rounding::NearestEven, |e| e.to_int(F32, two_and_a_half, true, 32)), (2, NX));
+        assert_eq!(run(Rounding::NearestMaxMagnitude, |e| e.to_int(F32, two_and_a_half, true, 32)), (3, NX));
+        assert_eq!(run(Rounding::NearestMaxMagnitude, |e| e.to_int(F32, minus, true, 64)), (-3i64 as u64, NX));
+        // 1 + 2^-24 is halfway between 1 and the next single.
+        assert_eq!(run(Rounding::NearestEven, |e| e.add(F32, 0x3F80_0000, 0x3380_0000)), (0x3F80_0000, NX));
+        assert_eq!(run(Rounding::NearestMaxMagnitude, |e| e.add(F32, 0x3F80_0000, 0x3380_0000)), (0x3F80_0001, NX));
+    }
+
+    #[test]
+    fn invalid_operations() {
+        let (inf, qnan, snan) = (F64.inf(false), F64.canonical_nan(), 0x7FF4_0000_0000_0000);
+        // infinity times zero is invalid even with a quiet NaN to add.
+        assert_eq!(run(Rounding::NearestEven, |e| e.fma(F64, inf, 0, qnan, false, false)), (qnan, NV));
+        assert_eq!(run(Rounding::NearestEven, |e| e.add(F64, qnan, 0)), (qnan, 0));
+        assert_eq!(run(Rounding::NearestEven, |e| e.add(F64, snan, 0)), (qnan, NV));
+        assert_eq!(run(Rounding::NearestEven, |e| e.sub(F64, inf, inf)), (qnan, NV));
+        assert_eq!(run(Rounding::NearestEven, |e| e.sqrt(F64, 0xBFF0_0000_0000_0000)), (qnan, NV));
+        assert_eq!(run(Rounding::NearestEven, |e| e.sqrt(F64, F64.zero(true))), (F64.zero(true), 0));
+        assert_eq!(run(Rounding::NearestEven, |e| e.div(F64, 0x3FF0_0000_0000_0000, 0)), (inf, DZ));
+        assert_eq!(run(Rounding::NearestEven, |e| e.eq(F64, qnan, qnan) as u64), (0, 0));
+        assert_eq!(run(Rounding::NearestEven, |e| e.le(F64, qnan, qnan) as u64), (0, NV));
+    }
+
+    #[test]
+    fn min_max_of_zeros_and_nans() {
+        let (zero, minus_zero, one) = (0, 0x8000_0000, 0x3F80_0000);
+        assert_eq!(run(Rounding::NearestEven, |e| e.min_max(F32, zero, minus_zero, false)), (minus_zero, 0));
+        assert_eq!(run(Rounding::NearestEven, |e| e.min_max(F32, minus_zero, zero, true)), (zero, 0));
+        assert_eq!(run(Rounding::NearestEven, |e| e.min_max(F32, 0x7FA0_0000, one, false)), (one, NV));
+        assert_eq!(run(Rounding::NearestEven, |e| e.min_max(F32, 0x7FC0_0001, 0x7FC0_0002, true)),
+            (F32.canonical_nan(), 0));
+    }
+
+    #[test]
+    fn conversions_saturate() {
+        let nan = F64.canonical_nan();
+        assert_eq!(run(Rounding::TowardZero, |e| e.to_int(F64, nan, true, 32)), (0x7FFF_FFFF, NV));
+        assert_eq!(run(Rounding::TowardZero, |e| e.to_int(F64, 0x41F0_0000_0000_0000, false, 32)), (u64::MAX, NV));
+        assert_eq!(run(Rounding::TowardZero, |e| e.to_int(F64, 0xBFF0_0000_0000_0000, false, 64)), (0, NV));
+        assert_eq!(run(Rounding::TowardZero, |e| e.to_int(F64, 0xBFE0_0000_0000_0000, false, 64)), (0, NX));
+        assert_eq!(run(Rounding::TowardZero, |e| e.to_int(F64, F64.inf(true), true, 64)), (i64::MIN as u64, NV));
+        assert_eq!(run(Rounding::NearestEven, |e| e.from_int(F32, u64::MAX, false, 32)), (0x4F80_0000, NX));
+    }
+
+    #[test]
+    fn nan_boxing() {
+        assert_eq!(box_f32(0x3F80_0000), 0xFFFF_FFFF_3F80_0000);
+        assert_eq!(unbox_f32(0xFFFF_FFFF_3F80_0000), 0x3F80_0000);
+        assert_eq!(unbox_f32(0x0000_0000_3F80_0000), F32.canonical_nan());
+        assert_eq!(unbox_f32(0xFFFF_FFFE_3F80_0000), F32.canonical_nan());
+    }
+
+    #[test]
+    fn half_precision() {
+        // 65504 + 16 is halfway to 65536, which is out of range, and only
+        // overflows when it rounds up.
+        assert_eq!(run(Rounding::NearestEven, |e| e.add(F16, 0x7BFF, 0x4C00)), (F16.inf(false), OF | NX));
+        assert_eq!(run(Rounding::TowardZero, |e| e.add(F16, 0x7BFF, 0x4C00)), (0x7BFF, NX));
+        // half the smallest subnormal ties to zero.
+        assert_eq!(run(Rounding::NearestEven, |e| e.mul(F16, 0x0001, 0x3800)), (0, UF | NX));
+        assert_eq!(run(Rounding::NearestEven, |e| e.convert(F32, F16, 0x3F80_0000)), (0x3C00, 0));
+        assert_eq!(run(Rounding::NearestEven, |e| e.convert(F16, F64, 0x0001)), (0x3E70_0000_0000_0000, 0));
+        assert_eq!(run(Rounding::NearestEven, |e| e.from_int(F16, 65520, true, 32)), (F16.inf(false), OF | NX));
+        assert_eq!(box_f16(0x3C00), 0xFFFF_FFFF_FFFF_3C00);
+        assert_eq!(unbox_f16(0xFFFF_FFFF_FFFF_3C00), 0x3C00);
+        assert_eq!(unbox_f16(box_f32(0x3C00)), F16.canonical_nan());
+    }
+
+    #[test]
+    fn classes() {
+        assert_eq!(classify(F64, F64.inf(true)), 1 << 0);
+        assert_eq!(classify(F64, 0xBFF0_0000_0000_0000), 1 << 1);
+        assert_eq!(classify(F64, 0x8000_0000_0000_0001), 1 << 2);
+        assert_eq!(classify(F64, F64.zero(true)), 1 << 3);
+        assert_eq!(classify(F32, 0), 1 << 4);
+        assert_eq!(classify(F32, 1), 1 << 5);
+        assert_eq!(classify(F32, 0x3F80_0000), 1 << 6);
+        assert_eq!(classify(F32, 0x7F80_0000), 1 << 7);
+        assert_eq!(classify(F32, 0x7FA0_0000), 1 << 8);
+        assert_eq!(classify(F32, 0x7FC0_0000), 1 << 9);
+    }
+}
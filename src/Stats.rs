@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use crate::Cpu::Hart;
+use crate::Crypto::CryptoOp;
+use crate::Decode::{Decoded, Op};
+use crate::Hypervisor::HypervisorAccess;
+use crate::InstructionDecoder::expand_compressed;
+use crate::Isa::{bitmanip, crypto, Isa};
+use crate::Vector::{VectorAccess, VectorOp};
+
+/// The classes `report` splits retired instructions into.
+const CLASSES: [&str; 11] = [
+    "load",
+    "store",
+    "atomic",
+    "branch taken",
+    "branch not taken",
+    "jump",
+    "alu",
+    "fp",
+    "csr",
+    "system",
+    "vector",
+];
+
+#[derive(Debug, Clone, Copy, Default)]
+struct EncodingCount {
+    retired: u64,
+    /// Of those, how many did not fall through to the next instruction:
+    /// for a conditional branch, how often it was taken.
+    taken: u64,
+}
+
+/// Instruction-mix statistics: how many instructions of each kind
+/// retired. Counting happens per encoding; decoding into `Op`s,
+/// extensions and classes is left to `report`, so the cost while
+/// running is one hash map update per instruction. Needs `Hart::commit`
+/// to be recorded.
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    /// Per `(raw, len, xlen)`: compressed encodings stay distinct from the
+    /// full instructions they expand to, which can depend on the XLEN.
+    encodings: HashMap<(u32, u64, u32), EncodingCount>,
+}
+
+/// The full-size form of an encoding.
+fn expand(raw: u32, len: u64, xlen: u32) -> Option<u32> {
+    match len {
+        2 => expand_compressed(raw as usize, xlen).map(|inst| inst as u32),
+        _ => Some(raw),
+    }
+}
+
+/// The extension a full-size instruction belongs to. The privileged
+/// instructions count as `I`, the hypervisor ones as `H`.
+fn extension(inst: u32) -> &'static str {
+    let (opcode, funct3, funct7) = (inst & 0x7F, (inst >> 12) & 0b111, inst >> 25);
+    let precision = |fmt: u32| match fmt & 0b11 {
+        0b01 => "D",
+        0b10 => "Zfh",
+        _ => "F",
+    };
+    if let Some(extension) = bitmanip(inst).or(crypto(inst).first().copied()) {
+        return extension;
+    }
+    match opcode {
+        0x33 | 0x3B if funct7 == 1 => "M",
+        0x2F => "A",
+        0x07 | 0x27 if VectorAccess::decode(inst).is_some() => "V",
+        0x07 | 0x27 => match funct3 {
+            0b001 => "Zfh",
+            0b011 => "D",
+            _ => "F",
+        },
+        0x57 => "V",
+        // conversions from half precision count as `Zfh`.
+        0x53 if funct7 >> 2 == 0b01000 && (inst >> 20) & 0x1F == 0b10 => "Zfh",
+        0x43 | 0x47 | 0x4B | 0x4F | 0x53 => precision(funct7),
+        0x73 if funct3 == 0b100 || (funct3 == 0 && matches!(funct7, 0b0010001 | 0b0110001)) => "H",
+        0x73 if funct3 != 0 => "Zicsr",
+        0x0F if funct3 == 0b001 => "Zifencei",
+        _ => "I",
+    }
+}
+
+/// Index into `CLASSES`, taken branches aside.
+fn class(inst: u32) -> usize {
+    let (opcode, funct3) = (inst & 0x7F, (inst >> 12) & 0b111);
+    match opcode {
+        0x03 | 0x07 => 0,
+        0x23 | 0x27 => 1,
+        // `lr` loads and `sc` stores; the rest read and write.
+        0x2F => match inst >> 27 {
+            0b00010 => 0,
+            0b00011 => 1,
+            _ => 2,
+        },
+        0x63 => 4,
+        0x67 | 0x6F => 5,
+        0x43 | 0x47 | 0x4B | 0x4F | 0x53 => 7,
+        // the hypervisor loads and stores.
+        0x73 if funct3 == 0b100 => if (inst >> 25) & 1 == 0 { 0 } else { 1 },
+        0x73 if funct3 != 0 => 8,
+        0x73 | 0x0F => 9,
+        0x57 => 10,
+        _ => 6,
+    }
+}
+
+/// The name of the `Op` an encoding decodes to, with the mnemonic for the
+/// ones `Vector`, `Crypto` and `Hypervisor` tell apart.
+fn variant(inst: u32, xlen: u32) -> String {
+    match Decoded::decode(inst, &Isa::default().with_xlen(xlen)) {
+        Some(decoded) => match decoded.op {
+            Op::VLOAD | Op::VSTORE => {
+                let access = VectorAccess::decode(inst).map(|access| access.mnemonic()).unwrap_or_default();
+                format!("{:?} {}", decoded.op, access)
+            },
+            Op::VOP => {
+                let op = VectorOp::decode(inst).map(|op| op.mnemonic(inst)).unwrap_or_default();
+                format!("{:?} {}", decoded.op, op)
+            },
+            Op::CRYPTO => {
+                let op = CryptoOp::decode(inst, xlen).map(|op| op.mnemonic()).unwrap_or_default();
+                format!("{:?} {}", decoded.op, op)
+            },
+            Op::HLV | Op::HSV => {
+                let access = HypervisorAccess::decode(inst).map(|access| access.mnemonic()).unwrap_or_default();
+                format!("{:?} {}", decoded.op, access)
+            },
+            op => format!("{:?}", op),
+        },
+        None => format!("{:#010x}", inst),
+    }
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts the instruction `hart` just ran, if it retired.
+    pub fn record(&mut self, hart: &Hart) {
+        let commit = match &hart.commit {
+            Some(commit) if commit.retired => commit,
+            _ => return,
+        };
+        let count = self.encodings.entry((commit.raw, commit.len, commit.xlen)).or_default();
+        count.retired += 1;
+        if hart.pc != commit.pc.wrapping_add(commit.len) {
+            count.taken += 1;
+        }
+    }
+
+    pub fn retired(&self) -> u64 {
+        self.encodings.values().map(|c| c.retired).sum()
+    }
+
+    /// The instruction mix by extension, class and `Op`, with compressed
+    /// and full-size encodings told apart. Compressed
+    /// instructions count towards `C` in the extension list and towards
+    /// the class of what they expand to everywhere else.
+    pub fn report(&self) -> String {
+        let total = self.retired();
+        let percent = |part: u64| if total == 0 { 0.0 } else { part as f64 * 100.0 / total as f64 };
+
+        let mut compressed = 0;
+        let mut extensions: HashMap<&'static str, u64> = HashMap::new();
+        let mut classes = [0u64; CLASSES.len()];
+        // per variant: (retired, of which compressed)
+        let mut variants: HashMap<String, (u64, u64)> = HashMap::new();
+        for ((raw, len, xlen), count) in self.encodings.iter() {
+            let inst = expand(*raw, *len, *xlen).unwrap_or(*raw);
+            if *len == 2 {
+                compressed += count.retired;
+                *extensions.entry("C").or_default() += count.retired;
+            } else {
+                *extensions.entry(extension(inst)).or_default() += count.retired;
+            }
+            match class(inst) {
+                4 => {
+                    classes[3] += count.taken;
+                    classes[4] += count.retired - count.taken;
+                }
+                index => classes[index] += count.retired,
+            }
+            let entry = variants.entry(variant(inst, *xlen)).or_default();
+            entry.0 += count.retired;
+            if *len == 2 {
+                entry.1 += count.retired;
+            }
+        }
+
+        let mut out = format!("{} instructions retired\n", total);
+        let _ = writeln!(out, "{:>14} {:>6.2}%  compressed", compressed, percent(compressed));
+        let _ = writeln!(out, "{:>14} {:>6.2}%  full size", total - compressed, percent(total - compressed));
+
+        let mut extensions: Vec<(&str, u64)> = extensions.into_iter().collect();
+        extensions.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        let _ = writeln!(out, "\nby extension:");
+        for (name, count) in extensions {
+            let _ = writeln!(out, "{:>14} {:>6.2}%  {}", count, percent(count), name);
+        }
+
+        let _ = writeln!(out, "\nby class:");
+        for (name, count) in CLASSES.iter().zip(classes) {
+            if count != 0 {
+                let _ = writeln!(out, "{:>14} {:>6.2}%  {}", count, percent(count), name);
+            }
+        }
+
+        let mut variants: Vec<(String, (u64, u64))> = variants.into_iter().collect();
+        variants.sort_by(|a, b| b.1 .0.cmp(&a.1 .0).then_with(|| a.0.cmp(&b.0)));
+        let _ = writeln!(out, "\nby instruction:\n{:>14} {:>7} {:>14}  instruction", "retired", "%", "compressed");
+        for (name, (count, compressed)) in variants {
+            let _ = writeln!(out, "{:>14} {:>6.2}% {:>14}  {}", count, percent(count), compressed, name);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Emulator::Machine;
+    use crate::Memory::RAM_BASE;
+
+    /// Mixes compressed and full-size encodings, with a full-size branch
+    /// taken twice and then falling through.
+    const PROGRAM: [u8; 38] = [
+        0x0D, 0x45, // c.li a0, 3
+        0xB3, 0x05, 0xA5, 0x02, // 1: mul a1, a0, a0
+        0x7D, 0x15, // c.addi a0, -1
+        0xE3, 0x1D, 0x05, 0xFE, // bnez a0, 1b
+        0x97, 0x02, 0x00, 0x00, // auipc t0, 0
+        0x03, 0xA6, 0x02, 0x00, // lw a2, 0(t0)
+        0x13, 0x83, 0x02, 0x04, // addi t1, t0, 64
+        0x23, 0x20, 0xC3, 0x00, // sw a2, 0(t1)
+        0xAF, 0x26, 0xC3, 0x00, // amoadd.w a3, a2, (t1)
+        0x73, 0x27, 0x40, 0xF1, // csrr a4, mhartid
+        0x01, 0xA0, // 2: c.j 2b
+    ];
+
+    fn stats(steps: u64) -> Stats {
+        let mut machine = Machine::new(1, 1 << 16);
+        for (offset, byte) in PROGRAM.iter().enumerate() {
+            assert!(machine.bus.store(RAM_BASE + offset as u64, 1, *byte as u64));
+        }
+        machine.harts[0].pc = RAM_BASE;
+        machine.enable_stats();
+        machine.run(Some(steps));
+        machine.stats.unwrap()
+    }
+
+    #[test]
+    fn instruction_mix() {
+        let stats = stats(20);
+        assert_eq!(stats.retired(), 20);
+        // `c.li` and `c.addi` count as compressed `ADDI`s, `c.j` as `JAL`s.
+        let expected = "\
+20 instructions retired
+             8  40.00%  compressed
+            12  60.00%  full size
+
+by extension:
+             8  40.00%  C
+             7  35.00%  I
+             3  15.00%  M
+             1   5.00%  A
+             1   5.00%  Zicsr
+
+by class:
+             1   5.00%  load
+             1   5.00%  store
+             1   5.00%  atomic
+             2  10.00%  branch taken
+             1   5.00%  branch not taken
+             4  20.00%  jump
+             9  45.00%  alu
+             1   5.00%  csr
+
+by instruction:
+       retired       %     compressed  instruction
+             5  25.00%              4  ADDI
+             4  20.00%              4  JAL
+             3  15.00%              0  BNE
+             3  15.00%              0  MUL
+             1   5.00%              0  AMOADDW
+             1   5.00%              0  AUIPC
+             1   5.00%              0  CSRRS
+             1   5.00%              0  LW
+             1   5.00%              0  SW
+";
+        assert_eq!(stats.report(), expected);
+    }
+
+    #[test]
+    fn nothing_retired() {
+        let expected = "\
+0 instructions retired
+             0   0.00%  compressed
+             0   0.00%  full size
+
+by extension:
+
+by class:
+
+by instruction:
+       retired       %     compressed  instruction
+";
+        assert_eq!(Stats::new().report(), expected);
+    }
+}
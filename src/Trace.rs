@@ -0,0 +1,243 @@
+use std::fmt::Write as _;
+use std::io::{BufWriter, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::Csr::csr_name;
+use crate::Disassembler::disassemble;
+
+/// Everything one instruction did that Spike's `--log-commits` reports.
+/// A hart fills this in while it executes when commit logging is on.
+#[derive(Debug, Clone, Default)]
+pub struct Commit {
+    /// Privilege level the instruction executed at, and its XLEN.
+    pub privilege: u64,
+    pub xlen: u32,
+    pub pc: u64,
+    pub raw: u32,
+    /// Instruction length in bytes.
+    pub len: u64,
+    /// Integer register writes as `(reg, value)`, `x0` excluded.
+    pub reg_writes: Vec<(usize, u64)>,
+    /// Floating point register writes as `(reg, value)`.
+    pub freg_writes: Vec<(usize, u64)>,
+    /// CSR writes as `(addr, value read back afterwards)`.
+    pub csr_writes: Vec<(usize, u64)>,
+    /// Vector registers written, one bit per register; `vreg_writes`
+    /// takes their contents once the instruction is done.
+    pub vreg_mask: u32,
+    /// Vector register writes as `(reg, VLEN/8 bytes)`.
+    pub vreg_writes: Vec<(usize, Vec<u8>)>,
+    /// `vtype` and `vl` after a vector register write.
+    pub vconfig: (u64, u64),
+    /// Virtual addresses loaded from.
+    pub loads: Vec<u64>,
+    /// Stores as `(virtual addr, value, size in bytes)`.
+    pub stores: Vec<(u64, u64, u64)>,
+    /// Cleared when the instruction trapped instead of retiring.
+    pub retired: bool,
+}
+
+impl Commit {
+    pub fn new(pc: u64, privilege: u64, xlen: u32) -> Self {
+        Self {
+            privilege,
+            xlen,
+            pc,
+            ..Self::default()
+        }
+    }
+
+    /// Formats the commit the way Spike does, with the disassembly added
+    /// after a `#`:
+    ///
+    /// `core   0: 3 0x0000000080000000 (0x00000297) x5  0x0000000080000000 # auipc   t0, 0x0`
+    ///
+    /// Everything before the `#` matches Spike byte for byte. Like Spike,
+    /// register and CSR writes are listed in the order of its keys for
+    /// them, so `fflags` comes before most integer registers. Addresses,
+    /// integer registers and CSRs are `xlen` bits wide, and vector
+    /// registers come whole, after the element width, LMUL and `vl`
+    /// they were written with: `e32 m1 l4 v1  0x...`.
+    pub fn format(&self, hart: u64) -> String {
+        let width = self.xlen as usize / 4;
+        let mut line = format!("core {:3}: {} 0x{:0width$x} ", hart, self.privilege, self.pc, width = width);
+        let _ = match self.len {
+            2 => write!(line, "(0x{:04x})", self.raw),
+            _ => write!(line, "(0x{:08x})", self.raw),
+        };
+        let mut writes: Vec<(usize, String)> = Vec::new();
+        for (reg, value) in self.reg_writes.iter() {
+            writes.push((reg << 4, format!(" x{:<2} 0x{:0width$x}", reg, self.narrow(*value), width = width)));
+        }
+        for (reg, value) in self.freg_writes.iter() {
+            writes.push(((reg << 4) | 1, format!(" f{:<2} 0x{:016x}", reg, value)));
+        }
+        if let Some(first) = self.vreg_writes.iter().map(|(reg, _)| *reg).min() {
+            writes.push(((first << 4) | 2, self.vconfig_text()));
+        }
+        for (reg, bytes) in self.vreg_writes.iter() {
+            let hex: String = bytes.iter().rev().map(|byte| format!("{:02x}", byte)).collect();
+            writes.push(((reg << 4) | 2, format!(" v{:<2} 0x{}", reg, hex)));
+        }
+        for (addr, value) in self.csr_writes.iter() {
+            let name = csr_name(*addr).unwrap_or("unknown");
+            let value = self.narrow(*value);
+            writes.push(((addr << 4) | 4, format!(" c{}_{} 0x{:0width$x}", addr, name, value, width = width)));
+        }
+        writes.sort_by_key(|(key, _)| *key);
+        for (_, write) in writes {
+            line.push_str(&write);
+        }
+        for addr in self.loads.iter() {
+            let _ = write!(line, " mem 0x{:0width$x}", addr, width = width);
+        }
+        for (addr, value, size) in self.stores.iter() {
+            let _ = write!(line, " mem 0x{:0width$x} 0x{:0size$x}", addr, value, width = width, size = *size as usize * 2);
+        }
+        let _ = write!(line, " # {}", disassemble(self.raw, self.xlen));
+        line
+    }
+
+    /// ` e32 m1 l4`: the element width, LMUL and `vl` vector register
+    /// writes are shown with.
+    fn vconfig_text(&self) -> String {
+        let (vtype, vl) = self.vconfig;
+        let lmul = ["m1", "m2", "m4", "m8", "m?", "mf8", "mf4", "mf2"][(vtype & 0b111) as usize];
+        format!(" e{} {} l{}", 8 << ((vtype >> 3) & 0b111), lmul, vl)
+    }
+
+    /// The low `xlen` bits of a register or CSR value: at XLEN 32 the
+    /// registers hold their values sign extended.
+    pub fn narrow(&self, value: u64) -> u64 {
+        if self.xlen == 32 { value & 0xFFFF_FFFF } else { value }
+    }
+}
+
+/// Where commit lines go. Clones share the same writer.
+#[derive(Clone)]
+pub struct CommitLog {
+    out: Arc<Mutex<BufWriter<Box<dyn Write + Send>>>>,
+}
+
+impl std::fmt::Debug for CommitLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CommitLog")
+    }
+}
+
+impl CommitLog {
+    pub fn new(out: Box<dyn Write + Send>) -> Self {
+        Self {
+            out: Arc::new(Mutex::new(BufWriter::new(out))),
+        }
+    }
+
+    /// Opens `path` for the log, with `-` meaning stdout.
+    pub fn create(path: &str) -> Result<Self, String> {
+        if path == "-" {
+            return Ok(Self::new(Box::new(std::io::stdout())));
+        }
+        let file = std::fs::File::create(path).map_err(|e| format!("could not create `{}`: {}", path, e))?;
+        Ok(Self::new(Box::new(file)))
+    }
+
+    pub fn write(&self, hart: u64, commit: &Commit) {
+        if let Ok(mut out) = self.out.lock() {
+            let _ = writeln!(out, "{}", commit.format(hart));
+        }
+    }
+
+    pub fn flush(&self) {
+        if let Ok(mut out) = self.out.lock() {
+            let _ = out.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit(xlen: u32, pc: u64, raw: u32, len: u64) -> Commit {
+        Commit {
+            raw,
+            len,
+            retired: true,
+            ..Commit::new(pc, 3, xlen)
+        }
+    }
+
+    #[test]
+    fn register_writes() {
+        let mut c = commit(64, 0x8000_0000, 0x0000_0297, 4);
+        c.reg_writes.push((5, 0x8000_0000));
+        assert_eq!(
+            c.format(0),
+            "core   0: 3 0x0000000080000000 (0x00000297) x5  0x0000000080000000 # auipc   t0, 0x0"
+        );
+        // compressed instructions show four hex digits.
+        let mut c = commit(64, 0x8000_0004, 0x4515, 2);
+        c.reg_writes.push((10, 5));
+        assert_eq!(c.format(1), "core   1: 3 0x0000000080000004 (0x4515) x10 0x0000000000000005 # li      a0, 5");
+    }
+
+    #[test]
+    fn csr_writes_sort_by_key() {
+        // `fflags` sorts before x10, `mscratch` after it.
+        let mut c = commit(64, 0x8000_0008, 0xC005_1553, 4);
+        c.reg_writes.push((10, 0x7FFF_FFFF));
+        c.csr_writes.push((0x001, 0x10));
+        assert_eq!(
+            c.format(0),
+            "core   0: 3 0x0000000080000008 (0xc0051553) c1_fflags 0x0000000000000010 x10 0x000000007fffffff \
+             # fcvt.w.s a0, fa0"
+        );
+        let mut c = commit(64, 0x8000_000C, 0x3405_9573, 4);
+        c.csr_writes.push((0x340, 5));
+        c.reg_writes.push((10, 3));
+        assert_eq!(
+            c.format(0),
+            "core   0: 3 0x000000008000000c (0x34059573) x10 0x0000000000000003 c832_mscratch 0x0000000000000005 \
+             # csrrw   a0, mscratch, a1"
+        );
+    }
+
+    #[test]
+    fn memory_accesses() {
+        let mut c = commit(64, 0x8000_0010, 0x1002_B583, 4);
+        c.reg_writes.push((11, 5));
+        c.loads.push(0x8000_0100);
+        assert_eq!(
+            c.format(0),
+            "core   0: 3 0x0000000080000010 (0x1002b583) x11 0x0000000000000005 mem 0x0000000080000100 \
+             # ld      a1, 256(t0)"
+        );
+        // store values are as wide as the store.
+        let mut c = commit(64, 0x8000_0014, 0x10A2_A023, 4);
+        c.stores.push((0x8000_0100, 5, 4));
+        assert_eq!(
+            c.format(0),
+            "core   0: 3 0x0000000080000014 (0x10a2a023) mem 0x0000000080000100 0x00000005 # sw      a0, 256(t0)"
+        );
+        let mut c = commit(64, 0x8000_0018, 0x10A2_8023, 4);
+        c.stores.push((0x8000_0100, 5, 1));
+        assert_eq!(
+            c.format(0),
+            "core   0: 3 0x0000000080000018 (0x10a28023) mem 0x0000000080000100 0x05 # sb      a0, 256(t0)"
+        );
+    }
+
+    #[test]
+    fn rv32_narrows() {
+        // registers hold RV32 values sign extended; the log shows 32 bits.
+        let mut c = commit(32, 0x8000_0000, 0x8000_02B7, 4);
+        c.reg_writes.push((5, 0xFFFF_FFFF_8000_0000));
+        c.csr_writes.push((0x340, 0xFFFF_FFFF_8000_0000));
+        c.stores.push((0x8000_0100, 0x8000_0000, 4));
+        assert_eq!(
+            c.format(0),
+            "core   0: 3 0x80000000 (0x800002b7) x5  0x80000000 c832_mscratch 0x80000000 \
+             mem 0x80000100 0x80000000 # lui     t0, 0x80000"
+        );
+    }
+}
@@ -0,0 +1,1562 @@
+use crate::Cpu::{Hart, Trap};
+#[cfg(feature = "v")]
+use crate::Csr::*;
+#[cfg(not(feature = "v"))]
+use crate::Csr::{VTYPE_VMA, VTYPE_VTA};
+use crate::Decode::Decoded;
+#[cfg(feature = "v")]
+use crate::Decode::Op;
+#[cfg(feature = "v")]
+use crate::Execute::{div, rem, sext};
+use crate::Memory::Bus;
+
+/// How a vector load or store walks memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Addressing {
+    /// Consecutive elements.
+    Unit,
+    /// Consecutive elements, where a fault past the first trims `vl`.
+    FaultOnlyFirst,
+    /// Whole registers, whatever `vtype` says.
+    Whole,
+    /// A mask register: `ceil(vl / 8)` bytes.
+    Mask,
+    /// Elements `rs2` bytes apart.
+    Strided,
+    /// Elements at the byte offsets in `vs2`, in element order if `ordered`.
+    Indexed { ordered: bool },
+}
+
+/// A vector load or store, as `Decoded` leaves it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VectorAccess {
+    pub store: bool,
+    pub addressing: Addressing,
+    /// Fields per segment, or registers for the whole register accesses.
+    pub fields: u64,
+    /// Element width in bytes: of the data, or of the indices when indexed.
+    pub eew: u64,
+    pub masked: bool,
+}
+
+impl VectorAccess {
+    /// Decodes a LOAD-FP or STORE-FP word with one of the vector widths,
+    /// `None` for the scalar ones and the reserved encodings.
+    pub fn decode(raw: u32) -> Option<Self> {
+        use Addressing::*;
+        let store = match raw & 0x7F {
+            0b0000111 => false,
+            0b0100111 => true,
+            _ => return None,
+        };
+        let eew = match (raw >> 12) & 0b111 {
+            0b000 => 1,
+            0b101 => 2,
+            0b110 => 4,
+            0b111 => 8,
+            _ => return None,
+        };
+        let (mew, mop, lumop) = ((raw >> 28) & 1, (raw >> 26) & 0b11, (raw >> 20) & 0x1F);
+        let masked = raw & (1 << 25) == 0;
+        let fields = (raw >> 29) as u64 + 1;
+        if mew != 0 {
+            return None;
+        }
+        let addressing = match (mop, lumop) {
+            (0b00, 0b00000) => Unit,
+            (0b00, 0b10000) if !store => FaultOnlyFirst,
+            (0b00, 0b01000) if !masked && fields.is_power_of_two() && (!store || eew == 1) => Whole,
+            (0b00, 0b01011) if !masked && fields == 1 && eew == 1 => Mask,
+            (0b00, _) => return None,
+            (0b10, _) => Strided,
+            (0b01, _) => Indexed { ordered: false },
+            _ => Indexed { ordered: true },
+        };
+        Some(Self {
+            store,
+            addressing,
+            fields,
+            eew,
+            masked,
+        })
+    }
+
+    /// `vle32.v`, `vlsseg2e16.v`, `vs2r.v` and the like.
+    pub fn mnemonic(&self) -> String {
+        let dir = if self.store { "s" } else { "l" };
+        let bits = self.eew * 8;
+        let seg = if self.fields > 1 { format!("seg{}", self.fields) } else { String::new() };
+        match self.addressing {
+            Addressing::Unit => format!("v{}{}e{}.v", dir, seg, bits),
+            Addressing::FaultOnlyFirst => format!("vl{}e{}ff.v", seg, bits),
+            Addressing::Whole if self.store => format!("vs{}r.v", self.fields),
+            Addressing::Whole => format!("vl{}re{}.v", self.fields, bits),
+            Addressing::Mask => format!("v{}m.v", dir),
+            Addressing::Strided => format!("v{}s{}e{}.v", dir, seg, bits),
+            Addressing::Indexed { ordered } => {
+                format!("v{}{}x{}ei{}.v", dir, if ordered { "o" } else { "u" }, seg, bits)
+            },
+        }
+    }
+}
+
+/// A valid `vtype`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VType {
+    /// Selected element width in bytes.
+    pub sew: u64,
+    /// LMUL in eighths, `1` for 1/8 up to `64` for 8.
+    pub lmul8: u64,
+    /// Tail agnostic.
+    pub ta: bool,
+    /// Mask agnostic.
+    pub ma: bool,
+}
+
+impl VType {
+    /// Decodes `vtype`, `None` for what sets `vill`: reserved bits or
+    /// encodings, and elements wider than ELEN, or than a fractional LMUL
+    /// leaves room for.
+    pub fn parse(vtype: u64, elen: u32) -> Option<Self> {
+        let (vsew, vlmul) = ((vtype >> 3) & 0b111, vtype & 0b111);
+        if vtype >> 8 != 0 || vlmul == 0b100 || vsew > 3 {
+            return None;
+        }
+        let lmul8 = if vlmul < 4 { 8 << vlmul } else { 1 << (vlmul - 5) };
+        let sew = 1 << vsew;
+        if sew * 8 * 8 > elen as u64 * lmul8.min(8) {
+            return None;
+        }
+        Some(Self {
+            sew,
+            lmul8,
+            ta: vtype & VTYPE_VTA != 0,
+            ma: vtype & VTYPE_VMA != 0,
+        })
+    }
+
+    /// The most elements an instruction can work on at VLEN `vlen`.
+    pub fn vlmax(&self, vlen: u32) -> u64 {
+        vlen as u64 * self.lmul8 / (64 * self.sew)
+    }
+
+    /// Registers in a group at this LMUL.
+    pub fn regs(&self) -> usize {
+        group(self.lmul8)
+    }
+
+    /// The EMUL, in eighths, of operands `eew` bytes wide: the ratio of
+    /// their width to SEW times LMUL, `None` outside 1/8 to 8.
+    pub fn emul8(&self, eew: u64) -> Option<u64> {
+        let emul8 = self.lmul8 * eew / self.sew;
+        ((1..=64).contains(&emul8) && emul8 * self.sew == self.lmul8 * eew).then_some(emul8)
+    }
+}
+
+/// `e32, m1, ta, mu`: a `vtype` as assemblers write it, or its number
+/// when it is reserved.
+pub fn vtype_name(vtype: u64) -> String {
+    let (vsew, vlmul) = ((vtype >> 3) & 0b111, vtype & 0b111);
+    if vtype >> 8 != 0 || vlmul == 0b100 || vsew > 3 {
+        return vtype.to_string();
+    }
+    let lmul = ["m1", "m2", "m4", "m8", "", "mf8", "mf4", "mf2"][vlmul as usize];
+    let ta = if vtype & VTYPE_VTA != 0 { "ta" } else { "tu" };
+    let ma = if vtype & VTYPE_VMA != 0 { "ma" } else { "mu" };
+    format!("e{}, {}, {}, {}", 8 << vsew, lmul, ta, ma)
+}
+
+/// Registers in a group `emul8` eighths of a register large.
+fn group(emul8: u64) -> usize {
+    (emul8 as usize / 8).max(1)
+}
+
+/// What an OP-V instruction does, told apart by `funct6`, the operand
+/// form in `funct3` and, for the unary ones, `vs1` or `vs2`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorOp {
+    Add,
+    Sub,
+    Rsub,
+    Minu,
+    Min,
+    Maxu,
+    Max,
+    And,
+    Or,
+    Xor,
+    Rgather,
+    Rgatherei16,
+    Slideup,
+    Slidedown,
+    Adc,
+    Madc,
+    Sbc,
+    Msbc,
+    Merge,
+    Mseq,
+    Msne,
+    Msltu,
+    Mslt,
+    Msleu,
+    Msle,
+    Msgtu,
+    Msgt,
+    Saddu,
+    Sadd,
+    Ssubu,
+    Ssub,
+    Sll,
+    Smul,
+    Mvnr,
+    Srl,
+    Sra,
+    Ssrl,
+    Ssra,
+    Nsrl,
+    Nsra,
+    Nclipu,
+    Nclip,
+    Wredsumu,
+    Wredsum,
+    Redsum,
+    Redand,
+    Redor,
+    Redxor,
+    Redminu,
+    Redmin,
+    Redmaxu,
+    Redmax,
+    Aaddu,
+    Aadd,
+    Asubu,
+    Asub,
+    Slide1up,
+    Slide1down,
+    MvXS,
+    Cpop,
+    First,
+    MvSX,
+    /// `vzext.vf<n>`, with the factor `n`.
+    Zext(u64),
+    /// `vsext.vf<n>`, with the factor `n`.
+    Sext(u64),
+    Msbf,
+    Msof,
+    Msif,
+    Iota,
+    Id,
+    Compress,
+    Mandn,
+    Mand,
+    Mor,
+    Mxor,
+    Morn,
+    Mnand,
+    Mnor,
+    Mxnor,
+    Divu,
+    Div,
+    Remu,
+    Rem,
+    Mulhu,
+    Mul,
+    Mulhsu,
+    Mulh,
+    Madd,
+    Nmsub,
+    Macc,
+    Nmsac,
+    Waddu,
+    Wadd,
+    Wsubu,
+    Wsub,
+    WadduW,
+    WaddW,
+    WsubuW,
+    WsubW,
+    Wmulu,
+    Wmulsu,
+    Wmul,
+    Wmaccu,
+    Wmacc,
+    Wmaccus,
+    Wmaccsu,
+}
+
+impl VectorOp {
+    /// Decodes an OP-V word other than the configuration instructions,
+    /// `None` for reserved encodings and, until they are implemented, the
+    /// floating point forms.
+    pub fn decode(raw: u32) -> Option<Self> {
+        use VectorOp::*;
+        let (funct6, funct3) = (raw >> 26, (raw >> 12) & 0b111);
+        let (vs1, vs2) = ((raw >> 15) & 0x1F, (raw >> 20) & 0x1F);
+        let masked = raw & (1 << 25) == 0;
+        let op = match funct3 {
+            // OPIVV, OPIVI and OPIVX
+            0b000 | 0b011 | 0b100 => {
+                let (v, i) = (funct3 == 0b000, funct3 == 0b011);
+                match funct6 {
+                    0b000000 => Add,
+                    0b000010 if !i => Sub,
+                    0b000011 if !v => Rsub,
+                    0b000100..=0b000111 if !i => [Minu, Min, Maxu, Max][funct6 as usize & 0b11],
+                    0b001001 => And,
+                    0b001010 => Or,
+                    0b001011 => Xor,
+                    0b001100 => Rgather,
+                    0b001110 if v => Rgatherei16,
+                    0b001110 => Slideup,
+                    0b001111 if !v => Slidedown,
+                    0b010000 if masked => Adc,
+                    0b010001 => Madc,
+                    0b010010 if masked && !i => Sbc,
+                    0b010011 if !i => Msbc,
+                    0b010111 if masked || vs2 == 0 => Merge,
+                    0b011000 => Mseq,
+                    0b011001 => Msne,
+                    0b011010 if !i => Msltu,
+                    0b011011 if !i => Mslt,
+                    0b011100 => Msleu,
+                    0b011101 => Msle,
+                    0b011110 if !v => Msgtu,
+                    0b011111 if !v => Msgt,
+                    0b100000 => Saddu,
+                    0b100001 => Sadd,
+                    0b100010 if !i => Ssubu,
+                    0b100011 if !i => Ssub,
+                    0b100101 => Sll,
+                    0b100111 if i && !masked && matches!(vs1, 0 | 1 | 3 | 7) => Mvnr,
+                    0b100111 if !i => Smul,
+                    0b101000 => Srl,
+                    0b101001 => Sra,
+                    0b101010 => Ssrl,
+                    0b101011 => Ssra,
+                    0b101100 => Nsrl,
+                    0b101101 => Nsra,
+                    0b101110 => Nclipu,
+                    0b101111 => Nclip,
+                    0b110000 if v => Wredsumu,
+                    0b110001 if v => Wredsum,
+                    _ => return None,
+                }
+            },
+            // OPMVV and OPMVX
+            0b010 | 0b110 => {
+                let v = funct3 == 0b010;
+                match funct6 {
+                    0b000000..=0b000111 if v => {
+                        [Redsum, Redand, Redor, Redxor, Redminu, Redmin, Redmaxu, Redmax][funct6 as usize]
+                    },
+                    0b001000..=0b001011 => [Aaddu, Aadd, Asubu, Asub][funct6 as usize & 0b11],
+                    0b001110 if !v => Slide1up,
+                    0b001111 if !v => Slide1down,
+                    0b010000 if v => match vs1 {
+                        0b00000 if !masked => MvXS,
+                        0b10000 => Cpop,
+                        0b10001 => First,
+                        _ => return None,
+                    },
+                    0b010000 if !masked && vs2 == 0 => MvSX,
+                    0b010010 if v => match vs1 {
+                        0b00010..=0b00111 if vs1 & 1 == 0 => Zext(8 >> (vs1 / 2 - 1)),
+                        0b00010..=0b00111 => Sext(8 >> (vs1 / 2 - 1)),
+                        _ => return None,
+                    },
+                    0b010100 if v => match vs1 {
+                        0b00001 => Msbf,
+                        0b00010 => Msof,
+                        0b00011 => Msif,
+                        0b10000 => Iota,
+                        0b10001 if vs2 == 0 => Id,
+                        _ => return None,
+                    },
+                    0b010111 if v && !masked => Compress,
+                    0b011000..=0b011111 if v && !masked => {
+                        [Mandn, Mand, Mor, Mxor, Morn, Mnand, Mnor, Mxnor][funct6 as usize & 0b111]
+                    },
+                    0b100000..=0b100111 => [Divu, Div, Remu, Rem, Mulhu, Mul, Mulhsu, Mulh][funct6 as usize & 0b111],
+                    0b101001 => Madd,
+                    0b101011 => Nmsub,
+                    0b101101 => Macc,
+                    0b101111 => Nmsac,
+                    0b110000..=0b110111 => {
+                        [Waddu, Wadd, Wsubu, Wsub, WadduW, WaddW, WsubuW, WsubW][funct6 as usize & 0b111]
+                    },
+                    0b111000 => Wmulu,
+                    0b111010 => Wmulsu,
+                    0b111011 => Wmul,
+                    0b111100 => Wmaccu,
+                    0b111101 => Wmacc,
+                    0b111110 if !v => Wmaccus,
+                    0b111111 => Wmaccsu,
+                    _ => return None,
+                }
+            },
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    /// The mnemonic without its operand-form suffix.
+    fn name(self) -> &'static str {
+        use VectorOp::*;
+        match self {
+            Add => "vadd",
+            Sub => "vsub",
+            Rsub => "vrsub",
+            Minu => "vminu",
+            Min => "vmin",
+            Maxu => "vmaxu",
+            Max => "vmax",
+            And => "vand",
+            Or => "vor",
+            Xor => "vxor",
+            Rgather => "vrgather",
+            Rgatherei16 => "vrgatherei16",
+            Slideup => "vslideup",
+            Slidedown => "vslidedown",
+            Adc => "vadc",
+            Madc => "vmadc",
+            Sbc => "vsbc",
+            Msbc => "vmsbc",
+            Merge => "vmerge",
+            Mseq => "vmseq",
+            Msne => "vmsne",
+            Msltu => "vmsltu",
+            Mslt => "vmslt",
+            Msleu => "vmsleu",
+            Msle => "vmsle",
+            Msgtu => "vmsgtu",
+            Msgt => "vmsgt",
+            Saddu => "vsaddu",
+            Sadd => "vsadd",
+            Ssubu => "vssubu",
+            Ssub => "vssub",
+            Sll => "vsll",
+            Smul => "vsmul",
+            Mvnr => "vmv",
+            Srl => "vsrl",
+            Sra => "vsra",
+            Ssrl => "vssrl",
+            Ssra => "vssra",
+            Nsrl => "vnsrl",
+            Nsra => "vnsra",
+            Nclipu => "vnclipu",
+            Nclip => "vnclip",
+            Wredsumu => "vwredsumu",
+            Wredsum => "vwredsum",
+            Redsum => "vredsum",
+            Redand => "vredand",
+            Redor => "vredor",
+            Redxor => "vredxor",
+            Redminu => "vredminu",
+            Redmin => "vredmin",
+            Redmaxu => "vredmaxu",
+            Redmax => "vredmax",
+            Aaddu => "vaaddu",
+            Aadd => "vaadd",
+            Asubu => "vasubu",
+            Asub => "vasub",
+            Slide1up => "vslide1up",
+            Slide1down => "vslide1down",
+            MvXS | MvSX => "vmv",
+            Cpop => "vcpop",
+            First => "vfirst",
+            Zext(_) => "vzext",
+            Sext(_) => "vsext",
+            Msbf => "vmsbf",
+            Msof => "vmsof",
+            Msif => "vmsif",
+            Iota => "viota",
+            Id => "vid",
+            Compress => "vcompress",
+            Mandn => "vmandn",
+            Mand => "vmand",
+            Mor => "vmor",
+            Mxor => "vmxor",
+            Morn => "vmorn",
+            Mnand => "vmnand",
+            Mnor => "vmnor",
+            Mxnor => "vmxnor",
+            Divu => "vdivu",
+            Div => "vdiv",
+            Remu => "vremu",
+            Rem => "vrem",
+            Mulhu => "vmulhu",
+            Mul => "vmul",
+            Mulhsu => "vmulhsu",
+            Mulh => "vmulh",
+            Madd => "vmadd",
+            Nmsub => "vnmsub",
+            Macc => "vmacc",
+            Nmsac => "vnmsac",
+            Waddu | WadduW => "vwaddu",
+            Wadd | WaddW => "vwadd",
+            Wsubu | WsubuW => "vwsubu",
+            Wsub | WsubW => "vwsub",
+            Wmulu => "vwmulu",
+            Wmulsu => "vwmulsu",
+            Wmul => "vwmul",
+            Wmaccu => "vwmaccu",
+            Wmacc => "vwmacc",
+            Wmaccus => "vwmaccus",
+            Wmaccsu => "vwmaccsu",
+        }
+    }
+
+    /// The full mnemonic of `raw`, which decodes as `self`: `vadd.vx`,
+    /// `vmerge.vim`, `vnsrl.wi`, `vredsum.vs`, `vmv2r.v`...
+    pub fn mnemonic(self, raw: u32) -> String {
+        use VectorOp::*;
+        let name = self.name();
+        let form = match (raw >> 12) & 0b111 {
+            0b000 | 0b010 => "v",
+            0b011 => "i",
+            _ => "x",
+        };
+        let masked = raw & (1 << 25) == 0;
+        match self {
+            Merge if !masked => format!("vmv.v.{}", form),
+            Merge | Adc | Sbc => format!("{}.v{}m", name, form),
+            Madc | Msbc if masked => format!("{}.v{}m", name, form),
+            MvXS => "vmv.x.s".to_string(),
+            MvSX => "vmv.s.x".to_string(),
+            Cpop | First | Msbf | Msof | Msif | Iota => format!("{}.m", name),
+            Zext(factor) | Sext(factor) => format!("{}.vf{}", name, factor),
+            Id => "vid.v".to_string(),
+            Compress => "vcompress.vm".to_string(),
+            Mvnr => format!("vmv{}r.v", ((raw >> 15) & 0x1F) + 1),
+            Mandn | Mand | Mor | Mxor | Morn | Mnand | Mnor | Mxnor => format!("{}.mm", name),
+            Redsum | Redand | Redor | Redxor | Redminu | Redmin | Redmaxu | Redmax | Wredsumu | Wredsum => {
+                format!("{}.vs", name)
+            },
+            Nsrl | Nsra | Nclipu | Nclip | WadduW | WaddW | WsubuW | WsubW => format!("{}.w{}", name, form),
+            _ => format!("{}.v{}", name, form),
+        }
+    }
+
+    /// Whether `rs1` and the immediate are unsigned: shift amounts, slide
+    /// offsets and gather indices.
+    pub fn unsigned_operand(self) -> bool {
+        use VectorOp::*;
+        matches!(
+            self,
+            Sll | Srl | Sra | Ssrl | Ssra | Nsrl | Nsra | Nclipu | Nclip | Slideup | Slidedown | Rgather
+        )
+    }
+
+    /// Whether `v0` is an operand, the carry-in or merge mask, rather than
+    /// masking off elements.
+    pub fn carry(self) -> bool {
+        use VectorOp::*;
+        matches!(self, Adc | Madc | Sbc | Msbc | Merge)
+    }
+
+    /// Whether the multiplicand comes before `vs2` in assembly, as in
+    /// `vmacc.vv vd, vs1, vs2`.
+    pub fn multiply_add(self) -> bool {
+        use VectorOp::*;
+        matches!(self, Madd | Nmsub | Macc | Nmsac | Wmaccu | Wmacc | Wmaccus | Wmaccsu)
+    }
+}
+
+/// The low `bits` bits set.
+#[cfg(feature = "v")]
+fn ones(bits: u32) -> u64 {
+    if bits >= 64 { u64::MAX } else { (1 << bits) - 1 }
+}
+
+/// Shifts `value` right by `shift` bits, rounding as `vxrm` says: to
+/// nearest up, to nearest even, down, or to odd.
+#[cfg(feature = "v")]
+fn round(value: i128, shift: u32, vxrm: u64) -> i128 {
+    if shift == 0 {
+        return value;
+    }
+    let shifted = value >> shift;
+    let half = (value >> (shift - 1)) & 1;
+    let below_half = value & ((1 << (shift - 1)) - 1) != 0;
+    let increment = match vxrm & 0b11 {
+        0 => half,
+        1 => half & (below_half as i128 | (shifted & 1)),
+        2 => 0,
+        _ => (shifted & 1 == 0 && value & ((1 << shift) - 1) != 0) as i128,
+    };
+    shifted + increment
+}
+
+/// Clamps `value` to a signed `bits`-bit element, and whether it had to.
+#[cfg(feature = "v")]
+fn clamp(value: i128, bits: u32) -> (u64, bool) {
+    let (min, max) = (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1);
+    let clamped = value.clamp(min, max);
+    (clamped as u64 & ones(bits), clamped != value)
+}
+
+/// Applies a single-width operation to element `a` of `vs2`, `b` of `vs1`
+/// (or the scalar) and `d` of `vd`, all `bits` wide, with `carry` the
+/// carry-in or merge bit from `v0`. Returns the result and whether it
+/// saturated.
+#[cfg(feature = "v")]
+fn alu(op: VectorOp, a: u64, b: u64, d: u64, bits: u32, carry: bool, vxrm: u64) -> (u64, bool) {
+    use VectorOp::*;
+    let (sa, sb) = (sext(a, bits) as i64, sext(b, bits) as i64);
+    let shift = (b & (bits as u64 - 1)) as u32;
+    let value = match op {
+        Add => a.wrapping_add(b),
+        Sub => a.wrapping_sub(b),
+        Rsub => b.wrapping_sub(a),
+        Minu => a.min(b),
+        Min => sa.min(sb) as u64,
+        Maxu => a.max(b),
+        Max => sa.max(sb) as u64,
+        And => a & b,
+        Or => a | b,
+        Xor => a ^ b,
+        Adc => a.wrapping_add(b).wrapping_add(carry as u64),
+        Sbc => a.wrapping_sub(b).wrapping_sub(carry as u64),
+        Merge => if carry { b } else { a },
+        Saddu => match a as u128 + b as u128 {
+            sum if sum > ones(bits) as u128 => return (ones(bits), true),
+            sum => sum as u64,
+        },
+        Sadd => return clamp(sa as i128 + sb as i128, bits),
+        Ssubu => match a.checked_sub(b) {
+            Some(difference) => difference,
+            None => return (0, true),
+        },
+        Ssub => return clamp(sa as i128 - sb as i128, bits),
+        Sll => a << shift,
+        Srl => a >> shift,
+        Sra => (sa >> shift) as u64,
+        Ssrl => round(a as i128, shift, vxrm) as u64,
+        Ssra => round(sa as i128, shift, vxrm) as u64,
+        Smul => return clamp(round(sa as i128 * sb as i128, bits - 1, vxrm), bits),
+        Aaddu => round(a as i128 + b as i128, 1, vxrm) as u64,
+        Aadd => round(sa as i128 + sb as i128, 1, vxrm) as u64,
+        Asubu => round(a as i128 - b as i128, 1, vxrm) as u64,
+        Asub => round(sa as i128 - sb as i128, 1, vxrm) as u64,
+        Divu => a.checked_div(b).unwrap_or(u64::MAX),
+        Div => div(sa, sb) as u64,
+        Remu => a.checked_rem(b).unwrap_or(a),
+        Rem => rem(sa, sb) as u64,
+        Mulhu => ((a as u128 * b as u128) >> bits) as u64,
+        Mul => a.wrapping_mul(b),
+        Mulhsu => ((sa as i128 * b as i128) >> bits) as u64,
+        Mulh => ((sa as i128 * sb as i128) >> bits) as u64,
+        Madd => b.wrapping_mul(d).wrapping_add(a),
+        Nmsub => a.wrapping_sub(b.wrapping_mul(d)),
+        Macc => b.wrapping_mul(a).wrapping_add(d),
+        Nmsac => d.wrapping_sub(b.wrapping_mul(a)),
+        _ => unreachable!("`{:?}` is not a single-width operation", op),
+    };
+    (value & ones(bits), false)
+}
+
+/// The single-width operation a widening one performs once its operands
+/// are extended to 2*SEW, and whether `vs2` and the other operand are
+/// sign extended.
+#[cfg(feature = "v")]
+fn widened(op: VectorOp) -> (VectorOp, bool, bool) {
+    use VectorOp::*;
+    match op {
+        Waddu | WadduW => (Add, false, false),
+        Wadd | WaddW => (Add, true, true),
+        Wsubu | WsubuW => (Sub, false, false),
+        Wsub | WsubW => (Sub, true, true),
+        Wmulu => (Mul, false, false),
+        Wmulsu => (Mul, true, false),
+        Wmul => (Mul, true, true),
+        Wmaccu => (Macc, false, false),
+        Wmacc => (Macc, true, true),
+        Wmaccsu => (Macc, false, true),
+        Wmaccus => (Macc, true, false),
+        _ => unreachable!("`{:?}` is not a widening operation", op),
+    }
+}
+
+/// Shifts a 2*SEW element `a` right by `b` and narrows it to `bits`, with
+/// whether clipping saturated.
+#[cfg(feature = "v")]
+fn narrow(op: VectorOp, a: u64, b: u64, bits: u32, vxrm: u64) -> (u64, bool) {
+    let shift = (b & (2 * bits as u64 - 1)) as u32;
+    let wide = sext(a, 2 * bits) as i64 as i128;
+    match op {
+        VectorOp::Nsrl => ((a >> shift) & ones(bits), false),
+        VectorOp::Nsra => ((wide >> shift) as u64 & ones(bits), false),
+        VectorOp::Nclipu => match round(a as i128, shift, vxrm) {
+            value if value > ones(bits) as i128 => (ones(bits), true),
+            value => (value as u64, false),
+        },
+        _ => clamp(round(wide, shift, vxrm), bits),
+    }
+}
+
+/// The mask bit an integer compare, or a carry or borrow out, produces.
+#[cfg(feature = "v")]
+fn compare(op: VectorOp, a: u64, b: u64, bits: u32, carry: bool) -> bool {
+    use VectorOp::*;
+    let (sa, sb) = (sext(a, bits) as i64, sext(b, bits) as i64);
+    match op {
+        Mseq => a == b,
+        Msne => a != b,
+        Msltu => a < b,
+        Mslt => sa < sb,
+        Msleu => a <= b,
+        Msle => sa <= sb,
+        Msgtu => a > b,
+        Msgt => sa > sb,
+        Madc => a as u128 + b as u128 + carry as u128 > ones(bits) as u128,
+        _ => (a as u128) < b as u128 + carry as u128,
+    }
+}
+
+/// Folds element `e` into a reduction's accumulator, both `bits` wide.
+#[cfg(feature = "v")]
+fn reduce(op: VectorOp, acc: u64, e: u64, bits: u32) -> u64 {
+    use VectorOp::*;
+    let (sa, se) = (sext(acc, bits) as i64, sext(e, bits) as i64);
+    let value = match op {
+        Redand => acc & e,
+        Redor => acc | e,
+        Redxor => acc ^ e,
+        Redminu => acc.min(e),
+        Redmin => sa.min(se) as u64,
+        Redmaxu => acc.max(e),
+        Redmax => sa.max(se) as u64,
+        _ => acc.wrapping_add(e),
+    };
+    value & ones(bits)
+}
+
+/// Whether groups of `a.1` registers at `a.0` and `b.1` at `b.0` share a
+/// register.
+#[cfg(feature = "v")]
+fn overlaps(a: (usize, usize), b: (usize, usize)) -> bool {
+    a.0 < b.0 + b.1 && b.0 < a.0 + a.1
+}
+
+/// Whether a destination group may overlap a source group of another
+/// element width: a narrower destination only in the lowest-numbered part
+/// of the source, a wider one only in its own highest-numbered part, and
+/// only when the source is at least a whole register. Masks are EEW `0`.
+#[cfg(feature = "v")]
+fn may_overlap(dst: (usize, usize), dst_eew: u64, src: (usize, usize), src_eew: u64, src_emul8: u64) -> bool {
+    if !overlaps(dst, src) || dst_eew == src_eew {
+        return true;
+    }
+    if dst_eew < src_eew {
+        return dst.0 == src.0;
+    }
+    src_emul8 >= 8 && dst.0 + dst.1 == src.0 + src.1
+}
+
+/// Element `index`, `eew` bytes wide, of the group at `reg` in `v`.
+#[cfg(feature = "v")]
+fn read_elem(v: &[u8], reg: usize, index: u64, eew: u64) -> u64 {
+    let at = reg * (v.len() / 32) + (index * eew) as usize;
+    v[at..at + eew as usize].iter().rev().fold(0, |value, byte| (value << 8) | *byte as u64)
+}
+
+/// Turns a check into `None` for illegal instructions.
+#[cfg(feature = "v")]
+fn require(ok: bool) -> Option<()> {
+    ok.then_some(())
+}
+
+/// The operands of an OP-V instruction. Sources are read from the
+/// registers as the instruction found them, so results written along the
+/// way never feed back in.
+#[cfg(feature = "v")]
+struct Operands {
+    vd: usize,
+    vs1: usize,
+    vs2: usize,
+    /// `rs1` or the immediate, for the `.vx` and `.vi` forms.
+    scalar: Option<u64>,
+    masked: bool,
+    vt: VType,
+    src: Vec<u8>,
+}
+
+#[cfg(feature = "v")]
+impl Operands {
+    fn elem(&self, reg: usize, index: u64, eew: u64) -> u64 {
+        read_elem(&self.src, reg, index, eew)
+    }
+
+    fn bit(&self, reg: usize, index: u64) -> bool {
+        (self.elem(reg, index / 8, 1) >> (index % 8)) & 1 != 0
+    }
+
+    /// Whether element `index` takes part: always, unless `v0` masks it
+    /// off.
+    fn active(&self, index: u64) -> bool {
+        !self.masked || self.bit(0, index)
+    }
+
+    /// The operand beside `vs2` for element `index`: `vs1`'s element, or
+    /// the scalar truncated to `eew` bytes.
+    fn operand(&self, index: u64, eew: u64) -> u64 {
+        match self.scalar {
+            Some(scalar) => scalar & ones(eew as u32 * 8),
+            None => self.elem(self.vs1, index, eew),
+        }
+    }
+
+    /// Whether `vs1`, when it is a source, starts a group of `regs`.
+    fn vs1_aligned(&self, regs: usize) -> bool {
+        self.scalar.is_some() || self.vs1.is_multiple_of(regs)
+    }
+}
+
+/// Built without the `V` Extension: its instructions never decode.
+#[cfg(not(feature = "v"))]
+impl Hart {
+    pub fn vector(&mut self, _bus: &mut Bus, inst: &Decoded) -> Result<(), Trap> {
+        Err(Trap::illegal(inst.raw))
+    }
+}
+
+#[cfg(feature = "v")]
+impl Hart {
+    /// Executes a `V` Extension instruction, all of which are illegal
+    /// while `mstatus.VS` is off, or a guest's `vsstatus.VS`. Elements the
+    /// tail or mask policy leaves agnostic are set to all ones.
+    pub fn vector(&mut self, bus: &mut Bus, inst: &Decoded) -> Result<(), Trap> {
+        if !self.vector_enabled() {
+            return Err(Trap::illegal(inst.raw));
+        }
+        let result = match inst.op {
+            Op::VSETVLI | Op::VSETIVLI | Op::VSETVL => {
+                self.vset(inst);
+                self.dirty_vector();
+                return Ok(());
+            },
+            Op::VLOAD | Op::VSTORE => self.vector_access(bus, inst.raw),
+            _ => self.vector_op(inst.raw).ok_or(Trap::illegal(inst.raw)),
+        };
+        match result {
+            Err(trap) if trap == Trap::illegal(inst.raw) => return Err(trap),
+            // a load or store trapped partway, and `vstart` says where.
+            Err(trap) => {
+                self.dirty_vector();
+                self.log_vregs();
+                return Err(trap);
+            },
+            Ok(()) => {},
+        }
+        self.dirty_vector();
+        self.csr.vstart = 0;
+        self.log_csr_write(VSTART, 0);
+        self.log_vregs();
+        Ok(())
+    }
+
+    /// `vsetvli`, `vsetivli` and `vsetvl`: sets `vtype`, and `vl` to as
+    /// much of the application vector length as fits. `rs1` of `x0` asks
+    /// for VLMAX, or with `rd` also `x0` keeps `vl`.
+    fn vset(&mut self, inst: &Decoded) {
+        let (rd, rs1, raw) = (inst.rd as usize, inst.rs1 as usize, inst.raw);
+        let vtype = match inst.op {
+            Op::VSETVLI => ((raw >> 20) & 0x7FF) as u64,
+            Op::VSETIVLI => ((raw >> 20) & 0x3FF) as u64,
+            _ => self.src_unsigned(inst.rs2 as usize),
+        };
+        let avl = match inst.op {
+            Op::VSETIVLI => rs1 as u64,
+            _ if rs1 != 0 => self.src_unsigned(rs1),
+            _ if rd != 0 => u64::MAX,
+            _ => self.csr.vl,
+        };
+        match VType::parse(vtype, self.isa.elen()) {
+            Some(vt) => {
+                self.csr.vtype = vtype;
+                self.csr.vl = avl.min(vt.vlmax(self.isa.vlen()));
+            },
+            None => {
+                self.csr.vtype = VTYPE_VILL;
+                self.csr.vl = 0;
+            },
+        }
+        self.csr.vstart = 0;
+        self.write_reg(rd, self.csr.vl);
+    }
+
+    /// The current `vtype`, `None` while `vill` is set.
+    fn vtype(&self) -> Option<VType> {
+        VType::parse(self.csr.vtype, self.isa.elen())
+    }
+
+    fn vlenb(&self) -> usize {
+        self.v.len() / 32
+    }
+
+    /// The elements from `vstart` up to `vl`.
+    fn body(&self) -> std::ops::Range<u64> {
+        self.csr.vstart..self.csr.vl
+    }
+
+    fn elem(&self, reg: usize, index: u64, eew: u64) -> u64 {
+        read_elem(&self.v, reg, index, eew)
+    }
+
+    fn set_elem(&mut self, reg: usize, index: u64, eew: u64, value: u64) {
+        let at = reg * self.vlenb() + (index * eew) as usize;
+        for (i, byte) in self.v[at..at + eew as usize].iter_mut().enumerate() {
+            *byte = (value >> (i * 8)) as u8;
+        }
+        let written = at / self.vlenb();
+        if let Some(commit) = &mut self.commit {
+            commit.vreg_mask |= 1 << written;
+        }
+    }
+
+    fn set_bit(&mut self, reg: usize, index: u64, value: bool) {
+        let byte = self.elem(reg, index / 8, 1);
+        let bit = 1 << (index % 8);
+        self.set_elem(reg, index / 8, 1, if value { byte | bit } else { byte & !bit });
+    }
+
+    /// Element `index` of `vd` is masked off: left alone when undisturbed,
+    /// all ones when agnostic.
+    fn inactive(&mut self, vd: usize, index: u64, eew: u64, ma: bool) {
+        if ma {
+            self.set_elem(vd, index, eew, u64::MAX);
+        }
+    }
+
+    /// Fills the elements of the `regs`-register group at `vd` from `from`
+    /// on with all ones when the tail is agnostic. Nothing is written when
+    /// `vstart` is not below `vl`.
+    fn tail(&mut self, vd: usize, regs: usize, eew: u64, from: u64, ta: bool) {
+        if !ta || self.csr.vstart >= self.csr.vl {
+            return;
+        }
+        for index in from..(regs * self.vlenb()) as u64 / eew {
+            self.set_elem(vd, index, eew, u64::MAX);
+        }
+    }
+
+    /// The tail of a mask destination, always agnostic.
+    fn mask_tail(&mut self, vd: usize) {
+        if self.csr.vstart >= self.csr.vl {
+            return;
+        }
+        for index in self.csr.vl..self.vlenb() as u64 * 8 {
+            self.set_bit(vd, index, true);
+        }
+    }
+
+    /// Sets `vxsat` after a fixed-point instruction saturated.
+    fn saturate(&mut self, saturated: bool) {
+        if saturated {
+            self.csr.vcsr |= 1;
+            self.log_csr_write(VXSAT, 1);
+        }
+    }
+
+    /// Hands the vector registers the instruction wrote to the commit log.
+    fn log_vregs(&mut self) {
+        let vlenb = self.vlenb();
+        if let Some(commit) = &mut self.commit {
+            for reg in (0..32).filter(|reg| commit.vreg_mask & (1 << reg) != 0) {
+                commit.vreg_writes.push((reg, self.v[reg * vlenb..(reg + 1) * vlenb].to_vec()));
+            }
+            commit.vconfig = (self.csr.vtype, self.csr.vl);
+        }
+    }
+
+    /// Executes a vector load or store element by element, in order, so
+    /// a trap leaves `vstart` at the element that took it.
+    fn vector_access(&mut self, bus: &mut Bus, raw: u32) -> Result<(), Trap> {
+        let illegal = Trap::illegal(raw);
+        let access = VectorAccess::decode(raw).ok_or(illegal)?;
+        let (vd, rs1, rs2) = (((raw >> 7) & 0x1F) as usize, ((raw >> 15) & 0x1F) as usize, ((raw >> 20) & 0x1F) as usize);
+        let whole = access.addressing == Addressing::Whole;
+        let vt = if whole { None } else { Some(self.vtype().ok_or(illegal)?) };
+        // the data's width, register group and element count, and the
+        // index EMUL when indexed.
+        let (eew, regs, count, index_emul8) = match (access.addressing, vt) {
+            (Addressing::Whole, _) => {
+                let regs = access.fields as usize;
+                (access.eew, regs, (regs * self.vlenb()) as u64 / access.eew, 0)
+            },
+            (Addressing::Mask, Some(_)) => (1, 1, self.csr.vl.div_ceil(8), 0),
+            (Addressing::Indexed { .. }, Some(vt)) => {
+                (vt.sew, vt.regs(), self.csr.vl, vt.emul8(access.eew).ok_or(illegal)?)
+            },
+            (_, Some(vt)) => (access.eew, group(vt.emul8(access.eew).ok_or(illegal)?), self.csr.vl, 0),
+            (_, None) => return Err(illegal),
+        };
+        let fields = if whole { 1 } else { access.fields as usize };
+        let mut ok = vd.is_multiple_of(regs) && regs * fields <= 8 && vd + regs * fields <= 32;
+        ok &= access.store || !access.masked || vd != 0;
+        if index_emul8 != 0 {
+            let index_regs = group(index_emul8);
+            let (data, index) = ((vd, regs * fields), (rs2, index_regs));
+            ok &= rs2.is_multiple_of(index_regs);
+            ok &= access.store
+                || if fields > 1 {
+                    !overlaps(data, index)
+                } else {
+                    may_overlap(data, eew, index, access.eew, index_emul8)
+                };
+        }
+        if !ok {
+            return Err(illegal);
+        }
+
+        let base = self.x[rs1];
+        let stride = match access.addressing {
+            Addressing::Strided => self.src(rs2),
+            _ => eew * fields as u64,
+        };
+        let ma = vt.is_some_and(|vt| vt.ma);
+        'elements: for i in self.csr.vstart..count {
+            if access.masked && self.elem(0, i / 8, 1) & (1 << (i % 8)) == 0 {
+                for f in 0..fields {
+                    if !access.store {
+                        self.inactive(vd + f * regs, i, eew, ma);
+                    }
+                }
+                continue;
+            }
+            let offset = match access.addressing {
+                Addressing::Indexed { .. } => self.elem(rs2, i, access.eew),
+                _ => i.wrapping_mul(stride),
+            };
+            for f in 0..fields {
+                let reg = vd + f * regs;
+                let addr = base.wrapping_add(offset).wrapping_add(f as u64 * eew);
+                let result = if access.store {
+                    let value = self.elem(reg, i, eew);
+                    self.store(bus, addr, eew, value)
+                } else {
+                    self.load(bus, addr, eew).map(|value| self.set_elem(reg, i, eew, value))
+                };
+                match result {
+                    Ok(()) => {},
+                    Err(_) if access.addressing == Addressing::FaultOnlyFirst && i > 0 => {
+                        self.csr.vl = i;
+                        self.log_csr_write(VL, i);
+                        break 'elements;
+                    },
+                    Err(trap) => {
+                        self.csr.vstart = i;
+                        return Err(trap);
+                    },
+                }
+            }
+        }
+        if let (false, Some(vt)) = (access.store, vt) {
+            let (from, ta) = if access.addressing == Addressing::Mask { (count, true) } else { (self.csr.vl, vt.ta) };
+            for f in 0..fields {
+                self.tail(vd + f * regs, regs, eew, from, ta);
+            }
+        }
+        Ok(())
+    }
+
+    /// Executes an OP-V instruction, `None` when it is illegal: under
+    /// `vill`, with misaligned or wrongly overlapping register groups, or
+    /// at element widths ELEN rules out.
+    fn vector_op(&mut self, raw: u32) -> Option<()> {
+        use VectorOp::*;
+        let op = VectorOp::decode(raw)?;
+        let (vd, vs1, vs2) = (((raw >> 7) & 0x1F) as usize, ((raw >> 15) & 0x1F) as usize, ((raw >> 20) & 0x1F) as usize);
+        if op == Mvnr {
+            return self.move_whole(vd, vs2, vs1 + 1);
+        }
+        let vt = self.vtype()?;
+        let scalar = match (raw >> 12) & 0b111 {
+            0b100 | 0b110 if op.unsigned_operand() => Some(self.src_unsigned(vs1)),
+            0b100 | 0b110 => Some(self.src(vs1)),
+            0b011 if op.unsigned_operand() => Some(vs1 as u64),
+            0b011 => Some(sext(vs1 as u64, 5)),
+            _ => None,
+        };
+        // the embedded subsets leave out the high halves of 64-bit products.
+        require(self.isa.has('v') || vt.sew < 8 || !matches!(op, Mulh | Mulhu | Mulhsu | Smul))?;
+        let o = Operands {
+            vd,
+            vs1,
+            vs2,
+            scalar,
+            masked: raw & (1 << 25) == 0,
+            vt,
+            src: self.v.clone(),
+        };
+        match op {
+            Redsum | Redand | Redor | Redxor | Redminu | Redmin | Redmaxu | Redmax | Wredsumu | Wredsum => {
+                self.reduction(op, &o)
+            },
+            Mandn | Mand | Mor | Mxor | Morn | Mnand | Mnor | Mxnor => self.mask_logical(op, &o),
+            Mseq | Msne | Msltu | Mslt | Msleu | Msle | Msgtu | Msgt | Madc | Msbc => self.comparison(op, &o),
+            Nsrl | Nsra | Nclipu | Nclip => self.narrowing(op, &o),
+            Waddu | Wadd | Wsubu | Wsub | WadduW | WaddW | WsubuW | WsubW | Wmulu | Wmulsu | Wmul | Wmaccu
+            | Wmacc | Wmaccus | Wmaccsu => self.widening(op, &o),
+            MvXS => {
+                let value = o.elem(vs2, 0, vt.sew);
+                self.write_reg(vd, sext(value, vt.sew as u32 * 8));
+                Some(())
+            },
+            MvSX => {
+                if self.csr.vstart < self.csr.vl {
+                    self.set_elem(vd, 0, vt.sew, o.operand(0, vt.sew));
+                    self.tail(vd, 1, vt.sew, 1, vt.ta);
+                }
+                Some(())
+            },
+            Cpop | First => {
+                require(self.csr.vstart == 0)?;
+                let mut set = (0..self.csr.vl).filter(|i| o.active(*i) && o.bit(vs2, *i));
+                let value = if op == Cpop { set.count() as u64 } else { set.next().unwrap_or(u64::MAX) };
+                self.write_reg(vd, value);
+                Some(())
+            },
+            Zext(factor) | Sext(factor) => self.extension(factor, op == Sext(factor), &o),
+            Msbf | Msof | Msif => self.set_first(op, &o),
+            Iota | Id => self.index(op, &o),
+            Compress => self.compress(&o),
+            Slideup | Slide1up | Slidedown | Slide1down => self.slide(op, &o),
+            Rgather | Rgatherei16 => self.gather(op, &o),
+            _ => self.single(op, &o),
+        }
+    }
+
+    /// The single-width element-wise operations, `vmerge` and `vmv.v.*`
+    /// included.
+    fn single(&mut self, op: VectorOp, o: &Operands) -> Option<()> {
+        let (eew, regs) = (o.vt.sew, o.vt.regs());
+        require(o.vd.is_multiple_of(regs) && o.vs2.is_multiple_of(regs) && o.vs1_aligned(regs))?;
+        require(!o.masked || o.vd != 0)?;
+        let (bits, vxrm) = (eew as u32 * 8, self.csr.vcsr >> 1);
+        let mut saturated = false;
+        for i in self.body() {
+            let bit = o.masked && o.bit(0, i);
+            if o.masked && !op.carry() && !bit {
+                self.inactive(o.vd, i, eew, o.vt.ma);
+                continue;
+            }
+            // an unmasked `vmerge` is `vmv.v.*`, which takes the operand.
+            let carry = bit || (!o.masked && op == VectorOp::Merge);
+            let (value, sat) = alu(op, o.elem(o.vs2, i, eew), o.operand(i, eew), o.elem(o.vd, i, eew), bits, carry, vxrm);
+            saturated |= sat;
+            self.set_elem(o.vd, i, eew, value);
+        }
+        self.saturate(saturated);
+        self.tail(o.vd, regs, eew, self.csr.vl, o.vt.ta);
+        Some(())
+    }
+
+    /// The widening operations: 2*SEW results of SEW operands, or of a
+    /// 2*SEW `vs2` for the `.w` forms.
+    fn widening(&mut self, op: VectorOp, o: &Operands) -> Option<()> {
+        let (base, sign_a, sign_b) = widened(op);
+        let wide_vs2 = matches!(op, VectorOp::WadduW | VectorOp::WaddW | VectorOp::WsubuW | VectorOp::WsubW);
+        let (eew, regs) = (o.vt.sew, o.vt.regs());
+        require(eew * 16 <= self.isa.elen() as u64 && o.vt.lmul8 <= 32)?;
+        let (wide, wide_regs) = (eew * 2, group(o.vt.lmul8 * 2));
+        let vs2_eew = if wide_vs2 { wide } else { eew };
+        let vs2_regs = if wide_vs2 { wide_regs } else { regs };
+        require(o.vd.is_multiple_of(wide_regs) && o.vs2.is_multiple_of(vs2_regs) && o.vs1_aligned(regs))?;
+        require(!o.masked || o.vd != 0)?;
+        let dst = (o.vd, wide_regs);
+        require(may_overlap(dst, wide, (o.vs2, vs2_regs), vs2_eew, o.vt.lmul8))?;
+        require(o.scalar.is_some() || may_overlap(dst, wide, (o.vs1, regs), eew, o.vt.lmul8))?;
+        let (bits, wide_bits) = (eew as u32 * 8, eew as u32 * 16);
+        let extend = |value: u64, sign: bool| if sign { sext(value, bits) & ones(wide_bits) } else { value };
+        for i in self.body() {
+            if !o.active(i) {
+                self.inactive(o.vd, i, wide, o.vt.ma);
+                continue;
+            }
+            let a = o.elem(o.vs2, i, vs2_eew);
+            let a = if wide_vs2 { a } else { extend(a, sign_a) };
+            let b = extend(o.operand(i, eew), sign_b);
+            let (value, _) = alu(base, a, b, o.elem(o.vd, i, wide), wide_bits, false, 0);
+            self.set_elem(o.vd, i, wide, value);
+        }
+        self.tail(o.vd, wide_regs, wide, self.csr.vl, o.vt.ta);
+        Some(())
+    }
+
+    /// The narrowing shifts and clips of a 2*SEW `vs2`.
+    fn narrowing(&mut self, op: VectorOp, o: &Operands) -> Option<()> {
+        let (eew, regs) = (o.vt.sew, o.vt.regs());
+        require(eew * 16 <= self.isa.elen() as u64 && o.vt.lmul8 <= 32)?;
+        let wide_regs = group(o.vt.lmul8 * 2);
+        require(o.vd.is_multiple_of(regs) && o.vs2.is_multiple_of(wide_regs) && o.vs1_aligned(regs))?;
+        require(!o.masked || o.vd != 0)?;
+        require(may_overlap((o.vd, regs), eew, (o.vs2, wide_regs), eew * 2, o.vt.lmul8 * 2))?;
+        let (bits, vxrm) = (eew as u32 * 8, self.csr.vcsr >> 1);
+        let mut saturated = false;
+        for i in self.body() {
+            if !o.active(i) {
+                self.inactive(o.vd, i, eew, o.vt.ma);
+                continue;
+            }
+            let (value, sat) = narrow(op, o.elem(o.vs2, i, eew * 2), o.operand(i, eew), bits, vxrm);
+            saturated |= sat;
+            self.set_elem(o.vd, i, eew, value);
+        }
+        self.saturate(saturated);
+        self.tail(o.vd, regs, eew, self.csr.vl, o.vt.ta);
+        Some(())
+    }
+
+    /// The integer compares, and the carry and borrow outs of `vmadc` and
+    /// `vmsbc`, all writing a mask.
+    fn comparison(&mut self, op: VectorOp, o: &Operands) -> Option<()> {
+        let (eew, regs) = (o.vt.sew, o.vt.regs());
+        require(o.vs2.is_multiple_of(regs) && o.vs1_aligned(regs))?;
+        require(may_overlap((o.vd, 1), 0, (o.vs2, regs), eew, o.vt.lmul8))?;
+        require(o.scalar.is_some() || may_overlap((o.vd, 1), 0, (o.vs1, regs), eew, o.vt.lmul8))?;
+        let bits = eew as u32 * 8;
+        for i in self.body() {
+            let bit = o.masked && o.bit(0, i);
+            if o.masked && !op.carry() && !bit {
+                if o.vt.ma {
+                    self.set_bit(o.vd, i, true);
+                }
+                continue;
+            }
+            let value = compare(op, o.elem(o.vs2, i, eew), o.operand(i, eew), bits, bit);
+            self.set_bit(o.vd, i, value);
+        }
+        self.mask_tail(o.vd);
+        Some(())
+    }
+
+    /// The reductions, folding the active elements of `vs2` into element 0
+    /// of `vs1` and writing the result to element 0 of `vd`.
+    fn reduction(&mut self, op: VectorOp, o: &Operands) -> Option<()> {
+        let widening = matches!(op, VectorOp::Wredsumu | VectorOp::Wredsum);
+        let eew = o.vt.sew;
+        let acc_eew = if widening { eew * 2 } else { eew };
+        require(self.csr.vstart == 0 && o.vs2.is_multiple_of(o.vt.regs()))?;
+        require(acc_eew * 8 <= self.isa.elen() as u64)?;
+        if self.csr.vl == 0 {
+            return Some(());
+        }
+        let (bits, acc_bits) = (eew as u32 * 8, acc_eew as u32 * 8);
+        let mut acc = o.elem(o.vs1, 0, acc_eew);
+        for i in (0..self.csr.vl).filter(|i| o.active(*i)) {
+            let e = o.elem(o.vs2, i, eew);
+            let e = if op == VectorOp::Wredsum { sext(e, bits) & ones(acc_bits) } else { e };
+            acc = reduce(op, acc, e, acc_bits);
+        }
+        self.set_elem(o.vd, 0, acc_eew, acc);
+        self.tail(o.vd, 1, acc_eew, 1, o.vt.ta);
+        Some(())
+    }
+
+    /// The mask-register logical instructions.
+    fn mask_logical(&mut self, op: VectorOp, o: &Operands) -> Option<()> {
+        use VectorOp::*;
+        for i in self.body() {
+            let (a, b) = (o.bit(o.vs2, i), o.bit(o.vs1, i));
+            let value = match op {
+                Mandn => a && !b,
+                Mand => a && b,
+                Mor => a || b,
+                Mxor => a != b,
+                Morn => a || !b,
+                Mnand => !(a && b),
+                Mnor => !(a || b),
+                _ => a == b,
+            };
+            self.set_bit(o.vd, i, value);
+        }
+        self.mask_tail(o.vd);
+        Some(())
+    }
+
+    /// `vzext.vf<n>` and `vsext.vf<n>`: extends elements of 1/`factor` of
+    /// SEW.
+    fn extension(&mut self, factor: u64, signed: bool, o: &Operands) -> Option<()> {
+        let (eew, regs) = (o.vt.sew, o.vt.regs());
+        require(eew >= factor && o.vt.lmul8 >= factor)?;
+        let (src_eew, src_emul8) = (eew / factor, o.vt.lmul8 / factor);
+        let src_regs = group(src_emul8);
+        require(o.vd.is_multiple_of(regs) && o.vs2.is_multiple_of(src_regs) && (!o.masked || o.vd != 0))?;
+        require(may_overlap((o.vd, regs), eew, (o.vs2, src_regs), src_eew, src_emul8))?;
+        for i in self.body() {
+            if !o.active(i) {
+                self.inactive(o.vd, i, eew, o.vt.ma);
+                continue;
+            }
+            let value = o.elem(o.vs2, i, src_eew);
+            let value = if signed { sext(value, src_eew as u32 * 8) & ones(eew as u32 * 8) } else { value };
+            self.set_elem(o.vd, i, eew, value);
+        }
+        self.tail(o.vd, regs, eew, self.csr.vl, o.vt.ta);
+        Some(())
+    }
+
+    /// `vmsbf.m`, `vmsif.m` and `vmsof.m`: the mask before, up to and
+    /// including, or at just the first set bit of `vs2`.
+    fn set_first(&mut self, op: VectorOp, o: &Operands) -> Option<()> {
+        require(self.csr.vstart == 0 && o.vd != o.vs2 && (!o.masked || o.vd != 0))?;
+        let mut found = false;
+        for i in 0..self.csr.vl {
+            if !o.active(i) {
+                if o.vt.ma {
+                    self.set_bit(o.vd, i, true);
+                }
+                continue;
+            }
+            let first = !found && o.bit(o.vs2, i);
+            let value = match op {
+                VectorOp::Msbf => !found && !first,
+                VectorOp::Msif => !found,
+                _ => first,
+            };
+            found |= first;
+            self.set_bit(o.vd, i, value);
+        }
+        self.mask_tail(o.vd);
+        Some(())
+    }
+
+    /// `viota.m`, the count of set bits of `vs2` below each active
+    /// element, and `vid.v`, each element's index.
+    fn index(&mut self, op: VectorOp, o: &Operands) -> Option<()> {
+        let (eew, regs) = (o.vt.sew, o.vt.regs());
+        require(o.vd.is_multiple_of(regs) && (!o.masked || o.vd != 0))?;
+        let iota = op == VectorOp::Iota;
+        require(!iota || (self.csr.vstart == 0 && !overlaps((o.vd, regs), (o.vs2, 1))))?;
+        let mut count = 0;
+        for i in self.body() {
+            if !o.active(i) {
+                self.inactive(o.vd, i, eew, o.vt.ma);
+                continue;
+            }
+            self.set_elem(o.vd, i, eew, if iota { count } else { i });
+            count += (iota && o.bit(o.vs2, i)) as u64;
+        }
+        self.tail(o.vd, regs, eew, self.csr.vl, o.vt.ta);
+        Some(())
+    }
+
+    /// `vcompress.vm`: packs the elements of `vs2` whose bit in `vs1` is
+    /// set at the start of `vd`.
+    fn compress(&mut self, o: &Operands) -> Option<()> {
+        let (eew, regs) = (o.vt.sew, o.vt.regs());
+        require(self.csr.vstart == 0 && o.vd.is_multiple_of(regs) && o.vs2.is_multiple_of(regs))?;
+        require(!overlaps((o.vd, regs), (o.vs2, regs)) && !overlaps((o.vd, regs), (o.vs1, 1)))?;
+        let mut count = 0;
+        for i in (0..self.csr.vl).filter(|i| o.bit(o.vs1, *i)) {
+            self.set_elem(o.vd, count, eew, o.elem(o.vs2, i, eew));
+            count += 1;
+        }
+        self.tail(o.vd, regs, eew, count, o.vt.ta);
+        Some(())
+    }
+
+    /// The slides: by the scalar, or by one with the scalar shifted in.
+    fn slide(&mut self, op: VectorOp, o: &Operands) -> Option<()> {
+        use VectorOp::*;
+        let (eew, regs) = (o.vt.sew, o.vt.regs());
+        require(o.vd.is_multiple_of(regs) && o.vs2.is_multiple_of(regs) && (!o.masked || o.vd != 0))?;
+        let up = matches!(op, Slideup | Slide1up);
+        require(!up || !overlaps((o.vd, regs), (o.vs2, regs)))?;
+        let scalar = o.scalar.unwrap_or(0);
+        let vlmax = o.vt.vlmax(self.isa.vlen());
+        let start = if op == Slideup { self.csr.vstart.max(scalar) } else { self.csr.vstart };
+        for i in start..self.csr.vl {
+            if !o.active(i) {
+                self.inactive(o.vd, i, eew, o.vt.ma);
+                continue;
+            }
+            let value = match op {
+                Slideup => o.elem(o.vs2, i - scalar, eew),
+                Slide1up if i == 0 => scalar & ones(eew as u32 * 8),
+                Slide1up => o.elem(o.vs2, i - 1, eew),
+                Slidedown => match i.checked_add(scalar) {
+                    Some(j) if j < vlmax => o.elem(o.vs2, j, eew),
+                    _ => 0,
+                },
+                _ if i + 1 < self.csr.vl => o.elem(o.vs2, i + 1, eew),
+                _ => scalar & ones(eew as u32 * 8),
+            };
+            self.set_elem(o.vd, i, eew, value);
+        }
+        self.tail(o.vd, regs, eew, self.csr.vl, o.vt.ta);
+        Some(())
+    }
+
+    /// `vrgather` and `vrgatherei16`: element `vs1[i]` of `vs2`, or `0`
+    /// past VLMAX.
+    fn gather(&mut self, op: VectorOp, o: &Operands) -> Option<()> {
+        let (eew, regs) = (o.vt.sew, o.vt.regs());
+        let (index_eew, index_emul8) = match op {
+            VectorOp::Rgatherei16 => (2, o.vt.emul8(2)?),
+            _ => (eew, o.vt.lmul8),
+        };
+        let index_regs = group(index_emul8);
+        require(o.vd.is_multiple_of(regs) && o.vs2.is_multiple_of(regs) && (!o.masked || o.vd != 0))?;
+        require(!overlaps((o.vd, regs), (o.vs2, regs)))?;
+        require(o.scalar.is_some() || (o.vs1.is_multiple_of(index_regs) && !overlaps((o.vd, regs), (o.vs1, index_regs))))?;
+        let vlmax = o.vt.vlmax(self.isa.vlen());
+        for i in self.body() {
+            if !o.active(i) {
+                self.inactive(o.vd, i, eew, o.vt.ma);
+                continue;
+            }
+            let index = o.scalar.unwrap_or_else(|| o.elem(o.vs1, i, index_eew));
+            let value = if index < vlmax { o.elem(o.vs2, index, eew) } else { 0 };
+            self.set_elem(o.vd, i, eew, value);
+        }
+        self.tail(o.vd, regs, eew, self.csr.vl, o.vt.ta);
+        Some(())
+    }
+
+    /// `vmv<nr>r.v`: copies `regs` whole registers, whatever `vtype` is.
+    fn move_whole(&mut self, vd: usize, vs2: usize, regs: usize) -> Option<()> {
+        require(vd.is_multiple_of(regs) && vs2.is_multiple_of(regs))?;
+        let eew = self.vtype().map_or(1, |vt| vt.sew);
+        for i in self.csr.vstart..(regs * self.vlenb()) as u64 / eew {
+            let value = self.elem(vs2, i, eew);
+            self.set_elem(vd, i, eew, value);
+        }
+        Some(())
+    }
+}
+
+#[cfg(all(test, feature = "v"))]
+mod tests {
+    use super::*;
+    use crate::Cpu::Exception;
+    use crate::Memory::RAM_BASE;
+
+    const VADD_MASKED: u32 = 0x0086_0257; // vadd.vv v4, v8, v12, v0.t
+    const VLE32FF: u32 = 0x0305_6207; // vle32ff.v v4, (a0)
+    const VWADD_LOW: u32 = 0xC644_2257; // vwadd.vv v4, v4, v8
+    const VWADD_HIGH: u32 = 0xC654_2257; // vwadd.vv v4, v5, v8
+    const VREDSUM: u32 = 0x0286_2257; // vredsum.vs v4, v8, v12
+    const VRGATHER: u32 = 0x3286_0257; // vrgather.vv v4, v8, v12
+
+    const E32_M1_TU_MU: u32 = 0x010;
+    const E32_M1_TA_MU: u32 = 0x050;
+    const E32_M1_TU_MA: u32 = 0x090;
+
+    /// `vsetvli a0, a1, <vtype>`.
+    fn vsetvli(vtype: u32) -> u32 {
+        0x0005_F557 | vtype << 20
+    }
+
+    /// A hart with its vector unit on, VLEN 128, and a page of RAM.
+    fn machine() -> (Hart, Bus) {
+        let mut hart = Hart::new(0);
+        hart.csr.mstatus |= MSTATUS_VS;
+        (hart, Bus::new(4096))
+    }
+
+    fn run(hart: &mut Hart, bus: &mut Bus, raw: u32) -> Result<(), Trap> {
+        let inst = Decoded::decode(raw, &hart.isa).expect("vector instructions decode");
+        hart.execute(bus, &inst)
+    }
+
+    /// Sets `vl` to `avl` under `vtype`.
+    fn configure(hart: &mut Hart, bus: &mut Bus, vtype: u32, avl: u64) {
+        hart.x[11] = avl;
+        run(hart, bus, vsetvli(vtype)).unwrap();
+    }
+
+    fn load(hart: &mut Hart, reg: usize, eew: u64, values: &[u64]) {
+        for (index, &value) in values.iter().enumerate() {
+            hart.set_elem(reg, index as u64, eew, value);
+        }
+    }
+
+    fn elems(hart: &Hart, reg: usize, eew: u64, count: u64) -> Vec<u64> {
+        (0..count).map(|index| hart.elem(reg, index, eew)).collect()
+    }
+
+    #[test]
+    fn vsetvli_limits_vl_to_vlmax() {
+        let (mut hart, mut bus) = machine();
+        // e8/m1, e32/m2, e16/m8 and e8/mf2, all tail and mask agnostic.
+        for (vtype, vlmax) in [(0x0C0, 16), (0x0D1, 8), (0x0CB, 64), (0x0C7, 8)] {
+            configure(&mut hart, &mut bus, vtype, 1000);
+            assert_eq!((hart.csr.vl, hart.x[10], hart.csr.vtype), (vlmax, vlmax, vtype as u64));
+            configure(&mut hart, &mut bus, vtype, 5);
+            assert_eq!(hart.csr.vl, 5);
+        }
+        // e64 does not fit in half a register of a 64-bit ELEN.
+        configure(&mut hart, &mut bus, 0x0DF, 1000);
+        assert_eq!((hart.csr.vl, hart.csr.vtype), (0, VTYPE_VILL));
+        assert_eq!(run(&mut hart, &mut bus, VREDSUM), Err(Trap::illegal(VREDSUM)));
+    }
+
+    #[test]
+    fn masked_add_undisturbed_and_agnostic() {
+        let (mut hart, mut bus) = machine();
+        for (vtype, off, tail) in [
+            (E32_M1_TU_MU, 7, 7),
+            (E32_M1_TA_MU, 7, 0xFFFF_FFFF),
+            (E32_M1_TU_MA, 0xFFFF_FFFF, 7),
+        ] {
+            configure(&mut hart, &mut bus, vtype, 3);
+            load(&mut hart, 0, 1, &[0b0101]);
+            load(&mut hart, 4, 4, &[7, 7, 7, 7]);
+            load(&mut hart, 8, 4, &[1, 2, 3, 4]);
+            load(&mut hart, 12, 4, &[10, 20, 30, 40]);
+            run(&mut hart, &mut bus, VADD_MASKED).unwrap();
+            assert_eq!(elems(&hart, 4, 4, 4), [11, off, 33, tail], "vtype {:#x}", vtype);
+        }
+    }
+
+    #[test]
+    fn fault_only_first_loads() {
+        let (mut hart, mut bus) = machine();
+        let end = RAM_BASE + 4096;
+        for (index, value) in [5u64, 6].iter().enumerate() {
+            bus.store(end - 8 + index as u64 * 4, 4, *value);
+        }
+        // a fault on element 0 traps as any load would...
+        configure(&mut hart, &mut bus, E32_M1_TU_MU, 4);
+        hart.x[10] = end;
+        let trap = run(&mut hart, &mut bus, VLE32FF).unwrap_err();
+        assert_eq!((trap.exception, trap.tval), (Exception::LoadAccessFault, end));
+        assert_eq!((hart.csr.vl, hart.csr.vstart), (4, 0));
+        // ...but past it only trims `vl` to the elements that loaded.
+        hart.x[10] = end - 8;
+        run(&mut hart, &mut bus, VLE32FF).unwrap();
+        assert_eq!(hart.csr.vl, 2);
+        assert_eq!(elems(&hart, 4, 4, 2), [5, 6]);
+    }
+
+    #[test]
+    fn widening_source_overlap() {
+        let (mut hart, mut bus) = machine();
+        configure(&mut hart, &mut bus, E32_M1_TU_MU, 4);
+        // a narrow source may only overlap the high half of the wide
+        // destination.
+        assert_eq!(run(&mut hart, &mut bus, VWADD_LOW), Err(Trap::illegal(VWADD_LOW)));
+        load(&mut hart, 5, 4, &[1, 2, 0xFFFF_FFFF, 4]);
+        load(&mut hart, 8, 4, &[10, 20, 0xFFFF_FFFF, 40]);
+        run(&mut hart, &mut bus, VWADD_HIGH).unwrap();
+        assert_eq!(elems(&hart, 4, 8, 4), [11, 22, u64::MAX - 1, 44]);
+    }
+
+    #[test]
+    fn reduction_and_gather() {
+        let (mut hart, mut bus) = machine();
+        configure(&mut hart, &mut bus, E32_M1_TU_MU, 4);
+        load(&mut hart, 4, 4, &[0, 99, 99, 99]);
+        load(&mut hart, 8, 4, &[1, 2, 3, 0xFFFF_FFFF]);
+        load(&mut hart, 12, 4, &[100, 7, 7, 7]);
+        run(&mut hart, &mut bus, VREDSUM).unwrap();
+        // the scalar result wraps at SEW, and the rest of `vd` is tail.
+        assert_eq!(elems(&hart, 4, 4, 4), [105, 99, 99, 99]);
+
+        load(&mut hart, 12, 4, &[3, 0, 9, 1]);
+        run(&mut hart, &mut bus, VRGATHER).unwrap();
+        assert_eq!(elems(&hart, 4, 4, 4), [0xFFFF_FFFF, 1, 0, 2]);
+    }
+}
@@ -0,0 +1,212 @@
+#![allow(non_snake_case)]
+
+use std::path::{Path, PathBuf};
+
+use z_rv64imc_emu::Elf::ElfFile;
+use z_rv64imc_emu::Emulator::{Machine, StopReason};
+use z_rv64imc_emu::Isa::Isa;
+use z_rv64imc_emu::Memory::{Bus, DEFAULT_RAM_SIZE};
+
+const USAGE: &str = "\
+usage: rvtest [options] <dir|test.elf>...
+
+Runs riscv-tests ELFs and reports pass/fail for each. Directories are
+searched for the physical-memory tests of the suites below; files named
+directly are always run.
+
+    rv64ui-p-*  rv64um-p-*  rv64uc-p-*  rv64ua-p-*  rv64uf-p-*  rv64ud-p-*
+    rv64uzfh-p-*  rv64si-p-*  rv32ui-p-*  rv32um-p-*  rv32uc-p-*  rv32ua-p-*
+    rv32uf-p-*  rv32ud-p-*  rv32uzfh-p-*  rv32si-p-*
+
+options:
+    --signature <file>  write the riscv-arch-test signature (the memory
+                        between `begin_signature` and `end_signature`)
+                        of the single test being run to <file>
+    --signature-granularity <bytes>
+                        bytes per signature line: 4 (default), 8 or 16
+    --max-instructions <n>
+                        give up on a test after <n> instructions
+                        (default 10000000)
+    --isa <string>      base ISA and extensions the hart implements, e.g.
+                        `rv64imac` (default every extension this build
+                        has, at the XLEN of each test's ELF class)
+    --jit               run the tests translated to x86-64
+    --jit-check         run them translated and interpreted side by side,
+                        failing at the first difference";
+
+/// Prefixes of the riscv-tests suites picked up from a directory.
+const SUITES: [&str; 16] = [
+    "rv64ui-p-", "rv64um-p-", "rv64uc-p-", "rv64ua-p-", "rv64uf-p-", "rv64ud-p-", "rv64uzfh-p-", "rv64si-p-",
+    "rv32ui-p-", "rv32um-p-", "rv32uc-p-", "rv32ua-p-", "rv32uf-p-", "rv32ud-p-", "rv32uzfh-p-", "rv32si-p-",
+];
+
+const DEFAULT_LIMIT: u64 = 10_000_000;
+
+#[derive(Debug)]
+enum Outcome {
+    Pass,
+    /// `TESTNUM` of the check that failed, as written to `tohost`.
+    Fail(u64),
+    Timeout,
+    Error(String),
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("error: {}\n\n{}", message, USAGE);
+    std::process::exit(1);
+}
+
+/// Collects the tests in `dir` belonging to one of the `SUITES`, skipping
+/// the `.dump` files the riscv-tests build leaves next to them.
+fn collect_tests(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("could not read `{}`: {}", dir.display(), e))?;
+    let mut tests: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            path.is_file()
+                && path.extension().is_none()
+                && SUITES.iter().any(|suite| name.starts_with(suite))
+        })
+        .collect();
+    tests.sort();
+    Ok(tests)
+}
+
+/// Formats the signature region in the riscv-arch-test reference format:
+/// one `granularity` byte little-endian word per line, printed as
+/// lowercase hex with the most significant digit first.
+fn signature(bus: &Bus, elf: &ElfFile, granularity: usize) -> Result<String, String> {
+    let begin = elf.symbol("begin_signature").ok_or("test has no `begin_signature` symbol")?;
+    let end = elf.symbol("end_signature").ok_or("test has no `end_signature` symbol")?;
+    if end < begin {
+        return Err("`end_signature` comes before `begin_signature`".to_string());
+    }
+    let bytes = bus
+        .read_bytes(begin, (end - begin) as usize)
+        .ok_or("signature region is outside of RAM")?;
+    let mut out = String::new();
+    for word in bytes.chunks(granularity) {
+        for i in (0..granularity).rev() {
+            out.push_str(&format!("{:02x}", word.get(i).copied().unwrap_or(0)));
+        }
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// Runs one test on a fresh single-hart machine.
+fn run_test(path: &Path, isa: Option<Isa>, limit: u64, signature_out: Option<(&str, usize)>, jit: Option<bool>) -> Outcome {
+    let image = match std::fs::read(path) {
+        Ok(image) => image,
+        Err(e) => return Outcome::Error(e.to_string()),
+    };
+    let elf = match ElfFile::parse(&image) {
+        Ok(elf) => elf,
+        Err(e) => return Outcome::Error(e),
+    };
+    if elf.symbol("tohost").is_none() {
+        return Outcome::Error("no `tohost` symbol".to_string());
+    }
+    let mut machine = Machine::new(1, DEFAULT_RAM_SIZE);
+    machine.set_isa(isa.unwrap_or_else(|| Isa::default().with_xlen(elf.xlen)));
+    let name = path.display().to_string();
+    if let Err(e) = machine.load_program(&elf, vec![name]) {
+        return Outcome::Error(e);
+    }
+    if let Some(check) = jit {
+        if let Err(e) = machine.enable_jit(check) {
+            return Outcome::Error(e);
+        }
+    }
+    let outcome = match machine.run(Some(limit)) {
+        StopReason::Exit(0) => Outcome::Pass,
+        StopReason::Exit(code) => Outcome::Fail(code),
+        StopReason::InstructionLimit => Outcome::Timeout,
+        StopReason::JitMismatch => {
+            let report = machine.jit.as_ref().and_then(|jit| jit.mismatch.clone()).unwrap_or_default();
+            return Outcome::Error(report.trim_end().to_string());
+        }
+    };
+    if let Some((file, granularity)) = signature_out {
+        let written = signature(&machine.bus, &elf, granularity)
+            .and_then(|sig| std::fs::write(file, sig).map_err(|e| format!("could not write `{}`: {}", file, e)));
+        if let Err(e) = written {
+            return Outcome::Error(e);
+        }
+    }
+    outcome
+}
+
+fn main() {
+    let mut signature_file: Option<String> = None;
+    let mut granularity: usize = 4;
+    let mut limit = DEFAULT_LIMIT;
+    let mut jit: Option<bool> = None;
+    let mut isa: Option<Isa> = None;
+    let mut inputs: Vec<PathBuf> = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| fail(&format!("`{}` needs a value", arg)));
+        match arg.as_str() {
+            "--signature" => signature_file = Some(value()),
+            "--signature-granularity" => {
+                granularity = value().parse().unwrap_or(0);
+                if ![4, 8, 16].contains(&granularity) {
+                    fail("signature granularity must be 4, 8 or 16");
+                }
+            }
+            "--max-instructions" => limit = value().parse().unwrap_or_else(|_| fail("bad instruction count")),
+            "--isa" => isa = Some(Isa::parse(&value()).unwrap_or_else(|e| fail(&e))),
+            "--jit" => jit = Some(false),
+            "--jit-check" => jit = Some(true),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if arg.starts_with("--") => fail(&format!("unknown argument `{}`", arg)),
+            _ => inputs.push(PathBuf::from(arg)),
+        }
+    }
+
+    let mut tests: Vec<PathBuf> = Vec::new();
+    for input in inputs {
+        if input.is_dir() {
+            tests.extend(collect_tests(&input).unwrap_or_else(|e| fail(&e)));
+        } else {
+            tests.push(input);
+        }
+    }
+    if tests.is_empty() {
+        fail("no tests to run");
+    }
+    if signature_file.is_some() && tests.len() != 1 {
+        fail("`--signature` needs exactly one test");
+    }
+
+    let width = tests
+        .iter()
+        .map(|t| t.file_name().map_or(0, |n| n.len()))
+        .max()
+        .unwrap_or(0);
+    let mut failed = 0;
+    for test in tests.iter() {
+        let name = test.file_name().map_or(test.display().to_string(), |n| n.to_string_lossy().into_owned());
+        let outcome = run_test(test, isa, limit, signature_file.as_deref().map(|f| (f, granularity)), jit);
+        let result = match &outcome {
+            Outcome::Pass => "PASS".to_string(),
+            Outcome::Fail(testnum) => format!("FAIL (test #{})", testnum),
+            Outcome::Timeout => format!("TIMEOUT (after {} instructions)", limit),
+            Outcome::Error(e) => format!("ERROR ({})", e),
+        };
+        if !matches!(outcome, Outcome::Pass) {
+            failed += 1;
+        }
+        println!("{:<width$}  {}", name, result, width = width);
+    }
+    println!("\n{} passed, {} failed", tests.len() - failed, failed);
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
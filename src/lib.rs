@@ -0,0 +1,1652 @@
+#![allow(non_snake_case, dead_code, unused_variables, non_camel_case_types)]
+
+pub mod BlockCache;
+pub mod Clint;
+pub mod Cosim;
+pub mod Coverage;
+pub mod Cpu;
+pub mod Crypto;
+pub mod Csr;
+pub mod Decode;
+pub mod DeviceTree;
+pub mod Disassembler;
+pub mod Dwarf;
+pub mod Elf;
+pub mod Emulator;
+pub mod Execute;
+pub mod Gdb;
+pub mod Htif;
+pub mod Hypervisor;
+pub mod Isa;
+pub mod Jit;
+pub mod LinuxBoot;
+pub mod Memory;
+pub mod Monitor;
+pub mod Profile;
+pub mod Replay;
+pub mod Reservation;
+pub mod Sbi;
+pub mod Snapshot;
+#[cfg(feature = "f")]
+pub mod SoftFloat;
+pub mod Stats;
+pub mod Trace;
+pub mod Vector;
+
+use crate::InstructionDecoder::{
+    Generic_Op,
+    R_Type, 
+    I_Type, 
+    S_Type, 
+    B_Type,
+    U_Type,
+    J_Type
+};
+
+/// index  | register | ABI   | Use                                 | preserved
+///   0x0  |   x0     | zero  | hardwired to `0`                    |    n/a
+///   0x1  |   x1     |  ra   | return address for jumps            |    no
+///   0x2  |   x2     |  sp   | stack pointer                       |    yes
+///   0x3  |   x3     |  gp   | global pointer                      |    n/a
+///   0x4  |   x4     |  tp   | thread pointer                      |    n/a
+///   0x5  |   x5     |  t0   | temporary register                  |    no
+///   0x6  |   x6     |  t1   | temporary register                  |    no
+///   0x7  |   x7     |  t2   | temperary register                  |    no
+///   0x8  |   x8     | s0/fp | saved register 0 / frame pointer    |    yes
+///   0x9  |   x9     |  s1   | saved register 1                    |    yes
+///   0xa  |   x10    |  a0   | return value or function argument 0 |    no
+///   0xb  |   x11    |  a1   | return value or function argument 1 |    no
+///   0xc  |   x12    |  a2   | function argument 2                 |    no
+///   0xd  |   x13    |  a3   | function argument 3                 |    no
+///   0xe  |   x14    |  a4   | function argument 4                 |    no
+///   0xf  |   x15    |  a5   | function argument 5                 |    no
+///  0x10  |   x16    |  a6   | function argument 6                 |    no
+///  0x11  |   x17    |  a7   | function argument 7                 |    no
+///  0x12  |   x18    |  s2   | saved register 2                    |    yes
+///  0x13  |   x19    |  s3   | saved register 3                    |    yes
+///  0x14  |   x20    |  s4   | saved register 4                    |    yes
+///  0x15  |   x21    |  s5   | saved register 5                    |    yes
+///  0x16  |   x22    |  s6   | saved register 6                    |    yes
+///  0x17  |   x23    |  s7   | saved register 7                    |    yes
+///  0x18  |   x24    |  s8   | saved register 8                    |    yes
+///  0x19  |   x25    |  s9   | saved register 9                    |    yes
+///  0x1a  |   x26    |  s10  | saved register 10                   |    yes
+///  0x1b  |   x27    |  s11  | saved register 11                   |    yes
+///  0x1c  |   x28    |  t3   | temporary register 3                |    no
+///  0x1d  |   x29    |  t4   | temporary register 4                |    no
+///  0x1e  |   x30    |  t5   | temporary register 5                |    no
+///  0x1f  |   x31    |  t6   | temporary register 6                |    no
+///  0x20? |   pc     |       | program counter                     |    n/a
+
+#[allow(
+    clippy::needless_return,
+    clippy::redundant_field_names,
+    clippy::identity_op,
+    clippy::let_and_return,
+    clippy::should_implement_trait,
+    clippy::empty_line_after_doc_comments
+)]
+pub mod InstructionDecoder {
+    use crate::Crypto::CryptoOp;
+    use crate::Hypervisor::HypervisorAccess;
+    use crate::Isa::Isa;
+    use crate::Vector::{VectorAccess, VectorOp};
+
+    #[derive(Debug)]
+    pub struct Generic_Op {
+        pub opcode: usize,
+        pub raw: usize,
+    }
+
+    impl Generic_Op {
+        pub fn new(data: usize) -> Self {
+            Self {
+                opcode: data & 0x7F,
+                raw: data,
+            }
+        }
+
+        pub fn default() -> Self {
+            Self { opcode: 0, raw: 0 }
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct R_Type {
+        pub funct7: usize,
+        pub rs3: usize,
+        pub funct2: usize,
+        pub rs2: usize,
+        pub rs1: usize,
+        pub funct3: usize,
+        pub rd: usize,
+        pub opcode: usize,
+        pub raw: usize,
+    }
+
+    impl R_Type {
+        pub fn new(raw: usize) -> Self {
+            Self {
+                funct7: (raw & (0x7F << 25)) >> 25,
+                rs3: (raw & (0b11111 << 27)) >> 27,
+                funct2: (raw & (0b11 << 25)) >> 25,
+                rs2: (raw & (0x1F << 20)) >> 20,
+                rs1: (raw & (0x1F << 15)) >> 15,
+                funct3: (raw & (0x07 << 12)) >> 12,
+                rd: (raw & (0x1F << 7)) >> 7,
+                opcode: (raw & (0x7F << 0)) >> 0,
+                raw: raw,
+            }
+        }
+
+        pub fn new_from_generic(op: Generic_Op) -> Self {
+            let raw = op.raw;
+            Self {
+                funct7: (raw & (0x7F << 25)) >> 25,
+                rs3: (raw & (0b11111 << 27)) >> 27,
+                funct2: (raw & (0b11 << 25)) >> 25,
+                rs2: (raw & (0x1F << 20)) >> 20,
+                rs1: (raw & (0x1F << 15)) >> 15,
+                funct3: (raw & (0x07 << 12)) >> 12,
+                rd: (raw & (0x1F << 7)) >> 7,
+                opcode: (raw & (0x7F << 0)) >> 0,
+                raw: raw,
+            }
+        }
+
+        pub fn default() -> Self {
+            Self {
+                funct7: 0,
+                rs3: 0,
+                funct2: 0,
+                rs2: 0,
+                rs1: 0,
+                funct3: 0,
+                rd: 0,
+                opcode: 0,
+                raw: 0,
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct I_Type {
+        pub parsed_imm: usize,
+        pub imm_110: usize,
+        pub rs1: usize,
+        pub funct3: usize,
+        pub rd: usize,
+        pub opcode: usize,
+        pub raw: usize,
+    }
+
+    impl I_Type {
+        pub fn new(raw: usize) -> Self {
+            let mut tmp = Self {
+                parsed_imm: 0,
+                imm_110: (raw & (0x0FFF << 20)) >> 20,
+                rs1: (raw & (0x1F << 15)) >> 15,
+                funct3: (raw & (0x07 << 12)) >> 12,
+                rd: (raw & (0x1F << 7)) >> 7,
+                opcode: (raw & (0x7F << 0)) >> 0,
+                raw: raw,
+            };
+            tmp.parsed_imm = tmp.imm_110;
+            let out = tmp;
+            out
+        }
+
+        pub fn new_from_generic(op: Generic_Op) -> Self {
+            let raw = op.raw;
+            let mut tmp = Self {
+                parsed_imm: 0,
+                imm_110: (raw & (0x0FFF << 20)) >> 20,
+                rs1: (raw & (0x1F << 15)) >> 15,
+                funct3: (raw & (0x07 << 12)) >> 12,
+                rd: (raw & (0x1F << 7)) >> 7,
+                opcode: (raw & (0x7F << 0)) >> 0,
+                raw: raw,
+            };
+            tmp.parsed_imm = tmp.imm_110;
+            let out = tmp;
+            out
+        }
+
+        pub fn default() -> Self {
+            Self {
+                parsed_imm: 0,
+                imm_110: 0,
+                rs1: 0,
+                funct3: 0,
+                rd: 0,
+                opcode: 0,
+                raw: 0,
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct S_Type {
+        pub parsed_imm: usize,
+        pub imm_115: usize,
+        pub rs2: usize,
+        pub rs1: usize,
+        pub funct3: usize,
+        pub imm_40: usize,
+        pub opcode: usize,
+        pub raw: usize,
+    }
+
+    impl S_Type {
+        pub fn new(raw: usize) -> Self {
+            let mut tmp = Self {
+                parsed_imm: 0,
+                imm_115: (raw & (0x7F << 25)) >> 25,
+                rs2: (raw & (0x1F << 20)) >> 20,
+                rs1: (raw & (0x1F << 15)) >> 15,
+                funct3: (raw & (0x07 << 12)) >> 12,
+                imm_40: (raw & (0x1F) << 7) >> 7,
+                opcode: (raw & (0x7F << 0)) >> 0,
+                raw: raw,
+            };
+            tmp.parsed_imm = (tmp.imm_115 << 5) | tmp.imm_40;
+            let out = tmp;
+            out
+        }
+
+        pub fn new_from_generic(op: Generic_Op) -> Self {
+            let raw = op.raw;
+            let mut tmp = Self {
+                parsed_imm: 0,
+                imm_115: (raw & (0x7F << 25)) >> 25,
+                rs2: (raw & (0x1F << 20)) >> 20,
+                rs1: (raw & (0x1F << 15)) >> 15,
+                funct3: (raw & (0x07 << 12)) >> 12,
+                imm_40: (raw & (0x1F) << 7) >> 7,
+                opcode: (raw & (0x7F << 0)) >> 0,
+                raw: raw,
+            };
+            tmp.parsed_imm = (tmp.imm_115 << 7) | tmp.imm_40;
+            let out = tmp;
+            out
+        }
+
+        pub fn default() -> Self {
+            Self {
+                parsed_imm: 0,
+                imm_115: 0,
+                rs2: 0,
+                rs1: 0,
+                funct3: 0,
+                imm_40: 0,
+                opcode: 0,
+                raw: 0,
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct B_Type {
+        pub parsed_imm: usize,
+        pub imm_12_105: usize,
+        pub rs2: usize,
+        pub rs1: usize,
+        pub funct3: usize,
+        pub imm_41_11: usize,
+        pub opcode: usize,
+        pub raw: usize,
+    }
+
+    impl B_Type {
+        pub fn new(raw: usize) -> Self {
+            let mut tmp = Self {
+                parsed_imm: 0,
+                imm_12_105: (raw & (0x7F << 25)) >> 25,
+                rs2: (raw & (0x1F << 20)) >> 20,
+                rs1: (raw & (0x1F << 15)) >> 15,
+                funct3: (raw & (0x07 << 12)) >> 12,
+                imm_41_11: (raw & (0x1F << 7)) >> 7,
+                opcode: (raw & (0x7F << 0)) >> 0,
+                raw: raw,
+            };
+            tmp.parsed_imm = (((tmp.imm_12_105 & 0x40) >> 6) << 12)
+                | (((tmp.imm_41_11 & 0x01) >> 0) << 11)
+                | (((tmp.imm_12_105 & 0x3F) >> 0) << 5)
+                | (((tmp.imm_41_11 & 0x1E) >> 1) << 1);
+            let out = tmp;
+            out
+        }
+
+        pub fn new_from_generic(op: Generic_Op) -> Self {
+            let raw = op.raw;
+            let mut tmp = Self {
+                parsed_imm: 0,
+                imm_12_105: (raw & (0x7F << 25)) >> 25,
+                rs2: (raw & (0x1F << 20)) >> 20,
+                rs1: (raw & (0x1F << 15)) >> 15,
+                funct3: (raw & (0x07 << 12)) >> 12,
+                imm_41_11: (raw & (0x1F << 7)) >> 7,
+                opcode: (raw & (0x7F << 0)) >> 0,
+                raw: raw,
+            };
+            tmp.parsed_imm = (((tmp.imm_12_105 & 0x40) >> 6) << 12)
+                | (((tmp.imm_41_11 & 0x01) >> 0) << 11)
+                | (((tmp.imm_12_105 & 0x3F) >> 0) << 5)
+                | (((tmp.imm_41_11 & 0x1E) >> 1) << 1);
+            let out = tmp;
+            out
+        }
+
+        pub fn default() -> Self {
+            Self {
+                parsed_imm: 0,
+                imm_12_105: 0,
+                rs2: 0,
+                rs1: 0,
+                funct3: 0,
+                imm_41_11: 0,
+                opcode: 0,
+                raw: 0,
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct U_Type {
+        pub parsed_imm: usize,
+        pub imm_3112: usize,
+        pub rd: usize,
+        pub opcode: usize,
+        pub raw: usize,
+    }
+
+    impl U_Type {
+        pub fn new(raw: usize) -> Self {
+            Self {
+                parsed_imm: (raw & (0x0FFFFF << 12)) >> 12,
+                imm_3112: (raw & (0x0FFFFF << 12)) >> 12,
+                rd: (raw & (0x1F << 7)) >> 7,
+                opcode: (raw & (0x7F << 0)) >> 0,
+                raw: raw,
+            }
+        }
+
+        pub fn new_from_generic(op: Generic_Op) -> Self {
+            let raw = op.raw;
+            Self {
+                parsed_imm: (raw & (0x0FFFFF << 12)) >> 12,
+                imm_3112: (raw & (0x0FFFFF << 12)) >> 12,
+                rd: (raw & (0x1F << 7)) >> 7,
+                opcode: (raw & (0x7F << 0)) >> 0,
+                raw: raw,
+            }
+        }
+
+        pub fn default() -> Self {
+            Self {
+                parsed_imm: 0,
+                imm_3112: 0,
+                rd: 0,
+                opcode: 0,
+                raw: 0,
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct J_Type {
+        pub parsed_imm: usize,
+        pub imm_20_101_11_1912: usize,
+        pub rd: usize,
+        pub opcode: usize,
+        pub raw: usize,
+    }
+
+    impl J_Type {
+        pub fn new(raw: usize) -> Self {
+            let mut tmp = Self {
+                parsed_imm: 0,
+                imm_20_101_11_1912: (raw & (0x0FFFFF << 12)) >> 12,
+                rd: (raw & (0x1F << 7)) >> 7,
+                opcode: (raw & (0x7F << 0)) >> 0,
+                raw: raw,
+            };
+            // parse the immediate.
+            tmp.parsed_imm = ((tmp.imm_20_101_11_1912 & 0x080000) >> 19) << 20
+                | ((tmp.imm_20_101_11_1912 & 0xFF) >> 0) << 12
+                | ((tmp.imm_20_101_11_1912 & 0x0100) >> 8) << 11
+                | ((tmp.imm_20_101_11_1912 & 0x07FE00) >> 9) << 1;
+            let out = tmp;
+            out
+        }
+        pub fn new_from_generic(op: Generic_Op) -> Self {
+            let raw = op.raw;
+            let mut tmp = Self {
+                parsed_imm: 0,
+                imm_20_101_11_1912: (raw & (0xFFFFF << 12)) >> 12,
+                rd: (raw & (0x1F << 7)) >> 7,
+                opcode: (raw & (0x7F << 0)) >> 0,
+                raw: raw,
+            };
+            // parse the immediate.
+            tmp.parsed_imm = ((tmp.imm_20_101_11_1912 & 0x080000) >> 19) << 20
+                | ((tmp.imm_20_101_11_1912 & 0xFF) >> 0) << 12
+                | ((tmp.imm_20_101_11_1912 & 0x0100) >> 8) << 11
+                | ((tmp.imm_20_101_11_1912 & 0x07FE00) >> 9) << 1;
+            let out = tmp;
+            out
+        }
+        pub fn default() -> Self {
+            Self {
+                parsed_imm: 0,
+                imm_20_101_11_1912: 0,
+                rd: 0,
+                opcode: 0,
+                raw: 0,
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub enum Instruction {
+        // RV32I Base Instruction Set
+        LUI(U_Type),
+        AUIPC(U_Type),
+        JAL(J_Type),
+        JALR(I_Type),
+        BEQ(B_Type),
+        BNE(B_Type),
+        BLT(B_Type),
+        BGE(B_Type),
+        BLTU(B_Type),
+        BGEU(B_Type),
+        LB(I_Type),
+        LH(I_Type),
+        LW(I_Type),
+        LBU(I_Type),
+        LHU(I_Type),
+        LWU(I_Type),
+        LD(I_Type),
+        SB(S_Type),
+        SH(S_Type),
+        SW(S_Type),
+        SD(S_Type),
+        ADDI(I_Type),
+        SLTI(I_Type),
+        SLTIU(I_Type),
+        XORI(I_Type),
+        ORI(I_Type),
+        ANDI(I_Type),
+        SLLI(R_Type),
+        SRLI(R_Type),
+        SRAI(R_Type),
+        ADD(R_Type),
+        SUB(R_Type),
+        SLL(R_Type),
+        SLT(R_Type),
+        SLTU(R_Type),
+        XOR(R_Type),
+        SRL(R_Type),
+        SRA(R_Type),
+        OR(R_Type),
+        AND(R_Type),
+        FENCE(I_Type),
+        ECALL(I_Type),
+        EBREAK(I_Type),
+        // Privileged Instructions
+        MRET(I_Type),
+        SRET(I_Type),
+        WFI(I_Type),
+        SFENCEVMA(R_Type),
+        // RV64I Base Instruction Set
+        ADDIW(I_Type),
+        SLLIW(R_Type),
+        SRLIW(R_Type),
+        SRAIW(R_Type),
+        ADDW(R_Type),
+        SUBW(R_Type),
+        SLLW(R_Type),
+        SRLW(R_Type),
+        SRAW(R_Type),
+        // RV32/RV64 Zifencei Extension
+        FENCEI(I_Type),
+        // RV32/RV64 Zicsr Extension
+        CSRRW(I_Type),
+        CSRRS(I_Type),
+        CSRRC(I_Type),
+        CSRRWI(I_Type),
+        CSRRSI(I_Type),
+        CSRRCI(I_Type),
+        // RV32M Extension
+        MUL(R_Type),
+        MULH(R_Type),
+        MULHSU(R_Type),
+        MULHU(R_Type),
+        DIV(R_Type),
+        DIVU(R_Type),
+        REM(R_Type),
+        REMU(R_Type),
+        // RV64M Extension
+        MULW(R_Type),
+        DIVW(R_Type),
+        DIVUW(R_Type),
+        REMW(R_Type),
+        REMUW(R_Type),
+        // RV32A Extension
+        LRW(R_Type),
+        SCW(R_Type),
+        AMOSWAPW(R_Type),
+        AMOADDW(R_Type),
+        AMOXORW(R_Type),
+        AMOANDW(R_Type),
+        AMOORW(R_Type),
+        AMOMINW(R_Type),
+        AMOMAXW(R_Type),
+        AMOMINUW(R_Type),
+        AMOMAXUW(R_Type),
+        // RV64A Extension
+        LRD(R_Type),
+        SCD(R_Type),
+        AMOSWAPD(R_Type),
+        AMOADDD(R_Type),
+        AMOXORD(R_Type),
+        AMOANDD(R_Type),
+        AMOORD(R_Type),
+        AMOMIND(R_Type),
+        AMOMAXD(R_Type),
+        AMOMINUD(R_Type),
+        AMOMAXUD(R_Type),
+        // RV32F Extension
+        FLW(I_Type),
+        FSW(S_Type),
+        FMADDS(R_Type),
+        FMSUBS(R_Type),
+        FNMSUBS(R_Type),
+        FNMADDS(R_Type),
+        FADDS(R_Type),
+        FSUBS(R_Type),
+        FMULS(R_Type),
+        FDIVS(R_Type),
+        FSQRTS(R_Type),
+        FSGNJS(R_Type),
+        FSGNJNS(R_Type),
+        FSGNJXS(R_Type),
+        FMINS(R_Type),
+        FMAXS(R_Type),
+        FCVTWS(R_Type),
+        FCVTWUS(R_Type),
+        FMVXW(R_Type),
+        FEQS(R_Type),
+        FLTS(R_Type),
+        FLES(R_Type),
+        FCLASSS(R_Type),
+        FCVTSW(R_Type),
+        FCVTSWU(R_Type),
+        FMVWX(R_Type),
+        // RV64F Extension
+        FCVTLS(R_Type),
+        FCVTLUS(R_Type),
+        FCVTSL(R_Type),
+        FCVTSLU(R_Type),
+        FLD(R_Type),
+        // RV32D Extension
+        FSD(R_Type),
+        FMADDD(R_Type),
+        FMSUBD(R_Type),
+        FNMSUBD(R_Type),
+        FNMADDD(R_Type),
+        FADDD(R_Type),
+        FSUBD(R_Type),
+        FMULD(R_Type),
+        FDIVD(R_Type),
+        FSQRTD(R_Type),
+        FSGNJD(R_Type),
+        FSGNJND(R_Type),
+        FSGNJXD(R_Type),
+        FMIND(R_Type),
+        FMAXD(R_Type),
+        FCVTSD(R_Type),
+        FCVTDS(R_Type),
+        FEQD(R_Type),
+        FLTD(R_Type),
+        FLED(R_Type),
+        FCLASSD(R_Type),
+        FCVTWD(R_Type),
+        FCVTWUD(R_Type),
+        FCVTDW(R_Type),
+        FCVTDWU(R_Type),
+        // RV64D Extension
+        FCVTLD(R_Type),
+        FCVTLUD(R_Type),
+        FMVXD(R_Type),
+        FCVTDL(R_Type),
+        FCVTDLU(R_Type),
+        FMVDX(R_Type),
+        // RV32Zfh Extension
+        FLH(I_Type),
+        FSH(S_Type),
+        FMADDH(R_Type),
+        FMSUBH(R_Type),
+        FNMSUBH(R_Type),
+        FNMADDH(R_Type),
+        FADDH(R_Type),
+        FSUBH(R_Type),
+        FMULH(R_Type),
+        FDIVH(R_Type),
+        FSQRTH(R_Type),
+        FSGNJH(R_Type),
+        FSGNJNH(R_Type),
+        FSGNJXH(R_Type),
+        FMINH(R_Type),
+        FMAXH(R_Type),
+        FCVTSH(R_Type),
+        FCVTHS(R_Type),
+        FCVTDH(R_Type),
+        FCVTHD(R_Type),
+        FEQH(R_Type),
+        FLTH(R_Type),
+        FLEH(R_Type),
+        FCLASSH(R_Type),
+        FCVTWH(R_Type),
+        FCVTWUH(R_Type),
+        FCVTHW(R_Type),
+        FCVTHWU(R_Type),
+        FMVXH(R_Type),
+        FMVHX(R_Type),
+        // RV64Zfh Extension
+        FCVTLH(R_Type),
+        FCVTLUH(R_Type),
+        FCVTHL(R_Type),
+        FCVTHLU(R_Type),
+        // RV32/RV64 Zba Extension
+        SH1ADD(R_Type),
+        SH2ADD(R_Type),
+        SH3ADD(R_Type),
+        // RV64 Zba Extension
+        ADDUW(R_Type),
+        SH1ADDUW(R_Type),
+        SH2ADDUW(R_Type),
+        SH3ADDUW(R_Type),
+        SLLIUW(R_Type),
+        // RV32/RV64 Zbb Extension
+        ANDN(R_Type),
+        ORN(R_Type),
+        XNOR(R_Type),
+        CLZ(R_Type),
+        CTZ(R_Type),
+        CPOP(R_Type),
+        MAX(R_Type),
+        MAXU(R_Type),
+        MIN(R_Type),
+        MINU(R_Type),
+        SEXTB(R_Type),
+        SEXTH(R_Type),
+        ZEXTH(R_Type),
+        ROL(R_Type),
+        ROR(R_Type),
+        RORI(R_Type),
+        ORCB(R_Type),
+        REV8(R_Type),
+        // RV64 Zbb Extension
+        CLZW(R_Type),
+        CTZW(R_Type),
+        CPOPW(R_Type),
+        ROLW(R_Type),
+        RORW(R_Type),
+        RORIW(R_Type),
+        // RV32/RV64 Zbc Extension
+        CLMUL(R_Type),
+        CLMULH(R_Type),
+        CLMULR(R_Type),
+        // RV32/RV64 Zbs Extension
+        BCLR(R_Type),
+        BCLRI(R_Type),
+        BEXT(R_Type),
+        BEXTI(R_Type),
+        BINV(R_Type),
+        BINVI(R_Type),
+        BSET(R_Type),
+        BSETI(R_Type),
+        // RV32/RV64 Zbkb Extension
+        PACK(R_Type),
+        PACKH(R_Type),
+        BREV8(R_Type),
+        // RV32 Zbkb Extension
+        ZIP(R_Type),
+        UNZIP(R_Type),
+        // RV64 Zbkb Extension
+        PACKW(R_Type),
+        // `Zknd`, `Zkne`, `Zknh`, `Zksed` and `Zksh`; `Crypto` tells them
+        // apart
+        CRYPTO(R_Type),
+        // `V` Extension; `Vector` tells the loads, stores and arithmetic
+        // apart
+        VSETVLI(I_Type),
+        VSETIVLI(I_Type),
+        VSETVL(R_Type),
+        VLOAD(R_Type),
+        VSTORE(R_Type),
+        VOP(R_Type),
+        // `H` Extension; `Hypervisor` tells the loads and stores apart
+        HFENCEVVMA(R_Type),
+        HFENCEGVMA(R_Type),
+        HLV(R_Type),
+        HSV(R_Type),
+        // `C` Extension instructions are expanded into the instructions
+        // above by `expand_compressed`.
+    }
+
+    impl Instruction {
+        /// Decodes `inst`, `None` for anything not a valid instruction of
+        /// an extension `isa` enables.
+        pub fn parse_instruction(inst: usize, isa: &Isa) -> Option<Self> {
+            if !isa.allows(inst as u32) {
+                return None;
+            }
+            let R_TYPE: R_Type = R_Type::new(inst);
+            let I_TYPE: I_Type = I_Type::new(inst);
+            let S_TYPE: S_Type = S_Type::new(inst);
+            let B_TYPE: B_Type = B_Type::new(inst);
+            let U_TYPE: U_Type = U_Type::new(inst);
+            let J_TYPE: J_Type = J_Type::new(inst);
+
+            let opcode = R_TYPE.opcode;
+            let funct7 = R_TYPE.funct7;
+            let funct6 = (funct7 & 0b1111110) >> 1;
+            let funct3 = R_TYPE.funct3;
+            let imm110 = I_TYPE.imm_110;
+            let funct5 = (funct7 & 0b1111100) >> 2;
+            let rs2    = R_TYPE.rs2;
+            let funct2 = funct7 & 0b0000011;
+            let rs3    = funct5;
+            if (opcode & 0b11) != 0b11 {
+                // opcode is a compressed opcode, decode the 32 bit
+                // instruction it stands for instead.
+                return Self::parse_instruction(expand_compressed(inst & 0xFFFF, isa.xlen())?, isa);
+            }
+
+            match (imm110, funct6, funct7, funct3, opcode) {
+                // `RV32I` Base Instructions
+                (            _,       _,         _,     _, 0b0110111) => {
+                    return Some(Instruction::LUI(U_TYPE));
+                },
+                (            _,       _,         _,     _, 0b0010111) => {
+                    return Some(Instruction::AUIPC(U_TYPE));
+                },
+                (            _,       _,         _,     _, 0b1101111) => {
+                    return Some(Instruction::JAL(J_TYPE));
+                },
+                (            _,       _,         _, 0b000, 0b1100111) => {
+                    return Some(Instruction::JALR(I_TYPE));
+                },
+                (            _,       _,         _, 0b000, 0b1100011) => {
+                    return Some(Instruction::BEQ(B_TYPE));
+                },
+                (            _,       _,         _, 0b001, 0b1100011) => {
+                    return Some(Instruction::BNE(B_TYPE));
+                },
+                (            _,       _,         _, 0b100, 0b1100011) => {
+                    return Some(Instruction::BLT(B_TYPE));
+                },
+                (            _,       _,         _, 0b101, 0b1100011) => {
+                    return Some(Instruction::BGE(B_TYPE));
+                },
+                (            _,       _,         _, 0b110, 0b1100011) => {
+                    return Some(Instruction::BLTU(B_TYPE));
+                },
+                (            _,       _,         _, 0b111, 0b1100011) => {
+                    return Some(Instruction::BGEU(B_TYPE));
+                },
+                (            _,       _,         _, 0b000, 0b0000011) => {
+                    return Some(Instruction::LB(I_TYPE));
+                },
+                (            _,       _,         _, 0b001, 0b0000011) => {
+                    return Some(Instruction::LH(I_TYPE));
+                },
+                (            _,       _,         _, 0b010, 0b0000011) => {
+                    return Some(Instruction::LW(I_TYPE));
+                },
+                (            _,       _,         _, 0b100, 0b0000011) => {
+                    return Some(Instruction::LBU(I_TYPE));
+                },
+                (            _,       _,         _, 0b101, 0b0000011) => {
+                    return Some(Instruction::LHU(I_TYPE));
+                },
+                (            _,       _,         _, 0b000, 0b0100011) => {
+                    return Some(Instruction::SB(S_TYPE));
+                },
+                (            _,       _,         _, 0b001, 0b0100011) => {
+                    return Some(Instruction::SH(S_TYPE));
+                },
+                (            _,       _,         _, 0b010, 0b0100011) => {
+                    return Some(Instruction::SW(S_TYPE));
+                },
+                (            _,       _,         _, 0b000, 0b0010011) => {
+                    return Some(Instruction::ADDI(I_TYPE));
+                },
+                (            _,       _,         _, 0b010, 0b0010011) => {
+                    return Some(Instruction::SLTI(I_TYPE));
+                },
+                (            _,       _,         _, 0b011, 0b0010011) => {
+                    return Some(Instruction::SLTIU(I_TYPE));
+                },
+                (            _,       _,         _, 0b100, 0b0010011) => {
+                    return Some(Instruction::XORI(I_TYPE));
+                },
+                (            _,       _,         _, 0b110, 0b0010011) => {
+                    return Some(Instruction::ORI(I_TYPE));
+                },
+                (            _,       _,         _, 0b111, 0b0010011) => {
+                    return Some(Instruction::ANDI(I_TYPE));
+                },
+                (            _, 0b000000,        _, 0b001, 0b0010011) => {
+                    return Some(Instruction::SLLI(R_TYPE));
+                },
+                (            _, 0b000000,        _, 0b101, 0b0010011) => {
+                    return Some(Instruction::SRLI(R_TYPE));
+                },
+                (            _, 0b010000,        _, 0b101, 0b0010011) => {
+                    return Some(Instruction::SRAI(R_TYPE));
+                },
+                (            _,       _, 0b0000000, 0b000, 0b0110011) => {
+                    return Some(Instruction::ADD(R_TYPE));
+                },
+                (            _,       _, 0b0100000, 0b000, 0b0110011) => {
+                    return Some(Instruction::SUB(R_TYPE));
+                },
+                (            _,       _, 0b0000000, 0b001, 0b0110011) => {
+                    return Some(Instruction::SLL(R_TYPE));
+                },
+                (            _,       _, 0b0000000, 0b010, 0b0110011) => {
+                    return Some(Instruction::SLT(R_TYPE));
+                },
+                (            _,       _, 0b0000000, 0b011, 0b0110011) => {
+                    return Some(Instruction::SLTU(R_TYPE));
+                },
+                (            _,       _, 0b0000000, 0b100, 0b0110011) => {
+                    return Some(Instruction::XOR(R_TYPE));
+                },
+                (            _,       _, 0b0000000, 0b101, 0b0110011) => {
+                    return Some(Instruction::SRL(R_TYPE));
+                },
+                (            _,       _, 0b0100000, 0b101, 0b0110011) => {
+                    return Some(Instruction::SRA(R_TYPE));
+                },
+                (            _,       _, 0b0000000, 0b110, 0b0110011) => {
+                    return Some(Instruction::OR(R_TYPE));
+                },
+                (            _,       _, 0b0000000, 0b111, 0b0110011) => {
+                    return Some(Instruction::AND(R_TYPE));
+                },
+                (            _,       _,         _, 0b000, 0b0001111) => {
+                    return Some(Instruction::FENCE(I_TYPE));
+                },
+                (0b00000000000,       _,         _, 0b000, 0b1110011) => {
+                    return Some(Instruction::ECALL(I_TYPE));
+                },
+                (0b00000000001,       _,         _, 0b000, 0b1110011) => {
+                    return Some(Instruction::EBREAK(I_TYPE));
+                },
+                // Privileged Instructions
+                (0b01100000010,       _,         _, 0b000, 0b1110011) => {
+                    return Some(Instruction::MRET(I_TYPE));
+                },
+                (0b00100000010,       _,         _, 0b000, 0b1110011) => {
+                    return Some(Instruction::SRET(I_TYPE));
+                },
+                (0b00100000101,       _,         _, 0b000, 0b1110011) => {
+                    return Some(Instruction::WFI(I_TYPE));
+                },
+                (            _,       _, 0b0001001, 0b000, 0b1110011) => {
+                    return Some(Instruction::SFENCEVMA(R_TYPE));
+                },
+                // `H` Extension
+                (            _,       _, 0b0010001, 0b000, 0b1110011) => {
+                    return Some(Instruction::HFENCEVVMA(R_TYPE));
+                },
+                (            _,       _, 0b0110001, 0b000, 0b1110011) => {
+                    return Some(Instruction::HFENCEGVMA(R_TYPE));
+                },
+                (            _,       _,         _, 0b100, 0b1110011) => {
+                    let access = HypervisorAccess::decode(inst as u32)?;
+                    return Some(if access.store { Instruction::HSV(R_TYPE) } else { Instruction::HLV(R_TYPE) });
+                },
+                // `RV64I` Base Instructions
+                (            _,       _,         _, 0b110, 0b0000011) => {
+                    return Some(Instruction::LWU(I_TYPE));
+                },
+                (            _,       _,         _, 0b011, 0b0000011) => {
+                    return Some(Instruction::LD(I_TYPE));
+                },
+                (            _,       _,         _, 0b011, 0b0100011) => {
+                    return Some(Instruction::SD(S_TYPE));
+                },
+                (            _,       _,         _, 0b000, 0b0011011) => {
+                    return Some(Instruction::ADDIW(I_TYPE));
+                },
+                (            _,       _, 0b0000000, 0b001, 0b0011011) => {
+                    return Some(Instruction::SLLIW(R_TYPE));
+                },
+                (            _,       _, 0b0000000, 0b101, 0b0011011) => {
+                    return Some(Instruction::SRLIW(R_TYPE));
+                },
+                (            _,       _, 0b0100000, 0b101, 0b0011011) => {
+                    return Some(Instruction::SRAIW(R_TYPE));
+                },
+                (            _,       _, 0b0000000, 0b000, 0b0111011) => {
+                    return Some(Instruction::ADDW(R_TYPE));
+                },
+                (            _,       _, 0b0100000, 0b000, 0b0111011) => {
+                    return Some(Instruction::SUBW(R_TYPE));
+                },
+                (            _,       _, 0b0000000, 0b001, 0b0111011) => {
+                    return Some(Instruction::SLLW(R_TYPE));
+                },
+                (            _,       _, 0b0000000, 0b101, 0b0111011) => {
+                    return Some(Instruction::SRLW(R_TYPE));
+                },
+                (            _,       _, 0b0100000, 0b101, 0b0111011) => {
+                    return Some(Instruction::SRAW(R_TYPE));
+                },
+                // `Zifencei` Extension
+                (            _,       _,         _, 0b001, 0b0001111) => {
+                    return Some(Instruction::FENCEI(I_TYPE));
+                },
+                // `Zicsr` Extension
+                (            _,       _,         _, 0b001, 0b1110011) => {
+                    return Some(Instruction::CSRRW(I_TYPE));
+                },
+                (            _,       _,         _, 0b010, 0b1110011) => {
+                    return Some(Instruction::CSRRS(I_TYPE));
+                },
+                (            _,       _,         _, 0b011, 0b1110011) => {
+                    return Some(Instruction::CSRRC(I_TYPE));
+                },
+                (            _,       _,         _, 0b101, 0b1110011) => {
+                    return Some(Instruction::CSRRWI(I_TYPE));
+                },
+                (            _,       _,         _, 0b110, 0b1110011) => {
+                    return Some(Instruction::CSRRSI(I_TYPE));
+                },
+                (            _,       _,         _, 0b111, 0b1110011) => {
+                    return Some(Instruction::CSRRCI(I_TYPE));
+                },
+                // `M` Extension
+                (            _,       _, 0b0000001, 0b000, 0b0110011) => {
+                    return Some(Instruction::MUL(R_TYPE));
+                },
+                (            _,       _, 0b0000001, 0b001, 0b0110011) => {
+                    return Some(Instruction::MULH(R_TYPE));
+                },
+                (            _,       _, 0b0000001, 0b010, 0b0110011) => {
+                    return Some(Instruction::MULHSU(R_TYPE));
+                },
+                (            _,       _, 0b0000001, 0b011, 0b0110011) => {
+                    return Some(Instruction::MULHU(R_TYPE));
+                },
+                (            _,       _, 0b0000001, 0b100, 0b0110011) => {
+                    return Some(Instruction::DIV(R_TYPE));
+                },
+                (            _,       _, 0b0000001, 0b101, 0b0110011) => {
+                    return Some(Instruction::DIVU(R_TYPE));
+                },
+                (            _,       _, 0b0000001, 0b110, 0b0110011) => {
+                    return Some(Instruction::REM(R_TYPE));
+                },
+                (            _,       _, 0b0000001, 0b111, 0b0110011) => {
+                    return Some(Instruction::REMU(R_TYPE));
+                },
+                (            _,       _, 0b0000001, 0b000, 0b0111011) => {
+                    return Some(Instruction::MULW(R_TYPE));
+                },
+                (            _,       _, 0b0000001, 0b100, 0b0111011) => {
+                    return Some(Instruction::DIVW(R_TYPE));
+                },
+                (            _,       _, 0b0000001, 0b101, 0b0111011) => {
+                    return Some(Instruction::DIVUW(R_TYPE));
+                },
+                (            _,       _, 0b0000001, 0b110, 0b0111011) => {
+                    return Some(Instruction::REMW(R_TYPE));
+                },
+                (            _,       _, 0b0000001, 0b111, 0b0111011) => {
+                    return Some(Instruction::REMUW(R_TYPE));
+                },
+                // `A` Extension
+                (            _,       _,         _, 0b010, 0b0101111)
+                | (          _,       _,         _, 0b011, 0b0101111) => {
+                    return Self::parse_atomic(R_TYPE, funct5, funct3, rs2);
+                },
+                // `V` Extension
+                (            _,       _,         _, 0b000, 0b0000111)
+                | (          _,       _,         _, 0b101, 0b0000111)
+                | (          _,       _,         _, 0b110, 0b0000111)
+                | (          _,       _,         _, 0b111, 0b0000111)
+                | (          _,       _,         _, 0b000, 0b0100111)
+                | (          _,       _,         _, 0b101, 0b0100111)
+                | (          _,       _,         _, 0b110, 0b0100111)
+                | (          _,       _,         _, 0b111, 0b0100111)
+                | (          _,       _,         _,     _, 0b1010111) => {
+                    return Self::parse_vector(inst, R_TYPE, I_TYPE);
+                },
+                // `F` and `D` Extensions
+                (            _,       _,         _,     _, 0b0000111)
+                | (          _,       _,         _,     _, 0b0100111)
+                | (          _,       _,         _,     _, 0b1000011)
+                | (          _,       _,         _,     _, 0b1000111)
+                | (          _,       _,         _,     _, 0b1001011)
+                | (          _,       _,         _,     _, 0b1001111)
+                | (          _,       _,         _,     _, 0b1010011) => {
+                    return Self::parse_float(R_TYPE, I_TYPE, S_TYPE);
+                },
+                // `Zba` Extension
+                (            _,       _, 0b0010000, 0b010, 0b0110011) => {
+                    return Some(Instruction::SH1ADD(R_TYPE));
+                },
+                (            _,       _, 0b0010000, 0b100, 0b0110011) => {
+                    return Some(Instruction::SH2ADD(R_TYPE));
+                },
+                (            _,       _, 0b0010000, 0b110, 0b0110011) => {
+                    return Some(Instruction::SH3ADD(R_TYPE));
+                },
+                (            _,       _, 0b0000100, 0b000, 0b0111011) => {
+                    return Some(Instruction::ADDUW(R_TYPE));
+                },
+                (            _,       _, 0b0010000, 0b010, 0b0111011) => {
+                    return Some(Instruction::SH1ADDUW(R_TYPE));
+                },
+                (            _,       _, 0b0010000, 0b100, 0b0111011) => {
+                    return Some(Instruction::SH2ADDUW(R_TYPE));
+                },
+                (            _,       _, 0b0010000, 0b110, 0b0111011) => {
+                    return Some(Instruction::SH3ADDUW(R_TYPE));
+                },
+                (            _, 0b000010,        _, 0b001, 0b0011011) => {
+                    return Some(Instruction::SLLIUW(R_TYPE));
+                },
+                // `Zbb` Extension
+                (            _,       _, 0b0100000, 0b111, 0b0110011) => {
+                    return Some(Instruction::ANDN(R_TYPE));
+                },
+                (            _,       _, 0b0100000, 0b110, 0b0110011) => {
+                    return Some(Instruction::ORN(R_TYPE));
+                },
+                (            _,       _, 0b0100000, 0b100, 0b0110011) => {
+                    return Some(Instruction::XNOR(R_TYPE));
+                },
+                (        0x600,       _,         _, 0b001, 0b0010011) => {
+                    return Some(Instruction::CLZ(R_TYPE));
+                },
+                (        0x601,       _,         _, 0b001, 0b0010011) => {
+                    return Some(Instruction::CTZ(R_TYPE));
+                },
+                (        0x602,       _,         _, 0b001, 0b0010011) => {
+                    return Some(Instruction::CPOP(R_TYPE));
+                },
+                (            _,       _, 0b0000101, 0b110, 0b0110011) => {
+                    return Some(Instruction::MAX(R_TYPE));
+                },
+                (            _,       _, 0b0000101, 0b111, 0b0110011) => {
+                    return Some(Instruction::MAXU(R_TYPE));
+                },
+                (            _,       _, 0b0000101, 0b100, 0b0110011) => {
+                    return Some(Instruction::MIN(R_TYPE));
+                },
+                (            _,       _, 0b0000101, 0b101, 0b0110011) => {
+                    return Some(Instruction::MINU(R_TYPE));
+                },
+                (        0x604,       _,         _, 0b001, 0b0010011) => {
+                    return Some(Instruction::SEXTB(R_TYPE));
+                },
+                (        0x605,       _,         _, 0b001, 0b0010011) => {
+                    return Some(Instruction::SEXTH(R_TYPE));
+                },
+                (        0x080,       _,         _, 0b100, 0b0111011) => {
+                    return Some(Instruction::ZEXTH(R_TYPE));
+                },
+                (        0x080,       _,         _, 0b100, 0b0110011) if isa.xlen() == 32 => {
+                    return Some(Instruction::ZEXTH(R_TYPE));
+                },
+                (            _,       _, 0b0110000, 0b001, 0b0110011) => {
+                    return Some(Instruction::ROL(R_TYPE));
+                },
+                (            _,       _, 0b0110000, 0b101, 0b0110011) => {
+                    return Some(Instruction::ROR(R_TYPE));
+                },
+                (            _, 0b011000,        _, 0b101, 0b0010011) => {
+                    return Some(Instruction::RORI(R_TYPE));
+                },
+                (        0x287,       _,         _, 0b101, 0b0010011) => {
+                    return Some(Instruction::ORCB(R_TYPE));
+                },
+                (        0x6B8,       _,         _, 0b101, 0b0010011) if isa.xlen() == 64 => {
+                    return Some(Instruction::REV8(R_TYPE));
+                },
+                (        0x698,       _,         _, 0b101, 0b0010011) if isa.xlen() == 32 => {
+                    return Some(Instruction::REV8(R_TYPE));
+                },
+                (        0x600,       _,         _, 0b001, 0b0011011) => {
+                    return Some(Instruction::CLZW(R_TYPE));
+                },
+                (        0x601,       _,         _, 0b001, 0b0011011) => {
+                    return Some(Instruction::CTZW(R_TYPE));
+                },
+                (        0x602,       _,         _, 0b001, 0b0011011) => {
+                    return Some(Instruction::CPOPW(R_TYPE));
+                },
+                (            _,       _, 0b0110000, 0b001, 0b0111011) => {
+                    return Some(Instruction::ROLW(R_TYPE));
+                },
+                (            _,       _, 0b0110000, 0b101, 0b0111011) => {
+                    return Some(Instruction::RORW(R_TYPE));
+                },
+                (            _,       _, 0b0110000, 0b101, 0b0011011) => {
+                    return Some(Instruction::RORIW(R_TYPE));
+                },
+                // `Zbc` Extension
+                (            _,       _, 0b0000101, 0b001, 0b0110011) => {
+                    return Some(Instruction::CLMUL(R_TYPE));
+                },
+                (            _,       _, 0b0000101, 0b011, 0b0110011) => {
+                    return Some(Instruction::CLMULH(R_TYPE));
+                },
+                (            _,       _, 0b0000101, 0b010, 0b0110011) => {
+                    return Some(Instruction::CLMULR(R_TYPE));
+                },
+                // `Zbs` Extension
+                (            _,       _, 0b0100100, 0b001, 0b0110011) => {
+                    return Some(Instruction::BCLR(R_TYPE));
+                },
+                (            _, 0b010010,        _, 0b001, 0b0010011) => {
+                    return Some(Instruction::BCLRI(R_TYPE));
+                },
+                (            _,       _, 0b0100100, 0b101, 0b0110011) => {
+                    return Some(Instruction::BEXT(R_TYPE));
+                },
+                (            _, 0b010010,        _, 0b101, 0b0010011) => {
+                    return Some(Instruction::BEXTI(R_TYPE));
+                },
+                (            _,       _, 0b0110100, 0b001, 0b0110011) => {
+                    return Some(Instruction::BINV(R_TYPE));
+                },
+                (            _, 0b011010,        _, 0b001, 0b0010011) => {
+                    return Some(Instruction::BINVI(R_TYPE));
+                },
+                (            _,       _, 0b0010100, 0b001, 0b0110011) => {
+                    return Some(Instruction::BSET(R_TYPE));
+                },
+                (            _, 0b001010,        _, 0b001, 0b0010011) => {
+                    return Some(Instruction::BSETI(R_TYPE));
+                },
+                // `Zbkb` Extension
+                (            _,       _, 0b0000100, 0b100, 0b0110011) if isa.zbkb => {
+                    return Some(Instruction::PACK(R_TYPE));
+                },
+                (            _,       _, 0b0000100, 0b111, 0b0110011) => {
+                    return Some(Instruction::PACKH(R_TYPE));
+                },
+                (        0x687,       _,         _, 0b101, 0b0010011) => {
+                    return Some(Instruction::BREV8(R_TYPE));
+                },
+                (        0x08F,       _,         _, 0b001, 0b0010011) if isa.xlen() == 32 => {
+                    return Some(Instruction::ZIP(R_TYPE));
+                },
+                (        0x08F,       _,         _, 0b101, 0b0010011) if isa.xlen() == 32 => {
+                    return Some(Instruction::UNZIP(R_TYPE));
+                },
+                (            _,       _, 0b0000100, 0b100, 0b0111011) => {
+                    return Some(Instruction::PACKW(R_TYPE));
+                },
+                // `Zknd`, `Zkne`, `Zknh`, `Zksed` and `Zksh`
+                (            _,       _,         _, 0b000, 0b0110011)
+                | (          _,       _,         _, 0b001, 0b0010011) if CryptoOp::decode(inst as u32, isa.xlen()).is_some() => {
+                    return Some(Instruction::CRYPTO(R_TYPE));
+                },
+                _ => None,
+            }
+        }
+
+        /// Decodes an `F`, `D` or `Zfh` Extension instruction. The rounding
+        /// mode in `funct3` of the arithmetic instructions is left for
+        /// execution to check, as `frm` decides what the dynamic mode means.
+        fn parse_float(r: R_Type, i: I_Type, s: S_Type) -> Option<Self> {
+            use Instruction::*;
+            let fmt = match r.opcode {
+                0b1010011 => r.funct7 & 0b11,
+                _ => r.funct2,
+            };
+            let pick = |single: fn(R_Type) -> Self, double: fn(R_Type) -> Self, half: fn(R_Type) -> Self| {
+                match fmt {
+                    0b00 => Some(single),
+                    0b01 => Some(double),
+                    0b10 => Some(half),
+                    _ => None,
+                }
+            };
+            let variant: Option<fn(R_Type) -> Self> = match (r.opcode, r.funct7 >> 2, r.funct3, r.rs2) {
+                (0b0000111, _, 0b001, _) => return Some(FLH(i)),
+                (0b0000111, _, 0b010, _) => return Some(FLW(i)),
+                (0b0000111, _, 0b011, _) => Some(FLD),
+                (0b0100111, _, 0b001, _) => return Some(FSH(s)),
+                (0b0100111, _, 0b010, _) => return Some(FSW(s)),
+                (0b0100111, _, 0b011, _) => Some(FSD),
+                (0b1000011, _, _, _) => pick(FMADDS, FMADDD, FMADDH),
+                (0b1000111, _, _, _) => pick(FMSUBS, FMSUBD, FMSUBH),
+                (0b1001011, _, _, _) => pick(FNMSUBS, FNMSUBD, FNMSUBH),
+                (0b1001111, _, _, _) => pick(FNMADDS, FNMADDD, FNMADDH),
+                (0b1010011, 0b00000, _, _) => pick(FADDS, FADDD, FADDH),
+                (0b1010011, 0b00001, _, _) => pick(FSUBS, FSUBD, FSUBH),
+                (0b1010011, 0b00010, _, _) => pick(FMULS, FMULD, FMULH),
+                (0b1010011, 0b00011, _, _) => pick(FDIVS, FDIVD, FDIVH),
+                (0b1010011, 0b01011, _, 0) => pick(FSQRTS, FSQRTD, FSQRTH),
+                (0b1010011, 0b00100, 0b000, _) => pick(FSGNJS, FSGNJD, FSGNJH),
+                (0b1010011, 0b00100, 0b001, _) => pick(FSGNJNS, FSGNJND, FSGNJNH),
+                (0b1010011, 0b00100, 0b010, _) => pick(FSGNJXS, FSGNJXD, FSGNJXH),
+                (0b1010011, 0b00101, 0b000, _) => pick(FMINS, FMIND, FMINH),
+                (0b1010011, 0b00101, 0b001, _) => pick(FMAXS, FMAXD, FMAXH),
+                // the conversions between formats take the source's in `rs2`.
+                (0b1010011, 0b01000, _, _) => match (fmt, r.rs2) {
+                    (0b00, 0b01) => Some(FCVTSD),
+                    (0b00, 0b10) => Some(FCVTSH),
+                    (0b01, 0b00) => Some(FCVTDS),
+                    (0b01, 0b10) => Some(FCVTDH),
+                    (0b10, 0b00) => Some(FCVTHS),
+                    (0b10, 0b01) => Some(FCVTHD),
+                    _ => None,
+                },
+                (0b1010011, 0b10100, 0b010, _) => pick(FEQS, FEQD, FEQH),
+                (0b1010011, 0b10100, 0b001, _) => pick(FLTS, FLTD, FLTH),
+                (0b1010011, 0b10100, 0b000, _) => pick(FLES, FLED, FLEH),
+                (0b1010011, 0b11000, _, 0) => pick(FCVTWS, FCVTWD, FCVTWH),
+                (0b1010011, 0b11000, _, 1) => pick(FCVTWUS, FCVTWUD, FCVTWUH),
+                (0b1010011, 0b11000, _, 2) => pick(FCVTLS, FCVTLD, FCVTLH),
+                (0b1010011, 0b11000, _, 3) => pick(FCVTLUS, FCVTLUD, FCVTLUH),
+                (0b1010011, 0b11010, _, 0) => pick(FCVTSW, FCVTDW, FCVTHW),
+                (0b1010011, 0b11010, _, 1) => pick(FCVTSWU, FCVTDWU, FCVTHWU),
+                (0b1010011, 0b11010, _, 2) => pick(FCVTSL, FCVTDL, FCVTHL),
+                (0b1010011, 0b11010, _, 3) => pick(FCVTSLU, FCVTDLU, FCVTHLU),
+                (0b1010011, 0b11100, 0b000, 0) => pick(FMVXW, FMVXD, FMVXH),
+                (0b1010011, 0b11100, 0b001, 0) => pick(FCLASSS, FCLASSD, FCLASSH),
+                (0b1010011, 0b11110, 0b000, 0) => pick(FMVWX, FMVDX, FMVHX),
+                _ => None,
+            };
+            variant.map(|variant| variant(r))
+        }
+
+        /// Decodes a `V` Extension instruction, leaving the loads, stores
+        /// and OP-V arithmetic for `Vector` to check.
+        fn parse_vector(inst: usize, r: R_Type, i: I_Type) -> Option<Self> {
+            use Instruction::*;
+            let raw = inst as u32;
+            match (r.opcode, r.funct3, inst >> 30) {
+                (0b1010111, 0b111, 0b00 | 0b01) => Some(VSETVLI(i)),
+                (0b1010111, 0b111, 0b11) => Some(VSETIVLI(i)),
+                (0b1010111, 0b111, _) if r.funct7 == 0b1000000 => Some(VSETVL(r)),
+                (0b1010111, 0b111, _) => None,
+                (0b1010111, _, _) => VectorOp::decode(raw).map(|_| VOP(r)),
+                (0b0000111, _, _) => VectorAccess::decode(raw).map(|_| VLOAD(r)),
+                _ => VectorAccess::decode(raw).map(|_| VSTORE(r)),
+            }
+        }
+
+        /// Decodes an `A` Extension instruction from its `funct5`, ignoring
+        /// the `aq` and `rl` bits; `funct3` tells words from doublewords.
+        fn parse_atomic(r: R_Type, funct5: usize, funct3: usize, rs2: usize) -> Option<Self> {
+            let word = funct3 == 0b010;
+            match funct5 {
+                0b00010 if rs2 != 0 => None,
+                0b00010 => Some(if word { Instruction::LRW(r) } else { Instruction::LRD(r) }),
+                0b00011 => Some(if word { Instruction::SCW(r) } else { Instruction::SCD(r) }),
+                0b00001 => Some(if word { Instruction::AMOSWAPW(r) } else { Instruction::AMOSWAPD(r) }),
+                0b00000 => Some(if word { Instruction::AMOADDW(r) } else { Instruction::AMOADDD(r) }),
+                0b00100 => Some(if word { Instruction::AMOXORW(r) } else { Instruction::AMOXORD(r) }),
+                0b01100 => Some(if word { Instruction::AMOANDW(r) } else { Instruction::AMOANDD(r) }),
+                0b01000 => Some(if word { Instruction::AMOORW(r) } else { Instruction::AMOORD(r) }),
+                0b10000 => Some(if word { Instruction::AMOMINW(r) } else { Instruction::AMOMIND(r) }),
+                0b10100 => Some(if word { Instruction::AMOMAXW(r) } else { Instruction::AMOMAXD(r) }),
+                0b11000 => Some(if word { Instruction::AMOMINUW(r) } else { Instruction::AMOMINUD(r) }),
+                0b11100 => Some(if word { Instruction::AMOMAXUW(r) } else { Instruction::AMOMAXUD(r) }),
+                _ => None,
+            }
+        }
+    }
+
+    fn encode_r(funct7: usize, rs2: usize, rs1: usize, funct3: usize, rd: usize, opcode: usize) -> usize {
+        (funct7 << 25) | (rs2 << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | opcode
+    }
+
+    fn encode_i(imm: usize, rs1: usize, funct3: usize, rd: usize, opcode: usize) -> usize {
+        ((imm & 0xFFF) << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | opcode
+    }
+
+    fn encode_s(imm: usize, rs2: usize, rs1: usize, funct3: usize, opcode: usize) -> usize {
+        (((imm >> 5) & 0x7F) << 25) | (rs2 << 20) | (rs1 << 15) | (funct3 << 12)
+            | ((imm & 0x1F) << 7) | opcode
+    }
+
+    fn encode_b(imm: usize, rs2: usize, rs1: usize, funct3: usize, opcode: usize) -> usize {
+        (((imm >> 12) & 0x1) << 31) | (((imm >> 5) & 0x3F) << 25) | (rs2 << 20) | (rs1 << 15)
+            | (funct3 << 12) | (((imm >> 1) & 0xF) << 8) | (((imm >> 11) & 0x1) << 7) | opcode
+    }
+
+    fn encode_u(imm: usize, rd: usize, opcode: usize) -> usize {
+        (imm & 0xFFFFF000) | (rd << 7) | opcode
+    }
+
+    fn encode_j(imm: usize, rd: usize, opcode: usize) -> usize {
+        (((imm >> 20) & 0x1) << 31) | (((imm >> 1) & 0x3FF) << 21) | (((imm >> 11) & 0x1) << 20)
+            | (((imm >> 12) & 0xFF) << 12) | (rd << 7) | opcode
+    }
+
+    /// Sign extends the low `bits` bits of `value`.
+    fn sext(value: usize, bits: u32) -> usize {
+        let shift = usize::BITS - bits;
+        (((value << shift) as isize) >> shift) as usize
+    }
+
+    /// Picks `len` bits of `inst` starting at `lo` and places them at `to`.
+    fn bits(inst: usize, lo: usize, len: usize, to: usize) -> usize {
+        ((inst >> lo) & ((1 << len) - 1)) << to
+    }
+
+    /// The jump offset of `C.J` and `C.JAL`.
+    fn cj_imm(inst: usize) -> usize {
+        sext(bits(inst, 12, 1, 11) | bits(inst, 11, 1, 4) | bits(inst, 9, 2, 8)
+            | bits(inst, 8, 1, 10) | bits(inst, 7, 1, 6) | bits(inst, 6, 1, 7)
+            | bits(inst, 3, 3, 1) | bits(inst, 2, 1, 5), 12)
+    }
+
+    /// Expands a 16 bit `C` Extension instruction into the 32 bit
+    /// instruction it is shorthand for at `xlen`, or `None` if it is
+    /// reserved. RV32 has `C.JAL` and the single-precision loads and stores
+    /// where RV64 has `C.ADDIW` and the doubleword ones.
+    pub fn expand_compressed(inst: usize, xlen: u32) -> Option<usize> {
+        let rv32 = xlen == 32;
+        let quadrant = inst & 0b11;
+        let funct3 = (inst >> 13) & 0b111;
+        // full register numbers from bits 11:7 and 6:2.
+        let rd = (inst >> 7) & 0x1F;
+        let rs2 = (inst >> 2) & 0x1F;
+        // compressed register numbers (x8 - x15) from bits 9:7 and 4:2.
+        let rs1c = ((inst >> 7) & 0b111) + 8;
+        let rs2c = ((inst >> 2) & 0b111) + 8;
+        // the 6 bit signed immediate shared by most of quadrant 1.
+        let imm6 = sext(bits(inst, 12, 1, 5) | bits(inst, 2, 5, 0), 6);
+
+        if inst == 0 {
+            // the all zero instruction is defined to be illegal.
+            return None;
+        }
+
+        let out = match (quadrant, funct3) {
+            // C.ADDI4SPN
+            (0b00, 0b000) => {
+                let imm = bits(inst, 11, 2, 4) | bits(inst, 7, 4, 6)
+                    | bits(inst, 6, 1, 2) | bits(inst, 5, 1, 3);
+                if imm == 0 {
+                    return None;
+                }
+                encode_i(imm, 2, 0b000, rs2c, 0b0010011)
+            },
+            // C.FLD
+            (0b00, 0b001) => {
+                let imm = bits(inst, 10, 3, 3) | bits(inst, 5, 2, 6);
+                encode_i(imm, rs1c, 0b011, rs2c, 0b0000111)
+            },
+            // C.LW
+            (0b00, 0b010) => {
+                let imm = bits(inst, 10, 3, 3) | bits(inst, 6, 1, 2) | bits(inst, 5, 1, 6);
+                encode_i(imm, rs1c, 0b010, rs2c, 0b0000011)
+            },
+            // C.FLW
+            (0b00, 0b011) if rv32 => {
+                let imm = bits(inst, 10, 3, 3) | bits(inst, 6, 1, 2) | bits(inst, 5, 1, 6);
+                encode_i(imm, rs1c, 0b010, rs2c, 0b0000111)
+            },
+            // C.LD
+            (0b00, 0b011) => {
+                let imm = bits(inst, 10, 3, 3) | bits(inst, 5, 2, 6);
+                encode_i(imm, rs1c, 0b011, rs2c, 0b0000011)
+            },
+            // C.FSD
+            (0b00, 0b101) => {
+                let imm = bits(inst, 10, 3, 3) | bits(inst, 5, 2, 6);
+                encode_s(imm, rs2c, rs1c, 0b011, 0b0100111)
+            },
+            // C.SW
+            (0b00, 0b110) => {
+                let imm = bits(inst, 10, 3, 3) | bits(inst, 6, 1, 2) | bits(inst, 5, 1, 6);
+                encode_s(imm, rs2c, rs1c, 0b010, 0b0100011)
+            },
+            // C.FSW
+            (0b00, 0b111) if rv32 => {
+                let imm = bits(inst, 10, 3, 3) | bits(inst, 6, 1, 2) | bits(inst, 5, 1, 6);
+                encode_s(imm, rs2c, rs1c, 0b010, 0b0100111)
+            },
+            // C.SD
+            (0b00, 0b111) => {
+                let imm = bits(inst, 10, 3, 3) | bits(inst, 5, 2, 6);
+                encode_s(imm, rs2c, rs1c, 0b011, 0b0100011)
+            },
+            // C.ADDI (C.NOP when rd is x0)
+            (0b01, 0b000) => encode_i(imm6, rd, 0b000, rd, 0b0010011),
+            // C.JAL
+            (0b01, 0b001) if rv32 => encode_j(cj_imm(inst), 1, 0b1101111),
+            // C.ADDIW
+            (0b01, 0b001) => {
+                if rd == 0 {
+                    return None;
+                }
+                encode_i(imm6, rd, 0b000, rd, 0b0011011)
+            },
+            // C.LI
+            (0b01, 0b010) => encode_i(imm6, 0, 0b000, rd, 0b0010011),
+            // C.ADDI16SP
+            (0b01, 0b011) if rd == 2 => {
+                let imm = sext(bits(inst, 12, 1, 9) | bits(inst, 6, 1, 4) | bits(inst, 5, 1, 6)
+                    | bits(inst, 3, 2, 7) | bits(inst, 2, 1, 5), 10);
+                if imm == 0 {
+                    return None;
+                }
+                encode_i(imm, 2, 0b000, 2, 0b0010011)
+            },
+            // C.LUI
+            (0b01, 0b011) => {
+                let imm = sext(bits(inst, 12, 1, 17) | bits(inst, 2, 5, 12), 18);
+                if imm == 0 {
+                    return None;
+                }
+                encode_u(imm, rd, 0b0110111)
+            },
+            (0b01, 0b100) => {
+                let shamt = bits(inst, 12, 1, 5) | bits(inst, 2, 5, 0);
+                match ((inst >> 10) & 0b11, (inst >> 12) & 0b1, (inst >> 5) & 0b11) {
+                    // shift amounts of 32 and up, C.SUBW and C.ADDW are
+                    // reserved on RV32.
+                    (0b00 | 0b01, 1, _) | (0b11, 1, _) if rv32 => return None,
+                    // C.SRLI
+                    (0b00, _, _) => encode_i(shamt, rs1c, 0b101, rs1c, 0b0010011),
+                    // C.SRAI
+                    (0b01, _, _) => encode_i(0x400 | shamt, rs1c, 0b101, rs1c, 0b0010011),
+                    // C.ANDI
+                    (0b10, _, _) => encode_i(imm6, rs1c, 0b111, rs1c, 0b0010011),
+                    // C.SUB
+                    (0b11, 0, 0b00) => encode_r(0b0100000, rs2c, rs1c, 0b000, rs1c, 0b0110011),
+                    // C.XOR
+                    (0b11, 0, 0b01) => encode_r(0b0000000, rs2c, rs1c, 0b100, rs1c, 0b0110011),
+                    // C.OR
+                    (0b11, 0, 0b10) => encode_r(0b0000000, rs2c, rs1c, 0b110, rs1c, 0b0110011),
+                    // C.AND
+                    (0b11, 0, 0b11) => encode_r(0b0000000, rs2c, rs1c, 0b111, rs1c, 0b0110011),
+                    // C.SUBW
+                    (0b11, 1, 0b00) => encode_r(0b0100000, rs2c, rs1c, 0b000, rs1c, 0b0111011),
+                    // C.ADDW
+                    (0b11, 1, 0b01) => encode_r(0b0000000, rs2c, rs1c, 0b000, rs1c, 0b0111011),
+                    _ => return None,
+                }
+            },
+            // C.J
+            (0b01, 0b101) => encode_j(cj_imm(inst), 0, 0b1101111),
+            // C.BEQZ, C.BNEZ
+            (0b01, 0b110) | (0b01, 0b111) => {
+                let imm = sext(bits(inst, 12, 1, 8) | bits(inst, 10, 2, 3) | bits(inst, 5, 2, 6)
+                    | bits(inst, 3, 2, 1) | bits(inst, 2, 1, 5), 9);
+                encode_b(imm, 0, rs1c, funct3 & 0b001, 0b1100011)
+            },
+            // C.SLLI, whose shift amounts of 32 and up are reserved on RV32
+            (0b10, 0b000) if rv32 && inst & (1 << 12) != 0 => return None,
+            (0b10, 0b000) => {
+                let shamt = bits(inst, 12, 1, 5) | bits(inst, 2, 5, 0);
+                encode_i(shamt, rd, 0b001, rd, 0b0010011)
+            },
+            // C.FLDSP
+            (0b10, 0b001) => {
+                let imm = bits(inst, 12, 1, 5) | bits(inst, 5, 2, 3) | bits(inst, 2, 3, 6);
+                encode_i(imm, 2, 0b011, rd, 0b0000111)
+            },
+            // C.LWSP
+            (0b10, 0b010) => {
+                if rd == 0 {
+                    return None;
+                }
+                let imm = bits(inst, 12, 1, 5) | bits(inst, 4, 3, 2) | bits(inst, 2, 2, 6);
+                encode_i(imm, 2, 0b010, rd, 0b0000011)
+            },
+            // C.FLWSP
+            (0b10, 0b011) if rv32 => {
+                let imm = bits(inst, 12, 1, 5) | bits(inst, 4, 3, 2) | bits(inst, 2, 2, 6);
+                encode_i(imm, 2, 0b010, rd, 0b0000111)
+            },
+            // C.LDSP
+            (0b10, 0b011) => {
+                if rd == 0 {
+                    return None;
+                }
+                let imm = bits(inst, 12, 1, 5) | bits(inst, 5, 2, 3) | bits(inst, 2, 3, 6);
+                encode_i(imm, 2, 0b011, rd, 0b0000011)
+            },
+            (0b10, 0b100) => match ((inst >> 12) & 0b1, rd, rs2) {
+                // C.JR
+                (0, 0, 0) => return None,
+                (0, _, 0) => encode_i(0, rd, 0b000, 0, 0b1100111),
+                // C.MV
+                (0, _, _) => encode_r(0b0000000, rs2, 0, 0b000, rd, 0b0110011),
+                // C.EBREAK
+                (1, 0, 0) => encode_i(1, 0, 0b000, 0, 0b1110011),
+                // C.JALR
+                (1, _, 0) => encode_i(0, rd, 0b000, 1, 0b1100111),
+                // C.ADD
+                _ => encode_r(0b0000000, rs2, rd, 0b000, rd, 0b0110011),
+            },
+            // C.FSDSP
+            (0b10, 0b101) => {
+                let imm = bits(inst, 10, 3, 3) | bits(inst, 7, 3, 6);
+                encode_s(imm, rs2, 2, 0b011, 0b0100111)
+            },
+            // C.SWSP
+            (0b10, 0b110) => {
+                let imm = bits(inst, 9, 4, 2) | bits(inst, 7, 2, 6);
+                encode_s(imm, rs2, 2, 0b010, 0b0100011)
+            },
+            // C.FSWSP
+            (0b10, 0b111) if rv32 => {
+                let imm = bits(inst, 9, 4, 2) | bits(inst, 7, 2, 6);
+                encode_s(imm, rs2, 2, 0b010, 0b0100111)
+            },
+            // C.SDSP
+            (0b10, 0b111) => {
+                let imm = bits(inst, 10, 3, 3) | bits(inst, 7, 3, 6);
+                encode_s(imm, rs2, 2, 0b011, 0b0100011)
+            },
+            _ => return None,
+        };
+        Some(out)
+    }
+}
+
+#[derive(Debug)]
+pub enum InstructionType {
+    R_TYPE(R_Type),
+    I_TYPE(I_Type),
+    S_TYPE(S_Type),
+    B_TYPE(B_Type),
+    U_TYPE(U_Type),
+    J_TYPE(J_Type),
+}
+
+#[allow(clippy::needless_return)]
+pub fn decode_instruction_type(data: usize) -> InstructionType {
+    let generic: Generic_Op = Generic_Op::new(data);
+    match generic.opcode {
+        // RV32I Base Instruction set
+        0b0110111 => {
+            // LUI
+            return InstructionType::U_TYPE(U_Type::new(generic.raw));
+        }
+        0b0010111 => {
+            // AUIPC
+            return InstructionType::U_TYPE(U_Type::new(generic.raw));
+        }
+        0b1101111 => {
+            // JAL
+            return InstructionType::J_TYPE(J_Type::new(generic.raw));
+        }
+        0b1100111 => {
+            // JALR
+            return InstructionType::I_TYPE(I_Type::new(generic.raw));
+        }
+        0b1100011 => {
+            // BEQ, BNE, BLT, BGE, BLTU, BGEU
+            return InstructionType::B_TYPE(B_Type::new(generic.raw));
+        }
+        0b0000011 => {
+            // LB, LH, LW, LBU, LHU, LWU, LD
+            return InstructionType::I_TYPE(I_Type::new(generic.raw));
+        }
+        0b0100011 => {
+            // SB, SH, SW, SD
+            return InstructionType::S_TYPE(S_Type::new(generic.raw));
+        }
+        0b0010011 => {
+            // ADDI, SLTI, SLTIU, XORI, ORI, ANDI,
+            // SLLI SRLI, SRAI, SLLI, SRLI, SRAI
+            // (The last 6 are a special case that isn't
+            // actually an I-Type, but it is close enough.)
+            return InstructionType::I_TYPE(I_Type::new(generic.raw));
+        }
+        0b0110011 => {
+            // ADD, SUB, SLL, SLT, SLTU, XOR, SRL, SRA, OR, AND
+            return InstructionType::R_TYPE(R_Type::new(generic.raw));
+        }
+        0b0001111 => {
+            // FENCE (Not actually I-type, but close enough
+            return InstructionType::I_TYPE(I_Type::new(generic.raw));
+        }
+        0b1110011 => {
+            // ECALL, EBREAK
+            // (Not actually I-type, but close enough)
+            return InstructionType::I_TYPE(I_Type::new(generic.raw));
+        }
+        // RV64I Base InstructionType Set (in addition to RV32I)
+        // LWU, LD +=> 0b0000011
+        // SD +=> 0b0100011
+        // SLLI, SRLI, SRAI +=> 0b0010011
+        0b0011011 => {
+            // ADDIW, SLLIW, SRLIW, SRAIW
+            //   (rs2 is actually shamt)
+            // MUL, MULH, MULHSU, MULHU, DIV, DIVU, REM, REMU
+            return InstructionType::R_TYPE(R_Type::new(generic.raw));
+        }
+        0b0111011 => {
+            // ADDW, SUBW, SLLW, SRLW, SRAW
+            return InstructionType::R_TYPE(R_Type::new(generic.raw));
+        }
+        // RV32M Standard Extension
+        // MUL, MULH, MULHSU, MULHU, DIV, DIVU, REM, REMU +=> 0b0110011
+        // // RV64M Standard Extension (in addition to RV32M)
+        // MULW, DIVW, DIVUW, REMW, REMUW +=> 0b0111011
+        // TODO: Add support for the `C` Extension.
+        _ => {
+            unimplemented!("{:#?}", generic)
+        }
+    }
+}